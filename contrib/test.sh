@@ -1,6 +1,6 @@
 #!/bin/sh -ex
 
-FEATURES="bitcoinconsensus use-serde rand"
+FEATURES="bitcoinconsensus use-serde rand test-utils"
 
 if [ "$DO_COV" = true ]
 then
@@ -23,6 +23,21 @@ do
     cargo test --verbose --features="$feature"
 done
 
+# tokio needs a newer Rust than our MSRV
+if [ "$TRAVIS_RUST_VERSION" != "1.32.0" ]; then
+    cargo test --verbose --features="tokio"
+fi
+
+# Run the examples which work on fixtures
+cargo run --verbose --example psbt_sign_finalize
+cargo run --verbose --example address_scan
+
+# The header sync example needs a peer to connect to
+if [ -n "$SPV_PEER" ]
+then
+    cargo run --verbose --example spv_header_sync -- "$SPV_PEER"
+fi
+
 # Fuzz if told to
 if [ "$DO_FUZZ" = true ]
 then
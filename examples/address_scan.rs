@@ -0,0 +1,108 @@
+extern crate monacoin;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use monacoin::blockdata::block::Block;
+use monacoin::blockdata::constants::genesis_block;
+use monacoin::blockdata::script::{Builder, Script};
+use monacoin::blockdata::locktime::LockTime;
+use monacoin::blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut};
+use monacoin::network::constants::Network;
+use monacoin::secp256k1::Secp256k1;
+use monacoin::util::address::Address;
+use monacoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey};
+use monacoin::util::key::PublicKey;
+
+// This example derives receive addresses from an account-level extended
+// public key, as a watch-only wallet would, and scans a block for outputs
+// paying to any of them. The block is a regtest fixture built on top of the
+// genesis block, so no network access is needed.
+
+const NETWORK: Network = Network::MonacoinRegtest;
+// The BIP32 test vector 1 master key, at m/0'/1
+const XPUB: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+const GAP_LIMIT: u32 = 5;
+
+fn main() {
+    let secp = Secp256k1::verification_only();
+    let account = ExtendedPubKey::from_str(XPUB).unwrap();
+
+    // Derive the first few receive keys and all the address types they can be used with
+    let mut derived = vec![];
+    for index in 0..GAP_LIMIT {
+        let path = DerivationPath::from(vec![
+            ChildNumber::from_normal_idx(0).unwrap(),
+            ChildNumber::from_normal_idx(index).unwrap(),
+        ]);
+        let pk = account.derive_pub(&secp, &path).unwrap().public_key;
+
+        let addresses = vec![
+            Address::p2pkh(&pk, NETWORK),
+            Address::p2shwpkh(&pk, NETWORK).unwrap(),
+            Address::p2wpkh(&pk, NETWORK).unwrap(),
+        ];
+        for address in addresses {
+            println!("{}: {}", path, address);
+            derived.push((address, path.clone()));
+        }
+    }
+    let watched: HashMap<Script, &(Address, DerivationPath)> = derived.iter()
+        .map(|entry| (entry.0.script_pubkey(), entry))
+        .collect();
+
+    let block = block_fixture(&[
+        derived[1].0.script_pubkey(),
+        Address::p2pkh(&genesis_pubkey(), NETWORK).script_pubkey(),
+        derived[7].0.script_pubkey(),
+    ]);
+
+    // Scan every output of every transaction in the block
+    let mut found = 0;
+    let mut total = 0;
+    for tx in &block.txdata {
+        for (vout, output) in tx.output.iter().enumerate() {
+            if let Some(&(address, path)) = watched.get(&output.script_pubkey) {
+                println!("Found {} satoshi paid to {} ({}) in {}:{}",
+                         output.value, address, path, tx.txid(), vout);
+                found += 1;
+                total += output.value;
+            }
+        }
+    }
+    println!("Block {}: {} matching output(s), {} satoshi in total", block.block_hash(), found, total);
+    assert_eq!(found, 2);
+}
+
+/// A block on top of the regtest genesis block with one transaction paying to `script_pubkeys`
+fn block_fixture(script_pubkeys: &[Script]) -> Block {
+    let genesis = genesis_block(NETWORK);
+    let tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(genesis.txdata[0].txid(), 0),
+            script_sig: Builder::new().push_slice(&[0; 71]).into_script(),
+            sequence: Sequence::MAX,
+            witness: vec![],
+        }],
+        output: script_pubkeys.iter().enumerate().map(|(i, spk)| TxOut {
+            value: (i as u64 + 1) * 1_000_000,
+            script_pubkey: spk.clone(),
+        }).collect(),
+    };
+
+    let mut block = Block {
+        header: genesis.header,
+        txdata: vec![tx],
+    };
+    block.header.prev_blockhash = genesis.block_hash();
+    block.header.time += 90;
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    block
+}
+
+/// The public key the genesis coinbase output pays to, standing in for somebody else's address
+fn genesis_pubkey() -> PublicKey {
+    genesis_block(NETWORK).txdata[0].output[0].script_pubkey.p2pk_public_key().unwrap()
+}
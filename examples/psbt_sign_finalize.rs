@@ -0,0 +1,112 @@
+extern crate monacoin;
+
+use std::str::FromStr;
+
+use monacoin::blockdata::script::Builder;
+use monacoin::blockdata::locktime::LockTime;
+use monacoin::blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut};
+use monacoin::consensus::encode;
+use monacoin::hashes::hex::{FromHex, ToHex};
+use monacoin::network::constants::Network;
+use monacoin::secp256k1::{Secp256k1, Signing, Verification};
+use monacoin::util::address::Address;
+use monacoin::util::bip32::{DerivationPath, ExtendedPrivKey};
+use monacoin::util::ecdsa::verify_input_signature;
+use monacoin::util::key::PublicKey;
+use monacoin::util::psbt::PartiallySignedTransaction;
+
+// This example walks a single-input P2WPKH spend through the PSBT roles on a
+// regtest fixture: the creator and updater build the PSBT, the signer adds a
+// partial signature using the BIP32 derivation recorded in the input, the
+// finalizer turns it into a witness and the extractor produces the network
+// transaction, whose signature is checked at the end.
+
+const NETWORK: Network = Network::MonacoinRegtest;
+const SEED: &str = "000102030405060708090a0b0c0d0e0f";
+const FUNDING_VALUE: u64 = 100_000_000;
+const FEE: u64 = 10_000;
+
+fn main() {
+    let secp = Secp256k1::new();
+    let master = ExtendedPrivKey::new_master(NETWORK, &Vec::from_hex(SEED).unwrap()).unwrap();
+    let fingerprint = master.fingerprint(&secp);
+
+    let receive_path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+    let change_path = DerivationPath::from_str("m/84'/1'/0'/1/0").unwrap();
+    let receive_pk = derive_pubkey(&secp, &master, &receive_path);
+    let change_pk = derive_pubkey(&secp, &master, &change_path);
+
+    let funding_tx = funding_fixture(&Address::p2wpkh(&receive_pk, NETWORK).unwrap());
+    println!("Funding transaction: {}", funding_tx.txid());
+
+    // Creator: an unsigned transaction sending the funds (minus the fee) to our change address
+    let unsigned_tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(funding_tx.txid(), 0),
+            script_sig: Default::default(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: FUNDING_VALUE - FEE,
+            script_pubkey: Address::p2wpkh(&change_pk, NETWORK).unwrap().script_pubkey(),
+        }],
+    };
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+
+    // Updater: add the spent output and the key origin information
+    psbt.inputs[0].witness_utxo = Some(funding_tx.output[0].clone());
+    psbt.inputs[0].hd_keypaths.insert(receive_pk, (fingerprint, receive_path));
+    psbt.outputs[0].hd_keypaths.insert(change_pk, (fingerprint, change_path));
+
+    // The PSBT would now be handed to the signer, e.g. a hardware wallet
+    let serialized = psbt.to_string();
+    println!("Unsigned PSBT: {}", serialized);
+    let mut psbt = PartiallySignedTransaction::from_str(&serialized).unwrap();
+
+    // Signer: sign every input with the keys derived along its BIP32 derivation paths
+    for (index, keys) in psbt.sign(&master, &secp).unwrap() {
+        println!("Input {}: {} signature(s) added", index, keys.len());
+    }
+    // Finalizer: check the signatures and turn them into the input's witness
+    psbt.finalize(&secp).unwrap();
+
+    let tx = psbt.extract_tx().unwrap();
+    verify(&secp, &tx, &funding_tx);
+    println!("Signed transaction {}: {}", tx.txid(), encode::serialize(&tx).to_hex());
+}
+
+/// Check the extracted transaction's signatures against the outputs it spends
+fn verify<C: Verification>(secp: &Secp256k1<C>, tx: &Transaction, funding_tx: &Transaction) {
+    for (index, input) in tx.input.iter().enumerate() {
+        assert_eq!(input.previous_output.txid, funding_tx.txid());
+        let prevout = &funding_tx.output[input.previous_output.vout as usize];
+        verify_input_signature(secp, tx, index, prevout).unwrap();
+        println!("Input {}: signature is valid", index);
+    }
+}
+
+fn derive_pubkey<C: Signing>(secp: &Secp256k1<C>, master: &ExtendedPrivKey, path: &DerivationPath) -> PublicKey {
+    master.derive_priv(secp, path).unwrap().private_key.public_key(secp)
+}
+
+/// A coinbase-like regtest transaction paying `FUNDING_VALUE` to `address`
+fn funding_fixture(address: &Address) -> Transaction {
+    Transaction {
+        version: 1,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Builder::new().push_int(101).push_int(0).into_script(),
+            sequence: Sequence::MAX,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: FUNDING_VALUE,
+            script_pubkey: address.script_pubkey(),
+        }],
+    }
+}
+
@@ -0,0 +1,142 @@
+extern crate monacoin;
+
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, process};
+
+use monacoin::blockdata::block::{header_chain_context_len, validate_header_chain, BlockHeader};
+use monacoin::blockdata::constants::genesis_block;
+use monacoin::consensus::encode;
+use monacoin::consensus::params::Params;
+use monacoin::network::{address, constants, message, message_blockdata, message_network};
+use monacoin::network::constants::Network;
+use monacoin::network::stream_reader::StreamReader;
+use monacoin::secp256k1;
+use monacoin::secp256k1::rand::Rng;
+
+// This example syncs block headers from a single peer, as an SPV client would:
+// it performs the version handshake, then repeatedly asks for the headers
+// following its tip and checks them with `validate_header_chain`, which needs
+// only the last `header_chain_context_len` headers of the chain as context.
+//
+// It needs network access, so it only runs when a peer is given:
+//
+//     cargo run --example spv_header_sync -- <ip[:port]> [monacoin|testnet|regtest] [max headers]
+
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: {} <ip[:port]> [network] [max headers]", args[0]);
+        process::exit(1);
+    }
+
+    let network = match args.get(2) {
+        Some(s) => Network::from_str(s).unwrap_or_else(|error| {
+            eprintln!("Error parsing network: {:?}", error);
+            process::exit(1);
+        }),
+        None => Network::Monacoin,
+    };
+    let max_headers = match args.get(3) {
+        Some(s) => s.parse().unwrap_or_else(|error| {
+            eprintln!("Error parsing header count: {:?}", error);
+            process::exit(1);
+        }),
+        None => 10_000,
+    };
+
+    let peer: SocketAddr = args[1].parse().or_else(|_| {
+        IpAddr::from_str(&args[1]).map(|ip| SocketAddr::new(ip, network.default_port()))
+    }).unwrap_or_else(|error| {
+        eprintln!("Error parsing address: {:?}", error);
+        process::exit(1);
+    });
+
+    let mut stream = TcpStream::connect(peer).unwrap_or_else(|error| {
+        eprintln!("Failed to open connection: {}", error);
+        process::exit(1);
+    });
+    let mut stream_reader = StreamReader::new(stream.try_clone().unwrap(), None);
+
+    let params = Params::new(network);
+    let context_len = header_chain_context_len(&params);
+    let mut chain = vec![genesis_block(network).header];
+
+    send(&mut stream, network, build_version_message(peer));
+    loop {
+        let reply: message::RawNetworkMessage = stream_reader.read_next().unwrap();
+        match reply.payload {
+            message::NetworkMessage::Version(version) => {
+                println!("Connected to {} ({}), at height {}", peer, version.user_agent, version.start_height);
+                send(&mut stream, network, message::NetworkMessage::Verack);
+            }
+            message::NetworkMessage::Verack => {
+                send(&mut stream, network, get_headers(&chain));
+            }
+            message::NetworkMessage::Ping(nonce) => {
+                send(&mut stream, network, message::NetworkMessage::Pong(nonce));
+            }
+            message::NetworkMessage::Headers(headers) => {
+                let count = headers.len();
+                let height = chain.len();
+                if let Err(error) = validate_header_chain(&chain[height.saturating_sub(context_len)..], &headers, height as u32, &params) {
+                    eprintln!("Invalid headers after height {}: {}", height - 1, error);
+                    process::exit(1);
+                }
+                chain.extend(headers);
+                let tip = chain.last().unwrap();
+                println!("Synced to height {}: {}", chain.len() - 1, tip.block_hash());
+
+                if count < message::MAX_HEADERS_ENTRIES as usize || chain.len() > max_headers {
+                    break;
+                }
+                send(&mut stream, network, get_headers(&chain));
+            }
+            _ => {}
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+/// Ask for the headers following our tip, with a locator so that the peer can
+/// find where our chain forks from its own.
+fn get_headers(chain: &[BlockHeader]) -> message::NetworkMessage {
+    let tip_height = chain.len() as u32 - 1;
+    message::NetworkMessage::GetHeaders(message_blockdata::GetHeadersMessage::from_locator_heights(
+        tip_height,
+        |height| chain.get(height as usize).map(BlockHeader::block_hash),
+        Default::default(),
+    ))
+}
+
+fn send(stream: &mut TcpStream, network: Network, payload: message::NetworkMessage) {
+    let message = message::RawNetworkMessage {
+        magic: network.magic(),
+        payload: payload,
+    };
+    stream.write_all(encode::serialize(&message).as_slice()).unwrap_or_else(|error| {
+        eprintln!("Failed to send message: {}", error);
+        process::exit(1);
+    });
+}
+
+fn build_version_message(peer: SocketAddr) -> message::NetworkMessage {
+    let my_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time error")
+        .as_secs();
+
+    message::NetworkMessage::Version(message_network::VersionMessage::new(
+        constants::ServiceFlags::NONE,
+        timestamp as i64,
+        address::Address::new(&peer, constants::ServiceFlags::NONE),
+        address::Address::new(&my_address, constants::ServiceFlags::NONE),
+        secp256k1::rand::thread_rng().gen(),
+        String::from("rust-example"),
+        0,
+    ))
+}
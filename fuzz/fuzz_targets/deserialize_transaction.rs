@@ -6,21 +6,20 @@ fn do_test(data: &[u8]) {
         Err(_) => {},
         Ok(mut tx) => {
             let ser = monacoin::consensus::encode::serialize(&tx);
-            assert_eq!(&ser[..], data);
+            // A witness flag without any witness is dropped when re-serializing
+            if tx.needs_witness_serialization() || data[4] != 0 {
+                assert_eq!(&ser[..], data);
+            } else {
+                let reparsed: monacoin::blockdata::transaction::Transaction = monacoin::consensus::encode::deserialize(&ser).unwrap();
+                assert_eq!(reparsed, tx);
+            }
             let len = ser.len();
             let calculated_weight = tx.get_weight();
             for input in &mut tx.input {
                 input.witness = vec![];
             }
             let no_witness_len = monacoin::consensus::encode::serialize(&tx).len();
-            // For 0-input transactions, `no_witness_len` will be incorrect because
-            // we serialize as segwit even after "stripping the witnesses". We need
-            // to drop two bytes (i.e. eight weight)
-            if tx.input.is_empty() {
-                assert_eq!(no_witness_len * 3 + len - 8, calculated_weight);
-            } else {
-                assert_eq!(no_witness_len * 3 + len, calculated_weight);
-            }
+            assert_eq!(no_witness_len * 3 + len, calculated_weight);
         },
     }
 }
@@ -20,24 +20,37 @@
 //! these blocks and the blockchain.
 //!
 
+
+use std::{cmp, fmt};
+use std::io;
+use std::sync::Arc;
+use std::error;
+
 use util;
 use util::Error::{BlockBadTarget, BlockBadProofOfWork};
-use util::hash::bitcoin_merkle_root;
+use util::difficulty;
+use util::hash::{bitcoin_merkle_root, merkle_tree_is_mutated};
 use hashes::{Hash, HashEngine};
-use hash_types::{Wtxid, BlockHash, TxMerkleNode, WitnessMerkleNode, WitnessCommitment};
+use hash_types::{Txid, Wtxid, BlockHash, TxMerkleNode, WitnessMerkleNode, WitnessCommitment};
 use util::uint::Uint256;
-use consensus::encode::{Encodable, serialize};
-use consensus::params::Params;
+use consensus::encode::{self, Decodable, Encodable, serialize};
+use consensus::params::{DeploymentPos, Params, DifficultyEra};
 use network::constants::Network;
+use blockdata::opcodes;
+use blockdata::script;
 use blockdata::transaction::Transaction;
-use blockdata::constants::{max_target, WITNESS_SCALE_FACTOR};
+use blockdata::constants::{MAX_BLOCK_WEIGHT, MIN_TRANSACTION_WEIGHT, WITNESS_SCALE_FACTOR};
 use VarInt;
 extern crate lyra2;
 extern crate scrypt;
 
 /// A block header, which contains all the block's information except
 /// the actual transactions
-#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+///
+/// The `Ord` implementation compares fields in the order they are encoded.
+/// It exists so headers can be kept in ordered containers and does not
+/// reflect chain order or work.
+#[derive(Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub struct BlockHeader {
     /// The protocol version. Should always be 1.
     pub version: i32,
@@ -54,6 +67,34 @@ pub struct BlockHeader {
     pub nonce: u32,
 }
 
+impl fmt::Debug for BlockHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlockHeader")
+            .field("block_hash", &self.block_hash())
+            .field("version", &format_args!("{:#010x}", self.version))
+            .field("prev_blockhash", &self.prev_blockhash)
+            .field("merkle_root", &self.merkle_root)
+            .field("time", &self.time)
+            .field("bits", &format_args!("{:#010x}", self.bits))
+            .field("nonce", &self.nonce)
+            .finish()
+    }
+}
+
+/// The start of a witness commitment output script, `OP_RETURN` followed by
+/// a push of the `0xaa21a9ed` header and the 32-byte commitment.
+const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// Builds the output script committing the coinbase to the witnesses of a
+/// block, given the block's [Block::compute_witness_root] and the witness
+/// reserved value, which is the single witness element of the coinbase input.
+pub fn make_witness_commitment(witness_root: &WitnessMerkleNode, reserved_value: &[u8]) -> script::Script {
+    let commitment = Block::compute_witness_commitment(witness_root, reserved_value);
+    let mut script = WITNESS_COMMITMENT_PREFIX.to_vec();
+    script.extend_from_slice(&commitment[..]);
+    script::Script::from(script)
+}
+
 /// A Bitcoin block, which is a collection of transactions with an attached
 /// proof of work.
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -65,21 +106,41 @@ pub struct Block {
 }
 
 impl Block {
+    /// Decodes a block from its consensus encoding in hex, as returned by the
+    /// `getblock` RPC with verbosity 0.
+    pub fn from_hex(hex: &str) -> Result<Block, encode::FromHexError> {
+        encode::decode_hex(hex)
+    }
+
+    /// Returns the consensus encoding of the block in lower case hex.
+    pub fn to_hex(&self) -> String {
+        encode::serialize_hex(self)
+    }
+
     /// Return the block hash.
     pub fn block_hash(&self) -> BlockHash {
         self.header.block_hash()
     }
 
+    /// The coinbase transaction, which must be the first transaction of a
+    /// block. `None` if the first transaction isn't a coinbase.
+    pub fn coinbase(&self) -> Option<&Transaction> {
+        self.txdata.first().filter(|tx| tx.is_coin_base())
+    }
+
     /// check if merkle root of header matches merkle root of the transaction list
     pub fn check_merkle_root (&self) -> bool {
-        self.header.merkle_root == self.merkle_root()
+        match self.compute_merkle_root() {
+            Some(merkle_root) => self.header.merkle_root == merkle_root,
+            None => false,
+        }
     }
 
     /// check if witness commitment in coinbase is matching the transaction list
     pub fn check_witness_commitment(&self) -> bool {
 
         // witness commitment is optional if there are no transactions using SegWit in the block
-        if self.txdata.iter().all(|t| t.input.iter().all(|i| i.witness.is_empty())) {
+        if !self.has_witness() {
             return true;
         }
         if !self.txdata.is_empty() {
@@ -89,12 +150,13 @@ impl Block {
                 if let Some(pos) = coinbase.output.iter()
                     .rposition(|o| {
                         o.script_pubkey.len () >= 38 &&
-                        o.script_pubkey[0..6] == [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed] }) {
+                        o.script_pubkey[0..6] == WITNESS_COMMITMENT_PREFIX }) {
                     let commitment = WitnessCommitment::from_slice(&coinbase.output[pos].script_pubkey.as_bytes()[6..38]).unwrap();
                     // witness reserved value is in coinbase input witness
                     if coinbase.input[0].witness.len() == 1 && coinbase.input[0].witness[0].len() == 32 {
-                        let witness_root = self.witness_root();
-                        return commitment == Self::compute_witness_commitment(&witness_root, coinbase.input[0].witness[0].as_slice())
+                        if let Some(witness_root) = self.compute_witness_root() {
+                            return commitment == Self::compute_witness_commitment(&witness_root, coinbase.input[0].witness[0].as_slice());
+                        }
                     }
                 }
             }
@@ -102,10 +164,114 @@ impl Block {
         false
     }
 
+    /// Whether the transaction merkle tree has identical siblings, which
+    /// happens when the last transactions of a valid block are duplicated
+    /// without changing its merkle root (CVE-2012-2459). Such a block is
+    /// invalid, but must not cause the valid block with the same hash to be
+    /// rejected.
+    pub fn is_merkle_mutated(&self) -> bool {
+        merkle_tree_is_mutated(self.txdata.iter().map(Transaction::txid).collect())
+    }
+
+    /// Check that no two transactions have the same txid, which the
+    /// duplication of transactions detected by [Block::is_merkle_mutated]
+    /// always causes.
+    pub fn check_duplicate_txids(&self) -> bool {
+        let mut txids: Vec<Txid> = self.txdata.iter().map(Transaction::txid).collect();
+        txids.sort();
+        txids.windows(2).all(|pair| pair[0] != pair[1])
+    }
+
+    /// Whether any transaction of the block has witness data.
+    pub fn has_witness(&self) -> bool {
+        self.txdata.iter().any(Transaction::has_witness)
+    }
+
+    /// Check that the block at `height` has no witness data if segwit is not
+    /// active there yet. Only the known activation height of
+    /// [DeploymentPos::Segwit] is considered, without one this always passes.
+    pub fn check_no_witness_before_activation(&self, params: &Params, height: u32) -> bool {
+        match params.deployment(DeploymentPos::Segwit).activation_height {
+            Some(activation_height) if height < activation_height => !self.has_witness(),
+            _ => true,
+        }
+    }
+
+    /// Check the structure of the block at `height`, independently of the
+    /// UTXO set and of the proof-of-work of its header: the merkle root and
+    /// witness commitment, the absence of CVE-2012-2459 mutations and of
+    /// witness data before segwit activation, the coinbase being the first and
+    /// only coinbase transaction, and the weight limit.
+    pub fn validate_structure(&self, params: &Params, height: u32) -> Result<(), BlockStructureError> {
+        if !self.check_merkle_root() {
+            return Err(BlockStructureError::BadMerkleRoot);
+        }
+        if self.is_merkle_mutated() || !self.check_duplicate_txids() {
+            return Err(BlockStructureError::MutatedMerkleTree);
+        }
+        if self.coinbase().is_none() {
+            return Err(BlockStructureError::NoCoinbase);
+        }
+        if let Some(index) = self.txdata.iter().skip(1).position(Transaction::is_coin_base) {
+            return Err(BlockStructureError::MultipleCoinbases(index + 1));
+        }
+        if !self.check_no_witness_before_activation(params, height) {
+            return Err(BlockStructureError::UnexpectedWitness);
+        }
+        if !self.check_witness_commitment() {
+            return Err(BlockStructureError::BadWitnessCommitment);
+        }
+        let weight = self.get_weight();
+        if weight > MAX_BLOCK_WEIGHT as usize {
+            return Err(BlockStructureError::Overweight(weight));
+        }
+        Ok(())
+    }
+
+    /// Calculate the transaction merkle root, `None` if there are no transactions.
+    pub fn compute_merkle_root(&self) -> Option<TxMerkleNode> {
+        let hashes = self.txdata.iter().map(Transaction::txid);
+        bitcoin_merkle_root(hashes).map(|root| root.as_hash().into())
+    }
+
     /// Calculate the transaction merkle root.
+    #[deprecated(since="0.25.1", note="please use `compute_merkle_root` instead")]
     pub fn merkle_root(&self) -> TxMerkleNode {
-        let hashes = self.txdata.iter().map(|obj| obj.txid().as_hash());
-        bitcoin_merkle_root(hashes).into()
+        self.compute_merkle_root().unwrap_or_default()
+    }
+
+    /// Get the block height the coinbase commits to, as required by BIP34
+    /// from version 2 blocks on.
+    ///
+    /// The height is the first item of the coinbase scriptSig, a minimally
+    /// encoded non-negative script number.
+    pub fn bip34_block_height(&self) -> Result<u64, Bip34Error> {
+        if self.header.version < 2 {
+            return Err(Bip34Error::Unsupported);
+        }
+        let coinbase = self.coinbase().ok_or(Bip34Error::NotPresent)?;
+        let input = coinbase.input.first().ok_or(Bip34Error::NotPresent)?;
+        let push = match input.script_sig.instructions_minimal().next() {
+            Some(Ok(push)) => push,
+            _ => return Err(Bip34Error::NotPresent),
+        };
+        match push {
+            script::Instruction::PushBytes(data) => match script::read_scriptint_non_minimal(data) {
+                Ok(height) if height >= 0 => Ok(height as u64),
+                _ => Err(Bip34Error::UnexpectedPush(data.to_vec())),
+            },
+            script::Instruction::Op(op) => match op.classify() {
+                opcodes::Class::PushNum(n) if n >= 0 => Ok(n as u64),
+                _ => Err(Bip34Error::NotPresent),
+            },
+        }
+    }
+
+    /// Take the transactions out of the block so they can be shared between
+    /// threads and indexes without being cloned. Copy the header first if it
+    /// is still needed.
+    pub fn into_shared(self) -> Vec<Arc<Transaction>> {
+        self.txdata.into_iter().map(Arc::new).collect()
     }
 
     /// compute witness commitment for the transaction list
@@ -116,8 +282,12 @@ impl Block {
         WitnessCommitment::from_engine(encoder)
     }
 
-    /// Merkle root of transactions hashed for witness
-    pub fn witness_root(&self) -> WitnessMerkleNode {
+    /// Merkle root of transactions hashed for witness, `None` if there are
+    /// no transactions.
+    ///
+    /// The coinbase counts as all zeroes, so the root doesn't change when the
+    /// witness commitment is added to the coinbase.
+    pub fn compute_witness_root(&self) -> Option<WitnessMerkleNode> {
         let hashes = self.txdata.iter().enumerate().map(|(i, t)|
             if i == 0 {
                 // Replace the first hash with zeroes.
@@ -126,7 +296,13 @@ impl Block {
                 t.wtxid().as_hash()
             }
         );
-        bitcoin_merkle_root(hashes).into()
+        bitcoin_merkle_root(hashes).map(|root| root.into())
+    }
+
+    /// Merkle root of transactions hashed for witness.
+    #[deprecated(since="0.25.1", note="please use `compute_witness_root` instead")]
+    pub fn witness_root(&self) -> Option<WitnessMerkleNode> {
+        self.compute_witness_root()
     }
 
     /// Get the size of the block
@@ -146,6 +322,17 @@ impl Block {
 }
 
 impl BlockHeader {
+    /// Decodes a header from its consensus encoding in hex, as returned by the
+    /// `getblockheader` RPC with verbose set to false.
+    pub fn from_hex(hex: &str) -> Result<BlockHeader, encode::FromHexError> {
+        encode::decode_hex(hex)
+    }
+
+    /// Returns the consensus encoding of the header in lower case hex.
+    pub fn to_hex(&self) -> String {
+        encode::serialize_hex(self)
+    }
+
     /// Return the block hash.
     pub fn block_hash(&self) -> BlockHash {
         let mut engine = BlockHash::engine();
@@ -175,12 +362,19 @@ impl BlockHeader {
         }
     }
 
+    /// Return the proof-of-work hash of the block at `height`, using the hash
+    /// function of the [`DifficultyEra`] that height belongs to.
+    pub fn pow_hash_at(&self, height: u32, params: &Params) -> BlockHash {
+        self.block_pow_hash(DifficultyEra::at_height(params, height).uses_lyra2rev2())
+    }
+
     /// Computes the target [0, T] that a blockhash must land in to be valid
     pub fn target(&self) -> Uint256 {
         Self::u256_from_compact_target(self.bits)
     }
 
-    /// Computes the target value in [`Uint256`] format, from a compact representation.
+    /// Computes the target value in [`Uint256`] format, from a compact
+    /// representation. Negative and overflowing values give a target of zero.
     ///
     /// [`Uint256`]: ../../util/uint/struct.Uint256.html
     ///
@@ -194,25 +388,7 @@ impl BlockHeader {
     /// );
     /// ```
     pub fn u256_from_compact_target(bits: u32) -> Uint256 {
-        // This is a floating-point "compact" encoding originally used by
-        // OpenSSL, which satoshi put into consensus code, so we're stuck
-        // with it. The exponent needs to have 3 subtracted from it, hence
-        // this goofy decoding code:
-        let (mant, expt) = {
-            let unshifted_expt = bits >> 24;
-            if unshifted_expt <= 3 {
-                ((bits & 0xFFFFFF) >> (8 * (3 - unshifted_expt as usize)), 0)
-            } else {
-                (bits & 0xFFFFFF, 8 * ((bits >> 24) - 3))
-            }
-        };
-
-        // The mantissa is signed but may not be negative
-        if mant > 0x7FFFFF {
-            Default::default()
-        } else {
-            Uint256::from_u64(mant as u64).unwrap() << (expt as usize)
-        }
+        Uint256::from_compact(bits).unwrap_or_default()
     }
 
     /// Computes the target value in float format from Uint256 format.
@@ -233,39 +409,586 @@ impl BlockHeader {
         compact | (size << 24) as u32
     }
 
-    /// Compute the popular "difficulty" measure for mining
-    pub fn difficulty(&self, network: Network) -> u64 {
-        (max_target(network) / self.target()).low_u64()
+    /// Whether the version of this header signals readiness for the version
+    /// bits (BIP9) deployment using `bit`, which requires the top three bits
+    /// of the version to be `001`.
+    pub fn is_signalling(&self, bit: u8) -> bool {
+        const VERSIONBITS_TOP_MASK: u32 = 0xE0000000;
+        const VERSIONBITS_TOP_BITS: u32 = 0x20000000;
+        let version = self.version as u32;
+        bit < 29 && version & VERSIONBITS_TOP_MASK == VERSIONBITS_TOP_BITS && version & (1 << bit) != 0
     }
 
-    /// Checks that the proof-of-work for the block is valid.
+    /// Compute the popular "difficulty" measure for mining, relative to the
+    /// target of difficulty 1 (compact `0x1d00ffff`) on every network
+    pub fn difficulty(&self, _: Network) -> u64 {
+        (BlockHeader::u256_from_compact_target(0x1d00ffff) / self.target()).low_u64()
+    }
+
+    /// The difficulty of this header as a float, as Monacoin Core reports it;
+    /// see [difficulty::difficulty_float]
+    pub fn difficulty_float(&self, params: &Params) -> f64 {
+        difficulty::difficulty_float(self.bits, params)
+    }
+
+    /// Checks that the proof-of-work for the block at `height` is valid.
+    ///
+    /// The bits of the header must be exactly the compact encoding of
+    /// `required_target`, like Core compares them with the bits it computed,
+    /// otherwise a header with a weaker target would be accepted as long as
+    /// its hash is below that. Then the proof-of-work hash must be at most
+    /// the target.
     pub fn validate_pow(&self, required_target: &Uint256, height: &u32, consensus_params: &Params) -> Result<(), util::Error> {
-        // let target = &self.target(); //TODO monacoin is OK?
-        let target = required_target;
-        if target != required_target {
+        if self.bits != BlockHeader::compact_target_from_u256(required_target) {
             return Err(BlockBadTarget);
         }
-        let data: [u8; 32] = self.block_pow_hash(height >= &consensus_params.switch_lyra2rev2_dgwblock).into_inner();
+        let target = required_target;
+        let data: [u8; 32] = self.pow_hash_at(*height, consensus_params).into_inner();
         let mut ret = [0u64; 4];
         util::endian::bytes_to_u64_slice_le(&data, &mut ret);
         let hash = &Uint256(ret);
         if hash <= target { Ok(()) } else { Err(BlockBadProofOfWork) }
     }
 
-    /// Returns the total work of the block
+    /// Returns the total work of the block, the expected number of hashes
+    /// needed to find a hash below the target. Like Bitcoin Core's
+    /// `GetBlockProof`, it is zero if the target is zero, negative or
+    /// overflowing.
     pub fn work(&self) -> Uint256 {
+        let target = match Uint256::from_compact(self.bits) {
+            Some(target) if target != Uint256::default() => target,
+            _ => return Default::default(),
+        };
         // 2**256 / (target + 1) == ~target / (target+1) + 1    (eqn shamelessly stolen from bitcoind)
-        let mut ret = !self.target();
-        let mut ret1 = self.target();
+        let mut ret1 = target;
         ret1.increment();
-        ret = ret / ret1;
+        let mut ret = !target / ret1;
         ret.increment();
         ret
     }
+
+    /// Checks the timestamp against the median time past `mtp` of the
+    /// previous blocks, see [median_time_past], and against the network
+    /// adjusted current time `adjusted_time`.
+    pub fn validate_timestamp(&self, mtp: u32, adjusted_time: u32) -> Result<(), TimeError> {
+        if self.time <= mtp {
+            return Err(TimeError::TooOld { time: self.time, mtp: mtp });
+        }
+        if self.time as u64 > adjusted_time as u64 + MAX_FUTURE_BLOCK_TIME as u64 {
+            return Err(TimeError::TooNew { time: self.time, adjusted_time: adjusted_time });
+        }
+        Ok(())
+    }
+}
+
+/// An error when looking for a BIP34 block height.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Bip34Error {
+    /// The block does not support BIP34 yet.
+    Unsupported,
+    /// No push was present where the BIP34 push was expected.
+    NotPresent,
+    /// The BIP34 push was not a non-negative script number of at most four bytes.
+    UnexpectedPush(Vec<u8>),
+}
+
+impl fmt::Display for Bip34Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bip34Error::Unsupported => f.write_str("block doesn't support BIP34"),
+            Bip34Error::NotPresent => f.write_str("BIP34 push not present in block's coinbase"),
+            Bip34Error::UnexpectedPush(ref p) => write!(f, "unexpected byte push of > 4 bytes or negative: {:?}", p),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Bip34Error {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// A rule of the block structure broken by a block, see
+/// [Block::validate_structure].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockStructureError {
+    /// The merkle root of the header doesn't match the transactions
+    BadMerkleRoot,
+    /// The merkle tree of the transactions is mutated or they contain a
+    /// txid twice
+    MutatedMerkleTree,
+    /// The first transaction is not a coinbase, or there are no transactions
+    NoCoinbase,
+    /// The transaction at the index is another coinbase
+    MultipleCoinbases(usize),
+    /// A transaction has witness data before segwit activation
+    UnexpectedWitness,
+    /// The witness commitment is missing or doesn't match the transactions
+    BadWitnessCommitment,
+    /// The block weight is above [MAX_BLOCK_WEIGHT]
+    Overweight(usize),
+}
+
+impl fmt::Display for BlockStructureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockStructureError::BadMerkleRoot => f.write_str("merkle root doesn't match the transactions"),
+            BlockStructureError::MutatedMerkleTree => f.write_str("duplicate transactions in the merkle tree"),
+            BlockStructureError::NoCoinbase => f.write_str("first transaction is not a coinbase"),
+            BlockStructureError::MultipleCoinbases(i) => write!(f, "transaction {} is another coinbase", i),
+            BlockStructureError::UnexpectedWitness => f.write_str("witness data before segwit activation"),
+            BlockStructureError::BadWitnessCommitment => f.write_str("witness commitment doesn't match the transactions"),
+            BlockStructureError::Overweight(weight) => write!(f, "block weight {} is above the limit of {}", weight, MAX_BLOCK_WEIGHT),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for BlockStructureError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// A header which breaks a rule of the header chain, identified by its index
+/// in the new headers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeaderChainError {
+    /// The preceding headers don't go back far enough to check the header
+    MissingContext(usize),
+    /// The header doesn't commit to the hash of the previous header
+    PrevHashMismatch(usize),
+    /// The timestamp is not above the median time past of the previous headers
+    TimeTooOld(usize),
+    /// The bits don't encode the target required by the difficulty retarget
+    BadBits {
+        /// Index of the header
+        index: usize,
+        /// Bits required by the retarget
+        expected: u32,
+        /// Bits of the header
+        actual: u32,
+    },
+    /// The proof-of-work hash is above the target
+    BadProofOfWork(usize),
+}
+
+impl HeaderChainError {
+    /// The index of the offending header in the new headers.
+    pub fn index(&self) -> usize {
+        match *self {
+            HeaderChainError::MissingContext(index) => index,
+            HeaderChainError::PrevHashMismatch(index) => index,
+            HeaderChainError::TimeTooOld(index) => index,
+            HeaderChainError::BadBits { index, .. } => index,
+            HeaderChainError::BadProofOfWork(index) => index,
+        }
+    }
+}
+
+impl fmt::Display for HeaderChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaderChainError::MissingContext(i) => write!(f, "not enough previous headers to check header {}", i),
+            HeaderChainError::PrevHashMismatch(i) => write!(f, "header {} doesn't link to the previous header", i),
+            HeaderChainError::TimeTooOld(i) => write!(f, "timestamp of header {} is not above the median time past", i),
+            HeaderChainError::BadBits { index, expected, actual } => write!(f, "header {} has bits {:08x} instead of {:08x}", index, actual, expected),
+            HeaderChainError::BadProofOfWork(i) => write!(f, "proof-of-work of header {} is above its target", i),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for HeaderChainError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// Number of previous headers the median time past is taken over
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// How far, in seconds, a block timestamp may be ahead of the network
+/// adjusted time.
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// A block timestamp which is out of the allowed range
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimeError {
+    /// The timestamp is not above the median time past of the previous blocks
+    TooOld {
+        /// Timestamp of the block
+        time: u32,
+        /// Median time past of the previous blocks
+        mtp: u32,
+    },
+    /// The timestamp is more than [MAX_FUTURE_BLOCK_TIME] ahead of the
+    /// network adjusted time
+    TooNew {
+        /// Timestamp of the block
+        time: u32,
+        /// Network adjusted time
+        adjusted_time: u32,
+    },
+}
+
+impl fmt::Display for TimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimeError::TooOld { time, mtp } => write!(f, "block time {} is not above the median time past {}", time, mtp),
+            TimeError::TooNew { time, adjusted_time } => write!(f, "block time {} is too far ahead of the adjusted time {}", time, adjusted_time),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for TimeError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// The median time past of a block, the median timestamp of the 11 blocks
+/// before it, given in `last_11` in chain order. Near the genesis block there
+/// are fewer previous blocks, and it is the median of those, the later one of
+/// the two middle timestamps for an even number, as in Monacoin Core.
+///
+/// When more than 11 headers are given, only the last 11 are used. Without
+/// any headers it is zero.
+pub fn median_time_past(last_11: &[BlockHeader]) -> u32 {
+    let start = last_11.len().saturating_sub(MEDIAN_TIME_SPAN);
+    let mut times: Vec<u32> = last_11[start..].iter().map(|h| h.time).collect();
+    if times.is_empty() {
+        return 0;
+    }
+    times.sort();
+    times[times.len() / 2]
+}
+
+/// Check that `new_headers`, the first of which is at `start_height`, extend
+/// the chain ending with the `context` headers. Each header must link to the
+/// previous one, have a timestamp above the median of the last 11 headers,
+/// have the bits required by the difficulty retarget of its era, and a
+/// proof-of-work hash, with the hash function of its era, below its target.
+///
+/// The bits of the Kimoto Gravity Well and DigiShield eras, from
+/// [Params::switch_kgw_block] up to [Params::switch_lyra2rev2_dgwblock], are
+/// not checked, since their retargets aren't implemented. Those blocks are
+/// below the checkpoints of Monacoin Core, which doesn't check them either.
+///
+/// `context` needs the 11 headers before `start_height` for the timestamp
+/// checks and 24 for Dark Gravity Wave, while the classic retarget needs the
+/// first header of the retarget period at a period boundary. On networks
+/// which allow minimum difficulty blocks, a header may also need all headers
+/// back to the last one with regular difficulty. A chain starting from the
+/// genesis block, which isn't checked, has an empty `context`.
+///
+/// The rule that timestamps can't be too far in the future depends on the
+/// current time and is not checked.
+pub fn validate_header_chain(
+    context: &[BlockHeader],
+    new_headers: &[BlockHeader],
+    start_height: u32,
+    params: &Params,
+) -> Result<(), HeaderChainError> {
+    let headers: Vec<BlockHeader> = context.iter().chain(new_headers).cloned().collect();
+    for (index, header) in new_headers.iter().enumerate() {
+        let height = start_height + index as u32;
+        let past = &headers[..context.len() + index];
+        let prev = match past.last() {
+            Some(prev) => prev,
+            None if height == 0 => continue,
+            None => return Err(HeaderChainError::MissingContext(index)),
+        };
+        if header.prev_blockhash != prev.block_hash() {
+            return Err(HeaderChainError::PrevHashMismatch(index));
+        }
+
+        let time_span = cmp::min(height as usize, MEDIAN_TIME_SPAN);
+        if past.len() < time_span {
+            return Err(HeaderChainError::MissingContext(index));
+        }
+        if header.time <= median_time_past(&past[past.len() - time_span..]) {
+            return Err(HeaderChainError::TimeTooOld(index));
+        }
+
+        match DifficultyEra::at_height(params, height) {
+            DifficultyEra::KimotoGravityWell | DifficultyEra::DigiShield => {}
+            DifficultyEra::Original | DifficultyEra::Lyra2Rev2Dgw3 => {
+                let expected = difficulty::next_work_required(past, height, header.time, params)
+                    .ok_or(HeaderChainError::MissingContext(index))?;
+                if header.bits != expected {
+                    return Err(HeaderChainError::BadBits { index: index, expected: expected, actual: header.bits });
+                }
+            }
+        }
+        if header.validate_pow(&header.target(), &height, params).is_err() {
+            return Err(HeaderChainError::BadProofOfWork(index));
+        }
+    }
+    Ok(())
+}
+
+/// The number of headers before `start_height` which are always enough
+/// `context` for [validate_header_chain]: one retarget interval and the
+/// header before it, which cover the classic retarget and the walk back over
+/// minimum difficulty blocks, as well as Dark Gravity Wave and the median
+/// time past. A client which keeps only the tail of its chain in memory can
+/// pass that many headers.
+pub fn header_chain_context_len(params: &Params) -> usize {
+    let interval = params.difficulty_adjustment_interval() as usize;
+    cmp::max(interval + 1, cmp::max(difficulty::DGW_PAST_BLOCKS, MEDIAN_TIME_SPAN))
 }
 
 impl_consensus_encoding!(BlockHeader, version, prev_blockhash, merkle_root, time, bits, nonce);
-impl_consensus_encoding!(Block, header, txdata);
+
+impl Encodable for Block {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        Ok(self.header.consensus_encode(&mut s)? + self.txdata.consensus_encode(s)?)
+    }
+}
+
+impl Decodable for Block {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let header = Decodable::consensus_decode(&mut d)?;
+        // no block fitting in the weight limit has more transactions
+        let count = encode::read_compact_size(&mut d)?;
+        if count > (MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT) as u64 {
+            return Err(encode::Error::ParseFailed("too many transactions in block"));
+        }
+        let mut txdata = Vec::with_capacity(encode::prealloc_capacity::<Transaction>(count));
+        for _ in 0..count {
+            txdata.push(Decodable::consensus_decode(&mut d)?);
+        }
+        Ok(Block {
+            header: header,
+            txdata: txdata,
+        })
+    }
+}
+
+/// A block whose transactions are kept in their raw consensus encoding and
+/// only decoded on demand.
+///
+/// Creating one decodes the header and walks the transactions just far enough
+/// to find where each of them ends, which is much cheaper than decoding them.
+/// This is useful when only the header, a few transactions or the txids are
+/// needed.
+#[derive(Clone, Debug)]
+pub struct BlockUncheckedTxs<'a> {
+    /// The block header
+    pub header: BlockHeader,
+    raw: &'a [u8],
+    txs: Vec<RawTx>,
+}
+
+/// Where a transaction is in the raw block.
+#[derive(Copy, Clone, Debug)]
+struct RawTx {
+    start: usize,
+    end: usize,
+    /// For segwit transactions, the start of the inputs (after the marker and
+    /// flag) and the start of the witnesses.
+    segwit: Option<(usize, usize)>,
+}
+
+impl RawTx {
+    fn txid(&self, raw: &[u8]) -> Txid {
+        match self.segwit {
+            None => Txid::hash(&raw[self.start..self.end]),
+            Some((inputs, witnesses)) => {
+                let mut enc = Txid::engine();
+                enc.input(&raw[self.start..self.start + 4]);
+                enc.input(&raw[inputs..witnesses]);
+                enc.input(&raw[self.end - 4..self.end]);
+                Txid::from_engine(enc)
+            }
+        }
+    }
+}
+
+/// Steps over consensus encoded transactions without decoding them.
+struct TxSkipper<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> TxSkipper<'a> {
+    fn skip(&mut self, n: u64) -> Result<(), encode::Error> {
+        if n > (self.data.len() - self.pos) as u64 {
+            return Err(encode::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
+        }
+        self.pos += n as usize;
+        Ok(())
+    }
+
+    fn byte(&mut self) -> Result<u8, encode::Error> {
+        self.skip(1)?;
+        Ok(self.data[self.pos - 1])
+    }
+
+    fn compact_size(&mut self) -> Result<u64, encode::Error> {
+        let mut rest = &self.data[self.pos..];
+        let n = encode::read_compact_size(&mut rest)?;
+        self.pos = self.data.len() - rest.len();
+        Ok(n)
+    }
+
+    fn skip_bytes(&mut self) -> Result<(), encode::Error> {
+        let len = self.compact_size()?;
+        self.skip(len)
+    }
+
+    /// Steps over a transaction, with the same checks on its structure as
+    /// decoding it does.
+    fn tx(&mut self) -> Result<RawTx, encode::Error> {
+        let start = self.pos;
+        self.skip(4)?; // version
+        let mut input_count = self.compact_size()?;
+        let mut inputs = None;
+        if input_count == 0 {
+            match self.byte()? {
+                1 => {}
+                x => return Err(encode::Error::UnsupportedSegwitFlag(x)),
+            }
+            inputs = Some(self.pos);
+            input_count = self.compact_size()?;
+            if input_count == 0 {
+                return Err(encode::Error::ParseFailed("witness flag set but no inputs"));
+            }
+        }
+        for _ in 0..input_count {
+            self.skip(36)?; // previous output
+            self.skip_bytes()?; // script_sig
+            self.skip(4)?; // sequence
+        }
+        for _ in 0..self.compact_size()? {
+            self.skip(8)?; // value
+            self.skip_bytes()?; // script_pubkey
+        }
+        let segwit = match inputs {
+            Some(inputs) => {
+                let witnesses = self.pos;
+                for _ in 0..input_count {
+                    for _ in 0..self.compact_size()? {
+                        self.skip_bytes()?;
+                    }
+                }
+                Some((inputs, witnesses))
+            }
+            None => None,
+        };
+        self.skip(4)?; // lock_time
+        Ok(RawTx {
+            start: start,
+            end: self.pos,
+            segwit: segwit,
+        })
+    }
+}
+
+impl<'a> BlockUncheckedTxs<'a> {
+    /// Decodes the header of a consensus encoded block and finds its
+    /// transactions. Fails if the data is not exactly one block, or if any of
+    /// the transactions is truncated or malformed in a way that decoding it
+    /// would detect from its structure alone.
+    pub fn from_slice(data: &'a [u8]) -> Result<BlockUncheckedTxs<'a>, encode::Error> {
+        let mut rest = data;
+        let header = BlockHeader::consensus_decode(&mut rest)?;
+        let count = encode::read_compact_size(&mut rest)?;
+        if count > (MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT) as u64 {
+            return Err(encode::Error::ParseFailed("too many transactions in block"));
+        }
+        let mut skipper = TxSkipper {
+            data: data,
+            pos: data.len() - rest.len(),
+        };
+        let mut txs = Vec::with_capacity(encode::prealloc_capacity::<RawTx>(count));
+        for _ in 0..count {
+            txs.push(skipper.tx()?);
+        }
+        if skipper.pos != data.len() {
+            return Err(encode::Error::ParseFailed("data not consumed entirely when explicitly deserializing"));
+        }
+        Ok(BlockUncheckedTxs {
+            header: header,
+            raw: data,
+            txs: txs,
+        })
+    }
+
+    /// Return the block hash.
+    pub fn block_hash(&self) -> BlockHash {
+        self.header.block_hash()
+    }
+
+    /// The number of transactions in the block.
+    pub fn tx_count(&self) -> usize {
+        self.txs.len()
+    }
+
+    /// The consensus encoding of the transaction at `index`, including any
+    /// witness data.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is not less than [BlockUncheckedTxs::tx_count].
+    pub fn raw_tx(&self, index: usize) -> &'a [u8] {
+        let tx = self.txs[index];
+        &self.raw[tx.start..tx.end]
+    }
+
+    /// Decodes the transaction at `index`.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is not less than [BlockUncheckedTxs::tx_count].
+    pub fn decode_tx(&self, index: usize) -> Result<Transaction, encode::Error> {
+        encode::deserialize(self.raw_tx(index))
+    }
+
+    /// The txids of the transactions in the block, in order. They are hashed
+    /// from the raw transactions without decoding them.
+    pub fn iter_txids<'b>(&'b self) -> RawTxids<'a, 'b> {
+        RawTxids {
+            raw: self.raw,
+            txs: self.txs.iter(),
+        }
+    }
+
+    /// Decodes all the transactions into a [Block].
+    pub fn decode(&self) -> Result<Block, encode::Error> {
+        let mut txdata = Vec::with_capacity(self.txs.len());
+        for index in 0..self.txs.len() {
+            txdata.push(self.decode_tx(index)?);
+        }
+        Ok(Block {
+            header: self.header,
+            txdata: txdata,
+        })
+    }
+}
+
+/// Iterator over the txids of a [BlockUncheckedTxs].
+pub struct RawTxids<'a, 'b> {
+    raw: &'a [u8],
+    txs: ::std::slice::Iter<'b, RawTx>,
+}
+
+impl<'a, 'b> Iterator for RawTxids<'a, 'b> {
+    type Item = Txid;
+
+    fn next(&mut self) -> Option<Txid> {
+        self.txs.next().map(|tx| tx.txid(self.raw))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.txs.size_hint()
+    }
+}
+
+impl<'a, 'b> ExactSizeIterator for RawTxids<'a, 'b> {}
 serde_struct_impl!(BlockHeader, version, prev_blockhash, merkle_root, time, bits, nonce);
 serde_struct_impl!(Block, header, txdata);
 
@@ -273,8 +996,17 @@ serde_struct_impl!(Block, header, txdata);
 mod tests {
     use hashes::hex::FromHex;
 
-    use blockdata::block::{Block, BlockHeader};
-    use consensus::encode::{deserialize, serialize};
+    use blockdata::block::{header_chain_context_len, make_witness_commitment, median_time_past, validate_header_chain, Block, BlockHeader, BlockStructureError, BlockUncheckedTxs, HeaderChainError, TimeError};
+    use blockdata::constants::MAX_BLOCK_WEIGHT;
+    use blockdata::constants::genesis_block;
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{CoinbaseError, OutPoint, Sequence, Transaction, TxIn, TxOut};
+    use consensus::encode::{self, deserialize, serialize, VarInt};
+    use hashes::Hash;
+    use hash_types::{BlockHash, Txid, Wtxid};
+    use consensus::params::{DeploymentPos, Params};
+    use network::constants::Network;
+    use util::{self, uint::Uint256};
 
     #[test]
     fn block_test() {
@@ -292,7 +1024,7 @@ mod tests {
         let real_decode = decode.unwrap();
         assert_eq!(real_decode.header.version, 1);
         assert_eq!(serialize(&real_decode.header.prev_blockhash), prevhash);
-        assert_eq!(real_decode.header.merkle_root, real_decode.merkle_root());
+        assert_eq!(real_decode.header.merkle_root, real_decode.compute_merkle_root().unwrap());
         assert_eq!(serialize(&real_decode.header.merkle_root), merkle);
         assert_eq!(real_decode.header.time, 1231965655);
         assert_eq!(real_decode.header.bits, 486604799);
@@ -304,14 +1036,18 @@ mod tests {
 
         // should be also ok for a non-witness block as commitment is optional in that case
         assert!(real_decode.check_witness_commitment());
+        assert_eq!(real_decode.bip34_block_height(), Err(super::Bip34Error::Unsupported));
 
         assert_eq!(serialize(&real_decode), some_block);
     }
 
+    // Testnet block 000000000000045e0b1660b6445b5e5c5ab63c9a4f956be7e1e69be04fa4497b
+    const SEGWIT_BLOCK: &'static str = "000000202aa2f2ca794ccbd40c16e2f3333f6b8b683f9e7179b2c4d7490600000000000010bc26e70a2f672ad420a6153dd0c28b40a6002c55531bfc99bf8994a8e8f67e5503bd5750d4061a4ed90a700f010000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff3603da1b0e00045503bd5704c7dd8a0d0ced13bb5785010800000000000a636b706f6f6c122f4e696e6a61506f6f6c2f5345475749542fffffffff02b4e5a212000000001976a914876fbb82ec05caa6af7a3b5e5a983aae6c6cc6d688ac0000000000000000266a24aa21a9edf91c46b49eb8a29089980f02ee6b57e7d63d33b18b4fddac2bcd7db2a3983704012000000000000000000000000000000000000000000000000000000000000000000000000001000000017e4f81175332a733e26d4ba4e29f53f67b7a5d7c2adebb276e447ca71d130b55000000006b483045022100cac809cd1a3d9ad5d5e31a84e2e1d8ec5542841e4d14c6b52e8b38cbe1ff1728022064470b7fb0c2efeccb2e84bfa36ec5f9e434c84b1101c00f7ee32f726371b7410121020e62280798b6b8c37f068df0915b0865b63fabc401c2457cbc3ef96887dd3647ffffffff02ca2f780c000000001976a914c6b5545b3592cb477d709896fa705592c9b6113a88ac663b2a06000000001976a914e7c1345fc8f87c68170b3aa798a956c2fe6a9eff88ac0000000001000000011e99f5a785e677e017d36b50aa4fd10010ffd039f38f42f447ca8895250e121f01000000d90047304402200d3d296ad641a281dd5c0d68b9ab0d1ad5f7052bec148c1fb81fb1ba69181ec502201a372bb16fb8e054ee9bef41e300d292153830f841a4db0ab7f7407f6581b9bc01473044022002584f313ae990236b6bebb82fbbb006a2b02a448dd5c93434428991eae960d60220491d67d2660c4dde19025cf86e5164a559e2c79c3b98b40e146fab974acd24690147522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452aeffffffff0240420f000000000017a9140ffdcf96700455074292a821c74922e8652993998788997bc60000000017a9148ce5408cfeaddb7ccb2545ded41ef478109454848700000000010000000113100b09e6a78d63ec4850654ab0f68806de29710b09172eddfef730652b155501000000da00473044022015389408e3446a3f36a05060e0e4a3c8b92ff3901ba2511aa944ec91a537a1cb022045a33b6ec47605b1718ed2e753263e54918edbf6126508ff039621fb928d28a001483045022100bb952fde81f216f7063575c0bb2bedc050ce08c96d9b437ea922f5eb98c882da02201b7cbf3a2f94ea4c5eb7f0df3af2ebcafa8705af7f410ab5d3d4bac13d6bc6120147522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452aeffffffff0240420f000000000017a914d3db9a20312c3ab896a316eb108dbd01e47e17d687e0ba7ac60000000017a9148ce5408cfeaddb7ccb2545ded41ef47810945484870000000001000000016e3cca1599cde54878e2f27f434df69df0afd1f313cb6e38c08d3ffb57f97a6c01000000da0048304502210095623b70ec3194fa4037a1c1106c2580caedc390e25e5b330bbeb3111e8184bc02205ae973c4a4454be2a3a03beb66297143c1044a3c4743742c5cdd1d516a1ad3040147304402202f3d6d89996f5b42773dd6ebaf367f1af1f3a95c7c7b487ec040131c40f4a4a30220524ffbb0b563f37b3eb1341228f792e8f84111b7c4a9f49cdd998e052ee42efa0147522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452aeffffffff0240420f000000000017a9141ade6b95896dde8ec4dee9e59af8849d3797348e8728af7ac60000000017a9148ce5408cfeaddb7ccb2545ded41ef47810945484870000000001000000011d9dc3a5df9b5b2eeb2bd11a2db243be9e8cc23e2f180bf317d32a499904c15501000000db00483045022100ebbd1c9a8ce626edbb1a7881df81e872ef8c6424feda36faa8a5745157400c6a02206eb463bc8acd5ea06a289e86115e1daae0c2cf10d9cbbd199e1311170d5543ef01483045022100809411a917dc8cf4f3a777f0388fdea6de06243ef7691e500c60abd1c7f19ae602205255d2b1191d8adedb77b814ccb66471eb8486cb4ff8727824254ee5589f176b0147522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452aeffffffff0240420f000000000017a914759a49c772347be81c49517f9e1e6def6a88d4dd87800b85c60000000017a9148ce5408cfeaddb7ccb2545ded41ef47810945484870000000001000000018c51902affd8e5247dfcc2e5d0528a3815f53c8b6d2c200ff290b2b2b486d7704f0000006a47304402201be0d485f6a3ce871be80064c593c5327b3fd7e450f05ab7fae38385bc40cfbe02206e2a6c9970b5d1d10207892376733757486634fce4f352e772149c486857612101210350c33bc9a790c9495195761577b34912a949b73d5bc5ae5343f5ba08b33220ccffffffff0110270000000000001976a9142ab1c62710a7bdfdb4bb6394bbedc58b32b4d5a388ac0000000001000000018c51902affd8e5247dfcc2e5d0528a3815f53c8b6d2c200ff290b2b2b486d7704e0000006b483045022100ccc8c0ac90bdb0402842aec91830c765cdead7a728552a6a34de7d13a6dab28e02206c96f8640cf3444054e9632b197be30598a09c3d5defcd95750bdb922a60d64801210350c33bc9a790c9495195761577b34912a949b73d5bc5ae5343f5ba08b33220ccffffffff0110270000000000001976a9142ab1c62710a7bdfdb4bb6394bbedc58b32b4d5a388ac0000000001000000011b436669c06cbf3442e21a2fe3edc20cd3cf13c358c53234bc4d88bfd8c4bd2a000000006a47304402204a63410ee13db52c7609ab08e25b7fe3c608cc21cc1755ad13460685eb55193202204cd1ea80c06a81571119be0b8cccd96ef7cdd90f62c1fe2d538622feb08e22ba0121024baa8b67cc9ed8a97d90895e3716b25469b67cb26d3324d7aff213f507764765ffffffff010000000000000000306a2e516d64523365345261445653324d436a736e536171734a5753324465655446624238354541794a4d5843784c7934000000000100000001be4a95ed36316cada5118b1982e4cb4a07f93e7a4153e227466f1cb0776de995000000006b483045022100a22d5251deea0470806bab817013d675a63cd52218d6e477ab0c9d601d018b7f022042121b46afcdcd0c66f189398212b66085e88c6973ae560f1810c13e55e2bee40121024baa8b67cc9ed8a97d90895e3716b25469b67cb26d3324d7aff213f507764765ffffffff010000000000000000306a2e516d57484d57504e5248515872504c7338554c586b4d483746745356413675366b5a6b4a4e3851796e4e583751340000000001000000016c061a65b49edec21acdbc22f97dc853aa872302aeef13fabf0bf6807de1b8bd010000006b483045022100dd80381f2d158b4dad7f98d2d97317c533fb36e737542473feb05fa74d0b73bb02207097d4331196069167e525b61d132532292fd75cc039a5839c04c2545d427e2b0121035e9a597df8b417bef66811882a2844604fc591c427f642628f0fef46be19a4c9feffffff0280a4bf07000000001976a914573b9106e16ee0b5c143dc40f0724f77dd0e282088ac9533b22c000000001976a9149c4da607efb1d759d33da71778bc6cafa56acb5988acd31b0e0001000000017dae20994b69b28534e5b22f3d7c50f9d7541348cbf6f43fcc654263ebaf8f68000000006b483045022100a85300eb94b24b044877d0b0d61e08e16dbc82ec7d69c723a8a45519f95c35b002203d78376e6bee31b455c097557af7fe4d6b620bc74269e9a75e2aad2b545abddb012103b0d08aba2a5ac6cf2788fda941c386040e35e49d3a57d2aefb16c0438fb98acbfeffffff022222305f000000001976a914cfda30dd836b596db6a9c230c45ae2179107f04888ac80a4bf07000000001976a91442dfcf5823aacb185844e663873c35fb98bfd21b88acd31b0e000100000002ad3e85e4af30678a330f8941ed7a9ca17cd0236368d238cac4e9ff09c466fed1020000006b483045022100d1196c48a0392e09592f1b96b4aec32ab0cecb6fd17b1d0c85ab3250a2fe45d9022059217c82f684fcdecdbe660a2077ea956dfbbb964d2648bc1e8ae0f0fe565449012103b64e32e5f62e03701428fb1e3151e9a57f149c67708f6164a235c8199fe17cc2ffffffff34f0a71c1c2cd610522e9c18c67931cded5e9647d4419c49b99715e2a0795f3d020000006a4730440220316e81d8242abf3c5f885d200feca12c3adb63cf2cd4dc74602f7b8b0cba50340220210d525758df77ccdca6908311c1895275e07bbb29b45963a19252acde55873f012103b64e32e5f62e03701428fb1e3151e9a57f149c67708f6164a235c8199fe17cc2ffffffff0510270000000000001976a914449d2394dde057bc199f23fb8aa2e400f344611788ac10270000000000001976a914449d2394dde057bc199f23fb8aa2e400f344611788aca0860100000000001976a91413d35ad337dd80a055757e5ea0a45b59fee3060c88ac70110100000000001976a91413d35ad337dd80a055757e5ea0a45b59fee3060c88ac0000000000000000026a000000000001000000018e33fecc2ddbd86c5ea919f7bd5a5acf8a09f3e0cdaaaf4f08c5ef095161ef1100000000fdfe0000483045022100d2489b225d39b7d8b6767a6928c8029a2a1297c08fdf00d683ba0c1987e7d7000220176cb66c8a243806bb7421f658325a69a51c82c0c3314e37f2400f33626390210148304502210096cfa57662a545830d0e29610becd41ea031e256339913718ce18dbb1a27bdb00220482911c851d15adcd37097dff99a9ff1f97d953bcebc528835118f447412553e014c695221028d9889862b29430278c084b5c4090b7b807b31e047bcd212ebc2c4e43fc0e3c52103160949a7c8c81f2c25d7763f57eb1cb407d867c5b7c290331bd2dc4b1182c6d32103fbef3b60914bda9173765902013a251ec89450c75d0b5a96a143db1dabf98d9553aeffffffff0220e8891c0100000017a914d996715e081c50f8f6b1b4e7fb6ca214f9924fdf87809698000000000017a9145611d812263f32960228cb5f85329bce4770a218870000000001000000017720507dcbe6c69f652b0c0ce19406f482372d1a8abc05d45fb7acf97fb80eec00000000fdfe00004830450221009821d8e117de44b1202c829c0f5063997acf007cf9b561c6fb8d1212cddb6c40022010ff5067b0d9d4eca2da0ceb876e9a16f1a2142da866d3042a7bae8968813e8001483045022100dea759d14a8a1c5da5f3dcc5509871aaa2c1e3be03752c1b858d80fa4227163702205183d70cc28dcb6df9b037714c8b6442ef84e0ddce07711a30c731e9f0925090014c695221028d70ea66fe7a7def282df7b2b498007e5072933e42c18f63ce85975dcbcf1a8821037e8f842b1e47e21d88002c5aab2559212a4c2c9dbe5ef5347f2a29afd0510ec1210251259cb9fd4f6206488408286e4475c9c9fe887e57a3e32ae4da222778a2aedf53aeffffffff023380cb020000000017a9143b5a7e85b22656a34d43187ac8dd09acd7109d2487809698000000000017a914b9b4b555f594a34deec3ad61d5c5f3738b17ee158700000000";
+
     // Check testnet block 000000000000045e0b1660b6445b5e5c5ab63c9a4f956be7e1e69be04fa4497b
     #[test]
     fn segwit_block_test() {
-        let segwit_block = Vec::from_hex("000000202aa2f2ca794ccbd40c16e2f3333f6b8b683f9e7179b2c4d7490600000000000010bc26e70a2f672ad420a6153dd0c28b40a6002c55531bfc99bf8994a8e8f67e5503bd5750d4061a4ed90a700f010000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff3603da1b0e00045503bd5704c7dd8a0d0ced13bb5785010800000000000a636b706f6f6c122f4e696e6a61506f6f6c2f5345475749542fffffffff02b4e5a212000000001976a914876fbb82ec05caa6af7a3b5e5a983aae6c6cc6d688ac0000000000000000266a24aa21a9edf91c46b49eb8a29089980f02ee6b57e7d63d33b18b4fddac2bcd7db2a3983704012000000000000000000000000000000000000000000000000000000000000000000000000001000000017e4f81175332a733e26d4ba4e29f53f67b7a5d7c2adebb276e447ca71d130b55000000006b483045022100cac809cd1a3d9ad5d5e31a84e2e1d8ec5542841e4d14c6b52e8b38cbe1ff1728022064470b7fb0c2efeccb2e84bfa36ec5f9e434c84b1101c00f7ee32f726371b7410121020e62280798b6b8c37f068df0915b0865b63fabc401c2457cbc3ef96887dd3647ffffffff02ca2f780c000000001976a914c6b5545b3592cb477d709896fa705592c9b6113a88ac663b2a06000000001976a914e7c1345fc8f87c68170b3aa798a956c2fe6a9eff88ac0000000001000000011e99f5a785e677e017d36b50aa4fd10010ffd039f38f42f447ca8895250e121f01000000d90047304402200d3d296ad641a281dd5c0d68b9ab0d1ad5f7052bec148c1fb81fb1ba69181ec502201a372bb16fb8e054ee9bef41e300d292153830f841a4db0ab7f7407f6581b9bc01473044022002584f313ae990236b6bebb82fbbb006a2b02a448dd5c93434428991eae960d60220491d67d2660c4dde19025cf86e5164a559e2c79c3b98b40e146fab974acd24690147522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452aeffffffff0240420f000000000017a9140ffdcf96700455074292a821c74922e8652993998788997bc60000000017a9148ce5408cfeaddb7ccb2545ded41ef478109454848700000000010000000113100b09e6a78d63ec4850654ab0f68806de29710b09172eddfef730652b155501000000da00473044022015389408e3446a3f36a05060e0e4a3c8b92ff3901ba2511aa944ec91a537a1cb022045a33b6ec47605b1718ed2e753263e54918edbf6126508ff039621fb928d28a001483045022100bb952fde81f216f7063575c0bb2bedc050ce08c96d9b437ea922f5eb98c882da02201b7cbf3a2f94ea4c5eb7f0df3af2ebcafa8705af7f410ab5d3d4bac13d6bc6120147522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452aeffffffff0240420f000000000017a914d3db9a20312c3ab896a316eb108dbd01e47e17d687e0ba7ac60000000017a9148ce5408cfeaddb7ccb2545ded41ef47810945484870000000001000000016e3cca1599cde54878e2f27f434df69df0afd1f313cb6e38c08d3ffb57f97a6c01000000da0048304502210095623b70ec3194fa4037a1c1106c2580caedc390e25e5b330bbeb3111e8184bc02205ae973c4a4454be2a3a03beb66297143c1044a3c4743742c5cdd1d516a1ad3040147304402202f3d6d89996f5b42773dd6ebaf367f1af1f3a95c7c7b487ec040131c40f4a4a30220524ffbb0b563f37b3eb1341228f792e8f84111b7c4a9f49cdd998e052ee42efa0147522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452aeffffffff0240420f000000000017a9141ade6b95896dde8ec4dee9e59af8849d3797348e8728af7ac60000000017a9148ce5408cfeaddb7ccb2545ded41ef47810945484870000000001000000011d9dc3a5df9b5b2eeb2bd11a2db243be9e8cc23e2f180bf317d32a499904c15501000000db00483045022100ebbd1c9a8ce626edbb1a7881df81e872ef8c6424feda36faa8a5745157400c6a02206eb463bc8acd5ea06a289e86115e1daae0c2cf10d9cbbd199e1311170d5543ef01483045022100809411a917dc8cf4f3a777f0388fdea6de06243ef7691e500c60abd1c7f19ae602205255d2b1191d8adedb77b814ccb66471eb8486cb4ff8727824254ee5589f176b0147522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452aeffffffff0240420f000000000017a914759a49c772347be81c49517f9e1e6def6a88d4dd87800b85c60000000017a9148ce5408cfeaddb7ccb2545ded41ef47810945484870000000001000000018c51902affd8e5247dfcc2e5d0528a3815f53c8b6d2c200ff290b2b2b486d7704f0000006a47304402201be0d485f6a3ce871be80064c593c5327b3fd7e450f05ab7fae38385bc40cfbe02206e2a6c9970b5d1d10207892376733757486634fce4f352e772149c486857612101210350c33bc9a790c9495195761577b34912a949b73d5bc5ae5343f5ba08b33220ccffffffff0110270000000000001976a9142ab1c62710a7bdfdb4bb6394bbedc58b32b4d5a388ac0000000001000000018c51902affd8e5247dfcc2e5d0528a3815f53c8b6d2c200ff290b2b2b486d7704e0000006b483045022100ccc8c0ac90bdb0402842aec91830c765cdead7a728552a6a34de7d13a6dab28e02206c96f8640cf3444054e9632b197be30598a09c3d5defcd95750bdb922a60d64801210350c33bc9a790c9495195761577b34912a949b73d5bc5ae5343f5ba08b33220ccffffffff0110270000000000001976a9142ab1c62710a7bdfdb4bb6394bbedc58b32b4d5a388ac0000000001000000011b436669c06cbf3442e21a2fe3edc20cd3cf13c358c53234bc4d88bfd8c4bd2a000000006a47304402204a63410ee13db52c7609ab08e25b7fe3c608cc21cc1755ad13460685eb55193202204cd1ea80c06a81571119be0b8cccd96ef7cdd90f62c1fe2d538622feb08e22ba0121024baa8b67cc9ed8a97d90895e3716b25469b67cb26d3324d7aff213f507764765ffffffff010000000000000000306a2e516d64523365345261445653324d436a736e536171734a5753324465655446624238354541794a4d5843784c7934000000000100000001be4a95ed36316cada5118b1982e4cb4a07f93e7a4153e227466f1cb0776de995000000006b483045022100a22d5251deea0470806bab817013d675a63cd52218d6e477ab0c9d601d018b7f022042121b46afcdcd0c66f189398212b66085e88c6973ae560f1810c13e55e2bee40121024baa8b67cc9ed8a97d90895e3716b25469b67cb26d3324d7aff213f507764765ffffffff010000000000000000306a2e516d57484d57504e5248515872504c7338554c586b4d483746745356413675366b5a6b4a4e3851796e4e583751340000000001000000016c061a65b49edec21acdbc22f97dc853aa872302aeef13fabf0bf6807de1b8bd010000006b483045022100dd80381f2d158b4dad7f98d2d97317c533fb36e737542473feb05fa74d0b73bb02207097d4331196069167e525b61d132532292fd75cc039a5839c04c2545d427e2b0121035e9a597df8b417bef66811882a2844604fc591c427f642628f0fef46be19a4c9feffffff0280a4bf07000000001976a914573b9106e16ee0b5c143dc40f0724f77dd0e282088ac9533b22c000000001976a9149c4da607efb1d759d33da71778bc6cafa56acb5988acd31b0e0001000000017dae20994b69b28534e5b22f3d7c50f9d7541348cbf6f43fcc654263ebaf8f68000000006b483045022100a85300eb94b24b044877d0b0d61e08e16dbc82ec7d69c723a8a45519f95c35b002203d78376e6bee31b455c097557af7fe4d6b620bc74269e9a75e2aad2b545abddb012103b0d08aba2a5ac6cf2788fda941c386040e35e49d3a57d2aefb16c0438fb98acbfeffffff022222305f000000001976a914cfda30dd836b596db6a9c230c45ae2179107f04888ac80a4bf07000000001976a91442dfcf5823aacb185844e663873c35fb98bfd21b88acd31b0e000100000002ad3e85e4af30678a330f8941ed7a9ca17cd0236368d238cac4e9ff09c466fed1020000006b483045022100d1196c48a0392e09592f1b96b4aec32ab0cecb6fd17b1d0c85ab3250a2fe45d9022059217c82f684fcdecdbe660a2077ea956dfbbb964d2648bc1e8ae0f0fe565449012103b64e32e5f62e03701428fb1e3151e9a57f149c67708f6164a235c8199fe17cc2ffffffff34f0a71c1c2cd610522e9c18c67931cded5e9647d4419c49b99715e2a0795f3d020000006a4730440220316e81d8242abf3c5f885d200feca12c3adb63cf2cd4dc74602f7b8b0cba50340220210d525758df77ccdca6908311c1895275e07bbb29b45963a19252acde55873f012103b64e32e5f62e03701428fb1e3151e9a57f149c67708f6164a235c8199fe17cc2ffffffff0510270000000000001976a914449d2394dde057bc199f23fb8aa2e400f344611788ac10270000000000001976a914449d2394dde057bc199f23fb8aa2e400f344611788aca0860100000000001976a91413d35ad337dd80a055757e5ea0a45b59fee3060c88ac70110100000000001976a91413d35ad337dd80a055757e5ea0a45b59fee3060c88ac0000000000000000026a000000000001000000018e33fecc2ddbd86c5ea919f7bd5a5acf8a09f3e0cdaaaf4f08c5ef095161ef1100000000fdfe0000483045022100d2489b225d39b7d8b6767a6928c8029a2a1297c08fdf00d683ba0c1987e7d7000220176cb66c8a243806bb7421f658325a69a51c82c0c3314e37f2400f33626390210148304502210096cfa57662a545830d0e29610becd41ea031e256339913718ce18dbb1a27bdb00220482911c851d15adcd37097dff99a9ff1f97d953bcebc528835118f447412553e014c695221028d9889862b29430278c084b5c4090b7b807b31e047bcd212ebc2c4e43fc0e3c52103160949a7c8c81f2c25d7763f57eb1cb407d867c5b7c290331bd2dc4b1182c6d32103fbef3b60914bda9173765902013a251ec89450c75d0b5a96a143db1dabf98d9553aeffffffff0220e8891c0100000017a914d996715e081c50f8f6b1b4e7fb6ca214f9924fdf87809698000000000017a9145611d812263f32960228cb5f85329bce4770a218870000000001000000017720507dcbe6c69f652b0c0ce19406f482372d1a8abc05d45fb7acf97fb80eec00000000fdfe00004830450221009821d8e117de44b1202c829c0f5063997acf007cf9b561c6fb8d1212cddb6c40022010ff5067b0d9d4eca2da0ceb876e9a16f1a2142da866d3042a7bae8968813e8001483045022100dea759d14a8a1c5da5f3dcc5509871aaa2c1e3be03752c1b858d80fa4227163702205183d70cc28dcb6df9b037714c8b6442ef84e0ddce07711a30c731e9f0925090014c695221028d70ea66fe7a7def282df7b2b498007e5072933e42c18f63ce85975dcbcf1a8821037e8f842b1e47e21d88002c5aab2559212a4c2c9dbe5ef5347f2a29afd0510ec1210251259cb9fd4f6206488408286e4475c9c9fe887e57a3e32ae4da222778a2aedf53aeffffffff023380cb020000000017a9143b5a7e85b22656a34d43187ac8dd09acd7109d2487809698000000000017a914b9b4b555f594a34deec3ad61d5c5f3738b17ee158700000000").unwrap();
+        let segwit_block = Vec::from_hex(SEGWIT_BLOCK).unwrap();
 
         let decode: Result<Block, _> = deserialize(&segwit_block);
 
@@ -323,7 +1059,7 @@ mod tests {
         assert_eq!(real_decode.header.version, 0x20000000);  // VERSIONBITS but no bits set
         assert_eq!(serialize(&real_decode.header.prev_blockhash), prevhash);
         assert_eq!(serialize(&real_decode.header.merkle_root), merkle);
-        assert_eq!(real_decode.header.merkle_root, real_decode.merkle_root());
+        assert_eq!(real_decode.header.merkle_root, real_decode.compute_merkle_root().unwrap());
         assert_eq!(real_decode.header.time, 1472004949);
         assert_eq!(real_decode.header.bits, 0x1a06d450);
         assert_eq!(real_decode.header.nonce, 1879759182);
@@ -333,8 +1069,105 @@ mod tests {
         assert_eq!(real_decode.get_weight(), 17168);
 
         assert!(real_decode.check_witness_commitment());
+        assert!(real_decode.check_merkle_root());
+        assert_eq!(real_decode.coinbase(), real_decode.txdata.first());
+        assert_eq!(real_decode.bip34_block_height(), Ok(924634));
+        let params = Params::new(Network::MonacoinTestnet);
+        assert_eq!(real_decode.coinbase().unwrap().validate_coinbase(924634, &params), Ok(()));
+        assert_eq!(
+            real_decode.coinbase().unwrap().validate_coinbase(924635, &params),
+            Err(CoinbaseError::BadHeight(924635))
+        );
+        assert_eq!(Block { header: real_decode.header, txdata: real_decode.txdata[1..].to_vec() }.coinbase(), None);
 
         assert_eq!(serialize(&real_decode), segwit_block);
+
+        // witness data is only committed to by the witness commitment
+        let mut tampered = real_decode.clone();
+        {
+            let witness = tampered.txdata.iter_mut()
+                .flat_map(|tx| tx.input.iter_mut())
+                .map(|input| &mut input.witness)
+                .find(|witness| !witness.is_empty())
+                .unwrap();
+            witness[0][0] ^= 1;
+        }
+        assert!(tampered.check_merkle_root());
+        assert!(!tampered.check_witness_commitment());
+        tampered.txdata[1].lock_time = LockTime::from_consensus(tampered.txdata[1].lock_time.to_consensus_u32() + 1);
+        assert!(!tampered.check_merkle_root());
+
+        // witness transactions require a commitment
+        let mut uncommitted = real_decode.clone();
+        uncommitted.txdata[0].output.retain(|out| !out.script_pubkey.is_op_return());
+        assert!(!uncommitted.check_witness_commitment());
+    }
+
+    #[test]
+    fn bip34_test() {
+        use blockdata::script::{Builder, Script};
+        use blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn};
+        use super::Bip34Error;
+
+        let block = |version: i32, script_sig: Script| Block {
+            header: BlockHeader {
+                version: version,
+                prev_blockhash: Default::default(),
+                merkle_root: Default::default(),
+                time: 0,
+                bits: 0,
+                nonce: 0,
+            },
+            txdata: vec![Transaction {
+                version: 1,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: script_sig,
+                    sequence: Sequence::MAX,
+                    witness: vec![],
+                }],
+                output: vec![],
+            }],
+        };
+
+        for &height in &[0, 1, 16, 17, 127, 128, 1_500_000, 0x7fffffff] {
+            let script_sig = Builder::new().push_int(height).push_slice(b"/pool/").into_script();
+            assert_eq!(block(2, script_sig).bip34_block_height(), Ok(height as u64));
+        }
+
+        let script_sig = Builder::new().push_int(100).into_script();
+        assert_eq!(block(1, script_sig).bip34_block_height(), Err(Bip34Error::Unsupported));
+        assert_eq!(block(2, Script::new()).bip34_block_height(), Err(Bip34Error::NotPresent));
+        let mut no_txs = block(2, Script::new());
+        no_txs.txdata.clear();
+        assert_eq!(no_txs.coinbase(), None);
+        assert_eq!(no_txs.compute_merkle_root(), None);
+        assert!(!no_txs.check_merkle_root());
+        assert_eq!(no_txs.bip34_block_height(), Err(Bip34Error::NotPresent));
+        // non-minimal pushes don't count
+        assert_eq!(block(2, hex_script!("0105")).bip34_block_height(), Err(Bip34Error::NotPresent));
+        assert_eq!(block(2, hex_script!("4f")).bip34_block_height(), Err(Bip34Error::NotPresent));
+        assert_eq!(
+            block(2, hex_script!("0481000000")).bip34_block_height(),
+            Ok(0x81)
+        );
+        assert_eq!(
+            block(2, hex_script!("0501020304")).bip34_block_height(),
+            Err(Bip34Error::NotPresent)
+        );
+        assert_eq!(
+            block(2, hex_script!("050102030405")).bip34_block_height(),
+            Err(Bip34Error::UnexpectedPush(vec![1, 2, 3, 4, 5]))
+        );
+        assert_eq!(
+            block(2, hex_script!("0381")).bip34_block_height(),
+            Err(Bip34Error::NotPresent)
+        );
+        assert_eq!(
+            block(2, hex_script!("0281ff")).bip34_block_height(),
+            Err(Bip34Error::UnexpectedPush(vec![0x81, 0xff]))
+        );
     }
 
     #[test]
@@ -360,4 +1193,504 @@ mod tests {
 
         assert_eq!(header.bits, BlockHeader::compact_target_from_u256(&header.target()));
     }
+
+    #[test]
+    fn pow_hash_era_dispatch_test() {
+        let some_header = Vec::from_hex("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914cd74d6e49ffff001d323b3a7b").unwrap();
+        let header: BlockHeader = deserialize(&some_header).unwrap();
+        let params = Params::new(Network::Monacoin);
+        let switch = params.switch_lyra2rev2_dgwblock;
+
+        assert_eq!(header.pow_hash_at(switch - 1, &params), header.block_pow_hash(false));
+        assert_eq!(header.pow_hash_at(switch, &params), header.block_pow_hash(true));
+        assert_eq!(header.pow_hash_at(0, &params), header.block_pow_hash(false));
+        assert_ne!(header.pow_hash_at(switch - 1, &params), header.pow_hash_at(switch, &params));
+    }
+
+    /// Testnet with a limit allowing about any hash, and a chain of headers
+    /// at heights 36 to 62 where the last five use the minimum difficulty,
+    /// across the switch to Dark Gravity Wave at height 60
+    fn testnet_chain(limit_bits: u32) -> (Params, Vec<BlockHeader>) {
+        let mut params = Params::new(Network::MonacoinTestnet);
+        params.pow_limit = Uint256::from_compact(limit_bits).unwrap();
+        let mut prev = BlockHeader { bits: 0x1e0ffff0, ..genesis_block(Network::MonacoinTestnet).header };
+        let mut headers = vec![prev];
+        for height in 37..63 {
+            let (spacing, bits) = if height < 58 { (90, 0x1e0ffff0) } else { (200, limit_bits) };
+            prev = BlockHeader { prev_blockhash: prev.block_hash(), time: prev.time + spacing, bits: bits, ..prev };
+            headers.push(prev);
+        }
+        (params, headers)
+    }
+
+    #[test]
+    fn validate_header_chain_test() {
+        let (params, headers) = testnet_chain(0x2100ffff);
+        let (context, new_headers) = headers.split_at(22);
+        assert_eq!(validate_header_chain(context, new_headers, 58, &params), Ok(()));
+        // only the median time past needs context when the minimum difficulty
+        // is allowed
+        assert_eq!(validate_header_chain(&context[11..], new_headers, 58, &params), Ok(()));
+        assert_eq!(validate_header_chain(&context[12..], new_headers, 58, &params),
+                   Err(HeaderChainError::MissingContext(0)));
+        assert_eq!(validate_header_chain(&[], new_headers, 58, &params),
+                   Err(HeaderChainError::MissingContext(0)));
+        let genesis = genesis_block(Network::MonacoinTestnet).header;
+        assert_eq!(validate_header_chain(&[], &[genesis], 0, &params), Ok(()));
+        assert_eq!(header_chain_context_len(&params), 1057);
+        let tail = context.len().saturating_sub(header_chain_context_len(&params));
+        assert_eq!(validate_header_chain(&context[tail..], new_headers, 58, &params), Ok(()));
+
+        let mut bad = new_headers.to_vec();
+        bad[1].prev_blockhash = Default::default();
+        assert_eq!(validate_header_chain(context, &bad, 58, &params),
+                   Err(HeaderChainError::PrevHashMismatch(1)));
+
+        // the median of the last eleven times is the time at height 52
+        let mut bad = new_headers.to_vec();
+        bad[0].time = context[16].time;
+        assert_eq!(validate_header_chain(context, &bad, 58, &params),
+                   Err(HeaderChainError::TimeTooOld(0)));
+
+        // within twice the target spacing of 90 seconds the first block
+        // retargeted by Dark Gravity Wave can't use the minimum difficulty
+        let mut bad = new_headers.to_vec();
+        bad[2].time = bad[1].time + 180;
+        match validate_header_chain(context, &bad, 58, &params) {
+            Err(HeaderChainError::BadBits { index: 2, actual: 0x2100ffff, .. }) => {}
+            e => panic!("unexpected result {:?}", e),
+        }
+        // the bits of the DigiShield era before the switch aren't checked
+        let mut unchecked = new_headers.to_vec();
+        unchecked[0].time = context[21].time + 180;
+        assert_eq!(validate_header_chain(context, &unchecked[..1], 58, &params), Ok(()));
+
+        // the easiest target still needs some work
+        let (params, headers) = testnet_chain(0x01010000);
+        assert_eq!(validate_header_chain(&headers[..22], &headers[22..], 58, &params),
+                   Err(HeaderChainError::BadProofOfWork(0)));
+    }
+
+    #[test]
+    fn median_time_past_test() {
+        let genesis = genesis_block(Network::Monacoin).header;
+        let times = [1_600_000_900, 1_600_000_300, 1_600_000_100, 1_600_001_000, 1_600_000_500, 1_600_000_200,
+                     1_600_000_700, 1_600_000_400, 1_600_000_800, 1_600_000_000, 1_600_000_600];
+        let headers: Vec<BlockHeader> = times.iter().map(|&time| BlockHeader { time: time, ..genesis }).collect();
+        assert_eq!(median_time_past(&headers), 1_600_000_500);
+        // fewer headers near genesis, the later middle one for an even number
+        assert_eq!(median_time_past(&headers[..4]), 1_600_000_900);
+        assert_eq!(median_time_past(&headers[..1]), 1_600_000_900);
+        assert_eq!(median_time_past(&[]), 0);
+        // only the last eleven count
+        let mut more = vec![BlockHeader { time: 2_000_000_000, ..genesis }; 5];
+        more.extend_from_slice(&headers);
+        assert_eq!(median_time_past(&more), 1_600_000_500);
+
+        let mtp = median_time_past(&headers);
+        let header = BlockHeader { time: mtp + 1, ..genesis };
+        assert_eq!(header.validate_timestamp(mtp, mtp), Ok(()));
+        assert_eq!(header.validate_timestamp(mtp + 1, mtp), Err(TimeError::TooOld { time: mtp + 1, mtp: mtp + 1 }));
+        assert_eq!(header.validate_timestamp(mtp, mtp + 1 - 2 * 60 * 60), Ok(()));
+        assert_eq!(header.validate_timestamp(mtp, mtp - 2 * 60 * 60),
+                   Err(TimeError::TooNew { time: mtp + 1, adjusted_time: mtp - 2 * 60 * 60 }));
+
+        // the same value decides time lock times
+        assert!(LockTime::from_consensus(mtp - 1).is_satisfied_by_consensus(0, mtp));
+        assert!(!LockTime::from_consensus(mtp).is_satisfied_by_consensus(0, mtp));
+    }
+
+    #[test]
+    fn validate_structure_test() {
+        let params = Params::new(Network::MonacoinTestnet);
+        let block: Block = deserialize(&Vec::from_hex(SEGWIT_BLOCK).unwrap()).unwrap();
+        assert_eq!(block.txdata.len(), 15);
+        assert!(block.has_witness());
+        assert!(!block.is_merkle_mutated());
+        assert!(block.check_duplicate_txids());
+        assert_eq!(block.validate_structure(&params, 924634), Ok(()));
+
+        // duplicating the last of an odd number of transactions keeps the merkle root
+        let mut duplicated = block.clone();
+        duplicated.txdata.push(block.txdata[14].clone());
+        assert!(duplicated.check_merkle_root());
+        assert!(duplicated.is_merkle_mutated());
+        assert!(!duplicated.check_duplicate_txids());
+        assert_eq!(duplicated.validate_structure(&params, 924634), Err(BlockStructureError::MutatedMerkleTree));
+
+        // without the commitment output, committed to by an updated merkle root
+        let mut stripped = block.clone();
+        let commitment_pos = stripped.txdata[0].output.iter().position(|o| o.script_pubkey.as_bytes().starts_with(&[0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed])).unwrap();
+        stripped.txdata[0].output.remove(commitment_pos);
+        assert_eq!(stripped.validate_structure(&params, 924634), Err(BlockStructureError::BadMerkleRoot));
+        stripped.header.merkle_root = stripped.compute_merkle_root().unwrap();
+        assert_eq!(stripped.validate_structure(&params, 924634), Err(BlockStructureError::BadWitnessCommitment));
+
+        let mut no_coinbase = block.clone();
+        no_coinbase.txdata.remove(0);
+        no_coinbase.header.merkle_root = no_coinbase.compute_merkle_root().unwrap();
+        assert_eq!(no_coinbase.validate_structure(&params, 924634), Err(BlockStructureError::NoCoinbase));
+        let mut two_coinbases = block.clone();
+        two_coinbases.txdata.insert(3, block.txdata[0].clone());
+        two_coinbases.txdata[3].lock_time = LockTime::from_consensus(1);
+        two_coinbases.header.merkle_root = two_coinbases.compute_merkle_root().unwrap();
+        assert_eq!(two_coinbases.validate_structure(&params, 924634), Err(BlockStructureError::MultipleCoinbases(3)));
+
+        let mut segwit_later = params.clone();
+        segwit_later.deployments[DeploymentPos::Segwit as usize].activation_height = Some(924635);
+        assert!(!block.check_no_witness_before_activation(&segwit_later, 924634));
+        assert_eq!(block.validate_structure(&segwit_later, 924634), Err(BlockStructureError::UnexpectedWitness));
+        assert_eq!(block.validate_structure(&segwit_later, 924635), Ok(()));
+
+        // distinct copies of a transaction, without witnesses so that no
+        // commitment is needed
+        let mut heavy = block.clone();
+        for tx in heavy.txdata.iter_mut() {
+            for input in tx.input.iter_mut() {
+                input.witness.clear();
+            }
+        }
+        let filler = heavy.txdata[14].clone();
+        let filler_weight = filler.get_weight();
+        // keep a running total, weighing the whole block every time is quadratic
+        let mut weight = heavy.get_weight();
+        while weight <= MAX_BLOCK_WEIGHT as usize {
+            let count_len = VarInt(heavy.txdata.len() as u64).len();
+            let mut tx = filler.clone();
+            tx.lock_time = LockTime::from_consensus(heavy.txdata.len() as u32);
+            heavy.txdata.push(tx);
+            weight += filler_weight + 4 * (VarInt(heavy.txdata.len() as u64).len() - count_len);
+        }
+        assert_eq!(weight, heavy.get_weight());
+        heavy.header.merkle_root = heavy.compute_merkle_root().unwrap();
+        match heavy.validate_structure(&params, 924634) {
+            Err(BlockStructureError::Overweight(actual)) => assert_eq!(actual, weight),
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn is_signalling_test() {
+        let params = Params::new(Network::Monacoin);
+        let segwit_bit = params.deployment(DeploymentPos::Segwit).bit;
+        let header = |version: u32| BlockHeader { version: version as i32, ..genesis_block(Network::Monacoin).header };
+
+        // signalling CSV and segwit, as Monacoin blocks did in 2017
+        assert!(header(0x20000003).is_signalling(segwit_bit));
+        assert!(header(0x20000003).is_signalling(params.deployment(DeploymentPos::Csv).bit));
+        assert!(!header(0x20000001).is_signalling(segwit_bit));
+        assert!(header(0x30000000).is_signalling(28));
+        // without the top bits set to 001 no bit signals
+        assert!(!header(2).is_signalling(segwit_bit));
+        assert!(!header(0x60000002).is_signalling(segwit_bit));
+        assert!(!header(0xe0000002).is_signalling(segwit_bit));
+        assert!(!header(0x20000000).is_signalling(29));
+    }
+
+    #[test]
+    fn streamed_hashes_match_reserialization() {
+        let mut corpus: Vec<Transaction> = genesis_block(Network::Monacoin).txdata;
+        let segwit_block: Block = deserialize(&Vec::from_hex(SEGWIT_BLOCK).unwrap()).unwrap();
+        corpus.extend(segwit_block.txdata.iter().cloned());
+        // Spending tx from BIP143 with a real witness on its second input.
+        corpus.push(deserialize(&Vec::from_hex("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000").unwrap()).unwrap());
+        assert!(corpus.iter().any(|tx| tx.has_witness()));
+        assert!(corpus.iter().any(|tx| !tx.has_witness()));
+
+        for tx in &corpus {
+            let mut stripped = tx.clone();
+            for input in &mut stripped.input {
+                input.witness.clear();
+            }
+            assert_eq!(tx.txid(), Txid::hash(&serialize(&stripped)));
+            assert_eq!(tx.wtxid(), Wtxid::hash(&serialize(tx)));
+        }
+
+        for header in &[genesis_block(Network::Monacoin).header, segwit_block.header] {
+            assert_eq!(header.block_hash(), BlockHash::hash(&serialize(header)));
+        }
+    }
+
+    #[test]
+    fn assemble_regtest_block() {
+        let params = Params::new(Network::MonacoinRegtest);
+        let genesis = genesis_block(Network::MonacoinRegtest);
+        let height = 1;
+        let segwit_block: Block = deserialize(&Vec::from_hex(SEGWIT_BLOCK).unwrap()).unwrap();
+        // Spending tx from BIP143 with a real witness on its second input.
+        let spends = vec![
+            segwit_block.txdata[1].clone(),
+            deserialize(&Vec::from_hex("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000").unwrap()).unwrap(),
+        ];
+        assert!(!spends[0].has_witness() && spends[1].has_witness());
+
+        let reserved_value = vec![0; 32];
+        let coinbase = Transaction {
+            version: 2,
+            lock_time: LockTime::from_consensus(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: TxIn::coinbase_script_sig(height, b"/extranonce/").unwrap(),
+                sequence: Sequence::MAX,
+                witness: vec![reserved_value.clone()],
+            }],
+            output: vec![TxOut {
+                value: 50_0000_0000,
+                script_pubkey: hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac"),
+            }],
+        };
+        let mut block = Block {
+            header: BlockHeader {
+                version: 0x20000000,
+                prev_blockhash: genesis.block_hash(),
+                merkle_root: Default::default(),
+                time: genesis.header.time + 90,
+                bits: genesis.header.bits,
+                nonce: 0,
+            },
+            txdata: vec![coinbase],
+        };
+        block.txdata.extend(spends);
+        assert!(!block.check_witness_commitment());
+
+        let witness_root = block.compute_witness_root().unwrap();
+        block.txdata[0].output.push(TxOut {
+            value: 0,
+            script_pubkey: make_witness_commitment(&witness_root, &reserved_value),
+        });
+        assert_eq!(block.compute_witness_root(), Some(witness_root));
+        assert!(block.txdata[0].output[1].script_pubkey.is_op_return());
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+
+        let target = block.header.target();
+        while block.header.validate_pow(&target, &height, &params).is_err() {
+            block.header.nonce += 1;
+        }
+
+        assert_eq!(block.txdata.len(), 3);
+        assert!(block.check_witness_commitment());
+        assert!(block.check_merkle_root());
+        assert_eq!(block.txdata[0].validate_coinbase(height, &params), Ok(()));
+        assert_eq!(block.validate_structure(&params, height), Ok(()));
+        assert_eq!(deserialize::<Block>(&serialize(&block)).unwrap(), block);
+    }
+
+    #[test]
+    fn lazy_block_matches_eager() {
+        let mut mixed: Block = deserialize(&Vec::from_hex(SEGWIT_BLOCK).unwrap()).unwrap();
+        // Spending tx from BIP143 with a real witness on its second input.
+        mixed.txdata.push(deserialize(&Vec::from_hex("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000").unwrap()).unwrap());
+        assert!(mixed.txdata.iter().any(|tx| tx.has_witness()));
+        assert!(mixed.txdata.iter().any(|tx| !tx.has_witness()));
+
+        for block in &[genesis_block(Network::Monacoin), mixed] {
+            let raw = serialize(block);
+            let lazy = BlockUncheckedTxs::from_slice(&raw).unwrap();
+            assert_eq!(lazy.header, block.header);
+            assert_eq!(lazy.block_hash(), block.block_hash());
+            assert_eq!(lazy.tx_count(), block.txdata.len());
+            for (i, tx) in block.txdata.iter().enumerate() {
+                assert_eq!(lazy.raw_tx(i), &serialize(tx)[..]);
+                assert_eq!(&lazy.decode_tx(i).unwrap(), tx);
+            }
+            assert_eq!(lazy.iter_txids().len(), block.txdata.len());
+            assert_eq!(lazy.iter_txids().collect::<Vec<_>>(), block.txdata.iter().map(|tx| tx.txid()).collect::<Vec<_>>());
+            assert_eq!(&lazy.decode().unwrap(), block);
+        }
+    }
+
+    #[test]
+    fn lazy_block_rejects_what_eager_rejects() {
+        let raw = Vec::from_hex(SEGWIT_BLOCK).unwrap();
+        // every truncation, and trailing data
+        for len in 0..raw.len() {
+            assert!(deserialize::<Block>(&raw[..len]).is_err());
+            assert!(BlockUncheckedTxs::from_slice(&raw[..len]).is_err(), "length {}", len);
+        }
+        let mut trailing = raw.clone();
+        trailing.push(0);
+        assert!(BlockUncheckedTxs::from_slice(&trailing).is_err());
+
+        // a coinbase with the segwit marker but an unknown flag, or no inputs
+        let mut block: Block = deserialize(&raw).unwrap();
+        block.txdata.truncate(1);
+        let mut raw = serialize(&block);
+        let flag = 80 + 1 + 4 + 1;
+        assert_eq!(&raw[flag - 1..flag + 1], &[0, 1]);
+        raw[flag] = 2;
+        match BlockUncheckedTxs::from_slice(&raw) {
+            Err(encode::Error::UnsupportedSegwitFlag(2)) => {}
+            x => panic!("unexpected {:?}", x.map(|b| b.tx_count())),
+        }
+        raw[flag] = 1;
+        raw[flag + 1] = 0;
+        assert!(deserialize::<Block>(&raw).is_err());
+        match BlockUncheckedTxs::from_slice(&raw) {
+            Err(encode::Error::ParseFailed("witness flag set but no inputs")) => {}
+            x => panic!("unexpected {:?}", x.map(|b| b.tx_count())),
+        }
+
+        // the witness flag without any witness is accepted by both
+        block.txdata[0].input[0].witness.clear();
+        let plain = serialize(&block);
+        let (head, lock_time) = plain.split_at(plain.len() - 4);
+        let mut flagged = head[..flag - 1].to_vec();
+        flagged.extend_from_slice(&[0, 1]);
+        flagged.extend_from_slice(&head[flag - 1..]);
+        flagged.push(0); // empty witness
+        flagged.extend_from_slice(lock_time);
+        assert_eq!(deserialize::<Block>(&flagged).unwrap(), block);
+        let lazy = BlockUncheckedTxs::from_slice(&flagged).unwrap();
+        assert_eq!(lazy.iter_txids().collect::<Vec<_>>(), vec![block.txdata[0].txid()]);
+        assert_eq!(lazy.decode().unwrap(), block);
+    }
+
+    #[test]
+    fn validate_pow_requires_exact_bits() {
+        let params = Params::new(Network::Monacoin);
+        let check = |header: &BlockHeader, target: &Uint256| match header.validate_pow(target, &0, &params) {
+            Ok(()) => "ok",
+            Err(util::Error::BlockBadTarget) => "bad target",
+            Err(util::Error::BlockBadProofOfWork) => "bad proof of work",
+            Err(e) => panic!("unexpected error {:?}", e),
+        };
+        // mine a header with an easy target
+        let mut header = BlockHeader { bits: 0x207fffff, ..genesis_block(Network::Monacoin).header };
+        let target = header.target();
+        while check(&header, &target) != "ok" {
+            header.nonce += 1;
+        }
+
+        // a weaker target than required, which the hash attains as well
+        let weak_header = BlockHeader { bits: 0x2100ffff, ..header };
+        assert!(weak_header.target() > target);
+        assert_eq!(check(&weak_header, &target), "bad target");
+        assert_eq!(check(&header, &weak_header.target()), "bad target");
+        // a stronger one, which the hash doesn't attain
+        let strong_header = BlockHeader { bits: 0x1c00ffff, ..header };
+        assert_eq!(check(&header, &strong_header.target()), "bad target");
+        assert_eq!(check(&strong_header, &strong_header.target()), "bad proof of work");
+    }
+
+    #[test]
+    fn genesis_debug_snapshot() {
+        let genesis = genesis_block(Network::Monacoin);
+        assert_eq!(format!("{:?}", genesis),
+            "Block { header: BlockHeader { \
+            block_hash: ff9f1c0116d19de7c9963845e129f9ed1bfc0b376eb54fd7afa42e0d418c8bb6, \
+            version: 0x00000001, \
+            prev_blockhash: 0000000000000000000000000000000000000000000000000000000000000000, \
+            merkle_root: 35e405a8a46f4dbc1941727aaf338939323c3b955232d0317f8731fe07ac4ba6, \
+            time: 1388479472, bits: 0x1e0ffff0, nonce: 1234534 }, \
+            txdata: [Transaction { \
+            txid: 35e405a8a46f4dbc1941727aaf338939323c3b955232d0317f8731fe07ac4ba6, \
+            version: 1, lock_time: Blocks(Height(0)), \
+            input: [TxIn { \
+            previous_output: OutPoint(0000000000000000000000000000000000000000000000000000000000000000:4294967295), \
+            script_sig: 04ffff001d01044c564465632e20333174682032303133204a6170616e2c205468652077696e6e696e67206e756d62657273206f6620746865203230313320596561722d456e64204a756d626f204c6f74746572793a32332d313330393136, \
+            sequence: Sequence(4294967295), witness: [] }], \
+            output: [TxOut { value: 5000000000, \
+            script_pubkey: 41040184710fa689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f470216fe1b51850b4acf21b179c45070ac7b03a9ac }] }] }");
+
+        let segwit_block: Block = deserialize(&Vec::from_hex(SEGWIT_BLOCK).unwrap()).unwrap();
+        let coinbase = format!("{:?}", segwit_block.txdata[0].input[0]);
+        assert!(coinbase.ends_with("witness: [0000000000000000000000000000000000000000000000000000000000000000] }"));
+        assert_eq!(format!("{:x}", segwit_block.txdata[0].output[1].script_pubkey),
+            "6a24aa21a9edf91c46b49eb8a29089980f02ee6b57e7d63d33b18b4fddac2bcd7db2a3983704");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_human_readable_test() {
+        use serde_json;
+
+        let genesis = genesis_block(Network::Monacoin);
+        let json = "{\"header\":{\"version\":1,\
+            \"prev_blockhash\":\"0000000000000000000000000000000000000000000000000000000000000000\",\
+            \"merkle_root\":\"35e405a8a46f4dbc1941727aaf338939323c3b955232d0317f8731fe07ac4ba6\",\
+            \"time\":1388479472,\"bits\":504365040,\"nonce\":1234534},\
+            \"txdata\":[{\"version\":1,\"lock_time\":0,\"input\":[{\
+            \"previous_output\":\"0000000000000000000000000000000000000000000000000000000000000000:4294967295\",\
+            \"script_sig\":\"04ffff001d01044c564465632e20333174682032303133204a6170616e2c205468652077696e6e696e67206e756d62657273206f6620746865203230313320596561722d456e64204a756d626f204c6f74746572793a32332d313330393136\",\
+            \"sequence\":4294967295,\"witness\":[]}],\
+            \"output\":[{\"value\":5000000000,\
+            \"script_pubkey\":\"41040184710fa689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f470216fe1b51850b4acf21b179c45070ac7b03a9ac\"}]}]}";
+        assert_eq!(serde_json::to_string(&genesis).unwrap(), json);
+        assert_eq!(serde_json::from_str::<Block>(json).unwrap(), genesis);
+
+        serde_round_trip!(genesis);
+        bincode_round_trip!(genesis);
+        serde_round_trip!(genesis.header);
+        bincode_round_trip!(genesis.header);
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod benches {
+    use super::{Block, BlockUncheckedTxs};
+    use blockdata::constants::genesis_block;
+    use blockdata::locktime::LockTime;
+    use consensus::encode::{deserialize, serialize};
+    use hashes::Hash;
+    use hash_types::Txid;
+    use network::constants::Network;
+    use test::{black_box, Bencher};
+
+    fn block_2000_txs() -> Block {
+        let mut block = genesis_block(Network::Monacoin);
+        let template = block.txdata[0].clone();
+        for i in 1..2000 {
+            let mut tx = template.clone();
+            tx.lock_time = LockTime::from_consensus(i);
+            tx.input[0].witness = vec![vec![i as u8; 72], vec![0x02; 33]];
+            block.txdata.push(tx);
+        }
+        block
+    }
+
+    #[bench]
+    pub fn bench_txids_streamed(bh: &mut Bencher) {
+        let block = block_2000_txs();
+        bh.iter(|| {
+            for tx in &block.txdata {
+                black_box(tx.txid());
+            }
+        });
+    }
+
+    #[bench]
+    pub fn bench_txids_reserialized(bh: &mut Bencher) {
+        let block = block_2000_txs();
+        bh.iter(|| {
+            for tx in &block.txdata {
+                let mut stripped = tx.clone();
+                for input in &mut stripped.input {
+                    input.witness.clear();
+                }
+                black_box(Txid::hash(&serialize(&stripped)));
+            }
+        });
+    }
+
+    #[bench]
+    pub fn bench_txids_full_decode(bh: &mut Bencher) {
+        let raw = serialize(&block_2000_txs());
+        bh.iter(|| {
+            let block: Block = deserialize(&raw).unwrap();
+            for tx in &block.txdata {
+                black_box(tx.txid());
+            }
+        });
+    }
+
+    #[bench]
+    pub fn bench_txids_lazy(bh: &mut Bencher) {
+        let raw = serialize(&block_2000_txs());
+        bh.iter(|| {
+            let block = BlockUncheckedTxs::from_slice(&raw).unwrap();
+            for txid in block.iter_txids() {
+                black_box(txid);
+            }
+        });
+    }
 }
@@ -19,13 +19,16 @@
 //! single transaction
 //!
 
+
 use std::default::Default;
+use std::fmt;
 
-use hashes::hex::FromHex;
-use hashes::sha256d;
+use hashes::Hash;
+use hash_types::{BlockHash, TxMerkleNode};
 use blockdata::opcodes;
 use blockdata::script;
-use blockdata::transaction::{OutPoint, Transaction, TxOut, TxIn};
+use blockdata::locktime::LockTime;
+use blockdata::transaction::{OutPoint, Sequence, Transaction, TxOut, TxIn};
 use blockdata::block::{Block, BlockHeader};
 use network::constants::Network;
 use util::uint::Uint256;
@@ -46,13 +49,37 @@ pub const MAX_BLOCK_WEIGHT: u32 = 4_000_000;
 pub const MIN_TRANSACTION_WEIGHT: u32 = 4 * 60;
 /// The factor that non-witness serialization data is multiplied by during weight calculation
 pub const WITNESS_SCALE_FACTOR: usize = 4;
+/// The maximum size of a script which can be executed, in bytes (network rule)
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+/// The maximum size of an element pushed onto the script stack, in bytes (network rule)
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// The number of confirmations a coinbase output needs before it can be spent (network rule)
+pub const COINBASE_MATURITY: u32 = 100;
 
 
-/// In Bitcoind this is insanely described as ~((u256)0 >> 32)
-pub fn max_target(_: Network) -> Uint256 {
-    Uint256::from_u64(0xFFFF).unwrap() << 208
+/// The proof-of-work limit of `network`
+#[deprecated(since="0.25.1", note="please use `Network::max_target` instead")]
+pub fn max_target(network: Network) -> Uint256 {
+    network.max_target()
 }
 
+/// The message in the scriptSig of the coinbase transaction of the genesis block
+pub const GENESIS_COINBASE_MESSAGE: &'static str =
+    "Dec. 31th 2013 Japan, The winning numbers of the 2013 Year-End Jumbo Lottery:23-130916";
+/// The uncompressed public key the output of the genesis coinbase transaction pays to
+pub const GENESIS_OUTPUT_PK: [u8; 65] = [
+    0x04, 0x01, 0x84, 0x71, 0x0f, 0xa6, 0x89, 0xad, 0x50, 0x23, 0x69, 0x0c, 0x80, 0xf3, 0xa4, 0x9c,
+    0x8f, 0x13, 0xf8, 0xd4, 0x5b, 0x8c, 0x85, 0x7f, 0xbc, 0xbc, 0x8b, 0xc4, 0xa8, 0xe4, 0xd3, 0xeb,
+    0x4b, 0x10, 0xf4, 0xd4, 0x60, 0x4f, 0xa0, 0x8d, 0xce, 0x60, 0x1a, 0xaf, 0x0f, 0x47, 0x02, 0x16,
+    0xfe, 0x1b, 0x51, 0x85, 0x0b, 0x4a, 0xcf, 0x21, 0xb1, 0x79, 0xc4, 0x50, 0x70, 0xac, 0x7b, 0x03,
+    0xa9,
+];
+/// The merkle root of the genesis block, the txid of its coinbase transaction
+const GENESIS_MERKLE_ROOT: [u8; 32] = [
+    0xa6, 0x4b, 0xac, 0x07, 0xfe, 0x31, 0x87, 0x7f, 0x31, 0xd0, 0x32, 0x52, 0x95, 0x3b, 0x3c, 0x32,
+    0x39, 0x89, 0x33, 0xaf, 0x7a, 0x72, 0x41, 0x19, 0xbc, 0x4d, 0x6f, 0xa4, 0xa8, 0x05, 0xe4, 0x35,
+];
+
 /// The maximum value allowed in an output (useful for sanity checking,
 /// since keeping everything below this value should prevent overflows
 /// if you are doing anything remotely sane with monetary values).
@@ -65,7 +92,7 @@ fn bitcoin_genesis_tx() -> Transaction {
     // Base
     let mut ret = Transaction {
         version: 1,
-        lock_time: 0,
+        lock_time: LockTime::ZERO,
         input: vec![],
         output: vec![],
     };
@@ -73,18 +100,18 @@ fn bitcoin_genesis_tx() -> Transaction {
     // Inputs
     let in_script = script::Builder::new().push_scriptint(486604799)
                                           .push_scriptint(4)
-                                          .push_slice(b"Dec. 31th 2013 Japan, The winning numbers of the 2013 Year-End Jumbo Lottery:23-130916")
+                                          .push_slice(GENESIS_COINBASE_MESSAGE.as_bytes())
                                           .into_script();
     ret.input.push(TxIn {
         previous_output: OutPoint::null(),
         script_sig: in_script,
-        sequence: MAX_SEQUENCE,
+        sequence: Sequence::MAX,
         witness: vec![],
     });
 
     // Outputs
     let out_script = script::Builder::new()
-        .push_slice(&Vec::from_hex("040184710fa689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f470216fe1b51850b4acf21b179c45070ac7b03a9").unwrap())
+        .push_slice(&GENESIS_OUTPUT_PK)
         .push_opcode(opcodes::all::OP_CHECKSIG)
         .into_script();
     ret.output.push(TxOut {
@@ -97,10 +124,12 @@ fn bitcoin_genesis_tx() -> Transaction {
 }
 
 /// Constructs and returns the genesis block
+///
+/// Nothing is hashed, the merkle root is a constant. To compare against the
+/// genesis block use [genesis_block_hash] rather than hashing this block.
 pub fn genesis_block(network: Network) -> Block {
     let txdata = vec![bitcoin_genesis_tx()];
-    let hash: sha256d::Hash = txdata[0].txid().into();
-    let merkle_root = hash.into();
+    let merkle_root = TxMerkleNode::from_inner(GENESIS_MERKLE_ROOT);
     match network {
         Network::Monacoin => {
             Block {
@@ -144,6 +173,42 @@ pub fn genesis_block(network: Network) -> Block {
     }
 }
 
+/// Returns the hash of the genesis block of `network` without constructing
+/// or hashing the block
+pub fn genesis_block_hash(network: Network) -> BlockHash {
+    BlockHash::from_inner(network.chain_hash().0)
+}
+
+/// The hash of the genesis block of a chain in internal byte order, which
+/// uniquely identifies the chain in protocols such as BOLT.
+pub struct ChainHash([u8; 32]);
+impl_array_newtype!(ChainHash, u8, 32);
+impl_array_newtype_show!(ChainHash);
+impl_bytes_newtype!(ChainHash, 32);
+
+impl ChainHash {
+    /// The chain hash of Monacoin mainnet
+    pub const MONACOIN: ChainHash = ChainHash([
+        0xb6, 0x8b, 0x8c, 0x41, 0x0d, 0x2e, 0xa4, 0xaf, 0xd7, 0x4f, 0xb5, 0x6e, 0x37, 0x0b, 0xfc, 0x1b,
+        0xed, 0xf9, 0x29, 0xe1, 0x45, 0x38, 0x96, 0xc9, 0xe7, 0x9d, 0xd1, 0x16, 0x01, 0x1c, 0x9f, 0xff,
+    ]);
+    /// The chain hash of Monacoin's testnet
+    pub const MONACOIN_TESTNET: ChainHash = ChainHash([
+        0xb2, 0xe0, 0x61, 0x10, 0x32, 0x9c, 0x44, 0x8f, 0x15, 0x78, 0xe4, 0x8a, 0x25, 0xa8, 0x8b, 0x63,
+        0x9d, 0xcf, 0xaa, 0xa6, 0xa6, 0xb2, 0x97, 0xd0, 0xc6, 0xe0, 0x3b, 0xba, 0xce, 0x06, 0xb1, 0xa2,
+    ]);
+    /// The chain hash of Monacoin's regtest
+    pub const MONACOIN_REGTEST: ChainHash = ChainHash([
+        0x9c, 0xa2, 0x90, 0xa5, 0x3a, 0x47, 0x49, 0xa7, 0xc8, 0x72, 0x30, 0x6f, 0x3b, 0x25, 0x35, 0x4a,
+        0x07, 0x4c, 0x06, 0xfc, 0xc2, 0xbe, 0x5e, 0x9a, 0xb2, 0xcd, 0x2f, 0x7c, 0x9d, 0xa6, 0x43, 0x75,
+    ]);
+
+    /// Computes the chain hash of `network` from its genesis block
+    pub fn using_genesis_block(network: Network) -> ChainHash {
+        ChainHash(genesis_block(network).block_hash().into_inner())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::default::Default;
@@ -151,8 +216,10 @@ mod test {
 
     use network::constants::Network;
     use consensus::encode::serialize;
-    use blockdata::constants::{genesis_block, bitcoin_genesis_tx};
+    use blockdata::constants::{genesis_block, genesis_block_hash, bitcoin_genesis_tx, ChainHash};
+    use blockdata::constants::{GENESIS_COINBASE_MESSAGE, GENESIS_OUTPUT_PK};
     use blockdata::constants::{MAX_SEQUENCE, COIN_VALUE};
+    use blockdata::locktime::LockTime;
 
     #[test]
     fn bitcoin_genesis_first_transaction() {
@@ -165,12 +232,12 @@ mod test {
         assert_eq!(serialize(&gen.input[0].script_sig),
                    Vec::from_hex("5f04ffff001d01044c564465632e20333174682032303133204a6170616e2c205468652077696e6e696e67206e756d62657273206f6620746865203230313320596561722d456e64204a756d626f204c6f74746572793a32332d313330393136").unwrap());
 
-        assert_eq!(gen.input[0].sequence, MAX_SEQUENCE);
+        assert_eq!(gen.input[0].sequence.0, MAX_SEQUENCE);
         assert_eq!(gen.output.len(), 1);
         assert_eq!(serialize(&gen.output[0].script_pubkey),
                    Vec::from_hex("4341040184710fa689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f470216fe1b51850b4acf21b179c45070ac7b03a9ac").unwrap());
         assert_eq!(gen.output[0].value, 50 * COIN_VALUE);
-        assert_eq!(gen.lock_time, 0);
+        assert_eq!(gen.lock_time, LockTime::ZERO);
 
         assert_eq!(format!("{:x}", gen.wtxid()),
                    "35e405a8a46f4dbc1941727aaf338939323c3b955232d0317f8731fe07ac4ba6".to_string());
@@ -204,5 +271,28 @@ mod test {
         assert_eq!(format!("{:x}", gen.header.block_hash()),
                    "a2b106ceba3be0c6d097b2a6a6aacf9d638ba8258ae478158f449c321061e0b2".to_string());
     }
-}
 
+    #[test]
+    fn genesis_constants() {
+        let gen = bitcoin_genesis_tx();
+        assert!(gen.input[0].script_sig.as_bytes().ends_with(GENESIS_COINBASE_MESSAGE.as_bytes()));
+        assert_eq!(&gen.output[0].script_pubkey[1..66], &GENESIS_OUTPUT_PK[..]);
+
+        for &network in &[Network::Monacoin, Network::MonacoinTestnet, Network::MonacoinRegtest] {
+            let block = genesis_block(network);
+            // the constant merkle root is the computed one
+            assert_eq!(Some(block.header.merkle_root), block.compute_merkle_root());
+            assert_eq!(genesis_block_hash(network), block.block_hash());
+            assert_eq!(genesis_block_hash(network), genesis_block_hash(network));
+            assert_eq!(genesis_block(network), block);
+        }
+    }
+
+    #[test]
+    fn chain_hashes() {
+        for &network in &[Network::Monacoin, Network::MonacoinTestnet, Network::MonacoinRegtest] {
+            assert_eq!(network.chain_hash(), ChainHash::using_genesis_block(network));
+        }
+        assert_eq!(ChainHash::MONACOIN.to_string(), "b68b8c410d2ea4afd74fb56e370bfc1bedf929e1453896c9e79dd116011c9fff");
+    }
+}
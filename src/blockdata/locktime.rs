@@ -0,0 +1,362 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Lock times
+//!
+//! This module defines the absolute lock time of a transaction, its
+//! `nLockTime` field, and in [relative] the BIP68 relative lock time which
+//! is encoded in the sequence number of an input.
+//!
+
+use std::fmt;
+use std::io;
+use std::error;
+
+use consensus::encode::{self, Decodable, Encodable};
+
+/// Lock time values below this threshold are block heights, values at or
+/// above it are UNIX timestamps.
+pub const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+/// A lock time value which is not of the expected kind
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The value is not below [LOCK_TIME_THRESHOLD], so it is not a block height
+    InvalidHeight(u32),
+    /// The value is below [LOCK_TIME_THRESHOLD], so it is not a timestamp
+    InvalidTime(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidHeight(n) => write!(f, "lock time value {} is not a block height", n),
+            Error::InvalidTime(n) => write!(f, "lock time value {} is not a block time", n),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// A block height usable as an absolute lock time, below [LOCK_TIME_THRESHOLD]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Height(u32);
+
+impl Height {
+    /// The genesis block height.
+    pub const ZERO: Height = Height(0);
+
+    /// Create a height, checking that it is below [LOCK_TIME_THRESHOLD].
+    pub fn from_consensus(n: u32) -> Result<Height, Error> {
+        if n < LOCK_TIME_THRESHOLD {
+            Ok(Height(n))
+        } else {
+            Err(Error::InvalidHeight(n))
+        }
+    }
+
+    /// The height as a consensus lock time value.
+    pub fn to_consensus_u32(self) -> u32 {
+        self.0
+    }
+}
+
+/// A UNIX timestamp usable as an absolute lock time, at or above [LOCK_TIME_THRESHOLD]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time(u32);
+
+impl Time {
+    /// The earliest timestamp which can be a lock time.
+    pub const MIN: Time = Time(LOCK_TIME_THRESHOLD);
+
+    /// Create a timestamp, checking that it is at or above [LOCK_TIME_THRESHOLD].
+    pub fn from_consensus(n: u32) -> Result<Time, Error> {
+        if n >= LOCK_TIME_THRESHOLD {
+            Ok(Time(n))
+        } else {
+            Err(Error::InvalidTime(n))
+        }
+    }
+
+    /// The timestamp as a consensus lock time value.
+    pub fn to_consensus_u32(self) -> u32 {
+        self.0
+    }
+}
+
+/// The absolute lock time of a transaction, which is either a block height
+/// or a time depending on whether the value is below [LOCK_TIME_THRESHOLD].
+///
+/// The lock time only applies when some input of the transaction has a
+/// sequence number other than `0xFFFFFFFF`, see `Transaction::is_lock_time_enabled`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LockTime {
+    /// The transaction can be included in blocks above this height
+    Blocks(Height),
+    /// The transaction can be included in blocks whose median time past
+    /// is later than this time
+    Seconds(Time),
+}
+
+impl LockTime {
+    /// The lock time of zero, which doesn't lock the transaction at all.
+    pub const ZERO: LockTime = LockTime::Blocks(Height::ZERO);
+
+    /// Interpret a consensus `nLockTime` value.
+    pub fn from_consensus(n: u32) -> LockTime {
+        if n < LOCK_TIME_THRESHOLD {
+            LockTime::Blocks(Height(n))
+        } else {
+            LockTime::Seconds(Time(n))
+        }
+    }
+
+    /// Create a lock time from a block height, failing if it would be
+    /// interpreted as a time.
+    pub fn from_height(n: u32) -> Result<LockTime, Error> {
+        Height::from_consensus(n).map(LockTime::Blocks)
+    }
+
+    /// Create a lock time from a UNIX timestamp, failing if it would be
+    /// interpreted as a block height.
+    pub fn from_time(n: u32) -> Result<LockTime, Error> {
+        Time::from_consensus(n).map(LockTime::Seconds)
+    }
+
+    /// The consensus `nLockTime` value.
+    pub fn to_consensus_u32(self) -> u32 {
+        match self {
+            LockTime::Blocks(h) => h.to_consensus_u32(),
+            LockTime::Seconds(t) => t.to_consensus_u32(),
+        }
+    }
+
+    /// Whether the lock time is a block height.
+    pub fn is_block_height(self) -> bool {
+        match self {
+            LockTime::Blocks(_) => true,
+            LockTime::Seconds(_) => false,
+        }
+    }
+
+    /// Whether the lock time is a time.
+    pub fn is_block_time(self) -> bool {
+        !self.is_block_height()
+    }
+
+    /// Whether both lock times are heights or both are times, so that they
+    /// can be compared.
+    pub fn is_same_unit(self, other: LockTime) -> bool {
+        self.is_block_height() == other.is_block_height()
+    }
+
+    /// Whether a transaction with this lock time can be included in the block
+    /// at `height`, whose previous block has the median time past `mtp`.
+    ///
+    /// This is the BIP113 rule of Bitcoin Core's `IsFinalTx`: the lock time
+    /// must be strictly below the height or the median time past. Whether the
+    /// lock time applies at all is decided by the sequence numbers of the
+    /// transaction inputs.
+    pub fn is_satisfied_by(self, height: Height, mtp: Time) -> bool {
+        self.is_satisfied_by_consensus(height.to_consensus_u32(), mtp.to_consensus_u32())
+    }
+
+    /// Like [LockTime::is_satisfied_by], for a height and a median time past
+    /// as plain numbers, such as the one computed by
+    /// `blockdata::block::median_time_past`.
+    pub fn is_satisfied_by_consensus(self, height: u32, mtp: u32) -> bool {
+        match self {
+            LockTime::Blocks(h) => h.0 < height,
+            LockTime::Seconds(t) => t.0 < mtp,
+        }
+    }
+}
+
+impl Default for LockTime {
+    fn default() -> LockTime {
+        LockTime::ZERO
+    }
+}
+
+impl From<u32> for LockTime {
+    fn from(n: u32) -> LockTime {
+        LockTime::from_consensus(n)
+    }
+}
+
+impl From<LockTime> for u32 {
+    fn from(lock_time: LockTime) -> u32 {
+        lock_time.to_consensus_u32()
+    }
+}
+
+impl From<Height> for LockTime {
+    fn from(h: Height) -> LockTime {
+        LockTime::Blocks(h)
+    }
+}
+
+impl From<Time> for LockTime {
+    fn from(t: Time) -> LockTime {
+        LockTime::Seconds(t)
+    }
+}
+
+impl fmt::Display for LockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockTime::Blocks(h) => write!(f, "block height {}", h.0),
+            LockTime::Seconds(t) => write!(f, "block time {}", t.0),
+        }
+    }
+}
+
+impl Encodable for LockTime {
+    fn consensus_encode<W: io::Write>(&self, w: W) -> Result<usize, encode::Error> {
+        self.to_consensus_u32().consensus_encode(w)
+    }
+}
+
+impl Decodable for LockTime {
+    fn consensus_decode<R: io::Read>(r: R) -> Result<Self, encode::Error> {
+        u32::consensus_decode(r).map(LockTime::from_consensus)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for LockTime {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u32(self.to_consensus_u32())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for LockTime {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<LockTime, D::Error> {
+        Ok(LockTime::from_consensus(::serde::Deserialize::deserialize(d)?))
+    }
+}
+
+pub mod relative {
+    //! BIP68 relative lock times
+    //!
+    //! A relative lock time is a number of blocks or of 512-second intervals
+    //! which must have passed since the output spent by an input was
+    //! confirmed. It is encoded in the sequence number of the input, see
+    //! [Sequence].
+
+    use blockdata::transaction::Sequence;
+
+    /// A relative lock time
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub enum LockTime {
+        /// A number of blocks
+        Blocks(u16),
+        /// A number of 512-second intervals
+        Time(u16),
+    }
+
+    impl LockTime {
+        /// A relative lock time of a number of blocks.
+        pub fn from_height(n: u16) -> LockTime {
+            LockTime::Blocks(n)
+        }
+
+        /// A relative lock time of a number of 512-second intervals.
+        pub fn from_512_second_intervals(n: u16) -> LockTime {
+            LockTime::Time(n)
+        }
+
+        /// The sequence number which encodes this lock time.
+        pub fn to_sequence(self) -> Sequence {
+            match self {
+                LockTime::Blocks(n) => Sequence::from_height(n),
+                LockTime::Time(n) => Sequence::from_512_second_intervals(n),
+            }
+        }
+
+        /// Whether an input with this lock time can be included in a block
+        /// when the spent output has `blocks` confirmations counting that
+        /// block, and the median time past has advanced by `seconds` since
+        /// the block before the one which confirmed the output.
+        pub fn is_satisfied_by(self, blocks: u32, seconds: u32) -> bool {
+            match self {
+                LockTime::Blocks(n) => n as u32 <= blocks,
+                LockTime::Time(n) => (n as u32) << 9 <= seconds,
+            }
+        }
+    }
+
+    impl From<LockTime> for Sequence {
+        fn from(lock_time: LockTime) -> Sequence {
+            lock_time.to_sequence()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use consensus::encode::{deserialize, serialize};
+
+    use super::*;
+
+    #[test]
+    fn absolute() {
+        let blocks = LockTime::from_consensus(499_999_999);
+        assert_eq!(blocks, LockTime::from_height(499_999_999).unwrap());
+        assert!(blocks.is_block_height());
+        let seconds = LockTime::from_consensus(500_000_000);
+        assert_eq!(seconds, LockTime::Seconds(Time::MIN));
+        assert!(seconds.is_block_time());
+        assert!(!blocks.is_same_unit(seconds));
+        assert_eq!(LockTime::from_height(500_000_000), Err(Error::InvalidHeight(500_000_000)));
+        assert_eq!(LockTime::from_time(1), Err(Error::InvalidTime(1)));
+
+        for &n in &[0, 1, 499_999_999, 500_000_000, 0xffffffff] {
+            let lock_time = LockTime::from(n);
+            assert_eq!(u32::from(lock_time), n);
+            assert_eq!(serialize(&lock_time), serialize(&n));
+            assert_eq!(deserialize::<LockTime>(&serialize(&n)).unwrap(), lock_time);
+        }
+    }
+
+    #[test]
+    fn absolute_satisfaction() {
+        let height = Height::from_consensus(100).unwrap();
+        let mtp = Time::from_consensus(1_600_000_000).unwrap();
+        assert!(LockTime::ZERO.is_satisfied_by(height, mtp));
+        assert!(LockTime::from_consensus(99).is_satisfied_by(height, mtp));
+        assert!(!LockTime::from_consensus(100).is_satisfied_by(height, mtp));
+        assert!(LockTime::from_consensus(1_599_999_999).is_satisfied_by(height, mtp));
+        assert!(!LockTime::from_consensus(1_600_000_000).is_satisfied_by(height, mtp));
+    }
+
+    #[test]
+    fn relative() {
+        let blocks = relative::LockTime::from_height(10);
+        assert!(blocks.is_satisfied_by(10, 0));
+        assert!(!blocks.is_satisfied_by(9, 1_000_000));
+        let time = relative::LockTime::from_512_second_intervals(2);
+        assert!(time.is_satisfied_by(0, 1024));
+        assert!(!time.is_satisfied_by(1000, 1023));
+
+        assert_eq!(blocks.to_sequence().to_relative_lock_time(), Some(blocks));
+        assert_eq!(time.to_sequence().to_relative_lock_time(), Some(time));
+    }
+}
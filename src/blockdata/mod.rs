@@ -21,6 +21,8 @@
 pub mod constants;
 pub mod opcodes;
 pub mod script;
+pub mod locktime;
 pub mod transaction;
 pub mod block;
+pub mod package;
 
@@ -20,9 +20,12 @@
 
 #![allow(non_camel_case_types)]
 
+
 #[cfg(feature = "serde")] use serde;
 
 use std::fmt;
+use std::error;
+use std::str::FromStr;
 
 // Note: I am deliberately not implementing PartialOrd or Ord on the
 //       opcode enum. If you want to check ranges of opcodes, etc.,
@@ -559,103 +562,198 @@ pub mod all {
 
 impl fmt::Debug for All {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str("OP_")?;
         match *self {
-            All {code: x} if x <= 75 => write!(f, "PUSHBYTES_{}", self.code),
-            all::OP_PUSHDATA1 => write!(f, "PUSHDATA1"),
-            all::OP_PUSHDATA2 => write!(f, "PUSHDATA2"),
-            all::OP_PUSHDATA4 => write!(f, "PUSHDATA4"),
-            all::OP_PUSHNUM_NEG1 => write!(f, "PUSHNUM_NEG1"),
-            all::OP_RESERVED => write!(f, "RESERVED"),
-            All {code: x} if x >= all::OP_PUSHNUM_1.code && x <= all::OP_PUSHNUM_16.code => write!(f, "PUSHNUM_{}", x - all::OP_PUSHNUM_1.code + 1),
-            all::OP_NOP => write!(f, "NOP"),
-            all::OP_VER => write!(f, "VER"),
-            all::OP_IF => write!(f, "IF"),
-            all::OP_NOTIF => write!(f, "NOTIF"),
-            all::OP_VERIF => write!(f, "VERIF"),
-            all::OP_VERNOTIF => write!(f, "VERNOTIF"),
-            all::OP_ELSE => write!(f, "ELSE"),
-            all::OP_ENDIF => write!(f, "ENDIF"),
-            all::OP_VERIFY => write!(f, "VERIFY"),
-            all::OP_RETURN => write!(f, "RETURN"),
-            all::OP_TOALTSTACK => write!(f, "TOALTSTACK"),
-            all::OP_FROMALTSTACK => write!(f, "FROMALTSTACK"),
-            all::OP_2DROP => write!(f, "2DROP"),
-            all::OP_2DUP => write!(f, "2DUP"),
-            all::OP_3DUP => write!(f, "3DUP"),
-            all::OP_2OVER => write!(f, "2OVER"),
-            all::OP_2ROT => write!(f, "2ROT"),
-            all::OP_2SWAP => write!(f, "2SWAP"),
-            all::OP_IFDUP => write!(f, "IFDUP"),
-            all::OP_DEPTH => write!(f, "DEPTH"),
-            all::OP_DROP => write!(f, "DROP"),
-            all::OP_DUP => write!(f, "DUP"),
-            all::OP_NIP => write!(f, "NIP"),
-            all::OP_OVER => write!(f, "OVER"),
-            all::OP_PICK => write!(f, "PICK"),
-            all::OP_ROLL => write!(f, "ROLL"),
-            all::OP_ROT => write!(f, "ROT"),
-            all::OP_SWAP => write!(f, "SWAP"),
-            all::OP_TUCK => write!(f, "TUCK"),
-            all::OP_CAT => write!(f, "CAT"),
-            all::OP_SUBSTR => write!(f, "SUBSTR"),
-            all::OP_LEFT => write!(f, "LEFT"),
-            all::OP_RIGHT => write!(f, "RIGHT"),
-            all::OP_SIZE => write!(f, "SIZE"),
-            all::OP_INVERT => write!(f, "INVERT"),
-            all::OP_AND => write!(f, "AND"),
-            all::OP_OR => write!(f, "OR"),
-            all::OP_XOR => write!(f, "XOR"),
-            all::OP_EQUAL => write!(f, "EQUAL"),
-            all::OP_EQUALVERIFY => write!(f, "EQUALVERIFY"),
-            all::OP_RESERVED1 => write!(f, "RESERVED1"),
-            all::OP_RESERVED2 => write!(f, "RESERVED2"),
-            all::OP_1ADD => write!(f, "1ADD"),
-            all::OP_1SUB => write!(f, "1SUB"),
-            all::OP_2MUL => write!(f, "2MUL"),
-            all::OP_2DIV => write!(f, "2DIV"),
-            all::OP_NEGATE => write!(f, "NEGATE"),
-            all::OP_ABS => write!(f, "ABS"),
-            all::OP_NOT => write!(f, "NOT"),
-            all::OP_0NOTEQUAL => write!(f, "0NOTEQUAL"),
-            all::OP_ADD => write!(f, "ADD"),
-            all::OP_SUB => write!(f, "SUB"),
-            all::OP_MUL => write!(f, "MUL"),
-            all::OP_DIV => write!(f, "DIV"),
-            all::OP_MOD => write!(f, "MOD"),
-            all::OP_LSHIFT => write!(f, "LSHIFT"),
-            all::OP_RSHIFT => write!(f, "RSHIFT"),
-            all::OP_BOOLAND => write!(f, "BOOLAND"),
-            all::OP_BOOLOR => write!(f, "BOOLOR"),
-            all::OP_NUMEQUAL => write!(f, "NUMEQUAL"),
-            all::OP_NUMEQUALVERIFY => write!(f, "NUMEQUALVERIFY"),
-            all::OP_NUMNOTEQUAL => write!(f, "NUMNOTEQUAL"),
-            all::OP_LESSTHAN  => write!(f, "LESSTHAN"),
-            all::OP_GREATERTHAN  => write!(f, "GREATERTHAN"),
-            all::OP_LESSTHANOREQUAL  => write!(f, "LESSTHANOREQUAL"),
-            all::OP_GREATERTHANOREQUAL  => write!(f, "GREATERTHANOREQUAL"),
-            all::OP_MIN => write!(f, "MIN"),
-            all::OP_MAX => write!(f, "MAX"),
-            all::OP_WITHIN => write!(f, "WITHIN"),
-            all::OP_RIPEMD160 => write!(f, "RIPEMD160"),
-            all::OP_SHA1 => write!(f, "SHA1"),
-            all::OP_SHA256 => write!(f, "SHA256"),
-            all::OP_HASH160 => write!(f, "HASH160"),
-            all::OP_HASH256 => write!(f, "HASH256"),
-            all::OP_CODESEPARATOR => write!(f, "CODESEPARATOR"),
-            all::OP_CHECKSIG => write!(f, "CHECKSIG"),
-            all::OP_CHECKSIGVERIFY => write!(f, "CHECKSIGVERIFY"),
-            all::OP_CHECKMULTISIG => write!(f, "CHECKMULTISIG"),
-            all::OP_CHECKMULTISIGVERIFY => write!(f, "CHECKMULTISIGVERIFY"),
-            all::OP_CLTV => write!(f, "CLTV"),
-            all::OP_CSV => write!(f, "CSV"),
-            All {code: x} if x >= all::OP_NOP1.code && x <= all::OP_NOP10.code => write!(f, "NOP{}", x - all::OP_NOP1.code + 1),
-            All {code: x} => write!(f, "RETURN_{}", x),
+            All {code: x} if x <= 75 => write!(f, "OP_PUSHBYTES_{}", x),
+            All {code: x} if x >= all::OP_PUSHNUM_1.code && x <= all::OP_PUSHNUM_16.code => write!(f, "OP_PUSHNUM_{}", x - all::OP_PUSHNUM_1.code + 1),
+            op => match op.name() {
+                Some(name) => f.write_str(name),
+                None if op.code >= all::OP_NOP1.code && op.code <= all::OP_NOP10.code => write!(f, "OP_NOP{}", op.code - all::OP_NOP1.code + 1),
+                None => write!(f, "OP_RETURN_{}", op.code),
+            },
+        }
+    }
+}
+
+/// Names of OP_PUSHNUM_1 to OP_PUSHNUM_16 in the script assembly of Bitcoin Core
+static CORE_PUSHNUM_NAMES: [&'static str; 16] = [
+    "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15", "16",
+];
+
+/// Names of OP_NOP1 to OP_NOP10 in the script assembly of Bitcoin Core
+static CORE_NOP_NAMES: [&'static str; 10] = [
+    "OP_NOP1", "OP_NOP2", "OP_NOP3", "OP_NOP4", "OP_NOP5",
+    "OP_NOP6", "OP_NOP7", "OP_NOP8", "OP_NOP9", "OP_NOP10",
+];
+
+/// An opcode name which doesn't exist
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnknownOpcodeError(pub String);
+
+impl fmt::Display for UnknownOpcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown opcode {}", self.0)
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for UnknownOpcodeError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// Parses the names of the `Debug` formatting, as well as the names used by
+/// Bitcoin Core: `OP_0`/`OP_FALSE`, `OP_1` to `OP_16`, `OP_TRUE`, `OP_1NEGATE`,
+/// `OP_CHECKLOCKTIMEVERIFY`/`OP_NOP2`, `OP_CHECKSEQUENCEVERIFY`/`OP_NOP3` and
+/// `OP_INVALIDOPCODE`.
+impl FromStr for All {
+    type Err = UnknownOpcodeError;
+
+    fn from_str(s: &str) -> Result<All, UnknownOpcodeError> {
+        let numbered = |prefix: &str, min: u8, max: u8| -> Option<u8> {
+            if !s.starts_with(prefix) || s[prefix.len()..].starts_with('0') {
+                return None;
+            }
+            s[prefix.len()..].parse::<u8>().ok().and_then(|n| if n >= min && n <= max { Some(n) } else { None })
+        };
+
+        match s {
+            "OP_0" | "OP_FALSE" | "OP_PUSHBYTES_0" => return Ok(all::OP_PUSHBYTES_0),
+            "OP_TRUE" => return Ok(all::OP_PUSHNUM_1),
+            "OP_1NEGATE" => return Ok(all::OP_PUSHNUM_NEG1),
+            "OP_CHECKLOCKTIMEVERIFY" | "OP_NOP2" => return Ok(all::OP_CLTV),
+            "OP_CHECKSEQUENCEVERIFY" | "OP_NOP3" => return Ok(all::OP_CSV),
+            "OP_INVALIDOPCODE" => return Ok(all::OP_RETURN_255),
+            _ => {}
+        }
+        if let Some(n) = numbered("OP_PUSHBYTES_", 1, 75) {
+            Ok(All::from(n))
+        } else if let Some(n) = numbered("OP_PUSHNUM_", 1, 16).or_else(|| numbered("OP_", 1, 16)) {
+            Ok(All::from(all::OP_PUSHNUM_1.code + n - 1))
+        } else if let Some(n) = numbered("OP_NOP", 1, 10) {
+            Ok(All::from(all::OP_NOP1.code + n - 1))
+        } else if let Some(n) = numbered("OP_RETURN_", all::OP_RETURN_186.code, 255) {
+            Ok(All::from(n))
+        } else {
+            (0..256)
+                .map(|n| All::from(n as u8))
+                .find(|op| op.name() == Some(s))
+                .ok_or_else(|| UnknownOpcodeError(s.to_owned()))
         }
     }
 }
 
 impl All {
+    /// The name of an opcode which isn't numbered within a range of similar opcodes
+    fn name(self) -> Option<&'static str> {
+        match self {
+            all::OP_PUSHDATA1 => Some("OP_PUSHDATA1"),
+            all::OP_PUSHDATA2 => Some("OP_PUSHDATA2"),
+            all::OP_PUSHDATA4 => Some("OP_PUSHDATA4"),
+            all::OP_PUSHNUM_NEG1 => Some("OP_PUSHNUM_NEG1"),
+            all::OP_RESERVED => Some("OP_RESERVED"),
+            all::OP_NOP => Some("OP_NOP"),
+            all::OP_VER => Some("OP_VER"),
+            all::OP_IF => Some("OP_IF"),
+            all::OP_NOTIF => Some("OP_NOTIF"),
+            all::OP_VERIF => Some("OP_VERIF"),
+            all::OP_VERNOTIF => Some("OP_VERNOTIF"),
+            all::OP_ELSE => Some("OP_ELSE"),
+            all::OP_ENDIF => Some("OP_ENDIF"),
+            all::OP_VERIFY => Some("OP_VERIFY"),
+            all::OP_RETURN => Some("OP_RETURN"),
+            all::OP_TOALTSTACK => Some("OP_TOALTSTACK"),
+            all::OP_FROMALTSTACK => Some("OP_FROMALTSTACK"),
+            all::OP_2DROP => Some("OP_2DROP"),
+            all::OP_2DUP => Some("OP_2DUP"),
+            all::OP_3DUP => Some("OP_3DUP"),
+            all::OP_2OVER => Some("OP_2OVER"),
+            all::OP_2ROT => Some("OP_2ROT"),
+            all::OP_2SWAP => Some("OP_2SWAP"),
+            all::OP_IFDUP => Some("OP_IFDUP"),
+            all::OP_DEPTH => Some("OP_DEPTH"),
+            all::OP_DROP => Some("OP_DROP"),
+            all::OP_DUP => Some("OP_DUP"),
+            all::OP_NIP => Some("OP_NIP"),
+            all::OP_OVER => Some("OP_OVER"),
+            all::OP_PICK => Some("OP_PICK"),
+            all::OP_ROLL => Some("OP_ROLL"),
+            all::OP_ROT => Some("OP_ROT"),
+            all::OP_SWAP => Some("OP_SWAP"),
+            all::OP_TUCK => Some("OP_TUCK"),
+            all::OP_CAT => Some("OP_CAT"),
+            all::OP_SUBSTR => Some("OP_SUBSTR"),
+            all::OP_LEFT => Some("OP_LEFT"),
+            all::OP_RIGHT => Some("OP_RIGHT"),
+            all::OP_SIZE => Some("OP_SIZE"),
+            all::OP_INVERT => Some("OP_INVERT"),
+            all::OP_AND => Some("OP_AND"),
+            all::OP_OR => Some("OP_OR"),
+            all::OP_XOR => Some("OP_XOR"),
+            all::OP_EQUAL => Some("OP_EQUAL"),
+            all::OP_EQUALVERIFY => Some("OP_EQUALVERIFY"),
+            all::OP_RESERVED1 => Some("OP_RESERVED1"),
+            all::OP_RESERVED2 => Some("OP_RESERVED2"),
+            all::OP_1ADD => Some("OP_1ADD"),
+            all::OP_1SUB => Some("OP_1SUB"),
+            all::OP_2MUL => Some("OP_2MUL"),
+            all::OP_2DIV => Some("OP_2DIV"),
+            all::OP_NEGATE => Some("OP_NEGATE"),
+            all::OP_ABS => Some("OP_ABS"),
+            all::OP_NOT => Some("OP_NOT"),
+            all::OP_0NOTEQUAL => Some("OP_0NOTEQUAL"),
+            all::OP_ADD => Some("OP_ADD"),
+            all::OP_SUB => Some("OP_SUB"),
+            all::OP_MUL => Some("OP_MUL"),
+            all::OP_DIV => Some("OP_DIV"),
+            all::OP_MOD => Some("OP_MOD"),
+            all::OP_LSHIFT => Some("OP_LSHIFT"),
+            all::OP_RSHIFT => Some("OP_RSHIFT"),
+            all::OP_BOOLAND => Some("OP_BOOLAND"),
+            all::OP_BOOLOR => Some("OP_BOOLOR"),
+            all::OP_NUMEQUAL => Some("OP_NUMEQUAL"),
+            all::OP_NUMEQUALVERIFY => Some("OP_NUMEQUALVERIFY"),
+            all::OP_NUMNOTEQUAL => Some("OP_NUMNOTEQUAL"),
+            all::OP_LESSTHAN => Some("OP_LESSTHAN"),
+            all::OP_GREATERTHAN => Some("OP_GREATERTHAN"),
+            all::OP_LESSTHANOREQUAL => Some("OP_LESSTHANOREQUAL"),
+            all::OP_GREATERTHANOREQUAL => Some("OP_GREATERTHANOREQUAL"),
+            all::OP_MIN => Some("OP_MIN"),
+            all::OP_MAX => Some("OP_MAX"),
+            all::OP_WITHIN => Some("OP_WITHIN"),
+            all::OP_RIPEMD160 => Some("OP_RIPEMD160"),
+            all::OP_SHA1 => Some("OP_SHA1"),
+            all::OP_SHA256 => Some("OP_SHA256"),
+            all::OP_HASH160 => Some("OP_HASH160"),
+            all::OP_HASH256 => Some("OP_HASH256"),
+            all::OP_CODESEPARATOR => Some("OP_CODESEPARATOR"),
+            all::OP_CHECKSIG => Some("OP_CHECKSIG"),
+            all::OP_CHECKSIGVERIFY => Some("OP_CHECKSIGVERIFY"),
+            all::OP_CHECKMULTISIG => Some("OP_CHECKMULTISIG"),
+            all::OP_CHECKMULTISIGVERIFY => Some("OP_CHECKMULTISIGVERIFY"),
+            all::OP_CLTV => Some("OP_CLTV"),
+            all::OP_CSV => Some("OP_CSV"),
+            _ => None,
+        }
+    }
+
+    /// The name of the opcode in the script assembly of Bitcoin Core, as in the
+    /// `asm` of `decodescript`. Small number pushes are named by their number,
+    /// e.g. `2` for [all::OP_PUSHNUM_2], and unassigned opcodes as well as
+    /// direct data pushes are all named `OP_UNKNOWN`.
+    pub fn core_name(self) -> &'static str {
+        match self {
+            all::OP_PUSHBYTES_0 => "0",
+            all::OP_PUSHNUM_NEG1 => "-1",
+            all::OP_CLTV => "OP_CHECKLOCKTIMEVERIFY",
+            all::OP_CSV => "OP_CHECKSEQUENCEVERIFY",
+            all::OP_RETURN_255 => "OP_INVALIDOPCODE",
+            All {code: x} if x >= all::OP_PUSHNUM_1.code && x <= all::OP_PUSHNUM_16.code => CORE_PUSHNUM_NAMES[(x - all::OP_PUSHNUM_1.code) as usize],
+            All {code: x} if x >= all::OP_NOP1.code && x <= all::OP_NOP10.code => CORE_NOP_NAMES[(x - all::OP_NOP1.code) as usize],
+            op => op.name().unwrap_or("OP_UNKNOWN"),
+        }
+    }
+
     /// Classifies an Opcode into a broad class
     #[inline]
     pub fn classify(self) -> Class {
@@ -700,6 +798,12 @@ impl All {
     pub fn into_u8(self) -> u8 {
         self.code
     }
+
+    /// Encode as a byte, same as [All::into_u8]
+    #[inline]
+    pub fn to_u8(self) -> u8 {
+        self.code
+    }
 }
 
 impl From<u8> for All {
@@ -730,6 +834,10 @@ pub static OP_TRUE: All = all::OP_PUSHNUM_1;
 pub static OP_NOP2: All = all::OP_CLTV;
 /// previously called OP_NOP3
 pub static OP_NOP3: All = all::OP_CSV;
+/// BIP65 name of OP_CLTV
+pub static OP_CHECKLOCKTIMEVERIFY: All = all::OP_CLTV;
+/// BIP112 name of OP_CSV
+pub static OP_CHECKSEQUENCEVERIFY: All = all::OP_CSV;
 
 /// Broad categories of opcodes with similar behavior
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -762,11 +870,14 @@ impl serde::Serialize for Class {
 
 macro_rules! ordinary_opcode {
     ($($op:ident),*) => (
+        /// An opcode which the interpreter executes normally, see [Class::Ordinary]
         #[repr(u8)]
-        #[doc(hidden)]
-        #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+        #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
         pub enum Ordinary {
-            $( $op = all::$op.code ),*
+            $(
+                /// The opcode of the same name in [all]
+                $op = all::$op.code
+            ),*
         }
 
         impl Ordinary {
@@ -814,6 +925,13 @@ impl Ordinary {
   }
 }
 
+impl From<Ordinary> for All {
+    #[inline]
+    fn from(op: Ordinary) -> All {
+        All {code: op.into_u8()}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
@@ -1093,5 +1211,70 @@ mod tests {
         roundtrip!(unique, OP_RETURN_255);
         assert_eq!(unique.len(), 256);
     }
+
+    #[test]
+    fn classify_opcodes() {
+        assert_eq!(all::OP_PUSHBYTES_0.classify(), Class::PushBytes(0));
+        assert_eq!(all::OP_PUSHBYTES_75.classify(), Class::PushBytes(75));
+        assert_eq!(all::OP_PUSHNUM_NEG1.classify(), Class::PushNum(-1));
+        assert_eq!(all::OP_PUSHNUM_1.classify(), Class::PushNum(1));
+        assert_eq!(all::OP_PUSHNUM_16.classify(), Class::PushNum(16));
+        // fail only when executed
+        assert_eq!(all::OP_RESERVED.classify(), Class::ReturnOp);
+        assert_eq!(all::OP_VER.classify(), Class::ReturnOp);
+        assert_eq!(all::OP_RETURN.classify(), Class::ReturnOp);
+        assert_eq!(all::OP_RETURN_186.classify(), Class::ReturnOp);
+        // fail even in an unexecuted branch
+        assert_eq!(all::OP_VERIF.classify(), Class::IllegalOp);
+        assert_eq!(all::OP_CAT.classify(), Class::IllegalOp);
+        assert_eq!(all::OP_MUL.classify(), Class::IllegalOp);
+        assert_eq!(all::OP_NOP.classify(), Class::NoOp);
+        assert_eq!(all::OP_NOP10.classify(), Class::NoOp);
+        assert_eq!(OP_CHECKLOCKTIMEVERIFY.classify(), Class::NoOp);
+        assert_eq!(all::OP_PUSHDATA1.classify(), Class::Ordinary(Ordinary::OP_PUSHDATA1));
+        assert_eq!(all::OP_CHECKSIG.classify(), Class::Ordinary(Ordinary::OP_CHECKSIG));
+
+        let mut ordinary = 0;
+        for n in 0..256 {
+            let op = All::from(n as u8);
+            assert_eq!(op.to_u8(), n as u8);
+            if let Class::Ordinary(o) = op.classify() {
+                assert_eq!(All::from(o), op);
+                ordinary += 1;
+            }
+        }
+        assert_eq!(ordinary, 60);
+    }
+
+    #[test]
+    fn parse_names() {
+        for n in 0..256 {
+            let op = All::from(n as u8);
+            assert_eq!(All::from_str(&op.to_string()), Ok(op));
+            let core_name = op.core_name();
+            if core_name != "OP_UNKNOWN" {
+                let core_name = if core_name.starts_with("OP_") { core_name.to_owned() } else { format!("OP_{}", core_name) };
+                let core_name = if core_name == "OP_-1" { "OP_1NEGATE".to_owned() } else { core_name };
+                assert_eq!(All::from_str(&core_name), Ok(op), "{}", core_name);
+            }
+        }
+
+        assert_eq!(all::OP_PUSHBYTES_0.core_name(), "0");
+        assert_eq!(all::OP_PUSHNUM_NEG1.core_name(), "-1");
+        assert_eq!(all::OP_PUSHNUM_16.core_name(), "16");
+        assert_eq!(all::OP_CLTV.core_name(), "OP_CHECKLOCKTIMEVERIFY");
+        assert_eq!(all::OP_NOP10.core_name(), "OP_NOP10");
+        assert_eq!(all::OP_PUSHBYTES_20.core_name(), "OP_UNKNOWN");
+        assert_eq!(all::OP_RETURN_186.core_name(), "OP_UNKNOWN");
+        assert_eq!(all::OP_RETURN_255.core_name(), "OP_INVALIDOPCODE");
+
+        assert_eq!(All::from_str("OP_TRUE"), Ok(all::OP_PUSHNUM_1));
+        assert_eq!(All::from_str("OP_FALSE"), Ok(all::OP_PUSHBYTES_0));
+        assert_eq!(All::from_str("OP_NOP2"), Ok(all::OP_CLTV));
+        assert_eq!(All::from_str("OP_CHECKSEQUENCEVERIFY"), Ok(all::OP_CSV));
+        for bad in &["OP_17", "OP_01", "OP_PUSHBYTES_76", "OP_RETURN_185", "OP_NOP11", "DUP", "OP_UNKNOWN", ""] {
+            assert_eq!(All::from_str(bad), Err(UnknownOpcodeError(bad.to_string())));
+        }
+    }
 }
 
@@ -0,0 +1,406 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Transaction packages
+//!
+//! A package is a set of related unconfirmed transactions, such as a parent
+//! and a child paying for it, which is meant to be relayed and evaluated as a
+//! whole. It is serialized as a vector of transactions, parents first.
+//!
+
+
+use std::fmt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::error;
+
+use blockdata::transaction::{OutPoint, Transaction, TxOut};
+use consensus::encode::{self, Decodable, Encodable};
+use hash_types::Txid;
+use util::fee_rate::{FeeRate, Weight};
+
+/// A rule of [TxPackage::validate] which a transaction of a package breaks
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PackageError {
+    /// A coinbase transaction, which can only be part of a block
+    Coinbase {
+        /// Position of the transaction
+        index: usize,
+    },
+    /// The transaction appears twice
+    Duplicate {
+        /// Position of the second occurrence
+        index: usize,
+        /// Position of the first occurrence
+        first: usize,
+    },
+    /// The transaction spends an output already spent by an earlier input of
+    /// the package
+    Conflict {
+        /// Position of the transaction
+        index: usize,
+        /// Position of the transaction spending the output first
+        other: usize,
+        /// The output spent twice
+        outpoint: OutPoint,
+    },
+    /// The transaction spends an output which its parent in the package
+    /// doesn't have
+    MissingOutput {
+        /// Position of the transaction
+        index: usize,
+        /// The spent output
+        outpoint: OutPoint,
+    },
+    /// The transaction comes before its parent
+    Unordered {
+        /// Position of the transaction
+        index: usize,
+        /// Position of its parent
+        parent: usize,
+    },
+    /// The transaction is part of a cycle of transactions spending each
+    /// other, so the package has no valid order
+    Cycle {
+        /// Position of the transaction
+        index: usize,
+    },
+}
+
+impl fmt::Display for PackageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PackageError::Coinbase { index } => write!(f, "transaction {} is a coinbase", index),
+            PackageError::Duplicate { index, first } => {
+                write!(f, "transaction {} duplicates transaction {}", index, first)
+            }
+            PackageError::Conflict { index, other, ref outpoint } => {
+                write!(f, "transaction {} spends {}, which transaction {} already spends", index, outpoint, other)
+            }
+            PackageError::MissingOutput { index, ref outpoint } => {
+                write!(f, "transaction {} spends nonexistent output {}", index, outpoint)
+            }
+            PackageError::Unordered { index, parent } => {
+                write!(f, "transaction {} comes before its parent {}", index, parent)
+            }
+            PackageError::Cycle { index } => write!(f, "transaction {} is part of a cycle", index),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for PackageError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// A package of related transactions, in topological order
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct TxPackage(pub Vec<Transaction>);
+
+impl TxPackage {
+    /// Check that the package is consistent: it has no coinbase or duplicate
+    /// transactions, no output is spent twice, outputs spent within the
+    /// package exist, and every transaction comes after its parents.
+    ///
+    /// Outputs of transactions outside of the package are assumed to be
+    /// confirmed or otherwise available.
+    pub fn validate(&self) -> Result<(), PackageError> {
+        for (index, parents) in self.parents()?.into_iter().enumerate() {
+            if let Some(&parent) = parents.iter().find(|&&parent| parent > index) {
+                return Err(PackageError::Unordered { index: index, parent: parent });
+            }
+        }
+        Ok(())
+    }
+
+    /// Sort the transactions into the canonical order: parents before their
+    /// children and otherwise by txid, so that any ordering of the same
+    /// transactions results in the same package.
+    ///
+    /// Fails, leaving the package as it is, if it can't be ordered or breaks
+    /// one of the other rules of [TxPackage::validate].
+    pub fn canonicalize(&mut self) -> Result<(), PackageError> {
+        let parents = self.parents()?;
+        let txids: Vec<Txid> = self.0.iter().map(Transaction::txid).collect();
+        let order = topological_order(&txids, &parents).map_err(|index| PackageError::Cycle { index: index })?;
+
+        let mut txs: Vec<Option<Transaction>> = self.0.drain(..).map(Some).collect();
+        self.0 = order.into_iter().map(|index| txs[index].take().expect("each index once")).collect();
+        Ok(())
+    }
+
+    /// The total weight of the package
+    pub fn weight(&self) -> Weight {
+        self.0.iter().fold(Weight::from_wu(0), |weight, tx| weight + tx.weight())
+    }
+
+    /// The total fee paid by the package. `spent` is called for the outputs
+    /// spent from outside of the package; if it doesn't know one, or the
+    /// package spends more than its inputs, there is no fee.
+    pub fn fee<S>(&self, mut spent: S) -> Option<u64>
+        where S: FnMut(&OutPoint) -> Option<TxOut>
+    {
+        let txs: BTreeMap<Txid, &Transaction> = self.0.iter().map(|tx| (tx.txid(), tx)).collect();
+        let mut input_value = 0u64;
+        let mut output_value = 0u64;
+        for tx in &self.0 {
+            for input in &tx.input {
+                let prevout = &input.previous_output;
+                let value = match txs.get(&prevout.txid) {
+                    Some(parent) => parent.output.get(prevout.vout as usize)?.value,
+                    None => spent(prevout)?.value,
+                };
+                input_value = input_value.checked_add(value)?;
+            }
+            for output in &tx.output {
+                output_value = output_value.checked_add(output.value)?;
+            }
+        }
+        input_value.checked_sub(output_value)
+    }
+
+    /// The fee rate of the package as a whole, see [TxPackage::fee]
+    pub fn fee_rate<S>(&self, spent: S) -> Option<FeeRate>
+        where S: FnMut(&OutPoint) -> Option<TxOut>
+    {
+        let weight = self.weight().to_wu();
+        if weight == 0 {
+            return None;
+        }
+        Some(FeeRate::from_sat_per_kwu(self.fee(spent)?.checked_mul(1000)? / weight))
+    }
+
+    /// The positions of the in-package parents of every transaction, checking
+    /// all rules but the order
+    fn parents(&self) -> Result<Vec<Vec<usize>>, PackageError> {
+        let mut positions = BTreeMap::new();
+        for (index, tx) in self.0.iter().enumerate() {
+            if tx.is_coin_base() {
+                return Err(PackageError::Coinbase { index: index });
+            }
+            if let Some(&first) = positions.get(&tx.txid()) {
+                return Err(PackageError::Duplicate { index: index, first: first });
+            }
+            positions.insert(tx.txid(), index);
+        }
+
+        let mut spenders = BTreeMap::new();
+        let mut parents = Vec::with_capacity(self.0.len());
+        for (index, tx) in self.0.iter().enumerate() {
+            let mut tx_parents = vec![];
+            for input in &tx.input {
+                let outpoint = input.previous_output;
+                if let Some(&other) = spenders.get(&outpoint) {
+                    return Err(PackageError::Conflict { index: index, other: other, outpoint: outpoint });
+                }
+                spenders.insert(outpoint, index);
+
+                if let Some(&parent) = positions.get(&outpoint.txid) {
+                    if outpoint.vout as usize >= self.0[parent].output.len() {
+                        return Err(PackageError::MissingOutput { index: index, outpoint: outpoint });
+                    }
+                    if !tx_parents.contains(&parent) {
+                        tx_parents.push(parent);
+                    }
+                }
+            }
+            parents.push(tx_parents);
+        }
+        Ok(parents)
+    }
+}
+
+/// Order the transactions with the given txids and parents so that parents come
+/// first, and by txid where that leaves a choice. On a cycle, returns the
+/// position of one of the transactions which could not be ordered.
+fn topological_order(txids: &[Txid], parents: &[Vec<usize>]) -> Result<Vec<usize>, usize> {
+    let mut missing_parents: Vec<usize> = parents.iter().map(Vec::len).collect();
+    let mut children = vec![vec![]; txids.len()];
+    for (index, tx_parents) in parents.iter().enumerate() {
+        for &parent in tx_parents {
+            children[parent].push(index);
+        }
+    }
+
+    let mut ready: BTreeSet<(Txid, usize)> = (0..txids.len())
+        .filter(|&index| missing_parents[index] == 0)
+        .map(|index| (txids[index], index))
+        .collect();
+    let mut order = Vec::with_capacity(txids.len());
+    loop {
+        // take the entry out in its own statement, so the borrow of `ready`
+        // ends before it is modified
+        let next = match ready.iter().next() {
+            Some(&next) => next,
+            None => break,
+        };
+        ready.remove(&next);
+        let index = next.1;
+        order.push(index);
+        for &child in &children[index] {
+            missing_parents[child] -= 1;
+            if missing_parents[child] == 0 {
+                ready.insert((txids[child], child));
+            }
+        }
+    }
+
+    match missing_parents.iter().position(|&missing| missing > 0) {
+        Some(index) => Err(index),
+        None => Ok(order),
+    }
+}
+
+impl Encodable for TxPackage {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl Decodable for TxPackage {
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(TxPackage(Decodable::consensus_decode(d)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+    use hash_types::Txid;
+    use blockdata::script::{Builder, Script};
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+    use util::fee_rate::FeeRate;
+
+    use super::{topological_order, PackageError, TxPackage};
+
+    /// A transaction spending `prevouts` into `outputs` outputs of 10k each
+    fn tx(prevouts: &[OutPoint], outputs: usize) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: prevouts.iter().map(|&prevout| TxIn {
+                previous_output: prevout,
+                script_sig: Builder::new().push_int(1).into_script(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }).collect(),
+            output: (0..outputs).map(|_| TxOut { value: 10_000, script_pubkey: Script::new() }).collect(),
+        }
+    }
+
+    fn confirmed(vout: u32) -> OutPoint {
+        OutPoint::new(Txid::hash(&[1]), vout)
+    }
+
+    #[test]
+    fn validate_package() {
+        let parent = tx(&[confirmed(0)], 2);
+        let child = tx(&[OutPoint::new(parent.txid(), 0), confirmed(1)], 1);
+        let grandchild = tx(&[OutPoint::new(child.txid(), 0), OutPoint::new(parent.txid(), 1)], 1);
+        let package = TxPackage(vec![parent.clone(), child.clone(), grandchild.clone()]);
+        assert_eq!(package.validate(), Ok(()));
+
+        let decoded: TxPackage = deserialize(&serialize(&package)).unwrap();
+        assert_eq!(decoded, package);
+        assert_eq!(serialize(&package), serialize(&package.0));
+
+        let invalid = TxPackage(vec![parent.clone(), grandchild.clone(), child.clone()]);
+        assert_eq!(invalid.validate(), Err(PackageError::Unordered { index: 1, parent: 2 }));
+
+        let invalid = TxPackage(vec![parent.clone(), child.clone(), parent.clone()]);
+        assert_eq!(invalid.validate(), Err(PackageError::Duplicate { index: 2, first: 0 }));
+
+        let double_spend = tx(&[confirmed(1)], 1);
+        let invalid = TxPackage(vec![parent.clone(), child.clone(), double_spend]);
+        assert_eq!(invalid.validate(), Err(PackageError::Conflict { index: 2, other: 1, outpoint: confirmed(1) }));
+
+        let missing = tx(&[OutPoint::new(parent.txid(), 2)], 1);
+        let invalid = TxPackage(vec![parent.clone(), missing]);
+        assert_eq!(invalid.validate(), Err(PackageError::MissingOutput {
+            index: 1,
+            outpoint: OutPoint::new(parent.txid(), 2),
+        }));
+
+        let coinbase = tx(&[OutPoint::null()], 1);
+        let invalid = TxPackage(vec![coinbase, parent.clone()]);
+        assert_eq!(invalid.validate(), Err(PackageError::Coinbase { index: 0 }));
+    }
+
+    #[test]
+    fn canonical_order() {
+        let parent = tx(&[confirmed(0)], 2);
+        let child = tx(&[OutPoint::new(parent.txid(), 0)], 1);
+        let sibling = tx(&[OutPoint::new(parent.txid(), 1)], 1);
+        let unrelated = tx(&[confirmed(1)], 1);
+        let grandchild = tx(&[OutPoint::new(child.txid(), 0), OutPoint::new(sibling.txid(), 0)], 1);
+
+        let mut package = TxPackage(vec![
+            grandchild.clone(), sibling.clone(), unrelated.clone(), child.clone(), parent.clone(),
+        ]);
+        assert_eq!(package.validate(), Err(PackageError::Unordered { index: 0, parent: 3 }));
+        package.canonicalize().unwrap();
+        assert_eq!(package.validate(), Ok(()));
+        assert_eq!(package.0.last(), Some(&grandchild));
+
+        // the same transactions in any order give the same package
+        let mut other = TxPackage(vec![
+            child.clone(), grandchild.clone(), parent.clone(), unrelated.clone(), sibling.clone(),
+        ]);
+        other.canonicalize().unwrap();
+        assert_eq!(other, package);
+
+        let mut invalid = TxPackage(vec![child.clone(), child.clone()]);
+        assert_eq!(invalid.canonicalize(), Err(PackageError::Duplicate { index: 1, first: 0 }));
+        assert_eq!(invalid.0, vec![child.clone(), child.clone()]);
+    }
+
+    #[test]
+    fn cycle() {
+        // transactions can't spend each other in a cycle since each txid
+        // commits to the txids spent, so the ordering is tested on its own
+        let txids: Vec<Txid> = (0..4u8).map(|i| Txid::hash(&[i])).collect();
+        let parents = vec![vec![], vec![0, 3], vec![1], vec![2]];
+        assert_eq!(topological_order(&txids, &parents), Err(1));
+
+        let parents = vec![vec![3], vec![], vec![1], vec![]];
+        let order = topological_order(&txids, &parents).unwrap();
+        assert!(order.iter().position(|&i| i == 3) < order.iter().position(|&i| i == 0));
+        assert!(order.iter().position(|&i| i == 1) < order.iter().position(|&i| i == 2));
+    }
+
+    #[test]
+    fn fee_rate() {
+        let parent = tx(&[confirmed(0)], 2);
+        let child = tx(&[OutPoint::new(parent.txid(), 0), OutPoint::new(parent.txid(), 1)], 1);
+        let package = TxPackage(vec![parent.clone(), child.clone()]);
+        let spent = |outpoint: &OutPoint| {
+            if *outpoint == confirmed(0) {
+                Some(TxOut { value: 30_000, script_pubkey: Script::new() })
+            } else {
+                None
+            }
+        };
+
+        assert_eq!(package.weight(), parent.weight() + child.weight());
+        // 30k in, 10k out: the child pays for its parent
+        assert_eq!(package.fee(spent), Some(20_000));
+        assert_eq!(package.fee_rate(spent), FeeRate::from_sat_per_kwu(20_000_000 / package.weight().to_wu()).into());
+        assert_eq!(TxPackage(vec![child.clone()]).fee(spent), None);
+        assert_eq!(TxPackage(vec![]).fee_rate(spent), None);
+    }
+}
@@ -24,23 +24,33 @@
 //! This module provides the structures and functions needed to support scripts.
 //!
 
+
 use std::default::Default;
-use std::{error, fmt, io};
+use std::fmt;
+use std::io;
+use std::error;
 
 #[cfg(feature = "serde")] use serde;
 
 use hash_types::{PubkeyHash, WPubkeyHash, ScriptHash, WScriptHash};
 use blockdata::opcodes;
+use blockdata::constants::WITNESS_SCALE_FACTOR;
 use consensus::{encode, Decodable, Encodable};
+use consensus::encode::VarInt;
 use hashes::Hash;
+use hashes::hex::{self, FromHex, ToHex};
 #[cfg(feature="bitcoinconsensus")] use bitcoinconsensus;
 #[cfg(feature="bitcoinconsensus")] use std::convert;
 #[cfg(feature="bitcoinconsensus")] use OutPoint;
 
 use util::key::PublicKey;
+use util::amount::Amount;
 
 #[derive(Clone, Default, PartialOrd, Ord, PartialEq, Eq, Hash)]
 /// A Bitcoin script
+///
+/// Scripts are ordered lexicographically by their bytes, which says nothing
+/// about what they do.
 pub struct Script(Box<[u8]>);
 
 impl fmt::Debug for Script {
@@ -92,9 +102,11 @@ pub enum Error {
     EarlyEndOfScript,
     /// Tried to read an array off the stack as a number when it was more than 4 bytes
     NumericOverflow,
+    /// A word of script assembly which is neither a number, an opcode nor hex data
+    InvalidAsm(String),
     #[cfg(feature="bitcoinconsensus")]
     /// Error validating the script with bitcoinconsensus library
-    BitcoinConsensus(bitcoinconsensus::Error),
+    BitcoinConsensus(ConsensusError),
     #[cfg(feature="bitcoinconsensus")]
     /// Can not find the spent output
     UnknownSpentOutput(OutPoint),
@@ -109,8 +121,9 @@ impl fmt::Display for Error {
             Error::NonMinimalPush => "non-minimal datapush",
             Error::EarlyEndOfScript => "unexpected end of script",
             Error::NumericOverflow => "numeric overflow (number on stack larger than 4 bytes)",
+            Error::InvalidAsm(ref word) => return write!(f, "invalid script assembly: {}", word),
             #[cfg(feature="bitcoinconsensus")]
-            Error::BitcoinConsensus(ref _n) => "bitcoinconsensus verification failed",
+            Error::BitcoinConsensus(ref e) => return write!(f, "bitcoinconsensus verification failed: {}", e),
             #[cfg(feature="bitcoinconsensus")]
             Error::UnknownSpentOutput(ref _point) => "unknown spent output Transaction::verify()",
             #[cfg(feature="bitcoinconsensus")]
@@ -122,6 +135,54 @@ impl fmt::Display for Error {
 
 #[allow(deprecated)]
 impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            #[cfg(feature="bitcoinconsensus")]
+            Error::BitcoinConsensus(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+#[cfg(feature="bitcoinconsensus")]
+/// Why libbitcoinconsensus rejected a spend
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ConsensusError {
+    /// The script failed to execute successfully
+    Script,
+    /// The input index is out of range for the spending transaction
+    TxIndex,
+    /// The spending transaction has trailing data after it
+    TxSizeMismatch,
+    /// The spending transaction could not be deserialized
+    TxDeserialize,
+    /// Witness verification was requested without the spent amount
+    AmountRequired,
+    /// The verification flags are not supported by the library
+    InvalidFlags,
+}
+
+#[cfg(feature="bitcoinconsensus")]
+impl fmt::Display for ConsensusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ConsensusError::Script => "script evaluation failed",
+            ConsensusError::TxIndex => "input index out of range",
+            ConsensusError::TxSizeMismatch => "spending transaction has trailing data",
+            ConsensusError::TxDeserialize => "spending transaction could not be deserialized",
+            ConsensusError::AmountRequired => "spent amount required for witness verification",
+            ConsensusError::InvalidFlags => "invalid verification flags",
+        })
+    }
+}
+
+#[cfg(feature="bitcoinconsensus")]
+#[allow(deprecated)]
+impl error::Error for ConsensusError {
     fn description(&self) -> &str {
         "description() is deprecated; use Display"
     }
@@ -129,40 +190,66 @@ impl error::Error for Error {
 
 #[cfg(feature="bitcoinconsensus")]
 #[doc(hidden)]
-impl convert::From<bitcoinconsensus::Error> for Error {
-    fn from(err: bitcoinconsensus::Error) -> Error {
+impl convert::From<bitcoinconsensus::Error> for ConsensusError {
+    fn from(err: bitcoinconsensus::Error) -> ConsensusError {
         match err {
-            _ => Error::BitcoinConsensus(err)
+            bitcoinconsensus::Error::ERR_SCRIPT => ConsensusError::Script,
+            bitcoinconsensus::Error::ERR_TX_INDEX => ConsensusError::TxIndex,
+            bitcoinconsensus::Error::ERR_TX_SIZE_MISMATCH => ConsensusError::TxSizeMismatch,
+            bitcoinconsensus::Error::ERR_TX_DESERIALIZE => ConsensusError::TxDeserialize,
+            bitcoinconsensus::Error::ERR_AMOUNT_REQUIRED => ConsensusError::AmountRequired,
+            bitcoinconsensus::Error::ERR_INVALID_FLAGS => ConsensusError::InvalidFlags,
         }
     }
 }
-/// Helper to encode an integer in script format
-fn build_scriptint(n: i64) -> Vec<u8> {
-    if n == 0 { return vec![] }
 
-    let neg = n < 0;
+#[cfg(feature="bitcoinconsensus")]
+#[doc(hidden)]
+impl convert::From<bitcoinconsensus::Error> for Error {
+    fn from(err: bitcoinconsensus::Error) -> Error {
+        Error::BitcoinConsensus(err.into())
+    }
+}
+/// Encode an integer in script format into `out`, returning the number of bytes
+/// used. The encoding is minimal: little-endian magnitude without trailing zero
+/// bytes, with the sign in the top bit and an extra byte only where the top bit
+/// of the magnitude is already set.
+pub fn write_scriptint(out: &mut [u8; 9], n: i64) -> usize {
+    if n == 0 { return 0 }
 
-    let mut abs = if neg { -n } else { n } as usize;
-    let mut v = vec![];
+    let neg = n < 0;
+    // two's complement negation, which also works for i64::min_value()
+    let mut abs = if neg { (!(n as u64)).wrapping_add(1) } else { n as u64 };
+    let mut len = 0;
     while abs > 0xFF {
-        v.push((abs & 0xFF) as u8);
+        out[len] = (abs & 0xFF) as u8;
+        len += 1;
         abs >>= 8;
     }
     // If the number's value causes the sign bit to be set, we need an extra
     // byte to get the correct value and correct sign bit
     if abs & 0x80 != 0 {
-        v.push(abs as u8);
-        v.push(if neg { 0x80u8 } else { 0u8 });
+        out[len] = abs as u8;
+        out[len + 1] = if neg { 0x80u8 } else { 0u8 };
+        len + 2
     }
     // Otherwise we just set the sign bit ourselves
     else {
-        abs |= if neg { 0x80 } else { 0 };
-        v.push(abs as u8);
+        out[len] = (abs | if neg { 0x80 } else { 0 }) as u8;
+        len + 1
     }
-    v
 }
 
-/// Helper to decode an integer in script format
+/// Helper to encode an integer in script format
+fn build_scriptint(n: i64) -> Vec<u8> {
+    let mut buf = [0u8; 9];
+    let len = write_scriptint(&mut buf, n);
+    buf[..len].to_vec()
+}
+
+/// Helper to decode an integer in script format, rejecting encodings which
+/// aren't minimal as Bitcoin Core does under the MINIMALDATA rule.
+///
 /// Notice that this fails on overflow: the result is the same as in
 /// bitcoind, that only 4-byte signed-magnitude values may be read as
 /// numbers. They can be added or subtracted (and a long time ago,
@@ -177,17 +264,43 @@ fn build_scriptint(n: i64) -> Vec<u8> {
 /// simply say, anything in excess of 32 bits is no longer a number.
 /// This is basically a ranged type implementation.
 pub fn read_scriptint(v: &[u8]) -> Result<i64, Error> {
+    read_scriptint_size(v, 4)
+}
+
+/// Decode a minimally encoded integer in script format of at most `max_size`
+/// bytes. Most opcodes take 4-byte numbers, but OP_CHECKLOCKTIMEVERIFY and
+/// OP_CHECKSEQUENCEVERIFY take 5-byte ones. Sizes above 8 bytes are treated
+/// as 8, the most which fits an `i64`.
+pub fn read_scriptint_size(v: &[u8], max_size: usize) -> Result<i64, Error> {
+    if let Some(&last) = v.last() {
+        // the last byte may only be zero, apart from the sign bit, if it
+        // holds the sign of a magnitude whose top bit is set
+        if last & 0x7f == 0 && (v.len() == 1 || v[v.len() - 2] & 0x80 == 0) {
+            return Err(Error::NonMinimalPush);
+        }
+    }
+    decode_scriptint(v, max_size)
+}
+
+/// Decode an integer in script format of at most 4 bytes, also accepting
+/// encodings which aren't minimal, such as zero padding or negative zero,
+/// as the interpreter does without the MINIMALDATA rule.
+pub fn read_scriptint_non_minimal(v: &[u8]) -> Result<i64, Error> {
+    decode_scriptint(v, 4)
+}
+
+fn decode_scriptint(v: &[u8], max_size: usize) -> Result<i64, Error> {
     let len = v.len();
+    if len > max_size || len > 8 { return Err(Error::NumericOverflow); }
     if len == 0 { return Ok(0); }
-    if len > 4 { return Err(Error::NumericOverflow); }
 
-    let (mut ret, sh) = v.iter()
-                         .fold((0, 0), |(acc, sh), n| (acc + ((*n as i64) << sh), sh + 8));
+    let mut ret = v.iter().rev().fold(0u64, |acc, n| (acc << 8) | *n as u64);
     if v[len - 1] & 0x80 != 0 {
-        ret &= (1 << (sh - 1)) - 1;
-        ret = -ret;
+        ret &= (1 << (8 * len - 1)) - 1;
+        Ok(-(ret as i64))
+    } else {
+        Ok(ret as i64)
     }
-    Ok(ret)
 }
 
 /// This is like "`read_scriptint` then map 0 to false and everything
@@ -245,13 +358,25 @@ impl Script {
     }
 
     /// Generates P2WPKH-type of scriptPubkey
+    #[deprecated(since="0.25.1", note="please use `new_v0_p2wpkh` instead")]
     pub fn new_v0_wpkh(pubkey_hash: &WPubkeyHash) -> Script {
-        Script::new_witness_program(::bech32::u5::try_from_u8(0).unwrap(), &pubkey_hash.to_vec())
+        Script::new_v0_p2wpkh(pubkey_hash)
     }
 
     /// Generates P2WSH-type of scriptPubkey with a given hash of the redeem script
+    #[deprecated(since="0.25.1", note="please use `new_v0_p2wsh` instead")]
     pub fn new_v0_wsh(script_hash: &WScriptHash) -> Script {
-        Script::new_witness_program(::bech32::u5::try_from_u8(0).unwrap(), &script_hash.to_vec())
+        Script::new_v0_p2wsh(script_hash)
+    }
+
+    /// Generates P2WPKH-type of scriptPubkey
+    pub fn new_v0_p2wpkh(pubkey_hash: &WPubkeyHash) -> Script {
+        Script::new_witness_program(::bech32::u5::try_from_u8(0).unwrap(), &pubkey_hash[..])
+    }
+
+    /// Generates P2WSH-type of scriptPubkey with a given hash of the witness script
+    pub fn new_v0_p2wsh(script_hash: &WScriptHash) -> Script {
+        Script::new_witness_program(::bech32::u5::try_from_u8(0).unwrap(), &script_hash[..])
     }
 
     /// Generates P2WSH-type of scriptPubkey with a given hash of the redeem script
@@ -281,13 +406,46 @@ impl Script {
     /// Whether the script is the empty script
     pub fn is_empty(&self) -> bool { self.0.is_empty() }
 
-    /// Returns the script data
+    /// The length in bytes of the consensus encoding of the script, which
+    /// prefixes the script with its length
+    pub fn consensus_encoded_len(&self) -> usize {
+        VarInt(self.0.len() as u64).len() + self.0.len()
+    }
+
+    /// The smallest value an output with this script pubkey can have without
+    /// being dust, which standard nodes don't relay. OP_RETURN outputs can
+    /// have any value.
+    ///
+    /// An output is dust if spending it would cost more than a third of its
+    /// value at the default dust relay fee rate of 3 sat/vB, counting the
+    /// output and a typical input spending it. For P2PKH outputs this is
+    /// [DUST_THRESHOLD](::util::risk::DUST_THRESHOLD).
+    pub fn dust_value(&self) -> Amount {
+        const DUST_RELAY_FEE_SAT_PER_VB: usize = 3;
+        if self.is_op_return() {
+            return Amount::ZERO;
+        }
+        // value and script pubkey of the output, outpoint, sequence and a
+        // scriptSig or witness with a signature and a public key
+        let output_size = 8 + self.consensus_encoded_len();
+        let input_size = if self.is_witness_program() {
+            32 + 4 + 1 + 107 / WITNESS_SCALE_FACTOR + 4
+        } else {
+            32 + 4 + 1 + 107 + 4
+        };
+        Amount::from_sat((DUST_RELAY_FEE_SAT_PER_VB * (output_size + input_size)) as u64)
+    }
+
+    /// Returns the script data. This is the bare script, without the length
+    /// prefix of its consensus encoding.
     pub fn as_bytes(&self) -> &[u8] { &*self.0 }
 
-    /// Returns a copy of the script data
+    /// Returns a copy of the script data, without the length prefix of its
+    /// consensus encoding
     pub fn to_bytes(&self) -> Vec<u8> { self.0.clone().into_vec() }
 
-    /// Convert the script into a byte vector
+    /// Convert the script into a byte vector, without the length prefix of its
+    /// consensus encoding
     pub fn into_bytes(self) -> Vec<u8> { self.0.into_vec() }
 
     /// Compute the P2SH output corresponding to this redeem script
@@ -298,7 +456,18 @@ impl Script {
     /// Compute the P2WSH output corresponding to this witnessScript (aka the "witness redeem
     /// script")
     pub fn to_v0_p2wsh(&self) -> Script {
-        Script::new_v0_wsh(&WScriptHash::hash(&self.0))
+        Script::new_v0_p2wsh(&WScriptHash::hash(&self.0))
+    }
+
+    /// The BIP143 script code of a P2WPKH output, the P2PKH script of the same
+    /// key hash, or [None] if this is not a P2WPKH output script. For P2SH-P2WPKH
+    /// outputs this is to be called on the redeem script.
+    pub fn p2wpkh_script_code(&self) -> Option<Script> {
+        if self.is_v0_p2wpkh() {
+            Some(Script::new_p2pkh(&PubkeyHash::from_slice(&self.0[2..]).expect("20 bytes")))
+        } else {
+            None
+        }
     }
 
     /// Checks whether a script pubkey is a p2sh output
@@ -332,6 +501,35 @@ impl Script {
             self.0[34] == opcodes::all::OP_CHECKSIG.into_u8())
     }
 
+    /// Checks whether a script pubkey is a bare `m`-of-`n` multisig output,
+    /// `OP_m <key>... OP_n OP_CHECKMULTISIG` with 33 or 65 byte keys as Monacoin
+    /// Core's standardness rules require.
+    pub fn is_multisig(&self) -> bool {
+        let mut ins = self.instructions_minimal();
+        let required = match ins.next() {
+            Some(Ok(Instruction::Op(op))) => match op.classify() {
+                opcodes::Class::PushNum(m) if m >= 1 => m,
+                _ => return false,
+            },
+            _ => return false,
+        };
+        let mut keys = 0;
+        loop {
+            match ins.next() {
+                Some(Ok(Instruction::PushBytes(key))) if key.len() == 33 || key.len() == 65 => keys += 1,
+                Some(Ok(Instruction::Op(op))) => match op.classify() {
+                    opcodes::Class::PushNum(n) if n == keys && n >= required => break,
+                    _ => return false,
+                },
+                _ => return false,
+            }
+        }
+        match (ins.next(), ins.next()) {
+            (Some(Ok(Instruction::Op(opcodes::all::OP_CHECKMULTISIG))), None) => true,
+            _ => false,
+        }
+    }
+
     /// Checks whether a script pubkey is a Segregated Witness (segwit) program.
     #[inline]
     pub fn is_witness_program(&self) -> bool {
@@ -368,6 +566,14 @@ impl Script {
             self.0[1] == opcodes::all::OP_PUSHBYTES_20.into_u8()
     }
 
+    /// Checks whether a script pubkey is a p2tr output
+    #[inline]
+    pub fn is_v1_p2tr(&self) -> bool {
+        self.0.len() == 34 &&
+            self.0[0] == opcodes::all::OP_PUSHNUM_1.into_u8() &&
+            self.0[1] == opcodes::all::OP_PUSHBYTES_32.into_u8()
+    }
+
     /// Check if this is an OP_RETURN output
     pub fn is_op_return (&self) -> bool {
         !self.0.is_empty() && (opcodes::All::from(self.0[0]) == opcodes::all::OP_RETURN)
@@ -379,6 +585,105 @@ impl Script {
                                opcodes::All::from(self.0[0]).classify() == opcodes::Class::IllegalOp)
     }
 
+    /// Whether the script consists of pushes only, as P2SH and standard
+    /// scriptSigs must.
+    ///
+    /// This matches Monacoin Core's `IsPushOnly`: every opcode up to and
+    /// including OP_16 counts as a push. Those are OP_0, the OP_PUSHBYTES and
+    /// OP_PUSHDATA opcodes, OP_1NEGATE, OP_1 to OP_16 and also OP_RESERVED,
+    /// which only fails when executed. A script with a truncated push is not
+    /// push-only.
+    pub fn is_push_only(&self) -> bool {
+        self.instructions().all(|ins| match ins {
+            Ok(Instruction::PushBytes(_)) => true,
+            Ok(Instruction::Op(op)) => op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8(),
+            Err(_) => false,
+        })
+    }
+
+    /// The data the script pushes, in order.
+    ///
+    /// OP_1NEGATE and OP_1 to OP_16 push their number as a one-byte script
+    /// integer and OP_RESERVED pushes nothing. The result stops at the first
+    /// instruction which is not a push in the sense of [Script::is_push_only]
+    /// or can't be parsed, so it is complete only for push-only scripts.
+    pub fn pushed_data(&self) -> Vec<&[u8]> {
+        // script integer encodings of -1 and 1 to 16
+        static PUSHNUMS: [u8; 17] = [0x81, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+
+        let mut data = vec![];
+        for ins in self.instructions() {
+            match ins {
+                Ok(Instruction::PushBytes(bytes)) => data.push(bytes),
+                Ok(Instruction::Op(op)) => match op.classify() {
+                    opcodes::Class::PushNum(-1) => data.push(&PUSHNUMS[0..1]),
+                    opcodes::Class::PushNum(n) => data.push(&PUSHNUMS[n as usize..n as usize + 1]),
+                    _ if op == opcodes::all::OP_RESERVED => {}
+                    _ => break,
+                },
+                Err(_) => break,
+            }
+        }
+        data
+    }
+
+    /// The public key of a p2pk output, or [None] for any other script or if the pushed key
+    /// is not a valid point, which some old outputs contain.
+    pub fn p2pk_public_key(&self) -> Option<PublicKey> {
+        let mut ins = self.instructions_minimal();
+        match (ins.next(), ins.next(), ins.next()) {
+            (Some(Ok(Instruction::PushBytes(key))),
+             Some(Ok(Instruction::Op(opcodes::all::OP_CHECKSIG))),
+             None) if key.len() == 33 || key.len() == 65 => PublicKey::from_slice(key).ok(),
+            _ => None,
+        }
+    }
+
+    /// The public key hash of a p2pkh output, or [None] for any other script.
+    pub fn p2pkh_pubkey_hash(&self) -> Option<PubkeyHash> {
+        let mut ins = self.instructions_minimal();
+        match (ins.next(), ins.next(), ins.next(), ins.next(), ins.next(), ins.next()) {
+            (Some(Ok(Instruction::Op(opcodes::all::OP_DUP))),
+             Some(Ok(Instruction::Op(opcodes::all::OP_HASH160))),
+             Some(Ok(Instruction::PushBytes(hash))),
+             Some(Ok(Instruction::Op(opcodes::all::OP_EQUALVERIFY))),
+             Some(Ok(Instruction::Op(opcodes::all::OP_CHECKSIG))),
+             None) => PubkeyHash::from_slice(hash).ok(),
+            _ => None,
+        }
+    }
+
+    /// The script hash of a p2sh output, or [None] for any other script.
+    pub fn p2sh_script_hash(&self) -> Option<ScriptHash> {
+        let mut ins = self.instructions_minimal();
+        match (ins.next(), ins.next(), ins.next(), ins.next()) {
+            (Some(Ok(Instruction::Op(opcodes::all::OP_HASH160))),
+             Some(Ok(Instruction::PushBytes(hash))),
+             Some(Ok(Instruction::Op(opcodes::all::OP_EQUAL))),
+             None) => ScriptHash::from_slice(hash).ok(),
+            _ => None,
+        }
+    }
+
+    /// The witness program of a segwit version 0 output, if it is one.
+    fn v0_witness_program(&self) -> Option<&[u8]> {
+        let mut ins = self.instructions_minimal();
+        match (ins.next(), ins.next(), ins.next()) {
+            (Some(Ok(Instruction::PushBytes(&[]))), Some(Ok(Instruction::PushBytes(program))), None) => Some(program),
+            _ => None,
+        }
+    }
+
+    /// The witness public key hash of a p2wpkh output, or [None] for any other script.
+    pub fn v0_wpkh(&self) -> Option<WPubkeyHash> {
+        self.v0_witness_program().and_then(|program| WPubkeyHash::from_slice(program).ok())
+    }
+
+    /// The witness script hash of a p2wsh output, or [None] for any other script.
+    pub fn v0_wsh(&self) -> Option<WScriptHash> {
+        self.v0_witness_program().and_then(|program| WScriptHash::from_slice(program).ok())
+    }
+
     /// Iterate over the script in the form of `Instruction`s, which are an enum covering
     /// opcodes, datapushes and errors. At most one error will be returned and then the
     /// iterator will end. To instead iterate over the script as sequence of bytes, treat
@@ -411,6 +716,14 @@ impl Script {
         Ok(bitcoinconsensus::verify (&self.0[..], amount, spending, index)?)
     }
 
+    #[cfg(feature="bitcoinconsensus")]
+    /// verify spend of an input script with the given `bitcoinconsensus::VERIFY_*`
+    /// flags, e.g. those of [::consensus::params::Params::script_verify_flags]
+    /// for the height the spending transaction is included at
+    pub fn verify_with_flags(&self, index: usize, amount: u64, spending: &[u8], flags: u32) -> Result<(), Error> {
+        Ok(bitcoinconsensus::verify_with_flags(&self.0[..], amount, spending, index, flags)?)
+    }
+
     /// Write the assembly decoding of the script to the formatter.
     pub fn fmt_asm(&self, f: &mut fmt::Write) -> fmt::Result {
         let mut index = 0;
@@ -480,11 +793,137 @@ impl Script {
         Ok(())
     }
 
-    /// Get the assembly decoding of the script.
+    /// Get the assembly of the script in the format of Bitcoin Core, as in the
+    /// `asm` of `decodescript`: pushes of up to 4 bytes are shown as numbers,
+    /// longer ones as hex, and opcodes by [opcodes::All::core_name]. A truncated
+    /// push ends the assembly with `[error]`. Unlike the `Debug` formatting,
+    /// the push opcodes themselves aren't shown.
     pub fn asm(&self) -> String {
-        let mut buf = String::new();
-        self.fmt_asm(&mut buf).unwrap();
-        buf
+        let mut ret = String::new();
+        for instruction in self.instructions() {
+            if !ret.is_empty() {
+                ret.push(' ');
+            }
+            match instruction {
+                Ok(Instruction::PushBytes(data)) if data.len() <= 4 => {
+                    ret.push_str(&read_scriptint_non_minimal(data).expect("at most 4 bytes").to_string());
+                }
+                Ok(Instruction::PushBytes(data)) => ret.push_str(&data.to_hex()),
+                Ok(Instruction::Op(op)) => ret.push_str(op.core_name()),
+                Err(_) => {
+                    ret.push_str("[error]");
+                    break;
+                }
+            }
+        }
+        ret
+    }
+
+    /// Parse script assembly in the format of [Script::asm]. Opcodes may be
+    /// named with or without their `OP_` prefix, and by the names of both
+    /// Bitcoin Core and the `Debug` formatting. Decimal numbers are pushed as
+    /// by [Builder::push_int], other words are pushed as hex data.
+    ///
+    /// As [Script::asm] shows short pushes as numbers, a script whose pushes
+    /// aren't minimal doesn't round-trip exactly, but its assembly does.
+    pub fn from_asm(asm: &str) -> Result<Script, Error> {
+        let mut builder = Builder::new();
+        for word in asm.split_whitespace() {
+            let digits = if word.starts_with('-') { &word[1..] } else { word };
+            let is_decimal = !digits.is_empty() && digits.bytes().all(|c| c >= b'0' && c <= b'9') &&
+                (digits == "0" || !digits.starts_with('0')) && digits.len() <= 10;
+            builder = if is_decimal {
+                match word.parse::<i32>() {
+                    Ok(n) => builder.push_int(n as i64),
+                    Err(_) => builder.push_slice(&Vec::from_hex(word).map_err(|_| Error::InvalidAsm(word.to_owned()))?),
+                }
+            } else if let Ok(op) = word.parse::<opcodes::All>().or_else(|_| format!("OP_{}", word).parse()) {
+                builder.push_opcode(op)
+            } else {
+                builder.push_slice(&Vec::from_hex(word).map_err(|_| Error::InvalidAsm(word.to_owned()))?)
+            };
+        }
+        Ok(builder.into_script())
+    }
+
+    /// Determine which of the well-known output script types this script is.
+    pub fn classify(&self) -> Classification {
+        if self.is_p2pkh() {
+            Classification::P2pkh
+        } else if self.is_p2sh() {
+            Classification::P2sh
+        } else if self.is_v0_p2wpkh() {
+            Classification::V0P2wpkh
+        } else if self.is_v0_p2wsh() {
+            Classification::V0P2wsh
+        } else if self.is_witness_program() {
+            Classification::WitnessProgram
+        } else if self.is_p2pk() {
+            Classification::P2pk
+        } else if self.is_op_return() {
+            Classification::OpReturn
+        } else if self.is_multisig() {
+            Classification::Multisig
+        } else {
+            Classification::NonStandard
+        }
+    }
+
+    /// Classify the script, trying the given custom patterns before the built-in ones.
+    ///
+    /// The patterns are tried in order and the first match wins, so they can refine a
+    /// built-in classification (e.g. recognize a particular kind of OP_RETURN output). If
+    /// none of them matches, this is the same as [Script::classify]. With no patterns it
+    /// costs nothing over calling [Script::classify] directly.
+    pub fn classify_with(&self, patterns: &[&ScriptPattern]) -> Classification {
+        for pattern in patterns {
+            if let Some(class) = pattern.matches(self) {
+                return class;
+            }
+        }
+        self.classify()
+    }
+}
+
+/// The type of an output script, see [Script::classify]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Classification {
+    /// Pay to public key
+    P2pk,
+    /// Pay to public key hash
+    P2pkh,
+    /// Pay to script hash
+    P2sh,
+    /// Segwit version 0 pay to witness public key hash
+    V0P2wpkh,
+    /// Segwit version 0 pay to witness script hash
+    V0P2wsh,
+    /// Witness program of a version or length without defined semantics
+    WitnessProgram,
+    /// OP_RETURN output
+    OpReturn,
+    /// Bare multisig output
+    Multisig,
+    /// Anything else
+    NonStandard,
+    /// A script recognized by a [ScriptPattern] defined outside of this library. The
+    /// string names the pattern.
+    Custom(&'static str),
+}
+
+/// Recognizes a kind of output script not known to this library, for use with
+/// [Script::classify_with].
+///
+/// Any `Fn(&Script) -> Option<Classification>` is a pattern.
+pub trait ScriptPattern {
+    /// Returns the classification of the script if it matches the pattern, or [None] to
+    /// leave it to the next pattern.
+    fn matches(&self, script: &Script) -> Option<Classification>;
+}
+
+impl<F: Fn(&Script) -> Option<Classification>> ScriptPattern for F {
+    fn matches(&self, script: &Script) -> Option<Classification> {
+        self(script)
     }
 }
 
@@ -495,6 +934,21 @@ impl From<Vec<u8>> for Script {
 
 impl_index_newtype!(Script, u8);
 
+/// The script data, without the length prefix of its consensus encoding
+impl AsRef<[u8]> for Script {
+    fn as_ref(&self) -> &[u8] { &self.0 }
+}
+
+/// Parses the hex of the script data, without the length prefix of its
+/// consensus encoding, as formatted by `to_hex`
+impl FromHex for Script {
+    fn from_byte_iter<I>(iter: I) -> Result<Self, hex::Error>
+        where I: Iterator<Item=Result<u8, hex::Error>> + ExactSizeIterator + DoubleEndedIterator,
+    {
+        Vec::from_byte_iter(iter).map(Script::from)
+    }
+}
+
 /// A "parsed opcode" which allows iterating over a Script in a more sensible way
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Instruction<'a> {
@@ -787,20 +1241,43 @@ impl<'de> serde::Deserialize<'de> for Script {
             {
                 self.visit_str(&v)
             }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(Script::from(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(Script::from(v))
+            }
         }
 
-        deserializer.deserialize_str(Visitor)
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(Visitor)
+        } else {
+            deserializer.deserialize_byte_buf(Visitor)
+        }
     }
 }
 
 #[cfg(feature = "serde")]
 impl serde::Serialize for Script {
-    /// User-facing serialization for `Script`.
+    /// User-facing serialization for `Script`, as hex in human-readable
+    /// formats and as raw bytes otherwise.
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        serializer.serialize_str(&format!("{:x}", self))
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&format!("{:x}", self))
+        } else {
+            serializer.serialize_bytes(self.as_bytes())
+        }
     }
 }
 
@@ -828,13 +1305,19 @@ mod test {
 
     use super::*;
     use super::build_scriptint;
+    use super::{read_scriptint_non_minimal, read_scriptint_size, write_scriptint};
 
     use hashes::hex::{FromHex, ToHex};
     use consensus::encode::{deserialize, serialize};
     use blockdata::opcodes;
+    use blockdata::constants::genesis_block;
+    use network::constants::Network;
     use util::key::PublicKey;
     use util::psbt::serialize::Serialize;
 
+    extern crate serde_json;
+    use self::serde_json::Value;
+
     #[test]
     fn script() {
         let mut comp = vec![];
@@ -892,7 +1375,13 @@ mod test {
         assert!(Script::new_p2pkh(&pubkey_hash).is_p2pkh());
 
         let wpubkey_hash = WPubkeyHash::hash(&pubkey.serialize());
-        assert!(Script::new_v0_wpkh(&wpubkey_hash).is_v0_p2wpkh());
+        let p2wpkh = Script::new_v0_p2wpkh(&wpubkey_hash);
+        assert!(p2wpkh.is_v0_p2wpkh());
+        let script_code = p2wpkh.p2wpkh_script_code().unwrap();
+        assert!(script_code.is_p2pkh());
+        assert_eq!(script_code.as_bytes()[3..23], wpubkey_hash[..]);
+        assert_eq!(script_code.p2wpkh_script_code(), None);
+        assert_eq!(Script::new_p2pkh(&pubkey_hash).p2wpkh_script_code(), None);
 
         let script = Builder::new().push_opcode(opcodes::all::OP_NUMEQUAL)
                                    .push_verify()
@@ -903,9 +1392,10 @@ mod test {
         assert_eq!(script.to_p2sh(), p2sh);
 
         let wscript_hash = WScriptHash::hash(&script.serialize());
-        let p2wsh = Script::new_v0_wsh(&wscript_hash);
+        let p2wsh = Script::new_v0_p2wsh(&wscript_hash);
         assert!(p2wsh.is_v0_p2wsh());
         assert_eq!(script.to_v0_p2wsh(), p2wsh);
+        assert_eq!(p2wsh.p2wpkh_script_code(), None);
 
         // Test data are taken from the second output of
         // 2ccb3a1f745eb4eefcf29391460250adda5fab78aaddb902d25d3cd97d9d8e61 transaction
@@ -995,6 +1485,21 @@ mod test {
         assert_eq!(serialize(&script.unwrap()), hex_script);
     }
 
+    #[test]
+    fn script_bytes() {
+        let hex = "76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac";
+        let script = Script::from_hex(hex).unwrap();
+        assert_eq!(script.to_hex(), hex);
+        assert_eq!(script.len(), 25);
+        assert_eq!(script.as_bytes(), script.as_ref());
+        assert_eq!(&script[3..23], &Vec::from_hex("ee61d57ab51b9d212335b1dba62794ac20d2bcf9").unwrap()[..]);
+        // the consensus encoding adds the length prefix
+        assert_eq!(serialize(&script)[1..], script.to_bytes()[..]);
+        assert_eq!(Script::from(script.clone().into_bytes()), script);
+        assert!(Script::from_hex("76a9zz").is_err());
+        assert!(Script::from_hex("").unwrap().is_empty());
+    }
+
     #[test]
     fn scriptint_round_trip() {
         assert_eq!(build_scriptint(-1), vec![0x81]);
@@ -1011,6 +1516,58 @@ mod test {
         assert!(read_scriptint(&build_scriptint(-(1 << 31))).is_err());
     }
 
+    #[test]
+    fn scriptint_boundaries() {
+        // minimal encodings, including the extra sign byte
+        let vectors: &[(i64, &str)] = &[
+            (0, ""),
+            (1, "01"),
+            (-1, "81"),
+            (127, "7f"),
+            (128, "8000"),
+            (-128, "8080"),
+            ((1 << 31) - 1, "ffffff7f"),
+            (-((1 << 31) - 1), "ffffffff"),
+            (-(1 << 31), "0000008080"),
+            ((1 << 39) - 1, "ffffffff7f"),
+            (i64::max_value(), "ffffffffffffff7f"),
+            (i64::min_value(), "000000000000008080"),
+        ];
+        for &(n, hex) in vectors {
+            let encoding = Vec::from_hex(hex).unwrap();
+            assert_eq!(build_scriptint(n), encoding, "{}", n);
+            let mut buf = [0u8; 9];
+            assert_eq!(write_scriptint(&mut buf, n), encoding.len());
+            if encoding.len() <= 4 {
+                assert_eq!(read_scriptint(&encoding), Ok(n));
+                assert_eq!(read_scriptint_non_minimal(&encoding), Ok(n));
+            } else {
+                assert_eq!(read_scriptint(&encoding), Err(Error::NumericOverflow));
+            }
+            if encoding.len() <= 5 {
+                assert_eq!(read_scriptint_size(&encoding, 5), Ok(n));
+            }
+        }
+        assert_eq!(read_scriptint_size(&Vec::from_hex("ffffffffffffff7f").unwrap(), 8), Ok(i64::max_value()));
+        assert_eq!(read_scriptint_size(&Vec::from_hex("ffffffffff7f").unwrap(), 5), Err(Error::NumericOverflow));
+
+        // padded encodings and negative zero
+        for &(hex, n) in &[("00", 0), ("80", 0), ("0100", 1), ("0180", -1), ("000080", 0), ("7f00", 127), ("ff0000", 255)] {
+            let encoding = Vec::from_hex(hex).unwrap();
+            assert_eq!(read_scriptint(&encoding), Err(Error::NonMinimalPush), "{}", hex);
+            assert_eq!(read_scriptint_size(&encoding, 5), Err(Error::NonMinimalPush), "{}", hex);
+            assert_eq!(read_scriptint_non_minimal(&encoding), Ok(n), "{}", hex);
+        }
+        assert_eq!(read_scriptint_non_minimal(&[0, 0, 0, 0, 0]), Err(Error::NumericOverflow));
+
+        // small numbers use their own opcodes
+        assert_eq!(Builder::new().push_int(-1).into_script(), hex_script!("4f"));
+        assert_eq!(Builder::new().push_int(0).into_script(), hex_script!("00"));
+        assert_eq!(Builder::new().push_int(16).into_script(), hex_script!("60"));
+        assert_eq!(Builder::new().push_int(17).into_script(), hex_script!("0111"));
+        assert_eq!(Builder::new().push_int(-2).into_script(), hex_script!("0182"));
+    }
+
     #[test]
     fn provably_unspendable_test() {
         // p2pk
@@ -1041,18 +1598,56 @@ mod test {
     }
 
     #[test]
-    fn script_asm() {
-        assert_eq!(hex_script!("6363636363686868686800").asm(),
+    fn script_debug_asm() {
+        fn debug_asm(script: &Script) -> String {
+            let mut buf = String::new();
+            script.fmt_asm(&mut buf).unwrap();
+            buf
+        }
+
+
+        assert_eq!(debug_asm(&hex_script!("6363636363686868686800")).as_str(),
                    "OP_IF OP_IF OP_IF OP_IF OP_IF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_0");
-        assert_eq!(hex_script!("6363636363686868686800").asm(),
+        assert_eq!(debug_asm(&hex_script!("6363636363686868686800")).as_str(),
                    "OP_IF OP_IF OP_IF OP_IF OP_IF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_0");
-        assert_eq!(hex_script!("2102715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699ac").asm(),
+        assert_eq!(debug_asm(&hex_script!("2102715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699ac")).as_str(),
                    "OP_PUSHBYTES_33 02715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699 OP_CHECKSIG");
         // Elements Alpha peg-out transaction with some signatures removed for brevity. Mainly to test PUSHDATA1
-        assert_eq!(hex_script!("0047304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401004cf1552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae").asm(),
+        assert_eq!(debug_asm(&hex_script!("0047304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401004cf1552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae")).as_str(),
                    "OP_0 OP_PUSHBYTES_71 304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401 OP_0 OP_PUSHDATA1 552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae");
     }
 
+    #[test]
+    fn script_asm() {
+        // the asm of Bitcoin Core's decodescript
+        let genesis = genesis_block(Network::Monacoin);
+        let script_sig = &genesis.txdata[0].input[0].script_sig;
+        let asm = format!("486604799 4 {}", b"Dec. 31th 2013 Japan, The winning numbers of the 2013 Year-End Jumbo Lottery:23-130916".to_hex());
+        assert_eq!(script_sig.asm(), asm);
+        // the push of 4 is shown as a number, so it is read back as OP_4
+        assert_eq!(Script::from_asm(&asm).unwrap().asm(), asm);
+
+        let multisig = hex_script!("522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452ae");
+        let asm = "2 02632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d6 03a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d4 2 OP_CHECKMULTISIG";
+        assert_eq!(multisig.asm(), asm);
+        assert_eq!(Script::from_asm(asm).unwrap(), multisig);
+
+        let p2pkh = hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac");
+        assert_eq!(p2pkh.asm(), "OP_DUP OP_HASH160 ee61d57ab51b9d212335b1dba62794ac20d2bcf9 OP_EQUALVERIFY OP_CHECKSIG");
+        // opcodes without prefix, and with the names of the Debug formatting
+        assert_eq!(Script::from_asm("DUP OP_HASH160 ee61d57ab51b9d212335b1dba62794ac20d2bcf9 EQUALVERIFY CHECKSIG").unwrap(), p2pkh);
+        assert_eq!(Script::from_asm("OP_0 OP_PUSHNUM_1 OP_16 -1").unwrap(), hex_script!("0051604f"));
+
+        assert_eq!(hex_script!("000180028080040100000004ffffff7f").asm(), "0 0 -128 1 2147483647");
+        assert_eq!(hex_script!("b1b2b0b9ba50ff").asm(), "OP_CHECKLOCKTIMEVERIFY OP_CHECKSEQUENCEVERIFY OP_NOP1 OP_NOP10 OP_UNKNOWN OP_RESERVED OP_INVALIDOPCODE");
+        assert_eq!(hex_script!("76a914ee61d5").asm(), "OP_DUP OP_HASH160 [error]");
+        assert_eq!(hex_script!("").asm(), "");
+
+        assert_eq!(Script::from_asm("OP_DUP OP_FOO"), Err(Error::InvalidAsm("OP_FOO".to_owned())));
+        assert_eq!(Script::from_asm("abc"), Err(Error::InvalidAsm("abc".to_owned())));
+        assert_eq!(Script::from_asm("OP_DUP [error]"), Err(Error::InvalidAsm("[error]".to_owned())));
+    }
+
     #[test]
     fn script_p2sh_p2p2k_template() {
         // random outputs I picked out of the mempool
@@ -1181,5 +1776,274 @@ mod test {
 		let spending = Vec::from_hex("010000000001011f97548fbbe7a0db7588a66e18d803d0089315aa7d4cc28360b6ec50ef36718a0100000000ffffffff02df1776000000000017a9146c002a686959067f4866b8fb493ad7970290ab728757d29f0000000000220020701a8d401c84fb13e6baf169d59684e17abd9fa216c8cc5b9fc63d622ff8c58d04004730440220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f8602207bdd20e307f062594022f12ed5017bbf4a055a06aea91c10110a0e3bb23117fc014730440220647d2dc5b15f60bc37dc42618a370b2a1490293f9e5c8464f53ec4fe1dfe067302203598773895b4b16d37485cbe21b337f4e4b650739880098c592553add7dd4355016952210375e00eb72e29da82b89367947f29ef34afb75e8654f6ea368e0acdfd92976b7c2103a1b26313f430c4b15bb1fdce663207659d8cac749a0e53d70eff01874496feff2103c96d495bfdd5ba4145e3e046fee45e84a8a48ad05bd8dbb395c011a32cf9f88053ae00000000").unwrap();
 		spent.verify(0, 18393430, spending.as_slice()).unwrap();
 	}
+
+    #[test]
+    fn push_only_every_opcode() {
+        for op in 0..256usize {
+            let op = op as u8;
+            // each opcode followed by what it pushes, if anything
+            let (script, pushed): (Vec<u8>, Option<Vec<u8>>) = match op {
+                0x4c => (vec![op, 1, 0xaa], Some(vec![0xaa])),
+                0x4d => (vec![op, 1, 0, 0xaa], Some(vec![0xaa])),
+                0x4e => (vec![op, 1, 0, 0, 0, 0xaa], Some(vec![0xaa])),
+                0x4f => (vec![op], Some(vec![0x81])),
+                0x50 => (vec![op], None),
+                _ if op < 0x4c => {
+                    let data = vec![0xaa; op as usize];
+                    ([vec![op], data.clone()].concat(), Some(data))
+                }
+                _ if op <= 0x60 => (vec![op], Some(vec![op - 0x50])),
+                _ => (vec![op], None),
+            };
+            let script = Script::from(script);
+            let push_only = op <= 0x60;
+            assert_eq!(script.is_push_only(), push_only, "opcode 0x{:02x}", op);
+
+            let pushed: Vec<&[u8]> = pushed.iter().map(|data| &data[..]).collect();
+            assert_eq!(script.pushed_data(), pushed, "opcode 0x{:02x}", op);
+
+            // preceded and followed by another push
+            let script = Builder::new().push_int(3).into_script().into_bytes().into_iter()
+                .chain(script.into_bytes())
+                .chain(vec![0x01, 0xbb])
+                .collect::<Vec<u8>>();
+            let script = Script::from(script);
+            assert_eq!(script.is_push_only(), push_only, "opcode 0x{:02x}", op);
+            let mut expected = vec![&[3u8][..]];
+            expected.extend(pushed);
+            if push_only {
+                expected.push(&[0xbb]);
+            }
+            assert_eq!(script.pushed_data(), expected, "opcode 0x{:02x}", op);
+        }
+
+        assert!(Script::new().is_push_only());
+        assert!(Script::new().pushed_data().is_empty());
+        // truncated pushes
+        for script in &[hex_script!("01"), hex_script!("4c"), hex_script!("4d0100"), hex_script!("510201")] {
+            assert!(!script.is_push_only(), "script {}", script);
+        }
+        assert_eq!(hex_script!("510201").pushed_data(), vec![&[1u8][..]]);
+    }
+
+    #[test]
+    fn dust_value() {
+        use util::amount::Amount;
+        use util::risk::DUST_THRESHOLD;
+
+        // the values of Bitcoin Core's GetDustThreshold at the default dust relay fee
+        assert_eq!(hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac").dust_value(), Amount::from_sat(DUST_THRESHOLD));
+        assert_eq!(hex_script!("a914acc91e6fef5c7f24e5c8b3f11a664aa8f1352ffd87").dust_value(), Amount::from_sat(540));
+        assert_eq!(hex_script!("0014d9bfc5fa9d14e4d6a7fb45d4fc4c3c9d4a45bd1d").dust_value(), Amount::from_sat(294));
+        assert_eq!(hex_script!("00201f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f").dust_value(), Amount::from_sat(330));
+        assert_eq!(hex_script!("6a0b68656c6c6f20776f726c64").dust_value(), Amount::ZERO);
+    }
+
+    #[test]
+    fn classify_builtin() {
+        assert_eq!(hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac").classify(), Classification::P2pkh);
+        assert_eq!(hex_script!("a914acc91e6fef5c7f24e5c8b3f11a664aa8f1352ffd87").classify(), Classification::P2sh);
+        assert_eq!(hex_script!("0014d9bfc5fa9d14e4d6a7fb45d4fc4c3c9d4a45bd1d").classify(), Classification::V0P2wpkh);
+        assert_eq!(hex_script!("00201f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f").classify(), Classification::V0P2wsh);
+        assert_eq!(hex_script!("51207979797979797979797979797979797979797979797979797979797979797979").classify(), Classification::WitnessProgram);
+        assert_eq!(hex_script!("2102cacacacacacacacacacacacacacacacacacacacacacacacacacacacacacacacaac").classify(), Classification::P2pk);
+        assert_eq!(hex_script!("6a0b68656c6c6f20776f726c64").classify(), Classification::OpReturn);
+        assert_eq!(hex_script!("").classify(), Classification::NonStandard);
+        assert_eq!(hex_script!("51").classify(), Classification::NonStandard);
+    }
+
+    #[test]
+    fn classify_multisig() {
+        let two_of_two = hex_script!("522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452ae");
+        assert!(two_of_two.is_multisig());
+        assert_eq!(two_of_two.classify(), Classification::Multisig);
+
+        // m > n, wrong key count, odd key size, trailing opcode, truncated
+        for script in &[
+            hex_script!("532102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452ae"),
+            hex_script!("522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d653ae"),
+            hex_script!("512002632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd6651ae"),
+            hex_script!("512102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d651ae75"),
+            hex_script!("512102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd"),
+            hex_script!("0051ae"),
+        ] {
+            assert!(!script.is_multisig(), "script {}", script);
+        }
+    }
+
+    #[test]
+    fn template_extraction() {
+        let key = PublicKey::from_str("03a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d4").unwrap();
+        let p2pk = Script::new_p2pk(&key);
+        let p2pkh = Script::new_p2pkh(&key.pubkey_hash());
+        let p2sh = p2pkh.to_p2sh();
+        let p2wpkh = Script::new_v0_p2wpkh(&key.wpubkey_hash().unwrap());
+        let p2wsh = p2pkh.to_v0_p2wsh();
+
+        assert_eq!(p2pk.p2pk_public_key(), Some(key));
+        assert_eq!(p2pkh.p2pkh_pubkey_hash(), Some(key.pubkey_hash()));
+        assert_eq!(p2sh.p2sh_script_hash(), Some(ScriptHash::hash(&p2pkh[..])));
+        assert_eq!(p2wpkh.v0_wpkh(), key.wpubkey_hash());
+        assert_eq!(p2wsh.v0_wsh(), Some(WScriptHash::hash(&p2pkh[..])));
+
+        for script in &[&p2pk, &p2pkh, &p2sh, &p2wpkh, &p2wsh] {
+            assert_eq!(script.p2pk_public_key().is_some(), script.is_p2pk());
+            assert_eq!(script.p2pkh_pubkey_hash().is_some(), script.is_p2pkh());
+            assert_eq!(script.p2sh_script_hash().is_some(), script.is_p2sh());
+            assert_eq!(script.v0_wpkh().is_some(), script.is_v0_p2wpkh());
+            assert_eq!(script.v0_wsh().is_some(), script.is_v0_p2wsh());
+        }
+
+        // a p2pk output with a key that is not on the curve
+        let bad_key = hex_script!("2102cacacacacacacacacacacacacacacacacacacacacacacacacacacacacacacacaac");
+        assert!(bad_key.is_p2pk());
+        assert_eq!(bad_key.p2pk_public_key(), None);
+
+        // non-minimal pushes of the right length don't match
+        assert_eq!(hex_script!("a94c14acc91e6fef5c7f24e5c8b3f11a664aa8f1352ffd87").p2sh_script_hash(), None);
+        assert_eq!(hex_script!("004c14d9bfc5fa9d14e4d6a7fb45d4fc4c3c9d4a45bd1d").v0_wpkh(), None);
+        // truncated
+        assert_eq!(hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bc").p2pkh_pubkey_hash(), None);
+        assert_eq!(hex_script!("0020").v0_wsh(), None);
+    }
+
+    #[test]
+    fn classify_block_scripts() {
+        use std::collections::HashMap;
+        use blockdata::block::Block;
+        use self::Classification::*;
+
+        // (height, classification counts) over the created outputs and the spent
+        // scripts of each block in the BIP158 test vectors
+        let expected: &[(u64, &[(Classification, usize)])] = &[
+            (0, &[(P2pk, 1)]),
+            (2, &[(P2pk, 1)]),
+            (3, &[(P2pk, 1)]),
+            (15007, &[(P2pk, 1)]),
+            (49291, &[(P2pk, 1), (P2pkh, 3), (Multisig, 6), (NonStandard, 1)]),
+            (180480, &[(P2pk, 2), (P2pkh, 11), (NonStandard, 3)]),
+            (926485, &[(P2pkh, 13), (P2sh, 3), (OpReturn, 1), (NonStandard, 1)]),
+            (987876, &[(NonStandard, 1)]),
+            (1263442, &[(P2pkh, 1), (V0P2wpkh, 1), (V0P2wsh, 1), (OpReturn, 1)]),
+            (1414221, &[(NonStandard, 1)]),
+        ];
+
+        let data = include_str!("../../test_data/blockfilters.json");
+        let testdata = serde_json::from_str::<Value>(data).unwrap().as_array().unwrap().clone();
+        assert_eq!(testdata.len() - 1, expected.len());
+        for (t, &(height, counts)) in testdata.iter().skip(1).zip(expected.iter()) {
+            assert_eq!(t.get(0).unwrap().as_u64().unwrap(), height);
+            let block: Block = deserialize(&Vec::from_hex(t.get(2).unwrap().as_str().unwrap()).unwrap()).unwrap();
+            let spent = t.get(3).unwrap().as_array().unwrap().iter()
+                .map(|s| Script::from(Vec::from_hex(s.as_str().unwrap()).unwrap()));
+            let scripts: Vec<Script> = block.txdata.iter()
+                .flat_map(|tx| tx.output.iter().map(|out| out.script_pubkey.clone()))
+                .chain(spent)
+                .collect();
+
+            let mut found = HashMap::new();
+            for script in &scripts {
+                *found.entry(script.classify()).or_insert(0) += 1;
+                assert_eq!(script.p2pkh_pubkey_hash().is_some(), script.classify() == P2pkh);
+                assert_eq!(script.p2sh_script_hash().is_some(), script.classify() == P2sh);
+                assert_eq!(script.v0_wpkh().is_some(), script.classify() == V0P2wpkh);
+                assert_eq!(script.v0_wsh().is_some(), script.classify() == V0P2wsh);
+                assert_eq!(script.is_multisig(), script.classify() == Multisig);
+                if script.p2pk_public_key().is_some() {
+                    assert_eq!(script.classify(), P2pk);
+                }
+            }
+            assert_eq!(found, counts.iter().cloned().collect::<HashMap<_, _>>(), "block {}", height);
+        }
+    }
+
+    #[test]
+    fn classify_with_custom_patterns() {
+        // An OP_RETURN carrying a "MONA" marker
+        fn marker(script: &Script) -> Option<Classification> {
+            if script.is_op_return() && script.len() > 6 && &script[2..6] == b"MONA" {
+                Some(Classification::Custom("mona-marker"))
+            } else {
+                None
+            }
+        }
+        // Claims every OP_RETURN, to check ordering between patterns
+        let any_op_return = |script: &Script| if script.is_op_return() {
+            Some(Classification::Custom("any-op-return"))
+        } else {
+            None
+        };
+
+        let marked = hex_script!("6a084d4f4e4100010203");
+        let unmarked = hex_script!("6a0b68656c6c6f20776f726c64");
+        let p2pkh = hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac");
+
+        // no patterns: built-in classification
+        assert_eq!(marked.classify_with(&[]), Classification::OpReturn);
+
+        // custom patterns take precedence over built-ins, and fall through when not matching
+        assert_eq!(marked.classify_with(&[&marker]), Classification::Custom("mona-marker"));
+        assert_eq!(unmarked.classify_with(&[&marker]), Classification::OpReturn);
+        assert_eq!(p2pkh.classify_with(&[&marker, &any_op_return]), Classification::P2pkh);
+
+        // among custom patterns, the first match wins
+        assert_eq!(marked.classify_with(&[&marker, &any_op_return]), Classification::Custom("mona-marker"));
+        assert_eq!(marked.classify_with(&[&any_op_return, &marker]), Classification::Custom("any-op-return"));
+        assert_eq!(unmarked.classify_with(&[&marker, &any_op_return]), Classification::Custom("any-op-return"));
+    }
 }
 
+#[cfg(all(test, feature = "unstable"))]
+mod benches {
+    use test::Bencher;
+
+    use super::{Classification, Script};
+
+    fn scripts() -> Vec<Script> {
+        vec![
+            hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac"),
+            hex_script!("a914acc91e6fef5c7f24e5c8b3f11a664aa8f1352ffd87"),
+            hex_script!("0014d9bfc5fa9d14e4d6a7fb45d4fc4c3c9d4a45bd1d"),
+            hex_script!("6a0b68656c6c6f20776f726c64"),
+            hex_script!("51"),
+        ]
+    }
+
+    #[bench]
+    pub fn bench_classify(bh: &mut Bencher) {
+        let scripts = scripts();
+        bh.iter(|| {
+            for script in &scripts {
+                let class = script.classify();
+                ::test::black_box(&class);
+            }
+        });
+    }
+
+    #[bench]
+    pub fn bench_classify_with_no_patterns(bh: &mut Bencher) {
+        let scripts = scripts();
+        bh.iter(|| {
+            for script in &scripts {
+                let class = script.classify_with(&[]);
+                ::test::black_box(&class);
+            }
+        });
+    }
+
+    #[bench]
+    pub fn bench_classify_with_one_pattern(bh: &mut Bencher) {
+        let scripts = scripts();
+        let pattern = |script: &Script| if script.len() == 10 {
+            Some(Classification::Custom("ten"))
+        } else {
+            None
+        };
+        bh.iter(|| {
+            for script in &scripts {
+                let class = script.classify_with(&[&pattern]);
+                ::test::black_box(&class);
+            }
+        });
+    }
+}
@@ -23,22 +23,33 @@
 //! This module provides the structures and functions needed to support transactions.
 //!
 
+
 use std::default::Default;
-use std::{fmt, io};
+use std::{cmp, fmt};
+use std::io;
 
 use hashes::{self, Hash, sha256d};
 use hashes::hex::FromHex;
 
-use util::endian;
-use blockdata::constants::WITNESS_SCALE_FACTOR;
-#[cfg(feature="bitcoinconsensus")] use blockdata::script;
-use blockdata::script::Script;
+use util::amount::Amount;
+use util::fee_rate::{FeeRate, Weight};
+use util::sighash::{self, SighashCache};
+use util::weight_prediction::{predict_weight, InputWeightPrediction};
+use blockdata::constants::{COINBASE_MATURITY, WITNESS_SCALE_FACTOR};
+use blockdata::locktime::{relative, LockTime};
+#[cfg(feature="bitcoinconsensus")] use bitcoinconsensus;
+use blockdata::script::{self, Script};
 use consensus::{encode, Decodable, Encodable};
+use consensus::params::Params;
 use hash_types::*;
 use VarInt;
 
 /// A reference to a transaction output
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+///
+/// Outpoints are ordered by txid in internal byte order and then by output
+/// index. Note that BIP69 instead compares txids in their displayed,
+/// byte-reversed form; see [util::bip69](../../util/bip69/index.html).
+#[derive(Copy, Clone, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub struct OutPoint {
     /// The referenced transaction's txid
     pub txid: Txid,
@@ -101,6 +112,24 @@ impl fmt::Display for OutPoint {
     }
 }
 
+impl fmt::Debug for OutPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OutPoint({})", self)
+    }
+}
+
+/// Formats a byte slice as plain hex in `Debug` output.
+struct DebugHex<'a>(&'a [u8]);
+
+impl<'a> fmt::Debug for DebugHex<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for ch in self.0 {
+            write!(f, "{:02x}", ch)?;
+        }
+        Ok(())
+    }
+}
+
 /// An error in parsing an OutPoint.
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum ParseOutPointError {
@@ -134,7 +163,7 @@ impl ::std::error::Error for ParseOutPointError {
         "description() is deprecated; use Display"
     }
 
-    fn cause(&self) -> Option<&::std::error::Error> {
+    fn source(&self) -> Option<&(::std::error::Error + 'static)> {
         match *self {
             ParseOutPointError::Txid(ref e) => Some(e),
             ParseOutPointError::Vout(ref e) => Some(e),
@@ -177,19 +206,180 @@ impl ::std::str::FromStr for OutPoint {
     }
 }
 
+/// The first byte of the annex, the optional last witness element of a
+/// taproot spend defined by BIP341
+pub const TAPROOT_ANNEX_PREFIX: u8 = 0x50;
+
+/// The sequence number of a transaction input.
+///
+/// Besides opting the transaction into the absolute lock time, it signals
+/// replaceability (BIP125) and encodes the relative lock time of the input
+/// (BIP68) when the transaction version is 2 or more.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sequence(pub u32);
+
+impl Sequence {
+    /// The final sequence number, which disables the absolute lock time of
+    /// the transaction if all inputs have it.
+    pub const MAX: Sequence = Sequence(0xFFFFFFFF);
+    /// The zero sequence number, which enables all of the features.
+    pub const ZERO: Sequence = Sequence(0);
+    /// The highest sequence number which signals replaceability, without a
+    /// relative lock time.
+    pub const ENABLE_RBF_NO_LOCKTIME: Sequence = Sequence(0xFFFFFFFD);
+    /// The highest sequence number which enables the absolute lock time, without
+    /// signalling replaceability or a relative lock time.
+    pub const ENABLE_LOCKTIME_NO_RBF: Sequence = Sequence(0xFFFFFFFE);
+
+    /// The bit which disables the relative lock time if set
+    const LOCK_TIME_DISABLE_FLAG: u32 = 1 << 31;
+    /// The bit which makes the relative lock time a time rather than a height
+    const LOCK_TYPE_TIME_FLAG: u32 = 1 << 22;
+    /// The bits holding the value of the relative lock time
+    const LOCK_TIME_MASK: u32 = 0x0000FFFF;
+
+    /// Whether the sequence number is final, which is [Sequence::MAX].
+    pub fn is_final(self) -> bool {
+        self == Sequence::MAX
+    }
+
+    /// Whether the sequence number enables the absolute lock time of the
+    /// transaction, which any non-final sequence number does.
+    pub fn enables_absolute_lock_time(self) -> bool {
+        !self.is_final()
+    }
+
+    /// Whether the sequence number signals that the transaction may be
+    /// replaced (BIP125).
+    pub fn is_rbf(self) -> bool {
+        self.0 < Sequence::ENABLE_LOCKTIME_NO_RBF.0
+    }
+
+    /// Whether the sequence number encodes a relative lock time, which only
+    /// applies when the transaction version is 2 or more.
+    pub fn is_relative_lock_time(self) -> bool {
+        self.0 & Sequence::LOCK_TIME_DISABLE_FLAG == 0
+    }
+
+    /// Whether the sequence number encodes a relative lock time in blocks.
+    pub fn is_height_locked(self) -> bool {
+        self.is_relative_lock_time() && self.0 & Sequence::LOCK_TYPE_TIME_FLAG == 0
+    }
+
+    /// Whether the sequence number encodes a relative lock time in
+    /// 512-second intervals.
+    pub fn is_time_locked(self) -> bool {
+        self.is_relative_lock_time() && self.0 & Sequence::LOCK_TYPE_TIME_FLAG != 0
+    }
+
+    /// The sequence number of a relative lock time of `height` blocks.
+    pub fn from_height(height: u16) -> Sequence {
+        Sequence(height as u32)
+    }
+
+    /// The sequence number of a relative lock time of `intervals` times
+    /// 512 seconds.
+    pub fn from_512_second_intervals(intervals: u16) -> Sequence {
+        Sequence(intervals as u32 | Sequence::LOCK_TYPE_TIME_FLAG)
+    }
+
+    /// Create a sequence number from its consensus value.
+    pub fn from_consensus(n: u32) -> Sequence {
+        Sequence(n)
+    }
+
+    /// The consensus value of the sequence number.
+    pub fn to_consensus_u32(self) -> u32 {
+        self.0
+    }
+
+    /// The relative lock time encoded in the sequence number, if any. The bits
+    /// which BIP68 leaves undefined are ignored.
+    pub fn to_relative_lock_time(self) -> Option<relative::LockTime> {
+        if !self.is_relative_lock_time() {
+            return None;
+        }
+        let value = (self.0 & Sequence::LOCK_TIME_MASK) as u16;
+        if self.is_time_locked() {
+            Some(relative::LockTime::from_512_second_intervals(value))
+        } else {
+            Some(relative::LockTime::from_height(value))
+        }
+    }
+}
+
+impl Default for Sequence {
+    fn default() -> Sequence {
+        Sequence::MAX
+    }
+}
+
+impl From<u32> for Sequence {
+    fn from(n: u32) -> Sequence {
+        Sequence(n)
+    }
+}
+
+impl From<Sequence> for u32 {
+    fn from(sequence: Sequence) -> u32 {
+        sequence.0
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::LowerHex for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+impl Encodable for Sequence {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl Decodable for Sequence {
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(Sequence(Decodable::consensus_decode(d)?))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Sequence {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u32(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Sequence {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<Sequence, D::Error> {
+        Ok(Sequence(::serde::Deserialize::deserialize(d)?))
+    }
+}
+
 /// A transaction input, which defines old coins to be consumed
-#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+///
+/// The `Ord` implementation compares fields in the order they are encoded.
+/// It exists so inputs can be kept in ordered containers and carries no
+/// semantic meaning.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TxIn {
     /// The reference to the previous output that is being used an an input
     pub previous_output: OutPoint,
     /// The script which pushes values on the stack which will cause
     /// the referenced output's script to accept
     pub script_sig: Script,
-    /// The sequence number, which suggests to miners which of two
-    /// conflicting transactions should be preferred, or 0xFFFFFFFF
-    /// to ignore this feature. This is generally never used since
-    /// the miner behaviour cannot be enforced.
-    pub sequence: u32,
+    /// The sequence number, which enables the lock time of the transaction,
+    /// signals replaceability and encodes the relative lock time of the
+    /// input, see [Sequence].
+    pub sequence: Sequence,
     /// Witness data: an array of byte-arrays.
     /// Note that this field is *not* (de)serialized with the rest of the TxIn in
     /// Encodable/Decodable, as it is (de)serialized at the end of the full
@@ -197,21 +387,181 @@ pub struct TxIn {
     /// (de)serialization routines.
     pub witness: Vec<Vec<u8>>
 }
-serde_struct_impl!(TxIn, previous_output, script_sig, sequence, witness);
+
+/// The witness of a [TxIn] for serde, with hex elements in human-readable
+/// formats
+#[cfg(feature = "serde")]
+struct SerdeWitness<W>(W);
+
+#[cfg(feature = "serde")]
+impl<'a> ::serde::Serialize for SerdeWitness<&'a [Vec<u8>]> {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use hashes::hex::ToHex;
+
+        if s.is_human_readable() {
+            s.collect_seq(self.0.iter().map(|elem| elem.to_hex()))
+        } else {
+            s.collect_seq(self.0.iter())
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for SerdeWitness<Vec<Vec<u8>>> {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        if d.is_human_readable() {
+            let hex: Vec<String> = ::serde::Deserialize::deserialize(d)?;
+            let witness = hex.iter()
+                .map(|elem| Vec::from_hex(elem).map_err(D::Error::custom))
+                .collect::<Result<_, _>>()?;
+            Ok(SerdeWitness(witness))
+        } else {
+            Ok(SerdeWitness(::serde::Deserialize::deserialize(d)?))
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for TxIn {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut st = s.serialize_struct("TxIn", 4)?;
+        st.serialize_field("previous_output", &self.previous_output)?;
+        st.serialize_field("script_sig", &self.script_sig)?;
+        st.serialize_field("sequence", &self.sequence)?;
+        st.serialize_field("witness", &SerdeWitness(&self.witness[..]))?;
+        st.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for TxIn {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<TxIn, D::Error> {
+        use serde::de::{self, Error, IgnoredAny, MapAccess, SeqAccess};
+
+        const FIELDS: &'static [&'static str] = &["previous_output", "script_sig", "sequence", "witness"];
+
+        struct Visitor;
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = TxIn;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a transaction input")
+            }
+
+            fn visit_seq<V: SeqAccess<'de>>(self, mut seq: V) -> Result<TxIn, V::Error> {
+                let missing = |i| V::Error::invalid_length(i, &"a transaction input");
+                Ok(TxIn {
+                    previous_output: seq.next_element()?.ok_or_else(|| missing(0))?,
+                    script_sig: seq.next_element()?.ok_or_else(|| missing(1))?,
+                    sequence: seq.next_element()?.ok_or_else(|| missing(2))?,
+                    witness: seq.next_element::<SerdeWitness<Vec<Vec<u8>>>>()?.ok_or_else(|| missing(3))?.0,
+                })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TxIn, A::Error> {
+                let mut previous_output = None;
+                let mut script_sig = None;
+                let mut sequence = None;
+                let mut witness = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match &key[..] {
+                        "previous_output" => previous_output = Some(map.next_value()?),
+                        "script_sig" => script_sig = Some(map.next_value()?),
+                        "sequence" => sequence = Some(map.next_value()?),
+                        "witness" => witness = Some(map.next_value::<SerdeWitness<Vec<Vec<u8>>>>()?.0),
+                        _ => { map.next_value::<IgnoredAny>()?; }
+                    }
+                }
+                Ok(TxIn {
+                    previous_output: previous_output.ok_or_else(|| A::Error::missing_field("previous_output"))?,
+                    script_sig: script_sig.ok_or_else(|| A::Error::missing_field("script_sig"))?,
+                    sequence: sequence.ok_or_else(|| A::Error::missing_field("sequence"))?,
+                    witness: witness.ok_or_else(|| A::Error::missing_field("witness"))?,
+                })
+            }
+        }
+
+        d.deserialize_struct("TxIn", FIELDS, Visitor)
+    }
+}
+
+impl fmt::Debug for TxIn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Witness<'a>(&'a [Vec<u8>]);
+        impl<'a> fmt::Debug for Witness<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_list().entries(self.0.iter().map(|elem| DebugHex(elem))).finish()
+            }
+        }
+
+        f.debug_struct("TxIn")
+            .field("previous_output", &self.previous_output)
+            .field("script_sig", &DebugHex(self.script_sig.as_bytes()))
+            .field("sequence", &self.sequence)
+            .field("witness", &Witness(&self.witness))
+            .finish()
+    }
+}
+
+impl TxIn {
+    /// Returns the annex of this input's witness, given the script pubkey of
+    /// the output it spends.
+    ///
+    /// Per BIP341, when a taproot output is spent with at least two witness
+    /// elements and the last one starts with [TAPROOT_ANNEX_PREFIX], that
+    /// element (including the prefix) is the annex. Witnesses spending other
+    /// kinds of outputs may have the same shape without having an annex.
+    ///
+    /// The annex is reserved for future extensions and is not relayed by
+    /// standard nodes, so an input with an annex should be considered
+    /// nonstandard. It is kept unchanged when the transaction is encoded.
+    pub fn annex(&self, spent_script_pubkey: &Script) -> Option<&[u8]> {
+        if !spent_script_pubkey.is_v1_p2tr() || self.witness.len() < 2 {
+            return None;
+        }
+        self.witness.last()
+            .filter(|elem| elem.first() == Some(&TAPROOT_ANNEX_PREFIX))
+            .map(|elem| &elem[..])
+    }
+
+    /// Builds the scriptSig of a coinbase for the block at `height`: a minimal
+    /// push of the height, as required by BIP34, followed by `extra` unchanged.
+    /// `extra` is typically an extra nonce and whatever the miner wants to put
+    /// in the coinbase; it isn't executed, so it doesn't have to be a valid
+    /// script.
+    ///
+    /// Fails if the scriptSig would not be between 2 and 100 bytes long, as
+    /// [Transaction::validate_coinbase] requires.
+    pub fn coinbase_script_sig(height: u32, extra: &[u8]) -> Result<Script, CoinbaseError> {
+        let mut script_sig = script::Builder::new().push_int(height as i64).into_script().into_bytes();
+        script_sig.extend_from_slice(extra);
+        if script_sig.len() < 2 || script_sig.len() > 100 {
+            return Err(CoinbaseError::BadLength(script_sig.len()));
+        }
+        Ok(Script::from(script_sig))
+    }
+}
 
 impl Default for TxIn {
     fn default() -> TxIn {
         TxIn {
             previous_output: OutPoint::default(),
             script_sig: Script::new(),
-            sequence: u32::max_value(),
+            sequence: Sequence::MAX,
             witness: Vec::new(),
         }
     }
 }
 
 /// A transaction output, which defines new coins to be created from old ones.
-#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+///
+/// Outputs are ordered by value and then by the bytes of their script
+/// pubkey, which is also the BIP69 output order.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TxOut {
     /// The value of the output, in satoshis
     pub value: u64,
@@ -220,6 +570,25 @@ pub struct TxOut {
 }
 serde_struct_impl!(TxOut, value, script_pubkey);
 
+impl fmt::Debug for TxOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TxOut")
+            .field("value", &self.value)
+            .field("script_pubkey", &DebugHex(self.script_pubkey.as_bytes()))
+            .finish()
+    }
+}
+
+impl TxOut {
+    /// Creates an output paying `amount` to `script_pubkey`.
+    pub fn new(amount: Amount, script_pubkey: Script) -> TxOut {
+        TxOut {
+            value: amount.as_sat(),
+            script_pubkey: script_pubkey,
+        }
+    }
+}
+
 // This is used as a "null txout" in consensus signing code
 impl Default for TxOut {
     fn default() -> TxOut {
@@ -227,6 +596,33 @@ impl Default for TxOut {
     }
 }
 
+/// An unspent output together with where it was created, the record a wallet
+/// or indexer keeps for each coin it tracks.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Coin {
+    /// The output being tracked
+    pub outpoint: OutPoint,
+    /// The value and script pubkey of the output
+    pub txout: TxOut,
+    /// The height of the block which contains the transaction of the output
+    pub height: u32,
+    /// Whether the output was created by a coinbase transaction
+    pub is_coinbase: bool,
+}
+serde_struct_impl!(Coin, outpoint, txout, height, is_coinbase);
+
+impl Coin {
+    /// Whether this coin can be spent in the block after `tip_height`.
+    ///
+    /// Coinbase outputs need [COINBASE_MATURITY] confirmations first, counting
+    /// the block which contains them, like Core compares the height of the
+    /// spending block against the height of the coin. Other outputs are
+    /// always mature.
+    pub fn is_mature(&self, tip_height: u32) -> bool {
+        !self.is_coinbase || (tip_height as u64 + 1).saturating_sub(self.height as u64) >= COINBASE_MATURITY as u64
+    }
+}
+
 /// A Bitcoin transaction, which describes an authenticated movement of coins.
 ///
 /// If any inputs have nonempty witnesses, the entire transaction is serialized
@@ -234,13 +630,11 @@ impl Default for TxOut {
 /// inputs have empty witnesses, the transaction is serialized in the pre-BIP141
 /// format.
 ///
-/// There is one major exception to this: to avoid deserialization ambiguity,
-/// if the transaction has no inputs, it is serialized in the BIP141 style. Be
-/// aware that this differs from the transaction format in PSBT, which _never_
-/// uses BIP141. (Ordinarily there is no conflict, since in PSBT transactions
-/// are always unsigned and therefore their inputs have empty witnesses.)
+/// This includes transactions without inputs, which can't be deserialized
+/// again. PSBT serializes its unsigned transaction manually, so that it can
+/// have no inputs.
 ///
-/// The specific ambiguity is that Segwit uses the flag bytes `0001` where an old
+/// The ambiguity is that Segwit uses the flag bytes `0001` where an old
 /// serializer would read the number of transaction inputs. The old serializer
 /// would interpret this as "no inputs, one output", which means the transaction
 /// is invalid, and simply reject it. Segwit further specifies that this encoding
@@ -255,15 +649,25 @@ impl Default for TxOut {
 /// Segwit flag in it, which confuses most Segwit parsers including the one in
 /// Bitcoin Core.
 ///
-/// We therefore deviate from the spec by always using the Segwit witness encoding
-/// for 0-input transactions, which results in unambiguously parseable transactions.
-#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+/// Like Core, we follow the spec and encode 0-input transactions in the
+/// traditional format (see [Transaction::needs_witness_serialization]), and
+/// reject the `0001` marker and flag when no inputs follow it. A transaction
+/// with the flag but only empty witnesses is accepted and encoded in the
+/// traditional format again, which doesn't change its txid.
+///
+/// The `Debug` output leads with the txid and prints hashes in the usual
+/// reversed byte order, so it can be compared directly with block explorers.
+///
+/// The `Ord` implementation compares fields in the order they are encoded,
+/// using the consensus value of the lock time. It exists so transactions can
+/// be kept in ordered containers and carries no semantic meaning.
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Transaction {
     /// The protocol version, is currently expected to be 1 or 2 (BIP 68).
     pub version: i32,
-    /// Block number before which this transaction is valid, or 0 for
-    /// valid immediately.
-    pub lock_time: u32,
+    /// Block height or time before which this transaction can't be included
+    /// in a block, or 0 for valid immediately.
+    pub lock_time: LockTime,
     /// List of inputs
     pub input: Vec<TxIn>,
     /// List of outputs
@@ -271,7 +675,73 @@ pub struct Transaction {
 }
 serde_struct_impl!(Transaction, version, lock_time, input, output);
 
+impl PartialOrd for Transaction {
+    fn partial_cmp(&self, other: &Transaction) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Transaction {
+    fn cmp(&self, other: &Transaction) -> cmp::Ordering {
+        self.version.cmp(&other.version)
+            .then_with(|| self.input.cmp(&other.input))
+            .then_with(|| self.output.cmp(&other.output))
+            .then_with(|| self.lock_time.to_consensus_u32().cmp(&other.lock_time.to_consensus_u32()))
+    }
+}
+
+impl fmt::Debug for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Transaction")
+            .field("txid", &self.txid())
+            .field("version", &self.version)
+            .field("lock_time", &self.lock_time)
+            .field("input", &self.input)
+            .field("output", &self.output)
+            .finish()
+    }
+}
+
+/// A coinbase transaction which breaks the coinbase-specific consensus rules
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum CoinbaseError {
+    /// The transaction doesn't have a single input spending the null outpoint
+    NotCoinbase,
+    /// The scriptSig is shorter than 2 or longer than 100 bytes
+    BadLength(usize),
+    /// The scriptSig doesn't begin with the block height, as required by BIP34
+    BadHeight(u32),
+}
+
+impl fmt::Display for CoinbaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CoinbaseError::NotCoinbase => f.write_str("transaction is not a coinbase"),
+            CoinbaseError::BadLength(len) => write!(f, "coinbase scriptSig of {} bytes is not between 2 and 100 bytes", len),
+            CoinbaseError::BadHeight(height) => write!(f, "coinbase scriptSig doesn't begin with the block height {}", height),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl ::std::error::Error for CoinbaseError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
 impl Transaction {
+    /// Decodes a transaction from its consensus encoding in hex, as returned
+    /// by the `getrawtransaction` RPC.
+    pub fn from_hex(hex: &str) -> Result<Transaction, encode::FromHexError> {
+        encode::decode_hex(hex)
+    }
+
+    /// Returns the consensus encoding of the transaction in lower case hex.
+    pub fn to_hex(&self) -> String {
+        encode::serialize_hex(self)
+    }
+
     /// Computes a "normalized TXID" which does not include any signatures.
     /// This gives a way to identify a transaction that is ``the same'' as
     /// another in the sense of having same inputs and outputs.
@@ -312,72 +782,32 @@ impl Transaction {
     /// ECDSA signer, the SigHashType appended to the resulting sig, and a
     /// script written around this, but this is the general (and hard) part.
     ///
-    /// *Warning* This does NOT attempt to support OP_CODESEPARATOR. In general
-    /// this would require evaluating `script_pubkey` to determine which separators
-    /// get evaluated and which don't, which we don't have the information to
-    /// determine.
+    /// All OP_CODESEPARATORs are removed from `script_pubkey` before hashing, as the
+    /// reference implementation does. Which separators get *executed*, and hence where the
+    /// script code starts, depends on evaluating the script and is left to the caller.
+    ///
+    /// If `sighash_u32` is SIGHASH_SINGLE and there is no output at `input_index`, this
+    /// returns [SigHash::single_bug] rather than an error. That is what the consensus rules
+    /// require, but a signature over it commits to nothing, so callers should check for it
+    /// before signing.
+    ///
+    /// To compute signature hashes for many inputs of the same transaction, use
+    /// [SighashCache](::util::sighash::SighashCache) instead.
+    pub fn signature_hash_checked(&self, input_index: usize, script_pubkey: &Script, sighash_u32: u32) -> Result<SigHash, sighash::Error> {
+        SighashCache::new(self).legacy_signature_hash(input_index, script_pubkey, sighash_u32)
+    }
+
+    /// Computes a signature hash for a given input index with a given sighash flag.
+    ///
+    /// This is [Transaction::signature_hash_checked], panicking instead of returning an
+    /// error.
     ///
     /// # Panics
     /// Panics if `input_index` is greater than or equal to `self.input.len()`
     ///
     pub fn signature_hash(&self, input_index: usize, script_pubkey: &Script, sighash_u32: u32) -> SigHash {
-        assert!(input_index < self.input.len());  // Panic on OOB
-
-        let (sighash, anyone_can_pay) = SigHashType::from_u32(sighash_u32).split_anyonecanpay_flag();
-
-        // Special-case sighash_single bug because this is easy enough.
-        if sighash == SigHashType::Single && input_index >= self.output.len() {
-            return SigHash::from_slice(&[1, 0, 0, 0, 0, 0, 0, 0,
-                                               0, 0, 0, 0, 0, 0, 0, 0,
-                                               0, 0, 0, 0, 0, 0, 0, 0,
-                                               0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
-        }
-
-        // Build tx to sign
-        let mut tx = Transaction {
-            version: self.version,
-            lock_time: self.lock_time,
-            input: vec![],
-            output: vec![],
-        };
-        // Add all inputs necessary..
-        if anyone_can_pay {
-            tx.input = vec![TxIn {
-                previous_output: self.input[input_index].previous_output,
-                script_sig: script_pubkey.clone(),
-                sequence: self.input[input_index].sequence,
-                witness: vec![],
-            }];
-        } else {
-            tx.input = Vec::with_capacity(self.input.len());
-            for (n, input) in self.input.iter().enumerate() {
-                tx.input.push(TxIn {
-                    previous_output: input.previous_output,
-                    script_sig: if n == input_index { script_pubkey.clone() } else { Script::new() },
-                    sequence: if n != input_index && (sighash == SigHashType::Single || sighash == SigHashType::None) { 0 } else { input.sequence },
-                    witness: vec![],
-                });
-            }
-        }
-        // ..then all outputs
-        tx.output = match sighash {
-            SigHashType::All => self.output.clone(),
-            SigHashType::Single => {
-                let output_iter = self.output.iter()
-                                      .take(input_index + 1)  // sign all outputs up to and including this one, but erase
-                                      .enumerate()            // all of them except for this one
-                                      .map(|(n, out)| if n == input_index { out.clone() } else { TxOut::default() });
-                output_iter.collect()
-            }
-            SigHashType::None => vec![],
-            _ => unreachable!()
-        };
-        // hash the result
-        let mut engine = SigHash::engine();
-        tx.consensus_encode(&mut engine).unwrap();
-        let sighash_arr = endian::u32_to_array_le(sighash_u32);
-        sighash_arr.consensus_encode(&mut engine).unwrap();
-        SigHash::from_engine(engine)
+        self.signature_hash_checked(input_index, script_pubkey, sighash_u32)
+            .expect("input index out of bounds")
     }
 
     /// Gets the "weight" of this transaction, as defined by BIP141. For transactions with an empty
@@ -389,59 +819,101 @@ impl Transaction {
         self.get_scaled_size(WITNESS_SCALE_FACTOR)
     }
 
+    /// Gets the "weight" of this transaction as a [Weight]. See [Transaction::get_weight].
+    #[inline]
+    pub fn weight(&self) -> Weight {
+        Weight::from_wu(self.get_weight() as u64)
+    }
+
     /// Gets the regular byte-wise consensus-serialized size of this transaction.
     #[inline]
     pub fn get_size(&self) -> usize {
         self.get_scaled_size(1)
     }
 
+    /// Gets the "virtual size" (vsize) of this transaction, as defined by BIP141. This is the
+    /// weight divided by four, rounded up.
+    #[inline]
+    pub fn get_vsize(&self) -> usize {
+        self.weight().to_vbytes_ceil() as usize
+    }
+
+    /// Computes the fee in satoshis this transaction has to pay at the given [FeeRate].
+    /// Returns [None] if the computation overflows.
+    #[inline]
+    pub fn fee_with(&self, rate: FeeRate) -> Option<u64> {
+        rate.fee_wu(self.weight())
+    }
+
+    /// Gets the length of the consensus encoding of this transaction, with or without the
+    /// witness data, computed without serializing it.
+    pub fn serialized_length(&self, include_witness: bool) -> usize {
+        let (base_size, witness_size) = self.split_size();
+        if include_witness {
+            base_size + witness_size
+        } else {
+            base_size
+        }
+    }
+
     /// Internal utility function for get_{size,weight}
     fn get_scaled_size(&self, scale_factor: usize) -> usize {
-        let mut input_weight = 0;
+        let (base_size, witness_size) = self.split_size();
+        base_size * scale_factor + witness_size
+    }
+
+    /// Size of the encoding without witness data, and the size the witness data, marker and
+    /// flag add to it. The latter is zero if no input has a witness.
+    fn split_size(&self) -> (usize, usize) {
+        let mut base_size =
+            // version:
+            4 +
+            // count varints:
+            VarInt(self.input.len() as u64).len() +
+            VarInt(self.output.len() as u64).len() +
+            // lock_time
+            4;
+        let mut witness_size = 0;
         let mut inputs_with_witnesses = 0;
         for input in &self.input {
-            input_weight += scale_factor*(32 + 4 + 4 + // outpoint (32+4) + nSequence
-                VarInt(input.script_sig.len() as u64).len() +
-                input.script_sig.len());
+            // outpoint (32+4) + nSequence
+            base_size += 32 + 4 + 4 + input.script_sig.consensus_encoded_len();
             if !input.witness.is_empty() {
                 inputs_with_witnesses += 1;
-                input_weight += VarInt(input.witness.len() as u64).len();
-                for elem in &input.witness {
-                    input_weight += VarInt(elem.len() as u64).len() + elem.len();
-                }
+            }
+            witness_size += VarInt(input.witness.len() as u64).len();
+            for elem in &input.witness {
+                witness_size += VarInt(elem.len() as u64).len() + elem.len();
             }
         }
-        let mut output_size = 0;
         for output in &self.output {
-            output_size += 8 + // value
-                VarInt(output.script_pubkey.len() as u64).len() +
-                output.script_pubkey.len();
+            base_size += 8 + output.script_pubkey.consensus_encoded_len();
         }
-        let non_input_size =
-        // version:
-        4 +
-        // count varints:
-        VarInt(self.input.len() as u64).len() +
-        VarInt(self.output.len() as u64).len() +
-        output_size +
-        // lock_time
-        4;
         if inputs_with_witnesses == 0 {
-            non_input_size * scale_factor + input_weight
+            (base_size, 0)
         } else {
-            non_input_size * scale_factor + input_weight + self.input.len() - inputs_with_witnesses + 2
+            // marker and flag
+            (base_size, witness_size + 2)
         }
     }
 
     #[cfg(feature="bitcoinconsensus")]
     /// Verify that this transaction is able to spend its inputs
     /// The lambda spent should not return the same TxOut twice!
-    pub fn verify<S>(&self, mut spent: S) -> Result<(), script::Error>
+    pub fn verify<S>(&self, spent: S) -> Result<(), script::Error>
+        where S: FnMut(&OutPoint) -> Option<TxOut> {
+        self.verify_with_flags(spent, bitcoinconsensus::VERIFY_ALL)
+    }
+
+    #[cfg(feature="bitcoinconsensus")]
+    /// Verify that this transaction is able to spend its inputs under the
+    /// given `bitcoinconsensus::VERIFY_*` flags, see [Transaction::verify]
+    pub fn verify_with_flags<S>(&self, mut spent: S, flags: u32) -> Result<(), script::Error>
         where S: FnMut(&OutPoint) -> Option<TxOut> {
         let tx = encode::serialize(&*self);
         for (idx, input) in self.input.iter().enumerate() {
             if let Some(output) = spent(&input.previous_output) {
-                output.script_pubkey.verify(idx, output.value, tx.as_slice())?;
+                output.script_pubkey.verify_with_flags(idx, output.value, tx.as_slice(), flags)?;
             } else {
                 return Err(script::Error::UnknownSpentOutput(input.previous_output.clone()));
             }
@@ -449,13 +921,84 @@ impl Transaction {
         Ok(())
     }
 
+    /// Whether any input of this transaction has witness data.
+    pub fn has_witness(&self) -> bool {
+        self.input.iter().any(|input| !input.witness.is_empty())
+    }
+
+    /// Whether this transaction is serialized in the BIP144 witness format, with
+    /// the segwit marker and flag. That is the case if and only if some input has
+    /// witness data. Transactions without inputs are serialized without the marker
+    /// too, which makes them ambiguous and impossible to deserialize, like in Core.
+    pub fn needs_witness_serialization(&self) -> bool {
+        self.has_witness()
+    }
+
     /// Is this a coin base transaction?
     pub fn is_coin_base(&self) -> bool {
         self.input.len() == 1 && self.input[0].previous_output.is_null()
     }
+
+    /// The coins this transaction creates when it is included in the block at
+    /// `height`, one for each output in order.
+    pub fn coins(&self, height: u32) -> Vec<Coin> {
+        let txid = self.txid();
+        let is_coinbase = self.is_coin_base();
+        self.output.iter().enumerate().map(|(vout, txout)| Coin {
+            outpoint: OutPoint::new(txid, vout as u32),
+            txout: txout.clone(),
+            height: height,
+            is_coinbase: is_coinbase,
+        }).collect()
+    }
+
+    /// Check the rules which only apply to coinbase transactions, for a
+    /// coinbase of the block at `height`: the scriptSig must be 2 to 100 bytes
+    /// long and, from the BIP34 activation height on, begin with a minimal
+    /// push of the height. The genesis block, which nodes don't validate, is
+    /// exempt from BIP34.
+    pub fn validate_coinbase(&self, height: u32, params: &Params) -> Result<(), CoinbaseError> {
+        if !self.is_coin_base() {
+            return Err(CoinbaseError::NotCoinbase);
+        }
+        let script_sig = &self.input[0].script_sig;
+        if script_sig.len() < 2 || script_sig.len() > 100 {
+            return Err(CoinbaseError::BadLength(script_sig.len()));
+        }
+        if height > 0 && height >= params.bip34_height {
+            let expected = script::Builder::new().push_int(height as i64).into_script();
+            if !script_sig.as_bytes().starts_with(expected.as_bytes()) {
+                return Err(CoinbaseError::BadHeight(height));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the lock time of the transaction applies, which is the case
+    /// if any input has a non-final sequence number.
+    pub fn is_lock_time_enabled(&self) -> bool {
+        self.input.iter().any(|i| i.sequence.enables_absolute_lock_time())
+    }
+
+    /// Whether any input signals that the transaction may be replaced (BIP125).
+    ///
+    /// A transaction which doesn't signal replaceability itself may still be
+    /// replaceable because it spends an unconfirmed transaction which does.
+    pub fn is_explicitly_rbf(&self) -> bool {
+        self.input.iter().any(|i| i.sequence.is_rbf())
+    }
+}
+
+/// Lets APIs which only read transactions accept both owned values and
+/// shared ones such as `Arc<Transaction>`
+impl AsRef<Transaction> for Transaction {
+    fn as_ref(&self) -> &Transaction {
+        self
+    }
 }
 
 impl_consensus_encoding!(TxOut, value, script_pubkey);
+impl_consensus_encoding!(Coin, outpoint, txout, height, is_coinbase);
 
 impl Encodable for OutPoint {
     fn consensus_encode<S: io::Write>(
@@ -505,14 +1048,7 @@ impl Encodable for Transaction {
     ) -> Result<usize, encode::Error> {
         let mut len = 0;
         len += self.version.consensus_encode(&mut s)?;
-        let mut have_witness = self.input.is_empty();
-        for input in &self.input {
-            if !input.witness.is_empty() {
-                have_witness = true;
-                break;
-            }
-        }
-        if !have_witness {
+        if !self.needs_witness_serialization() {
             len += self.input.consensus_encode(&mut s)?;
             len += self.output.consensus_encode(&mut s)?;
         } else {
@@ -540,20 +1076,21 @@ impl Decodable for Transaction {
                 // BIP144 input witnesses
                 1 => {
                     let mut input = Vec::<TxIn>::consensus_decode(&mut d)?;
+                    if input.is_empty() {
+                        return Err(encode::Error::ParseFailed("witness flag set but no inputs"));
+                    }
                     let output = Vec::<TxOut>::consensus_decode(&mut d)?;
                     for txin in input.iter_mut() {
                         txin.witness = Decodable::consensus_decode(&mut d)?;
                     }
-                    if !input.is_empty() && input.iter().all(|input| input.witness.is_empty()) {
-                        Err(encode::Error::ParseFailed("witness flag set but no witnesses present"))
-                    } else {
-                        Ok(Transaction {
-                            version: version,
-                            input: input,
-                            output: output,
-                            lock_time: Decodable::consensus_decode(d)?,
-                        })
-                    }
+                    // If all witnesses are empty the flag is superfluous, and the
+                    // transaction is serialized without it again.
+                    Ok(Transaction {
+                        version: version,
+                        input: input,
+                        output: output,
+                        lock_time: Decodable::consensus_decode(d)?,
+                    })
                 }
                 // We don't support anything else
                 x => {
@@ -572,6 +1109,177 @@ impl Decodable for Transaction {
     }
 }
 
+/// Fee rates above this are assumed to be a mistake, such as a rate in sat/kvB
+/// passed as sat/vB, and rejected by [Builder].
+pub const MAX_FEE_RATE: FeeRate = FeeRate::from_sat_per_kwu(2_500_000);
+
+/// An error building a transaction with [Builder]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BuilderError {
+    /// No inputs were added
+    NoInputs,
+    /// The number of input weight predictions doesn't match the number of inputs
+    InputWeightMismatch {
+        /// The number of inputs
+        inputs: usize,
+        /// The number of predictions
+        predictions: usize,
+    },
+    /// The fee rate is above [MAX_FEE_RATE]
+    AbsurdFeeRate(FeeRate),
+    /// The inputs don't cover the outputs and the fee
+    InsufficientFunds {
+        /// The value of the outputs plus the fee without a change output
+        needed: Amount,
+        /// The value of the inputs
+        available: Amount,
+    },
+}
+
+impl fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuilderError::NoInputs => f.write_str("transaction has no inputs"),
+            BuilderError::InputWeightMismatch { inputs, predictions } =>
+                write!(f, "{} weight predictions for {} inputs", predictions, inputs),
+            BuilderError::AbsurdFeeRate(rate) => write!(f, "absurd fee rate {}", rate),
+            BuilderError::InsufficientFunds { needed, available } =>
+                write!(f, "insufficient funds: {} needed, {} available", needed, available),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl ::std::error::Error for BuilderError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// Assembles an unsigned transaction from inputs chosen by the caller and
+/// recipient outputs, working out the fee and change.
+///
+/// This does no coin selection: the inputs must already cover the outputs
+/// and the fee.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Builder {
+    version: i32,
+    lock_time: LockTime,
+    input: Vec<TxIn>,
+    input_value: Amount,
+    output: Vec<TxOut>,
+}
+
+impl Builder {
+    /// Creates a builder for a version 2 transaction with a zero lock time.
+    pub fn new() -> Builder {
+        Builder {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            input_value: Amount::ZERO,
+            output: vec![],
+        }
+    }
+
+    /// Sets the transaction version.
+    pub fn version(mut self, version: i32) -> Builder {
+        self.version = version;
+        self
+    }
+
+    /// Sets the lock time. It only applies if an input has a non-final
+    /// sequence number.
+    pub fn lock_time(mut self, lock_time: LockTime) -> Builder {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// Adds an input spending `previous_output`, which is worth `value`. The
+    /// sequence number defaults to [Sequence::MAX].
+    pub fn add_input(mut self, previous_output: OutPoint, value: Amount, sequence: Option<Sequence>) -> Builder {
+        self.input.push(TxIn {
+            previous_output: previous_output,
+            script_sig: Script::new(),
+            sequence: sequence.unwrap_or(Sequence::MAX),
+            witness: vec![],
+        });
+        self.input_value = self.input_value + value;
+        self
+    }
+
+    /// Adds a recipient output.
+    pub fn add_output(mut self, output: TxOut) -> Builder {
+        self.output.push(output);
+        self
+    }
+
+    /// Builds the transaction, paying what the inputs leave after the
+    /// recipient outputs and the fee to `change_script`.
+    ///
+    /// The fee is `fee_rate` applied to the weight predicted from
+    /// `input_weights`, one for each input in the order they were added. A
+    /// change output is only added if it would be worth at least its
+    /// [Script::dust_value], otherwise the change is left to the fee.
+    pub fn finish_with_change(
+        self,
+        change_script: Script,
+        fee_rate: FeeRate,
+        input_weights: &[InputWeightPrediction],
+    ) -> Result<Transaction, BuilderError> {
+        if self.input.is_empty() {
+            return Err(BuilderError::NoInputs);
+        }
+        if input_weights.len() != self.input.len() {
+            return Err(BuilderError::InputWeightMismatch {
+                inputs: self.input.len(),
+                predictions: input_weights.len(),
+            });
+        }
+        if fee_rate > MAX_FEE_RATE {
+            return Err(BuilderError::AbsurdFeeRate(fee_rate));
+        }
+
+        let output_value = self.output.iter().fold(Amount::ZERO, |sum, out| sum + Amount::from_sat(out.value));
+        let (fee, fee_with_change) = {
+            let fee = |change: Option<&Script>| {
+                let script_lens = self.output.iter().map(|out| &out.script_pubkey).chain(change).map(|script| script.len());
+                let weight = predict_weight(input_weights.iter().cloned(), script_lens);
+                // can't overflow for fee rates up to MAX_FEE_RATE
+                Amount::from_sat(fee_rate.fee_wu(weight).expect("fee overflow"))
+            };
+            (fee(None), fee(Some(&change_script)))
+        };
+
+        let needed = output_value + fee;
+        if self.input_value < needed {
+            return Err(BuilderError::InsufficientFunds {
+                needed: needed,
+                available: self.input_value,
+            });
+        }
+
+        let mut output = self.output;
+        if let Some(change) = self.input_value.checked_sub(output_value + fee_with_change) {
+            if change >= change_script.dust_value() {
+                output.push(TxOut::new(change, change_script));
+            }
+        }
+        Ok(Transaction {
+            version: self.version,
+            lock_time: self.lock_time,
+            input: self.input,
+            output: output,
+        })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
 /// Hashtype of a transaction, encoded in the last byte of a signature
 /// Fixed values so they can be casted as integer types for encoding
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
@@ -607,7 +1315,15 @@ impl SigHashType {
      }
 
      /// Reads a 4-byte uint32 as a sighash type
+     #[deprecated(since="0.25.1", note="please use `from_u32_consensus` or `from_u32_standard` instead")]
      pub fn from_u32(n: u32) -> SigHashType {
+         SigHashType::from_u32_consensus(n)
+     }
+
+     /// Reads a 4-byte uint32 as a sighash type the way the script interpreter does: any value
+     /// is accepted, and undefined values are mapped to the type they behave as when signing
+     /// and verifying.
+     pub fn from_u32_consensus(n: u32) -> SigHashType {
          match n & 0x9f {
              // "real" sighashes
              0x01 => SigHashType::All,
@@ -622,25 +1338,120 @@ impl SigHashType {
          }
      }
 
+     /// Reads a 4-byte uint32 as a standard sighash type, returning an error if the value is
+     /// not exactly one of the six defined types. This is what should be used for anything
+     /// which will be signed.
+     pub fn from_u32_standard(n: u32) -> Result<SigHashType, NonStandardSigHashType> {
+         match n {
+             0x01 => Ok(SigHashType::All),
+             0x02 => Ok(SigHashType::None),
+             0x03 => Ok(SigHashType::Single),
+             0x81 => Ok(SigHashType::AllPlusAnyoneCanPay),
+             0x82 => Ok(SigHashType::NonePlusAnyoneCanPay),
+             0x83 => Ok(SigHashType::SinglePlusAnyoneCanPay),
+             non_standard => Err(NonStandardSigHashType(non_standard))
+         }
+     }
+
      /// Converts to a u32
-     pub fn as_u32(self) -> u32 { self as u32 }
+     #[deprecated(since="0.25.1", note="please use `to_u32` instead")]
+     pub fn as_u32(self) -> u32 { self.to_u32() }
+
+     /// Converts to a u32
+     pub fn to_u32(self) -> u32 { self as u32 }
+}
+
+impl fmt::Display for SigHashType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            SigHashType::All => "ALL",
+            SigHashType::None => "NONE",
+            SigHashType::Single => "SINGLE",
+            SigHashType::AllPlusAnyoneCanPay => "ALL|ANYONECANPAY",
+            SigHashType::NonePlusAnyoneCanPay => "NONE|ANYONECANPAY",
+            SigHashType::SinglePlusAnyoneCanPay => "SINGLE|ANYONECANPAY",
+        };
+        f.write_str(s)
+    }
+}
+
+impl ::std::str::FromStr for SigHashType {
+    type Err = SigHashTypeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ALL" => Ok(SigHashType::All),
+            "NONE" => Ok(SigHashType::None),
+            "SINGLE" => Ok(SigHashType::Single),
+            "ALL|ANYONECANPAY" => Ok(SigHashType::AllPlusAnyoneCanPay),
+            "NONE|ANYONECANPAY" => Ok(SigHashType::NonePlusAnyoneCanPay),
+            "SINGLE|ANYONECANPAY" => Ok(SigHashType::SinglePlusAnyoneCanPay),
+            _ => Err(SigHashTypeParseError { unrecognized: s.to_owned() }),
+        }
+    }
+}
+
+serde_string_impl!(SigHashType, "a SigHashType string such as ALL or NONE|ANYONECANPAY");
+
+/// This type is returned when a sighash u32 which is not one of the standard types is
+/// parsed with [SigHashType::from_u32_standard].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NonStandardSigHashType(pub u32);
+
+impl fmt::Display for NonStandardSigHashType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "non-standard sighash type {:#x}", self.0)
+    }
+}
+
+#[allow(deprecated)]
+impl ::std::error::Error for NonStandardSigHashType {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// Error returned when parsing a [SigHashType] from a string fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigHashTypeParseError {
+    /// The unrecognized string
+    pub unrecognized: String,
+}
+
+impl fmt::Display for SigHashTypeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unrecognized sighash type string '{}'", self.unrecognized)
+    }
+}
+
+#[allow(deprecated)]
+impl ::std::error::Error for SigHashTypeParseError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::{OutPoint, ParseOutPointError, Transaction, TxIn};
+    use super::{Coin, CoinbaseError, OutPoint, ParseOutPointError, Sequence, Transaction, TxIn, TxOut, TAPROOT_ANNEX_PREFIX};
+    use super::{SigHashType, NonStandardSigHashType, SigHashTypeParseError};
 
     use std::str::FromStr;
-    use blockdata::constants::WITNESS_SCALE_FACTOR;
-    use blockdata::script::Script;
-    use consensus::encode::serialize;
+    use blockdata::constants::{genesis_block, COINBASE_MATURITY, WITNESS_SCALE_FACTOR};
+    use blockdata::locktime::{relative, LockTime};
+    use blockdata::script::{Builder, Script};
+    use consensus::encode::{self, serialize, serialize_hex};
     use consensus::encode::deserialize;
+    use consensus::params::Params;
+    use network::constants::Network;
 
     use hashes::Hash;
     use hashes::hex::FromHex;
 
     use hash_types::*;
+    use util::fee_rate::{FeeRate, Weight};
+    use util::sighash::SighashCache;
 
     #[test]
     fn test_outpoint() {
@@ -686,11 +1497,215 @@ mod tests {
         let txin = TxIn::default();
         assert_eq!(txin.previous_output, OutPoint::default());
         assert_eq!(txin.script_sig, Script::new());
-        assert_eq!(txin.sequence, 0xFFFFFFFF);
+        assert_eq!(txin.sequence, Sequence::MAX);
         assert_eq!(txin.previous_output, OutPoint::default());
         assert_eq!(txin.witness.len(), 0 as usize);
     }
 
+    #[test]
+    fn test_sequence() {
+        assert!(Sequence::MAX.is_final());
+        assert!(!Sequence::MAX.is_rbf());
+        assert!(!Sequence::ENABLE_LOCKTIME_NO_RBF.is_rbf());
+        assert!(Sequence::ENABLE_LOCKTIME_NO_RBF.enables_absolute_lock_time());
+        assert!(Sequence::ENABLE_RBF_NO_LOCKTIME.is_rbf());
+        assert!(!Sequence::ENABLE_RBF_NO_LOCKTIME.is_relative_lock_time());
+        assert_eq!(Sequence::ENABLE_RBF_NO_LOCKTIME.to_relative_lock_time(), None);
+
+        assert!(Sequence::ZERO.is_height_locked());
+        assert_eq!(Sequence::from_height(144), Sequence(144));
+        assert!(Sequence::from_height(144).is_height_locked());
+        assert_eq!(Sequence::from_512_second_intervals(2), Sequence(0x00400002));
+        assert!(Sequence::from_512_second_intervals(2).is_time_locked());
+        // undefined bits don't change the lock time
+        assert_eq!(Sequence(0x7fbf0010).to_relative_lock_time(), Some(relative::LockTime::Blocks(16)));
+        assert_eq!(Sequence(0x00c0ffff).to_relative_lock_time(), Some(relative::LockTime::Time(0xffff)));
+
+        assert_eq!(u32::from(Sequence::from(0xfffffffd)), 0xfffffffd);
+        assert_eq!(serialize(&Sequence(0x01020304)), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_validate_coinbase() {
+        let mainnet = Params::new(Network::Monacoin);
+        let regtest = Params::new(Network::MonacoinRegtest);
+
+        // the genesis coinbase predates BIP34
+        let genesis = genesis_block(Network::Monacoin).txdata[0].clone();
+        assert!(genesis.is_coin_base());
+        assert_eq!(genesis.validate_coinbase(0, &mainnet), Ok(()));
+        assert_eq!(genesis.validate_coinbase(1000, &regtest), Ok(()));
+        assert_eq!(genesis.validate_coinbase(1000, &mainnet), Err(CoinbaseError::BadHeight(1000)));
+
+        let coinbase = |script_sig: Script| Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn { script_sig: script_sig, previous_output: OutPoint::null(), ..Default::default() }],
+            output: vec![TxOut::default()],
+        };
+        for &height in &[1, 16, 17, 1_500_000] {
+            let script_sig = Builder::new().push_int(height).push_slice(b"/pool/").into_script();
+            assert_eq!(coinbase(script_sig).validate_coinbase(height as u32, &mainnet), Ok(()));
+        }
+        // a non-minimal push of the height
+        assert_eq!(coinbase(hex_script!("01100000")).validate_coinbase(16, &mainnet), Err(CoinbaseError::BadHeight(16)));
+        assert_eq!(coinbase(hex_script!("60")).validate_coinbase(16, &mainnet), Err(CoinbaseError::BadLength(1)));
+        assert_eq!(coinbase(Script::from(vec![0x60; 101])).validate_coinbase(16, &mainnet), Err(CoinbaseError::BadLength(101)));
+        assert_eq!(coinbase(Script::from(vec![0x60; 100])).validate_coinbase(16, &mainnet), Ok(()));
+
+        let mut not_coinbase = genesis.clone();
+        not_coinbase.input[0].previous_output.vout = 0;
+        assert!(!not_coinbase.is_coin_base());
+        assert_eq!(not_coinbase.validate_coinbase(0, &mainnet), Err(CoinbaseError::NotCoinbase));
+    }
+
+    #[test]
+    fn test_coinbase_script_sig() {
+        let mainnet = Params::new(Network::Monacoin);
+        for &height in &[1, 16, 17, 1_500_000] {
+            let script_sig = TxIn::coinbase_script_sig(height, b"/pool/").unwrap();
+            let mut expected = Builder::new().push_int(height as i64).into_script().into_bytes();
+            expected.extend_from_slice(b"/pool/");
+            assert_eq!(script_sig, Script::from(expected));
+            let coinbase = Transaction {
+                version: 1,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn { script_sig: script_sig, previous_output: OutPoint::null(), ..Default::default() }],
+                output: vec![TxOut::default()],
+            };
+            assert_eq!(coinbase.validate_coinbase(height, &mainnet), Ok(()));
+        }
+        assert_eq!(TxIn::coinbase_script_sig(16, &[0xff]).unwrap(), hex_script!("60ff"));
+        assert_eq!(TxIn::coinbase_script_sig(17, &[]).unwrap(), hex_script!("0111"));
+        assert_eq!(TxIn::coinbase_script_sig(1, &[]), Err(CoinbaseError::BadLength(1)));
+        assert_eq!(TxIn::coinbase_script_sig(0, &[]), Err(CoinbaseError::BadLength(1)));
+        assert_eq!(TxIn::coinbase_script_sig(1_000_000, &[0; 96]).unwrap().len(), 100);
+        assert_eq!(TxIn::coinbase_script_sig(1_000_000, &[0; 97]), Err(CoinbaseError::BadLength(101)));
+    }
+
+    #[test]
+    fn test_builder_fee_and_change() {
+        use super::{Builder as TxBuilder, BuilderError, MAX_FEE_RATE};
+        use util::amount::Amount;
+        use util::weight_prediction::{predict_weight, InputWeightPrediction};
+
+        let recipient = hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac");
+        let change = hex_script!("0014d9bfc5fa9d14e4d6a7fb45d4fc4c3c9d4a45bd1d");
+        let weights = [InputWeightPrediction::p2wpkh(), InputWeightPrediction::p2pkh_compressed()];
+        let rate = FeeRate::from_sat_per_vb(10).unwrap();
+        let fee = |scripts: &[&Script]| Amount::from_sat(rate.fee_wu(
+            predict_weight(weights.iter().cloned(), scripts.iter().map(|s| s.len()))).unwrap());
+        let fee_without_change = fee(&[&recipient]);
+        let fee_with_change = fee(&[&recipient, &change]);
+        assert_eq!(change.dust_value(), Amount::from_sat(294));
+
+        let builder = |first_input: Amount| TxBuilder::new()
+            .add_input(OutPoint::new(Txid::hash(b"a"), 0), first_input, None)
+            .add_input(OutPoint::new(Txid::hash(b"b"), 1), Amount::from_sat(50_000), Some(Sequence::ENABLE_RBF_NO_LOCKTIME))
+            .add_output(TxOut::new(Amount::from_sat(100_000), recipient.clone()));
+        let fund = |change_value: Amount| builder(Amount::from_sat(50_000) + fee_with_change + change_value);
+
+        // change of exactly its dust value is kept
+        let tx = fund(change.dust_value()).finish_with_change(change.clone(), rate, &weights).unwrap();
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.input[0].sequence, Sequence::MAX);
+        assert_eq!(tx.input[1].sequence, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[1], TxOut::new(change.dust_value(), change.clone()));
+
+        // one satoshi less is left to the fee
+        let tx = fund(change.dust_value() - Amount::ONE_SAT).finish_with_change(change.clone(), rate, &weights).unwrap();
+        assert_eq!(tx.output, vec![TxOut::new(Amount::from_sat(100_000), recipient.clone())]);
+
+        // enough for the fee without change, but not with it
+        let exact = builder(Amount::from_sat(50_000) + fee_without_change);
+        let tx = exact.clone().finish_with_change(change.clone(), rate, &weights).unwrap();
+        assert_eq!(tx.output.len(), 1);
+
+        let short = builder(Amount::from_sat(50_000) + fee_without_change - Amount::ONE_SAT);
+        assert_eq!(short.finish_with_change(change.clone(), rate, &weights), Err(BuilderError::InsufficientFunds {
+            needed: Amount::from_sat(100_000) + fee_without_change,
+            available: Amount::from_sat(100_000) + fee_without_change - Amount::ONE_SAT,
+        }));
+
+        assert_eq!(TxBuilder::new().finish_with_change(change.clone(), rate, &[]), Err(BuilderError::NoInputs));
+        assert_eq!(exact.clone().finish_with_change(change.clone(), rate, &weights[..1]),
+            Err(BuilderError::InputWeightMismatch { inputs: 2, predictions: 1 }));
+        let absurd = MAX_FEE_RATE.checked_add(FeeRate::from_sat_per_kwu(1)).unwrap();
+        assert_eq!(exact.clone().finish_with_change(change.clone(), absurd, &weights), Err(BuilderError::AbsurdFeeRate(absurd)));
+        match exact.finish_with_change(change.clone(), MAX_FEE_RATE, &weights) {
+            Err(BuilderError::InsufficientFunds { .. }) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_lock_time_and_rbf() {
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: LockTime::from_consensus(700_000),
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![TxOut::default()],
+        };
+        assert!(!tx.is_lock_time_enabled());
+        assert!(!tx.is_explicitly_rbf());
+
+        tx.input[1].sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+        assert!(tx.is_lock_time_enabled());
+        assert!(!tx.is_explicitly_rbf());
+
+        tx.input[0].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        assert!(tx.is_explicitly_rbf());
+
+        let ser = serialize(&tx);
+        assert_eq!(&ser[ser.len() - 4..], &[0x60, 0xae, 0x0a, 0x00]);
+        assert_eq!(deserialize::<Transaction>(&ser).unwrap(), tx);
+    }
+
+    #[test]
+    fn test_annex() {
+        let annex = vec![TAPROOT_ANNEX_PREFIX, 0xde, 0xad];
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::hash(&[1]), 0),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: vec![vec![0x01; 64], annex.clone()],
+            }],
+            output: vec![TxOut::default()],
+        };
+
+        // the annex is kept as it is through encoding
+        let ser = serialize(&tx);
+        let decoded: Transaction = deserialize(&ser).unwrap();
+        assert_eq!(decoded, tx);
+        assert_eq!(serialize(&decoded), ser);
+        assert_eq!(decoded.input[0].witness[1], annex);
+
+        let p2tr = hex_script!("51200101010101010101010101010101010101010101010101010101010101010101");
+        let p2wsh = hex_script!("00200101010101010101010101010101010101010101010101010101010101010101");
+        let p2sh = hex_script!("a914010101010101010101010101010101010101010187");
+        assert!(p2tr.is_v1_p2tr());
+        assert!(!p2wsh.is_v1_p2tr());
+        assert_eq!(decoded.input[0].annex(&p2tr), Some(&annex[..]));
+        // the same witness doesn't have an annex when spending other outputs
+        assert_eq!(decoded.input[0].annex(&p2wsh), None);
+        assert_eq!(decoded.input[0].annex(&p2sh), None);
+
+        // a single witness element is never an annex
+        let mut input = decoded.input[0].clone();
+        input.witness.remove(0);
+        assert_eq!(input.annex(&p2tr), None);
+        // nor is a last element with another prefix
+        let mut input = decoded.input[0].clone();
+        input.witness[1][0] = 0x51;
+        assert_eq!(input.annex(&p2tr), None);
+        input.witness[1].clear();
+        assert_eq!(input.annex(&p2tr), None);
+    }
+
     #[test]
     fn test_is_coinbase () {
         use network::constants::Network;
@@ -719,7 +1734,7 @@ mod tests {
                    "ce9ea9f6f5e422c6a9dbcddb3b9a14d1c78fab9ab520cb281aa2a74a09575da1".to_string());
         assert_eq!(realtx.input[0].previous_output.vout, 1);
         assert_eq!(realtx.output.len(), 1);
-        assert_eq!(realtx.lock_time, 0);
+        assert_eq!(realtx.lock_time, LockTime::ZERO);
 
         assert_eq!(format!("{:x}", realtx.txid()),
                    "a6eab3c14ab5272a58a5ba91505ba1a4b6d7a3a9fcbd187b6cd99a7b6d548cb7".to_string());
@@ -751,14 +1766,18 @@ mod tests {
                    "7cac3cf9a112cf04901a51d605058615d56ffe6d04b45270e89d1720ea955859".to_string());
         assert_eq!(realtx.input[0].previous_output.vout, 1);
         assert_eq!(realtx.output.len(), 1);
-        assert_eq!(realtx.lock_time, 0);
+        assert_eq!(realtx.lock_time, LockTime::ZERO);
 
         assert_eq!(format!("{:x}", realtx.txid()),
                    "f5864806e3565c34d1b41e716f72609d00b55ea5eac5b924c9719a842ef42206".to_string());
         assert_eq!(format!("{:x}", realtx.wtxid()),
                    "80b7d8a82d5d5bf92905b06f2014dd699e03837ca172e3a59d51426ebbe3e7f5".to_string());
         assert_eq!(realtx.get_weight(), 442);
+        assert_eq!(realtx.weight(), Weight::from_wu(442));
+        assert_eq!(realtx.get_vsize(), 111);
         assert_eq!(realtx.get_size(), tx_bytes.len());
+        assert_eq!(realtx.fee_with(FeeRate::from_sat_per_vb(2).unwrap()), Some(221));
+        assert_eq!(realtx.fee_with(FeeRate::from_sat_per_kwu(u64::max_value())), None);
     }
 
     #[test]
@@ -778,16 +1797,74 @@ mod tests {
 
     #[test]
     fn tx_no_input_deserialization() {
+        // the witness marker and flag must be followed by inputs
         let tx_bytes = Vec::from_hex(
             "010000000001000100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000"
         ).unwrap();
-        let tx: Transaction = deserialize(&tx_bytes).expect("deserialize tx");
+        match deserialize::<Transaction>(&tx_bytes) {
+            Err(encode::Error::ParseFailed("witness flag set but no inputs")) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
 
-        assert_eq!(tx.input.len(), 0);
-        assert_eq!(tx.output.len(), 1);
+        // and transactions without inputs are serialized without them
+        let tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: 100_000_000,
+                script_pubkey: hex_script!("76a9140389035a9225b3839e2bbf32d826a1e222031fd888ac"),
+            }],
+        };
+        assert!(!tx.needs_witness_serialization());
+        let tx_bytes = Vec::from_hex(
+            "01000000000100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000"
+        ).unwrap();
+        assert_eq!(serialize(&tx), tx_bytes);
+        assert_eq!(tx.serialized_length(true), tx_bytes.len());
+        assert_eq!(tx.get_weight(), 4 * tx_bytes.len());
+        assert_eq!(tx.txid(), Txid::hash(&tx_bytes));
+        // which can't be deserialized: the output count is read as the flag
+        assert!(deserialize::<Transaction>(&tx_bytes).is_err());
+    }
+
+    #[test]
+    fn test_witness_flag_edge_cases() {
+        let legacy_hex = "0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000";
+        let legacy_bytes = Vec::from_hex(legacy_hex).unwrap();
+        let legacy: Transaction = deserialize(&legacy_bytes).unwrap();
+        assert!(!legacy.needs_witness_serialization());
+        let (head, lock_time) = legacy_hex.split_at(legacy_hex.len() - 8);
+
+        // the marker with an unknown flag
+        for flag in &["00", "02", "ff"] {
+            let bytes = Vec::from_hex(&format!("{}00{}{}", &head[..8], flag, &legacy_hex[8..])).unwrap();
+            match deserialize::<Transaction>(&bytes) {
+                Err(encode::Error::UnsupportedSegwitFlag(f)) => assert_eq!(f, bytes[5]),
+                res => panic!("unexpected result {:?}", res),
+            }
+        }
 
-        let reser = serialize(&tx);
-        assert_eq!(tx_bytes, reser);
+        // the marker and flag, but only an empty witness
+        let flagged_hex = format!("{}0001{}00{}", &head[..8], &head[8..], lock_time);
+        let flagged: Transaction = deserialize(&Vec::from_hex(&flagged_hex).unwrap()).unwrap();
+        assert_eq!(flagged, legacy);
+        assert!(!flagged.needs_witness_serialization());
+        assert_eq!(serialize_hex(&flagged), legacy_hex);
+        assert_eq!(format!("{:x}", flagged.txid()), "a6eab3c14ab5272a58a5ba91505ba1a4b6d7a3a9fcbd187b6cd99a7b6d548cb7");
+        assert_eq!(flagged.wtxid().as_inner(), flagged.txid().as_inner());
+        assert_eq!(flagged.get_size(), legacy_bytes.len());
+
+        // an actual witness keeps the marker and flag
+        let segwit_hex = "02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93cac7c0100000000ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e370bd87cb5a078702483045022100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753b88db122e70c79f9370220756e6633b17fd2710e626347d28d60b0a2d6cbb41de51740644b9fb3ba7751040121028fa937ca8cba2197a37c007176ed8941055d3bcb8627d085e94553e62f057dcc00000000";
+        let segwit: Transaction = deserialize(&Vec::from_hex(segwit_hex).unwrap()).unwrap();
+        assert!(segwit.needs_witness_serialization());
+        assert_eq!(serialize_hex(&segwit), segwit_hex);
+        let mut stripped = segwit.clone();
+        stripped.input[0].witness.clear();
+        assert!(!stripped.needs_witness_serialization());
+        assert_eq!(stripped.txid(), segwit.txid());
+        assert_eq!(Txid::hash(&serialize(&stripped)), segwit.txid());
     }
 
     #[test]
@@ -867,6 +1944,47 @@ mod tests {
         serde_round_trip!(tx);
     }
 
+    #[test]
+    fn coins() {
+        let coinbase = genesis_block(Network::Monacoin).txdata[0].clone();
+        let coins = coinbase.coins(1000);
+        assert_eq!(coins.len(), 1);
+        let coin = &coins[0];
+        assert_eq!(coin.outpoint, OutPoint::new(coinbase.txid(), 0));
+        assert_eq!(coin.txout, coinbase.output[0]);
+        assert_eq!(coin.height, 1000);
+        assert!(coin.is_coinbase);
+
+        // spendable in the block after 100 confirmations, counting its own
+        assert!(!coin.is_mature(0));
+        assert!(!coin.is_mature(999));
+        assert!(!coin.is_mature(1000 + COINBASE_MATURITY - 2));
+        assert!(coin.is_mature(1000 + COINBASE_MATURITY - 1));
+        assert!(coin.is_mature(::std::u32::MAX));
+        let spend = Coin { is_coinbase: false, ..coin.clone() };
+        assert!(spend.is_mature(0));
+        assert!(spend.is_mature(1000));
+
+        let tx: Transaction = deserialize(&Vec::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+        let coins = tx.coins(5);
+        assert_eq!(coins.len(), tx.output.len());
+        assert!(!coins[0].is_coinbase);
+        assert!(coins[0].is_mature(5));
+
+        let encoded = serialize(&coins[0]);
+        assert_eq!(encoded.len(), 36 + serialize(&tx.output[0]).len() + 4 + 1);
+        assert_eq!(deserialize::<Coin>(&encoded).unwrap(), coins[0]);
+        assert_eq!(deserialize::<Coin>(&serialize(coin)).unwrap(), *coin);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn coin_serde() {
+        let coin = genesis_block(Network::Monacoin).txdata[0].coins(7).remove(0);
+        serde_round_trip!(coin);
+        bincode_round_trip!(coin);
+    }
+
     fn run_test_sighash(tx: &str, script: &str, input_index: usize, hash_type: i32, expected_result: &str) {
         let tx: Transaction = deserialize(&Vec::from_hex(tx).unwrap()[..]).unwrap();
         let script = Script::from(Vec::from_hex(script).unwrap());
@@ -876,6 +1994,7 @@ mod tests {
 
         let actual_result = tx.signature_hash(input_index, &script, hash_type as u32);
         assert_eq!(actual_result, expected_result);
+        assert_eq!(tx.signature_hash_checked(input_index, &script, hash_type as u32), Ok(expected_result));
     }
 
     // Test decoding transaction `4be105f158ea44aec57bf12c5817d073a712ab131df6f37786872cfc70734188`
@@ -892,10 +2011,40 @@ mod tests {
         assert_eq!(consensus_encoded, tx_bytes);
     }
 
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_human_readable() {
+        use serde_json;
+
+        let tx_bytes = Vec::from_hex("010000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff3603da1b0e00045503bd5704c7dd8a0d0ced13bb5785010800000000000a636b706f6f6c122f4e696e6a61506f6f6c2f5345475749542fffffffff02b4e5a212000000001976a914876fbb82ec05caa6af7a3b5e5a983aae6c6cc6d688ac0000000000000000266a24aa21a9edf91c46b49eb8a29089980f02ee6b57e7d63d33b18b4fddac2bcd7db2a39837040120000000000000000000000000000000000000000000000000000000000000000000000000").unwrap();
+        let tx: Transaction = deserialize(&tx_bytes).unwrap();
+
+        let json = serde_json::to_value(&tx.input[0]).unwrap();
+        assert_eq!(json["previous_output"], "0000000000000000000000000000000000000000000000000000000000000000:4294967295");
+        assert_eq!(json["script_sig"], "03da1b0e00045503bd5704c7dd8a0d0ced13bb5785010800000000000a636b706f6f6c122f4e696e6a61506f6f6c2f5345475749542f");
+        assert_eq!(json["sequence"], 0xffffffffu32);
+        assert_eq!(json["witness"][0], "0000000000000000000000000000000000000000000000000000000000000000");
+        let json = serde_json::to_value(&tx.output[0]).unwrap();
+        assert_eq!(json["value"], 312665524u64);
+        assert_eq!(json["script_pubkey"], "76a914876fbb82ec05caa6af7a3b5e5a983aae6c6cc6d688ac");
+
+        serde_round_trip!(tx);
+        bincode_round_trip!(tx);
+        serde_round_trip!(tx.input[0]);
+        bincode_round_trip!(tx.input[0]);
+        serde_round_trip!(tx.output[1]);
+        bincode_round_trip!(tx.output[1]);
+        serde_round_trip!(tx.input[0].previous_output);
+        bincode_round_trip!(tx.input[0].previous_output);
+        serde_round_trip!(tx.output[1].script_pubkey);
+        bincode_round_trip!(tx.output[1].script_pubkey);
+    }
+
 
     // These test vectors were stolen from libbtc, which is Copyright 2014 Jonas Schnelli MIT
     // They were transformed by replacing {...} with run_test_sighash(...), then the ones containing
-    // OP_CODESEPARATOR in their pubkeys were removed
+    // OP_CODESEPARATOR in their pubkeys were removed. Those scripts are covered by
+    // test_sighash_reference instead, against the implementation the vectors were generated with.
     #[test]
     fn test_sighash() {
         run_test_sighash("907c2bc503ade11cc3b04eb2918b6f547b0630ab569273824748c87ea14b0696526c66ba740200000004ab65ababfd1f9bdd4ef073c7afc4ae00da8a66f429c917a0081ad1e1dabce28d373eab81d8628de802000000096aab5253ab52000052ad042b5f25efb33beec9f3364e8a9139e8439d9d7e26529c3c30b6c3fd89f8684cfd68ea0200000009ab53526500636a52ab599ac2fe02a526ed040000000008535300516352515164370e010000000003006300ab2ec229", "", 2, 1864164639, "31af167a6cf3f9d5f6875caa4d31704ceb0eba078d132b78dab52c3b8997317e");
@@ -1189,6 +2338,223 @@ mod tests {
         run_test_sighash("cf781855040a755f5ba85eef93837236b34a5d3daeb2dbbdcf58bb811828d806ed05754ab8010000000351ac53ffffffffda1e264727cf55c67f06ebcc56dfe7fa12ac2a994fecd0180ce09ee15c480f7d00000000096351516a51acac00ab53dd49ff9f334befd6d6f87f1a832cddfd826a90b78fd8cf19a52cb8287788af94e939d6020000000700525251ac526310d54a7e8900ed633f0f6f0841145aae7ee0cbbb1e2a0cae724ee4558dbabfdc58ba6855010000000552536a53abfd1b101102c51f910500000000096300656a525252656a300bee010000000009ac52005263635151abe19235c9", "53005365", 2, 1422854188, "d5981bd4467817c1330da72ddb8760d6c2556cd809264b2d85e6d274609fc3a3");
     }
 
+    #[test]
+    fn test_sighash_checked() {
+        use util::sighash;
+
+        // two inputs, one output
+        let tx: Transaction = deserialize(&Vec::from_hex("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff01202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac11000000").unwrap()[..]).unwrap();
+        let script = Script::new();
+
+        assert_eq!(
+            tx.signature_hash_checked(2, &script, 0x01),
+            Err(sighash::Error::IndexOutOfInputsBounds { index: 2, inputs_size: 2 })
+        );
+        // SIGHASH_SINGLE with no corresponding output, with and without ANYONECANPAY
+        assert_eq!(tx.signature_hash_checked(1, &script, 0x03), Ok(SigHash::single_bug()));
+        assert_eq!(tx.signature_hash_checked(1, &script, 0x83), Ok(SigHash::single_bug()));
+        assert_eq!(tx.signature_hash(1, &script, 0x03), SigHash::single_bug());
+        assert!(tx.signature_hash_checked(0, &script, 0x03).unwrap() != SigHash::single_bug());
+        assert_eq!(&SigHash::single_bug()[..], &[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                                                 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0][..]);
+    }
+
+    #[test]
+    #[should_panic(expected = "input index out of bounds")]
+    fn test_sighash_oob_panics() {
+        let tx = Transaction { version: 1, lock_time: LockTime::ZERO, input: vec![], output: vec![] };
+        tx.signature_hash(0, &Script::new(), 0x01);
+    }
+
+    #[test]
+    fn test_sighash_codeseparator() {
+        let tx: Transaction = deserialize(&Vec::from_hex("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff01202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac11000000").unwrap()[..]).unwrap();
+
+        // OP_CODESEPARATORs are dropped from the script code, but not 0xab bytes inside pushes
+        let with_separators = Script::from(Vec::from_hex("ab51ab02abab52ab").unwrap());
+        let without_separators = Script::from(Vec::from_hex("5102abab52").unwrap());
+        for &hash_type in &[0x01, 0x02, 0x03, 0x81] {
+            assert_eq!(
+                tx.signature_hash(0, &with_separators, hash_type),
+                tx.signature_hash(0, &without_separators, hash_type)
+            );
+        }
+        assert!(tx.signature_hash(0, &with_separators, 0x01) != tx.signature_hash(0, &Script::from(Vec::from_hex("51ab52").unwrap()), 0x01));
+
+        // A truncated push stops the parse: trailing bytes are not written, though the
+        // length prefix still counts them (as in the reference implementation)
+        let truncated = Script::from(Vec::from_hex("ab5104ab").unwrap());
+        let mut preimage = Vec::new();
+        SighashCache::new(&tx).legacy_encode_signing_data_to(&mut preimage, 0, &truncated, 0x81).unwrap();
+        let expected_script_part = Vec::from_hex("035104").unwrap();
+        assert!(preimage.windows(3).any(|w| w == &expected_script_part[..]));
+        assert_eq!(preimage.len(), 4 + 1 + 36 + 3 + 4 + 1 + 34 + 4 + 4);
+    }
+
+    /// `SignatureHashOld` of Bitcoin Core's sighash_tests.cpp, the original
+    /// implementation that Core's sighash.json vectors were generated with and
+    /// are checked against
+    fn reference_signature_hash(tx: &Transaction, input_index: usize, script_code: &Script, hash_type: u32) -> SigHash {
+        // FindAndDelete(scriptCode, CScript(OP_CODESEPARATOR)): drop the
+        // separators at opcode boundaries, and keep everything from an
+        // unparseable push on
+        let code = script_code.as_bytes();
+        let mut deleted = vec![];
+        let mut pc = 0;
+        while pc < code.len() {
+            if code[pc] == 0xab {
+                pc += 1;
+                continue;
+            }
+            let len = match code[pc] {
+                n @ 0x00...0x4b => 1 + n as usize,
+                0x4c if pc + 1 < code.len() => 2 + code[pc + 1] as usize,
+                0x4d if pc + 2 < code.len() => 3 + (code[pc + 1] as usize | (code[pc + 2] as usize) << 8),
+                0x4c...0x4e => code.len() - pc,
+                _ => 1,
+            };
+            let end = ::std::cmp::min(pc + len, code.len());
+            deleted.extend_from_slice(&code[pc..end]);
+            pc = end;
+        }
+
+        let mut tmp = tx.clone();
+        for input in tmp.input.iter_mut() {
+            input.script_sig = Script::new();
+            input.witness.clear();
+        }
+        tmp.input[input_index].script_sig = Script::from(deleted);
+        match hash_type & 0x1f {
+            0x02 => {
+                tmp.output.clear();
+                for (i, input) in tmp.input.iter_mut().enumerate() {
+                    if i != input_index { input.sequence = Sequence(0); }
+                }
+            }
+            0x03 => {
+                if input_index >= tmp.output.len() {
+                    return SigHash::single_bug();
+                }
+                tmp.output.truncate(input_index + 1);
+                for output in tmp.output[..input_index].iter_mut() {
+                    *output = TxOut { value: 0xffffffffffffffff, script_pubkey: Script::new() };
+                }
+                for (i, input) in tmp.input.iter_mut().enumerate() {
+                    if i != input_index { input.sequence = Sequence(0); }
+                }
+            }
+            _ => {}
+        }
+        if hash_type & 0x80 != 0 {
+            tmp.input = vec![tmp.input[input_index].clone()];
+        }
+        let mut preimage = serialize(&tmp);
+        preimage.extend_from_slice(&serialize(&hash_type));
+        SigHash::hash(&preimage)
+    }
+
+    #[test]
+    fn test_sighash_reference() {
+        // xorshift, so the test is deterministic
+        let mut state = 0x9e3779b97f4a7c15u64;
+        let mut rand = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        // RandomScript of sighash_tests.cpp, which draws from these opcodes, plus
+        // pushes of separator bytes which must not be deleted
+        let random_script = |rand: &mut FnMut() -> u64| {
+            let ops = [0x00, 0x51, 0x52, 0x53, 0xac, 0x63, 0x65, 0x6a, 0xab];
+            let mut script = vec![];
+            for _ in 0..rand() % 10 {
+                match rand() % 12 {
+                    9 => script.extend_from_slice(&[0x02, 0xab, 0xab]),
+                    10 => script.extend_from_slice(&[0x4c, 0x01, 0xab]),
+                    11 => script.extend_from_slice(&[0x03, 0x51, 0xab, 0x52]),
+                    n => script.push(ops[n as usize % ops.len()]),
+                }
+            }
+            Script::from(script)
+        };
+
+        for _ in 0..2000 {
+            let tx = Transaction {
+                version: rand() as i32,
+                lock_time: if rand() % 2 == 0 { LockTime::from_consensus(rand() as u32) } else { LockTime::ZERO },
+                input: (0..1 + rand() % 4).map(|_| TxIn {
+                    previous_output: OutPoint::new(Txid::hash(&serialize(&rand())), rand() as u32 % 4),
+                    script_sig: random_script(&mut rand),
+                    sequence: if rand() % 2 == 0 { Sequence(rand() as u32) } else { Sequence::MAX },
+                    witness: vec![],
+                }).collect(),
+                output: (0..1 + rand() % 4).map(|_| TxOut {
+                    value: rand() % 100_000_000,
+                    script_pubkey: random_script(&mut rand),
+                }).collect(),
+            };
+            let script_code = random_script(&mut rand);
+            let input_index = rand() as usize % tx.input.len();
+            let hash_type = rand() as u32;
+
+            assert_eq!(
+                tx.signature_hash(input_index, &script_code, hash_type),
+                reference_signature_hash(&tx, input_index, &script_code, hash_type),
+                "tx {} script {:x} input {} hash type {:#x}", serialize_hex(&tx), script_code, input_index, hash_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_sighashtype_standard() {
+        let types = [
+            (SigHashType::All, 0x01, "ALL"),
+            (SigHashType::None, 0x02, "NONE"),
+            (SigHashType::Single, 0x03, "SINGLE"),
+            (SigHashType::AllPlusAnyoneCanPay, 0x81, "ALL|ANYONECANPAY"),
+            (SigHashType::NonePlusAnyoneCanPay, 0x82, "NONE|ANYONECANPAY"),
+            (SigHashType::SinglePlusAnyoneCanPay, 0x83, "SINGLE|ANYONECANPAY"),
+        ];
+        for &(ty, n, s) in types.iter() {
+            assert_eq!(ty.to_u32(), n);
+            assert_eq!(SigHashType::from_u32_standard(n), Ok(ty));
+            assert_eq!(SigHashType::from_u32_consensus(n), ty);
+            assert_eq!(ty.to_string(), s);
+            assert_eq!(SigHashType::from_str(s), Ok(ty));
+        }
+
+        for s in ["all", "ALL|", "ANYONECANPAY", "ALL | ANYONECANPAY", "SIGHASH_ALL", ""].iter() {
+            assert_eq!(SigHashType::from_str(s), Err(SigHashTypeParseError { unrecognized: s.to_string() }));
+        }
+    }
+
+    #[test]
+    fn test_sighashtype_nonstandard() {
+        // ANYONECANPAY with an undefined base type signs like ALL|ANYONECANPAY, but is not standard
+        assert_eq!(SigHashType::from_u32_standard(0x84), Err(NonStandardSigHashType(0x84)));
+        assert_eq!(SigHashType::from_u32_consensus(0x84), SigHashType::AllPlusAnyoneCanPay);
+        assert_eq!(SigHashType::from_u32_standard(0x80), Err(NonStandardSigHashType(0x80)));
+        assert_eq!(SigHashType::from_u32_consensus(0x80), SigHashType::AllPlusAnyoneCanPay);
+        // bits outside of the low byte and undefined base types without ANYONECANPAY
+        assert_eq!(SigHashType::from_u32_standard(0x101), Err(NonStandardSigHashType(0x101)));
+        assert_eq!(SigHashType::from_u32_consensus(0x101), SigHashType::All);
+        assert_eq!(SigHashType::from_u32_standard(0x00), Err(NonStandardSigHashType(0x00)));
+        assert_eq!(SigHashType::from_u32_consensus(0x04), SigHashType::All);
+        assert_eq!(SigHashType::from_u32_consensus(0xc3), SigHashType::SinglePlusAnyoneCanPay);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_sighashtype_serde() {
+        use serde_json;
+
+        let json = serde_json::to_value(&SigHashType::SinglePlusAnyoneCanPay).unwrap();
+        assert_eq!(json, serde_json::Value::String("SINGLE|ANYONECANPAY".to_owned()));
+        let des: SigHashType = serde_json::from_value(json).unwrap();
+        assert_eq!(des, SigHashType::SinglePlusAnyoneCanPay);
+    }
+
     #[test]
     #[cfg(feature="bitcoinconsensus")]
     fn test_transaction_verify () {
@@ -1239,8 +2605,48 @@ mod tests {
             }
             None
         }).err().unwrap() {
-            script::Error::BitcoinConsensus(_) => {},
+            script::Error::BitcoinConsensus(script::ConsensusError::Script) => {},
             _ => panic!("Wrong error type"),
         }
     }
+
+    #[test]
+    #[cfg(feature="bitcoinconsensus")]
+    fn test_transaction_verify_with_flags() {
+        use hashes::hex::FromHex;
+        use blockdata::script;
+        use consensus::params::Params;
+        use network::constants::Network;
+
+        // the native segwit spend of script::tests::test_bitcoinconsensus
+        let spending: Transaction = deserialize(&Vec::from_hex("010000000001011f97548fbbe7a0db7588a66e18d803d0089315aa7d4cc28360b6ec50ef36718a0100000000ffffffff02df1776000000000017a9146c002a686959067f4866b8fb493ad7970290ab728757d29f0000000000220020701a8d401c84fb13e6baf169d59684e17abd9fa216c8cc5b9fc63d622ff8c58d04004730440220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f8602207bdd20e307f062594022f12ed5017bbf4a055a06aea91c10110a0e3bb23117fc014730440220647d2dc5b15f60bc37dc42618a370b2a1490293f9e5c8464f53ec4fe1dfe067302203598773895b4b16d37485cbe21b337f4e4b650739880098c592553add7dd4355016952210375e00eb72e29da82b89367947f29ef34afb75e8654f6ea368e0acdfd92976b7c2103a1b26313f430c4b15bb1fdce663207659d8cac749a0e53d70eff01874496feff2103c96d495bfdd5ba4145e3e046fee45e84a8a48ad05bd8dbb395c011a32cf9f88053ae00000000").unwrap()).unwrap();
+        let spent = TxOut {
+            value: 18393430,
+            script_pubkey: Script::from(Vec::from_hex("0020701a8d401c84fb13e6baf169d59684e17abd9fa216c8cc5b9fc63d622ff8c58d").unwrap()),
+        };
+        let params = Params::new(Network::MonacoinRegtest);
+        let flags = params.script_verify_flags(params.bip66_height);
+        spending.verify_with_flags(|_| Some(spent.clone()), flags).unwrap();
+
+        // the witness commits to the amount
+        let wrong_amount = TxOut { value: spent.value - 1, ..spent.clone() };
+        assert_eq!(
+            spending.verify_with_flags(|_| Some(wrong_amount.clone()), flags),
+            Err(script::Error::BitcoinConsensus(script::ConsensusError::Script))
+        );
+
+        let mut corrupted = spending.clone();
+        corrupted.input[0].witness[1][10] ^= 1;
+        assert_eq!(
+            corrupted.verify_with_flags(|_| Some(spent.clone()), flags),
+            Err(script::Error::BitcoinConsensus(script::ConsensusError::Script))
+        );
+        // without witness verification the output is anyone-can-spend
+        corrupted.verify_with_flags(|_| Some(spent.clone()), bitcoinconsensus::VERIFY_P2SH).unwrap();
+
+        assert_eq!(
+            spent.script_pubkey.verify(1, spent.value, &serialize(&spending)),
+            Err(script::Error::BitcoinConsensus(script::ConsensusError::TxIndex))
+        );
+    }
 }
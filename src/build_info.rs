@@ -0,0 +1,182 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Build Information
+//!
+//! Which version of the library an application was built against and with
+//! which cargo features, for diagnostics and bug reports.
+
+
+use std::fmt;
+
+use network::constants::PROTOCOL_VERSION;
+
+/// PSBT versions which can be parsed and serialized.
+pub const PSBT_VERSIONS: &'static [u32] = &[0];
+
+/// The optional cargo features the library was compiled with.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Features {
+    /// Serde serialization, the `serde` or `use-serde` feature
+    pub serde: bool,
+    /// Random key generation, the `rand` feature
+    pub rand: bool,
+    /// ECDSA public key recovery, the `secp-recovery` feature
+    pub secp_recovery: bool,
+    /// Script verification through libbitcoinconsensus, the `bitcoinconsensus` feature
+    pub bitcoinconsensus: bool,
+    /// Network message I/O on tokio streams, the `tokio` feature
+    pub tokio: bool,
+}
+
+impl Features {
+    /// The names of the enabled features.
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let mut ret = vec![];
+        if self.serde { ret.push("serde"); }
+        if self.rand { ret.push("rand"); }
+        if self.secp_recovery { ret.push("secp-recovery"); }
+        if self.bitcoinconsensus { ret.push("bitcoinconsensus"); }
+        if self.tokio { ret.push("tokio"); }
+        ret
+    }
+}
+
+/// Version, features and supported protocol versions of this build of the library.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BuildInfo {
+    /// The crate version
+    pub version: &'static str,
+    /// The enabled features
+    pub features: Features,
+    /// The P2P protocol version sent in version messages
+    pub protocol_version: u32,
+    /// The supported PSBT versions
+    pub psbt_versions: &'static [u32],
+}
+
+/// Describe this build of the library. Everything is determined at compile time.
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        features: Features {
+            serde: cfg!(feature = "serde"),
+            rand: cfg!(feature = "rand"),
+            secp_recovery: cfg!(feature = "secp-recovery"),
+            bitcoinconsensus: cfg!(feature = "bitcoinconsensus"),
+            tokio: cfg!(feature = "tokio"),
+        },
+        protocol_version: PROTOCOL_VERSION,
+        psbt_versions: PSBT_VERSIONS,
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    /// Formats as, for example, `monacoin 0.25.0 (features: rand, serde; protocol 70001; psbt v0)`,
+    /// with `none` when no optional feature is enabled..
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let features = self.features.enabled();
+        let features = if features.is_empty() { "none".to_owned() } else { features.join(", ") };
+        write!(f, "monacoin {} (features: {}; protocol {}; psbt ", self.version, features, self.protocol_version)?;
+        for (i, version) in self.psbt_versions.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "v{}", version)?;
+        }
+        f.write_str(")")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Features {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut st = s.serialize_struct("Features", 5)?;
+        st.serialize_field("serde", &self.serde)?;
+        st.serialize_field("rand", &self.rand)?;
+        st.serialize_field("secp_recovery", &self.secp_recovery)?;
+        st.serialize_field("bitcoinconsensus", &self.bitcoinconsensus)?;
+        st.serialize_field("tokio", &self.tokio)?;
+        st.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for BuildInfo {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut st = s.serialize_struct("BuildInfo", 4)?;
+        st.serialize_field("version", self.version)?;
+        st.serialize_field("features", &self.features)?;
+        st.serialize_field("protocol_version", &self.protocol_version)?;
+        st.serialize_field("psbt_versions", self.psbt_versions)?;
+        st.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_info;
+
+    #[test]
+    fn build_info_features() {
+        let info = build_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.protocol_version, 70001);
+        assert_eq!(info.psbt_versions, &[0]);
+
+        #[cfg(feature = "rand")]
+        assert!(info.features.rand);
+        #[cfg(not(feature = "rand"))]
+        assert!(!info.features.rand);
+        #[cfg(feature = "serde")]
+        assert!(info.features.serde);
+        #[cfg(not(feature = "serde"))]
+        assert!(!info.features.serde);
+        #[cfg(feature = "secp-recovery")]
+        assert!(info.features.secp_recovery);
+        #[cfg(not(feature = "secp-recovery"))]
+        assert!(!info.features.secp_recovery);
+        #[cfg(feature = "bitcoinconsensus")]
+        assert!(info.features.bitcoinconsensus);
+        #[cfg(not(feature = "bitcoinconsensus"))]
+        assert!(!info.features.bitcoinconsensus);
+        #[cfg(feature = "tokio")]
+        assert!(info.features.tokio);
+        #[cfg(not(feature = "tokio"))]
+        assert!(!info.features.tokio);
+
+        let display = info.to_string();
+        assert!(display.starts_with(&format!("monacoin {} (features: ", info.version)));
+        assert!(display.ends_with("; protocol 70001; psbt v0)"));
+        #[cfg(all(feature = "rand", not(feature = "serde")))]
+        assert!(display.contains("features: rand"));
+        #[cfg(all(not(feature = "rand"), not(feature = "serde"), not(feature = "secp-recovery"), not(feature = "bitcoinconsensus"), not(feature = "tokio")))]
+        assert_eq!(display, format!("monacoin {} (features: none; protocol 70001; psbt v0)", info.version));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn build_info_serialize() {
+        let json = ::serde_json::to_value(&build_info()).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["features"]["serde"], true);
+        assert_eq!(json["features"]["rand"], cfg!(feature = "rand"));
+        assert_eq!(json["protocol_version"], 70001);
+        assert_eq!(json["psbt_versions"][0], 0);
+    }
+}
@@ -29,10 +29,13 @@
 //! big-endian decimals, etc.)
 //!
 
-use std::{fmt, error, io, mem, u32};
+
+use std::{cmp, fmt, mem, u32};
 use std::borrow::Cow;
+use std::io;
+use std::error;
 use std::io::{Cursor, Read, Write};
-use hashes::hex::ToHex;
+use hashes::hex::{self, FromHex, ToHex};
 
 use hashes::{sha256d, Hash};
 use hash_types::{BlockHash, FilterHash, TxMerkleNode};
@@ -41,8 +44,11 @@ use util::endian;
 use util::psbt;
 
 use blockdata::transaction::{TxOut, Transaction, TxIn};
+use network::constants::Magic;
 use network::message_blockdata::Inventory;
-use network::address::Address;
+use network::address::{Address, AddrV2Message};
+
+#[cfg(feature = "tokio")] pub mod async_io;
 
 /// Encoding error
 #[derive(Debug)]
@@ -51,12 +57,12 @@ pub enum Error {
     Io(io::Error),
     /// PSBT-related error
     Psbt(psbt::Error),
-    /// Network magic was not expected
+    /// A message was for another known network
     UnexpectedNetworkMagic {
         /// The expected network magic
-        expected: u32,
-        /// The unexpected network magic
-        actual: u32,
+        expected: Magic,
+        /// The magic of the other network
+        actual: Magic,
     },
     /// Tried to allocate an oversized vector
     OversizedVectorAllocation{
@@ -65,6 +71,13 @@ pub enum Error {
         /// The maximum capacity
         max: usize,
     },
+    /// A network message payload was larger than allowed
+    OversizedPayload {
+        /// The declared payload size
+        requested: usize,
+        /// The maximum payload size
+        max: usize,
+    },
     /// Checksum was invalid
     InvalidChecksum {
         /// The expected checksum
@@ -74,8 +87,13 @@ pub enum Error {
     },
     /// VarInt was encoded in a non-minimal way
     NonMinimalVarInt,
-    /// Network magic was unknown
-    UnknownNetworkMagic(u32),
+    /// A message had a magic which is not of any known network
+    UnknownNetworkMagic {
+        /// The expected network magic
+        expected: Magic,
+        /// The unknown network magic
+        actual: Magic,
+    },
     /// Parsing error
     ParseFailed(&'static str),
     /// Unsupported Segwit flag
@@ -84,6 +102,8 @@ pub enum Error {
     UnrecognizedNetworkCommand(String),
     /// Invalid Inventory type
     UnknownInventoryType(u32),
+    /// Invalid hex string
+    Hex(hex::Error),
 }
 
 impl fmt::Display for Error {
@@ -95,31 +115,37 @@ impl fmt::Display for Error {
                 "unexpected network magic: expected {}, actual {}", e, a),
             Error::OversizedVectorAllocation { requested: ref r, max: ref m } => write!(f,
                 "allocation of oversized vector: requested {}, maximum {}", r, m),
+            Error::OversizedPayload { requested: ref r, max: ref m } => write!(f,
+                "oversized message payload: requested {}, maximum {}", r, m),
             Error::InvalidChecksum { expected: ref e, actual: ref a } => write!(f,
                 "invalid checksum: expected {}, actual {}", e.to_hex(), a.to_hex()),
             Error::NonMinimalVarInt => write!(f, "non-minimal varint"),
-            Error::UnknownNetworkMagic(ref m) => write!(f, "unknown network magic: {}", m),
+            Error::UnknownNetworkMagic { expected: ref e, actual: ref a } => write!(f,
+                "unknown network magic: expected {}, actual {}", e, a),
             Error::ParseFailed(ref e) => write!(f, "parse failed: {}", e),
             Error::UnsupportedSegwitFlag(ref swflag) => write!(f,
                 "unsupported segwit version: {}", swflag),
             Error::UnrecognizedNetworkCommand(ref nwcmd) => write!(f,
                 "unrecognized network command: {}", nwcmd),
             Error::UnknownInventoryType(ref tp) => write!(f, "Unknown Inventory type: {}", tp),
+            Error::Hex(ref e) => write!(f, "hex error: {}", e),
         }
     }
 }
 
 #[allow(deprecated)]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
         match *self {
             Error::Io(ref e) => Some(e),
             Error::Psbt(ref e) => Some(e),
+            Error::Hex(ref e) => Some(e),
             Error::UnexpectedNetworkMagic { .. }
             | Error::OversizedVectorAllocation { .. }
+            | Error::OversizedPayload { .. }
             | Error::InvalidChecksum { .. }
             | Error::NonMinimalVarInt
-            | Error::UnknownNetworkMagic(..)
+            | Error::UnknownNetworkMagic { .. }
             | Error::ParseFailed(..)
             | Error::UnsupportedSegwitFlag(..)
             | Error::UnrecognizedNetworkCommand(..)
@@ -161,6 +187,85 @@ pub fn serialize_hex<T: Encodable + ?Sized>(data: &T) -> String {
     serialize(data)[..].to_hex()
 }
 
+/// A `Write` sink which only counts the bytes written to it
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Length of the encoding of an object, computed without allocating
+pub fn serialized_length<T: Encodable + ?Sized>(data: &T) -> usize {
+    let mut counter = ByteCounter(0);
+    let len = data.consensus_encode(&mut counter).unwrap();
+    assert_eq!(len, counter.0);
+    len
+}
+
+/// An error decoding an object from a hex string with [decode_hex]
+#[derive(Debug)]
+pub enum FromHexError {
+    /// The string is not valid hex, e.g. it has an odd length
+    Hex(hex::Error),
+    /// The bytes are not a valid consensus encoding of the object
+    Decode(Error),
+    /// The object was decoded, but this many bytes were left over
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for FromHexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FromHexError::Hex(ref e) => write!(f, "hex error: {}", e),
+            FromHexError::Decode(ref e) => write!(f, "decoding error: {}", e),
+            FromHexError::TrailingBytes(n) => write!(f, "{} trailing bytes after the decoded object", n),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for FromHexError {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            FromHexError::Hex(ref e) => Some(e),
+            FromHexError::Decode(ref e) => Some(e),
+            FromHexError::TrailingBytes(..) => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// Decode an object from its consensus encoding in hex, upper or lower case.
+/// Unlike [deserialize_hex], the error tells invalid hex, an invalid encoding
+/// and trailing bytes apart.
+pub fn decode_hex<T: Decodable>(hex: &str) -> Result<T, FromHexError> {
+    let data = Vec::from_hex(hex).map_err(FromHexError::Hex)?;
+    let (rv, consumed) = deserialize_partial(&data).map_err(FromHexError::Decode)?;
+    if consumed == data.len() {
+        Ok(rv)
+    } else {
+        Err(FromHexError::TrailingBytes(data.len() - consumed))
+    }
+}
+
+/// Deserialize an object from a hex-encoded string, will error if said
+/// deserialization doesn't consume the entire string.
+pub fn deserialize_hex<T: Decodable>(hex: &str) -> Result<T, Error> {
+    deserialize(&Vec::from_hex(hex).map_err(Error::Hex)?)
+}
+
 /// Deserialize an object from a vector, will error if said deserialization
 /// doesn't consume the entire vector.
 pub fn deserialize<T: Decodable>(data: &[u8]) -> Result<T, Error> {
@@ -175,7 +280,8 @@ pub fn deserialize<T: Decodable>(data: &[u8]) -> Result<T, Error> {
 }
 
 /// Deserialize an object from a vector, but will not report an error if said deserialization
-/// doesn't consume the entire vector.
+/// doesn't consume the entire vector. Returns the object and the number of bytes consumed,
+/// so that several objects can be read from one buffer.
 pub fn deserialize_partial<T: Decodable>(
     data: &[u8],
 ) -> Result<(T, usize), Error> {
@@ -321,6 +427,17 @@ impl<R: Read> ReadExt for R {
 /// Maximum size, in bytes, of a vector we are allowed to decode
 pub const MAX_VEC_SIZE: usize = 4_000_000;
 
+/// Maximum memory, in bytes, reserved up front for a decoded vector. Longer
+/// vectors grow as their elements arrive, so a forged length prefix doesn't
+/// make the decoder allocate more than this before failing.
+pub const MAX_VEC_PREALLOC: usize = 64 * 1024;
+
+/// The capacity to reserve for a vector of `len` elements of type `T` whose
+/// length was read from untrusted data.
+pub(crate) fn prealloc_capacity<T>(len: u64) -> usize {
+    cmp::min(len, (MAX_VEC_PREALLOC / cmp::max(mem::size_of::<T>(), 1)) as u64) as usize
+}
+
 /// Data which can be encoded in a consensus-consistent way
 pub trait Encodable {
     /// Encode an object with a well-defined format, should only ever error if
@@ -335,8 +452,11 @@ pub trait Decodable: Sized {
     fn consensus_decode<D: io::Read>(d: D) -> Result<Self, Error>;
 }
 
-/// A variable-length unsigned integer
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+/// A variable-length unsigned integer, the CompactSize of Bitcoin Core
+///
+/// Only the minimal encoding of a value is accepted when decoding, others are
+/// rejected with [Error::NonMinimalVarInt] as they are by Bitcoin Core.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Debug)]
 pub struct VarInt(pub u64);
 
 /// Data which must be preceded by a 4-byte checksum
@@ -389,6 +509,34 @@ impl VarInt {
     }
 }
 
+impl From<u64> for VarInt {
+    fn from(n: u64) -> VarInt {
+        VarInt(n)
+    }
+}
+
+impl From<usize> for VarInt {
+    fn from(n: usize) -> VarInt {
+        VarInt(n as u64)
+    }
+}
+
+impl From<VarInt> for u64 {
+    fn from(n: VarInt) -> u64 {
+        n.0
+    }
+}
+
+/// Read a [VarInt], rejecting non-minimal encodings.
+pub fn read_compact_size<R: io::Read>(r: R) -> Result<u64, Error> {
+    VarInt::consensus_decode(r).map(|n| n.0)
+}
+
+/// Write `n` as a [VarInt], returning the number of bytes written.
+pub fn write_compact_size<W: io::Write>(w: W, n: u64) -> Result<usize, Error> {
+    VarInt(n).consensus_encode(w)
+}
+
 impl Encodable for VarInt {
     #[inline]
     fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, Error> {
@@ -534,6 +682,7 @@ macro_rules! impl_array {
 
 impl_array!(2);
 impl_array!(4);
+impl_array!(6);
 impl_array!(8);
 impl_array!(12);
 impl_array!(16);
@@ -586,13 +735,35 @@ macro_rules! impl_vec {
                 if byte_size > MAX_VEC_SIZE {
                     return Err(self::Error::OversizedVectorAllocation { requested: byte_size, max: MAX_VEC_SIZE })
                 }
-                let mut ret = Vec::with_capacity(len as usize);
+                let mut ret = Vec::with_capacity(prealloc_capacity::<$type>(len));
                 for _ in 0..len {
                     ret.push(Decodable::consensus_decode(&mut d)?);
                 }
                 Ok(ret)
             }
         }
+
+        impl Encodable for Box<[$type]> {
+            #[inline]
+            fn consensus_encode<S: io::Write>(
+                &self,
+                mut s: S,
+            ) -> Result<usize, Error> {
+                let mut len = 0;
+                len += VarInt(self.len() as u64).consensus_encode(&mut s)?;
+                for c in self.iter() {
+                    len += c.consensus_encode(&mut s)?;
+                }
+                Ok(len)
+            }
+        }
+
+        impl Decodable for Box<[$type]> {
+            #[inline]
+            fn consensus_decode<D: io::Read>(d: D) -> Result<Self, Error> {
+                <Vec<$type>>::consensus_decode(d).map(From::from)
+            }
+        }
     }
 }
 impl_vec!(BlockHash);
@@ -604,6 +775,7 @@ impl_vec!(TxIn);
 impl_vec!(Inventory);
 impl_vec!(Vec<u8>);
 impl_vec!((u32, Address));
+impl_vec!(AddrV2Message);
 impl_vec!(u64);
 
 fn consensus_encode_with_size<S: io::Write>(data: &[u8], mut s: S) -> Result<usize, Error> {
@@ -627,9 +799,7 @@ impl Decodable for Vec<u8> {
         if len > MAX_VEC_SIZE {
             return Err(self::Error::OversizedVectorAllocation { requested: len, max: MAX_VEC_SIZE })
         }
-        let mut ret = vec![0u8; len];
-        d.read_slice(&mut ret)?;
-        Ok(ret)
+        read_bytes(d, len)
     }
 }
 
@@ -675,18 +845,33 @@ impl Decodable for CheckedData {
                 max: MAX_VEC_SIZE
             });
         }
-        let checksum = <[u8; 4]>::consensus_decode(&mut d)?;
-        let mut ret = vec![0u8; len as usize];
-        d.read_slice(&mut ret)?;
-        let expected_checksum = sha2_checksum(&ret);
-        if expected_checksum != checksum {
-            Err(self::Error::InvalidChecksum {
-                expected: expected_checksum,
-                actual: checksum,
-            })
-        } else {
-            Ok(CheckedData(ret))
-        }
+        read_checked(d, len as usize).map(CheckedData)
+    }
+}
+
+/// Read `len` bytes of data. Memory is only allocated as data arrives, so a
+/// truncated stream claiming a large length doesn't allocate that length up front.
+fn read_bytes<D: io::Read>(d: D, len: usize) -> Result<Vec<u8>, Error> {
+    let mut ret = Vec::with_capacity(cmp::min(len, MAX_VEC_PREALLOC));
+    d.take(len as u64).read_to_end(&mut ret)?;
+    if ret.len() < len {
+        return Err(self::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
+    }
+    Ok(ret)
+}
+
+/// Read a checksum followed by `len` bytes of data, verifying the checksum.
+pub(crate) fn read_checked<D: io::Read>(mut d: D, len: usize) -> Result<Vec<u8>, Error> {
+    let checksum = <[u8; 4]>::consensus_decode(&mut d)?;
+    let ret = read_bytes(d, len)?;
+    let expected_checksum = sha2_checksum(&ret);
+    if expected_checksum != checksum {
+        Err(self::Error::InvalidChecksum {
+            expected: expected_checksum,
+            actual: checksum,
+        })
+    } else {
+        Ok(ret)
     }
 }
 
@@ -718,10 +903,19 @@ macro_rules! tuple_encode {
 }
 
 tuple_encode!(T0, T1);
+tuple_encode!(T0, T1, T2);
 tuple_encode!(T0, T1, T2, T3);
 tuple_encode!(T0, T1, T2, T3, T4, T5);
 tuple_encode!(T0, T1, T2, T3, T4, T5, T6, T7);
 
+// References, so that borrowed data can be encoded without cloning it
+impl<'a, T: Encodable + ?Sized> Encodable for &'a T {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, Error> {
+        (**self).consensus_encode(s)
+    }
+}
+
 impl Encodable for sha256d::Hash {
     fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, Error> {
         self.into_inner().consensus_encode(s)
@@ -739,7 +933,19 @@ impl Decodable for sha256d::Hash {
 mod tests {
     use std::{io, mem, fmt};
     use std::mem::discriminant;
-    use super::{deserialize, serialize, Error, CheckedData, VarInt};
+    use super::{deserialize, deserialize_hex, serialize, serialize_hex, serialized_length, Error, FromHexError, CheckedData, VarInt};
+    use super::{read_compact_size, write_compact_size, MAX_VEC_PREALLOC};
+    use blockdata::constants::{MAX_BLOCK_WEIGHT, MIN_TRANSACTION_WEIGHT};
+    use hashes::Hash;
+    use hashes::hex::{self, FromHex};
+    use hash_types::Txid;
+    use blockdata::block::{Block, BlockHeader};
+    use blockdata::constants::genesis_block;
+    use network::constants::Network;
+    use blockdata::locktime::LockTime;
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, Sequence};
+    use network::message::{NetworkMessage, RawNetworkMessage};
     use super::{Transaction, BlockHash, FilterHash, TxMerkleNode, TxOut, TxIn};
     use consensus::{Encodable, deserialize_partial, Decodable};
     use util::endian::{u64_to_array_le, u32_to_array_le, u16_to_array_le};
@@ -817,6 +1023,27 @@ mod tests {
         test_varint_len(VarInt(0xFFFFFFFF), 5);
         test_varint_len(VarInt(0xFFFFFFFF+1), 9);
         test_varint_len(VarInt(u64::max_value()), 9);
+
+        // the boundaries and their minimal encodings
+        let boundaries: &[(u64, &[u8])] = &[
+            (0xFC, &[0xFC]),
+            (0xFD, &[0xFD, 0xFD, 0x00]),
+            (0xFFFF, &[0xFD, 0xFF, 0xFF]),
+            (0x10000, &[0xFE, 0x00, 0x00, 0x01, 0x00]),
+            (0xFFFFFFFF, &[0xFE, 0xFF, 0xFF, 0xFF, 0xFF]),
+            (0x100000000, &[0xFF, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]),
+        ];
+        for &(n, encoding) in boundaries {
+            assert_eq!(serialize(&VarInt(n)), encoding);
+            assert_eq!(VarInt::from(n).len(), encoding.len());
+            assert_eq!(deserialize::<VarInt>(encoding).unwrap(), VarInt(n));
+            assert_eq!(read_compact_size(encoding).unwrap(), n);
+            let mut written = vec![];
+            assert_eq!(write_compact_size(&mut written, n).unwrap(), encoding.len());
+            assert_eq!(written, encoding);
+        }
+        assert_eq!(VarInt::from(300usize), VarInt(300));
+        assert_eq!(u64::from(VarInt(300)), 300);
     }
 
     fn test_varint_len(varint: VarInt, expected: usize) {
@@ -844,6 +1071,8 @@ mod tests {
 
         assert_eq!(discriminant(&deserialize::<Vec<u8>>(&[0xfd, 0x00, 0x00]).unwrap_err()),
                    discriminant(&Error::NonMinimalVarInt));
+        assert_eq!(discriminant(&read_compact_size(&[0xfd, 0x20, 0x00][..]).unwrap_err()),
+                   discriminant(&Error::NonMinimalVarInt));
         assert_eq!(discriminant(&deserialize::<Vec<u8>>(&[0xfd, 0xfc, 0x00]).unwrap_err()),
                    discriminant(&Error::NonMinimalVarInt));
         assert_eq!(discriminant(&deserialize::<Vec<u8>>(&[0xfd, 0xfc, 0x00]).unwrap_err()),
@@ -883,6 +1112,39 @@ mod tests {
         // TODO: test vectors of more interesting objects
     }
 
+    #[test]
+    fn array_box_ref_test() {
+        // fixed-size arrays are encoded without a length prefix
+        let short_id = [1u8, 2, 3, 4, 5, 6];
+        assert_eq!(serialize(&short_id), short_id.to_vec());
+        assert_eq!(deserialize::<[u8; 6]>(&short_id).unwrap(), short_id);
+        assert_eq!(deserialize_partial::<[u8; 4]>(&short_id).unwrap(), ([1, 2, 3, 4], 4));
+        match deserialize::<[u8; 12]>(&short_id) {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(deserialize::<[u8; 4]>(&short_id).is_err());
+
+        // boxed slices are encoded like vectors
+        let outs = vec![TxOut::default(), TxOut { value: 1, script_pubkey: Script::new() }];
+        let boxed: Box<[TxOut]> = outs.clone().into_boxed_slice();
+        assert_eq!(serialize(&boxed), serialize(&outs));
+        assert_eq!(deserialize::<Box<[TxOut]>>(&serialize(&outs)).unwrap(), boxed);
+        let hashes: Box<[BlockHash]> = vec![BlockHash::hash(&[1]), BlockHash::hash(&[2])].into_boxed_slice();
+        assert_eq!(deserialize::<Box<[BlockHash]>>(&serialize(&hashes)).unwrap(), hashes);
+
+        // references are encoded like what they point to
+        let header = genesis_block(Network::Monacoin).header;
+        assert_eq!(serialize(&&header), serialize(&header));
+        assert_eq!(serialize(&(&header, &outs[1])), [serialize(&header), serialize(&outs[1])].concat());
+        assert_eq!(serialize(&(7u32, &[1u8, 2, 3, 4], &vec![1u8])), vec![7, 0, 0, 0, 1, 2, 3, 4, 1, 1]);
+
+        let payload = (7u32, vec![1u8, 2]);
+        assert_eq!(deserialize::<(u32, Vec<u8>)>(&serialize(&payload)).unwrap(), payload);
+        let triple = (1u8, [2u8; 4], vec![3u8]);
+        assert_eq!(deserialize::<(u8, [u8; 4], Vec<u8>)>(&serialize(&triple)).unwrap(), triple);
+    }
+
     #[test]
     fn serialize_strbuf_test() {
         assert_eq!(serialize(&"Andrew".to_string()), vec![6u8, 0x41, 0x6e, 0x64, 0x72, 0x65, 0x77]);
@@ -973,10 +1235,97 @@ mod tests {
         );
     }
 
+    #[test]
+    fn deserialize_partial_test() {
+        let tx1_hex = "0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000";
+        let tx2_hex = "0200000000010166c3d39490dc827a2594c7b17b7d37445e1f4b372179649cd2ce4475e3641bbb0100000017160014e69aa750e9bff1aca1e32e57328b641b611fc817fdffffff01e87c5d010000000017a914f3890da1b99e44cd3d52f7bcea6a1351658ea7be87024830450221009eb97597953dc288de30060ba02d4e91b2bde1af2ecf679c7f5ab5989549aa8002202a98f8c3bd1a5a31c0d72950dd6e2e3870c6c5819a6c3db740e91ebbbc5ef4800121023f3d3b8e74b807e32217dea2c75c8d0bd46b8665b3a2d9b3cb310959de52a09bc9d20700";
+        let mut data = Vec::from_hex(tx1_hex).unwrap();
+        let tx1_len = data.len();
+        data.extend(Vec::from_hex(tx2_hex).unwrap());
+
+        let (tx1, consumed) = deserialize_partial::<Transaction>(&data).unwrap();
+        assert_eq!(consumed, tx1_len);
+        let (tx2, consumed) = deserialize_partial::<Transaction>(&data[tx1_len..]).unwrap();
+        assert_eq!(tx1_len + consumed, data.len());
+        assert_eq!(serialize_hex(&tx1), tx1_hex);
+        assert_eq!(serialize_hex(&tx2), tx2_hex);
+        assert_eq!(deserialize_hex::<Transaction>(tx2_hex).unwrap(), tx2);
+
+        // trailing bytes are only an error when deserializing entirely
+        assert!(deserialize::<Transaction>(&data).is_err());
+
+        // truncated data is an unexpected end of file
+        for len in &[0, 1, 10, tx1_len - 1] {
+            match deserialize_partial::<Transaction>(&data[..*len]) {
+                Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+                res => panic!("unexpected result for {} bytes: {:?}", len, res),
+            }
+        }
+
+        match deserialize_hex::<Transaction>("0g") {
+            Err(Error::Hex(_)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn decode_hex_test() {
+        // the Monacoin genesis block
+        let genesis_hex = "010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d612000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff5f04ffff001d01044c564465632e20333174682032303133204a6170616e2c205468652077696e6e696e67206e756d62657273206f6620746865203230313320596561722d456e64204a756d626f204c6f74746572793a32332d313330393136ffffffff0100f2052a010000004341040184710fa689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f470216fe1b51850b4acf21b179c45070ac7b03a9ac00000000";
+        let genesis = Block::from_hex(genesis_hex).unwrap();
+        assert_eq!(genesis, genesis_block(Network::Monacoin));
+        assert_eq!(genesis.to_hex(), genesis_hex);
+        assert_eq!(Block::from_hex(&genesis_hex.to_uppercase()).unwrap(), genesis);
+        assert_eq!(BlockHeader::from_hex(&genesis_hex[..160]).unwrap(), genesis.header);
+        assert_eq!(genesis.header.to_hex(), &genesis_hex[..160]);
+
+        // a segwit transaction
+        let tx_hex = "010000000001011f97548fbbe7a0db7588a66e18d803d0089315aa7d4cc28360b6ec50ef36718a0100000000ffffffff02df1776000000000017a9146c002a686959067f4866b8fb493ad7970290ab728757d29f0000000000220020701a8d401c84fb13e6baf169d59684e17abd9fa216c8cc5b9fc63d622ff8c58d04004730440220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f8602207bdd20e307f062594022f12ed5017bbf4a055a06aea91c10110a0e3bb23117fc014730440220647d2dc5b15f60bc37dc42618a370b2a1490293f9e5c8464f53ec4fe1dfe067302203598773895b4b16d37485cbe21b337f4e4b650739880098c592553add7dd4355016952210375e00eb72e29da82b89367947f29ef34afb75e8654f6ea368e0acdfd92976b7c2103a1b26313f430c4b15bb1fdce663207659d8cac749a0e53d70eff01874496feff2103c96d495bfdd5ba4145e3e046fee45e84a8a48ad05bd8dbb395c011a32cf9f88053ae00000000";
+        let tx = Transaction::from_hex(tx_hex).unwrap();
+        assert_eq!(format!("{:x}", tx.txid()), "1a3e89644985fbbb41e0dcfe176739813542b5937003c46a07de1e3ee7a4a7f3");
+        assert_eq!(format!("{:x}", tx.wtxid()), "2c35e81b238aa8e41ea9c0d110d640a430698e21d1b65dcf0c5b35e64bc89fe9");
+        assert_eq!(tx.to_hex(), tx_hex);
+
+        // the three kinds of errors
+        match Transaction::from_hex(&tx_hex[1..]) {
+            Err(FromHexError::Hex(hex::Error::OddLengthString(759))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match Transaction::from_hex("0g") {
+            Err(FromHexError::Hex(hex::Error::InvalidChar(b'g'))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match Transaction::from_hex(&tx_hex[..100]) {
+            Err(FromHexError::Decode(Error::Io(ref e))) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match Transaction::from_hex(&format!("{}00ff", tx_hex)) {
+            Err(FromHexError::TrailingBytes(2)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match BlockHeader::from_hex(genesis_hex) {
+            Err(FromHexError::TrailingBytes(223)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
     #[test]
     fn deserialize_checkeddata_test() {
         let cd: Result<CheckedData, _> = deserialize(&[5u8, 0, 0, 0, 162, 107, 175, 90, 1, 2, 3, 4, 5]);
         assert_eq!(cd.ok(), Some(CheckedData(vec![1u8, 2, 3, 4, 5])));
+
+        // truncated data and a bad checksum
+        match deserialize::<CheckedData>(&[5u8, 0, 0, 0, 162, 107, 175, 90, 1, 2, 3, 4]) {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match deserialize::<CheckedData>(&[5u8, 0, 0, 0, 162, 107, 175, 91, 1, 2, 3, 4, 5]) {
+            Err(Error::InvalidChecksum { expected, actual }) => {
+                assert_eq!(expected, [162, 107, 175, 90]);
+                assert_eq!(actual, [162, 107, 175, 91]);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
     }
 
     #[test]
@@ -1017,5 +1366,97 @@ mod tests {
 
         }
     }
-}
+    fn random_bytes<R: Rng>(rng: &mut R, max_len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; rng.gen_range(0, max_len)];
+        rng.fill(&mut data[..]);
+        data
+    }
+
+    fn random_transaction<R: Rng>(rng: &mut R) -> Transaction {
+        let segwit = rng.gen();
+        Transaction {
+            version: rng.gen(),
+            lock_time: LockTime::from_consensus(rng.gen()),
+            // without inputs, transactions can't be deserialized
+            input: (0..rng.gen_range(1, 4)).map(|_| TxIn {
+                previous_output: OutPoint::new(Txid::hash(&random_bytes(rng, 8)), rng.gen()),
+                script_sig: Script::from(random_bytes(rng, 300)),
+                sequence: Sequence(rng.gen()),
+                witness: if segwit {
+                    (0..rng.gen_range(0, 4)).map(|_| random_bytes(rng, 300)).collect()
+                } else {
+                    vec![]
+                },
+            }).collect(),
+            output: (0..rng.gen_range(0, 4)).map(|_| TxOut {
+                value: rng.gen(),
+                script_pubkey: Script::from(random_bytes(rng, 300)),
+            }).collect(),
+        }
+    }
 
+    #[test]
+    fn serialized_length_test() {
+        let mut rng = thread_rng();
+        for len in &[0u64, 0xfc, 0xfd, 0xffff, 0x10000, 0xffffffff, 0x100000000] {
+            assert_eq!(VarInt(*len).len(), serialize(&VarInt(*len)).len());
+        }
+        for _ in 0..50 {
+            let tx = random_transaction(&mut rng);
+            let ser = serialize(&tx);
+            assert_eq!(serialized_length(&tx), ser.len());
+            assert_eq!(tx.serialized_length(true), ser.len());
+            let mut stripped = tx.clone();
+            for input in &mut stripped.input {
+                input.witness.clear();
+                assert_eq!(input.script_sig.consensus_encoded_len(), serialize(&input.script_sig).len());
+            }
+            assert_eq!(tx.serialized_length(false), serialize(&stripped).len());
+            assert_eq!(tx.get_size(), ser.len());
+            assert_eq!(tx.get_weight(), 3 * serialize(&stripped).len() + ser.len());
+
+            let block = Block {
+                header: BlockHeader {
+                    version: rng.gen(),
+                    prev_blockhash: BlockHash::hash(&random_bytes(&mut rng, 8)),
+                    merkle_root: TxMerkleNode::hash(&random_bytes(&mut rng, 8)),
+                    time: rng.gen(),
+                    bits: rng.gen(),
+                    nonce: rng.gen(),
+                },
+                txdata: (0..rng.gen_range(0, 4)).map(|_| random_transaction(&mut rng)).collect(),
+            };
+            assert_eq!(serialized_length(&block), serialize(&block).len());
+
+            for payload in vec![
+                NetworkMessage::Tx(tx),
+                NetworkMessage::Headers(vec![block.header; rng.gen_range(0, 4)]),
+                NetworkMessage::Block(block),
+                NetworkMessage::Ping(rng.gen()),
+                NetworkMessage::Verack,
+            ] {
+                let msg = RawNetworkMessage { magic: rng.gen(), payload: payload };
+                let ser = serialize(&msg);
+                assert_eq!(serialized_length(&msg), ser.len());
+                assert_eq!(deserialize::<RawNetworkMessage>(&ser).unwrap(), msg);
+            }
+        }
+    }
+    #[test]
+    fn block_tx_count_test() {
+        let mut block = serialize(&Block {
+            header: BlockHeader { version: 1, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 0, bits: 0, nonce: 0 },
+            txdata: vec![],
+        });
+        block.pop();
+        block.extend(serialize(&VarInt(MAX_BLOCK_WEIGHT as u64 / MIN_TRANSACTION_WEIGHT as u64 + 1)));
+        match deserialize::<Block>(&block) {
+            Err(Error::ParseFailed("too many transactions in block")) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // data arriving is still read in full
+        let long = vec![7u8; 3 * MAX_VEC_PREALLOC];
+        assert_eq!(deserialize::<Vec<u8>>(&serialize(&long)).unwrap(), long);
+    }
+}
@@ -0,0 +1,337 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Asynchronous Network Message I/O
+//!
+//! Reading and writing network messages on tokio's [AsyncRead] and
+//! [AsyncWrite] streams, without buffering more than the message at hand. The
+//! messages are decoded by a [MessageDecoder] and encoded with
+//! [serialize].
+//!
+//! This module needs the `tokio` feature, and so a newer Rust than the rest
+//! of the library. The futures are written out by hand, as the crate is on
+//! the 2015 edition, so they are awaited like those of `async fn`s.
+//!
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{cmp, io};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use consensus::encode::{serialize, Error};
+use network::message::RawNetworkMessage;
+use network::message_decoder::MessageDecoder;
+
+/// Size of the buffer the payload is read through before it is decoded
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Read the next message with the network magic `magic` from `reader`.
+///
+/// Only the bytes of this message are read, so the function can be called
+/// again for the next one. The magic and payload size are checked as soon as
+/// the header arrived, before the payload is read. A stream which ends
+/// halfway through a message is an [io::ErrorKind::UnexpectedEof] error.
+///
+/// If the future is dropped before it completes, the part of the message read
+/// so far is lost and the stream can't be decoded any further. Use a
+/// [MessageReader] where reads may be cancelled, e.g. in `select!`.
+pub fn read_message<'a, R: AsyncRead + Unpin + ?Sized>(reader: &'a mut R, magic: u32) -> ReadMessage<'a, R> {
+    ReadMessage {
+        reader: reader,
+        decoder: Decoder::Owned(MessageDecoder::new(magic)),
+    }
+}
+
+/// Write `msg` to `writer` and flush it.
+///
+/// If the future is dropped before it completes, part of the message may have
+/// been written, and the connection should be dropped.
+pub fn write_message<'a, W: AsyncWrite + Unpin + ?Sized>(writer: &'a mut W, msg: &RawNetworkMessage) -> WriteMessage<'a, W> {
+    WriteMessage {
+        writer: writer,
+        data: serialize(msg),
+        written: 0,
+    }
+}
+
+/// Reads network messages from a stream, keeping a partially read message
+/// when a read is cancelled.
+#[derive(Debug)]
+pub struct MessageReader<R> {
+    reader: R,
+    decoder: MessageDecoder,
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    /// Create a reader of messages with the network magic `magic`.
+    pub fn new(reader: R, magic: u32) -> MessageReader<R> {
+        MessageReader::with_decoder(reader, MessageDecoder::new(magic))
+    }
+
+    /// Create a reader of the messages `decoder` accepts.
+    pub fn with_decoder(reader: R, decoder: MessageDecoder) -> MessageReader<R> {
+        MessageReader {
+            reader: reader,
+            decoder: decoder,
+        }
+    }
+
+    /// Read the next message, like [read_message]. Dropping the future before
+    /// it completes loses nothing, the next call continues the message.
+    pub fn read_message<'a>(&'a mut self) -> ReadMessage<'a, R> {
+        ReadMessage {
+            reader: &mut self.reader,
+            decoder: Decoder::Borrowed(&mut self.decoder),
+        }
+    }
+
+    /// The underlying stream.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// The underlying stream, reading from which desynchronizes the reader
+    /// unless it is between messages.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Take the underlying stream and the bytes of a partially read message.
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        let buffered = self.decoder.buffer().to_vec();
+        (self.reader, buffered)
+    }
+}
+
+/// The decoder a [ReadMessage] future pushes the read bytes into
+#[derive(Debug)]
+enum Decoder<'a> {
+    Owned(MessageDecoder),
+    Borrowed(&'a mut MessageDecoder),
+}
+
+impl<'a> Decoder<'a> {
+    fn get_mut(&mut self) -> &mut MessageDecoder {
+        match *self {
+            Decoder::Owned(ref mut decoder) => decoder,
+            Decoder::Borrowed(ref mut decoder) => &mut **decoder,
+        }
+    }
+}
+
+/// The future of [read_message] and [MessageReader::read_message]
+#[derive(Debug)]
+pub struct ReadMessage<'a, R: 'a + ?Sized> {
+    reader: &'a mut R,
+    decoder: Decoder<'a>,
+}
+
+impl<'a, R: AsyncRead + Unpin + ?Sized> Future for ReadMessage<'a, R> {
+    type Output = Result<RawNetworkMessage, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let decoder = this.decoder.get_mut();
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            match decoder.next_message() {
+                Ok(Some(msg)) => return Poll::Ready(Ok(msg)),
+                Ok(None) => {}
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+            // never read past the end of the message
+            let len = cmp::min(decoder.bytes_needed(), READ_CHUNK_SIZE);
+            let mut buf = ReadBuf::new(&mut chunk[..len]);
+            match Pin::new(&mut *this.reader).poll_read(cx, &mut buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::Io(e))),
+                Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                    return Poll::Ready(Err(Error::Io(io::ErrorKind::UnexpectedEof.into())));
+                }
+                Poll::Ready(Ok(())) => decoder.push_bytes(buf.filled()),
+            }
+        }
+    }
+}
+
+/// The future of [write_message]
+#[derive(Debug)]
+pub struct WriteMessage<'a, W: 'a + ?Sized> {
+    writer: &'a mut W,
+    data: Vec<u8>,
+    written: usize,
+}
+
+impl<'a, W: AsyncWrite + Unpin + ?Sized> Future for WriteMessage<'a, W> {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        while this.written < this.data.len() {
+            match Pin::new(&mut *this.writer).poll_write(cx, &this.data[this.written..]) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::Io(e))),
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(Error::Io(io::ErrorKind::WriteZero.into()))),
+                Poll::Ready(Ok(n)) => this.written += n,
+            }
+        }
+        Pin::new(&mut *this.writer).poll_flush(cx).map(|res| res.map_err(Error::Io))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use hashes::hex::FromHex;
+    use tokio::io::{duplex, AsyncWrite, DuplexStream};
+
+    use blockdata::block::BlockHeader;
+    use consensus::encode::{deserialize, serialize, Error};
+    use network::message::{NetworkMessage, RawNetworkMessage};
+
+    use super::{read_message, write_message, MessageReader};
+
+    const MAGIC: u32 = 0xdbb6c0fb;
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn poll<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        Pin::new(future).poll(&mut Context::from_waker(&waker))
+    }
+
+    fn send(stream: &mut DuplexStream, data: &[u8]) {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        match Pin::new(stream).poll_write(&mut Context::from_waker(&waker), data) {
+            Poll::Ready(Ok(n)) => assert_eq!(n, data.len()),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    fn headers_message() -> RawNetworkMessage {
+        let header: BlockHeader = deserialize(&Vec::from_hex("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914cd74d6e49ffff001d323b3a7b").unwrap()).unwrap();
+        RawNetworkMessage {
+            magic: MAGIC,
+            payload: NetworkMessage::Headers(vec![header; 3]),
+        }
+    }
+
+    #[test]
+    fn read_in_chunks() {
+        let msg = headers_message();
+        let raw = serialize(&msg);
+        let ping = RawNetworkMessage { magic: MAGIC, payload: NetworkMessage::Ping(7) };
+        let (mut client, mut server) = duplex(1024);
+
+        let mut read = read_message(&mut server, MAGIC);
+        assert!(poll(&mut read).is_pending());
+        send(&mut client, &raw[..10]);
+        assert!(poll(&mut read).is_pending());
+        send(&mut client, &raw[10..100]);
+        assert!(poll(&mut read).is_pending());
+        // the next message arrives along with the rest of this one
+        let mut rest = raw[100..].to_vec();
+        rest.extend(serialize(&ping));
+        send(&mut client, &rest);
+        assert_eq!(poll(&mut read).map(Result::unwrap), Poll::Ready(msg));
+
+        // which was left in the stream
+        assert_eq!(poll(&mut read_message(&mut server, MAGIC)).map(Result::unwrap), Poll::Ready(ping));
+    }
+
+    #[test]
+    fn cancelled_read() {
+        let msg = headers_message();
+        let raw = serialize(&msg);
+        let (mut client, server) = duplex(1024);
+        let mut reader = MessageReader::new(server, MAGIC);
+
+        send(&mut client, &raw[..50]);
+        assert!(poll(&mut reader.read_message()).is_pending());
+        send(&mut client, &raw[50..]);
+        assert_eq!(poll(&mut reader.read_message()).map(Result::unwrap), Poll::Ready(msg));
+        assert!(reader.into_inner().1.is_empty());
+    }
+
+    #[test]
+    fn header_checks() {
+        let raw = serialize(&headers_message());
+        let (mut client, mut server) = duplex(1024);
+
+        // an oversized payload is refused before it is read
+        let mut huge = raw[..24].to_vec();
+        huge[16..20].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        send(&mut client, &huge);
+        match poll(&mut read_message(&mut server, MAGIC)) {
+            Poll::Ready(Err(Error::OversizedPayload { requested: 0xffffffff, .. })) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let (mut client, mut server) = duplex(1024);
+        send(&mut client, &raw);
+        match poll(&mut read_message(&mut server, 0xd9b4bef9)) {
+            Poll::Ready(Err(Error::UnexpectedNetworkMagic { .. })) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn unexpected_eof() {
+        let raw = serialize(&headers_message());
+        let (mut client, mut server) = duplex(1024);
+        send(&mut client, &raw[..100]);
+        drop(client);
+        match poll(&mut read_message(&mut server, MAGIC)) {
+            Poll::Ready(Err(Error::Io(ref e))) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn write_and_read() {
+        let msg = headers_message();
+        // a buffer smaller than the message, so writing has to wait for the reader
+        let (mut client, mut server) = duplex(64);
+
+        let mut write = write_message(&mut client, &msg);
+        let mut read = read_message(&mut server, MAGIC);
+        let (mut sent, mut received) = (false, None);
+        for _ in 0..100 {
+            if !sent {
+                if let Poll::Ready(res) = poll(&mut write) {
+                    res.unwrap();
+                    sent = true;
+                }
+            }
+            if received.is_none() {
+                if let Poll::Ready(res) = poll(&mut read) {
+                    received = Some(res.unwrap());
+                }
+            }
+        }
+        assert!(sent);
+        assert_eq!(received, Some(msg));
+    }
+}
@@ -22,5 +22,5 @@ pub mod encode;
 pub mod params;
 
 pub use self::encode::{Encodable, Decodable, WriteExt, ReadExt};
-pub use self::encode::{serialize, deserialize, deserialize_partial};
-pub use self::params::Params;
+pub use self::encode::{serialize, serialize_hex, serialized_length, deserialize, deserialize_hex, deserialize_partial};
+pub use self::params::{Params, DifficultyEra};
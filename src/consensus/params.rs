@@ -17,6 +17,9 @@
 //! This module provides predefined set of parameters for different chains.
 //!
 
+use std::cmp;
+
+#[cfg(feature = "bitcoinconsensus")] use bitcoinconsensus;
 use network::constants::Network;
 use util::uint::Uint256;
 
@@ -42,6 +45,45 @@ const MAX_BITS_REGTEST: Uint256 = Uint256([
     0x7fffff0000000000u64,
 ]);
 
+/// The version bits (BIP9) deployments of soft forks, indexing
+/// [Params::deployments].
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum DeploymentPos {
+    /// Relative lock times (BIP68, BIP112 and BIP113)
+    Csv = 0,
+    /// Segregated witness (BIP141, BIP143 and BIP147)
+    Segwit = 1,
+}
+
+/// Number of [DeploymentPos] variants, the length of [Params::deployments]
+pub const MAX_VERSION_BITS_DEPLOYMENTS: usize = 2;
+
+/// A version bits (BIP9) deployment of a soft fork.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Deployment {
+    /// The bit of the block version which signals readiness for the deployment.
+    pub bit: u8,
+    /// Median time past from which signalling counts.
+    pub start_time: u32,
+    /// Median time past from which the deployment fails if it isn't locked in yet.
+    pub timeout: u32,
+    /// The height from which the rules are enforced, if the deployment is
+    /// known to have activated there.
+    pub activation_height: Option<u32>,
+}
+
+impl Deployment {
+    /// A deployment which is active from the genesis block on
+    pub fn always_active(bit: u8) -> Deployment {
+        Deployment {
+            bit: bit,
+            start_time: 0,
+            timeout: u32::max_value(),
+            activation_height: Some(0),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Parameters that influence chain consensus.
 pub struct Params {
@@ -78,8 +120,14 @@ pub struct Params {
     pub allow_min_difficulty_blocks: bool,
     /// Determines whether retargeting is disabled for this network or not.
     pub no_pow_retargeting: bool,
+    /// Block height at which Kimoto Gravity Well retargeting becomes active.
+    pub switch_kgw_block: u32,
+    /// Block height at which DigiShield retargeting becomes active.
+    pub switch_digishield_block: u32,
     /// Block height at which Lyra2REv2 and DGWv3 becomes active.
     pub switch_lyra2rev2_dgwblock: u32,
+    /// The version bits deployments, indexed by [DeploymentPos].
+    pub deployments: [Deployment; MAX_VERSION_BITS_DEPLOYMENTS],
 }
 
 impl Params {
@@ -99,7 +147,14 @@ impl Params {
                 pow_target_timespan: 95040, // 1.1 days(1.1 * 24 * 60 * 60)
                 allow_min_difficulty_blocks: false,
                 no_pow_retargeting: false,
+                switch_kgw_block: 80000,
+                switch_digishield_block: 140000,
                 switch_lyra2rev2_dgwblock: 450000,
+                deployments: [
+                    // February 1st, 2017 to February 1st, 2018
+                    Deployment { bit: 0, start_time: 1485878400, timeout: 1517414400, activation_height: None },
+                    Deployment { bit: 1, start_time: 1485878400, timeout: 1517414400, activation_height: None },
+                ],
             },
             Network::MonacoinTestnet => Params {
                 network: Network::MonacoinTestnet,
@@ -114,7 +169,14 @@ impl Params {
                 pow_target_timespan: 95040, // 1.1 days(1.1 * 24 * 60 * 60)
                 allow_min_difficulty_blocks: true,
                 no_pow_retargeting: false,
+                switch_kgw_block: 10,
+                switch_digishield_block: 20,
                 switch_lyra2rev2_dgwblock: 60,
+                deployments: [
+                    // March 8th, 2017 to March 8th, 2018
+                    Deployment { bit: 0, start_time: 1488931200, timeout: 1520467200, activation_height: None },
+                    Deployment { bit: 1, start_time: 1488931200, timeout: 1520467200, activation_height: None },
+                ],
             },
             Network::MonacoinRegtest => Params {
                 network: Network::MonacoinRegtest,
@@ -129,7 +191,10 @@ impl Params {
                 pow_target_timespan: 95040, // 1.1 days(1.1 * 24 * 60 * 60)
                 allow_min_difficulty_blocks: true,
                 no_pow_retargeting: true,
+                switch_kgw_block: 10,
+                switch_digishield_block: 20,
                 switch_lyra2rev2_dgwblock: 30,
+                deployments: [Deployment::always_active(0), Deployment::always_active(1)],
             },
         }
     }
@@ -138,4 +203,308 @@ impl Params {
     pub fn difficulty_adjustment_interval(&self) -> u64 {
         self.pow_target_timespan / self.pow_target_spacing
     }
+
+    /// Returns the height of the next block after `current_height` whose
+    /// bits are retargeted.
+    ///
+    /// Before [Params::switch_kgw_block] this is the next multiple of
+    /// [Params::difficulty_adjustment_interval]. The classic retarget of that
+    /// block measures the time since the block one interval earlier, or since
+    /// the genesis block for the first retarget. From the switch on every
+    /// block is retargeted.
+    pub fn next_retarget_height(&self, current_height: u32) -> u32 {
+        let next = current_height.saturating_add(1);
+        if next >= self.switch_kgw_block {
+            return next;
+        }
+        let interval = self.difficulty_adjustment_interval() as u32;
+        let boundary = (current_height / interval).saturating_add(1).saturating_mul(interval);
+        cmp::min(boundary, self.switch_kgw_block)
+    }
+
+    /// Whether the bits of the block at `height` are retargeted rather than
+    /// copied from its parent, that is whether it is the first block of a
+    /// classic retarget period or follows [Params::switch_kgw_block]. Like
+    /// [Params::next_retarget_height], this ignores
+    /// [Params::no_pow_retargeting]. The genesis block is never retargeted.
+    pub fn is_retarget_height(&self, height: u32) -> bool {
+        height != 0
+            && (height >= self.switch_kgw_block
+                || height % self.difficulty_adjustment_interval() as u32 == 0)
+    }
+
+    /// Returns the height of the first block of the retarget period `height`
+    /// belongs to. Before [Params::switch_kgw_block] that is the last
+    /// multiple of [Params::difficulty_adjustment_interval], from the switch
+    /// on every block is a period of its own.
+    pub fn retarget_period_start(&self, height: u32) -> u32 {
+        if height >= self.switch_kgw_block {
+            return height;
+        }
+        height - height % self.difficulty_adjustment_interval() as u32
+    }
+
+    /// Returns the version bits deployment at `pos`.
+    pub fn deployment(&self, pos: DeploymentPos) -> &Deployment {
+        &self.deployments[pos as usize]
+    }
+
+    /// Whether the rules of the deployment at `pos` apply to the block at
+    /// `height`, whose parent has the median time past `mtp`.
+    ///
+    /// Returns [None] if that depends on the signalling of earlier blocks,
+    /// which is the case once `mtp` reached the start time of a deployment
+    /// whose activation height isn't known.
+    pub fn deployment_active_at(&self, pos: DeploymentPos, height: u32, mtp: u32) -> Option<bool> {
+        let deployment = self.deployment(pos);
+        match deployment.activation_height {
+            Some(activation_height) => Some(height >= activation_height),
+            None if mtp < deployment.start_time => Some(false),
+            None => None,
+        }
+    }
+
+    /// Returns the difficulty era the block at `height` belongs to.
+    pub fn difficulty_era(&self, height: u32) -> DifficultyEra {
+        DifficultyEra::at_height(self, height)
+    }
+
+    #[cfg(feature = "bitcoinconsensus")]
+    /// Returns the `bitcoinconsensus::VERIFY_*` flags which scripts of a block
+    /// at `height` are verified with.
+    ///
+    /// DERSIG and CHECKLOCKTIMEVERIFY follow [Params::bip66_height] and
+    /// [Params::bip65_height], CHECKSEQUENCEVERIFY the activation height of
+    /// [DeploymentPos::Csv], and WITNESS and NULLDUMMY that of
+    /// [DeploymentPos::Segwit]. A deployment without a known activation
+    /// height is not enforced. P2SH predates every Monacoin block.
+    pub fn script_verify_flags(&self, height: u32) -> u32 {
+        let active = |pos: DeploymentPos| match self.deployment(pos).activation_height {
+            Some(activation_height) => height >= activation_height,
+            None => false,
+        };
+        let mut flags = bitcoinconsensus::VERIFY_P2SH;
+        if active(DeploymentPos::Csv) {
+            flags |= bitcoinconsensus::VERIFY_CHECKSEQUENCEVERIFY;
+        }
+        if active(DeploymentPos::Segwit) {
+            flags |= bitcoinconsensus::VERIFY_WITNESS | bitcoinconsensus::VERIFY_NULLDUMMY;
+        }
+        if height >= self.bip66_height {
+            flags |= bitcoinconsensus::VERIFY_DERSIG;
+        }
+        if height >= self.bip65_height {
+            flags |= bitcoinconsensus::VERIFY_CHECKLOCKTIMEVERIFY;
+        }
+        flags
+    }
+}
+
+/// The eras of proof-of-work hashing and difficulty retargeting a Monacoin
+/// chain went through, in chronological order.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum DifficultyEra {
+    /// Scrypt proof-of-work, retargeting every
+    /// [`Params::difficulty_adjustment_interval`] blocks like Bitcoin.
+    Original,
+    /// Scrypt proof-of-work, Kimoto Gravity Well retargeting on every block.
+    KimotoGravityWell,
+    /// Scrypt proof-of-work, DigiShield retargeting on every block.
+    DigiShield,
+    /// Lyra2REv2 proof-of-work, Dark Gravity Wave v3 retargeting on every block.
+    Lyra2Rev2Dgw3,
+}
+
+impl DifficultyEra {
+    /// Returns the era the block at `height` belongs to. Each switch height is
+    /// the first block of the new era.
+    pub fn at_height(params: &Params, height: u32) -> DifficultyEra {
+        if height >= params.switch_lyra2rev2_dgwblock {
+            DifficultyEra::Lyra2Rev2Dgw3
+        } else if height >= params.switch_digishield_block {
+            DifficultyEra::DigiShield
+        } else if height >= params.switch_kgw_block {
+            DifficultyEra::KimotoGravityWell
+        } else {
+            DifficultyEra::Original
+        }
+    }
+
+    /// Returns the height of the first block of this era.
+    pub fn start_height(self, params: &Params) -> u32 {
+        match self {
+            DifficultyEra::Original => 0,
+            DifficultyEra::KimotoGravityWell => params.switch_kgw_block,
+            DifficultyEra::DigiShield => params.switch_digishield_block,
+            DifficultyEra::Lyra2Rev2Dgw3 => params.switch_lyra2rev2_dgwblock,
+        }
+    }
+
+    /// Whether blocks of this era are hashed with Lyra2REv2 rather than scrypt
+    /// for proof-of-work.
+    pub fn uses_lyra2rev2(self) -> bool {
+        self == DifficultyEra::Lyra2Rev2Dgw3
+    }
+
+    /// Whether the difficulty is recalculated on every block rather than once
+    /// per [`Params::difficulty_adjustment_interval`].
+    pub fn retargets_every_block(self) -> bool {
+        self != DifficultyEra::Original
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use network::constants::Network;
+    use util::uint::Uint256;
+    use super::{DeploymentPos, DifficultyEra, Params, MAX_BITS_BITCOIN, MAX_BITS_REGTEST};
+
+    #[test]
+    fn max_targets() {
+        assert_eq!(Network::MonacoinRegtest.max_target(), MAX_BITS_REGTEST);
+        assert_eq!(Uint256::from_compact(0x207fffff), Some(MAX_BITS_REGTEST));
+        assert_eq!(Network::Monacoin.max_target(), MAX_BITS_BITCOIN);
+        assert!(Network::MonacoinRegtest.max_target() > Network::Monacoin.max_target());
+    }
+
+    #[test]
+    fn deployments() {
+        let params = Params::new(Network::Monacoin);
+        assert_eq!(params.deployment(DeploymentPos::Csv).bit, 0);
+        assert_eq!(params.deployment(DeploymentPos::Segwit).bit, 1);
+        assert_eq!(params.deployment_active_at(DeploymentPos::Segwit, 900000, 1485878399), Some(false));
+        assert_eq!(params.deployment_active_at(DeploymentPos::Segwit, 1100000, 1485878400), None);
+
+        let mut params = params;
+        params.deployments[DeploymentPos::Csv as usize].activation_height = Some(1000);
+        assert_eq!(params.deployment_active_at(DeploymentPos::Csv, 999, 2000000000), Some(false));
+        assert_eq!(params.deployment_active_at(DeploymentPos::Csv, 1000, 0), Some(true));
+
+        let params = Params::new(Network::MonacoinRegtest);
+        for &pos in &[DeploymentPos::Csv, DeploymentPos::Segwit] {
+            assert_eq!(params.deployment_active_at(pos, 0, 0), Some(true));
+        }
+    }
+
+    #[test]
+    fn next_retarget_height() {
+        let params = Params::new(Network::Monacoin);
+        assert_eq!(params.next_retarget_height(0), 1056);
+        assert_eq!(params.next_retarget_height(1055), 1056);
+        assert_eq!(params.next_retarget_height(1056), 2112);
+        assert_eq!(params.next_retarget_height(79199), 79200);
+        // every block retargets from Kimoto Gravity Well on
+        assert_eq!(params.next_retarget_height(79200), 80000);
+        assert_eq!(params.next_retarget_height(79999), 80000);
+        assert_eq!(params.next_retarget_height(80000), 80001);
+        assert_eq!(params.next_retarget_height(u32::max_value()), u32::max_value());
+    }
+
+    #[test]
+    fn retarget_heights() {
+        let params = Params::new(Network::Monacoin);
+        assert!(!params.is_retarget_height(0));
+        assert_eq!(params.retarget_period_start(0), 0);
+        for &(height, retarget, start) in &[
+            (1, false, 0),
+            (1055, false, 0),
+            (1056, true, 1056),
+            (1057, false, 1056),
+            (79199, false, 78144),
+            (79200, true, 79200),
+            (79999, false, 79200),
+            // every block retargets from Kimoto Gravity Well on
+            (80000, true, 80000),
+            (80001, true, 80001),
+            (450000, true, 450000),
+        ] {
+            assert_eq!(params.is_retarget_height(height), retarget, "height {}", height);
+            assert_eq!(params.retarget_period_start(height), start, "height {}", height);
+        }
+        // consistent with the next retarget height
+        for height in (0..2200).chain(79000..80100) {
+            let next = params.next_retarget_height(height);
+            assert!(params.is_retarget_height(next));
+            assert!((height + 1..next).all(|h| !params.is_retarget_height(h)));
+            if next > height + 1 {
+                assert_eq!(params.retarget_period_start(next - 1), params.retarget_period_start(height + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn difficulty_era_boundaries() {
+        let params = Params::new(Network::Monacoin);
+        assert_eq!(DifficultyEra::at_height(&params, 0), DifficultyEra::Original);
+        assert_eq!(DifficultyEra::at_height(&params, 79999), DifficultyEra::Original);
+        assert_eq!(DifficultyEra::at_height(&params, 80000), DifficultyEra::KimotoGravityWell);
+        assert_eq!(DifficultyEra::at_height(&params, 139999), DifficultyEra::KimotoGravityWell);
+        assert_eq!(DifficultyEra::at_height(&params, 140000), DifficultyEra::DigiShield);
+        assert_eq!(DifficultyEra::at_height(&params, 449999), DifficultyEra::DigiShield);
+        assert_eq!(DifficultyEra::at_height(&params, 450000), DifficultyEra::Lyra2Rev2Dgw3);
+        assert_eq!(params.difficulty_era(u32::max_value()), DifficultyEra::Lyra2Rev2Dgw3);
+
+        assert!(!params.difficulty_era(449999).uses_lyra2rev2());
+        assert!(params.difficulty_era(450000).uses_lyra2rev2());
+        assert!(!params.difficulty_era(79999).retargets_every_block());
+        assert!(params.difficulty_era(80000).retargets_every_block());
+    }
+
+    #[test]
+    #[cfg(feature = "bitcoinconsensus")]
+    fn script_verify_flags() {
+        use bitcoinconsensus;
+
+        let mut params = Params::new(Network::Monacoin);
+        params.deployments[DeploymentPos::Csv as usize].activation_height = Some(1000);
+        params.deployments[DeploymentPos::Segwit as usize].activation_height = Some(2000);
+        params.bip65_height = 3000;
+        params.bip66_height = 4000;
+        let csv = bitcoinconsensus::VERIFY_CHECKSEQUENCEVERIFY;
+        let segwit = bitcoinconsensus::VERIFY_WITNESS | bitcoinconsensus::VERIFY_NULLDUMMY;
+        let cltv = bitcoinconsensus::VERIFY_CHECKLOCKTIMEVERIFY;
+        let p2sh = bitcoinconsensus::VERIFY_P2SH;
+
+        assert_eq!(params.script_verify_flags(0), p2sh);
+        assert_eq!(params.script_verify_flags(999), p2sh);
+        assert_eq!(params.script_verify_flags(1000), p2sh | csv);
+        assert_eq!(params.script_verify_flags(1999), p2sh | csv);
+        assert_eq!(params.script_verify_flags(2000), p2sh | csv | segwit);
+        assert_eq!(params.script_verify_flags(2999), p2sh | csv | segwit);
+        assert_eq!(params.script_verify_flags(3000), p2sh | csv | segwit | cltv);
+        assert_eq!(params.script_verify_flags(3999), p2sh | csv | segwit | cltv);
+        assert_eq!(params.script_verify_flags(4000), bitcoinconsensus::VERIFY_ALL);
+        assert_eq!(params.script_verify_flags(u32::max_value()), bitcoinconsensus::VERIFY_ALL);
+
+        // deployments without a known activation height are not enforced
+        params.deployments[DeploymentPos::Csv as usize].activation_height = None;
+        params.deployments[DeploymentPos::Segwit as usize].activation_height = None;
+        assert_eq!(params.script_verify_flags(u32::max_value()), p2sh | cltv | bitcoinconsensus::VERIFY_DERSIG);
+        // and regtest enforces them from the genesis block on
+        let regtest = Params::new(Network::MonacoinRegtest);
+        assert_eq!(regtest.script_verify_flags(0), p2sh | csv | segwit);
+    }
+
+    #[test]
+    fn difficulty_eras_are_ordered() {
+        for &network in [Network::Monacoin, Network::MonacoinTestnet, Network::MonacoinRegtest].iter() {
+            let params = Params::new(network);
+            let eras = [
+                DifficultyEra::Original,
+                DifficultyEra::KimotoGravityWell,
+                DifficultyEra::DigiShield,
+                DifficultyEra::Lyra2Rev2Dgw3,
+            ];
+            for window in eras.windows(2) {
+                assert!(window[0].start_height(&params) < window[1].start_height(&params));
+            }
+            for era in eras.iter() {
+                let start = era.start_height(&params);
+                assert_eq!(params.difficulty_era(start), *era);
+                if start > 0 {
+                    assert!(params.difficulty_era(start - 1) < *era);
+                }
+            }
+        }
+    }
 }
@@ -52,7 +52,7 @@ hash_newtype!(WitnessMerkleNode, sha256d::Hash, 32, doc="A hash corresponding to
 hash_newtype!(WitnessCommitment, sha256d::Hash, 32, doc="A hash corresponding to the witness structure commitment in the coinbase transaction");
 hash_newtype!(XpubIdentifier, hash160::Hash, 20, doc="XpubIdentifier as defined in BIP-32.");
 
-hash_newtype!(FilterHash, sha256d::Hash, 32, doc="Bloom filter souble-SHA256 locator hash, as defined in BIP-168");
+hash_newtype!(FilterHash, sha256d::Hash, 32, doc="Filter double-SHA256 hash, as defined in BIP-157");
 
 
 impl_hashencode!(Txid);
@@ -62,3 +62,38 @@ impl_hashencode!(BlockHash);
 impl_hashencode!(TxMerkleNode);
 impl_hashencode!(WitnessMerkleNode);
 impl_hashencode!(FilterHash);
+
+#[cfg(test)]
+mod tests {
+    use hashes::{sha256d, Hash};
+    use hashes::hex::FromHex;
+
+    use blockdata::constants::genesis_block;
+    use consensus::encode::{deserialize, serialize};
+    use network::constants::Network;
+    use super::{BlockHash, Txid};
+
+    #[test]
+    fn newtypes_wrap_raw_hashes() {
+        let genesis = genesis_block(Network::Monacoin);
+        let txid: Txid = genesis.txdata[0].txid();
+        let raw: sha256d::Hash = txid.into();
+        assert_eq!(Txid::from(raw), txid);
+        assert_eq!(Txid::from_hash(raw), txid);
+        assert_eq!(txid.as_hash(), raw);
+
+        // the same encoding and reversed hex as the raw hash
+        assert_eq!(serialize(&txid), serialize(&raw));
+        assert_eq!(deserialize::<Txid>(&serialize(&raw)).unwrap(), txid);
+        assert_eq!(txid.to_string(), raw.to_string());
+        assert_eq!(Txid::from_hex(&txid.to_string()).unwrap(), txid);
+        assert_eq!(serialize(&txid)[..], txid[..]);
+        assert_eq!(genesis.header.merkle_root.as_hash(), raw);
+
+        let block_hash: BlockHash = genesis.block_hash();
+        let mut reversed = block_hash.into_inner();
+        reversed.reverse();
+        assert_eq!(block_hash.to_string(), BlockHash::from_inner(reversed)[..].iter()
+            .map(|b| format!("{:02x}", b)).collect::<String>());
+    }
+}
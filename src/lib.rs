@@ -59,8 +59,10 @@ pub extern crate bech32;
 #[cfg(all(test, feature = "serde"))] #[macro_use] extern crate serde_derive; // for 1.22.0 compat
 #[cfg(all(test, feature = "serde"))] extern crate serde_json;
 #[cfg(all(test, feature = "serde"))] extern crate serde_test;
+#[cfg(all(test, feature = "serde"))] extern crate bincode;
 #[cfg(all(test, feature = "unstable"))] extern crate test;
 #[cfg(feature="bitcoinconsensus")] extern crate bitcoinconsensus;
+#[cfg(feature = "tokio")] extern crate tokio;
 
 #[cfg(target_pointer_width = "16")]
 compile_error!("rust-bitcoin cannot be used on 16-bit architectures");
@@ -78,8 +80,10 @@ pub mod consensus;
 // Do not remove: required in order to get hash types implementation macros to work correctly
 #[allow(unused_imports)]
 pub mod hash_types;
+mod build_info;
 
 pub use hash_types::*;
+pub use build_info::{build_info, BuildInfo, Features};
 pub use blockdata::block::Block;
 pub use blockdata::block::BlockHeader;
 pub use blockdata::script::Script;
@@ -96,6 +100,9 @@ pub use util::address::AddressType;
 pub use util::amount::Amount;
 pub use util::amount::Denomination;
 pub use util::amount::SignedAmount;
+pub use util::ecdsa::EcdsaSig;
+pub use util::fee_rate::FeeRate;
+pub use util::fee_rate::Weight;
 pub use util::key::PrivateKey;
 pub use util::key::PublicKey;
 pub use util::merkleblock::MerkleBlock;
@@ -20,10 +20,10 @@
 
 use std::io;
 use std::fmt;
-use std::net::{SocketAddr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use std::net::{SocketAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
 
 use network::constants::ServiceFlags;
-use consensus::encode::{self, Decodable, Encodable};
+use consensus::encode::{self, Decodable, Encodable, ReadExt, VarInt, WriteExt};
 
 /// A message which can be sent on the Bitcoin network
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -110,10 +110,196 @@ impl fmt::Debug for Address {
     }
 }
 
+/// Largest address accepted in an `addrv2` message, in bytes
+pub const MAX_ADDR_V2_SIZE: u64 = 512;
+
+/// Supported networks for use in BIP155 addrv2 message
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum AddrV2 {
+    /// IPV4
+    Ipv4(Ipv4Addr),
+    /// IPV6
+    Ipv6(Ipv6Addr),
+    /// TORV2, deprecated by Tor
+    TorV2([u8; 10]),
+    /// TORV3
+    TorV3([u8; 32]),
+    /// I2P
+    I2p([u8; 32]),
+    /// CJDNS
+    Cjdns(Ipv6Addr),
+    /// Unknown network id and its address
+    Unknown(u8, Vec<u8>),
+}
+
+impl AddrV2 {
+    /// The BIP155 network id.
+    pub fn network_id(&self) -> u8 {
+        match *self {
+            AddrV2::Ipv4(_) => 1,
+            AddrV2::Ipv6(_) => 2,
+            AddrV2::TorV2(_) => 3,
+            AddrV2::TorV3(_) => 4,
+            AddrV2::I2p(_) => 5,
+            AddrV2::Cjdns(_) => 6,
+            AddrV2::Unknown(id, _) => id,
+        }
+    }
+
+    /// The socket address with the given port, for IP addresses.
+    pub fn socket_addr(&self, port: u16) -> Option<SocketAddr> {
+        match *self {
+            AddrV2::Ipv4(ip) => Some(SocketAddr::V4(SocketAddrV4::new(ip, port))),
+            AddrV2::Ipv6(ip) => Some(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, 0))),
+            _ => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for AddrV2 {
+    fn from(addr: SocketAddr) -> AddrV2 {
+        match addr {
+            SocketAddr::V4(addr) => AddrV2::Ipv4(*addr.ip()),
+            SocketAddr::V6(addr) => AddrV2::Ipv6(*addr.ip()),
+        }
+    }
+}
+
+impl Encodable for AddrV2 {
+    fn consensus_encode<W: io::Write>(&self, mut w: W) -> Result<usize, encode::Error> {
+        fn encode_addr<W: io::Write>(mut w: W, network: u8, bytes: &[u8]) -> Result<usize, encode::Error> {
+            let len = network.consensus_encode(&mut w)?
+                + VarInt(bytes.len() as u64).consensus_encode(&mut w)?
+                + bytes.len();
+            w.emit_slice(bytes)?;
+            Ok(len)
+        }
+        let network = self.network_id();
+        match *self {
+            AddrV2::Ipv4(ref addr) => encode_addr(w, network, &addr.octets()),
+            AddrV2::Ipv6(ref addr) | AddrV2::Cjdns(ref addr) => encode_addr(w, network, &addr.octets()),
+            AddrV2::TorV2(ref bytes) => encode_addr(w, network, bytes),
+            AddrV2::TorV3(ref bytes) | AddrV2::I2p(ref bytes) => encode_addr(w, network, bytes),
+            AddrV2::Unknown(_, ref bytes) => {
+                if bytes.len() as u64 > MAX_ADDR_V2_SIZE {
+                    return Err(encode::Error::ParseFailed("addrv2 address longer than 512 bytes"));
+                }
+                encode_addr(&mut w, network, bytes)
+            }
+        }
+    }
+}
+
+impl Decodable for AddrV2 {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let network_id = u8::consensus_decode(&mut d)?;
+        let len = VarInt::consensus_decode(&mut d)?.0;
+        if len > MAX_ADDR_V2_SIZE {
+            return Err(encode::Error::ParseFailed("addrv2 address longer than 512 bytes"));
+        }
+        let expected_len = match network_id {
+            1 => Some(4),
+            2 | 6 => Some(16),
+            3 => Some(10),
+            4 | 5 => Some(32),
+            _ => None,
+        };
+        if expected_len.map_or(false, |expected| len != expected) {
+            return Err(encode::Error::ParseFailed("addrv2 address length doesn't match its network"));
+        }
+        let mut bytes = vec![0; len as usize];
+        d.read_slice(&mut bytes)?;
+
+        Ok(match network_id {
+            1 => AddrV2::Ipv4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+            2 => {
+                let addr = ipv6_from_slice(&bytes);
+                let segments = addr.segments();
+                if segments[0..3] == ONION {
+                    return Err(encode::Error::ParseFailed("OnionCat address sent with IPv6 network id"));
+                }
+                if segments[0..6] == [0, 0, 0, 0, 0, 0xFFFF] {
+                    return Err(encode::Error::ParseFailed("IPv4 wrapped address sent with IPv6 network id"));
+                }
+                AddrV2::Ipv6(addr)
+            }
+            3 => {
+                let mut addr = [0; 10];
+                addr.copy_from_slice(&bytes);
+                AddrV2::TorV2(addr)
+            }
+            4 | 5 => {
+                let mut addr = [0; 32];
+                addr.copy_from_slice(&bytes);
+                if network_id == 4 { AddrV2::TorV3(addr) } else { AddrV2::I2p(addr) }
+            }
+            6 => {
+                // CJDNS addresses are in fc00::/8
+                if bytes[0] != 0xFC {
+                    return Err(encode::Error::ParseFailed("CJDNS address must start with 0xFC"));
+                }
+                AddrV2::Cjdns(ipv6_from_slice(&bytes))
+            }
+            _ => AddrV2::Unknown(network_id, bytes),
+        })
+    }
+}
+
+fn ipv6_from_slice(bytes: &[u8]) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(bytes);
+    Ipv6Addr::from(octets)
+}
+
+/// Address received from BIP155 addrv2 message
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct AddrV2Message {
+    /// Time that this node was last seen as connected to the network
+    pub time: u32,
+    /// Service bits
+    pub services: ServiceFlags,
+    /// Network ID + Network Address
+    pub addr: AddrV2,
+    /// Network port, 0 if not applicable
+    pub port: u16,
+}
+
+impl AddrV2Message {
+    /// Extract socket address from an addrv2 message.
+    /// This will return io::Error ErrorKind::AddrNotAvailable if the address
+    /// isn't an IP address.
+    pub fn socket_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.addr.socket_addr(self.port).ok_or(io::Error::from(io::ErrorKind::AddrNotAvailable))
+    }
+}
+
+impl Encodable for AddrV2Message {
+    fn consensus_encode<W: io::Write>(&self, mut w: W) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.time.consensus_encode(&mut w)?;
+        // the services are a compact size here
+        len += VarInt(self.services.as_u64()).consensus_encode(&mut w)?;
+        len += self.addr.consensus_encode(&mut w)?;
+        len += self.port.to_be().consensus_encode(w)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for AddrV2Message {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        Ok(AddrV2Message {
+            time: Decodable::consensus_decode(&mut d)?,
+            services: ServiceFlags::from(VarInt::consensus_decode(&mut d)?.0),
+            addr: Decodable::consensus_decode(&mut d)?,
+            port: u16::from_be(Decodable::consensus_decode(d)?),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
-    use super::Address;
+    use super::{Address, AddrV2, AddrV2Message};
     use network::constants::ServiceFlags;
     use std::net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr};
 
@@ -191,5 +377,55 @@ mod test {
         let addr = Address::new(&onionaddr, ServiceFlags::NONE);
         assert!(addr.socket_addr().is_err());
     }
-}
 
+    #[test]
+    fn addrv2_test() {
+        use hashes::hex::{FromHex, ToHex};
+
+        let check = |addr: AddrV2, hex: &str| {
+            assert_eq!(serialize(&addr).to_hex(), hex);
+            assert_eq!(deserialize::<AddrV2>(&Vec::from_hex(hex).unwrap()).unwrap(), addr);
+        };
+        check(AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)), "010401020304");
+        check(AddrV2::Ipv6(Ipv6Addr::from_str("1a1b:2a2b:3a3b:4a4b:5a5b:6a6b:7a7b:8a8b").unwrap()),
+            "02101a1b2a2b3a3b4a4b5a5b6a6b7a7b8a8b");
+        check(AddrV2::TorV2([0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa]), "030af1f2f3f4f5f6f7f8f9fa");
+        check(AddrV2::TorV3([0x79; 32]), &format!("0420{}", "79".repeat(32)));
+        check(AddrV2::I2p([0xa2; 32]), &format!("0520{}", "a2".repeat(32)));
+        check(AddrV2::Cjdns(Ipv6Addr::from_str("fc00:1:2:3:4:5:6:7").unwrap()), "0610fc000001000200030004000500060007");
+        check(AddrV2::Unknown(170, vec![0x01, 0x02]), "aa020102");
+
+        // wrong lengths for known networks
+        assert!(deserialize::<AddrV2>(&Vec::from_hex("01050102030405").unwrap()).is_err());
+        assert!(deserialize::<AddrV2>(&Vec::from_hex("030b0102030405060708090a0b").unwrap()).is_err());
+        // IPv4-mapped and OnionCat addresses may not be sent as IPv6
+        assert!(deserialize::<AddrV2>(&Vec::from_hex("021000000000000000000000ffff01020304").unwrap()).is_err());
+        assert!(deserialize::<AddrV2>(&Vec::from_hex("0210fd87d87eeb430102030405060708090a").unwrap()).is_err());
+        // CJDNS outside fc00::/8
+        assert!(deserialize::<AddrV2>(&Vec::from_hex("0610aa000001000200030004000500060007").unwrap()).is_err());
+        // oversized unknown address
+        let mut oversized = Vec::from_hex("aafd0102").unwrap();
+        oversized.extend(vec![0; 513]);
+        assert!(deserialize::<AddrV2>(&oversized).is_err());
+    }
+
+    #[test]
+    fn addrv2_message_test() {
+        let msg = AddrV2Message {
+            time: 0x4966bc61,
+            services: ServiceFlags::NETWORK | ServiceFlags::WITNESS,
+            addr: AddrV2::Ipv4(Ipv4Addr::new(127, 0, 0, 1)),
+            port: 8333,
+        };
+        let raw = serialize(&msg);
+        assert_eq!(raw, vec![0x61, 0xbc, 0x66, 0x49, 0x09, 0x01, 0x04, 127, 0, 0, 1, 0x20, 0x8d]);
+        assert_eq!(deserialize::<AddrV2Message>(&raw).unwrap(), msg);
+        assert_eq!(msg.socket_addr().unwrap(), SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8333));
+
+        let tor = AddrV2Message { addr: AddrV2::TorV3([1; 32]), ..msg };
+        assert!(tor.socket_addr().is_err());
+
+        let s6 = SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0x1111, 0x2222, 0x3333, 0x4444, 0x5555, 0x6666, 0x7777, 0x8888)), 9999);
+        assert_eq!(AddrV2::from(s6).socket_addr(9999), Some(s6));
+    }
+}
@@ -37,11 +37,18 @@
 //! assert_eq!(&bytes[..], &[0xFB, 0xC0, 0xB6, 0xDB]);
 //! ```
 
-use std::{fmt, io, ops};
+use std::{fmt, ops};
+use std::io;
 
+use blockdata::constants::ChainHash;
 use consensus::encode::{self, Encodable, Decodable};
+use consensus::params::Params;
+use util::endian;
+use util::uint::Uint256;
 
 /// Version of the protocol as appearing in network message headers
+///
+/// See [ProtocolVersion] for the versions at which optional features appeared.
 pub const PROTOCOL_VERSION: u32 = 70001;
 
 user_enum! {
@@ -65,14 +72,15 @@ impl Network {
     /// ```rust
     /// use monacoin::network::constants::Network;
     ///
-    /// assert_eq!(Some(Network::Monacoin), Network::from_magic(0xD9B4BEF9));
+    /// assert_eq!(Some(Network::Monacoin), Network::from_magic(0xDBB6C0FB));
+    /// assert_eq!(None, Network::from_magic(0xD9B4BEF9)); // Bitcoin
     /// assert_eq!(None, Network::from_magic(0xFFFFFFFF));
     /// ```
     pub fn from_magic(magic: u32) -> Option<Network> {
         // Note: any new entries here must be added to `magic` below
         match magic {
-            0xD9B4BEF9 => Some(Network::Monacoin),
-            0x0709110B => Some(Network::MonacoinTestnet),
+            0xDBB6C0FB => Some(Network::Monacoin),
+            0xF1C8D2FD => Some(Network::MonacoinTestnet),
             0xDAB5BFFA => Some(Network::MonacoinRegtest),
             _ => None
         }
@@ -97,6 +105,167 @@ impl Network {
             Network::MonacoinRegtest => 0xDAB5BFFA,
         }
     }
+
+    /// Return the human-readable part of bech32 addresses on this network
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use monacoin::network::constants::Network;
+    ///
+    /// assert_eq!(Network::Monacoin.bech32_hrp(), "mona");
+    /// ```
+    pub fn bech32_hrp(self) -> &'static str {
+        match self {
+            Network::Monacoin => "mona",
+            Network::MonacoinTestnet => "tmona",
+            Network::MonacoinRegtest => "rmona",
+        }
+    }
+
+    /// Return the TCP port nodes of this network listen on by default
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use monacoin::network::constants::Network;
+    ///
+    /// assert_eq!(Network::Monacoin.default_port(), 9401);
+    /// ```
+    pub fn default_port(self) -> u16 {
+        match self {
+            Network::Monacoin => 9401,
+            Network::MonacoinTestnet => 19403,
+            Network::MonacoinRegtest => 20444,
+        }
+    }
+
+    /// Return the hostnames of the DNS seeders nodes of this network are
+    /// bootstrapped from
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use monacoin::network::constants::Network;
+    ///
+    /// assert!(Network::Monacoin.dns_seeds().contains(&"dnsseed.monacoin.org"));
+    /// assert!(Network::MonacoinRegtest.dns_seeds().is_empty());
+    /// ```
+    pub fn dns_seeds(&self) -> &'static [&'static str] {
+        match *self {
+            Network::Monacoin => &[
+                "dnsseed.monacoin.org",
+                "monacoin.seed.lapool.me",
+                "dnsseed.tamami-foundation.org",
+            ],
+            Network::MonacoinTestnet => &[
+                "testnet-dnsseed.monacoin.org",
+                "testnet-dnsseed.tamami-foundation.org",
+            ],
+            Network::MonacoinRegtest => &[],
+        }
+    }
+
+    /// Return the [ChainHash] of this network, the hash of its genesis block
+    pub fn chain_hash(self) -> ChainHash {
+        match self {
+            Network::Monacoin => ChainHash::MONACOIN,
+            Network::MonacoinTestnet => ChainHash::MONACOIN_TESTNET,
+            Network::MonacoinRegtest => ChainHash::MONACOIN_REGTEST,
+        }
+    }
+
+    /// Return the proof-of-work limit of this network, the highest target a
+    /// block may have. See [Params::pow_limit].
+    pub fn max_target(self) -> Uint256 {
+        Params::new(self).pow_limit
+    }
+}
+
+/// The magic bytes at the start of every network message, which identify the
+/// network it is meant for.
+///
+/// The bytes are in the order they appear on the wire, which is the
+/// little-endian encoding of [Network::magic].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Magic([u8; 4]);
+
+impl Magic {
+    /// The magic of [Network::Monacoin]
+    pub const MONACOIN: Magic = Magic([0xfb, 0xc0, 0xb6, 0xdb]);
+    /// The magic of [Network::MonacoinTestnet]
+    pub const MONACOIN_TESTNET: Magic = Magic([0xfd, 0xd2, 0xc8, 0xf1]);
+    /// The magic of [Network::MonacoinRegtest]
+    pub const MONACOIN_REGTEST: Magic = Magic([0xfa, 0xbf, 0xb5, 0xda]);
+
+    /// Creates a magic from the bytes as they appear on the wire.
+    pub fn from_bytes(bytes: [u8; 4]) -> Magic {
+        Magic(bytes)
+    }
+
+    /// The bytes of the magic as they appear on the wire.
+    pub fn to_bytes(self) -> [u8; 4] {
+        self.0
+    }
+
+    /// The network using this magic, if it is one of ours.
+    pub fn network(self) -> Option<Network> {
+        Network::from_magic(self.into())
+    }
+
+    /// Checks that `actual`, the magic of a received message, is this one.
+    ///
+    /// A mismatch is reported as [encode::Error::UnexpectedNetworkMagic] if
+    /// `actual` is the magic of another known network, which means the peer is
+    /// on the wrong network, and as [encode::Error::UnknownNetworkMagic]
+    /// otherwise.
+    pub fn check(self, actual: Magic) -> Result<(), encode::Error> {
+        if actual == self {
+            Ok(())
+        } else if actual.network().is_some() {
+            Err(encode::Error::UnexpectedNetworkMagic { expected: self, actual: actual })
+        } else {
+            Err(encode::Error::UnknownNetworkMagic { expected: self, actual: actual })
+        }
+    }
+}
+
+impl fmt::Display for Magic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}{:02x}{:02x}{:02x}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+impl From<Network> for Magic {
+    fn from(network: Network) -> Magic {
+        Magic::from(network.magic())
+    }
+}
+
+impl From<u32> for Magic {
+    fn from(magic: u32) -> Magic {
+        Magic(endian::u32_to_array_le(magic))
+    }
+}
+
+impl From<Magic> for u32 {
+    fn from(magic: Magic) -> u32 {
+        endian::slice_to_u32_le(&magic.0)
+    }
+}
+
+impl Encodable for Magic {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl Decodable for Magic {
+    #[inline]
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(Magic(Decodable::consensus_decode(d)?))
+    }
 }
 
 /// Flags to indicate which network services a node supports.
@@ -149,7 +318,7 @@ impl ServiceFlags {
     ///
     /// Returns itself.
     pub fn remove(&mut self, other: ServiceFlags) -> ServiceFlags {
-        self.0 ^= other.0;
+        self.0 &= !other.0;
         *self
     }
 
@@ -219,9 +388,9 @@ impl From<u64> for ServiceFlags {
     }
 }
 
-impl Into<u64> for ServiceFlags {
-    fn into(self) -> u64 {
-        self.0
+impl From<ServiceFlags> for u64 {
+    fn from(flags: ServiceFlags) -> Self {
+        flags.0
     }
 }
 
@@ -239,6 +408,20 @@ impl ops::BitOrAssign for ServiceFlags {
     }
 }
 
+impl ops::BitAnd for ServiceFlags {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        ServiceFlags(self.0 & rhs.0)
+    }
+}
+
+impl ops::BitAndAssign for ServiceFlags {
+    fn bitand_assign(&mut self, rhs: Self) {
+        self.0 &= rhs.0;
+    }
+}
+
 impl ops::BitXor for ServiceFlags {
     type Output = Self;
 
@@ -270,10 +453,143 @@ impl Decodable for ServiceFlags {
     }
 }
 
+/// A P2P protocol version, as announced in the `version` message.
+///
+/// The associated constants are the versions at which Monacoin Core started
+/// to understand the corresponding messages. They match Bitcoin Core's, as
+/// Monacoin never renumbered its protocol versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProtocolVersion(pub u32);
+
+impl ProtocolVersion {
+    /// Initial protocol version, used until the version handshake is done.
+    pub const INIT_PROTO_VERSION: ProtocolVersion = ProtocolVersion(209);
+    /// BIP31: `ping` carries a nonce and is answered with `pong`.
+    pub const BIP0031_VERSION: ProtocolVersion = ProtocolVersion(60000);
+    /// BIP111: bloom filter support has to be announced with NODE_BLOOM.
+    pub const NO_BLOOM_VERSION: ProtocolVersion = ProtocolVersion(70011);
+    /// BIP130: `sendheaders` is understood.
+    pub const SENDHEADERS_VERSION: ProtocolVersion = ProtocolVersion(70012);
+    /// BIP133: `feefilter` is understood.
+    pub const FEEFILTER_VERSION: ProtocolVersion = ProtocolVersion(70013);
+    /// BIP152: compact blocks (`sendcmpct`, `cmpctblock`, ...) are understood.
+    pub const SHORT_IDS_BLOCKS_VERSION: ProtocolVersion = ProtocolVersion(70014);
+    /// Peers do not ban for an invalid compact block.
+    pub const INVALID_CB_NO_BAN_VERSION: ProtocolVersion = ProtocolVersion(70015);
+    /// BIP339 `wtxidrelay`, and BIP155 `sendaddrv2` which is gated on the same version.
+    pub const WTXID_RELAY_VERSION: ProtocolVersion = ProtocolVersion(70016);
+
+    /// The protocol version both sides of a connection speak: the lower of the two.
+    pub fn negotiate(self, other: ProtocolVersion) -> ProtocolVersion {
+        ::std::cmp::min(self, other)
+    }
+}
+
+impl From<u32> for ProtocolVersion {
+    fn from(v: u32) -> Self {
+        ProtocolVersion(v)
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl Encodable for ProtocolVersion {
+    #[inline]
+    fn consensus_encode<S: io::Write>(
+        &self,
+        mut s: S,
+    ) -> Result<usize, encode::Error> {
+        self.0.consensus_encode(&mut s)
+    }
+}
+
+impl Decodable for ProtocolVersion {
+    #[inline]
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        Ok(ProtocolVersion(Decodable::consensus_decode(&mut d)?))
+    }
+}
+
+/// The optional protocol features usable on a connection, decided from both
+/// sides' protocol versions and the services the peer announced.
+///
+/// Sending a message the peer does not understand typically gets us
+/// disconnected, so this should be consulted before sending any message
+/// which is not part of the original protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NegotiatedFeatures {
+    /// The common protocol version of the connection
+    pub version: ProtocolVersion,
+    /// The services announced by the peer
+    pub their_services: ServiceFlags,
+}
+
+impl NegotiatedFeatures {
+    /// Work out the features of a connection from our protocol version and the
+    /// version and services from the peer's `version` message.
+    pub fn from_versions(ours: ProtocolVersion, theirs: ProtocolVersion, their_services: ServiceFlags) -> NegotiatedFeatures {
+        NegotiatedFeatures {
+            version: ours.negotiate(theirs),
+            their_services: their_services,
+        }
+    }
+
+    /// Whether the peer answers a `ping` with a `pong`.
+    pub fn expects_pong(&self) -> bool {
+        self.version > ProtocolVersion::BIP0031_VERSION
+    }
+
+    /// Whether a `sendheaders` message can be sent.
+    pub fn can_send_sendheaders(&self) -> bool {
+        self.version >= ProtocolVersion::SENDHEADERS_VERSION
+    }
+
+    /// Whether a `feefilter` message can be sent.
+    pub fn can_send_feefilter(&self) -> bool {
+        self.version >= ProtocolVersion::FEEFILTER_VERSION
+    }
+
+    /// Whether a `sendcmpct` message can be sent.
+    pub fn can_send_sendcmpct(&self) -> bool {
+        self.version >= ProtocolVersion::SHORT_IDS_BLOCKS_VERSION
+    }
+
+    /// Whether compact blocks with witnesses (`sendcmpct` version 2) can be used.
+    pub fn can_use_witness_compact_blocks(&self) -> bool {
+        self.can_send_sendcmpct() && self.their_services.has(ServiceFlags::WITNESS)
+    }
+
+    /// Whether a `sendaddrv2` message can be sent.
+    pub fn can_send_sendaddrv2(&self) -> bool {
+        self.version >= ProtocolVersion::WTXID_RELAY_VERSION
+    }
+
+    /// Whether a `wtxidrelay` message can be sent.
+    pub fn can_send_wtxidrelay(&self) -> bool {
+        self.version >= ProtocolVersion::WTXID_RELAY_VERSION
+    }
+
+    /// Whether bloom filter messages (`filterload`, `filteradd`, `filterclear`)
+    /// can be sent. Peers before BIP111 serve bloom filters without
+    /// announcing NODE_BLOOM.
+    pub fn can_send_filterload(&self) -> bool {
+        self.their_services.has(ServiceFlags::BLOOM) || self.version < ProtocolVersion::NO_BLOOM_VERSION
+    }
+
+    /// Whether witness data can be requested from the peer.
+    pub fn can_request_witness(&self) -> bool {
+        self.their_services.has(ServiceFlags::WITNESS)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Network, ServiceFlags};
-    use consensus::encode::{deserialize, serialize};
+    use super::{Magic, Network, ServiceFlags, ProtocolVersion, NegotiatedFeatures};
+    use consensus::encode::{self, deserialize, serialize};
 
     #[test]
     fn serialize_test() {
@@ -304,6 +620,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn magic_test() {
+        let networks = [Network::Monacoin, Network::MonacoinTestnet, Network::MonacoinRegtest];
+        let magics = [Magic::MONACOIN, Magic::MONACOIN_TESTNET, Magic::MONACOIN_REGTEST];
+        for (&network, &magic) in networks.iter().zip(magics.iter()) {
+            assert_eq!(Magic::from(network), magic);
+            assert_eq!(magic.network(), Some(network));
+            assert_eq!(u32::from(magic), network.magic());
+            assert_eq!(Network::from_magic(network.magic()), Some(network));
+            assert_eq!(serialize(&magic), serialize(&network.magic()));
+            assert_eq!(deserialize::<Magic>(&magic.to_bytes()).unwrap(), magic);
+            assert!(magic.check(magic).is_ok());
+        }
+        assert_eq!(Magic::MONACOIN.to_string(), "fbc0b6db");
+
+        let bitcoin = Magic::from_bytes([0xf9, 0xbe, 0xb4, 0xd9]);
+        assert_eq!(bitcoin.network(), None);
+        assert_eq!(Network::from_magic(u32::from(bitcoin)), None);
+        match Magic::MONACOIN.check(bitcoin) {
+            Err(encode::Error::UnknownNetworkMagic { .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match Magic::MONACOIN.check(Magic::MONACOIN_REGTEST) {
+            Err(encode::Error::UnexpectedNetworkMagic { .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
     #[test]
     fn string_test() {
         assert_eq!(Network::Monacoin.to_string(), "monacoin");
@@ -316,6 +660,13 @@ mod tests {
         assert!("fakenet".parse::<Network>().is_err());
     }
 
+    #[test]
+    fn default_port_test() {
+        assert_eq!(Network::Monacoin.default_port(), 9401);
+        assert_eq!(Network::MonacoinTestnet.default_port(), 19403);
+        assert_eq!(Network::MonacoinRegtest.default_port(), 20444);
+    }
+
     #[test]
     fn service_flags_test() {
         let all = [
@@ -354,6 +705,96 @@ mod tests {
         assert_eq!("ServiceFlags(NETWORK|BLOOM|WITNESS)", flag.to_string());
         let flag = ServiceFlags::WITNESS | 0xf0.into();
         assert_eq!("ServiceFlags(WITNESS|COMPACT_FILTERS|0xb0)", flag.to_string());
+        let flag = ServiceFlags::NETWORK | ServiceFlags::from(1 << 63);
+        assert_eq!("ServiceFlags(NETWORK|0x8000000000000000)", flag.to_string());
+        assert_eq!("ServiceFlags(0x1000000)", ServiceFlags::from(1 << 24).to_string());
     }
-}
 
+    #[test]
+    fn service_flags_remove_test() {
+        let mut flags = ServiceFlags::NETWORK | ServiceFlags::WITNESS;
+        // removing a flag which isn't set leaves the others alone
+        assert_eq!(flags.remove(ServiceFlags::BLOOM), ServiceFlags::NETWORK | ServiceFlags::WITNESS);
+        assert_eq!(flags.remove(ServiceFlags::WITNESS), ServiceFlags::NETWORK);
+        assert_eq!(flags ^ ServiceFlags::NETWORK_LIMITED, ServiceFlags::NETWORK);
+
+        let flags = ServiceFlags::NETWORK | ServiceFlags::WITNESS | ServiceFlags::NETWORK_LIMITED;
+        assert_eq!(flags & (ServiceFlags::WITNESS | ServiceFlags::BLOOM), ServiceFlags::WITNESS);
+        let mut masked = flags;
+        masked &= ServiceFlags::NETWORK_LIMITED;
+        assert_eq!(masked, ServiceFlags::NETWORK_LIMITED);
+    }
+
+    #[test]
+    fn service_flags_encoding_test() {
+        let flags = ServiceFlags::NETWORK | ServiceFlags::WITNESS | ServiceFlags::from(1 << 40);
+        assert_eq!(serialize(&flags), serialize(&0x0000_0100_0000_0009u64));
+        assert_eq!(serialize(&flags), vec![0x09, 0, 0, 0, 0, 0x01, 0, 0]);
+        assert_eq!(deserialize::<ServiceFlags>(&serialize(&flags)).unwrap(), flags);
+
+        let raw: u64 = flags.into();
+        assert_eq!(raw, 0x0000_0100_0000_0009);
+        assert_eq!(u64::from(flags), flags.as_u64());
+        assert_eq!(ServiceFlags::from(raw), flags);
+    }
+
+    #[test]
+    fn protocol_version_negotiate() {
+        let ours = ProtocolVersion(70015);
+        assert_eq!(ours.negotiate(ProtocolVersion(70012)), ProtocolVersion(70012));
+        assert_eq!(ours.negotiate(ProtocolVersion(70016)), ours);
+        assert_eq!(serialize(&ProtocolVersion(70015)), serialize(&70015u32));
+        assert_eq!(deserialize::<ProtocolVersion>(&[0x7f, 0x11, 0x01, 0x00]).unwrap(), ProtocolVersion(70015));
+        assert_eq!(ProtocolVersion(70015).to_string(), "70015");
+    }
+
+    #[test]
+    fn negotiated_features_test() {
+        // (their version, sendheaders, feefilter, sendcmpct, sendaddrv2/wtxidrelay, pong)
+        let table = [
+            (209, false, false, false, false, false),
+            (60000, false, false, false, false, false),
+            (60001, false, false, false, false, true),
+            (70011, false, false, false, false, true),
+            (70012, true, false, false, false, true),
+            (70013, true, true, false, false, true),
+            (70014, true, true, true, false, true),
+            (70015, true, true, true, false, true),
+            (70016, true, true, true, true, true),
+            (80000, true, true, true, true, true),
+        ];
+        for &(theirs, sendheaders, feefilter, sendcmpct, addrv2, pong) in table.iter() {
+            let features = NegotiatedFeatures::from_versions(ProtocolVersion(70016), ProtocolVersion(theirs), ServiceFlags::NONE);
+            assert_eq!(features.can_send_sendheaders(), sendheaders, "sendheaders at {}", theirs);
+            assert_eq!(features.can_send_feefilter(), feefilter, "feefilter at {}", theirs);
+            assert_eq!(features.can_send_sendcmpct(), sendcmpct, "sendcmpct at {}", theirs);
+            assert_eq!(features.can_send_sendaddrv2(), addrv2, "sendaddrv2 at {}", theirs);
+            assert_eq!(features.can_send_wtxidrelay(), addrv2, "wtxidrelay at {}", theirs);
+            assert_eq!(features.expects_pong(), pong, "pong at {}", theirs);
+
+            // our own version caps everything the same way
+            let reversed = NegotiatedFeatures::from_versions(ProtocolVersion(theirs), ProtocolVersion(70016), ServiceFlags::NONE);
+            assert_eq!(reversed, features);
+        }
+    }
+
+    #[test]
+    fn negotiated_features_services_test() {
+        // (their version, their services, filterload, witness compact blocks, witness)
+        let table = [
+            (70010, ServiceFlags::NONE, true, false, false),
+            (70011, ServiceFlags::NONE, false, false, false),
+            (70011, ServiceFlags::BLOOM, true, false, false),
+            (70013, ServiceFlags::WITNESS, false, false, true),
+            (70014, ServiceFlags::WITNESS, false, true, true),
+            (70015, ServiceFlags::NETWORK, false, false, false),
+            (70015, ServiceFlags::NETWORK | ServiceFlags::WITNESS | ServiceFlags::BLOOM, true, true, true),
+        ];
+        for &(theirs, services, filterload, cmpct_witness, witness) in table.iter() {
+            let features = NegotiatedFeatures::from_versions(ProtocolVersion(70015), ProtocolVersion(theirs), services);
+            assert_eq!(features.can_send_filterload(), filterload, "filterload at {} {}", theirs, services);
+            assert_eq!(features.can_use_witness_compact_blocks(), cmpct_witness, "witness cmpct at {} {}", theirs, services);
+            assert_eq!(features.can_request_witness(), witness, "witness at {} {}", theirs, services);
+        }
+    }
+}
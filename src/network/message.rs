@@ -25,18 +25,49 @@ use std::io::Cursor;
 
 use blockdata::block;
 use blockdata::transaction;
-use network::address::Address;
+use network::address::{Address, AddrV2Message};
 use network::message_network;
 use network::message_blockdata;
 use network::message_filter;
-use consensus::encode::{CheckedData, Decodable, Encodable, VarInt};
-use consensus::{encode, serialize};
+use network::message_bloom;
+use network::message_compact_blocks;
+use hashes::{sha256d, Hash};
+
+use consensus::encode::{Decodable, Encodable, VarInt, WriteExt};
+use consensus::encode;
 use consensus::encode::MAX_VEC_SIZE;
 
-/// Serializer for command string
+/// Largest message payload accepted by default, in bytes
+pub const MAX_MSG_SIZE: usize = 32 * 1024 * 1024;
+/// Most inventory items in a single `inv`, `getdata` or `notfound` message
+pub const MAX_INV_ENTRIES: u64 = 50_000;
+/// Most addresses a peer sends in a single `addr` message
+pub const MAX_ADDR_ENTRIES: u64 = 1000;
+/// Most addresses a peer sends in a single `addrv2` message
+pub const MAX_ADDR_V2_ENTRIES: u64 = 1000;
+/// Most headers in a single `headers` message
+pub const MAX_HEADERS_ENTRIES: u64 = 2000;
+
+/// The maximum number of bytes of a command string
+pub const COMMAND_LEN: usize = 12;
+
+/// A P2P message command: at most 12 ASCII characters without NUL bytes
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct CommandString(Cow<'static, str>);
 
+impl CommandString {
+    /// Check a command, which must consist of at most 12 ASCII characters
+    /// and may not contain NUL bytes.
+    pub fn try_from<S: Into<Cow<'static, str>>>(s: S) -> Result<CommandString, CommandStringError> {
+        let cow = s.into();
+        if cow.len() > COMMAND_LEN || !cow.is_ascii() || cow.contains('\0') {
+            Err(CommandStringError { cow: cow })
+        } else {
+            Ok(CommandString(cow))
+        }
+    }
+}
+
 impl fmt::Display for CommandString {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str(self.0.as_ref())
@@ -44,14 +75,18 @@ impl fmt::Display for CommandString {
 }
 
 impl From<&'static str> for CommandString {
+    /// Panics if the command isn't valid; use [CommandString::try_from] for
+    /// untrusted input.
     fn from(f: &'static str) -> Self {
-        CommandString(f.into())
+        CommandString::try_from(f).expect("invalid command string")
     }
 }
 
 impl From<String> for CommandString {
+    /// Panics if the command isn't valid; use [CommandString::try_from] for
+    /// untrusted input.
     fn from(f: String) -> Self {
-        CommandString(f.into())
+        CommandString::try_from(f).expect("invalid command string")
     }
 }
 
@@ -67,9 +102,9 @@ impl Encodable for CommandString {
         &self,
         s: S,
     ) -> Result<usize, encode::Error> {
-        let mut rawbytes = [0u8; 12];
+        let mut rawbytes = [0u8; COMMAND_LEN];
         let strbytes = self.0.as_bytes();
-        if strbytes.len() > 12 {
+        if strbytes.len() > COMMAND_LEN {
             return Err(encode::Error::UnrecognizedNetworkCommand(self.0.clone().into_owned()));
         }
         rawbytes[..strbytes.len()].clone_from_slice(&strbytes[..]);
@@ -80,13 +115,36 @@ impl Encodable for CommandString {
 impl Decodable for CommandString {
     #[inline]
     fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
-        let rawbytes: [u8; 12] = Decodable::consensus_decode(d)?;
-        let rv = iter::FromIterator::from_iter(
-            rawbytes
-                .iter()
-                .filter_map(|&u| if u > 0 { Some(u as char) } else { None })
-        );
-        Ok(CommandString(rv))
+        let rawbytes: [u8; COMMAND_LEN] = Decodable::consensus_decode(d)?;
+        let len = rawbytes.iter().position(|&u| u == 0).unwrap_or(COMMAND_LEN);
+        if rawbytes[len..].iter().any(|&u| u != 0) {
+            return Err(encode::Error::ParseFailed("command string has bytes after its NUL padding"));
+        }
+        if !rawbytes[..len].is_ascii() {
+            return Err(encode::Error::ParseFailed("command string is not ASCII"));
+        }
+        let rv = iter::FromIterator::from_iter(rawbytes[..len].iter().map(|&u| u as char));
+        Ok(CommandString(Cow::Owned(rv)))
+    }
+}
+
+/// Error returned when a command string is invalid.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CommandStringError {
+    cow: Cow<'static, str>,
+}
+
+impl fmt::Display for CommandStringError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the command string {:?} is not at most {} ASCII characters without NUL bytes",
+            self.cow, COMMAND_LEN)
+    }
+}
+
+#[allow(deprecated)]
+impl ::std::error::Error for CommandStringError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
     }
 }
 
@@ -138,7 +196,12 @@ pub enum NetworkMessage {
     Ping(u64),
     /// `pong`
     Pong(u64),
-    // TODO: bloom filtering
+    /// BIP37 `filterload`
+    FilterLoad(message_bloom::FilterLoad),
+    /// BIP37 `filteradd`
+    FilterAdd(message_bloom::FilterAdd),
+    /// BIP37 `filterclear`
+    FilterClear,
     /// BIP157 getcfilters
     GetCFilters(message_filter::GetCFilters),
     /// BIP157 cfilter
@@ -151,17 +214,40 @@ pub enum NetworkMessage {
     GetCFCheckpt(message_filter::GetCFCheckpt),
     /// BIP157 cfcheckpt
     CFCheckpt(message_filter::CFCheckpt),
+    /// BIP152 sendcmpct
+    SendCmpct(message_compact_blocks::SendCmpct),
+    /// BIP152 cmpctblock
+    CmpctBlock(message_compact_blocks::CmpctBlock),
+    /// BIP152 getblocktxn
+    GetBlockTxn(message_compact_blocks::GetBlockTxn),
+    /// BIP152 blocktxn
+    BlockTxn(message_compact_blocks::BlockTxn),
     /// `alert`
     Alert(Vec<u8>),
     /// `reject`
     Reject(message_network::Reject),
     /// `feefilter`
     FeeFilter(i64),
+    /// BIP155 `addrv2`
+    AddrV2(Vec<AddrV2Message>),
+    /// BIP155 `sendaddrv2`, sent between `version` and `verack` if
+    /// `NegotiatedFeatures::can_send_sendaddrv2`
+    SendAddrV2,
+    /// BIP339 `wtxidrelay`, sent between `version` and `verack` if
+    /// `NegotiatedFeatures::can_send_wtxidrelay`
+    WtxidRelay,
+    /// Any message with a command not understood by this library
+    Unknown {
+        /// The command of this message
+        command: CommandString,
+        /// The raw payload of this message
+        payload: Vec<u8>,
+    },
 }
 
 impl NetworkMessage {
     /// Return the message command. This is useful for debug outputs.
-    pub fn cmd(&self) -> &'static str {
+    pub fn cmd(&self) -> &str {
         match *self {
             NetworkMessage::Version(_) => "version",
             NetworkMessage::Verack     => "verack",
@@ -179,27 +265,41 @@ impl NetworkMessage {
             NetworkMessage::GetAddr    => "getaddr",
             NetworkMessage::Ping(_)    => "ping",
             NetworkMessage::Pong(_)    => "pong",
+            NetworkMessage::FilterLoad(_) => "filterload",
+            NetworkMessage::FilterAdd(_) => "filteradd",
+            NetworkMessage::FilterClear => "filterclear",
             NetworkMessage::GetCFilters(_) => "getcfilters",
             NetworkMessage::CFilter(_) => "cfilter",
             NetworkMessage::GetCFHeaders(_) => "getcfheaders",
             NetworkMessage::CFHeaders(_) => "cfheaders",
             NetworkMessage::GetCFCheckpt(_) => "getcfcheckpt",
             NetworkMessage::CFCheckpt(_) => "cfcheckpt",
+            NetworkMessage::SendCmpct(_) => "sendcmpct",
+            NetworkMessage::CmpctBlock(_) => "cmpctblock",
+            NetworkMessage::GetBlockTxn(_) => "getblocktxn",
+            NetworkMessage::BlockTxn(_) => "blocktxn",
             NetworkMessage::Alert(_)    => "alert",
             NetworkMessage::Reject(_)    => "reject",
             NetworkMessage::FeeFilter(_) => "feefilter",
+            NetworkMessage::AddrV2(_)  => "addrv2",
+            NetworkMessage::SendAddrV2 => "sendaddrv2",
+            NetworkMessage::WtxidRelay => "wtxidrelay",
+            NetworkMessage::Unknown { ref command, .. } => command.as_ref(),
         }
     }
 
     /// Return the CommandString for the message command.
     pub fn command(&self) -> CommandString {
-        self.cmd().into()
+        match *self {
+            NetworkMessage::Unknown { ref command, .. } => command.clone(),
+            _ => CommandString(Cow::Owned(self.cmd().to_owned())),
+        }
     }
 }
 
 impl RawNetworkMessage {
     /// Return the message command. This is useful for debug outputs.
-    pub fn cmd(&self) -> &'static str {
+    pub fn cmd(&self) -> &str {
         self.payload.cmd()
     }
 
@@ -227,41 +327,71 @@ impl<'a> Encodable for HeaderSerializationWrapper<'a> {
     }
 }
 
+impl NetworkMessage {
+    /// Encode the payload of the message, without header.
+    fn encode_payload<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        match *self {
+            NetworkMessage::Version(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::Addr(ref dat)    => dat.consensus_encode(s),
+            NetworkMessage::Inv(ref dat)     => dat.consensus_encode(s),
+            NetworkMessage::GetData(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::NotFound(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::GetBlocks(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::GetHeaders(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::Tx(ref dat)      => dat.consensus_encode(s),
+            NetworkMessage::Block(ref dat)   => dat.consensus_encode(s),
+            NetworkMessage::Headers(ref dat) => HeaderSerializationWrapper(dat).consensus_encode(s),
+            NetworkMessage::Ping(ref dat)    => dat.consensus_encode(s),
+            NetworkMessage::Pong(ref dat)    => dat.consensus_encode(s),
+            NetworkMessage::FilterLoad(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::FilterAdd(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::GetCFilters(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::CFilter(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::GetCFHeaders(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::CFHeaders(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::GetCFCheckpt(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::CFCheckpt(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::SendCmpct(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::CmpctBlock(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::GetBlockTxn(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::BlockTxn(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::Alert(ref dat)    => dat.consensus_encode(s),
+            NetworkMessage::Reject(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::FeeFilter(ref data) => data.consensus_encode(s),
+            NetworkMessage::AddrV2(ref dat) => dat.consensus_encode(s),
+            NetworkMessage::Unknown { ref payload, .. } => {
+                s.emit_slice(payload)?;
+                Ok(payload.len())
+            }
+            NetworkMessage::Verack
+            | NetworkMessage::SendHeaders
+            | NetworkMessage::MemPool
+            | NetworkMessage::GetAddr
+            | NetworkMessage::FilterClear
+            | NetworkMessage::SendAddrV2
+            | NetworkMessage::WtxidRelay => Ok(0),
+        }
+    }
+}
+
 impl Encodable for RawNetworkMessage {
     fn consensus_encode<S: io::Write>(
         &self,
         mut s: S,
     ) -> Result<usize, encode::Error> {
+        // The checksum precedes the payload, so the payload is encoded twice,
+        // first into the hash engine, rather than into a temporary buffer.
+        let mut engine = sha256d::Hash::engine();
+        let payload_len = self.payload.encode_payload(&mut engine)?;
+        let checksum = sha256d::Hash::from_engine(engine);
+
         let mut len = 0;
         len += self.magic.consensus_encode(&mut s)?;
         len += self.command().consensus_encode(&mut s)?;
-        len += CheckedData(match self.payload {
-            NetworkMessage::Version(ref dat) => serialize(dat),
-            NetworkMessage::Addr(ref dat)    => serialize(dat),
-            NetworkMessage::Inv(ref dat)     => serialize(dat),
-            NetworkMessage::GetData(ref dat) => serialize(dat),
-            NetworkMessage::NotFound(ref dat) => serialize(dat),
-            NetworkMessage::GetBlocks(ref dat) => serialize(dat),
-            NetworkMessage::GetHeaders(ref dat) => serialize(dat),
-            NetworkMessage::Tx(ref dat)      => serialize(dat),
-            NetworkMessage::Block(ref dat)   => serialize(dat),
-            NetworkMessage::Headers(ref dat) => serialize(&HeaderSerializationWrapper(dat)),
-            NetworkMessage::Ping(ref dat)    => serialize(dat),
-            NetworkMessage::Pong(ref dat)    => serialize(dat),
-            NetworkMessage::GetCFilters(ref dat) => serialize(dat),
-            NetworkMessage::CFilter(ref dat) => serialize(dat),
-            NetworkMessage::GetCFHeaders(ref dat) => serialize(dat),
-            NetworkMessage::CFHeaders(ref dat) => serialize(dat),
-            NetworkMessage::GetCFCheckpt(ref dat) => serialize(dat),
-            NetworkMessage::CFCheckpt(ref dat) => serialize(dat),
-            NetworkMessage::Alert(ref dat)    => serialize(dat),
-            NetworkMessage::Reject(ref dat) => serialize(dat),
-            NetworkMessage::FeeFilter(ref data) => serialize(data),
-            NetworkMessage::Verack
-            | NetworkMessage::SendHeaders
-            | NetworkMessage::MemPool
-            | NetworkMessage::GetAddr => vec![],
-        }).consensus_encode(&mut s)?;
+        len += (payload_len as u32).consensus_encode(&mut s)?;
+        s.emit_slice(&checksum[0..4])?;
+        len += 4;
+        len += self.payload.encode_payload(&mut s)?;
         Ok(len)
     }
 }
@@ -272,13 +402,16 @@ impl Decodable for HeaderDeserializationWrapper {
     #[inline]
     fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
         let len = VarInt::consensus_decode(&mut d)?.0;
+        if len > MAX_HEADERS_ENTRIES {
+            return Err(encode::Error::ParseFailed("Too many headers in headers message"));
+        }
         let byte_size = (len as usize)
                             .checked_mul(mem::size_of::<block::BlockHeader>())
                             .ok_or(encode::Error::ParseFailed("Invalid length"))?;
         if byte_size > MAX_VEC_SIZE {
             return Err(encode::Error::OversizedVectorAllocation { requested: byte_size, max: MAX_VEC_SIZE })
         }
-        let mut ret = Vec::with_capacity(len as usize);
+        let mut ret = Vec::with_capacity(encode::prealloc_capacity::<block::BlockHeader>(len));
         for _ in 0..len {
             ret.push(Decodable::consensus_decode(&mut d)?);
             if u8::consensus_decode(&mut d)? != 0u8 {
@@ -289,20 +422,39 @@ impl Decodable for HeaderDeserializationWrapper {
     }
 }
 
-impl Decodable for RawNetworkMessage {
-    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+/// Decode a vector with at most `max` entries, as allowed by the protocol for its message.
+fn decode_limited<T: Decodable, D: io::Read>(mut d: D, max: u64, err: &'static str) -> Result<Vec<T>, encode::Error> {
+    let len = VarInt::consensus_decode(&mut d)?.0;
+    if len > max {
+        return Err(encode::Error::ParseFailed(err));
+    }
+    let mut ret = Vec::with_capacity(encode::prealloc_capacity::<T>(len));
+    for _ in 0..len {
+        ret.push(Decodable::consensus_decode(&mut d)?);
+    }
+    Ok(ret)
+}
+
+impl RawNetworkMessage {
+    /// Decode a message whose payload is at most `max_payload_size` bytes.
+    /// The size and checksum are checked before the payload is parsed.
+    pub fn consensus_decode_with_max_size<D: io::Read>(mut d: D, max_payload_size: usize) -> Result<Self, encode::Error> {
         let magic = Decodable::consensus_decode(&mut d)?;
         let cmd = CommandString::consensus_decode(&mut d)?.0;
-        let raw_payload = CheckedData::consensus_decode(&mut d)?.0;
+        let len = u32::consensus_decode(&mut d)? as usize;
+        if len > max_payload_size {
+            return Err(encode::Error::OversizedPayload { requested: len, max: max_payload_size });
+        }
+        let raw_payload = encode::read_checked(&mut d, len)?;
 
         let mut mem_d = Cursor::new(raw_payload);
         let payload = match &cmd[..] {
             "version" => NetworkMessage::Version(Decodable::consensus_decode(&mut mem_d)?),
             "verack"  => NetworkMessage::Verack,
-            "addr"    => NetworkMessage::Addr(Decodable::consensus_decode(&mut mem_d)?),
-            "inv"     => NetworkMessage::Inv(Decodable::consensus_decode(&mut mem_d)?),
-            "getdata" => NetworkMessage::GetData(Decodable::consensus_decode(&mut mem_d)?),
-            "notfound" => NetworkMessage::NotFound(Decodable::consensus_decode(&mut mem_d)?),
+            "addr"    => NetworkMessage::Addr(decode_limited(&mut mem_d, MAX_ADDR_ENTRIES, "Too many addresses in addr message")?),
+            "inv"     => NetworkMessage::Inv(decode_limited(&mut mem_d, MAX_INV_ENTRIES, "Too many items in inv message")?),
+            "getdata" => NetworkMessage::GetData(decode_limited(&mut mem_d, MAX_INV_ENTRIES, "Too many items in getdata message")?),
+            "notfound" => NetworkMessage::NotFound(decode_limited(&mut mem_d, MAX_INV_ENTRIES, "Too many items in notfound message")?),
             "getblocks" => NetworkMessage::GetBlocks(Decodable::consensus_decode(&mut mem_d)?),
             "getheaders" => NetworkMessage::GetHeaders(Decodable::consensus_decode(&mut mem_d)?),
             "mempool" => NetworkMessage::MemPool,
@@ -314,6 +466,9 @@ impl Decodable for RawNetworkMessage {
             "getaddr" => NetworkMessage::GetAddr,
             "ping"    => NetworkMessage::Ping(Decodable::consensus_decode(&mut mem_d)?),
             "pong"    => NetworkMessage::Pong(Decodable::consensus_decode(&mut mem_d)?),
+            "filterload" => NetworkMessage::FilterLoad(Decodable::consensus_decode(&mut mem_d)?),
+            "filteradd" => NetworkMessage::FilterAdd(Decodable::consensus_decode(&mut mem_d)?),
+            "filterclear" => NetworkMessage::FilterClear,
             "tx"      => NetworkMessage::Tx(Decodable::consensus_decode(&mut mem_d)?),
             "getcfilters" => NetworkMessage::GetCFilters(Decodable::consensus_decode(&mut mem_d)?),
             "cfilter" => NetworkMessage::CFilter(Decodable::consensus_decode(&mut mem_d)?),
@@ -321,10 +476,20 @@ impl Decodable for RawNetworkMessage {
             "cfheaders" => NetworkMessage::CFHeaders(Decodable::consensus_decode(&mut mem_d)?),
             "getcfcheckpt" => NetworkMessage::GetCFCheckpt(Decodable::consensus_decode(&mut mem_d)?),
             "cfcheckpt" => NetworkMessage::CFCheckpt(Decodable::consensus_decode(&mut mem_d)?),
+            "sendcmpct" => NetworkMessage::SendCmpct(Decodable::consensus_decode(&mut mem_d)?),
+            "cmpctblock" => NetworkMessage::CmpctBlock(Decodable::consensus_decode(&mut mem_d)?),
+            "getblocktxn" => NetworkMessage::GetBlockTxn(Decodable::consensus_decode(&mut mem_d)?),
+            "blocktxn" => NetworkMessage::BlockTxn(Decodable::consensus_decode(&mut mem_d)?),
             "reject" => NetworkMessage::Reject(Decodable::consensus_decode(&mut mem_d)?),
             "alert"   => NetworkMessage::Alert(Decodable::consensus_decode(&mut mem_d)?),
             "feefilter" => NetworkMessage::FeeFilter(Decodable::consensus_decode(&mut mem_d)?),
-            _ => return Err(encode::Error::UnrecognizedNetworkCommand(cmd.into_owned())),
+            "addrv2" => NetworkMessage::AddrV2(decode_limited(&mut mem_d, MAX_ADDR_V2_ENTRIES, "Too many addresses in addrv2 message")?),
+            "sendaddrv2" => NetworkMessage::SendAddrV2,
+            "wtxidrelay" => NetworkMessage::WtxidRelay,
+            _ => NetworkMessage::Unknown {
+                command: CommandString(cmd.clone()),
+                payload: mem_d.into_inner(),
+            },
         };
         Ok(RawNetworkMessage {
             magic: magic,
@@ -333,20 +498,31 @@ impl Decodable for RawNetworkMessage {
     }
 }
 
+impl Decodable for RawNetworkMessage {
+    #[inline]
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        RawNetworkMessage::consensus_decode_with_max_size(d, MAX_MSG_SIZE)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::io;
-    use super::{RawNetworkMessage, NetworkMessage, CommandString};
-    use network::constants::ServiceFlags;
-    use consensus::encode::{Encodable, deserialize, deserialize_partial, serialize};
+    use std::net::Ipv4Addr;
+    use super::{RawNetworkMessage, NetworkMessage, CommandString, MAX_INV_ENTRIES, MAX_MSG_SIZE};
+    use network::constants::{Network, ServiceFlags};
+    use consensus::encode::{self, Encodable, VarInt, deserialize, deserialize_partial, serialize};
     use hashes::hex::FromHex;
     use hashes::sha256d::Hash;
     use hashes::Hash as HashTrait;
-    use network::address::Address;
+    use network::address::{Address, AddrV2, AddrV2Message};
     use super::message_network::{Reject, RejectReason, VersionMessage};
     use network::message_blockdata::{Inventory, GetBlocksMessage, GetHeadersMessage};
     use blockdata::block::{Block, BlockHeader};
     use network::message_filter::{GetCFilters, CFilter, GetCFHeaders, CFHeaders, GetCFCheckpt, CFCheckpt};
+    use network::message_bloom::{BloomFlags, FilterAdd, FilterLoad};
+    use network::message_compact_blocks::{BlockTxn, CmpctBlock, GetBlockTxn, SendCmpct};
+    use util::bip152::{BlockTransactions, BlockTransactionsRequest, HeaderAndShortIds};
     use blockdata::transaction::Transaction;
 
     fn hash(slice: [u8;32]) -> Hash {
@@ -372,21 +548,31 @@ mod test {
             NetworkMessage::GetHeaders(GetHeadersMessage::new(vec![hash([10u8; 32]).into(), hash([40u8; 32]).into()], hash([50u8; 32]).into())),
             NetworkMessage::MemPool,
             NetworkMessage::Tx(tx),
-            NetworkMessage::Block(block),
+            NetworkMessage::Block(block.clone()),
             NetworkMessage::Headers(vec![header]),
             NetworkMessage::SendHeaders,
             NetworkMessage::GetAddr,
             NetworkMessage::Ping(15),
             NetworkMessage::Pong(23),
+            NetworkMessage::FilterLoad(FilterLoad {filter: vec![3, 0x61, 0x4e], hash_funcs: 5, tweak: 2, flags: BloomFlags::All}),
+            NetworkMessage::FilterAdd(FilterAdd {data: vec![1, 2, 3]}),
+            NetworkMessage::FilterClear,
             NetworkMessage::GetCFilters(GetCFilters{filter_type: 2, start_height: 52, stop_hash: hash([42u8; 32]).into()}),
             NetworkMessage::CFilter(CFilter{filter_type: 7, block_hash: hash([25u8; 32]).into(), filter: vec![1,2,3]}),
             NetworkMessage::GetCFHeaders(GetCFHeaders{filter_type: 4, start_height: 102, stop_hash: hash([47u8; 32]).into()}),
             NetworkMessage::CFHeaders(CFHeaders{filter_type: 13, stop_hash: hash([53u8; 32]).into(), previous_filter: hash([12u8; 32]).into(), filter_hashes: vec![hash([4u8; 32]).into(), hash([12u8; 32]).into()]}),
             NetworkMessage::GetCFCheckpt(GetCFCheckpt{filter_type: 17, stop_hash: hash([25u8; 32]).into()}),
             NetworkMessage::CFCheckpt(CFCheckpt{filter_type: 27, stop_hash: hash([77u8; 32]).into(), filter_headers: vec![hash([3u8; 32]).into(), hash([99u8; 32]).into()]}),
+            NetworkMessage::SendCmpct(SendCmpct{send_compact: true, version: 2}),
+            NetworkMessage::CmpctBlock(CmpctBlock{compact_block: HeaderAndShortIds::from_block(&block, 0x1234, 2, &[1]).unwrap()}),
+            NetworkMessage::GetBlockTxn(GetBlockTxn{txs_request: BlockTransactionsRequest{block_hash: hash([11u8; 32]).into(), indexes: vec![0, 1, 5, 6]}}),
+            NetworkMessage::BlockTxn(BlockTxn{transactions: BlockTransactions{block_hash: hash([11u8; 32]).into(), transactions: vec![block.txdata[1].clone()]}}),
             NetworkMessage::Alert(vec![45,66,3,2,6,8,9,12,3,130]),
-            NetworkMessage::Reject(Reject{message: "Test reject".into(), ccode: RejectReason::Duplicate, reason: "Cause".into(), hash: hash([255u8; 32])}),
+            NetworkMessage::Reject(Reject{message: "Test reject".into(), ccode: RejectReason::Duplicate, reason: "Cause".into(), hash: Some(hash([255u8; 32]))}),
             NetworkMessage::FeeFilter(1000),
+            NetworkMessage::AddrV2(vec![AddrV2Message{services: ServiceFlags::NETWORK, time: 0, port: 8333, addr: AddrV2::Ipv4(Ipv4Addr::new(127, 0, 0, 1))}]),
+            NetworkMessage::SendAddrV2,
+            NetworkMessage::WtxidRelay,
         ];
 
         for msg in msgs {
@@ -396,6 +582,28 @@ mod test {
 
     }
 
+    #[test]
+    fn monacoin_magic_round_trip_test() {
+        let magic = Network::Monacoin.magic();
+        let msgs = vec![
+            NetworkMessage::FeeFilter(1000),
+            NetworkMessage::Ping(0x0123_4567_89ab_cdef),
+            NetworkMessage::Pong(0x0123_4567_89ab_cdef),
+            NetworkMessage::Reject(Reject{message: "block".into(), ccode: RejectReason::Invalid, reason: "bad-txnmrklroot".into(), hash: Some(hash([7u8; 32]))}),
+            NetworkMessage::Reject(Reject{message: "version".into(), ccode: RejectReason::Obsolete, reason: "Version must be 31800 or greater".into(), hash: None}),
+        ];
+        for msg in msgs {
+            let raw_msg = RawNetworkMessage {magic: magic, payload: msg};
+            let raw = serialize(&raw_msg);
+            assert_eq!(&raw[..4], &[0xfb, 0xc0, 0xb6, 0xdb]);
+            assert_eq!(deserialize::<RawNetworkMessage>(&raw).unwrap(), raw_msg);
+        }
+
+        let feefilter = RawNetworkMessage {magic: magic, payload: NetworkMessage::FeeFilter(1000)};
+        assert_eq!(feefilter.cmd(), "feefilter");
+        assert_eq!(serialize(&feefilter)[24..], [0xe8, 0x03, 0, 0, 0, 0, 0, 0]);
+    }
+
     #[test]
     fn serialize_commandstring_test() {
         let cs = CommandString("Andrew".into());
@@ -406,6 +614,17 @@ mod test {
         assert!(CommandString("AndrewAndrewA".into()).consensus_encode(&mut encoder).is_err());
     }
 
+    #[test]
+    fn commandstring_try_from_test() {
+        assert_eq!(CommandString::try_from("AndrewAndrew").unwrap().as_ref(), "AndrewAndrew");
+        assert_eq!(CommandString::try_from(String::from("wtxidrelay")).unwrap(), "wtxidrelay".into());
+        assert!(CommandString::try_from("AndrewAndrewA").is_err());
+        assert!(CommandString::try_from("And\0rew").is_err());
+        assert!(CommandString::try_from("Andr\u{e9}").is_err());
+        assert_eq!(CommandString::try_from("AndrewAndrewA").unwrap_err().to_string(),
+            "the command string \"AndrewAndrewA\" is not at most 12 ASCII characters without NUL bytes");
+    }
+
     #[test]
     fn deserialize_commandstring_test() {
         let cs: Result<CommandString, _> = deserialize(&[0x41u8, 0x6e, 0x64, 0x72, 0x65, 0x77, 0, 0, 0, 0, 0, 0]);
@@ -415,6 +634,53 @@ mod test {
 
         let short_cs: Result<CommandString, _> = deserialize(&[0x41u8, 0x6e, 0x64, 0x72, 0x65, 0x77, 0, 0, 0, 0, 0]);
         assert!(short_cs.is_err());
+
+        // bytes after the padding and non-ASCII commands are malformed
+        assert!(deserialize::<CommandString>(&[0x41u8, 0x6e, 0, 0x72, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(deserialize::<CommandString>(&[0x41u8, 0xe9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        let full: CommandString = deserialize(b"AndrewAndrew").unwrap();
+        assert_eq!(full.as_ref(), "AndrewAndrew");
+    }
+
+    #[test]
+    fn unknown_message_stream_test() {
+        let inv = |n: u8| RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::Inv(vec![Inventory::Transaction(hash([n; 32]).into())]),
+        };
+        let sendtxrcncl = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::Unknown { command: "sendtxrcncl".into(), payload: vec![1, 0, 0, 0, 2, 3, 4, 5, 6, 7, 8, 9] },
+        };
+        let garbage = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::Unknown { command: "~g4rb@ge~".into(), payload: vec![0xde, 0xad, 0xbe, 0xef] },
+        };
+        let msgs = vec![inv(1), sendtxrcncl, inv(2), garbage, inv(3)];
+
+        let mut stream = vec![];
+        for msg in &msgs {
+            stream.extend(serialize(msg));
+        }
+        // the raw bytes of an unknown message are reproduced by its encoding
+        let raw_garbage = Vec::from_hex("f9beb4d97e673472624067657e00000004000000").unwrap();
+        assert!(serialize(&msgs[3]).starts_with(&raw_garbage));
+
+        let mut decoded = vec![];
+        let mut pos = 0;
+        while pos < stream.len() {
+            let (msg, consumed) = deserialize_partial::<RawNetworkMessage>(&stream[pos..]).unwrap();
+            decoded.push(msg);
+            pos += consumed;
+        }
+        assert_eq!(decoded, msgs);
+        assert_eq!(decoded[1].cmd(), "sendtxrcncl");
+        assert_eq!(decoded[3].command(), "~g4rb@ge~".into());
+
+        // a bad checksum is still an error
+        let mut bad = serialize(&msgs[3]);
+        *bad.last_mut().unwrap() ^= 1;
+        assert!(deserialize::<RawNetworkMessage>(&bad).is_err());
     }
 
     #[test]
@@ -451,6 +717,75 @@ mod test {
                                   0x00, 0x00, 0x00, 0x00, 0x5d, 0xf6, 0xe0, 0xe2]);
     }
 
+    #[test]
+    fn addrv2_limit_test() {
+        let addr = AddrV2Message{services: ServiceFlags::NONE, time: 0, port: 8333, addr: AddrV2::Ipv4(Ipv4Addr::new(127, 0, 0, 1))};
+        let msg = RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::AddrV2(vec![addr.clone(); 1000]) };
+        assert_eq!(deserialize::<RawNetworkMessage>(&serialize(&msg)).unwrap(), msg);
+        let msg = RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::AddrV2(vec![addr; 1001]) };
+        assert!(deserialize::<RawNetworkMessage>(&serialize(&msg)).is_err());
+    }
+
+    #[test]
+    fn payload_size_test() {
+        let header = |len: u32| {
+            let mut raw = Vec::from_hex("f9beb4d9696e76000000000000000000").unwrap();
+            raw.extend(serialize(&len));
+            raw.extend(&[0x5d, 0xf6, 0xe0, 0xe2, 0x01, 0x02]);
+            raw
+        };
+        match deserialize::<RawNetworkMessage>(&header(0xffff_ffff)) {
+            Err(encode::Error::OversizedPayload { requested, max }) => {
+                assert_eq!(requested, 0xffff_ffff);
+                assert_eq!(max, MAX_MSG_SIZE);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        // a truncated payload claiming the maximum size is an unexpected EOF
+        match deserialize::<RawNetworkMessage>(&header(MAX_MSG_SIZE as u32)) {
+            Err(encode::Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let msg = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::Inv(vec![Inventory::Transaction(hash([1u8; 32]).into()); 10]),
+        };
+        let raw = serialize(&msg);
+        assert_eq!(RawNetworkMessage::consensus_decode_with_max_size(&raw[..], 361).unwrap(), msg);
+        match RawNetworkMessage::consensus_decode_with_max_size(&raw[..], 360) {
+            Err(encode::Error::OversizedPayload { requested: 361, max: 360 }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let mut bad_checksum = raw.clone();
+        bad_checksum[20] ^= 0xff;
+        match deserialize::<RawNetworkMessage>(&bad_checksum) {
+            Err(encode::Error::InvalidChecksum { .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn entry_limits_test() {
+        // only the length prefix is checked, so no entries need to follow it
+        for &(command, max) in &[("inv", 50_000), ("getdata", 50_000), ("notfound", 50_000),
+                                  ("addr", 1000), ("addrv2", 1000), ("headers", 2000)] {
+            let msg = RawNetworkMessage {
+                magic: 0xd9b4bef9,
+                payload: NetworkMessage::Unknown { command: command.into(), payload: serialize(&VarInt(max + 1)) },
+            };
+            match deserialize::<RawNetworkMessage>(&serialize(&msg)) {
+                Err(encode::Error::ParseFailed(_)) => {}
+                res => panic!("unexpected result for {}: {:?}", command, res),
+            }
+        }
+
+        let inv = vec![Inventory::Block(hash([2u8; 32]).into()); MAX_INV_ENTRIES as usize];
+        let msg = RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::GetData(inv) };
+        assert_eq!(deserialize::<RawNetworkMessage>(&serialize(&msg)).unwrap(), msg);
+    }
+
     #[test]
     fn deserialize_getaddr_test() {
         let msg = deserialize(
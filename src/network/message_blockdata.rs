@@ -23,8 +23,17 @@ use std::io;
 use hashes::sha256d;
 
 use network::constants;
-use consensus::encode::{self, Decodable, Encodable};
-use hash_types::{BlockHash, Txid};
+use consensus::encode::{self, Decodable, Encodable, VarInt};
+use hash_types::{BlockHash, Txid, Wtxid};
+
+/// Flag set in the inventory type to request witness data
+pub const MSG_WITNESS_FLAG: u32 = 1 << 30;
+
+/// Most locator hashes accepted in a `getblocks` or `getheaders` message
+pub const MAX_LOCATOR_SIZE: usize = 101;
+
+/// Number of most recent blocks which are all included in a block locator
+const LOCATOR_DENSE_BLOCKS: usize = 10;
 
 /// An inventory item.
 #[derive(PartialEq, Eq, Clone, Debug, Copy, Hash)]
@@ -39,6 +48,17 @@ pub enum Inventory {
     WitnessTransaction(Txid),
     /// Witness Block
     WitnessBlock(BlockHash),
+    /// Transaction by its wtxid, as announced to BIP339 `wtxidrelay` peers
+    WTx(Wtxid),
+    /// BIP152 compact block
+    CompactBlock(BlockHash),
+    /// Any other inventory type, such as filtered blocks
+    Unknown {
+        /// The inventory type
+        inv_type: u32,
+        /// The hash of the inventory item
+        hash: [u8; 32],
+    },
 }
 
 impl Encodable for Inventory {
@@ -57,8 +77,11 @@ impl Encodable for Inventory {
             Inventory::Error => encode_inv!(0, sha256d::Hash::default()),
             Inventory::Transaction(ref t) => encode_inv!(1, t),
             Inventory::Block(ref b) => encode_inv!(2, b),
-            Inventory::WitnessTransaction(ref t) => encode_inv!(0x40000001, t),
-            Inventory::WitnessBlock(ref b) => encode_inv!(0x40000002, b),
+            Inventory::WitnessTransaction(ref t) => encode_inv!(MSG_WITNESS_FLAG | 1, t),
+            Inventory::WitnessBlock(ref b) => encode_inv!(MSG_WITNESS_FLAG | 2, b),
+            Inventory::CompactBlock(ref b) => encode_inv!(4, b),
+            Inventory::WTx(ref w) => encode_inv!(5, w),
+            Inventory::Unknown { inv_type, ref hash } => encode_inv!(inv_type, hash),
         })
     }
 }
@@ -68,16 +91,92 @@ impl Decodable for Inventory {
     fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
         let inv_type: u32 = Decodable::consensus_decode(&mut d)?;
         Ok(match inv_type {
-            0 => Inventory::Error,
+            0 => {
+                // the hash is meaningless, but it is there
+                sha256d::Hash::consensus_decode(&mut d)?;
+                Inventory::Error
+            }
             1 => Inventory::Transaction(Decodable::consensus_decode(&mut d)?),
             2 => Inventory::Block(Decodable::consensus_decode(&mut d)?),
+            4 => Inventory::CompactBlock(Decodable::consensus_decode(&mut d)?),
+            5 => Inventory::WTx(Decodable::consensus_decode(&mut d)?),
             0x40000001 => Inventory::WitnessTransaction(Decodable::consensus_decode(&mut d)?),
             0x40000002 => Inventory::WitnessBlock(Decodable::consensus_decode(&mut d)?),
-            tp => return Err(encode::Error::UnknownInventoryType(tp)),
+            tp => Inventory::Unknown {
+                inv_type: tp,
+                hash: Decodable::consensus_decode(&mut d)?,
+            },
         })
     }
 }
 
+/// The heights of a block locator for a chain with its tip at `tip_height`,
+/// newest first: the tip and the ten blocks before it, then with doubling
+/// steps back to the genesis block, which is always included.
+pub fn locator_heights(tip_height: u32) -> Vec<u32> {
+    let mut heights = vec![];
+    let mut height = tip_height;
+    let mut step = 1u32;
+    loop {
+        heights.push(height);
+        if height == 0 {
+            break;
+        }
+        height = height.saturating_sub(step);
+        if heights.len() > LOCATOR_DENSE_BLOCKS {
+            step = step.saturating_mul(2);
+        }
+    }
+    heights
+}
+
+/// Build a block locator for a chain with its tip at `tip_height`, as Bitcoin
+/// Core does, looking up the hashes of the chain with `hash_at`. Heights for
+/// which `hash_at` returns `None` are left out.
+pub fn build_locator<F>(tip_height: u32, hash_at: F) -> Vec<BlockHash>
+    where F: Fn(u32) -> Option<BlockHash>
+{
+    locator_heights(tip_height).into_iter().filter_map(hash_at).collect()
+}
+
+/// Decode locator hashes, of which there are at most [MAX_LOCATOR_SIZE]
+fn decode_locator<D: io::Read>(mut d: D) -> Result<Vec<BlockHash>, encode::Error> {
+    let len = VarInt::consensus_decode(&mut d)?.0;
+    if len > MAX_LOCATOR_SIZE as u64 {
+        return Err(encode::Error::ParseFailed("too many locator hashes"));
+    }
+    let mut ret = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        ret.push(Decodable::consensus_decode(&mut d)?);
+    }
+    Ok(ret)
+}
+
+/// Implement encoding for a message with a protocol version, locator hashes and a stop hash
+macro_rules! impl_locator_message_encoding {
+    ($thing:ident) => {
+        impl Encodable for $thing {
+            #[inline]
+            fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+                Ok(self.version.consensus_encode(&mut s)?
+                    + self.locator_hashes.consensus_encode(&mut s)?
+                    + self.stop_hash.consensus_encode(&mut s)?)
+            }
+        }
+
+        impl Decodable for $thing {
+            #[inline]
+            fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+                Ok($thing {
+                    version: Decodable::consensus_decode(&mut d)?,
+                    locator_hashes: decode_locator(&mut d)?,
+                    stop_hash: Decodable::consensus_decode(&mut d)?,
+                })
+            }
+        }
+    }
+}
+
 // Some simple messages
 
 /// The `getblocks` message
@@ -117,7 +216,7 @@ impl GetBlocksMessage {
     }
 }
 
-impl_consensus_encoding!(GetBlocksMessage, version, locator_hashes, stop_hash);
+impl_locator_message_encoding!(GetBlocksMessage);
 
 impl GetHeadersMessage {
     /// Construct a new `getheaders` message
@@ -128,15 +227,26 @@ impl GetHeadersMessage {
             stop_hash: stop_hash
         }
     }
+
+    /// Construct a new `getheaders` message with a locator for a chain with its
+    /// tip at `tip_height`, whose block hashes are looked up with `hash_at`.
+    /// See [build_locator].
+    pub fn from_locator_heights<F>(tip_height: u32, hash_at: F, stop_hash: BlockHash) -> GetHeadersMessage
+        where F: Fn(u32) -> Option<BlockHash>
+    {
+        GetHeadersMessage::new(build_locator(tip_height, hash_at), stop_hash)
+    }
 }
 
-impl_consensus_encoding!(GetHeadersMessage, version, locator_hashes, stop_hash);
+impl_locator_message_encoding!(GetHeadersMessage);
 
 #[cfg(test)]
 mod tests {
-    use super::{GetHeadersMessage, GetBlocksMessage};
+    use super::{Inventory, build_locator, locator_heights, GetHeadersMessage, GetBlocksMessage, MAX_LOCATOR_SIZE};
 
     use hashes::hex::FromHex;
+    use hashes::Hash;
+    use hash_types::{BlockHash, Txid, Wtxid};
 
     use consensus::encode::{deserialize, serialize};
     use std::default::Default;
@@ -172,5 +282,85 @@ mod tests {
 
         assert_eq!(serialize(&real_decode), from_sat);
     }
-}
 
+    fn synthetic_hash(height: u32) -> BlockHash {
+        BlockHash::hash(&serialize(&height))
+    }
+
+    #[test]
+    fn locator_test() {
+        let heights = locator_heights(100_000);
+        let mut expected: Vec<u32> = (99_990..100_001).rev().collect();
+        expected.extend(&[99_989, 99_987, 99_983, 99_975, 99_959, 99_927, 99_863, 99_735, 99_479,
+                          98_967, 97_943, 95_895, 91_799, 83_607, 67_223, 34_455, 0]);
+        assert_eq!(heights, expected);
+
+        assert_eq!(locator_heights(0), vec![0]);
+        assert_eq!(locator_heights(5), vec![5, 4, 3, 2, 1, 0]);
+        assert_eq!(locator_heights(12), vec![12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0]);
+        assert_eq!(*locator_heights(u32::max_value()).last().unwrap(), 0);
+
+        let locator = build_locator(100_000, |h| Some(synthetic_hash(h)));
+        let expected_hashes: Vec<BlockHash> = expected.iter().map(|&h| synthetic_hash(h)).collect();
+        assert_eq!(locator, expected_hashes);
+
+        // heights without a known hash are skipped
+        let sparse = build_locator(100_000, |h| if h % 2 == 0 { Some(synthetic_hash(h)) } else { None });
+        assert_eq!(sparse.len(), 6 + 1);
+
+        let msg = GetHeadersMessage::from_locator_heights(100_000, |h| Some(synthetic_hash(h)), Default::default());
+        assert_eq!(msg.locator_hashes, expected_hashes);
+        assert_eq!(deserialize::<GetHeadersMessage>(&serialize(&msg)).unwrap(), msg);
+    }
+
+    #[test]
+    fn locator_size_limit_test() {
+        let max = GetHeadersMessage::new(vec![synthetic_hash(1); MAX_LOCATOR_SIZE], Default::default());
+        assert_eq!(deserialize::<GetHeadersMessage>(&serialize(&max)).unwrap(), max);
+        let too_many = GetHeadersMessage::new(vec![synthetic_hash(1); MAX_LOCATOR_SIZE + 1], Default::default());
+        assert!(deserialize::<GetHeadersMessage>(&serialize(&too_many)).is_err());
+        let too_many = GetBlocksMessage::new(vec![synthetic_hash(1); MAX_LOCATOR_SIZE + 1], Default::default());
+        assert!(deserialize::<GetBlocksMessage>(&serialize(&too_many)).is_err());
+    }
+
+    #[test]
+    fn inventory_test() {
+        let hash = synthetic_hash(1);
+        let check = |inv: Inventory, inv_type: u32| {
+            let raw = serialize(&inv);
+            assert_eq!(raw[..4], serialize(&inv_type)[..]);
+            assert_eq!(raw[4..], serialize(&hash)[..]);
+            assert_eq!(deserialize::<Inventory>(&raw).unwrap(), inv);
+        };
+        check(Inventory::Transaction(Txid::from_hash(hash.as_hash())), 1);
+        check(Inventory::Block(hash), 2);
+        check(Inventory::CompactBlock(hash), 4);
+        check(Inventory::WTx(Wtxid::from_hash(hash.as_hash())), 5);
+        check(Inventory::WitnessTransaction(Txid::from_hash(hash.as_hash())), 0x40000001);
+        check(Inventory::WitnessBlock(hash), 0x40000002);
+        // filtered blocks aren't known
+        check(Inventory::Unknown { inv_type: 3, hash: hash.into_inner() }, 3);
+        check(Inventory::Unknown { inv_type: 0x40000003, hash: hash.into_inner() }, 0x40000003);
+        // the hash of an error is zero, and skipped when decoding
+        let raw = serialize(&Inventory::Error);
+        assert_eq!(raw, [0u8; 36].to_vec());
+        assert_eq!(deserialize::<Inventory>(&raw).unwrap(), Inventory::Error);
+        let raw = serialize(&(Inventory::Error, hash));
+        assert_eq!(deserialize::<(Inventory, BlockHash)>(&raw).unwrap(), (Inventory::Error, hash));
+    }
+
+    #[test]
+    fn getdata_witness_tx_test() {
+        use network::message::{NetworkMessage, RawNetworkMessage};
+
+        let txid = Txid::from_hash(synthetic_hash(2).as_hash());
+        let msg = RawNetworkMessage {
+            magic: 0xdbb6c0fb,
+            payload: NetworkMessage::GetData(vec![Inventory::WitnessTransaction(txid)]),
+        };
+        let raw = serialize(&msg);
+        // one item, of type 0x40000001
+        assert_eq!(raw[24..29], [0x01, 0x01, 0x00, 0x00, 0x40]);
+        assert_eq!(deserialize::<RawNetworkMessage>(&raw).unwrap(), msg);
+    }
+}
@@ -0,0 +1,144 @@
+//!
+//! BIP37 Connection Bloom filtering network messages
+//!
+
+use std::io;
+
+use consensus::encode::{self, Decodable, Encodable};
+
+/// Largest filter a peer accepts in a `filterload` message, in bytes
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+/// Largest number of hash functions a peer accepts in a `filterload` message
+pub const MAX_HASH_FUNCS: u32 = 50;
+/// Largest data element a peer accepts in a `filteradd` message, in bytes
+pub const MAX_FILTER_ADD_SIZE: usize = 520;
+
+/// How the serving peer updates the filter when an output matches
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum BloomFlags {
+    /// Never update the filter
+    None,
+    /// Add the outpoint of every matched output
+    All,
+    /// Add the outpoint of matched pay-to-pubkey and bare multisig outputs
+    PubkeyOnly,
+}
+
+impl Encodable for BloomFlags {
+    fn consensus_encode<W: io::Write>(&self, w: W) -> Result<usize, encode::Error> {
+        let flags: u8 = match *self {
+            BloomFlags::None => 0,
+            BloomFlags::All => 1,
+            BloomFlags::PubkeyOnly => 2,
+        };
+        flags.consensus_encode(w)
+    }
+}
+
+impl Decodable for BloomFlags {
+    fn consensus_decode<R: io::Read>(r: R) -> Result<Self, encode::Error> {
+        match u8::consensus_decode(r)? {
+            0 => Ok(BloomFlags::None),
+            1 => Ok(BloomFlags::All),
+            2 => Ok(BloomFlags::PubkeyOnly),
+            _ => Err(encode::Error::ParseFailed("unknown bloom flag")),
+        }
+    }
+}
+
+/// filterload message sets the current bloom filter
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FilterLoad {
+    /// The filter itself
+    pub filter: Vec<u8>,
+    /// The number of hash functions to use
+    pub hash_funcs: u32,
+    /// A random value
+    pub tweak: u32,
+    /// Controls how matched items are added to the filter
+    pub flags: BloomFlags,
+}
+
+impl Encodable for FilterLoad {
+    fn consensus_encode<W: io::Write>(&self, mut w: W) -> Result<usize, encode::Error> {
+        Ok(self.filter.consensus_encode(&mut w)?
+            + self.hash_funcs.consensus_encode(&mut w)?
+            + self.tweak.consensus_encode(&mut w)?
+            + self.flags.consensus_encode(w)?)
+    }
+}
+
+impl Decodable for FilterLoad {
+    fn consensus_decode<R: io::Read>(mut r: R) -> Result<Self, encode::Error> {
+        let filter: Vec<u8> = Decodable::consensus_decode(&mut r)?;
+        if filter.len() > MAX_BLOOM_FILTER_SIZE {
+            return Err(encode::Error::ParseFailed("bloom filter too large"));
+        }
+        let hash_funcs: u32 = Decodable::consensus_decode(&mut r)?;
+        if hash_funcs > MAX_HASH_FUNCS {
+            return Err(encode::Error::ParseFailed("too many bloom filter hash functions"));
+        }
+        Ok(FilterLoad {
+            filter: filter,
+            hash_funcs: hash_funcs,
+            tweak: Decodable::consensus_decode(&mut r)?,
+            flags: Decodable::consensus_decode(r)?,
+        })
+    }
+}
+
+/// filteradd message updates the current filter with new data
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FilterAdd {
+    /// The data element to add to the current filter.
+    pub data: Vec<u8>,
+}
+
+impl Encodable for FilterAdd {
+    fn consensus_encode<W: io::Write>(&self, w: W) -> Result<usize, encode::Error> {
+        self.data.consensus_encode(w)
+    }
+}
+
+impl Decodable for FilterAdd {
+    fn consensus_decode<R: io::Read>(r: R) -> Result<Self, encode::Error> {
+        let data: Vec<u8> = Decodable::consensus_decode(r)?;
+        if data.len() > MAX_FILTER_ADD_SIZE {
+            return Err(encode::Error::ParseFailed("filteradd data too large"));
+        }
+        Ok(FilterAdd { data: data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use consensus::encode::{deserialize, serialize};
+
+    use super::*;
+
+    #[test]
+    fn filter_load_limits() {
+        let load = FilterLoad {
+            filter: vec![0; MAX_BLOOM_FILTER_SIZE],
+            hash_funcs: MAX_HASH_FUNCS,
+            tweak: 7,
+            flags: BloomFlags::PubkeyOnly,
+        };
+        assert_eq!(deserialize::<FilterLoad>(&serialize(&load)).unwrap(), load);
+
+        let mut too_large = load.clone();
+        too_large.filter.push(0);
+        assert!(deserialize::<FilterLoad>(&serialize(&too_large)).is_err());
+        let mut too_many = load.clone();
+        too_many.hash_funcs += 1;
+        assert!(deserialize::<FilterLoad>(&serialize(&too_many)).is_err());
+
+        let mut bad_flags = serialize(&load);
+        *bad_flags.last_mut().unwrap() = 3;
+        assert!(deserialize::<FilterLoad>(&bad_flags).is_err());
+
+        let add = FilterAdd { data: vec![1; MAX_FILTER_ADD_SIZE] };
+        assert_eq!(deserialize::<FilterAdd>(&serialize(&add)).unwrap(), add);
+        assert!(deserialize::<FilterAdd>(&serialize(&FilterAdd { data: vec![1; MAX_FILTER_ADD_SIZE + 1] })).is_err());
+    }
+}
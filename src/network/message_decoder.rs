@@ -0,0 +1,226 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Incremental Message Decoder
+//!
+//! A network message decoder which doesn't do any I/O itself: bytes are pushed
+//! into it as they are received and complete messages are taken out. This
+//! suits asynchronous code, which can read from a socket with whichever runtime
+//! it uses without having to buffer whole messages itself.
+//!
+//! As all state is kept in the decoder, a read which is cancelled halfway
+//! through a message loses nothing, as long as every received byte is pushed.
+//! Messages are encoded for sending with [consensus::encode::serialize].
+//! With the `tokio` feature, `consensus::encode::async_io` does both on
+//! tokio's streams.
+//!
+
+use std::cmp;
+
+use consensus::encode;
+use network::constants::Magic;
+use network::message::{RawNetworkMessage, MAX_MSG_SIZE};
+use util::endian;
+
+/// Size of a network message header: magic, command, length and checksum
+const HEADER_SIZE: usize = 24;
+
+/// Decoder of the network messages of a single peer
+#[derive(Clone, Debug)]
+pub struct MessageDecoder {
+    magic: u32,
+    max_payload_size: usize,
+    buf: Vec<u8>,
+}
+
+impl MessageDecoder {
+    /// Create a decoder for messages with the network magic `magic` and
+    /// payloads of at most [MAX_MSG_SIZE] bytes.
+    pub fn new(magic: u32) -> MessageDecoder {
+        MessageDecoder::with_max_payload_size(magic, MAX_MSG_SIZE)
+    }
+
+    /// Create a decoder for messages with the network magic `magic` and
+    /// payloads of at most `max_payload_size` bytes.
+    pub fn with_max_payload_size(magic: u32, max_payload_size: usize) -> MessageDecoder {
+        MessageDecoder {
+            magic: magic,
+            max_payload_size: max_payload_size,
+            buf: vec![],
+        }
+    }
+
+    /// Add received bytes.
+    pub fn push_bytes(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// The bytes which have been pushed but not decoded yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// The payload size declared in the buffered message header, if complete.
+    fn payload_size(&self) -> Option<usize> {
+        if self.buf.len() < HEADER_SIZE {
+            None
+        } else {
+            Some(endian::slice_to_u32_le(&self.buf[16..20]) as usize)
+        }
+    }
+
+    /// How many more bytes the next message needs to be complete, as far as
+    /// is known: the rest of the header or, once the header is complete, the
+    /// rest of the payload. Zero if a complete message is buffered.
+    pub fn bytes_needed(&self) -> usize {
+        match self.payload_size() {
+            None => HEADER_SIZE - self.buf.len(),
+            Some(size) => (HEADER_SIZE + cmp::min(size, self.max_payload_size)).saturating_sub(self.buf.len()),
+        }
+    }
+
+    /// Take the next message out of the buffer, or `None` if it isn't complete
+    /// yet. The network magic and payload size are checked as soon as the
+    /// header is complete, before waiting for the payload.
+    ///
+    /// After an error the stream can't be decoded any further, and the
+    /// connection should be dropped.
+    pub fn next_message(&mut self) -> Result<Option<RawNetworkMessage>, encode::Error> {
+        let payload_size = match self.payload_size() {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+        let magic = endian::slice_to_u32_le(&self.buf[0..4]);
+        Magic::from(self.magic).check(Magic::from(magic))?;
+        if payload_size > self.max_payload_size {
+            return Err(encode::Error::OversizedPayload {
+                requested: payload_size,
+                max: self.max_payload_size,
+            });
+        }
+        let size = HEADER_SIZE + payload_size;
+        if self.buf.len() < size {
+            return Ok(None);
+        }
+        let msg = RawNetworkMessage::consensus_decode_with_max_size(&self.buf[..size], self.max_payload_size)?;
+        self.buf.drain(..size);
+        Ok(Some(msg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+
+    use blockdata::block::BlockHeader;
+    use consensus::encode::{self, deserialize, serialize};
+    use network::constants::{Magic, Network};
+    use network::message::{NetworkMessage, RawNetworkMessage, MAX_MSG_SIZE};
+
+    use super::MessageDecoder;
+
+    const MAGIC: u32 = 0xdbb6c0fb;
+
+    fn headers_message() -> RawNetworkMessage {
+        let header: BlockHeader = deserialize(&Vec::from_hex("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914cd74d6e49ffff001d323b3a7b").unwrap()).unwrap();
+        RawNetworkMessage {
+            magic: MAGIC,
+            payload: NetworkMessage::Headers(vec![header; 3]),
+        }
+    }
+
+    #[test]
+    fn decode_in_chunks() {
+        let msg = headers_message();
+        let raw = serialize(&msg);
+        let mut decoder = MessageDecoder::new(MAGIC);
+        assert_eq!(decoder.bytes_needed(), 24);
+
+        decoder.push_bytes(&raw[..10]);
+        assert_eq!(decoder.bytes_needed(), 14);
+        assert_eq!(decoder.next_message().unwrap(), None);
+
+        decoder.push_bytes(&raw[10..100]);
+        assert_eq!(decoder.bytes_needed(), raw.len() - 100);
+        assert_eq!(decoder.next_message().unwrap(), None);
+
+        decoder.push_bytes(&raw[100..]);
+        assert_eq!(decoder.bytes_needed(), 0);
+        assert_eq!(decoder.next_message().unwrap(), Some(msg));
+        assert!(decoder.buffer().is_empty());
+        assert_eq!(decoder.next_message().unwrap(), None);
+    }
+
+    #[test]
+    fn decode_several_messages() {
+        let ping = RawNetworkMessage { magic: MAGIC, payload: NetworkMessage::Ping(7) };
+        let mut raw = serialize(&headers_message());
+        raw.extend(serialize(&ping));
+        raw.extend(&serialize(&ping)[..5]);
+
+        let mut decoder = MessageDecoder::new(MAGIC);
+        decoder.push_bytes(&raw);
+        assert_eq!(decoder.next_message().unwrap(), Some(headers_message()));
+        assert_eq!(decoder.next_message().unwrap(), Some(ping));
+        assert_eq!(decoder.next_message().unwrap(), None);
+        assert_eq!(decoder.buffer().len(), 5);
+    }
+
+    #[test]
+    fn header_checks() {
+        let raw = serialize(&headers_message());
+
+        // the size is checked before the payload arrives
+        let mut decoder = MessageDecoder::with_max_payload_size(MAGIC, 100);
+        decoder.push_bytes(&raw[..24]);
+        assert_eq!(decoder.bytes_needed(), 100);
+        match decoder.next_message() {
+            Err(encode::Error::OversizedPayload { requested: 244, max: 100 }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let mut huge = raw[..24].to_vec();
+        huge[16..20].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        let mut decoder = MessageDecoder::new(MAGIC);
+        decoder.push_bytes(&huge);
+        assert_eq!(decoder.bytes_needed(), MAX_MSG_SIZE);
+        assert!(decoder.next_message().is_err());
+
+        let mut decoder = MessageDecoder::new(0xd9b4bef9);
+        decoder.push_bytes(&raw);
+        match decoder.next_message() {
+            Err(encode::Error::UnexpectedNetworkMagic { expected, actual }) => {
+                assert_eq!(expected.to_bytes(), [0xf9, 0xbe, 0xb4, 0xd9]);
+                assert_eq!(actual, Magic::MONACOIN);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let mut decoder = MessageDecoder::new(Network::MonacoinTestnet.magic());
+        decoder.push_bytes(&raw);
+        match decoder.next_message() {
+            Err(encode::Error::UnexpectedNetworkMagic { expected: Magic::MONACOIN_TESTNET, actual: Magic::MONACOIN }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let mut bad_checksum = raw.clone();
+        bad_checksum[20] ^= 1;
+        let mut decoder = MessageDecoder::new(MAGIC);
+        decoder.push_bytes(&bad_checksum);
+        match decoder.next_message() {
+            Err(encode::Error::InvalidChecksum { .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
@@ -18,7 +18,8 @@
 //! capabilities
 //!
 
-use std::io;
+use std::{error, fmt, io};
+use std::io::Read;
 use std::borrow::Cow;
 
 use network::address::Address;
@@ -28,6 +29,9 @@ use consensus::encode;
 use network::message::CommandString;
 use hashes::sha256d;
 
+/// Longest user agent accepted in a `version` message, in bytes
+pub const MAX_USER_AGENT_LEN: usize = 256;
+
 /// Some simple messages
 
 /// The `version` message
@@ -78,6 +82,115 @@ impl VersionMessage {
             relay: false,
         }
     }
+
+    /// Start building a `version` message to the peer at `receiver`.
+    pub fn builder(receiver: Address) -> VersionMessageBuilder {
+        VersionMessageBuilder(VersionMessage {
+            version: constants::PROTOCOL_VERSION,
+            services: ServiceFlags::NONE,
+            timestamp: 0,
+            receiver: receiver,
+            sender: Address {
+                services: ServiceFlags::NONE,
+                address: [0; 8],
+                port: 0,
+            },
+            nonce: 0,
+            user_agent: String::new(),
+            start_height: 0,
+            relay: false,
+        })
+    }
+}
+
+/// An error building a `version` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum VersionMessageError {
+    /// The user agent is longer than [MAX_USER_AGENT_LEN] bytes
+    UserAgentTooLong(usize),
+}
+
+impl fmt::Display for VersionMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VersionMessageError::UserAgentTooLong(len) => write!(f,
+                "user agent of {} bytes is longer than {} bytes", len, MAX_USER_AGENT_LEN),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for VersionMessageError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// Builder for a `version` message, created by [VersionMessage::builder].
+///
+/// The protocol version defaults to [constants::PROTOCOL_VERSION], the sender
+/// to an unspecified address with the services of the message, and all other
+/// fields to zero, empty or false.
+#[derive(Clone, Debug)]
+pub struct VersionMessageBuilder(VersionMessage);
+
+impl VersionMessageBuilder {
+    /// Set the protocol version.
+    pub fn version(mut self, version: u32) -> VersionMessageBuilder {
+        self.0.version = version;
+        self
+    }
+
+    /// Set the services of this node, which are also used for the default sender address.
+    pub fn services(mut self, services: ServiceFlags) -> VersionMessageBuilder {
+        self.0.services = services;
+        self.0.sender.services = services;
+        self
+    }
+
+    /// Set the time at which the message is sent, in seconds since the epoch.
+    pub fn timestamp(mut self, timestamp: i64) -> VersionMessageBuilder {
+        self.0.timestamp = timestamp;
+        self
+    }
+
+    /// Set the address of this node.
+    pub fn sender(mut self, sender: Address) -> VersionMessageBuilder {
+        self.0.sender = sender;
+        self
+    }
+
+    /// Set the nonce used to detect connections to ourselves.
+    pub fn nonce(mut self, nonce: u64) -> VersionMessageBuilder {
+        self.0.nonce = nonce;
+        self
+    }
+
+    /// Set the user agent, at most [MAX_USER_AGENT_LEN] bytes.
+    pub fn user_agent<S: Into<String>>(mut self, user_agent: S) -> VersionMessageBuilder {
+        self.0.user_agent = user_agent.into();
+        self
+    }
+
+    /// Set the height of our best chain.
+    pub fn start_height(mut self, start_height: i32) -> VersionMessageBuilder {
+        self.0.start_height = start_height;
+        self
+    }
+
+    /// Set whether the peer should relay transactions before a bloom filter is loaded.
+    pub fn relay(mut self, relay: bool) -> VersionMessageBuilder {
+        self.0.relay = relay;
+        self
+    }
+
+    /// Check the fields and return the message.
+    pub fn build(self) -> Result<VersionMessage, VersionMessageError> {
+        if self.0.user_agent.len() > MAX_USER_AGENT_LEN {
+            return Err(VersionMessageError::UserAgentTooLong(self.0.user_agent.len()));
+        }
+        Ok(self.0)
+    }
 }
 
 impl_consensus_encoding!(VersionMessage, version, services, timestamp,
@@ -137,15 +250,51 @@ pub struct Reject {
     pub ccode: RejectReason,
     /// reason of rejectection
     pub reason: Cow<'static, str>,
-    /// reference to rejected item
-    pub hash: sha256d::Hash
+    /// reference to rejected item, for rejected blocks and transactions
+    pub hash: Option<sha256d::Hash>
 }
 
-impl_consensus_encoding!(Reject, message, ccode, reason, hash);
+impl Encodable for Reject {
+    fn consensus_encode<W: io::Write>(&self, mut e: W) -> Result<usize, encode::Error> {
+        // unlike in message headers, the command is a variable length string here
+        let mut len = self.message.as_ref().to_owned().consensus_encode(&mut e)?
+            + self.ccode.consensus_encode(&mut e)?
+            + self.reason.consensus_encode(&mut e)?;
+        if let Some(ref hash) = self.hash {
+            len += hash.consensus_encode(&mut e)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for Reject {
+    /// Decodes a reject message, which must be the whole rest of `d` as the
+    /// hash is optional.
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let message = CommandString::try_from(String::consensus_decode(&mut d)?)
+            .map_err(|_| encode::Error::ParseFailed("invalid command in reject message"))?;
+        let ccode = Decodable::consensus_decode(&mut d)?;
+        let reason = Decodable::consensus_decode(&mut d)?;
+        let mut hash = vec![];
+        (&mut d).take(32).read_to_end(&mut hash)?;
+        let hash = match hash.len() {
+            0 => None,
+            32 => Some(Decodable::consensus_decode(&hash[..])?),
+            _ => return Err(encode::Error::ParseFailed("truncated hash in reject message")),
+        };
+        Ok(Reject {
+            message: message,
+            ccode: ccode,
+            reason: reason,
+            hash: hash,
+        })
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use super::VersionMessage;
+    use super::{Reject, RejectReason, VersionMessage, VersionMessageError, MAX_USER_AGENT_LEN};
+    use network::address::Address;
 
     use hashes::hex::FromHex;
     use network::constants::ServiceFlags;
@@ -171,4 +320,64 @@ mod tests {
 
         assert_eq!(serialize(&real_decode), from_sat);
     }
+
+    #[test]
+    fn version_message_builder_test() {
+        // A version message as sent by Monacoin Core 0.20.2 with a wtxidrelay
+        // protocol version, assembled field by field
+        let raw = Vec::from_hex("80110100090400000000000000105e5f00000000000000000000000000000000000000000000ffff7f00000124b90904000000000000000000000000000000000000000000000000efcdab8967452301162f4d6f6e61636f696e20436f72653a302e32302e322f80841e0001").unwrap();
+
+        let services = ServiceFlags::NETWORK | ServiceFlags::WITNESS | ServiceFlags::NETWORK_LIMITED;
+        let receiver = Address::new(&([127, 0, 0, 1], 9401).into(), ServiceFlags::NONE);
+        let msg = VersionMessage::builder(receiver)
+            .version(70016)
+            .services(services)
+            .timestamp(1600000000)
+            .nonce(0x0123456789abcdef)
+            .user_agent("/Monacoin Core:0.20.2/")
+            .start_height(2000000)
+            .relay(true)
+            .build()
+            .unwrap();
+        assert_eq!(msg.sender.services, services);
+        assert_eq!(msg.sender.address, [0; 8]);
+        assert_eq!(serialize(&msg), raw);
+        assert_eq!(deserialize::<VersionMessage>(&raw).unwrap(), msg);
+
+        let default = VersionMessage::builder(msg.receiver.clone()).build().unwrap();
+        assert_eq!(default.version, ::network::constants::PROTOCOL_VERSION);
+        assert!(!default.relay);
+
+        let long = VersionMessage::builder(msg.receiver.clone()).user_agent("a".repeat(MAX_USER_AGENT_LEN)).build();
+        assert!(long.is_ok());
+        let too_long = VersionMessage::builder(msg.receiver).user_agent("a".repeat(MAX_USER_AGENT_LEN + 1)).build();
+        assert_eq!(too_long.unwrap_err(), VersionMessageError::UserAgentTooLong(MAX_USER_AGENT_LEN + 1));
+    }
+
+    #[test]
+    fn reject_message_test() {
+        // a rejected transaction, with the txid
+        let raw_tx = Vec::from_hex("027478121474786e2d6d656d706f6f6c2d636f6e666c69637405df54d3860b3c41806a3546ab48279300affacf4b88591b229141dcf2f47004").unwrap();
+        let reject: Reject = deserialize(&raw_tx).unwrap();
+        assert_eq!(reject.message.as_ref(), "tx");
+        assert_eq!(reject.ccode, RejectReason::Duplicate);
+        assert_eq!(reject.reason, "txn-mempool-conflict");
+        assert_eq!(
+            reject.hash.unwrap().to_string(),
+            "0470f4f2dc4191221b59884bcffaaf00932748ab46356a80413c0b86d354df05"
+        );
+        assert_eq!(serialize(&reject), raw_tx);
+
+        // a rejected version message has no hash
+        let raw_version = Vec::from_hex("0776657273696f6e12114475706c69636174652076657273696f6e").unwrap();
+        let reject: Reject = deserialize(&raw_version).unwrap();
+        assert_eq!(reject.message.as_ref(), "version");
+        assert_eq!(reject.ccode, RejectReason::Duplicate);
+        assert_eq!(reject.reason, "Duplicate version");
+        assert_eq!(reject.hash, None);
+        assert_eq!(serialize(&reject), raw_version);
+
+        // a truncated hash is an error
+        assert!(deserialize::<Reject>(&raw_tx[..raw_tx.len() - 1]).is_err());
+    }
 }
@@ -30,7 +30,10 @@ pub mod message;
 pub mod message_blockdata;
 pub mod message_network;
 pub mod message_filter;
+pub mod message_bloom;
+pub mod message_compact_blocks;
 pub mod stream_reader;
+pub mod message_decoder;
 
 /// Network error
 #[derive(Debug)]
@@ -66,7 +69,7 @@ impl error::Error for Error {
         "description() is deprecated; use Display"
     }
 
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
         match *self {
             Error::Io(ref e) => Some(e),
             Error::SocketMutexPoisoned | Error::SocketNotConnectedToPeer => None,
@@ -19,59 +19,168 @@
 //! messages from multiple packets or dealing with partial or multiple messages in the stream
 //! (like can happen with reading from TCP socket)
 //!
+//! Unparsed bytes are kept in a growable buffer. When reading network messages
+//! no more is read from the stream than the next message needs, so the stream
+//! can also be read directly between messages. Errors from the stream,
+//! including `WouldBlock` from non-blocking sockets, leave the buffer as it
+//! was, and a later call resumes where the previous one stopped.
+//!
 
-use std::fmt;
+use std::{cmp, fmt};
 use std::io::{self, Read};
 
 use consensus::{encode, Decodable};
+use network::constants::Magic;
+use util::endian;
+
+/// Size of a network message header: magic, command, length and checksum
+const HEADER_SIZE: usize = 24;
 
 /// Struct used to configure stream reader function
 pub struct StreamReader<R: Read> {
     /// Stream to read from
     pub stream: R,
-    /// I/O buffer
-    data: Vec<u8>,
+    /// Largest single read from the stream
+    buffer_size: usize,
     /// Buffer containing unparsed message part
-    unparsed: Vec<u8>
+    unparsed: Vec<u8>,
+    /// Magic the messages must start with, if checked
+    magic: Option<Magic>,
+    /// Whether to skip ahead to the magic instead of failing
+    resync: bool,
 }
 
 impl<R: Read> fmt::Debug for StreamReader<R> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "StreamReader with buffer_size={} and unparsed content {:?}",
-               self.data.capacity(), self.unparsed)
+               self.buffer_size, self.unparsed)
     }
 }
 
 impl<R: Read> StreamReader<R> {
     /// Constructs new stream reader for a given input stream `stream` with
-    /// optional parameter `buffer_size` determining reading buffer size
+    /// optional parameter `buffer_size` determining the largest single read
     pub fn new(stream: R, buffer_size: Option<usize>) -> StreamReader<R> {
         StreamReader {
             stream,
-            data: vec![0u8; buffer_size.unwrap_or(64 * 1024)],
-            unparsed: vec![]
+            buffer_size: cmp::max(buffer_size.unwrap_or(64 * 1024), 1),
+            unparsed: vec![],
+            magic: None,
+            resync: false,
+        }
+    }
+
+    /// Makes [StreamReader::read_next] check that messages start with
+    /// `magic`, or stops checking with `None`. See [Magic::check] for the
+    /// errors reported on a mismatch.
+    pub fn set_magic(&mut self, magic: Option<Magic>) {
+        self.magic = magic;
+    }
+
+    /// With `true`, a message which doesn't start with the magic set by
+    /// [StreamReader::set_magic] is not an error. Instead everything up to the
+    /// next occurrence of the magic in the stream is skipped.
+    pub fn set_resync(&mut self, resync: bool) {
+        self.resync = resync;
+    }
+
+    /// The buffered bytes which haven't been parsed yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.unparsed
+    }
+
+    /// How much to read next: the rest of the next message header, or the rest
+    /// of its payload once the header is complete. Streams which don't consist
+    /// of network messages are read in chunks of at most `buffer_size` bytes.
+    fn read_size(&self) -> usize {
+        let len = self.unparsed.len();
+        let wanted = if len < HEADER_SIZE {
+            HEADER_SIZE - len
+        } else {
+            let payload_len = endian::slice_to_u32_le(&self.unparsed[16..20]) as usize;
+            match (HEADER_SIZE + payload_len).checked_sub(len) {
+                Some(wanted) if wanted > 0 => wanted,
+                _ => self.buffer_size,
+            }
+        };
+        cmp::min(wanted, self.buffer_size)
+    }
+
+    /// Reads more data from the stream into the buffer and returns all buffered
+    /// bytes, for example to peek at the next message header. At most as much
+    /// as the next network message still needs is read. Returns an
+    /// `UnexpectedEof` I/O error when the stream has ended.
+    pub fn fill_buf(&mut self) -> Result<&[u8], encode::Error> {
+        let start = self.unparsed.len();
+        let size = self.read_size();
+        self.unparsed.resize(start + size, 0);
+        loop {
+            match self.stream.read(&mut self.unparsed[start..]) {
+                Ok(0) => {
+                    self.unparsed.truncate(start);
+                    return Err(encode::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
+                }
+                Ok(count) => {
+                    self.unparsed.truncate(start + count);
+                    return Ok(&self.unparsed);
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.unparsed.truncate(start);
+                    return Err(encode::Error::Io(err));
+                }
+            }
+        }
+    }
+
+    /// Removes the first `amount` bytes from the buffer, after the caller
+    /// handled them itself.
+    pub fn consume(&mut self, amount: usize) {
+        let amount = cmp::min(amount, self.unparsed.len());
+        self.unparsed.drain(..amount);
+    }
+
+    /// Reads from the stream until the buffer starts with `expected`, or
+    /// fails if it doesn't and we aren't resyncing.
+    fn sync_to(&mut self, expected: Magic) -> Result<(), encode::Error> {
+        loop {
+            while self.unparsed.len() < 4 {
+                self.fill_buf()?;
+            }
+            let actual = Magic::from_bytes([self.unparsed[0], self.unparsed[1], self.unparsed[2], self.unparsed[3]]);
+            match expected.check(actual) {
+                Ok(()) => return Ok(()),
+                Err(err) => if !self.resync {
+                    return Err(err);
+                }
+            }
+            // the magic doesn't start at 0, but may start in the last three bytes
+            let skip = self.unparsed.windows(4)
+                .position(|bytes| bytes == expected.to_bytes())
+                .unwrap_or(self.unparsed.len() - 3);
+            self.consume(skip);
         }
     }
 
     /// Reads stream and parses next message from its current input,
     /// also taking into account previously unparsed partial message (if there was such).
+    ///
+    /// If a magic was set with [StreamReader::set_magic], it is checked
+    /// before anything else.
     pub fn read_next<D: Decodable>(&mut self) -> Result<D, encode::Error> {
+        if let Some(magic) = self.magic {
+            self.sync_to(magic)?;
+        }
         loop {
             match encode::deserialize_partial::<D>(&self.unparsed) {
                 // In this case we just have an incomplete data, so we need to read more
                 Err(encode::Error::Io(ref err)) if err.kind () == io::ErrorKind::UnexpectedEof => {
-                    let count = self.stream.read(&mut self.data)?;
-                    if count > 0 {
-                        self.unparsed.extend(self.data[0..count].iter());
-                    }
-                    else {
-                        return Err(encode::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
-                    }
+                    self.fill_buf()?;
                 },
                 Err(err) => return Err(err),
                 // We have successfully read from the buffer
                 Ok((message, index)) => {
-                    self.unparsed.drain(..index);
+                    self.consume(index);
                     return Ok(message)
                 },
             }
@@ -89,6 +198,8 @@ mod test {
     use network::constants::ServiceFlags;
 
     use super::StreamReader;
+    use consensus::encode::{self, serialize};
+    use network::constants::{Magic, Network};
     use network::message::{NetworkMessage, RawNetworkMessage};
 
     // First, let's define some byte arrays for sample messages - dumps are taken from live
@@ -233,6 +344,157 @@ mod test {
         }
     }
 
+    /// A stream returning a single byte per read, and `WouldBlock` before every byte
+    struct TrickleStream {
+        data: Vec<u8>,
+        pos: usize,
+        blocked: bool,
+    }
+
+    impl io::Read for TrickleStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.blocked = !self.blocked;
+            if self.blocked {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+            if self.pos == self.data.len() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.data[self.pos];
+            self.pos += 1;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn read_byte_by_byte_test() {
+        let mut data = MSG_VERSION.to_vec();
+        data.extend(&MSG_PING);
+        let stream = TrickleStream { data: data, pos: 0, blocked: false };
+        let mut reader = StreamReader::new(stream, None);
+
+        let mut would_block = 0;
+        let mut messages = vec![];
+        while messages.len() < 2 {
+            match reader.read_next::<RawNetworkMessage>() {
+                Ok(msg) => messages.push(msg),
+                Err(encode::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => would_block += 1,
+                Err(e) => panic!("unexpected error {:?}", e),
+            }
+        }
+        assert_eq!(would_block, MSG_VERSION.len() + MSG_PING.len());
+        check_version_msg(&messages[0]);
+        assert_eq!(messages[1].payload, NetworkMessage::Ping(100));
+        assert!(reader.buffer().is_empty());
+    }
+
+    #[test]
+    fn read_two_msgs_in_one_read_test() {
+        // both messages already buffered
+        let mut reader = StreamReader::new(io::empty(), None);
+        reader.unparsed = MSG_VERACK.to_vec();
+        reader.unparsed.extend(&MSG_PING);
+        let msg: RawNetworkMessage = reader.read_next().unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Verack);
+        let msg: RawNetworkMessage = reader.read_next().unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Ping(100));
+
+        // both messages available from the stream, of which no more than the
+        // first message is read
+        let mut data = MSG_VERACK.to_vec();
+        data.extend(&MSG_PING);
+        let mut reader = StreamReader::new(io::Cursor::new(data), None);
+        let msg: RawNetworkMessage = reader.read_next().unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Verack);
+        assert_eq!(reader.stream.position(), MSG_VERACK.len() as u64);
+        let msg: RawNetworkMessage = reader.read_next().unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Ping(100));
+        assert!(reader.read_next::<RawNetworkMessage>().is_err());
+    }
+
+    #[test]
+    fn peek_header_test() {
+        let mut data = MSG_PING.to_vec();
+        data.extend(&MSG_VERACK);
+        let mut reader = StreamReader::new(&data[..], None);
+        while reader.buffer().len() < 24 {
+            reader.fill_buf().unwrap();
+        }
+        assert_eq!(&reader.buffer()[4..8], b"ping");
+        // skip the ping message by hand
+        while reader.buffer().len() < MSG_PING.len() {
+            reader.fill_buf().unwrap();
+        }
+        reader.consume(MSG_PING.len());
+        let msg: RawNetworkMessage = reader.read_next().unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Verack);
+    }
+
+    #[test]
+    fn check_magic_test() {
+        // the sample messages carry the bitcoin magic
+        let mut reader = StreamReader::new(&MSG_VERSION[..], None);
+        reader.set_magic(Some(Magic::MONACOIN));
+        match reader.read_next::<RawNetworkMessage>() {
+            Err(encode::Error::UnknownNetworkMagic { expected, actual }) => {
+                assert_eq!(expected, Magic::MONACOIN);
+                assert_eq!(actual, Magic::from_bytes([0xf9, 0xbe, 0xb4, 0xd9]));
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let testnet = serialize(&RawNetworkMessage {
+            magic: Network::MonacoinTestnet.magic(),
+            payload: NetworkMessage::Verack,
+        });
+        let mut reader = StreamReader::new(&testnet[..], None);
+        reader.set_magic(Some(Magic::MONACOIN));
+        match reader.read_next::<RawNetworkMessage>() {
+            Err(encode::Error::UnexpectedNetworkMagic { expected, actual }) => {
+                assert_eq!(expected, Magic::MONACOIN);
+                assert_eq!(actual, Magic::MONACOIN_TESTNET);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        // nothing was consumed
+        assert_eq!(reader.buffer(), &testnet[..]);
+
+        reader.set_magic(Some(Magic::MONACOIN_TESTNET));
+        let msg: RawNetworkMessage = reader.read_next().unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Verack);
+    }
+
+    #[test]
+    fn resync_test() {
+        let mainnet = serialize(&RawNetworkMessage {
+            magic: Network::Monacoin.magic(),
+            payload: NetworkMessage::Verack,
+        });
+        let mut data = vec![0xfb, 0xc0, 0xb6, 0x00, 0xfb, 0xc0];
+        data.extend(&MSG_VERSION);
+        data.extend(&mainnet);
+        data.extend(&MSG_PING);
+        data.extend(&mainnet);
+
+        let stream = TrickleStream { data: data, pos: 0, blocked: false };
+        let mut reader = StreamReader::new(stream, None);
+        reader.set_magic(Some(Magic::MONACOIN));
+        reader.set_resync(true);
+        let mut messages = vec![];
+        while messages.len() < 2 {
+            match reader.read_next::<RawNetworkMessage>() {
+                Ok(msg) => messages.push(msg),
+                Err(encode::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => panic!("unexpected error {:?}", e),
+            }
+        }
+        for msg in &messages {
+            assert_eq!(msg.magic, Network::Monacoin.magic());
+            assert_eq!(msg.payload, NetworkMessage::Verack);
+        }
+        assert!(reader.buffer().is_empty());
+    }
+
     // Helper function that set ups emulation of client-server TCP connection for
     // testing message transfer via TCP packets
     fn serve_tcp(pieces: Vec<Vec<u8>>) -> (JoinHandle<()>, BufReader<TcpStream>) {
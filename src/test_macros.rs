@@ -27,3 +27,14 @@ macro_rules! serde_round_trip (
     })
 );
 
+#[cfg(feature = "serde")]
+macro_rules! bincode_round_trip (
+    ($var:expr) => ({
+        use bincode;
+
+        let encoded = bincode::serialize(&$var).unwrap();
+        let decoded = bincode::deserialize(&encoded).unwrap();
+        assert_eq!($var, decoded);
+    })
+);
+
@@ -36,16 +36,19 @@
 //! let address = Address::p2pkh(&public_key, Network::Monacoin);
 //! ```
 
+
 use std::fmt::{self, Display, Formatter};
 use std::str::FromStr;
 
 use bech32;
 use hashes::Hash;
+use secp256k1::{Secp256k1, Verification};
 use hash_types::{PubkeyHash, WPubkeyHash, ScriptHash, WScriptHash};
-use blockdata::script;
+use blockdata::{opcodes, script};
 use network::constants::Network;
 use util::base58;
 use util::key;
+use util::taproot::{TapBranchHash, TapTweak, XOnlyPublicKey};
 
 /// Address error.
 #[derive(Debug, PartialEq)]
@@ -56,6 +59,13 @@ pub enum Error {
     Bech32(bech32::Error),
     /// The bech32 payload was empty
     EmptyBech32Payload,
+    /// The wrong checksum algorithm was used for the witness version, see BIP350
+    InvalidBech32Variant {
+        /// Bech32 variant required by the witness version
+        expected: bech32::Variant,
+        /// Bech32 variant the address was encoded with
+        found: bech32::Variant,
+    },
     /// Script version must be 0 to 16 inclusive
     InvalidWitnessVersion(u8),
     /// The witness program must be between 2 and 40 bytes in length.
@@ -64,6 +74,18 @@ pub enum Error {
     InvalidSegwitV0ProgramLength(usize),
     /// An uncompressed pubkey was used where it is not allowed.
     UncompressedPubkey,
+    /// A valid bech32 string whose human-readable part is not the one of any
+    /// network, such as a Bitcoin address
+    UnknownBech32Hrp(String),
+    /// The human-readable part of a bech32 address is not the expected one
+    UnexpectedBech32Hrp {
+        /// The human-readable part required by the caller
+        expected: String,
+        /// The human-readable part of the address
+        found: String,
+    },
+    /// Only witness programs can be encoded as bech32 addresses
+    NotWitnessProgram,
 }
 
 impl fmt::Display for Error {
@@ -72,6 +94,9 @@ impl fmt::Display for Error {
             Error::Base58(ref e) => write!(f, "base58: {}", e),
             Error::Bech32(ref e) => write!(f, "bech32: {}", e),
             Error::EmptyBech32Payload => write!(f, "the bech32 payload was empty"),
+            Error::InvalidBech32Variant { expected, found } => write!(f,
+                "invalid bech32 checksum variant found {:?} when {:?} was expected", found, expected,
+            ),
             Error::InvalidWitnessVersion(v) => write!(f, "invalid witness script version: {}", v),
             Error::InvalidWitnessProgramLength(l) => write!(f,
                 "the witness program must be between 2 and 40 bytes in length: length={}", l,
@@ -82,13 +107,20 @@ impl fmt::Display for Error {
             Error::UncompressedPubkey => write!(f,
                 "an uncompressed pubkey was used where it is not allowed",
             ),
+            Error::UnknownBech32Hrp(ref hrp) => write!(f,
+                "bech32 address with the unknown human-readable part {:?}", hrp,
+            ),
+            Error::UnexpectedBech32Hrp { ref expected, ref found } => write!(f,
+                "bech32 address with the human-readable part {:?} when {:?} was expected", found, expected,
+            ),
+            Error::NotWitnessProgram => write!(f, "only witness programs have bech32 addresses"),
         }
     }
 }
 
 #[allow(deprecated)]
 impl ::std::error::Error for Error {
-    fn cause(&self) -> Option<&::std::error::Error> {
+    fn source(&self) -> Option<&(::std::error::Error + 'static)> {
         match *self {
             Error::Base58(ref e) => Some(e),
             Error::Bech32(ref e) => Some(e),
@@ -126,6 +158,8 @@ pub enum AddressType {
     P2wpkh,
     /// pay-to-witness-script-hash
     P2wsh,
+    /// pay-to-taproot
+    P2tr,
 }
 
 impl fmt::Display for AddressType {
@@ -135,6 +169,7 @@ impl fmt::Display for AddressType {
             AddressType::P2sh => "p2sh",
             AddressType::P2wpkh => "p2wpkh",
             AddressType::P2wsh => "p2wsh",
+            AddressType::P2tr => "p2tr",
         })
     }
 }
@@ -147,6 +182,7 @@ impl FromStr for AddressType {
             "p2sh" => Ok(AddressType::P2sh),
             "p2wpkh" => Ok(AddressType::P2wpkh),
             "p2wsh" => Ok(AddressType::P2wsh),
+            "p2tr" => Ok(AddressType::P2tr),
             _ => Err(()),
         }
     }
@@ -208,6 +244,88 @@ impl Payload {
             } => script::Script::new_witness_program(ver, prog)
         }
     }
+
+    /// Whether `script` is the script pubkey of this [Payload].
+    ///
+    /// This is the same as comparing against [Payload::script_pubkey] but
+    /// does not allocate.
+    pub fn matches_script_pubkey(&self, script: &script::Script) -> bool {
+        let bytes = script.as_bytes();
+        match *self {
+            Payload::PubkeyHash(ref hash) =>
+                script.is_p2pkh() && bytes[3..23] == hash[..],
+            Payload::ScriptHash(ref hash) =>
+                script.is_p2sh() && bytes[2..22] == hash[..],
+            Payload::WitnessProgram { version, ref program } => {
+                if program.len() > opcodes::all::OP_PUSHBYTES_75.into_u8() as usize {
+                    // Not a valid witness program, so not pushed directly.
+                    return self.script_pubkey() == *script;
+                }
+                let verop = match version.to_u8() {
+                    0 => 0,
+                    v => v + 0x50,
+                };
+                bytes.len() == program.len() + 2
+                    && bytes[0] == verop
+                    && bytes[1] as usize == program.len()
+                    && bytes[2..] == program[..]
+            }
+        }
+    }
+
+    /// Encodes a witness program as a bech32 address with the human-readable
+    /// part `hrp`, for chains other than the built-in [Network]s. Fails for
+    /// other payloads and for human-readable parts which BIP173 doesn't allow,
+    /// such as ones longer than 83 characters.
+    pub fn to_bech32_with_hrp(&self, hrp: &str) -> Result<String, Error> {
+        match *self {
+            Payload::WitnessProgram { version, ref program } => {
+                let mut data = vec![version];
+                data.extend(bech32::ToBase32::to_base32(program));
+                Ok(bech32::encode(hrp, data, bech32_variant(version))?)
+            }
+            Payload::PubkeyHash(_) | Payload::ScriptHash(_) => Err(Error::NotWitnessProgram),
+        }
+    }
+
+    /// Decodes a bech32 address, returning its human-readable part and the
+    /// witness program.
+    fn from_bech32(s: &str) -> Result<(String, Payload), Error> {
+        let (hrp, payload, variant) = bech32::decode(s)?;
+        if payload.is_empty() {
+            return Err(Error::EmptyBech32Payload);
+        }
+
+        // Get the script version and program (converted from 5-bit to 8-bit)
+        let (version, program): (bech32::u5, Vec<u8>) = {
+            let (v, p5) = payload.split_at(1);
+            (v[0], bech32::FromBase32::from_base32(p5)?)
+        };
+
+        // Generic segwit checks.
+        if version.to_u8() > 16 {
+            return Err(Error::InvalidWitnessVersion(version.to_u8()));
+        }
+        if program.len() < 2 || program.len() > 40 {
+            return Err(Error::InvalidWitnessProgramLength(program.len()));
+        }
+
+        // Specific segwit v0 check.
+        if version.to_u8() == 0 && (program.len() != 20 && program.len() != 32) {
+            return Err(Error::InvalidSegwitV0ProgramLength(program.len()));
+        }
+
+        // Encoding check
+        let expected = bech32_variant(version);
+        if expected != variant {
+            return Err(Error::InvalidBech32Variant { expected: expected, found: variant });
+        }
+
+        Ok((hrp, Payload::WitnessProgram {
+            version: version,
+            program: program,
+        }))
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -288,6 +406,11 @@ impl Address {
     }
 
     /// Create a witness pay to script hash address
+    ///
+    /// Any script can be hashed, but a witness script longer than
+    /// [MAX_SCRIPT_SIZE](../../blockdata/constants/constant.MAX_SCRIPT_SIZE.html)
+    /// bytes can never be spent, and an empty witness script can be spent
+    /// by anyone.
     pub fn p2wsh(script: &script::Script, network: Network) -> Address {
         Address {
             network: network,
@@ -298,8 +421,30 @@ impl Address {
         }
     }
 
+    /// Create a pay to taproot address from an untweaked internal key, committing to the
+    /// script tree with the given merkle root, if any (BIP341)
+    ///
+    /// The address is encoded with bech32m, as BIP350 requires for witness version 1.
+    pub fn p2tr<C: Verification>(
+        secp: &Secp256k1<C>,
+        internal_key: XOnlyPublicKey,
+        merkle_root: Option<TapBranchHash>,
+        network: Network,
+    ) -> Address {
+        let (output_key, _parity) = internal_key.tap_tweak(secp, merkle_root);
+        Address {
+            network: network,
+            payload: Payload::WitnessProgram {
+                version: bech32::u5::try_from_u8(1).expect("1<32"),
+                program: output_key.serialize().to_vec(),
+            },
+        }
+    }
+
     /// Create a pay to script address that embeds a witness pay to script hash address
     /// This is a segwit address type that looks familiar (as p2sh) to legacy clients
+    ///
+    /// The same spendability limits as for [Address::p2wsh] apply.
     pub fn p2shwsh(script: &script::Script, network: Network) -> Address {
         let ws = script::Builder::new()
             .push_int(0)
@@ -322,13 +467,14 @@ impl Address {
                 version: ver,
                 program: ref prog,
             } => {
-                // BIP-141 p2wpkh or p2wsh addresses.
+                // BIP-141 p2wpkh or p2wsh addresses, BIP-341 p2tr addresses.
                 match ver.to_u8() {
                     0 => match prog.len() {
                         20 => Some(AddressType::P2wpkh),
                         32 => Some(AddressType::P2wsh),
                         _ => None,
                     },
+                    1 if prog.len() == 32 => Some(AddressType::P2tr),
                     _ => None,
                 }
             }
@@ -356,46 +502,116 @@ impl Address {
     pub fn script_pubkey(&self) -> script::Script {
         self.payload.script_pubkey()
     }
+
+    /// Whether `script` is the script pubkey of this address, without
+    /// allocating.
+    pub fn matches_script_pubkey(&self, script: &script::Script) -> bool {
+        self.payload.matches_script_pubkey(script)
+    }
+
+    /// Whether the address can be used on `network`. Testnet and regtest
+    /// share the base58 versions, so base58 addresses parsed as testnet ones
+    /// are also valid on regtest, while bech32 addresses are specific to one
+    /// network.
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        if self.network == network {
+            return true;
+        }
+        match (self.network, network) {
+            (Network::Monacoin, _) | (_, Network::Monacoin) => false,
+            _ => match self.payload {
+                Payload::WitnessProgram { .. } => false,
+                Payload::PubkeyHash(_) | Payload::ScriptHash(_) => true,
+            },
+        }
+    }
+
+    /// Decodes a bech32 address with the human-readable part `expected_hrp`,
+    /// for chains other than the built-in [Network]s. As the address doesn't
+    /// belong to any of them, only its [Payload] is returned.
+    pub fn from_bech32_with_hrp(s: &str, expected_hrp: &str) -> Result<Payload, Error> {
+        let (hrp, payload) = Payload::from_bech32(s)?;
+        if !hrp.eq_ignore_ascii_case(expected_hrp) {
+            return Err(Error::UnexpectedBech32Hrp {
+                expected: expected_hrp.to_lowercase(),
+                found: hrp,
+            });
+        }
+        Ok(payload)
+    }
+
+    /// A URI for QR codes, `monacoin:` followed by the address. Bech32
+    /// addresses are upper-cased, so that the QR code can use the smaller
+    /// alphanumeric mode.
+    pub fn to_qr_uri(&self) -> String {
+        format!("monacoin:{:#}", self)
+    }
+}
+
+/// A [fmt::Write] which upper-cases everything written to it, for bech32
+/// addresses in the alternate format
+struct UpperWriter<W: fmt::Write>(W);
+
+impl<W: fmt::Write> fmt::Write for UpperWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.0.write_char(c.to_ascii_uppercase())?;
+        }
+        Ok(())
+    }
 }
 
+/// Formats the address straight into the formatter without allocating. The
+/// alternate form `{:#}` upper-cases bech32 addresses, which makes QR codes
+/// smaller, and leaves base58 addresses as they are.
 impl Display for Address {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         match self.payload {
             Payload::PubkeyHash(ref hash) => {
-                let mut prefixed = [0; 21];
-                prefixed[0] = match self.network {
+                let version = match self.network {
                     Network::Monacoin => 50,
                     Network::MonacoinTestnet | Network::MonacoinRegtest => 111,
                 };
-                prefixed[1..].copy_from_slice(&hash[..]);
-                base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
+                base58::encode_check_with_version_to_fmt(fmt, &[version], &hash[..])
             }
             Payload::ScriptHash(ref hash) => {
-                let mut prefixed = [0; 21];
-                prefixed[0] = match self.network {
+                let version = match self.network {
                     Network::Monacoin => 55,
                     Network::MonacoinTestnet | Network::MonacoinRegtest => 117,
                 };
-                prefixed[1..].copy_from_slice(&hash[..]);
-                base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
+                base58::encode_check_with_version_to_fmt(fmt, &[version], &hash[..])
             }
             Payload::WitnessProgram {
                 version: ver,
                 program: ref prog,
             } => {
-                let hrp = match self.network {
-                    Network::Monacoin => "mona",
-                    Network::MonacoinTestnet => "tmona",
-                    Network::MonacoinRegtest => "rmona",
-                };
-                let mut bech32_writer = bech32::Bech32Writer::new(hrp, fmt)?;
-                bech32::WriteBase32::write_u5(&mut bech32_writer, ver)?;
-                bech32::ToBase32::write_base32(&prog, &mut bech32_writer)
+                let hrp = self.network.bech32_hrp();
+                let variant = bech32_variant(ver);
+                if fmt.alternate() {
+                    let mut upper = UpperWriter(fmt);
+                    let mut bech32_writer = bech32::Bech32Writer::new(hrp, variant, &mut upper)?;
+                    bech32::WriteBase32::write_u5(&mut bech32_writer, ver)?;
+                    bech32::ToBase32::write_base32(&prog, &mut bech32_writer)
+                } else {
+                    let mut bech32_writer = bech32::Bech32Writer::new(hrp, variant, fmt)?;
+                    bech32::WriteBase32::write_u5(&mut bech32_writer, ver)?;
+                    bech32::ToBase32::write_base32(&prog, &mut bech32_writer)
+                }
             }
         }
     }
 }
 
+/// The checksum variant used for a witness version: bech32 for v0 and bech32m for
+/// every later version (BIP350).
+fn bech32_variant(version: bech32::u5) -> bech32::Variant {
+    if version.to_u8() == 0 {
+        bech32::Variant::Bech32
+    } else {
+        bech32::Variant::Bech32m
+    }
+}
+
 /// Extract the bech32 prefix.
 /// Returns the same slice when no prefix is found.
 fn find_bech32_prefix(bech32: &str) -> &str {
@@ -410,76 +626,47 @@ impl FromStr for Address {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Address, Error> {
-        // try bech32
-        let bech32_network = match find_bech32_prefix(s) {
-            // note that upper or lowercase is allowed but NOT mixed case
-            "mona" | "MONA" => Some(Network::Monacoin),
-            "tmona" | "TMONA" => Some(Network::MonacoinTestnet),
-            "rmona" | "RMONA" => Some(Network::MonacoinRegtest),
-            _ => None,
-        };
-        if let Some(network) = bech32_network {
-            // decode as bech32
-            let (_, payload) = bech32::decode(s)?;
-            if payload.is_empty() {
-                return Err(Error::EmptyBech32Payload);
-            }
-
-            // Get the script version and program (converted from 5-bit to 8-bit)
-            let (version, program): (bech32::u5, Vec<u8>) = {
-                let (v, p5) = payload.split_at(1);
-                (v[0], bech32::FromBase32::from_base32(p5)?)
-            };
-
-            // Generic segwit checks.
-            if version.to_u8() > 16 {
-                return Err(Error::InvalidWitnessVersion(version.to_u8()));
-            }
-            if program.len() < 2 || program.len() > 40 {
-                return Err(Error::InvalidWitnessProgramLength(program.len()));
-            }
-
-            // Specific segwit v0 check.
-            if version.to_u8() == 0 && (program.len() != 20 && program.len() != 32) {
-                return Err(Error::InvalidSegwitV0ProgramLength(program.len()));
-            }
-
+        // try bech32, note that upper or lowercase is allowed but NOT mixed case
+        let prefix = find_bech32_prefix(s);
+        let networks = [Network::Monacoin, Network::MonacoinTestnet, Network::MonacoinRegtest];
+        if let Some(&network) = networks.iter().find(|n| prefix.eq_ignore_ascii_case(n.bech32_hrp())) {
+            let (_, payload) = Payload::from_bech32(s)?;
             return Ok(Address {
-                payload: Payload::WitnessProgram {
-                    version: version,
-                    program: program,
-                },
+                payload: payload,
                 network: network,
             });
         }
+        // any other valid bech32 string, such as a Bitcoin address
+        if let Ok((hrp, _, _)) = bech32::decode(s) {
+            return Err(Error::UnknownBech32Hrp(hrp));
+        }
 
         // Base58
         if s.len() > 50 {
             return Err(Error::Base58(base58::Error::InvalidLength(s.len() * 11 / 15)));
         }
-        let data = base58::from_check(s)?;
-        if data.len() != 21 {
-            return Err(Error::Base58(base58::Error::InvalidLength(data.len())));
+        let (version, data) = base58::decode_check_version(s, &[&[50], &[55], &[111], &[117]])?;
+        if data.len() != 20 {
+            return Err(Error::Base58(base58::Error::InvalidLength(data.len() + 1)));
         }
 
-        let (network, payload) = match data[0] {
-            50 => (
+        let (network, payload) = match version {
+            0 => (
                 Network::Monacoin,
-                Payload::PubkeyHash(PubkeyHash::from_slice(&data[1..]).unwrap()),
+                Payload::PubkeyHash(PubkeyHash::from_slice(&data).unwrap()),
             ),
-            55 => (
+            1 => (
                 Network::Monacoin,
-                Payload::ScriptHash(ScriptHash::from_slice(&data[1..]).unwrap()),
+                Payload::ScriptHash(ScriptHash::from_slice(&data).unwrap()),
             ),
-            111 => (
+            2 => (
                 Network::MonacoinTestnet,
-                Payload::PubkeyHash(PubkeyHash::from_slice(&data[1..]).unwrap()),
+                Payload::PubkeyHash(PubkeyHash::from_slice(&data).unwrap()),
             ),
-            117 => (
+            _ => (
                 Network::MonacoinTestnet,
-                Payload::ScriptHash(ScriptHash::from_slice(&data[1..]).unwrap()),
+                Payload::ScriptHash(ScriptHash::from_slice(&data).unwrap()),
             ),
-            x => return Err(Error::Base58(base58::Error::InvalidVersion(vec![x]))),
         };
 
         Ok(Address {
@@ -491,7 +678,7 @@ impl FromStr for Address {
 
 impl ::std::fmt::Debug for Address {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        Display::fmt(self, f)
     }
 }
 
@@ -502,6 +689,7 @@ mod tests {
 
     use hashes::hex::{FromHex, ToHex};
 
+    use blockdata::constants::MAX_SCRIPT_SIZE;
     use blockdata::script::Script;
     use network::constants::Network::{Monacoin, MonacoinTestnet};
     use util::key::PublicKey;
@@ -527,6 +715,7 @@ mod tests {
             "script round-trip failed for {}",
             addr,
         );
+        assert!(addr.matches_script_pubkey(&addr.script_pubkey()), "script match failed for {}", addr);
         //TODO: add serde roundtrip after no-strason PR
     }
 
@@ -623,7 +812,7 @@ mod tests {
 
         // Test uncompressed pubkey
         key.compressed = false;
-        assert_eq!(Address::p2wpkh(&key, Monacoin), Err(Error::UncompressedPubkey));
+        assert_eq!(Address::p2shwpkh(&key, Monacoin), Err(Error::UncompressedPubkey));
     }
 
     #[test]
@@ -636,6 +825,51 @@ mod tests {
         roundtrips(&addr);
     }
 
+    #[test]
+    fn test_wsh_round_trips() {
+        let multisig = hex_script!("522103e5529d8eaa3d559903adb2e881eb06c86ac2574ffa503c45f4e942e2a693b33e2102e5f10fcdcdbab211e0af6a481f5532536ec61a5fdbf7183770cf8680fe729d8152ae");
+        // Neither of these could be spent sensibly, but both hash fine.
+        let empty = Script::new();
+        let oversized = Script::from(vec![0x61; MAX_SCRIPT_SIZE + 1]);
+
+        for script in &[multisig, empty, oversized] {
+            for &network in &[Monacoin, MonacoinTestnet] {
+                let native = Address::p2wsh(script, network);
+                assert_eq!(native.address_type(), Some(AddressType::P2wsh));
+                assert_eq!(native.script_pubkey(), Script::new_v0_p2wsh(&WScriptHash::hash(&script[..])));
+                roundtrips(&native);
+
+                let wrapped = Address::p2shwsh(script, network);
+                assert_eq!(wrapped.address_type(), Some(AddressType::P2sh));
+                assert_eq!(wrapped, Address::p2sh(&native.script_pubkey(), network));
+                roundtrips(&wrapped);
+
+                assert!(!native.matches_script_pubkey(&wrapped.script_pubkey()));
+                assert!(!wrapped.matches_script_pubkey(&native.script_pubkey()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches_script_pubkey() {
+        let addrs = [
+            "MXCeTRYF62fdUtHT8CJujoaVEduGf5hQP6",
+            "PD8zuohH1XJsLXctuAAYFRyXaAhR5qD44F",
+            "mona1qvzvkjn4q3nszqxrv3nraga2r822xjty3q96530",
+            "mona1qwqdg6squsna38e46795at95yu9atm8azzmyvckulcc7kytlcckxs824ha6",
+        ];
+        for a in addrs.iter() {
+            let addr = Address::from_str(a).unwrap();
+            for b in addrs.iter() {
+                let other = Address::from_str(b).unwrap();
+                assert_eq!(addr.matches_script_pubkey(&other.script_pubkey()), a == b);
+            }
+        }
+        let addr = Address::from_str(addrs[2]).unwrap();
+        assert!(!addr.matches_script_pubkey(&Script::new()));
+        assert!(!addr.matches_script_pubkey(&hex_script!("0014")));
+    }
+
     #[test]
     fn test_non_existent_segwit_version() {
         let version = 13;
@@ -658,8 +892,9 @@ mod tests {
         let valid_vectors = [
             ("MONA1Q4KPN6PSTHGD5UR894AUHJJ2G02WLGMP8KE08NE", "0014ad833d060bba1b4e0ce5af797949487a9df46c27"),
             ("mona1qp8f842ywwr9h5rdxyzggex7q3trvvvaarfssxccju52rj6htfzfsqr79j2", "002009d27aa88e70cb7a0da620908c9bc08ac6c633bd1a61036312e514396aeb4893"),
-            ("mona1zw508d6qejxtdg4y5r3zarvaryvhm3vz7", "5210751e76e8199196d454941c45d1b3a323"),
-            ("mona1sw50qpvnxy8", "6002751e"),
+            ("mona1zw508d6qejxtdg4y5r3zarvaryvz8pq8u", "5210751e76e8199196d454941c45d1b3a323"),
+            ("mona1sw50q5sr2p9", "6002751e"),
+            ("mona1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dpsarwqxu", "512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"),
             ("tmona1qfj8lu0rafk2mpvk7jj62q8eerjpex3xlcadtupkrkhh5a73htmhs68e55m", "00204c8ffe3c7d4d95b0b2de94b4a01f391c839344dfc75abe06c3b5ef4efa375eef"),
         ];
         for vector in &valid_vectors {
@@ -679,12 +914,143 @@ mod tests {
             "mona1zw508d6qejxtdg4y5r3zarvaryvq0fn2th",
             "tmona1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3pnfscjq",
             "mona1c0fp8z",
+            // witness versions above 0 must use bech32m (BIP350), and version 0 bech32
+            "mona1zw508d6qejxtdg4y5r3zarvaryvhm3vz7",
+            "mona1sw50qpvnxy8",
+            "mona1qw508d6qejxtdg4y5r3zarvary0c5xw7kag0lrk",
         ];
         for vector in &invalid_vectors {
             assert!(vector.parse::<Address>().is_err());
         }
     }
 
+    #[test]
+    fn test_parse_errors() {
+        use std::error::Error as StdError;
+
+        match "M9vQFWksNwMShpHKZJqDdMPFjkyGDRtxy0".parse::<Address>() {
+            Err(e @ Error::Base58(base58::Error::BadByte(b'0'))) => {
+                assert_eq!(e.source().unwrap().to_string(), base58::Error::BadByte(b'0').to_string());
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        match "M9vQFWksNwMShpHKZJqDdMPFjkyGDRtxym".parse::<Address>() {
+            Err(Error::Base58(base58::Error::BadChecksum(..))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match "mona1zw508d6qejxtdg4y5r3zarvaryvhm3vz7".parse::<Address>() {
+            Err(e @ Error::InvalidBech32Variant { .. }) => assert!(e.source().is_none()),
+            res => panic!("unexpected result {:?}", res),
+        }
+        match "mona1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".parse::<Address>() {
+            Err(e @ Error::Bech32(_)) => assert!(e.source().is_some()),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_bech32_hrp() {
+        assert_eq!(Monacoin.bech32_hrp(), "mona");
+        assert_eq!(MonacoinTestnet.bech32_hrp(), "tmona");
+        assert_eq!(Network::MonacoinRegtest.bech32_hrp(), "rmona");
+
+        // BIP173 test vector
+        let payload = Payload::WitnessProgram {
+            version: bech32::u5::try_from_u8(0).unwrap(),
+            program: Vec::from_hex("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap(),
+        };
+        let bitcoin = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        assert_eq!(payload.to_bech32_with_hrp("bc").unwrap(), bitcoin);
+        assert_eq!(payload.to_bech32_with_hrp("BC").unwrap(), bitcoin);
+        assert_eq!(Address::from_bech32_with_hrp(bitcoin, "bc"), Ok(payload.clone()));
+        assert_eq!(Address::from_bech32_with_hrp(&bitcoin.to_uppercase(), "bc"), Ok(payload.clone()));
+        match Address::from_str(bitcoin) {
+            Err(e @ Error::UnknownBech32Hrp(_)) => {
+                assert_eq!(e, Error::UnknownBech32Hrp("bc".to_owned()));
+                assert!(e.to_string().contains("\"bc\""));
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // the built-in networks use the same encoding
+        let mona = Address { payload: payload.clone(), network: Monacoin };
+        assert_eq!(payload.to_bech32_with_hrp("mona").unwrap(), mona.to_string());
+        assert_eq!(Address::from_bech32_with_hrp(&mona.to_string(), "mona"), Ok(payload.clone()));
+        assert_eq!(
+            Address::from_bech32_with_hrp(&mona.to_string(), "bc"),
+            Err(Error::UnexpectedBech32Hrp { expected: "bc".to_owned(), found: "mona".to_owned() })
+        );
+
+        // a custom prefix round trips, but isn't a known network
+        let custom = payload.to_bech32_with_hrp("monatest").unwrap();
+        assert!(custom.starts_with("monatest1q"));
+        assert_eq!(Address::from_bech32_with_hrp(&custom, "monatest"), Ok(payload.clone()));
+        assert_eq!(Address::from_str(&custom), Err(Error::UnknownBech32Hrp("monatest".to_owned())));
+
+        // BIP173 limits and mixed case
+        let long_hrp: String = ::std::iter::repeat('a').take(84).collect();
+        assert_eq!(payload.to_bech32_with_hrp(&long_hrp), Err(Error::Bech32(bech32::Error::InvalidLength)));
+        assert_eq!(payload.to_bech32_with_hrp("Mona"), Err(Error::Bech32(bech32::Error::MixedCase)));
+        let mixed = format!("mona1Q{}", &mona.to_string()[6..]);
+        assert_eq!(Address::from_str(&mixed), Err(Error::Bech32(bech32::Error::MixedCase)));
+        let mixed = format!("Mona{}", &mona.to_string()[4..]);
+        assert_eq!(Address::from_str(&mixed), Err(Error::Bech32(bech32::Error::MixedCase)));
+        assert_eq!(Address::from_str(&mona.to_string().to_uppercase()), Ok(mona));
+
+        let p2pkh = Payload::PubkeyHash(PubkeyHash::hash(&[]));
+        assert_eq!(p2pkh.to_bech32_with_hrp("mona"), Err(Error::NotWitnessProgram));
+    }
+
+    #[test]
+    fn test_p2tr() {
+        let secp = Secp256k1::verification_only();
+        // BIP341 wallet test vectors, whose addresses only differ in the HRP
+        let internal_key = XOnlyPublicKey::from_str("d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d").unwrap();
+        let addr = Address::p2tr(&secp, internal_key, None, Monacoin);
+        assert_eq!(&addr.to_string(), "mona1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dpsarwqxu");
+        assert_eq!(addr.address_type(), Some(AddressType::P2tr));
+        assert!(addr.is_standard());
+        roundtrips(&addr);
+        let program = match addr.payload {
+            Payload::WitnessProgram { ref program, .. } => program.clone(),
+            _ => unreachable!(),
+        };
+        let mut data = vec![bech32::u5::try_from_u8(1).unwrap()];
+        data.extend(bech32::ToBase32::to_base32(&program));
+        assert_eq!(
+            bech32::encode("bc", data, bech32::Variant::Bech32m).unwrap(),
+            "bc1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dps59h4z5"
+        );
+
+        let internal_key = XOnlyPublicKey::from_str("187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27").unwrap();
+        let merkle_root = TapBranchHash::from_hex("5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21").unwrap();
+        let addr = Address::p2tr(&secp, internal_key, Some(merkle_root), MonacoinTestnet);
+        assert_eq!(&addr.script_pubkey().as_bytes().to_hex(), "5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3");
+        roundtrips(&addr);
+    }
+
+    #[test]
+    fn test_qr_uri_and_network() {
+        let bech32: Address = "mona1qp8f842ywwr9h5rdxyzggex7q3trvvvaarfssxccju52rj6htfzfsqr79j2".parse().unwrap();
+        assert_eq!(format!("{:#}", bech32), "MONA1QP8F842YWWR9H5RDXYZGGEX7Q3TRVVVAARFSSXCCJU52RJ6HTFZFSQR79J2");
+        assert_eq!(bech32.to_qr_uri(), "monacoin:MONA1QP8F842YWWR9H5RDXYZGGEX7Q3TRVVVAARFSSXCCJU52RJ6HTFZFSQR79J2");
+        assert_eq!(format!("{:?}", bech32), bech32.to_string());
+        let base58: Address = "M9vQFWksNwMShpHKZJqDdMPFjkyGDRtxyn".parse().unwrap();
+        assert_eq!(base58.to_qr_uri(), "monacoin:M9vQFWksNwMShpHKZJqDdMPFjkyGDRtxyn");
+        assert_eq!(format!("{:#}", base58), base58.to_string());
+
+        assert!(bech32.is_valid_for_network(Monacoin));
+        assert!(!bech32.is_valid_for_network(MonacoinTestnet));
+        assert!(!base58.is_valid_for_network(MonacoinTestnet));
+        let testnet: Address = "mqkhEMH6NCeYjFybv7pvFC22MFeaNT9AQC".parse().unwrap();
+        assert!(testnet.is_valid_for_network(MonacoinTestnet));
+        assert!(testnet.is_valid_for_network(Network::MonacoinRegtest));
+        assert!(!testnet.is_valid_for_network(Monacoin));
+        let testnet: Address = "tmona1qfj8lu0rafk2mpvk7jj62q8eerjpex3xlcadtupkrkhh5a73htmhs68e55m".parse().unwrap();
+        assert!(testnet.is_valid_for_network(MonacoinTestnet));
+        assert!(!testnet.is_valid_for_network(Network::MonacoinRegtest));
+    }
+
     #[test]
     #[cfg(feature = "serde")]
     fn test_json_serialize() {
@@ -747,3 +1113,32 @@ mod tests {
         );
     }
 }
+
+#[cfg(all(test, feature = "unstable"))]
+mod benches {
+    use std::fmt::Write;
+    use test::Bencher;
+
+    use super::Address;
+
+    fn format_all(bh: &mut Bencher, addr: &Address) {
+        let mut buf = String::with_capacity(100);
+        bh.iter(|| {
+            for _ in 0..100_000 {
+                buf.clear();
+                write!(buf, "{}", addr).unwrap();
+                ::test::black_box(&buf);
+            }
+        });
+    }
+
+    #[bench]
+    pub fn bench_format_base58_100k(bh: &mut Bencher) {
+        format_all(bh, &"M9vQFWksNwMShpHKZJqDdMPFjkyGDRtxyn".parse().unwrap());
+    }
+
+    #[bench]
+    pub fn bench_format_bech32_100k(bh: &mut Bencher) {
+        format_all(bh, &"mona1qp8f842ywwr9h5rdxyzggex7q3trvvvaarfssxccju52rj6htfzfsqr79j2".parse().unwrap());
+    }
+}
@@ -14,6 +14,7 @@
 //! We refer to the documentation on the types for more information.
 //!
 
+
 use std::default;
 use std::error;
 use std::fmt::{self, Write};
@@ -0,0 +1,506 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Arbitrary Values
+//!
+//! Generators of random but structurally valid blockdata and network
+//! values, for property tests in this library and in libraries using it.
+//! Only available with the `test-utils` feature.
+//!
+//! Every generated value survives a consensus encoding round trip: scripts
+//! are sequences of opcodes and pushes no larger than the stack element
+//! limit, transactions have at least one input so that they can't be
+//! mistaken for the segwit marker, and witnesses are only generated on
+//! transactions. Hashes, signatures and amounts are random and carry no
+//! meaning. How large values get is bounded by [SizeParams].
+//!
+//! Generators for other types are built from the ones here:
+//!
+//! ```rust
+//! use monacoin::secp256k1::rand::{thread_rng, Rng};
+//! use monacoin::util::arbitrary::{Arbitrary, SizeParams};
+//! use monacoin::{OutPoint, Transaction};
+//!
+//! /// A transaction and one of its outputs
+//! struct Spendable {
+//!     tx: Transaction,
+//!     vout: u32,
+//! }
+//!
+//! impl Arbitrary for Spendable {
+//!     fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> Spendable {
+//!         let size = SizeParams { min_outputs: 1, ..*size };
+//!         let tx = Transaction::arbitrary(rng, &size);
+//!         let vout = rng.gen_range(0, tx.output.len() as u32);
+//!         Spendable { tx: tx, vout: vout }
+//!     }
+//! }
+//!
+//! let spendable = Spendable::arbitrary(&mut thread_rng(), &SizeParams::default());
+//! let outpoint = OutPoint::new(spendable.tx.txid(), spendable.vout);
+//! assert!((outpoint.vout as usize) < spendable.tx.output.len());
+//! ```
+
+
+use std::cmp;
+
+use hashes::Hash;
+use secp256k1::rand::Rng;
+
+use blockdata::block::{Block, BlockHeader};
+use blockdata::constants::MAX_SCRIPT_ELEMENT_SIZE;
+use blockdata::locktime::LockTime;
+use blockdata::script::{Builder, Script};
+use blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut};
+use consensus::encode::VarInt;
+use hash_types::{BlockHash, TxMerkleNode, Txid, WitnessMerkleNode, Wtxid};
+use network::address::Address;
+use network::constants::ServiceFlags;
+use network::message_blockdata::{GetBlocksMessage, GetHeadersMessage, Inventory, MAX_LOCATOR_SIZE};
+
+/// Bounds on the size of generated values.
+///
+/// The defaults keep values small, so that many of them can be generated
+/// quickly; raise them to exercise size limits.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SizeParams {
+    /// The maximum length of a script, in bytes
+    pub max_script_len: usize,
+    /// The maximum length of data pushed by a script, in bytes. Pushes are
+    /// never larger than [MAX_SCRIPT_ELEMENT_SIZE] regardless.
+    pub max_push_len: usize,
+    /// The maximum number of inputs of a transaction, at least one
+    pub max_inputs: usize,
+    /// The minimum number of outputs of a transaction
+    pub min_outputs: usize,
+    /// The maximum number of outputs of a transaction
+    pub max_outputs: usize,
+    /// The maximum number of elements of an input witness
+    pub max_witness_elements: usize,
+    /// The maximum length of a witness element, in bytes
+    pub max_witness_element_len: usize,
+    /// The maximum number of transactions in a block, at least one
+    pub max_transactions: usize,
+    /// The maximum length of other collections, such as block locators
+    pub max_collection_len: usize,
+}
+
+impl Default for SizeParams {
+    fn default() -> SizeParams {
+        SizeParams {
+            max_script_len: 100,
+            max_push_len: 80,
+            max_inputs: 4,
+            min_outputs: 0,
+            max_outputs: 4,
+            max_witness_elements: 4,
+            max_witness_element_len: 80,
+            max_transactions: 4,
+            max_collection_len: 8,
+        }
+    }
+}
+
+/// Types of which random values can be generated.
+pub trait Arbitrary: Sized {
+    /// Generate a random value within the bounds of `size`.
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> Self;
+}
+
+/// A length from `min` to `max`, both inclusive. A `max` below `min` is
+/// treated as `min`.
+fn len<R: Rng + ?Sized>(rng: &mut R, min: usize, max: usize) -> usize {
+    rng.gen_range(min, cmp::max(min, max) + 1)
+}
+
+/// Random bytes, at most `max_len` of them
+fn bytes<R: Rng + ?Sized>(rng: &mut R, max_len: usize) -> Vec<u8> {
+    let mut ret = vec![0; len(rng, 0, max_len)];
+    rng.fill_bytes(&mut ret);
+    ret
+}
+
+/// A random number of random values, from `min` to `max` of them
+fn values<T: Arbitrary, R: Rng + ?Sized>(rng: &mut R, size: &SizeParams, min: usize, max: usize) -> Vec<T> {
+    (0..len(rng, min, max)).map(|_| T::arbitrary(rng, size)).collect()
+}
+
+macro_rules! impl_arbitrary_gen {
+    ($($ty:ty),*) => {
+        $(
+            impl Arbitrary for $ty {
+                fn arbitrary<R: Rng + ?Sized>(rng: &mut R, _: &SizeParams) -> $ty {
+                    rng.gen()
+                }
+            }
+        )*
+    }
+}
+impl_arbitrary_gen!(bool, u8, u16, u32, u64, i8, i16, i32, i64);
+
+macro_rules! impl_arbitrary_hash {
+    ($($ty:ident),*) => {
+        $(
+            impl Arbitrary for $ty {
+                fn arbitrary<R: Rng + ?Sized>(rng: &mut R, _: &SizeParams) -> $ty {
+                    let mut inner = [0u8; 32];
+                    rng.fill_bytes(&mut inner);
+                    $ty::from_inner(inner)
+                }
+            }
+        )*
+    }
+}
+impl_arbitrary_hash!(Txid, Wtxid, BlockHash, TxMerkleNode, WitnessMerkleNode);
+
+impl<T: Arbitrary> Arbitrary for Vec<T> {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> Vec<T> {
+        values(rng, size, 0, size.max_collection_len)
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Option<T> {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> Option<T> {
+        if rng.gen() {
+            Some(T::arbitrary(rng, size))
+        } else {
+            None
+        }
+    }
+}
+
+impl Arbitrary for VarInt {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, _: &SizeParams) -> VarInt {
+        // spread the values over all encoding lengths
+        VarInt(rng.gen::<u64>() >> rng.gen_range(0, 64))
+    }
+}
+
+impl Arbitrary for Script {
+    /// A sequence of pushes and other opcodes. Pushes use the minimal push
+    /// opcode for their length, the other opcodes are random and need not
+    /// be defined.
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> Script {
+        let max_push_len = cmp::min(size.max_push_len, MAX_SCRIPT_ELEMENT_SIZE);
+        let max_len = len(rng, 0, size.max_script_len);
+        let mut ret = vec![];
+        loop {
+            let item = if rng.gen() {
+                Builder::new().push_slice(&bytes(rng, max_push_len)).into_script().into_bytes()
+            } else {
+                // anything above OP_PUSHDATA4
+                vec![rng.gen_range(0x4fu16, 0x100) as u8]
+            };
+            if ret.len() + item.len() > max_len {
+                break;
+            }
+            ret.extend(item);
+        }
+        Script::from(ret)
+    }
+}
+
+impl Arbitrary for OutPoint {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> OutPoint {
+        OutPoint::new(Txid::arbitrary(rng, size), rng.gen_range(0, 16))
+    }
+}
+
+impl Arbitrary for Sequence {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, _: &SizeParams) -> Sequence {
+        match rng.gen_range(0, 4) {
+            0 => Sequence::MAX,
+            1 => Sequence::ENABLE_RBF_NO_LOCKTIME,
+            _ => Sequence(rng.gen()),
+        }
+    }
+}
+
+impl Arbitrary for LockTime {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, _: &SizeParams) -> LockTime {
+        if rng.gen() {
+            LockTime::ZERO
+        } else {
+            LockTime::from_consensus(rng.gen())
+        }
+    }
+}
+
+impl Arbitrary for TxIn {
+    /// An input without witness, which isn't part of its consensus encoding;
+    /// see [Transaction] for inputs with witnesses.
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> TxIn {
+        TxIn {
+            previous_output: OutPoint::arbitrary(rng, size),
+            script_sig: Script::arbitrary(rng, size),
+            sequence: Sequence::arbitrary(rng, size),
+            witness: vec![],
+        }
+    }
+}
+
+impl Arbitrary for TxOut {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> TxOut {
+        TxOut {
+            value: rng.gen::<u64>() >> rng.gen_range(0, 64),
+            script_pubkey: Script::arbitrary(rng, size),
+        }
+    }
+}
+
+impl Arbitrary for Transaction {
+    /// A transaction with at least one input. Half of the transactions have
+    /// witnesses, which may all be empty, in which case the transaction is
+    /// serialized without them.
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> Transaction {
+        let mut input: Vec<TxIn> = values(rng, size, 1, size.max_inputs);
+        if rng.gen() {
+            for txin in input.iter_mut() {
+                txin.witness = (0..len(rng, 0, size.max_witness_elements))
+                    .map(|_| bytes(rng, size.max_witness_element_len))
+                    .collect();
+            }
+        }
+        Transaction {
+            version: rng.gen_range(1, 3),
+            lock_time: LockTime::arbitrary(rng, size),
+            input: input,
+            output: values(rng, size, size.min_outputs, size.max_outputs),
+        }
+    }
+}
+
+impl Arbitrary for BlockHeader {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> BlockHeader {
+        BlockHeader {
+            version: rng.gen(),
+            prev_blockhash: BlockHash::arbitrary(rng, size),
+            merkle_root: TxMerkleNode::arbitrary(rng, size),
+            time: rng.gen(),
+            bits: rng.gen(),
+            nonce: rng.gen(),
+        }
+    }
+}
+
+impl Arbitrary for Block {
+    /// A block with at least one transaction, the first of which has a single
+    /// input spending the null outpoint like a coinbase, and a header committing to them. The
+    /// proof of work isn't valid.
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> Block {
+        let mut block = Block {
+            header: BlockHeader::arbitrary(rng, size),
+            txdata: values(rng, size, 1, size.max_transactions),
+        };
+        block.txdata[0].input.truncate(1);
+        block.txdata[0].input[0].previous_output = OutPoint::null();
+        block.header.merkle_root = block.compute_merkle_root().expect("at least one transaction");
+        block
+    }
+}
+
+impl Arbitrary for ServiceFlags {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, _: &SizeParams) -> ServiceFlags {
+        ServiceFlags::from(rng.gen::<u64>())
+    }
+}
+
+impl Arbitrary for Address {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> Address {
+        Address {
+            services: ServiceFlags::arbitrary(rng, size),
+            address: rng.gen(),
+            port: rng.gen(),
+        }
+    }
+}
+
+impl Arbitrary for Inventory {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> Inventory {
+        match rng.gen_range(0, 8) {
+            0 => Inventory::Error,
+            1 => Inventory::Transaction(Txid::arbitrary(rng, size)),
+            2 => Inventory::Block(BlockHash::arbitrary(rng, size)),
+            3 => Inventory::WitnessTransaction(Txid::arbitrary(rng, size)),
+            4 => Inventory::WitnessBlock(BlockHash::arbitrary(rng, size)),
+            5 => Inventory::WTx(Wtxid::arbitrary(rng, size)),
+            6 => Inventory::CompactBlock(BlockHash::arbitrary(rng, size)),
+            _ => {
+                // a type which doesn't decode as one of the above
+                let inv_type = match rng.gen() {
+                    0 | 1 | 2 | 4 | 5 | 0x40000001 | 0x40000002 => 3,
+                    inv_type => inv_type,
+                };
+                let mut hash = [0u8; 32];
+                rng.fill_bytes(&mut hash);
+                Inventory::Unknown { inv_type: inv_type, hash: hash }
+            }
+        }
+    }
+}
+
+impl Arbitrary for GetBlocksMessage {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> GetBlocksMessage {
+        GetBlocksMessage {
+            version: rng.gen(),
+            locator_hashes: values(rng, size, 0, cmp::min(size.max_collection_len, MAX_LOCATOR_SIZE)),
+            stop_hash: BlockHash::arbitrary(rng, size),
+        }
+    }
+}
+
+impl Arbitrary for GetHeadersMessage {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R, size: &SizeParams) -> GetHeadersMessage {
+        GetHeadersMessage {
+            version: rng.gen(),
+            locator_hashes: values(rng, size, 0, cmp::min(size.max_collection_len, MAX_LOCATOR_SIZE)),
+            stop_hash: BlockHash::arbitrary(rng, size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use secp256k1::rand::thread_rng;
+
+    use blockdata::block::{Block, BlockHeader};
+    use blockdata::constants::MAX_SCRIPT_ELEMENT_SIZE;
+    use blockdata::locktime::LockTime;
+    use blockdata::script::{Instruction, Script};
+    use blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize, Decodable, Encodable, VarInt};
+    use hash_types::{BlockHash, Txid};
+    use network::address::Address;
+    use network::constants::ServiceFlags;
+    use network::message_blockdata::{GetBlocksMessage, GetHeadersMessage, Inventory};
+
+    use super::{Arbitrary, SizeParams};
+
+    /// Check that random values of `T` survive a consensus encoding round trip
+    fn roundtrip<T>(size: &SizeParams)
+        where T: Arbitrary + Encodable + Decodable + PartialEq + fmt::Debug
+    {
+        let mut rng = thread_rng();
+        for _ in 0..200 {
+            let value = T::arbitrary(&mut rng, size);
+            let encoded = serialize(&value);
+            match deserialize::<T>(&encoded) {
+                Ok(decoded) => assert_eq!(decoded, value),
+                Err(e) => panic!("{:?} doesn't decode from {:?}: {}", value, encoded, e),
+            }
+        }
+    }
+
+    fn roundtrip_all(size: &SizeParams) {
+        roundtrip::<u8>(size);
+        roundtrip::<u16>(size);
+        roundtrip::<u32>(size);
+        roundtrip::<u64>(size);
+        roundtrip::<i32>(size);
+        roundtrip::<i64>(size);
+        roundtrip::<bool>(size);
+        roundtrip::<VarInt>(size);
+        roundtrip::<Txid>(size);
+        roundtrip::<BlockHash>(size);
+        roundtrip::<Vec<u8>>(size);
+        roundtrip::<Vec<BlockHash>>(size);
+        roundtrip::<Script>(size);
+        roundtrip::<OutPoint>(size);
+        roundtrip::<Sequence>(size);
+        roundtrip::<LockTime>(size);
+        roundtrip::<TxIn>(size);
+        roundtrip::<TxOut>(size);
+        roundtrip::<Transaction>(size);
+        roundtrip::<Vec<Transaction>>(size);
+        roundtrip::<BlockHeader>(size);
+        roundtrip::<Block>(size);
+        roundtrip::<ServiceFlags>(size);
+        roundtrip::<Address>(size);
+        roundtrip::<Inventory>(size);
+        roundtrip::<Vec<Inventory>>(size);
+        roundtrip::<GetBlocksMessage>(size);
+        roundtrip::<GetHeadersMessage>(size);
+    }
+
+    #[test]
+    fn consensus_roundtrip() {
+        roundtrip_all(&SizeParams::default());
+    }
+
+    #[test]
+    fn consensus_roundtrip_sizes() {
+        roundtrip_all(&SizeParams {
+            max_script_len: 0,
+            max_push_len: 0,
+            max_inputs: 0,
+            min_outputs: 0,
+            max_outputs: 0,
+            max_witness_elements: 0,
+            max_witness_element_len: 0,
+            max_transactions: 0,
+            max_collection_len: 0,
+        });
+        roundtrip_all(&SizeParams {
+            max_script_len: 2000,
+            max_push_len: 1000,
+            max_inputs: 2,
+            min_outputs: 1,
+            max_outputs: 2,
+            max_witness_elements: 2,
+            max_witness_element_len: 1000,
+            max_transactions: 2,
+            max_collection_len: 200,
+        });
+    }
+
+    #[test]
+    fn size_bounds() {
+        let mut rng = thread_rng();
+        let size = SizeParams {
+            max_script_len: 1000,
+            max_push_len: 1000,
+            min_outputs: 1,
+            ..Default::default()
+        };
+        let mut witnesses = false;
+        for _ in 0..200 {
+            let tx = Transaction::arbitrary(&mut rng, &size);
+            assert!(1 <= tx.input.len() && tx.input.len() <= size.max_inputs);
+            assert!(1 <= tx.output.len() && tx.output.len() <= size.max_outputs);
+            witnesses |= tx.has_witness();
+
+            let scripts = tx.input.iter().map(|txin| &txin.script_sig)
+                .chain(tx.output.iter().map(|txout| &txout.script_pubkey));
+            for script in scripts {
+                assert!(script.len() <= size.max_script_len);
+                for instruction in script.instructions() {
+                    if let Instruction::PushBytes(data) = instruction.unwrap() {
+                        assert!(data.len() <= MAX_SCRIPT_ELEMENT_SIZE);
+                    }
+                }
+            }
+            for txin in &tx.input {
+                assert!(txin.witness.len() <= size.max_witness_elements);
+                assert!(txin.witness.iter().all(|elem| elem.len() <= size.max_witness_element_len));
+            }
+        }
+        assert!(witnesses);
+
+        let block = Block::arbitrary(&mut rng, &size);
+        assert!(block.txdata[0].is_coin_base());
+        assert!(block.check_merkle_root());
+    }
+}
@@ -14,9 +14,11 @@
 
 //! Base58 encoder and decoder
 
-use std::{error, fmt, str, slice, iter};
 
-use hashes::{sha256d, Hash};
+use std::{fmt, str, slice, iter};
+use std::error;
+
+use hashes::{sha256d, Hash, HashEngine};
 
 use util::endian;
 
@@ -35,8 +37,8 @@ pub enum Error {
     InvalidVersion(Vec<u8>),
     /// Checked data was less than 4 bytes
     TooShort(usize),
-    /// Any other error
-    Other(String)
+    /// The string is longer than [MAX_INPUT_LENGTH] characters
+    InputTooLong(usize),
 }
 
 impl fmt::Display for Error {
@@ -47,7 +49,7 @@ impl fmt::Display for Error {
             Error::InvalidLength(ell) => write!(f, "length {} invalid for this base58 type", ell),
             Error::InvalidVersion(ref v) => write!(f, "version {:?} invalid for this base58 type", v),
             Error::TooShort(_) => write!(f, "base58ck data not even long enough for a checksum"),
-            Error::Other(ref s) => f.write_str(s)
+            Error::InputTooLong(len) => write!(f, "base58 string of {} characters is too long to decode", len),
         }
     }
 }
@@ -96,6 +98,11 @@ impl<T: Default + Copy> SmallVec<T> {
     }
 }
 
+/// The longest string which is decoded. Decoding takes time quadratic in the
+/// length of the string, and the longest base58 strings in use, extended keys,
+/// are 111 characters.
+pub const MAX_INPUT_LENGTH: usize = 200;
+
 static BASE58_CHARS: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
 
 static BASE58_DIGITS: [Option<u8>; 128] = [
@@ -119,6 +126,9 @@ static BASE58_DIGITS: [Option<u8>; 128] = [
 
 /// Decode base58-encoded string into a byte vector
 pub fn from(data: &str) -> Result<Vec<u8>, Error> {
+    if data.len() > MAX_INPUT_LENGTH {
+        return Err(Error::InputTooLong(data.len()));
+    }
     // 11/15 is just over log_256(58)
     let mut scratch = vec![0u8; 1 + data.len() * 11 / 15];
     // Build in base 256
@@ -165,6 +175,23 @@ pub fn from_check(data: &str) -> Result<Vec<u8>, Error> {
     Ok(ret)
 }
 
+/// Decode a base58check-encoded string which starts with one of the
+/// `expected_versions`, returning the index of the version which matched and
+/// the payload after it. The first matching version wins, so longer versions
+/// sharing a prefix with shorter ones must come first.
+pub fn decode_check_version(s: &str, expected_versions: &[&[u8]]) -> Result<(usize, Vec<u8>), Error> {
+    let mut data = from_check(s)?;
+    for (i, version) in expected_versions.iter().enumerate() {
+        if data.starts_with(version) {
+            data.drain(..version.len());
+            return Ok((i, data));
+        }
+    }
+    let len = expected_versions.iter().map(|v| v.len()).max().unwrap_or(1);
+    data.truncate(len);
+    Err(Error::InvalidVersion(data))
+}
+
 fn format_iter<I, W>(writer: &mut W, data: I) -> Result<(), fmt::Error>
 where
     I: Iterator<Item = u8> + Clone,
@@ -206,6 +233,22 @@ where
     Ok(())
 }
 
+/// Iterator over a base58check object: the version, the payload and the
+/// checksum of both
+fn check_iter<'a>(version: &'a [u8], payload: &'a [u8], checksum: &'a sha256d::Hash)
+    -> iter::Chain<iter::Chain<slice::Iter<'a, u8>, slice::Iter<'a, u8>>, slice::Iter<'a, u8>>
+{
+    version.iter().chain(payload.iter()).chain(checksum[0..4].iter())
+}
+
+/// The base58check checksum of `version` followed by `payload`
+fn checksum(version: &[u8], payload: &[u8]) -> sha256d::Hash {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(version);
+    engine.input(payload);
+    sha256d::Hash::from_engine(engine)
+}
+
 fn encode_iter<I>(data: I) -> String
 where
     I: Iterator<Item = u8> + Clone,
@@ -232,6 +275,26 @@ pub fn check_encode_slice(data: &[u8]) -> String {
     )
 }
 
+/// Directly encode a slice as base58 into a formatter, without allocating
+/// for data up to about 70 bytes.
+pub fn encode_slice_to_fmt(fmt: &mut fmt::Formatter, data: &[u8]) -> fmt::Result {
+    format_iter(fmt, data.iter().cloned())
+}
+
+/// Obtain a string with the base58check encoding of a payload prefixed with
+/// a version of any length, such as the four bytes of extended keys.
+pub fn encode_check_with_version(version: &[u8], payload: &[u8]) -> String {
+    let checksum = checksum(version, payload);
+    encode_iter(check_iter(version, payload, &checksum).cloned())
+}
+
+/// Write the base58check encoding of a payload prefixed with a version of any
+/// length into a formatter, see [encode_check_with_version].
+pub fn encode_check_with_version_to_fmt(fmt: &mut fmt::Formatter, version: &[u8], payload: &[u8]) -> fmt::Result {
+    let checksum = checksum(version, payload);
+    format_iter(fmt, check_iter(version, payload, &checksum).cloned())
+}
+
 /// Obtain a string with the base58check encoding of a slice
 /// (Tack the first 4 256-digits of the object's Bitcoin hash onto the end.)
 pub fn check_encode_slice_to_fmt(fmt: &mut fmt::Formatter, data: &[u8]) -> fmt::Result {
@@ -305,5 +368,60 @@ mod tests {
         assert_eq!(from_check(&encode_slice(&[1,2,3])), Err(Error::TooShort(3)));
 
     }
-}
 
+    #[test]
+    fn test_base58_version() {
+        // the BIP32 test vector 1 master key
+        let payload = Vec::from_hex("000000000000000000873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d50800e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35").unwrap();
+        let xprv = [0x04, 0x88, 0xAD, 0xE4];
+        let xpub = [0x04, 0x88, 0xB2, 0x1E];
+        let s = encode_check_with_version(&xprv, &payload);
+        assert_eq!(s, "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi");
+        let mut data = xprv.to_vec();
+        data.extend_from_slice(&payload);
+        assert_eq!(s, check_encode_slice(&data));
+        assert_eq!(decode_check_version(&s, &[&xpub, &xprv]), Ok((1, payload.clone())));
+        assert_eq!(decode_check_version(&s, &[&xpub]), Err(Error::InvalidVersion(xprv.to_vec())));
+        assert_eq!(decode_check_version(&s, &[&[0x04], &xpub]), Ok((0, data[1..].to_vec())));
+
+        assert_eq!(decode_check_version("1PfJpZsjreyVrqeoAfabrRwwjQyoSQMmHH", &[&[5], &[0]]),
+                   Ok((1, Vec::from_hex("f8917303bfa8ef24f292e8fa1419b20460ba064d").unwrap())));
+        assert_eq!(decode_check_version("1PfJpZsjreyVrqeoAfabrRwwjQyoSQMmHH", &[&[5]]), Err(Error::InvalidVersion(vec![0])));
+
+        struct Base58<'a>(&'a [u8]);
+        impl<'a> fmt::Display for Base58<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                encode_slice_to_fmt(f, self.0)
+            }
+        }
+        struct Check<'a>(&'a [u8], &'a [u8]);
+        impl<'a> fmt::Display for Check<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                encode_check_with_version_to_fmt(f, self.0, self.1)
+            }
+        }
+        assert_eq!(Base58(&[0, 13, 36]).to_string(), "1211");
+        assert_eq!(Check(&xprv, &payload).to_string(), s);
+    }
+
+    #[test]
+    fn test_base58_errors() {
+        // changing any character breaks the checksum
+        let s = "1PfJpZsjreyVrqeoAfabrRwwjQyoSQMmHH";
+        for i in 1..s.len() {
+            let mut corrupted = s.as_bytes().to_vec();
+            corrupted[i] = if corrupted[i] == b'2' { b'3' } else { b'2' };
+            match from_check(str::from_utf8(&corrupted).unwrap()) {
+                Err(Error::BadChecksum(..)) => {}
+                r => panic!("unexpected result {:?} for {}", r, i),
+            }
+        }
+
+        // long strings are rejected before decoding
+        let long = "2".repeat(10_000);
+        assert_eq!(from(&long), Err(Error::InputTooLong(10_000)));
+        assert_eq!(from_check(&long), Err(Error::InputTooLong(10_000)));
+        assert_eq!(decode_check_version(&long, &[&[0]]), Err(Error::InputTooLong(10_000)));
+        assert!(from(&"2".repeat(MAX_INPUT_LENGTH)).is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Base64 encoder and decoder
+//!
+//! The standard alphabet with padding, as specified in RFC 4648, which is the
+//! encoding BIP174 uses for PSBTs. Decoding is strict: only canonical
+//! encodings, i.e. those which [encode] produces, are accepted.
+//!
+
+
+use std::fmt;
+use std::error;
+
+const ALPHABET: &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const PAD: u8 = b'=';
+
+/// An error that might occur during base64 decoding
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Error {
+    /// Invalid character at the given position
+    BadByte(u8, usize),
+    /// The length is not a multiple of four
+    InvalidLength(usize),
+    /// Padding is misplaced, or the bits it stands in for are not zero
+    NonCanonicalPadding,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BadByte(b, pos) => write!(f, "invalid base64 character 0x{:x} at position {}", b, pos),
+            Error::InvalidLength(len) => write!(f, "base64 length {} is not a multiple of four", len),
+            Error::NonCanonicalPadding => f.write_str("non-canonical base64 padding"),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// Encode bytes as base64, with padding
+pub fn encode(data: &[u8]) -> String {
+    let mut ret = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                ret.push(PAD as char);
+            }
+        }
+    }
+    ret
+}
+
+/// Decode a canonical, padded base64 string
+pub fn decode(data: &str) -> Result<Vec<u8>, Error> {
+    let data = data.as_bytes();
+    if data.len() % 4 != 0 {
+        return Err(Error::InvalidLength(data.len()));
+    }
+
+    let mut ret = Vec::with_capacity(data.len() / 4 * 3);
+    for (chunk_idx, chunk) in data.chunks(4).enumerate() {
+        let last = (chunk_idx + 1) * 4 == data.len();
+        let padding = chunk.iter().rev().take_while(|&&b| b == PAD).count();
+        if padding > 2 || (padding > 0 && !last) {
+            return Err(Error::NonCanonicalPadding);
+        }
+
+        let mut n = 0u32;
+        for (i, &b) in chunk[..4 - padding].iter().enumerate() {
+            let digit = match ALPHABET.iter().position(|&c| c == b) {
+                Some(digit) => digit as u32,
+                None => return Err(Error::BadByte(b, chunk_idx * 4 + i)),
+            };
+            n |= digit << (18 - 6 * i);
+        }
+        // the bits of the last digit which don't make it into a byte must be zero
+        if n & ((1 << (8 * padding)) - 1) != 0 {
+            return Err(Error::NonCanonicalPadding);
+        }
+        for i in 0..3 - padding {
+            ret.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for &(plain, encoded) in &vectors {
+            assert_eq!(encode(plain.as_bytes()), encoded);
+            assert_eq!(decode(encoded), Ok(plain.as_bytes().to_vec()));
+        }
+
+        let all: Vec<u8> = (0..256).map(|b| b as u8).collect();
+        assert_eq!(decode(&encode(&all)), Ok(all));
+    }
+
+    #[test]
+    fn strict_decoding() {
+        // missing or superfluous padding
+        assert_eq!(decode("Zg"), Err(Error::InvalidLength(2)));
+        assert_eq!(decode("Zm9v="), Err(Error::InvalidLength(5)));
+        assert_eq!(decode("Zg==Zm9v"), Err(Error::NonCanonicalPadding));
+        assert_eq!(decode("Z==="), Err(Error::NonCanonicalPadding));
+        assert_eq!(decode("===="), Err(Error::NonCanonicalPadding));
+        // padding stands in for non-zero bits
+        assert_eq!(decode("Zh=="), Err(Error::NonCanonicalPadding));
+        assert_eq!(decode("Zm9="), Err(Error::NonCanonicalPadding));
+        // characters outside the alphabet, including the URL-safe ones and whitespace
+        assert_eq!(decode("Zm9v\n"), Err(Error::InvalidLength(5)));
+        assert_eq!(decode("Zm-v"), Err(Error::BadByte(b'-', 2)));
+        assert_eq!(decode("Zm9vY_=="), Err(Error::BadByte(b'_', 5)));
+        assert_eq!(decode("Zm=v"), Err(Error::BadByte(b'=', 2)));
+    }
+}
@@ -19,18 +19,21 @@
 //! signatures, which are placed in the scriptSig.
 //!
 
+
 use hashes::{Hash, sha256d};
 use hash_types::SigHash;
 use blockdata::script::Script;
 use blockdata::transaction::{Transaction, TxIn, SigHashType};
 use consensus::encode::Encodable;
+use util::sighash;
 
+use std::borrow::{Borrow, BorrowMut};
 use std::ops::{Deref, DerefMut};
 
 /// Parts of a sighash which are common across inputs or signatures, and which are
 /// sufficient (in conjunction with a private key) to sign the transaction
 #[derive(Clone, PartialEq, Eq, Debug)]
-#[deprecated(since="0.24.0", note="please use `SigHashCache` instead")]
+#[deprecated(since="0.24.0", note="please use `util::sighash::SighashCache` instead")]
 pub struct SighashComponents {
     tx_version: i32,
     tx_locktime: u32,
@@ -75,7 +78,7 @@ impl SighashComponents {
 
         SighashComponents {
             tx_version: tx.version,
-            tx_locktime: tx.lock_time,
+            tx_locktime: tx.lock_time.to_consensus_u32(),
             hash_prevouts: hash_prevouts,
             hash_sequence: hash_sequence,
             hash_outputs: hash_outputs,
@@ -104,132 +107,83 @@ impl SighashComponents {
 }
 
 /// A replacement for SigHashComponents which supports all sighash modes
+///
+/// All signature hashes are computed by [sighash::SighashCache], which this wraps.
+#[deprecated(since="0.25.0", note="please use `util::sighash::SighashCache` instead")]
 pub struct SigHashCache<R: Deref<Target=Transaction>> {
-    /// Access to transaction required for various introspection
-    tx: R,
-    /// Hash of all the previous outputs, computed as required
-    hash_prevouts: Option<sha256d::Hash>,
-    /// Hash of all the input sequence nos, computed as required
-    hash_sequence: Option<sha256d::Hash>,
-    /// Hash of all the outputs in this transaction, computed as required
-    hash_outputs: Option<sha256d::Hash>,
+    cache: sighash::SighashCache<DerefTransaction<R>>,
+}
+
+/// Lets a pointer to a transaction be used with [sighash::SighashCache]
+struct DerefTransaction<R>(R);
+
+impl<R: Deref<Target=Transaction>> Borrow<Transaction> for DerefTransaction<R> {
+    fn borrow(&self) -> &Transaction {
+        &self.0
+    }
 }
 
+impl<R: DerefMut<Target=Transaction>> BorrowMut<Transaction> for DerefTransaction<R> {
+    fn borrow_mut(&mut self) -> &mut Transaction {
+        &mut self.0
+    }
+}
+
+#[allow(deprecated)]
 impl<R: Deref<Target=Transaction>> SigHashCache<R> {
     /// Compute the sighash components from an unsigned transaction and auxiliary
     /// in a lazy manner when required.
     /// For the generated sighashes to be valid, no fields in the transaction may change except for
     /// script_sig and witnesses.
     pub fn new(tx: R) -> Self {
-        SigHashCache {
-            tx: tx,
-            hash_prevouts: None,
-            hash_sequence: None,
-            hash_outputs: None,
-        }
+        SigHashCache { cache: sighash::SighashCache::new(DerefTransaction(tx)) }
     }
 
     /// Calculate hash for prevouts
     pub fn hash_prevouts(&mut self) -> sha256d::Hash {
-        let hash_prevout = &mut self.hash_prevouts;
-        let input = &self.tx.input;
-        *hash_prevout.get_or_insert_with(|| {
-            let mut enc = sha256d::Hash::engine();
-            for txin in input {
-                txin.previous_output.consensus_encode(&mut enc).unwrap();
-            }
-            sha256d::Hash::from_engine(enc)
-        })
+        self.cache.hash_prevouts()
     }
 
     /// Calculate hash for input sequence values
     pub fn hash_sequence(&mut self) -> sha256d::Hash {
-        let hash_sequence = &mut self.hash_sequence;
-        let input = &self.tx.input;
-        *hash_sequence.get_or_insert_with(|| {
-            let mut enc = sha256d::Hash::engine();
-            for txin in input {
-                txin.sequence.consensus_encode(&mut enc).unwrap();
-            }
-            sha256d::Hash::from_engine(enc)
-        })
+        self.cache.hash_sequence()
     }
 
     /// Calculate hash for outputs
     pub fn hash_outputs(&mut self) -> sha256d::Hash {
-        let hash_output = &mut self.hash_outputs;
-        let output = &self.tx.output;
-        *hash_output.get_or_insert_with(|| {
-            let mut enc = sha256d::Hash::engine();
-            for txout in output {
-                txout.consensus_encode(&mut enc).unwrap();
-            }
-            sha256d::Hash::from_engine(enc)
-        })
+        self.cache.hash_outputs()
     }
 
     /// Compute the BIP143 sighash for any flag type. See SighashComponents::sighash_all simpler
     /// API for the most common case
+    ///
+    /// For P2WPKH inputs `script_code` is not the output script but the P2PKH script of the
+    /// same key hash, see [Script::p2wpkh_script_code].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `input_index` is out of bounds, which
+    /// [sighash::SighashCache::segwit_signature_hash] returns as an error instead.
     pub fn signature_hash(&mut self, input_index: usize, script_code: &Script, value: u64, sighash_type: SigHashType) -> SigHash {
-
-        let zero_hash = sha256d::Hash::default();
-
-        let (sighash, anyone_can_pay) = sighash_type.split_anyonecanpay_flag();
-
-        let mut enc = SigHash::engine();
-        self.tx.version.consensus_encode(&mut enc).unwrap();
-
-        if !anyone_can_pay {
-            self.hash_prevouts().consensus_encode(&mut enc).unwrap();
-        } else {
-            zero_hash.consensus_encode(&mut enc).unwrap();
-        }
-
-        if !anyone_can_pay && sighash != SigHashType::Single && sighash != SigHashType::None {
-            self.hash_sequence().consensus_encode(&mut enc).unwrap();
-        } else {
-            zero_hash.consensus_encode(&mut enc).unwrap();
-        }
-
-        {
-            let txin = &self.tx.input[input_index];
-
-            txin
-                .previous_output
-                .consensus_encode(&mut enc)
-                .unwrap();
-            script_code.consensus_encode(&mut enc).unwrap();
-            value.consensus_encode(&mut enc).unwrap();
-            txin.sequence.consensus_encode(&mut enc).unwrap();
-        }
-
-        if sighash != SigHashType::Single && sighash != SigHashType::None {
-            self.hash_outputs().consensus_encode(&mut enc).unwrap();
-        } else if sighash == SigHashType::Single && input_index < self.tx.output.len() {
-            let mut single_enc = SigHash::engine();
-            self.tx.output[input_index].consensus_encode(&mut single_enc).unwrap();
-            SigHash::from_engine(single_enc).consensus_encode(&mut enc).unwrap();
-        } else {
-            zero_hash.consensus_encode(&mut enc).unwrap();
-        }
-
-        self.tx.lock_time.consensus_encode(&mut enc).unwrap();
-        sighash_type.as_u32().consensus_encode(&mut enc).unwrap();
-        SigHash::from_engine(enc)
+        self.cache.segwit_signature_hash(input_index, script_code, value, sighash_type)
+            .expect("input index out of bounds")
     }
 }
 
+#[allow(deprecated)]
 impl<R: DerefMut<Target=Transaction>> SigHashCache<R> {
     /// When the SigHashCache is initialized with a mutable reference to a transaction instead of a
     /// regular reference, this method is available to allow modification to the witnesses.
     ///
     /// This allows in-line signing such as
     /// ```
+    /// # #![allow(deprecated)]
+    /// use monacoin::blockdata::locktime::LockTime;
     /// use monacoin::blockdata::transaction::{Transaction, SigHashType};
     /// use monacoin::util::bip143::SigHashCache;
     /// use monacoin::Script;
     ///
-    /// let mut tx_to_sign = Transaction { version: 2, lock_time: 0, input: Vec::new(), output: Vec::new() };
+    /// let mut tx_to_sign = Transaction { version: 2, lock_time: LockTime::ZERO, input: Vec::new(), output: Vec::new() };
     /// let input_count = tx_to_sign.input.len();
     ///
     /// let mut sig_hasher = SigHashCache::new(&mut tx_to_sign);
@@ -241,7 +195,7 @@ impl<R: DerefMut<Target=Transaction>> SigHashCache<R> {
     /// }
     /// ```
     pub fn access_witness(&mut self, input_index: usize) -> &mut Vec<Vec<u8>> {
-        &mut self.tx.input[input_index].witness
+        self.cache.access_witness(input_index)
     }
 }
 
@@ -272,7 +226,7 @@ mod tests {
         let raw_expected = SigHash::from_hex(expected_result).unwrap();
         let expected_result = SigHash::from_slice(&raw_expected[..]).unwrap();
         let mut cache = SigHashCache::new(&tx);
-        let sighash_type = SigHashType::from_u32(hash_type);
+        let sighash_type = SigHashType::from_u32_consensus(hash_type);
         let actual_result = cache.signature_hash(input_index, &script, value, sighash_type);
         assert_eq!(actual_result, expected_result);
     }
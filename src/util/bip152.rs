@@ -0,0 +1,562 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # BIP152 Compact Blocks
+//!
+//! Implementation of compact blocks data structure and algorithms.
+//!
+//! Transaction and prefilled indexes are differentially encoded on the wire
+//! and limited to 16 bits, as in Bitcoin Core.
+
+
+use std::fmt;
+use std::io;
+use std::error;
+
+use hashes::{sha256, siphash24, Hash};
+use hash_types::BlockHash;
+
+use blockdata::block::{Block, BlockHeader};
+use blockdata::transaction::Transaction;
+use consensus::encode::{self, Decodable, Encodable, MAX_VEC_SIZE};
+use util::endian;
+
+/// Largest transaction index which can be encoded.
+const MAX_INDEX: u64 = 0xffff;
+
+/// A BIP152 error
+#[derive(Clone, PartialEq, Eq, Debug, Copy, Hash)]
+pub enum Error {
+    /// An unknown version number was used.
+    UnknownVersion,
+    /// The prefill slice provided was invalid.
+    InvalidPrefill,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownVersion => f.write_str("an unknown version number was used"),
+            Error::InvalidPrefill => f.write_str("the prefill slice provided was invalid"),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// A transaction requested with [BlockTransactionsRequest] is not in the block.
+#[derive(Clone, PartialEq, Eq, Debug, Copy, Hash)]
+pub struct TxIndexOutOfRangeError(pub u64);
+
+impl fmt::Display for TxIndexOutOfRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "index {} is out-of-bounds of the block's transactions", self.0)
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for TxIndexOutOfRangeError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// A [PrefilledTransaction] structure is used in [HeaderAndShortIds] to
+/// provide a list of a few transactions explicitly.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PrefilledTransaction {
+    /// The index of the transaction in the block, differentially encoded:
+    /// the number of transactions since the previous prefilled transaction.
+    pub idx: u16,
+    /// The actual transaction, without witness data for version 1 compact
+    /// blocks.
+    pub tx: Transaction,
+}
+
+impl Encodable for PrefilledTransaction {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        Ok(encode::write_compact_size(&mut s, self.idx as u64)? + self.tx.consensus_encode(s)?)
+    }
+}
+
+impl Decodable for PrefilledTransaction {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let idx = encode::read_compact_size(&mut d)?;
+        if idx > MAX_INDEX {
+            return Err(encode::Error::ParseFailed("BIP152 prefilled tx index out of bounds"));
+        }
+        Ok(PrefilledTransaction {
+            idx: idx as u16,
+            tx: Decodable::consensus_decode(d)?,
+        })
+    }
+}
+
+/// Short transaction IDs are used to represent a transaction without sending
+/// a full 256-bit hash.
+#[derive(Default)]
+pub struct ShortId([u8; 6]);
+impl_array_newtype!(ShortId, u8, 6);
+impl_array_newtype_show!(ShortId);
+impl_bytes_newtype!(ShortId, 6);
+
+impl ShortId {
+    /// Calculate the SipHash24 keys used to calculate short IDs: the first
+    /// 16 bytes of the SHA256 of the header and nonce.
+    pub fn calculate_siphash_keys(header: &BlockHeader, nonce: u64) -> (u64, u64) {
+        let mut engine = sha256::Hash::engine();
+        header.consensus_encode(&mut engine).expect("engines don't error");
+        nonce.consensus_encode(&mut engine).expect("engines don't error");
+        let hash = sha256::Hash::from_engine(engine);
+        (endian::slice_to_u64_le(&hash[0..8]), endian::slice_to_u64_le(&hash[8..16]))
+    }
+
+    /// Calculate the short ID of a txid or wtxid with the given SipHash24 keys.
+    pub fn with_siphash_keys<T: AsRef<[u8]>>(txid: &T, siphash_keys: (u64, u64)) -> ShortId {
+        // The lowest 6 bytes of the SipHash, little endian
+        let hash = siphash24::Hash::hash_to_u64_with_keys(siphash_keys.0, siphash_keys.1, txid.as_ref());
+        let mut id = [0; 6];
+        id.copy_from_slice(&endian::u64_to_array_le(hash)[0..6]);
+        ShortId(id)
+    }
+}
+
+impl Encodable for ShortId {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl Decodable for ShortId {
+    fn consensus_decode<D: io::Read>(d: D) -> Result<ShortId, encode::Error> {
+        Ok(ShortId(Decodable::consensus_decode(d)?))
+    }
+}
+
+/// Decode the count of a vector of `item_size` byte items, limiting it to
+/// the 16-bit transaction indexes.
+fn decode_count<D: io::Read>(d: D, item_size: usize) -> Result<usize, encode::Error> {
+    let count = encode::read_compact_size(d)?;
+    if count > MAX_INDEX + 1 {
+        return Err(encode::Error::ParseFailed("BIP152 index count overflows 16 bits"));
+    }
+    let byte_size = count as usize * item_size;
+    if byte_size > MAX_VEC_SIZE {
+        return Err(encode::Error::OversizedVectorAllocation { requested: byte_size, max: MAX_VEC_SIZE });
+    }
+    Ok(count as usize)
+}
+
+/// A [HeaderAndShortIds] structure is used to relay a block header, the short
+/// transactions IDs used for matching already-available transactions, and a
+/// select few transactions which we expect a peer may be missing.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct HeaderAndShortIds {
+    /// The header of the block being provided.
+    pub header: BlockHeader,
+    /// A nonce for use in short transaction ID calculations.
+    pub nonce: u64,
+    /// The short transaction IDs calculated from the transactions
+    /// which were not provided explicitly in prefilled_txs.
+    pub short_ids: Vec<ShortId>,
+    /// Used to provide the coinbase transaction and a select few
+    /// which we expect a peer may be missing.
+    pub prefilled_txs: Vec<PrefilledTransaction>,
+}
+
+impl Encodable for HeaderAndShortIds {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = self.header.consensus_encode(&mut s)?;
+        len += self.nonce.consensus_encode(&mut s)?;
+        len += encode::write_compact_size(&mut s, self.short_ids.len() as u64)?;
+        for short_id in &self.short_ids {
+            len += short_id.consensus_encode(&mut s)?;
+        }
+        len += encode::write_compact_size(&mut s, self.prefilled_txs.len() as u64)?;
+        for tx in &self.prefilled_txs {
+            len += tx.consensus_encode(&mut s)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for HeaderAndShortIds {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let header = Decodable::consensus_decode(&mut d)?;
+        let nonce = Decodable::consensus_decode(&mut d)?;
+
+        let count = decode_count(&mut d, 6)?;
+        let mut short_ids = Vec::with_capacity(encode::prealloc_capacity::<ShortId>(count as u64));
+        for _ in 0..count {
+            short_ids.push(Decodable::consensus_decode(&mut d)?);
+        }
+
+        // don't preallocate for the transactions, their size is unknown
+        let count = decode_count(&mut d, 1)?;
+        let mut prefilled_txs: Vec<PrefilledTransaction> = vec![];
+        let mut next_idx = 0u64;
+        for _ in 0..count {
+            let tx: PrefilledTransaction = Decodable::consensus_decode(&mut d)?;
+            next_idx += tx.idx as u64;
+            if next_idx > MAX_INDEX {
+                return Err(encode::Error::ParseFailed("BIP152 prefilled tx index out of bounds"));
+            }
+            next_idx += 1;
+            prefilled_txs.push(tx);
+        }
+        if short_ids.len() + prefilled_txs.len() > MAX_INDEX as usize + 1 {
+            return Err(encode::Error::ParseFailed("BIP152 index count overflows 16 bits"));
+        }
+
+        Ok(HeaderAndShortIds {
+            header: header,
+            nonce: nonce,
+            short_ids: short_ids,
+            prefilled_txs: prefilled_txs,
+        })
+    }
+}
+
+impl HeaderAndShortIds {
+    /// Create a new [HeaderAndShortIds] from a full block.
+    ///
+    /// The version number must be either 1 or 2: version 1 short IDs are
+    /// calculated from txids and version 2 ones from wtxids.
+    ///
+    /// The `prefill` slice indicates which transactions should be prefilled
+    /// in the block. It should contain the indexes in the block of the txs
+    /// to prefill. It must be ordered. 0 should not be included as the
+    /// coinbase tx is always prefilled.
+    ///
+    /// > Nodes SHOULD NOT use the same nonce across multiple different blocks.
+    pub fn from_block(block: &Block, nonce: u64, version: u32, prefill: &[usize]) -> Result<HeaderAndShortIds, Error> {
+        if version != 1 && version != 2 {
+            return Err(Error::UnknownVersion);
+        }
+        if block.txdata.len() as u64 > MAX_INDEX + 1 {
+            return Err(Error::InvalidPrefill);
+        }
+
+        let siphash_keys = ShortId::calculate_siphash_keys(&block.header, nonce);
+
+        let mut prefill = prefill;
+        let mut prefilled = Vec::with_capacity(prefill.len() + 1); // +1 for coinbase tx
+        let mut short_ids = Vec::with_capacity(block.txdata.len().saturating_sub(prefill.len()));
+        let mut next_prefill = 0;
+        for (idx, tx) in block.txdata.iter().enumerate() {
+            // Check if we should prefill this tx.
+            let prefill_tx = if prefill.first() == Some(&idx) {
+                prefill = &prefill[1..];
+                true
+            } else {
+                idx == 0 // Always prefill coinbase.
+            };
+
+            if prefill_tx {
+                let diff_idx = idx - next_prefill;
+                next_prefill = idx + 1;
+                prefilled.push(PrefilledTransaction {
+                    idx: diff_idx as u16,
+                    tx: match version {
+                        // This implementation is used for
+                        // compact blocks without witness data
+                        1 => Transaction {
+                            version: tx.version,
+                            lock_time: tx.lock_time,
+                            input: tx.input.iter().map(|input| {
+                                let mut input = input.clone();
+                                input.witness.clear();
+                                input
+                            }).collect(),
+                            output: tx.output.clone(),
+                        },
+                        _ => tx.clone(),
+                    },
+                });
+            } else {
+                short_ids.push(match version {
+                    1 => ShortId::with_siphash_keys(&tx.txid(), siphash_keys),
+                    _ => ShortId::with_siphash_keys(&tx.wtxid(), siphash_keys),
+                });
+            }
+        }
+
+        // unsorted, duplicate or out of range indexes are left over
+        if !prefill.is_empty() {
+            return Err(Error::InvalidPrefill);
+        }
+
+        Ok(HeaderAndShortIds {
+            header: block.header,
+            nonce: nonce,
+            short_ids: short_ids,
+            prefilled_txs: prefilled,
+        })
+    }
+}
+
+/// A [BlockTransactionsRequest] structure is used to list transaction indexes
+/// in a block being requested.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BlockTransactionsRequest {
+    /// The blockhash of the block which the transactions being requested are in.
+    pub block_hash: BlockHash,
+    /// The indexes of the transactions being requested in the block, in
+    /// ascending order.
+    pub indexes: Vec<u64>,
+}
+
+impl Encodable for BlockTransactionsRequest {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = self.block_hash.consensus_encode(&mut s)?;
+        len += encode::write_compact_size(&mut s, self.indexes.len() as u64)?;
+        // the indexes are encoded as the differences to the previous index plus one
+        let mut next_idx = 0u64;
+        for idx in &self.indexes {
+            if *idx < next_idx || *idx > MAX_INDEX {
+                return Err(encode::Error::ParseFailed("BIP152 indexes must be ascending 16-bit numbers"));
+            }
+            len += encode::write_compact_size(&mut s, *idx - next_idx)?;
+            next_idx = *idx + 1;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for BlockTransactionsRequest {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let block_hash = Decodable::consensus_decode(&mut d)?;
+        let count = decode_count(&mut d, 8)?;
+        let mut indexes = Vec::with_capacity(encode::prealloc_capacity::<u64>(count as u64));
+        let mut next_idx = 0u64;
+        for _ in 0..count {
+            let differential = encode::read_compact_size(&mut d)?;
+            let idx = match next_idx.checked_add(differential) {
+                Some(idx) if idx <= MAX_INDEX => idx,
+                _ => return Err(encode::Error::ParseFailed("BIP152 tx index out of bounds")),
+            };
+            indexes.push(idx);
+            next_idx = idx + 1;
+        }
+        Ok(BlockTransactionsRequest {
+            block_hash: block_hash,
+            indexes: indexes,
+        })
+    }
+}
+
+/// A [BlockTransactions] structure is used to provide some of the
+/// transactions in a block, as requested.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BlockTransactions {
+    /// The blockhash of the block which the transactions being provided are in.
+    pub block_hash: BlockHash,
+    /// The transactions provided.
+    pub transactions: Vec<Transaction>,
+}
+impl_consensus_encoding!(BlockTransactions, block_hash, transactions);
+
+impl BlockTransactions {
+    /// Construct a [BlockTransactions] from a [BlockTransactionsRequest] and
+    /// the corresponding full [Block] by providing all requested transactions.
+    pub fn from_request(request: &BlockTransactionsRequest, block: &Block) -> Result<BlockTransactions, TxIndexOutOfRangeError> {
+        Ok(BlockTransactions {
+            block_hash: request.block_hash,
+            transactions: {
+                let mut txs = Vec::with_capacity(request.indexes.len());
+                for idx in &request.indexes {
+                    if *idx >= block.txdata.len() as u64 {
+                        return Err(TxIndexOutOfRangeError(*idx));
+                    }
+                    txs.push(block.txdata[*idx as usize].clone());
+                }
+                txs
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hashes::hex::{FromHex, ToHex};
+
+    use blockdata::block::{Block, BlockHeader};
+    use blockdata::locktime::LockTime;
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+    use hash_types::{BlockHash, TxMerkleNode};
+
+    use super::*;
+
+    fn dummy_tx(nonce: &[u8]) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: LockTime::from_consensus(2),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Default::default(), 0),
+                script_sig: Script::from(nonce.to_vec()),
+                sequence: Sequence(3),
+                witness: vec![vec![4, 5]],
+            }],
+            output: vec![TxOut { value: 1, script_pubkey: Script::new() }],
+        }
+    }
+
+    fn dummy_block() -> Block {
+        Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: BlockHash::hash(&[0]),
+                merkle_root: TxMerkleNode::hash(&[1]),
+                time: 2,
+                bits: 3,
+                nonce: 4,
+            },
+            txdata: vec![dummy_tx(&[2]), dummy_tx(&[3]), dummy_tx(&[4])],
+        }
+    }
+
+    #[test]
+    fn header_and_short_ids_from_block() {
+        let block = dummy_block();
+
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[]).unwrap();
+        assert_eq!(compact.nonce, 42);
+        assert_eq!(compact.short_ids.len(), 2);
+        assert_eq!(compact.prefilled_txs.len(), 1);
+        assert_eq!(compact.prefilled_txs[0].idx, 0);
+        assert_eq!(&compact.prefilled_txs[0].tx, &block.txdata[0]);
+
+        let keys = ShortId::calculate_siphash_keys(&block.header, 42);
+        assert_eq!(compact.short_ids[0], ShortId::with_siphash_keys(&block.txdata[1].wtxid(), keys));
+        assert_eq!(compact.short_ids[1], ShortId::with_siphash_keys(&block.txdata[2].wtxid(), keys));
+
+        let compact = HeaderAndShortIds::from_block(&block, 42, 1, &[0, 2]).unwrap();
+        assert_eq!(compact.short_ids, vec![ShortId::with_siphash_keys(&block.txdata[1].txid(), keys)]);
+        assert_eq!(compact.prefilled_txs.iter().map(|tx| tx.idx).collect::<Vec<_>>(), vec![0, 1]);
+        // version 1 prefilled transactions go without witness
+        assert_eq!(compact.prefilled_txs[1].tx.txid(), block.txdata[2].txid());
+        assert!(compact.prefilled_txs[1].tx.input[0].witness.is_empty());
+
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[2]).unwrap();
+        assert_eq!(compact.prefilled_txs.iter().map(|tx| tx.idx).collect::<Vec<_>>(), vec![0, 1]);
+        let decoded: HeaderAndShortIds = deserialize(&serialize(&compact)).unwrap();
+        assert_eq!(decoded, compact);
+
+        assert_eq!(HeaderAndShortIds::from_block(&block, 42, 3, &[]), Err(Error::UnknownVersion));
+        assert_eq!(HeaderAndShortIds::from_block(&block, 42, 2, &[2, 1]), Err(Error::InvalidPrefill));
+        assert_eq!(HeaderAndShortIds::from_block(&block, 42, 2, &[1, 1]), Err(Error::InvalidPrefill));
+        assert_eq!(HeaderAndShortIds::from_block(&block, 42, 2, &[3]), Err(Error::InvalidPrefill));
+    }
+
+    #[test]
+    fn short_id_encoding() {
+        let keys = (0x0706050403020100, 0x0f0e0d0c0b0a0908);
+        let txid = [0u8; 32];
+        let hash = siphash24::Hash::hash_to_u64_with_keys(keys.0, keys.1, &txid);
+        let short_id = ShortId::with_siphash_keys(&txid, keys);
+        // six bytes, little endian
+        assert_eq!(serialize(&short_id), endian::u64_to_array_le(hash)[0..6].to_vec());
+        assert_eq!(short_id.to_hex(), endian::u64_to_array_le(hash)[0..6].to_hex());
+        assert_eq!(deserialize::<ShortId>(&serialize(&short_id)).unwrap(), short_id);
+    }
+
+    #[test]
+    fn block_transactions_request_encoding() {
+        let request = BlockTransactionsRequest {
+            block_hash: Default::default(),
+            indexes: vec![1, 2, 3, 5, 0xffff],
+        };
+        let encoded = serialize(&request);
+        // differentially encoded: 1, 0, 0, 1, 0xfff9
+        assert_eq!(encoded[32..].to_hex(), "0501000001fdf9ff");
+        assert_eq!(deserialize::<BlockTransactionsRequest>(&encoded).unwrap(), request);
+
+        // the index list has to be ascending
+        let mut unordered = request.clone();
+        unordered.indexes = vec![2, 1];
+        assert!(unordered.consensus_encode(&mut vec![]).is_err());
+        unordered.indexes = vec![1, 1];
+        assert!(unordered.consensus_encode(&mut vec![]).is_err());
+
+        // indexes past 16 bits are rejected, as is overflowing the differential encoding
+        let mut too_large = encoded.clone();
+        too_large.truncate(32);
+        too_large.extend(Vec::<u8>::from_hex("0201fdfeff").unwrap());
+        assert!(deserialize::<BlockTransactionsRequest>(&too_large).is_err());
+        let mut overflow = encoded.clone();
+        overflow.truncate(32);
+        overflow.extend(Vec::<u8>::from_hex("0201ffffffffffffffffff").unwrap());
+        assert!(deserialize::<BlockTransactionsRequest>(&overflow).is_err());
+    }
+
+    #[test]
+    fn prefilled_index_overflow() {
+        let block = dummy_block();
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[]).unwrap();
+        let mut encoded = serialize(&compact);
+        // replace the index of the prefilled coinbase
+        let prefilled_start = 80 + 8 + 1 + 2 * 6 + 1;
+        encoded.splice(prefilled_start..prefilled_start + 1, vec![0xfd, 0xff, 0xff]);
+        assert_eq!(deserialize::<HeaderAndShortIds>(&encoded).unwrap().prefilled_txs[0].idx, 0xffff);
+        encoded.splice(prefilled_start..prefilled_start + 3, vec![0xfe, 0x00, 0x00, 0x01, 0x00]);
+        assert!(deserialize::<HeaderAndShortIds>(&encoded).is_err());
+
+        // a second prefilled transaction past 0xffff
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[1]).unwrap();
+        let mut encoded = serialize(&compact);
+        let prefilled_start = 80 + 8 + 1 + 6 + 1;
+        encoded.splice(prefilled_start..prefilled_start + 1, vec![0xfd, 0xff, 0xff]);
+        assert!(deserialize::<HeaderAndShortIds>(&encoded).is_err());
+    }
+
+    #[test]
+    fn block_transactions_from_request() {
+        let block = dummy_block();
+        let request = BlockTransactionsRequest {
+            block_hash: block.block_hash(),
+            indexes: vec![0, 2],
+        };
+        let txs = BlockTransactions::from_request(&request, &block).unwrap();
+        assert_eq!(txs.block_hash, block.block_hash());
+        assert_eq!(txs.transactions, vec![block.txdata[0].clone(), block.txdata[2].clone()]);
+        assert_eq!(deserialize::<BlockTransactions>(&serialize(&txs)).unwrap(), txs);
+
+        let request = BlockTransactionsRequest {
+            block_hash: block.block_hash(),
+            indexes: vec![0, 3],
+        };
+        assert_eq!(BlockTransactions::from_request(&request, &block), Err(TxIndexOutOfRangeError(3)));
+    }
+
+    #[test]
+    fn compact_block_vector() {
+        // regtest block with a witness spend, compact block created by the
+        // Elements implementation of BIP152
+        let raw_block = Vec::<u8>::from_hex("000000206c750a364035aefd5f81508a08769975116d9195312ee4520dceac39e1fdc62c4dc67473b8e354358c1e610afeaff7410858bd45df43e2940f8a62bd3d5e3ac943c2975cffff7f200000000002020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff04016b0101ffffffff020006062a0100000001510000000000000000266a24aa21a9ed4a3d9f3343dafcc0d6f6d4310f2ee5ce273ed34edca6c75db3a73e7f368734200120000000000000000000000000000000000000000000000000000000000000000000000000020000000001021fc20ba2bd745507b8e00679e3b362558f9457db374ca28ffa5243f4c23a4d5f00000000171600147c9dea14ffbcaec4b575e03f05ceb7a81cd3fcbffdffffff915d689be87b43337f42e26033df59807b768223368f189a023d0242d837768900000000171600147c9dea14ffbcaec4b575e03f05ceb7a81cd3fcbffdffffff0200cdf5050000000017a9146803c72d9154a6a20f404bed6d3dcee07986235a8700e1f5050000000017a9144e6a4c7cb5b5562904843bdf816342f4db9f5797870247304402205e9bf6e70eb0e4b495bf483fd8e6e02da64900f290ef8aaa64bb32600d973c450220670896f5d0e5f33473e5f399ab680cc1d25c2d2afd15abd722f04978f28be887012103e4e4d9312b2261af508b367d8ba9be4f01b61d6d6e78bec499845b4f410bcf2702473044022045ac80596a6ac9c8c572f94708709adaf106677221122e08daf8b9741a04f66a022003ccd52a3b78f8fd08058fc04fc0cffa5f4c196c84eae9e37e2a85babe731b57012103e4e4d9312b2261af508b367d8ba9be4f01b61d6d6e78bec499845b4f410bcf276a000000").unwrap();
+        let raw_compact = Vec::<u8>::from_hex("000000206c750a364035aefd5f81508a08769975116d9195312ee4520dceac39e1fdc62c4dc67473b8e354358c1e610afeaff7410858bd45df43e2940f8a62bd3d5e3ac943c2975cffff7f2000000000a4df3c3744da89fa010a6979e971450100020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff04016b0101ffffffff020006062a0100000001510000000000000000266a24aa21a9ed4a3d9f3343dafcc0d6f6d4310f2ee5ce273ed34edca6c75db3a73e7f368734200120000000000000000000000000000000000000000000000000000000000000000000000000").unwrap();
+        let block: Block = deserialize(&raw_block).unwrap();
+        let nonce = 18053200567810711460;
+        let compact = HeaderAndShortIds::from_block(&block, nonce, 2, &[]).unwrap();
+        let compact_expected: HeaderAndShortIds = deserialize(&raw_compact).unwrap();
+        assert_eq!(compact, compact_expected);
+        assert_eq!(serialize(&compact), raw_compact);
+    }
+}
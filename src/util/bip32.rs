@@ -16,12 +16,14 @@
 //! Implementation of BIP32 hierarchical deterministic wallets, as defined
 //! at https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
 
+
 use std::default::Default;
-use std::{error, fmt};
+use std::fmt;
+use std::error;
 use std::str::FromStr;
 #[cfg(feature = "serde")] use serde;
 
-use hash_types::XpubIdentifier;
+pub use hash_types::XpubIdentifier;
 use hashes::{sha512, Hash, HashEngine, Hmac, HmacEngine};
 use secp256k1::{self, Secp256k1};
 
@@ -29,6 +31,15 @@ use network::constants::Network;
 use util::{base58, endian};
 use util::key::{PublicKey, PrivateKey};
 
+/// Base58 version of mainnet extended private keys, `xprv`
+const XPRV_MAINNET: [u8; 4] = [0x04, 0x88, 0xAD, 0xE4];
+/// Base58 version of testnet extended private keys, `tprv`
+const XPRV_TESTNET: [u8; 4] = [0x04, 0x35, 0x83, 0x94];
+/// Base58 version of mainnet extended public keys, `xpub`
+const XPUB_MAINNET: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
+/// Base58 version of testnet extended public keys, `tpub`
+const XPUB_TESTNET: [u8; 4] = [0x04, 0x35, 0x87, 0xCF];
+
 /// A chain code
 pub struct ChainCode([u8; 32]);
 impl_array_newtype!(ChainCode, u8, 32);
@@ -45,8 +56,15 @@ impl Default for Fingerprint {
     fn default() -> Fingerprint { Fingerprint([0; 4]) }
 }
 
+/// The master key fingerprint and derivation path a key was derived with,
+/// as stored in the BIP32 derivation maps of PSBT inputs and outputs
+pub type KeySource = (Fingerprint, DerivationPath);
+
 /// Extended private key
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+///
+/// Equality is checked in constant time with respect to the private key and
+/// chain code, so comparing keys does not leak them through timing.
+#[derive(Copy, Clone, Debug)]
 pub struct ExtendedPrivKey {
     /// The network this key is to be used on
     pub network: Network,
@@ -63,6 +81,25 @@ pub struct ExtendedPrivKey {
 }
 serde_string_impl!(ExtendedPrivKey, "a BIP-32 extended private key");
 
+impl PartialEq for ExtendedPrivKey {
+    fn eq(&self, other: &ExtendedPrivKey) -> bool {
+        // Accumulate the differences of all secret bytes without returning
+        // early, then combine with the public fields without short-circuiting.
+        let secret_diff = self.private_key[..].iter().zip(other.private_key[..].iter())
+            .chain(self.chain_code[..].iter().zip(other.chain_code[..].iter()))
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        (secret_diff == 0)
+            & (self.network == other.network)
+            & (self.depth == other.depth)
+            & (self.parent_fingerprint == other.parent_fingerprint)
+            & (self.child_number == other.child_number)
+            & (self.private_key.compressed == other.private_key.compressed)
+            & (self.private_key.network == other.private_key.network)
+    }
+}
+
+impl Eq for ExtendedPrivKey {}
+
 /// Extended public key
 #[derive(Copy, Clone, PartialEq, Eq, Debug, PartialOrd, Ord, Hash)]
 pub struct ExtendedPubKey {
@@ -297,6 +334,11 @@ impl<'a> Iterator for DerivationPathIterator<'a> {
 }
 
 impl DerivationPath {
+    /// Whether this is the empty path `m`, i.e. refers to the master key.
+    pub fn is_master(&self) -> bool {
+        self.0.is_empty()
+    }
+
     /// Create a new [DerivationPath] that is a child of this one.
     pub fn child(&self, cn: ChildNumber) -> DerivationPath {
         let mut path = self.0.clone();
@@ -360,6 +402,17 @@ pub enum Error {
     InvalidChildNumberFormat,
     /// Invalid derivation path format.
     InvalidDerivationPathFormat,
+    /// Base58 decoding of an extended key failed, including unknown versions
+    Base58(base58::Error),
+    /// An encoded extended key did not have 78 bytes
+    WrongExtendedKeyLength(usize),
+    /// A master key (depth 0) had a non-zero parent fingerprint
+    NonZeroParentFingerprintForMasterKey,
+    /// A master key (depth 0) had a non-zero child number
+    NonZeroChildNumberForMasterKey,
+    /// The private key in an encoded extended private key was not prefixed
+    /// with a zero byte
+    InvalidPrivateKeyPrefix(u8),
 }
 
 impl fmt::Display for Error {
@@ -371,17 +424,22 @@ impl fmt::Display for Error {
             Error::RngError(ref s) => write!(f, "rng error {}", s),
             Error::InvalidChildNumberFormat => f.write_str("invalid child number format"),
             Error::InvalidDerivationPathFormat => f.write_str("invalid derivation path format"),
+            Error::Base58(ref e) => fmt::Display::fmt(e, f),
+            Error::WrongExtendedKeyLength(n) => write!(f, "encoded extended key has {} bytes instead of 78", n),
+            Error::NonZeroParentFingerprintForMasterKey => f.write_str("master key has a non-zero parent fingerprint"),
+            Error::NonZeroChildNumberForMasterKey => f.write_str("master key has a non-zero child number"),
+            Error::InvalidPrivateKeyPrefix(b) => write!(f, "private key is prefixed with 0x{:02x} instead of zero", b),
         }
     }
 }
 
 impl error::Error for Error {
-    fn cause(&self) -> Option<&error::Error> {
-       if let Error::Ecdsa(ref e) = *self {
-           Some(e)
-       } else {
-           None
-       }
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            Error::Ecdsa(ref e) => Some(e),
+            Error::Base58(ref e) => Some(e),
+            _ => None,
+        }
     }
 
     fn description(&self) -> &str {
@@ -393,6 +451,31 @@ impl From<secp256k1::Error> for Error {
     fn from(e: secp256k1::Error) -> Error { Error::Ecdsa(e) }
 }
 
+impl From<base58::Error> for Error {
+    fn from(e: base58::Error) -> Error { Error::Base58(e) }
+}
+
+/// Decode the parts common to extended private and public keys from the
+/// 74 bytes following the version, checking the master key rules
+fn decode_header(data: &[u8]) -> Result<(u8, Fingerprint, ChildNumber, ChainCode), Error> {
+    if data.len() != 74 {
+        return Err(Error::WrongExtendedKeyLength(data.len() + 4));
+    }
+
+    let depth = data[0];
+    let parent_fingerprint = Fingerprint::from(&data[1..5]);
+    let child_number = ChildNumber::from(endian::slice_to_u32_be(&data[5..9]));
+    if depth == 0 {
+        if parent_fingerprint != Fingerprint::default() {
+            return Err(Error::NonZeroParentFingerprintForMasterKey);
+        }
+        if u32::from(child_number) != 0 {
+            return Err(Error::NonZeroChildNumberForMasterKey);
+        }
+    }
+    Ok((depth, parent_fingerprint, child_number, ChainCode::from(&data[9..41])))
+}
+
 impl ExtendedPrivKey {
     /// Construct a new master key from a seed value
     pub fn new_master(network: Network, seed: &[u8]) -> Result<ExtendedPrivKey, Error> {
@@ -563,55 +646,42 @@ impl ExtendedPubKey {
 
 impl fmt::Display for ExtendedPrivKey {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let mut ret = [0; 78];
-        ret[0..4].copy_from_slice(&match self.network {
-            Network::Monacoin => [0x04, 0x88, 0xAD, 0xE4],
-            Network::MonacoinTestnet | Network::MonacoinRegtest => [0x04, 0x35, 0x83, 0x94],
-        }[..]);
-        ret[4] = self.depth as u8;
-        ret[5..9].copy_from_slice(&self.parent_fingerprint[..]);
-        ret[9..13].copy_from_slice(&endian::u32_to_array_be(u32::from(self.child_number)));
-        ret[13..45].copy_from_slice(&self.chain_code[..]);
-        ret[45] = 0;
-        ret[46..78].copy_from_slice(&self.private_key[..]);
-        fmt.write_str(&base58::check_encode_slice(&ret[..]))
+        let version = match self.network {
+            Network::Monacoin => XPRV_MAINNET,
+            Network::MonacoinTestnet | Network::MonacoinRegtest => XPRV_TESTNET,
+        };
+        let mut ret = [0; 74];
+        ret[0] = self.depth as u8;
+        ret[1..5].copy_from_slice(&self.parent_fingerprint[..]);
+        ret[5..9].copy_from_slice(&endian::u32_to_array_be(u32::from(self.child_number)));
+        ret[9..41].copy_from_slice(&self.chain_code[..]);
+        ret[41] = 0;
+        ret[42..74].copy_from_slice(&self.private_key[..]);
+        base58::encode_check_with_version_to_fmt(fmt, &version, &ret[..])
     }
 }
 
 impl FromStr for ExtendedPrivKey {
-    type Err = base58::Error;
-
-    fn from_str(inp: &str) -> Result<ExtendedPrivKey, base58::Error> {
-        let data = base58::from_check(inp)?;
+    type Err = Error;
 
-        if data.len() != 78 {
-            return Err(base58::Error::InvalidLength(data.len()));
+    fn from_str(inp: &str) -> Result<ExtendedPrivKey, Error> {
+        let (version, data) = base58::decode_check_version(inp, &[&XPRV_MAINNET, &XPRV_TESTNET])?;
+        let (depth, parent_fingerprint, child_number, chain_code) = decode_header(&data)?;
+        if data[41] != 0 {
+            return Err(Error::InvalidPrivateKeyPrefix(data[41]));
         }
-
-        let cn_int: u32 = endian::slice_to_u32_be(&data[9..13]);
-        let child_number: ChildNumber = ChildNumber::from(cn_int);
-        let network = if data[0..4] == [0x04u8, 0x88, 0xAD, 0xE4] {
-            Network::Monacoin
-        } else if data[0..4] == [0x04u8, 0x35, 0x83, 0x94] {
-            Network::MonacoinTestnet
-        } else {
-            return Err(base58::Error::InvalidVersion((&data[0..4]).to_vec()));
-        };
+        let network = if version == 0 { Network::Monacoin } else { Network::MonacoinTestnet };
 
         Ok(ExtendedPrivKey {
             network: network,
-            depth: data[4],
-            parent_fingerprint: Fingerprint::from(&data[5..9]),
+            depth: depth,
+            parent_fingerprint: parent_fingerprint,
             child_number: child_number,
-            chain_code: ChainCode::from(&data[13..45]),
+            chain_code: chain_code,
             private_key: PrivateKey {
                 compressed: true,
                 network: network,
-                key: secp256k1::SecretKey::from_slice(
-                    &data[46..78]
-                ).map_err(|e|
-                        base58::Error::Other(e.to_string())
-                )?,
+                key: secp256k1::SecretKey::from_slice(&data[42..74])?,
             },
         })
     }
@@ -619,48 +689,37 @@ impl FromStr for ExtendedPrivKey {
 
 impl fmt::Display for ExtendedPubKey {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let mut ret = [0; 78];
-        ret[0..4].copy_from_slice(&match self.network {
-            Network::Monacoin => [0x04u8, 0x88, 0xB2, 0x1E],
-            Network::MonacoinTestnet | Network::MonacoinRegtest => [0x04u8, 0x35, 0x87, 0xCF],
-        }[..]);
-        ret[4] = self.depth as u8;
-        ret[5..9].copy_from_slice(&self.parent_fingerprint[..]);
-        ret[9..13].copy_from_slice(&endian::u32_to_array_be(u32::from(self.child_number)));
-        ret[13..45].copy_from_slice(&self.chain_code[..]);
-        ret[45..78].copy_from_slice(&self.public_key.key.serialize()[..]);
-        fmt.write_str(&base58::check_encode_slice(&ret[..]))
+        let version = match self.network {
+            Network::Monacoin => XPUB_MAINNET,
+            Network::MonacoinTestnet | Network::MonacoinRegtest => XPUB_TESTNET,
+        };
+        let mut ret = [0; 74];
+        ret[0] = self.depth as u8;
+        ret[1..5].copy_from_slice(&self.parent_fingerprint[..]);
+        ret[5..9].copy_from_slice(&endian::u32_to_array_be(u32::from(self.child_number)));
+        ret[9..41].copy_from_slice(&self.chain_code[..]);
+        ret[41..74].copy_from_slice(&self.public_key.key.serialize()[..]);
+        base58::encode_check_with_version_to_fmt(fmt, &version, &ret[..])
     }
 }
 
 impl FromStr for ExtendedPubKey {
-    type Err = base58::Error;
-
-    fn from_str(inp: &str) -> Result<ExtendedPubKey, base58::Error> {
-        let data = base58::from_check(inp)?;
-
-        if data.len() != 78 {
-            return Err(base58::Error::InvalidLength(data.len()));
-        }
+    type Err = Error;
 
-        let cn_int: u32 = endian::slice_to_u32_be(&data[9..13]);
-        let child_number: ChildNumber = ChildNumber::from(cn_int);
+    fn from_str(inp: &str) -> Result<ExtendedPubKey, Error> {
+        let (version, data) = base58::decode_check_version(inp, &[&XPUB_MAINNET, &XPUB_TESTNET])?;
+        let (depth, parent_fingerprint, child_number, chain_code) = decode_header(&data)?;
 
         Ok(ExtendedPubKey {
-            network: if data[0..4] == [0x04u8, 0x88, 0xB2, 0x1E] {
-                Network::Monacoin
-            } else if data[0..4] == [0x04u8, 0x35, 0x87, 0xCF] {
-                Network::MonacoinTestnet
-            } else {
-                return Err(base58::Error::InvalidVersion((&data[0..4]).to_vec()));
-            },
-            depth: data[4],
-            parent_fingerprint: Fingerprint::from(&data[5..9]),
+            network: if version == 0 { Network::Monacoin } else { Network::MonacoinTestnet },
+            depth: depth,
+            parent_fingerprint: parent_fingerprint,
             child_number: child_number,
-            chain_code: ChainCode::from(&data[13..45]),
-            public_key: PublicKey::from_slice(
-                             &data[45..78]).map_err(|e|
-                                 base58::Error::Other(e.to_string()))?
+            chain_code: chain_code,
+            public_key: PublicKey {
+                compressed: true,
+                key: secp256k1::PublicKey::from_slice(&data[41..74])?,
+            },
         })
     }
 }
@@ -725,6 +784,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_derivation_path_is_master() {
+        assert!(DerivationPath::from_str("m").unwrap().is_master());
+        assert!(DerivationPath::from(vec![]).is_master());
+        let child = DerivationPath::from_str("m").unwrap().into_child(ChildNumber::from_hardened_idx(0).unwrap());
+        assert!(!child.is_master());
+        assert_eq!(child, DerivationPath::from_str("m/0'").unwrap());
+        let mut hardened = child.hardened_children();
+        assert_eq!(hardened.next(), Some(DerivationPath::from_str("m/0'/0'").unwrap()));
+        assert_eq!(hardened.next(), Some(DerivationPath::from_str("m/0'/1'").unwrap()));
+    }
+
     #[test]
     fn test_derivation_path_conversion_index() {
         let path = DerivationPath::from_str("m/0h/1/2'").unwrap();
@@ -839,6 +910,124 @@ mod tests {
         assert!(iter.next().is_none());
     }
 
+    #[test]
+    fn test_invalid_encodings() {
+        // BIP32 test vector 5
+        let prv_err = |s: &str| ExtendedPrivKey::from_str(s).unwrap_err();
+        let pub_err = |s: &str| ExtendedPubKey::from_str(s).unwrap_err();
+
+        // pubkey version / prvkey mismatch
+        assert_eq!(
+            pub_err("xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6LBpB85b3D2yc8sfvZU521AAwdZafEz7mnzBBsz4wKY5fTtTQBm"),
+            Error::Ecdsa(secp256k1::Error::InvalidPublicKey)
+        );
+        // prvkey version / pubkey mismatch
+        assert_eq!(
+            prv_err("xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFGTQQD3dC4H2D5GBj7vWvSQaaBv5cxi9gafk7NF3pnBju6dwKvH"),
+            Error::InvalidPrivateKeyPrefix(3)
+        );
+        // invalid pubkey prefix 04
+        assert_eq!(
+            pub_err("xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6Txnt3siSujt9RCVYsx4qHZGc62TG4McvMGcAUjeuwZdduYEvFn"),
+            Error::Ecdsa(secp256k1::Error::InvalidPublicKey)
+        );
+        // invalid prvkey prefix 04
+        assert_eq!(
+            prv_err("xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFGpWnsj83BHtEy5Zt8CcDr1UiRXuWCmTQLxEK9vbz5gPstX92JQ"),
+            Error::InvalidPrivateKeyPrefix(4)
+        );
+        // invalid pubkey prefix 01
+        assert_eq!(
+            pub_err("xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6N8ZMMXctdiCjxTNq964yKkwrkBJJwpzZS4HS2fxvyYUA4q2Xe4"),
+            Error::Ecdsa(secp256k1::Error::InvalidPublicKey)
+        );
+        // invalid prvkey prefix 01
+        assert_eq!(
+            prv_err("xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFAzHGBP2UuGCqWLTAPLcMtD9y5gkZ6Eq3Rjuahrv17fEQ3Qen6J"),
+            Error::InvalidPrivateKeyPrefix(1)
+        );
+        // zero depth with non-zero parent fingerprint
+        assert_eq!(
+            prv_err("xprv9s2SPatNQ9Vc6GTbVMFPFo7jsaZySyzk7L8n2uqKXJen3KUmvQNTuLh3fhZMBoG3G4ZW1N2kZuHEPY53qmbZzCHshoQnNf4GvELZfqTUrcv"),
+            Error::NonZeroParentFingerprintForMasterKey
+        );
+        assert_eq!(
+            pub_err("xpub661no6RGEX3uJkY4bNnPcw4URcQTrSibUZ4NqJEw5eBkv7ovTwgiT91XX27VbEXGENhYRCf7hyEbWrR3FewATdCEebj6znwMfQkhRYHRLpJ"),
+            Error::NonZeroParentFingerprintForMasterKey
+        );
+        // zero depth with non-zero index
+        assert_eq!(
+            prv_err("xprv9s21ZrQH4r4TsiLvyLXqM9P7k1K3EYhA1kkD6xuquB5i39AU8KF42acDyL3qsDbU9NmZn6MsGSUYZEsuoePmjzsB3eFKSUEh3Gu1N3cqVUN"),
+            Error::NonZeroChildNumberForMasterKey
+        );
+        assert_eq!(
+            pub_err("xpub661MyMwAuDcm6CRQ5N4qiHKrJ39Xe1R1NyfouMKTTWcguwVcfrZJaNvhpebzGerh7gucBvzEQWRugZDuDXjNDRmXzSZe4c7mnTK97pTvGS8"),
+            Error::NonZeroChildNumberForMasterKey
+        );
+        // unknown extended key version
+        assert_eq!(
+            prv_err("DMwo58pR1QLEFihHiXPVykYB6fJmsTeHvyTp7hRThAtCX8CvYzgPcn8XnmdfHGMQzT7ayAmfo4z3gY5KfbrZWZ6St24UVf2Qgo6oujFktLHdHY4"),
+            Error::Base58(base58::Error::InvalidVersion(vec![1, 1, 1, 1]))
+        );
+        assert_eq!(
+            pub_err("DMwo58pR1QLEFihHiXPVykYB6fJmsTeHvyTp7hRThAtCX8CvYzgPcn8XnmdfHPmHJiEDXkTiJTVV9rHEBUem2mwVbbNfvT2MTcAqj3nesx8uBf9"),
+            Error::Base58(base58::Error::InvalidVersion(vec![1, 1, 1, 1]))
+        );
+        // private key 0 not in 1..n-1
+        assert_eq!(
+            prv_err("xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzF93Y5wvzdUayhgkkFoicQZcP3y52uPPxFnfoLZB21Teqt1VvEHx"),
+            Error::Ecdsa(secp256k1::Error::InvalidSecretKey)
+        );
+        // private key n not in 1..n-1
+        assert_eq!(
+            prv_err("xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFAzHGBP2UuGCqWLTAPLcMtD5SDKr24z3aiUvKr9bJpdrcLg1y3G"),
+            Error::Ecdsa(secp256k1::Error::InvalidSecretKey)
+        );
+        // invalid pubkey 020000000000000000000000000000000000000000000000000000000000000007
+        assert_eq!(
+            pub_err("xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6Q5JXayek4PRsn35jii4veMimro1xefsM58PgBMrvdYre8QyULY"),
+            Error::Ecdsa(secp256k1::Error::InvalidPublicKey)
+        );
+        // invalid checksum
+        match prv_err("xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHL") {
+            Error::Base58(base58::Error::BadChecksum(..)) => {},
+            e => panic!("unexpected {:?}", e),
+        }
+
+        // not part of the test vector: a valid key with a version of the other kind, and a
+        // truncated key
+        let master = ExtendedPrivKey::new_master(Monacoin, &Vec::from_hex("000102030405060708090a0b0c0d0e0f").unwrap()).unwrap();
+        let master_pub = ExtendedPubKey::from_private(&Secp256k1::new(), &master);
+        assert_eq!(
+            prv_err(&master_pub.to_string()),
+            Error::Base58(base58::Error::InvalidVersion(XPUB_MAINNET.to_vec()))
+        );
+        assert_eq!(
+            pub_err(&master.to_string()),
+            Error::Base58(base58::Error::InvalidVersion(XPRV_MAINNET.to_vec()))
+        );
+        let mut truncated = base58::from_check(&master_pub.to_string()).unwrap();
+        truncated.pop();
+        assert_eq!(pub_err(&base58::check_encode_slice(&truncated)), Error::WrongExtendedKeyLength(77));
+    }
+
+    #[test]
+    fn test_priv_key_eq() {
+        let seed = Vec::from_hex("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedPrivKey::new_master(Monacoin, &seed).unwrap();
+        assert_eq!(master, ExtendedPrivKey::from_str(&master.to_string()).unwrap());
+
+        let mut chain_code = master.chain_code.to_bytes();
+        chain_code[31] ^= 1;
+        let mut other = master;
+        other.chain_code = ChainCode::from(&chain_code[..]);
+        assert_ne!(master, other);
+        let mut other = master;
+        other.depth = 1;
+        assert_ne!(master, other);
+        assert_ne!(master, ExtendedPrivKey::new_master(Monacoin, &seed[1..]).unwrap());
+    }
+
     #[test]
     fn test_vector_1() {
         let secp = Secp256k1::new();
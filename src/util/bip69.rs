@@ -0,0 +1,166 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # BIP69 Lexicographical Indexing
+//!
+//! Canonical ordering of transaction inputs and outputs, so that their order
+//! does not leak information about the wallet which created the transaction.
+//!
+//! Inputs are sorted by previous txid, compared in its usual byte-reversed
+//! hex form, and then by output index. Outputs are sorted by value and then
+//! by the bytes of their script pubkey. Sorting is stable, so identical
+//! entries keep their relative order.
+
+use std::cmp::Ordering;
+
+use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+
+/// Compares two outpoints in BIP69 order.
+pub fn cmp_outpoints(a: &OutPoint, b: &OutPoint) -> Ordering {
+    a.txid[..].iter().rev().cmp(b.txid[..].iter().rev())
+        .then(a.vout.cmp(&b.vout))
+}
+
+/// Compares two inputs in BIP69 order, by the outpoints they spend.
+pub fn cmp_inputs(a: &TxIn, b: &TxIn) -> Ordering {
+    cmp_outpoints(&a.previous_output, &b.previous_output)
+}
+
+/// Compares two outputs in BIP69 order. This is the same as their `Ord`.
+pub fn cmp_outputs(a: &TxOut, b: &TxOut) -> Ordering {
+    a.cmp(b)
+}
+
+/// Sorts the inputs and outputs of a transaction into BIP69 order.
+///
+/// Any signatures on the inputs are kept but will generally be invalidated,
+/// so this should be done before signing.
+pub fn sort_transaction(tx: &mut Transaction) {
+    tx.input.sort_by(cmp_inputs);
+    tx.output.sort_by(cmp_outputs);
+}
+
+/// Checks whether the inputs and outputs of a transaction are in BIP69 order.
+pub fn is_sorted(tx: &Transaction) -> bool {
+    tx.input.windows(2).all(|w| cmp_inputs(&w[0], &w[1]) != Ordering::Greater)
+        && tx.output.windows(2).all(|w| cmp_outputs(&w[0], &w[1]) != Ordering::Greater)
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+
+    use blockdata::locktime::LockTime;
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use hash_types::Txid;
+    use super::*;
+
+    fn input(outpoint: &str) -> TxIn {
+        TxIn {
+            previous_output: outpoint.parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn output(value: u64, script: &str) -> TxOut {
+        TxOut { value: value, script_pubkey: Script::from(Vec::from_hex(script).unwrap()) }
+    }
+
+    fn tx(input: Vec<TxIn>, output: Vec<TxOut>) -> Transaction {
+        Transaction { version: 1, lock_time: LockTime::ZERO, input: input, output: output }
+    }
+
+    #[test]
+    fn bip69_vector_1() {
+        // Inputs of the first BIP69 example, in their expected order.
+        let sorted = [
+            "0e53ec5dfb2cb8a71fec32dc9a634a35b7e24799295ddd5278217822e0b31f57:0",
+            "26aa6e6d8b9e49bb0630aac301db6757c02e3619feb4ee0eea81eb1672947024:1",
+            "28e0fdd185542f2c6ea19030b0796051e7772b6026dd5ddccd7a2f93b73e6fc2:0",
+            "381de9b9ae1a94d9c17f6a08ef9d341a5ce29e2e60c36a52d333ff6203e58d5d:1",
+            "3b8b2f8efceb60ba78ca8bba206a137f14cb5ea4035e761ee204302d46b98de2:0",
+            "402b2c02411720bf409eff60d05adad684f135838962823f3614cc657dd7bc0a:1",
+            "54ffff182965ed0957dba1239c27164ace5a73c9b62a660c74b7b7f15ff61e7a:1",
+            "643e5f4e66373a57251fb173151e838ccd27d279aca882997e005016bb53d5aa:0",
+            "6c1d56f31b2de4bfc6aaea28396b333102b1f600da9c6d6149e96ca43f1102b1:1",
+            "7a1de137cbafb5c70405455c49c5104ca3057a1f1243e6563bb9245c9c88c191:0",
+            "7d037ceb2ee0dc03e82f17be7935d238b35d1deabf953a892a4507bfbeeb3ba4:1",
+            "a5e899dddb28776ea9ddac0a502316d53a4a3fca607c72f66c470e0412e34086:0",
+            "b4112b8f900a7ca0c8b0e7c4dfad35c6be5f6be46b3458974988e1cdb2fa61b8:0",
+            "bafd65e3c7f3f9fdfdc1ddb026131b278c3be1af90a4a6ffa78c4658f9ec0c85:0",
+            "de0411a1e97484a2804ff1dbde260ac19de841bebad1880c782941aca883b4e9:1",
+            "f0a130a84912d03c1d284974f563c5949ac13f8342b8112edff52971599e6a45:0",
+            "f320832a9d2e2452af63154bc687493484a0e7745ebd3aaf9ca19eb80834ad60:0",
+        ];
+        let expected: Vec<TxIn> = sorted.iter().map(|s| input(s)).collect();
+
+        let mut shuffled = expected.clone();
+        shuffled.reverse();
+        shuffled.swap(3, 11);
+        shuffled.swap(0, 7);
+        let outputs = vec![
+            output(400057456, "76a9144a5fba237213a062f6f57978f796390bdcf8d01588ac"),
+            output(40000000000, "76a9145be32612930b8323add2212a4ec03c1562084f8488ac"),
+        ];
+        let mut transaction = tx(shuffled, outputs.clone());
+        assert!(!is_sorted(&transaction));
+        sort_transaction(&mut transaction);
+        assert!(is_sorted(&transaction));
+        assert_eq!(transaction.input, expected);
+        assert_eq!(transaction.output, outputs);
+    }
+
+    #[test]
+    fn bip69_vector_2() {
+        let txid = "35288d269cee1941eaebb2ea85e32b42cdb2b04284a56d8b14dcc3f5c65d6055";
+        let small = output(100000000, "41046a0765b5865641ce08dd39690aade26dfbf5511430ca428a3089261361cef170e3929a68aee3d8d4848b0c5111b0a37b82b86ad559fd2a745b44d8e8d9dfdc0cac");
+        let large = output(2400000000, "41044a656f065871a353f216ca26cef8dde2f03e8c16202d2e8ad769f02032cb86a5eb5e56842e92e19141d60a01928f8dd2c875a390f67c1f6c94cfc617c0ea45afac");
+
+        let mut transaction = tx(
+            vec![input(&format!("{}:1", txid)), input(&format!("{}:0", txid))],
+            vec![large.clone(), small.clone()],
+        );
+        sort_transaction(&mut transaction);
+        assert_eq!(transaction.input[0].previous_output.vout, 0);
+        assert_eq!(transaction.input[1].previous_output.vout, 1);
+        assert_eq!(transaction.output, vec![small, large]);
+    }
+
+    #[test]
+    fn txids_compare_reversed() {
+        // The txids differ only in their first and last internal bytes.
+        let a = OutPoint::new(Txid::from_hex("0100000000000000000000000000000000000000000000000000000000000000").unwrap(), 0);
+        let b = OutPoint::new(Txid::from_hex("0000000000000000000000000000000000000000000000000000000000000002").unwrap(), 0);
+        assert!(a < b);
+        assert_eq!(cmp_outpoints(&a, &b), Ordering::Greater);
+    }
+
+    #[test]
+    fn duplicate_outputs_are_stable() {
+        let script = "76a914000000000000000000000000000000000000000088ac";
+        let outputs = vec![output(2, script), output(1, script), output(2, script), output(1, "51")];
+        let mut transaction = tx(vec![input("0000000000000000000000000000000000000000000000000000000000000001:0")], outputs);
+        sort_transaction(&mut transaction);
+        let once = transaction.clone();
+        sort_transaction(&mut transaction);
+        assert_eq!(transaction, once);
+        assert_eq!(transaction.output, vec![
+            output(1, "51"),
+            output(1, script),
+            output(2, script),
+            output(2, script),
+        ]);
+    }
+}
@@ -0,0 +1,312 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # BIP37 Bloom Filters
+//!
+//! Bloom filters which an SPV client loads into a peer with `filterload` so
+//! that it is only sent matching transactions and merkle blocks. The hashing
+//! follows BIP37, so a filter built here matches the same elements in the
+//! serving peer.
+
+
+use std::{cmp, fmt};
+use std::error;
+
+use blockdata::transaction::OutPoint;
+use consensus::encode::serialize;
+use network::message_bloom::{BloomFlags, FilterLoad, MAX_BLOOM_FILTER_SIZE, MAX_HASH_FUNCS};
+use util::endian;
+
+const LN2SQUARED: f64 = 0.480_453_013_918_201_4;
+const LN2: f64 = 0.693_147_180_559_945_3;
+
+/// Multiplier of the hash function number in the MurmurHash3 seed
+const SEED_MULTIPLIER: u32 = 0xFBA4_C795;
+
+/// A bloom filter which violates the BIP37 limits
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The filter is larger than [MAX_BLOOM_FILTER_SIZE] bytes
+    FilterTooLarge(usize),
+    /// The filter uses more than [MAX_HASH_FUNCS] hash functions
+    TooManyHashFuncs(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::FilterTooLarge(size) => write!(f, "bloom filter of {} bytes exceeds {} bytes", size, MAX_BLOOM_FILTER_SIZE),
+            Error::TooManyHashFuncs(n) => write!(f, "{} bloom filter hash functions exceed {}", n, MAX_HASH_FUNCS),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// A BIP37 bloom filter
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BloomFilter {
+    data: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    flags: BloomFlags,
+}
+
+impl BloomFilter {
+    /// Create an empty filter sized for `elements` elements with a false
+    /// positive rate of `fp_rate`, between 0 and 1, in the same way as
+    /// Bitcoin Core. Size and number of hash functions are capped at the
+    /// BIP37 limits, so the false positive rate of a large filter can be
+    /// worse than requested. A rate of 0 or less gives the largest filter,
+    /// and a rate of 1 or more, or NaN, an empty one matching everything.
+    pub fn new(elements: u32, fp_rate: f64, tweak: u32, flags: BloomFlags) -> BloomFilter {
+        let elements = cmp::max(elements, 1);
+        // clamp before casting, the float to int casts saturate only since Rust 1.45
+        let max_bits = (MAX_BLOOM_FILTER_SIZE * 8) as f64;
+        let bits = if fp_rate <= 0.0 {
+            max_bits
+        } else {
+            (-1.0 / LN2SQUARED * elements as f64 * fp_rate.ln()).max(0.0).min(max_bits)
+        };
+        let size = bits as usize / 8;
+        let hash_funcs = ((size * 8) as f64 / elements as f64 * LN2).min(MAX_HASH_FUNCS as f64) as u32;
+        BloomFilter {
+            data: vec![0; size],
+            hash_funcs: hash_funcs,
+            tweak: tweak,
+            flags: flags,
+        }
+    }
+
+    /// Take over the filter of a `filterload` message, checking the BIP37 limits.
+    pub fn from_filter_load(msg: FilterLoad) -> Result<BloomFilter, Error> {
+        if msg.filter.len() > MAX_BLOOM_FILTER_SIZE {
+            return Err(Error::FilterTooLarge(msg.filter.len()));
+        }
+        if msg.hash_funcs > MAX_HASH_FUNCS {
+            return Err(Error::TooManyHashFuncs(msg.hash_funcs));
+        }
+        Ok(BloomFilter {
+            data: msg.filter,
+            hash_funcs: msg.hash_funcs,
+            tweak: msg.tweak,
+            flags: msg.flags,
+        })
+    }
+
+    /// The `filterload` message loading this filter into a peer.
+    pub fn filter_load(&self) -> FilterLoad {
+        FilterLoad {
+            filter: self.data.clone(),
+            hash_funcs: self.hash_funcs,
+            tweak: self.tweak,
+            flags: self.flags,
+        }
+    }
+
+    /// The filter bits.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The number of hash functions.
+    pub fn hash_funcs(&self) -> u32 {
+        self.hash_funcs
+    }
+
+    /// The tweak added to the hash seeds.
+    pub fn tweak(&self) -> u32 {
+        self.tweak
+    }
+
+    /// How the serving peer updates the filter.
+    pub fn flags(&self) -> BloomFlags {
+        self.flags
+    }
+
+    /// Bit index of `data` for hash function number `n`.
+    fn bit_index(&self, n: u32, data: &[u8]) -> usize {
+        let seed = n.wrapping_mul(SEED_MULTIPLIER).wrapping_add(self.tweak);
+        murmur3(seed, data) as usize % (self.data.len() * 8)
+    }
+
+    /// Add a data element to the filter.
+    pub fn insert(&mut self, data: &[u8]) {
+        if self.data.is_empty() {
+            return;
+        }
+        for n in 0..self.hash_funcs {
+            let index = self.bit_index(n, data);
+            self.data[index >> 3] |= 1 << (index & 7);
+        }
+    }
+
+    /// Whether a data element might be in the filter. An empty filter matches everything.
+    pub fn contains(&self, data: &[u8]) -> bool {
+        if self.data.is_empty() {
+            return true;
+        }
+        (0..self.hash_funcs).all(|n| {
+            let index = self.bit_index(n, data);
+            self.data[index >> 3] & (1 << (index & 7)) != 0
+        })
+    }
+
+    /// Add an outpoint, as spent by the transactions to be matched.
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&serialize(outpoint));
+    }
+
+    /// Whether an outpoint might be in the filter.
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&serialize(outpoint))
+    }
+}
+
+/// MurmurHash3 (x86, 32-bit) as used by BIP37
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut h1 = seed;
+    let mut blocks = data.chunks(4);
+    let mut tail: &[u8] = &[];
+    for block in &mut blocks {
+        if block.len() < 4 {
+            tail = block;
+            break;
+        }
+        let k1 = endian::slice_to_u32_le(block).wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    if !tail.is_empty() {
+        let mut k1 = 0u32;
+        for (i, byte) in tail.iter().enumerate() {
+            k1 ^= (*byte as u32) << (8 * i);
+        }
+        h1 ^= k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85eb_ca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2_ae35);
+    h1 ^= h1 >> 16;
+    h1
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::{FromHex, ToHex};
+
+    use blockdata::transaction::OutPoint;
+    use consensus::encode::serialize;
+    use network::message_bloom::{BloomFlags, FilterLoad, MAX_BLOOM_FILTER_SIZE, MAX_HASH_FUNCS};
+
+    use super::{murmur3, BloomFilter, Error};
+
+    fn hex(s: &str) -> Vec<u8> {
+        Vec::from_hex(s).unwrap()
+    }
+
+    #[test]
+    fn murmur3_vectors() {
+        // from Bitcoin Core's hash_tests.cpp
+        let vectors: &[(u32, u32, &str)] = &[
+            (0x00000000, 0x00000000, ""),
+            (0x6a396f08, 0xFBA4C795, ""),
+            (0x81f16f39, 0xffffffff, ""),
+            (0x514e28b7, 0x00000000, "00"),
+            (0xea3f0b17, 0xFBA4C795, "00"),
+            (0xfd6cf10d, 0x00000000, "ff"),
+            (0x16c6b7ab, 0x00000000, "0011"),
+            (0x8eb51c3d, 0x00000000, "001122"),
+            (0xb4471bf8, 0x00000000, "00112233"),
+            (0xe2301fa8, 0x00000000, "0011223344"),
+            (0xfc2e4a15, 0x00000000, "001122334455"),
+            (0xb074502c, 0x00000000, "00112233445566"),
+            (0x8034d2a0, 0x00000000, "0011223344556677"),
+            (0xb4698def, 0x00000000, "001122334455667788"),
+        ];
+        for &(expected, seed, data) in vectors {
+            assert_eq!(murmur3(seed, &hex(data)), expected, "{} with seed {:x}", data, seed);
+        }
+    }
+
+    #[test]
+    fn create_insert_serialize() {
+        // from Bitcoin Core's bloom_tests.cpp
+        for &(tweak, expected) in &[(0, "03614e9b050000000000000001"), (2147483649, "03ce4299050000000100008001")] {
+            let mut filter = BloomFilter::new(3, 0.01, tweak, BloomFlags::All);
+            filter.insert(&hex("99108ad8ed9bb6274d3980bab5a85c048f0950c8"));
+            assert!(filter.contains(&hex("99108ad8ed9bb6274d3980bab5a85c048f0950c8")));
+            // one bit different in first byte
+            assert!(!filter.contains(&hex("19108ad8ed9bb6274d3980bab5a85c048f0950c8")));
+            filter.insert(&hex("b5a2c786d9ef4658287ced5914b37a1b4aa32eee"));
+            assert!(filter.contains(&hex("b5a2c786d9ef4658287ced5914b37a1b4aa32eee")));
+            filter.insert(&hex("b9300670b4c5366e95b2699e8b18bc75e5f729c5"));
+            assert!(filter.contains(&hex("b9300670b4c5366e95b2699e8b18bc75e5f729c5")));
+
+            let load = filter.filter_load();
+            assert_eq!(serialize(&load).to_hex(), expected);
+            assert_eq!(BloomFilter::from_filter_load(load), Ok(filter));
+        }
+    }
+
+    #[test]
+    fn outpoints() {
+        let outpoint = OutPoint::new(Default::default(), 3);
+        let mut filter = BloomFilter::new(10, 0.0001, 5, BloomFlags::None);
+        assert!(!filter.contains_outpoint(&outpoint));
+        filter.insert_outpoint(&outpoint);
+        assert!(filter.contains_outpoint(&outpoint));
+        assert!(!filter.contains_outpoint(&OutPoint::new(Default::default(), 4)));
+    }
+
+    #[test]
+    fn limits() {
+        let huge = BloomFilter::new(10_000_000, 0.000_001, 0, BloomFlags::All);
+        assert_eq!(huge.as_bytes().len(), MAX_BLOOM_FILTER_SIZE);
+        let precise = BloomFilter::new(1, 1e-30, 0, BloomFlags::All);
+        assert_eq!(precise.hash_funcs(), MAX_HASH_FUNCS);
+
+        // a filter without bits matches everything
+        let empty = BloomFilter::new(1, 1.0, 0, BloomFlags::All);
+        assert!(empty.as_bytes().is_empty());
+        assert!(empty.contains(b"anything"));
+
+        // rates outside of (0, 1) are clamped rather than overflowing the size
+        for &rate in &[0.0, -0.5, ::std::f64::NEG_INFINITY] {
+            let filter = BloomFilter::new(100, rate, 0, BloomFlags::All);
+            assert_eq!(filter.as_bytes().len(), MAX_BLOOM_FILTER_SIZE);
+            assert_eq!(filter.hash_funcs(), MAX_HASH_FUNCS);
+        }
+        for &rate in &[1.5, ::std::f64::INFINITY, ::std::f64::NAN] {
+            assert!(BloomFilter::new(100, rate, 0, BloomFlags::All).as_bytes().is_empty());
+        }
+
+        let load = FilterLoad { filter: vec![0; MAX_BLOOM_FILTER_SIZE + 1], hash_funcs: 1, tweak: 0, flags: BloomFlags::All };
+        assert_eq!(BloomFilter::from_filter_load(load), Err(Error::FilterTooLarge(MAX_BLOOM_FILTER_SIZE + 1)));
+        let load = FilterLoad { filter: vec![0; 10], hash_funcs: MAX_HASH_FUNCS + 1, tweak: 0, flags: BloomFlags::All };
+        assert_eq!(BloomFilter::from_filter_load(load), Err(Error::TooManyHashFuncs(MAX_HASH_FUNCS + 1)));
+    }
+}
@@ -17,16 +17,21 @@
 //! See Appendix A of the Blockstream sidechains whitepaper
 //! at http://blockstream.com/sidechains.pdf for details of
 //! what this does.
+//!
+//! This module is deprecated in favour of `util::pay_to_contract`, which
+//! also supports segwit outputs and reports invalid tweaks as errors.
 
 #![cfg_attr(not(test), deprecated)]
 
+
 use secp256k1::{self, Secp256k1};
 use PrivateKey;
 use PublicKey;
 use hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
 use blockdata::{opcodes, script};
 
-use std::{error, fmt};
+use std::fmt;
+use std::error;
 
 use hash_types::ScriptHash;
 use network::constants::Network;
@@ -73,7 +78,7 @@ impl fmt::Display for Error {
 
 #[allow(deprecated)]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
         match *self {
             Error::Secp(ref e) => Some(e),
             Error::Script(ref e) => Some(e),
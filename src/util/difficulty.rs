@@ -0,0 +1,434 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Difficulty and chain work
+//!
+//! Helpers to compare chains by the work which went into them, as needed for
+//! fork choice, and the difficulty retargeting which decides the `bits` each
+//! header must have. The work of a single block is [BlockHeader::work].
+//!
+
+use std::cmp;
+
+use blockdata::block::BlockHeader;
+use consensus::params::{DifficultyEra, Params};
+use util::uint::Uint256;
+
+/// Number of blocks Dark Gravity Wave v3 averages the targets and times of
+pub(crate) const DGW_PAST_BLOCKS: usize = 24;
+
+/// The total work of a chain of headers, the sum of the work of each header.
+///
+/// The sum saturates at the largest [Uint256] rather than overflowing, which
+/// no chain following the consensus rules can reach.
+pub fn total_work<'a, I>(headers: I) -> Uint256
+    where I: IntoIterator<Item = &'a BlockHeader>
+{
+    let max = !Uint256::default();
+    headers.into_iter().fold(Uint256::default(), |total, header| {
+        total.checked_add(header.work()).unwrap_or(max)
+    })
+}
+
+/// The difficulty of the compact target `bits`, as Monacoin Core's
+/// `GetDifficulty` reports it in `getblock` and `getmininginfo`.
+///
+/// Difficulty 1 is the target of compact `0x1d00ffff` on every network, like
+/// [BlockHeader::difficulty]. The float is computed from the mantissa and
+/// exponent of `bits` directly, as Core does, rather than by dividing the
+/// expanded targets, so the result matches Core's to the last digit.
+pub fn difficulty_float(bits: u32, _: &Params) -> f64 {
+    let mut shift = (bits >> 24) & 0xff;
+    let mut difficulty = 0x0000ffff as f64 / (bits & 0x00ffffff) as f64;
+    while shift < 29 {
+        difficulty *= 256.0;
+        shift += 1;
+    }
+    while shift > 29 {
+        difficulty /= 256.0;
+        shift -= 1;
+    }
+    difficulty
+}
+
+/// The target of the given `difficulty`, the inverse of [difficulty_float].
+///
+/// This is approximate, since a float only carries 53 bits of the target, and
+/// is meant for display and estimation only, never for validating headers.
+/// A zero, negative or NaN `difficulty` gives the largest [Uint256], as does
+/// any difficulty too small for its target to fit.
+pub fn target_from_difficulty(difficulty: f64) -> Uint256 {
+    let max = !Uint256::default();
+    // the target of difficulty 1 is 0xffff << 208
+    let mut quotient = 65535.0 / difficulty;
+    if !(difficulty > 0.0) || !quotient.is_finite() {
+        return max;
+    }
+    if quotient == 0.0 {
+        return Uint256::default();
+    }
+    // scale the quotient to an integer with exactly 53 significant bits
+    let mut exponent: i32 = 208;
+    while quotient >= 9007199254740992.0 {
+        quotient /= 2.0;
+        exponent += 1;
+    }
+    while quotient < 4503599627370496.0 {
+        quotient *= 2.0;
+        exponent -= 1;
+    }
+    let mantissa = quotient as u64;
+    if exponent > 256 - 53 {
+        max
+    } else if exponent >= 0 {
+        Uint256::from_u64(mantissa).unwrap() << exponent as usize
+    } else if exponent > -64 {
+        Uint256::from_u64(mantissa >> (-exponent) as u32).unwrap()
+    } else {
+        Uint256::default()
+    }
+}
+
+/// The compact form of the proof-of-work limit
+fn pow_limit_bits(params: &Params) -> u32 {
+    BlockHeader::compact_target_from_u256(&params.pow_limit)
+}
+
+/// The bits required of the header at `height` with timestamp `time`, given
+/// the headers before it with the last one at `height - 1`. `None` if `past`
+/// doesn't go back far enough, or if the retarget of the block's era isn't
+/// implemented.
+///
+/// As in Monacoin Core, blocks of [DifficultyEra::Lyra2Rev2Dgw3] are
+/// retargeted by Dark Gravity Wave v3, and blocks of [DifficultyEra::Original]
+/// with the classic retarget once per [Params::difficulty_adjustment_interval].
+/// The Kimoto Gravity Well and DigiShield retargets of the eras in between are
+/// not implemented; those blocks are below the checkpoints of Monacoin Core,
+/// which doesn't validate their retargets any more either.
+pub(crate) fn next_work_required(past: &[BlockHeader], height: u32, time: u32, params: &Params) -> Option<u32> {
+    let last = past.last()?;
+    match DifficultyEra::at_height(params, height) {
+        DifficultyEra::Original => classic_retarget(past, height, time, params),
+        DifficultyEra::KimotoGravityWell | DifficultyEra::DigiShield => None,
+        DifficultyEra::Lyra2Rev2Dgw3 => {
+            if params.no_pow_retargeting {
+                Some(last.bits)
+            } else if allows_min_difficulty(last, time, params) {
+                Some(pow_limit_bits(params))
+            } else {
+                dark_gravity_wave(past, height, params)
+            }
+        }
+    }
+}
+
+/// Whether a block at `time` after `last` may have the minimum difficulty,
+/// which test networks allow when no block has been found for twice the
+/// target spacing.
+fn allows_min_difficulty(last: &BlockHeader, time: u32, params: &Params) -> bool {
+    params.allow_min_difficulty_blocks && time as u64 > last.time as u64 + 2 * params.pow_target_spacing
+}
+
+/// Dark Gravity Wave v3, which retargets every block from the average
+/// target and the time taken of the last [DGW_PAST_BLOCKS] blocks.
+fn dark_gravity_wave(past: &[BlockHeader], height: u32, params: &Params) -> Option<u32> {
+    if (height as usize) <= DGW_PAST_BLOCKS {
+        return Some(pow_limit_bits(params));
+    }
+    if past.len() < DGW_PAST_BLOCKS {
+        return None;
+    }
+    let blocks = &past[past.len() - DGW_PAST_BLOCKS..];
+    // not really an average, but what Dash and Monacoin Core compute
+    let mut average = Uint256::default();
+    for (i, header) in blocks.iter().rev().enumerate() {
+        let count = i as u32 + 1;
+        let target = header.target();
+        average = if count == 1 {
+            target
+        } else {
+            (average.mul_u32(count) + target) / Uint256::from_u64(count as u64 + 1).unwrap()
+        };
+    }
+
+    let target_timespan = DGW_PAST_BLOCKS as i64 * params.pow_target_spacing as i64;
+    let actual_timespan = blocks[DGW_PAST_BLOCKS - 1].time as i64 - blocks[0].time as i64;
+    let actual_timespan = cmp::max(target_timespan / 3, cmp::min(actual_timespan, target_timespan * 3));
+
+    let new_target = average.mul_u32(actual_timespan as u32) / Uint256::from_u64(target_timespan as u64).unwrap();
+    Some(BlockHeader::compact_target_from_u256(&cmp::min(new_target, params.pow_limit)))
+}
+
+/// The classic retarget of Bitcoin once per difficulty adjustment interval,
+/// with Litecoin's fix of measuring the time over the whole interval.
+fn classic_retarget(past: &[BlockHeader], height: u32, time: u32, params: &Params) -> Option<u32> {
+    let interval = params.difficulty_adjustment_interval() as u32;
+    let last = past.last()?;
+    if height % interval != 0 {
+        if !params.allow_min_difficulty_blocks {
+            return Some(last.bits);
+        }
+        if allows_min_difficulty(last, time, params) {
+            return Some(pow_limit_bits(params));
+        }
+        // the bits of the last block which didn't use the minimum
+        // difficulty exception
+        let limit_bits = pow_limit_bits(params);
+        for (i, header) in past.iter().rev().enumerate() {
+            let header_height = height - 1 - i as u32;
+            if header_height == 0 || header_height % interval == 0 || header.bits != limit_bits {
+                return Some(header.bits);
+            }
+        }
+        return None;
+    }
+
+    if params.no_pow_retargeting {
+        return Some(last.bits);
+    }
+    let blocks_back = if height == interval { interval - 1 } else { interval } as usize;
+    if past.len() <= blocks_back {
+        return None;
+    }
+    let first = &past[past.len() - 1 - blocks_back];
+    Some(calculate_next_work_required(last.bits, first.time, last.time, params))
+}
+
+/// The bits after a classic retarget from `last_bits`, when the interval
+/// took from `first_block_time` to `last_block_time`.
+///
+/// The time taken is clamped to between a quarter and four times
+/// [Params::pow_target_timespan] and the new target to [Params::pow_limit].
+/// Networks with [Params::no_pow_retargeting] keep `last_bits`. The block
+/// whose time is `first_block_time` is found with
+/// [Params::next_retarget_height].
+pub fn calculate_next_work_required(last_bits: u32, first_block_time: u32, last_block_time: u32, params: &Params) -> u32 {
+    if params.no_pow_retargeting {
+        return last_bits;
+    }
+    let timespan = params.pow_target_timespan as i64;
+    let actual_timespan = last_block_time as i64 - first_block_time as i64;
+    let actual_timespan = cmp::max(timespan / 4, cmp::min(actual_timespan, timespan * 4));
+
+    let mut target = BlockHeader::u256_from_compact_target(last_bits);
+    // the intermediate value can overflow by one bit
+    let shift = target.bits() > params.pow_limit.bits() - 1;
+    if shift {
+        target = target >> 1;
+    }
+    target = target.mul_u32(actual_timespan as u32) / Uint256::from_u64(timespan as u64).unwrap();
+    if shift {
+        target = target << 1;
+    }
+    BlockHeader::compact_target_from_u256(&cmp::min(target, params.pow_limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::block::BlockHeader;
+    use blockdata::constants::genesis_block;
+    use consensus::params::Params;
+    use network::constants::Network;
+    use util::BitArray;
+    use util::uint::Uint256;
+
+    use super::{calculate_next_work_required, difficulty_float, next_work_required, target_from_difficulty, total_work};
+
+    /// Headers with the given bits, `spacing` seconds apart
+    fn headers(count: usize, bits: u32, spacing: u32) -> Vec<BlockHeader> {
+        let genesis = genesis_block(Network::Monacoin).header;
+        (0..count as u32).map(|i| BlockHeader {
+            time: genesis.time + i * spacing,
+            bits: bits,
+            ..genesis
+        }).collect()
+    }
+
+    #[test]
+    fn genesis_work() {
+        let genesis = genesis_block(Network::Monacoin).header;
+        assert_eq!(genesis.bits, 0x1e0ffff0);
+        // chainwork of the genesis block reported by monacoind
+        assert_eq!(genesis.work(), Uint256::from_u64(0x100010).unwrap());
+        assert_eq!(total_work(&[genesis]), genesis.work());
+        assert_eq!(total_work(&[genesis, genesis, genesis]), Uint256::from_u64(3 * 0x100010).unwrap());
+        assert_eq!(total_work(&[]), Uint256::default());
+    }
+
+    #[test]
+    fn invalid_bits() {
+        let header = |bits: u32| BlockHeader { bits: bits, ..genesis_block(Network::Monacoin).header };
+        // the largest possible target still needs some work
+        assert_eq!(header(0x2100ffff).work(), Uint256::one());
+        assert_eq!(header(0x1d00ffff).work(), Uint256::from_u64(0x100010001).unwrap());
+        for &bits in &[0, 0x01003456, 0x04923456, 0x01fedcba, 0x22000100, 0xff123456] {
+            assert_eq!(header(bits).work(), Uint256::default(), "bits {:x}", bits);
+        }
+        assert_eq!(total_work(&[header(0), header(0x04923456)]), Uint256::default());
+
+        // the total saturates
+        let easiest = header(0x03000001);
+        assert_eq!(easiest.work(), Uint256::one() << 255);
+        assert_eq!(total_work(&[easiest, easiest, easiest]), !Uint256::default());
+    }
+
+    #[test]
+    fn dark_gravity_wave() {
+        let params = Params::new(Network::Monacoin);
+        let height = params.switch_lyra2rev2_dgwblock;
+        let next = |past: &[BlockHeader]| next_work_required(past, height, past.last().unwrap().time + 90, &params);
+
+        // 23 intervals of 90 seconds against a target of 24
+        assert_eq!(next(&headers(24, 0x1e0ffff0, 90)), Some(0x1e0f5546));
+        assert_eq!(next(&headers(100, 0x1e0ffff0, 90)), Some(0x1e0f5546));
+        // the time taken is clamped to a third and three times the target
+        assert_eq!(next(&headers(24, 0x1e0ffff0, 1)), Some(0x1e055550));
+        assert_eq!(next(&headers(24, 0x1b0ffff0, 1000)), Some(0x1b2fffd0));
+        // and the target to the limit
+        assert_eq!(next(&headers(24, 0x1e0ffff0, 1000)), Some(0x1e0fffff));
+        // the average is weighted towards recent blocks
+        let mut past = headers(24, 0x1b0ffff0, 90);
+        past[0].bits = 0x1c0ffff0;
+        assert_eq!(next(&past), Some(0x1c00abbb));
+
+        assert_eq!(next(&headers(23, 0x1e0ffff0, 90)), None);
+    }
+
+    #[test]
+    fn classic_retarget() {
+        let params = Params::new(Network::Monacoin);
+        let interval = params.difficulty_adjustment_interval() as usize;
+        assert_eq!(interval, 1056);
+        let next = |past: &[BlockHeader], height: usize| {
+            next_work_required(past, height as u32, past.last().unwrap().time + 90, &params)
+        };
+
+        // no retarget between the boundaries
+        assert_eq!(next(&headers(1, 0x1e0ffff0, 90), 1057), Some(0x1e0ffff0));
+        // the first retarget only looks back to the genesis block, one
+        // interval short of the target timespan
+        assert_eq!(next(&headers(interval, 0x1e0ffff0, 90), interval), Some(0x1e0ffc0f));
+        // later ones over the whole interval
+        assert_eq!(next(&headers(interval, 0x1e0ffff0, 90), 2 * interval), None);
+        assert_eq!(next(&headers(interval + 1, 0x1e0ffff0, 90), 2 * interval), Some(0x1e0ffff0));
+        assert_eq!(next(&headers(interval + 1, 0x1c0ffff0, 1), 2 * interval), Some(0x1c03fffc));
+        assert_eq!(next(&headers(interval + 1, 0x1c0ffff0, 1000), 2 * interval), Some(0x1c3fffc0));
+        assert_eq!(next(&headers(interval + 1, 0x1e0ffff0, 1000), 2 * interval), Some(0x1e0fffff));
+    }
+
+    #[test]
+    fn era_switch_heights() {
+        let params = Params::new(Network::Monacoin);
+        let past = headers(24, 0x1e0ffff0, 90);
+        let next = |height: u32| next_work_required(&past, height, past.last().unwrap().time + 90, &params);
+
+        // the last block of the classic retarget keeps the bits, the Kimoto
+        // Gravity Well and DigiShield retargets after it aren't implemented
+        assert_eq!(next(params.switch_kgw_block - 1), Some(0x1e0ffff0));
+        assert_eq!(next(params.switch_kgw_block), None);
+        assert_eq!(next(params.switch_digishield_block), None);
+        // and Dark Gravity Wave takes over at the Lyra2REv2 switch
+        assert_eq!(next(params.switch_lyra2rev2_dgwblock - 1), None);
+        assert_eq!(next(params.switch_lyra2rev2_dgwblock), Some(0x1e0f5546));
+    }
+
+    #[test]
+    fn calculate_classic_retarget() {
+        let params = Params::new(Network::Monacoin);
+        let timespan = params.pow_target_timespan as u32;
+        let next = |bits: u32, time_taken: u32| calculate_next_work_required(bits, 1000, 1000 + time_taken, &params);
+
+        assert_eq!(next(0x1c0ffff0, timespan), 0x1c0ffff0);
+        assert_eq!(next(0x1c0ffff0, timespan / 2), 0x1c07fff8);
+        assert_eq!(next(0x1d1a2b3c, 80000), 0x1d160716);
+        // the time taken is clamped to a quarter and four times the target
+        assert_eq!(next(0x1c0ffff0, 1), 0x1c03fffc);
+        assert_eq!(next(0x1c0ffff0, 0), 0x1c03fffc);
+        assert_eq!(calculate_next_work_required(0x1c0ffff0, 2000, 1000, &params), 0x1c03fffc);
+        assert_eq!(next(0x1c0ffff0, 10_000_000), 0x1c3fffc0);
+        // and the target to the limit, without overflowing in between
+        assert_eq!(next(0x1e0ffff0, 2 * timespan), 0x1e0fffff);
+
+        let params = Params::new(Network::MonacoinRegtest);
+        assert_eq!(calculate_next_work_required(0x1c0ffff0, 1000, 1001, &params), 0x1c0ffff0);
+    }
+
+    #[test]
+    fn difficulty_floats() {
+        let params = Params::new(Network::Monacoin);
+        let close = |bits: u32, expected: f64| {
+            let difficulty = difficulty_float(bits, &params);
+            assert!(((difficulty - expected) / expected).abs() < 1e-9, "{:08x}: {} != {}", bits, difficulty, expected);
+        };
+        // difficulty of the genesis block reported by monacoind
+        let genesis = genesis_block(Network::Monacoin).header;
+        assert_eq!(genesis.difficulty_float(&params), 0.000244140625);
+        // the same formula as bitcoind, whose difficulty 1, block 100000 and
+        // the example of the Bitcoin wiki are well known
+        close(0x1d00ffff, 1.0);
+        close(0x1b04864c, 14484.1623612254);
+        close(0x1b0404cb, 16307.420938523983);
+        // the testnet proof-of-work limit
+        close(0x1e0fffff, 0.000244140625 * 0x0ffff0 as f64 / 0x0fffff as f64);
+
+        // the inverse is exact for powers of two times difficulty 1
+        assert_eq!(target_from_difficulty(1.0), BlockHeader::u256_from_compact_target(0x1d00ffff));
+        assert_eq!(target_from_difficulty(0.000244140625), genesis.target());
+        assert_eq!(target_from_difficulty(65536.0), BlockHeader::u256_from_compact_target(0x1b00ffff));
+        // and otherwise close
+        let target = BlockHeader::u256_from_compact_target(0x1b0404cb);
+        let approx = target_from_difficulty(difficulty_float(0x1b0404cb, &params));
+        let error = if approx > target { approx - target } else { target - approx };
+        assert!(error < target >> 40);
+
+        assert_eq!(target_from_difficulty(0.0), !Uint256::default());
+        assert_eq!(target_from_difficulty(-1.0), !Uint256::default());
+        assert_eq!(target_from_difficulty(::std::f64::NAN), !Uint256::default());
+        assert_eq!(target_from_difficulty(1e-80), !Uint256::default());
+        assert_eq!(target_from_difficulty(1e80), Uint256::default());
+        assert_eq!(target_from_difficulty(::std::f64::INFINITY), Uint256::default());
+    }
+
+    #[test]
+    fn min_difficulty_blocks() {
+        let params = Params::new(Network::MonacoinTestnet);
+        assert_eq!(params.switch_kgw_block, 10);
+        let limit_bits = 0x1e0fffff;
+        // heights 0 to 8 and 0 to 29, the last three using the exception
+        let mut classic = headers(9, 0x1d0ffff0, 90);
+        let mut dgw = headers(30, 0x1d0ffff0, 90);
+        for header in classic[6..].iter_mut().chain(dgw[27..].iter_mut()) {
+            header.bits = limit_bits;
+        }
+        let last_time = classic.last().unwrap().time;
+        let dgw_last_time = dgw.last().unwrap().time;
+
+        // after twice the target spacing of 90 seconds without a block, in
+        // either era
+        assert_eq!(next_work_required(&classic, 9, last_time + 181, &params), Some(limit_bits));
+        assert_eq!(next_work_required(&dgw, 60, dgw_last_time + 181, &params), Some(limit_bits));
+        // otherwise the last bits not using the exception
+        assert_eq!(next_work_required(&classic, 9, last_time + 180, &params), Some(0x1d0ffff0));
+        assert_eq!(next_work_required(&classic[6..], 9, last_time + 180, &params), None);
+        assert_eq!(next_work_required(&classic[6..], 3, last_time + 180, &params), Some(limit_bits));
+        assert_eq!(next_work_required(&dgw, 60, dgw_last_time + 180, &params), Some(0x1e0280ee));
+
+        // the retargets of the eras in between aren't implemented
+        assert_eq!(next_work_required(&dgw, 10, dgw_last_time + 180, &params), None);
+        assert_eq!(next_work_required(&dgw, 30, dgw_last_time + 181, &params), None);
+
+        let params = Params::new(Network::MonacoinRegtest);
+        assert_eq!(next_work_required(&dgw[29..], 100, dgw_last_time + 90, &params), Some(limit_bits));
+    }
+}
@@ -0,0 +1,629 @@
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! ECDSA signatures
+//!
+//! This module provides the [EcdsaSig] type, an ECDSA signature together with
+//! the sighash type it commits to, in the form which appears in scriptSigs,
+//! witnesses and PSBTs: a DER-encoded signature followed by one sighash byte.
+//! [is_valid_der_encoding] checks that encoding by the strict rules of BIP66.
+//!
+
+
+use std::{fmt, str};
+use std::error;
+
+use secp256k1::{self, Message, Secp256k1, Verification};
+
+use hashes::hex::{self, FromHex};
+use blockdata::script::{Builder, Script};
+use blockdata::transaction::{SigHashType, NonStandardSigHashType, Transaction, TxOut};
+use util::key::{self, PublicKey};
+use util::sighash::{self, SighashCache};
+
+/// An ECDSA signature with the corresponding hash type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EcdsaSig {
+    /// The underlying ECDSA signature
+    pub sig: secp256k1::Signature,
+    /// The corresponding hash type
+    pub hash_ty: SigHashType,
+}
+
+impl EcdsaSig {
+    /// Constructs an ECDSA signature for `SIGHASH_ALL`
+    pub fn sighash_all(sig: secp256k1::Signature) -> EcdsaSig {
+        EcdsaSig {
+            sig: sig,
+            hash_ty: SigHashType::All,
+        }
+    }
+
+    /// Deserialize from the format used in scripts: a strictly DER-encoded signature followed
+    /// by a standard sighash byte. The encoding must pass [is_valid_der_encoding].
+    pub fn from_slice(sl: &[u8]) -> Result<EcdsaSig, EcdsaSigError> {
+        let (hash_ty, sig) = split_hash_ty(sl)?;
+        if !is_valid_der_encoding(sl) {
+            return Err(EcdsaSigError::NonStrictDer);
+        }
+        let sig = secp256k1::Signature::from_der(sig)?;
+        Ok(EcdsaSig { sig: sig, hash_ty: hash_ty })
+    }
+
+    /// Deserialize from the format used in scripts, accepting signatures which are not
+    /// strictly DER-encoded.
+    ///
+    /// Signatures from before BIP66 was activated may use any BER encoding, so this is
+    /// needed to parse historical chain data. The sighash byte must still be standard.
+    /// Note that [EcdsaSig::to_vec] will not reproduce the original encoding of such a
+    /// signature, and [is_valid_der_encoding] tells whether it would be valid after BIP66.
+    pub fn from_slice_lax(sl: &[u8]) -> Result<EcdsaSig, EcdsaSigError> {
+        let (hash_ty, sig) = split_hash_ty(sl)?;
+        let sig = secp256k1::Signature::from_der_lax(sig)?;
+        Ok(EcdsaSig { sig: sig, hash_ty: hash_ty })
+    }
+
+    /// Deserialize a BER-encoded signature followed by a standard sighash byte, the same as
+    /// [EcdsaSig::from_slice_lax] under the name of [secp256k1::Signature::from_der_lax].
+    pub fn from_der_lax(sl: &[u8]) -> Result<EcdsaSig, EcdsaSigError> {
+        EcdsaSig::from_slice_lax(sl)
+    }
+
+    /// Replace the S value of the signature by its negation if it is in the upper half of
+    /// the curve order, giving the low-S form which standardness rules require. Both forms
+    /// are valid for the same message and key.
+    pub fn normalize_s(&mut self) {
+        self.sig.normalize_s();
+    }
+
+    /// Serialize to the format used in scripts: the DER-encoded signature followed by the
+    /// sighash byte.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut ser_sig = self.sig.serialize_der().to_vec();
+        ser_sig.push(self.hash_ty.to_u32() as u8);
+        ser_sig
+    }
+
+    /// Push the serialized signature onto a script being built, e.g. a scriptSig.
+    pub fn serialize_into_builder(&self, builder: Builder) -> Builder {
+        builder.push_slice(&self.to_vec())
+    }
+
+    /// Push the serialized signature as a new element of a witness stack.
+    pub fn serialize_into_witness(&self, witness: &mut Vec<Vec<u8>>) {
+        witness.push(self.to_vec());
+    }
+}
+
+/// Split the trailing sighash byte off a serialized signature.
+fn split_hash_ty(sl: &[u8]) -> Result<(SigHashType, &[u8]), EcdsaSigError> {
+    let (hash_ty, sig) = sl.split_last().ok_or(EcdsaSigError::EmptySignature)?;
+    let hash_ty = SigHashType::from_u32_standard(*hash_ty as u32)
+        .map_err(|NonStandardSigHashType(n)| EcdsaSigError::NonStandardSigHashType(n))?;
+    Ok((hash_ty, sig))
+}
+
+/// Whether `sig_with_hashtype`, a signature followed by its sighash byte as it is pushed
+/// in scripts, is strictly DER-encoded following the rules of BIP66, which Monacoin
+/// enforces from [Params::bip66_height](::consensus::params::Params::bip66_height) on.
+///
+/// This is a port of Core's `IsValidSignatureEncoding`: the signature must be a DER
+/// sequence of exactly two integers R and S, with correct lengths throughout, at most 73
+/// bytes long with the sighash byte, and R and S must be neither negative nor padded with
+/// unnecessary zero bytes. The sighash byte itself is not checked.
+pub fn is_valid_der_encoding(sig_with_hashtype: &[u8]) -> bool {
+    let sig = sig_with_hashtype;
+    // 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [sighash]
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    // a compound structure whose length covers the rest but the sighash byte
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    // the lengths of R and S must add up to the length of the signature
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+    // R and S must be non-empty integers, neither negative nor with a zero byte
+    // in front unless it is needed to keep them positive
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+    true
+}
+
+impl fmt::Display for EcdsaSig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        hex::format_hex(&self.sig.serialize_der(), f)?;
+        hex::format_hex(&[self.hash_ty.to_u32() as u8], f)
+    }
+}
+
+impl str::FromStr for EcdsaSig {
+    type Err = EcdsaSigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = Vec::from_hex(s)?;
+        EcdsaSig::from_slice(&bytes)
+    }
+}
+
+/// An error parsing a serialized [EcdsaSig].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EcdsaSigError {
+    /// Hex encoding error
+    HexEncoding(hex::Error),
+    /// The trailing sighash byte is not a standard sighash type
+    NonStandardSigHashType(u32),
+    /// Empty signature, so no sighash byte is present
+    EmptySignature,
+    /// The signature is not strictly DER-encoded, see [is_valid_der_encoding]
+    NonStrictDer,
+    /// secp256k1-related error
+    Secp256k1(secp256k1::Error),
+}
+
+impl fmt::Display for EcdsaSigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EcdsaSigError::HexEncoding(ref e) => write!(f, "EcdsaSig hex encoding error: {}", e),
+            EcdsaSigError::NonStandardSigHashType(hash_ty) =>
+                write!(f, "Non standard signature hash type {}", hash_ty),
+            EcdsaSigError::EmptySignature => write!(f, "Empty ECDSA signature"),
+            EcdsaSigError::NonStrictDer => write!(f, "ECDSA signature is not strictly DER-encoded"),
+            EcdsaSigError::Secp256k1(ref e) => write!(f, "Invalid ECDSA signature: {}", e),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for EcdsaSigError {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            EcdsaSigError::HexEncoding(ref e) => Some(e),
+            EcdsaSigError::Secp256k1(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+#[doc(hidden)]
+impl From<secp256k1::Error> for EcdsaSigError {
+    fn from(e: secp256k1::Error) -> EcdsaSigError {
+        EcdsaSigError::Secp256k1(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<hex::Error> for EcdsaSigError {
+    fn from(err: hex::Error) -> Self {
+        EcdsaSigError::HexEncoding(err)
+    }
+}
+
+/// Check the signature of a transaction input spending one of the standard
+/// single-key templates: P2PK, P2PKH, P2WPKH or P2SH-P2WPKH.
+///
+/// The signature and public key are taken from the input's scriptSig and
+/// witness, which must have exactly the shape the template requires, and the
+/// signature is checked against the legacy or BIP143 sighash of `tx` as
+/// appropriate. `spent` is the output the input spends. As in consensus,
+/// signatures with a high S value are accepted.
+pub fn verify_input_signature<C: Verification>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    index: usize,
+    spent: &TxOut,
+) -> Result<(), InputSignatureError> {
+    let input = tx.input.get(index).ok_or(InputSignatureError::SigHash(sighash::Error::IndexOutOfInputsBounds {
+        index: index,
+        inputs_size: tx.input.len(),
+    }))?;
+    let spk = &spent.script_pubkey;
+    let script_sig = pushes(&input.script_sig)?;
+    let witness: Vec<&[u8]> = input.witness.iter().map(Vec::as_slice).collect();
+
+    // the witness program is `None` for legacy spends
+    let (sig, pk, program) = if spk.is_p2pk() {
+        match (&script_sig[..], &witness[..]) {
+            (&[sig], &[]) => (sig, &spk.as_bytes()[1..spk.len() - 1], None),
+            _ => return Err(InputSignatureError::UnexpectedStack),
+        }
+    } else if spk.is_p2pkh() {
+        match (&script_sig[..], &witness[..]) {
+            (&[sig, pk], &[]) => (sig, pk, None),
+            _ => return Err(InputSignatureError::UnexpectedStack),
+        }
+    } else if spk.is_v0_p2wpkh() {
+        match (&script_sig[..], &witness[..]) {
+            (&[], &[sig, pk]) => (sig, pk, Some(&spk.as_bytes()[2..])),
+            _ => return Err(InputSignatureError::UnexpectedStack),
+        }
+    } else if spk.is_p2sh() {
+        let redeem_script = match script_sig[..] {
+            [redeem_script] => redeem_script,
+            _ => return Err(InputSignatureError::UnexpectedStack),
+        };
+        // consensus requires the witness program to be pushed with the
+        // minimal push, or the spend is malleable
+        if input.script_sig != Builder::new().push_slice(redeem_script).into_script() {
+            return Err(InputSignatureError::UnexpectedStack);
+        }
+        let script = Script::from(redeem_script.to_vec());
+        if !script.is_v0_p2wpkh() {
+            return Err(InputSignatureError::UnsupportedScript);
+        }
+        if script.to_p2sh() != *spk {
+            return Err(InputSignatureError::ScriptMismatch);
+        }
+        match witness[..] {
+            [sig, pk] => (sig, pk, Some(&redeem_script[2..])),
+            _ => return Err(InputSignatureError::UnexpectedStack),
+        }
+    } else {
+        return Err(InputSignatureError::UnsupportedScript);
+    };
+
+    let sig = EcdsaSig::from_slice(sig)?;
+    let pk = PublicKey::from_slice(pk)?;
+    let script_code = Script::new_p2pkh(&pk.pubkey_hash());
+    let sighash = if let Some(program) = program {
+        if !pk.compressed {
+            return Err(InputSignatureError::UncompressedSegwitKey);
+        }
+        if program != &pk.pubkey_hash()[..] {
+            return Err(InputSignatureError::ScriptMismatch);
+        }
+        SighashCache::new(tx).segwit_signature_hash(index, &script_code, spent.value, sig.hash_ty)?
+    } else {
+        if spk.is_p2pkh() && script_code != *spk {
+            return Err(InputSignatureError::ScriptMismatch);
+        }
+        SighashCache::new(tx).legacy_signature_hash(index, spk, sig.hash_ty.to_u32())?
+    };
+
+    let mut low_s = sig.sig;
+    low_s.normalize_s();
+    let msg = Message::from_slice(&sighash[..]).expect("sighashes are 32 bytes");
+    secp.verify(&msg, &low_s, &pk.key).map_err(|_| InputSignatureError::IncorrectSignature)
+}
+
+/// The data pushed by a push-only script
+fn pushes(script: &Script) -> Result<Vec<&[u8]>, InputSignatureError> {
+    if script.is_push_only() {
+        Ok(script.pushed_data())
+    } else {
+        Err(InputSignatureError::UnexpectedStack)
+    }
+}
+
+/// An error checking a transaction input's signature with [verify_input_signature].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputSignatureError {
+    /// The spent output is not one of the supported templates
+    UnsupportedScript,
+    /// The scriptSig or witness doesn't have the shape the template requires
+    UnexpectedStack,
+    /// The public key or redeem script doesn't hash to the spent output
+    ScriptMismatch,
+    /// Segwit v0 spends must use compressed keys
+    UncompressedSegwitKey,
+    /// The signature could not be parsed
+    Signature(EcdsaSigError),
+    /// The public key could not be parsed
+    Key(key::Error),
+    /// The signature hash could not be computed
+    SigHash(sighash::Error),
+    /// The signature is well-formed but doesn't sign the transaction
+    IncorrectSignature,
+}
+
+impl fmt::Display for InputSignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InputSignatureError::UnsupportedScript => write!(f, "unsupported spent script"),
+            InputSignatureError::UnexpectedStack => write!(f, "scriptSig or witness doesn't match the spent script"),
+            InputSignatureError::ScriptMismatch => write!(f, "public key or redeem script doesn't hash to the spent script"),
+            InputSignatureError::UncompressedSegwitKey => write!(f, "uncompressed public key in a segwit spend"),
+            InputSignatureError::Signature(ref e) => write!(f, "invalid signature: {}", e),
+            InputSignatureError::Key(ref e) => write!(f, "invalid public key: {}", e),
+            InputSignatureError::SigHash(ref e) => write!(f, "sighash error: {}", e),
+            InputSignatureError::IncorrectSignature => write!(f, "incorrect signature"),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for InputSignatureError {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            InputSignatureError::Signature(ref e) => Some(e),
+            InputSignatureError::Key(ref e) => Some(e),
+            InputSignatureError::SigHash(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+#[doc(hidden)]
+impl From<EcdsaSigError> for InputSignatureError {
+    fn from(e: EcdsaSigError) -> InputSignatureError {
+        InputSignatureError::Signature(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<key::Error> for InputSignatureError {
+    fn from(e: key::Error) -> InputSignatureError {
+        InputSignatureError::Key(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<sighash::Error> for InputSignatureError {
+    fn from(e: sighash::Error) -> InputSignatureError {
+        InputSignatureError::SigHash(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use secp256k1::{self, Secp256k1, Message};
+
+    use hashes::hex::FromHex;
+    use blockdata::script::Builder;
+    use blockdata::script::Script;
+    use blockdata::transaction::{SigHashType, Transaction, TxOut};
+    use consensus::encode::deserialize;
+    use network::constants::Network;
+    use util::address::Address;
+    use util::key::PublicKey;
+
+    use super::*;
+
+    // Spends a P2PKH output; the transaction used throughout the transaction tests
+    const TX: &'static str = "0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000";
+    const SIG: &'static str = "3046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c01";
+    const PUBKEY: &'static str = "033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52";
+
+    #[test]
+    fn chain_signature() {
+        let tx: Transaction = deserialize(&Vec::from_hex(TX).unwrap()).unwrap();
+        let script_sig: Vec<Vec<u8>> = tx.input[0].script_sig.instructions()
+            .map(|ins| match ins.unwrap() {
+                ::blockdata::script::Instruction::PushBytes(data) => data.to_vec(),
+                ::blockdata::script::Instruction::Op(_) => panic!("unexpected opcode"),
+            })
+            .collect();
+        assert_eq!(script_sig[0], Vec::from_hex(SIG).unwrap());
+
+        let ecdsa_sig = EcdsaSig::from_slice(&script_sig[0]).unwrap();
+        assert_eq!(ecdsa_sig.hash_ty, SigHashType::All);
+        assert_eq!(ecdsa_sig.to_vec(), script_sig[0]);
+        assert_eq!(ecdsa_sig.to_string(), SIG);
+        assert_eq!(EcdsaSig::from_str(SIG), Ok(ecdsa_sig));
+
+        // and it really signs the transaction
+        let pk = PublicKey::from_slice(&Vec::from_hex(PUBKEY).unwrap()).unwrap();
+        let script_pubkey = Address::p2pkh(&pk, Network::Monacoin).script_pubkey();
+        let sighash = tx.signature_hash(0, &script_pubkey, ecdsa_sig.hash_ty.to_u32());
+        let msg = Message::from_slice(&sighash[..]).unwrap();
+        // the signature predates low-S enforcement, which libsecp256k1 requires
+        let mut low_s = ecdsa_sig.sig;
+        low_s.normalize_s();
+        Secp256k1::verification_only().verify(&msg, &low_s, &pk.key).unwrap();
+
+        // re-serialize into a scriptSig and a witness
+        let rebuilt = ecdsa_sig.serialize_into_builder(Builder::new())
+            .push_slice(&Vec::from_hex(PUBKEY).unwrap())
+            .into_script();
+        assert_eq!(rebuilt, tx.input[0].script_sig);
+        let mut witness = vec![];
+        ecdsa_sig.serialize_into_witness(&mut witness);
+        assert_eq!(witness, vec![script_sig[0].clone()]);
+    }
+
+    #[test]
+    fn sighash_byte() {
+        let der = Vec::from_hex(&SIG[..SIG.len() - 2]).unwrap();
+        let sig = secp256k1::Signature::from_der(&der).unwrap();
+
+        for &(byte, hash_ty) in &[(0x01, SigHashType::All), (0x03, SigHashType::Single), (0x82, SigHashType::NonePlusAnyoneCanPay)] {
+            let mut ser = der.clone();
+            ser.push(byte);
+            let ecdsa_sig = EcdsaSig::from_slice(&ser).unwrap();
+            assert_eq!(ecdsa_sig, EcdsaSig { sig: sig, hash_ty: hash_ty });
+            assert_eq!(ecdsa_sig.to_vec(), ser);
+        }
+
+        // missing or non-standard sighash byte
+        assert_eq!(EcdsaSig::from_slice(&[]), Err(EcdsaSigError::EmptySignature));
+        assert_eq!(EcdsaSig::from_slice(&der), Err(EcdsaSigError::NonStandardSigHashType(0x8c)));
+        for &byte in &[0x00, 0x04, 0x80, 0x84, 0xff] {
+            let mut ser = der.clone();
+            ser.push(byte);
+            assert_eq!(EcdsaSig::from_slice(&ser), Err(EcdsaSigError::NonStandardSigHashType(byte as u32)));
+            assert_eq!(EcdsaSig::from_slice_lax(&ser), Err(EcdsaSigError::NonStandardSigHashType(byte as u32)));
+        }
+    }
+
+    // The signed transactions of the BIP143 native P2WPKH and P2SH-P2WPKH examples. The
+    // first spends a P2PK and a P2WPKH output, the second a P2SH-P2WPKH output.
+    const P2WPKH_TX: &'static str = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+    const P2SH_P2WPKH_TX: &'static str = "01000000000101db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a5477010000001716001479091972186c449eb1ded22b78e40d009bdf0089feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac02473044022047ac8e878352d3ebbde1c94ce3a10d057c24175747116f8288e5d794d12d482f0220217f36a485cae903c713331d877c1f64677e3622ad4010726870540656fe9dcb012103ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a2687392040000";
+
+    fn prevout(value: u64, script_pubkey: &str) -> TxOut {
+        TxOut { value: value, script_pubkey: Script::from(Vec::from_hex(script_pubkey).unwrap()) }
+    }
+
+    #[test]
+    fn verify_input_signatures() {
+        let secp = Secp256k1::verification_only();
+
+        let tx: Transaction = deserialize(&Vec::from_hex(P2WPKH_TX).unwrap()).unwrap();
+        let p2pk = prevout(625_000_000, "2103c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c241ce9fc198bd25432ac");
+        let p2wpkh = prevout(600_000_000, "00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1");
+        assert_eq!(verify_input_signature(&secp, &tx, 0, &p2pk), Ok(()));
+        assert_eq!(verify_input_signature(&secp, &tx, 1, &p2wpkh), Ok(()));
+
+        // the amount is committed to in segwit only
+        assert_eq!(verify_input_signature(&secp, &tx, 0, &TxOut { value: 1, ..p2pk.clone() }), Ok(()));
+        assert_eq!(
+            verify_input_signature(&secp, &tx, 1, &TxOut { value: 1, ..p2wpkh.clone() }),
+            Err(InputSignatureError::IncorrectSignature)
+        );
+        // wrong input, wrong template or tampered transaction
+        assert_eq!(verify_input_signature(&secp, &tx, 0, &p2wpkh), Err(InputSignatureError::UnexpectedStack));
+        assert_eq!(verify_input_signature(&secp, &tx, 1, &p2pk), Err(InputSignatureError::UnexpectedStack));
+        let p2wsh = TxOut { script_pubkey: p2wpkh.script_pubkey.to_v0_p2wsh(), ..p2wpkh.clone() };
+        assert_eq!(verify_input_signature(&secp, &tx, 1, &p2wsh), Err(InputSignatureError::UnsupportedScript));
+        assert_eq!(
+            verify_input_signature(&secp, &tx, 1, &prevout(600_000_000, "0014aef3890b85e63ceeb2f6d59522aa73e358b3fc3c")),
+            Err(InputSignatureError::ScriptMismatch)
+        );
+        let mut tampered = tx.clone();
+        tampered.output[0].value -= 1;
+        assert_eq!(verify_input_signature(&secp, &tampered, 0, &p2pk), Err(InputSignatureError::IncorrectSignature));
+        assert_eq!(verify_input_signature(&secp, &tampered, 1, &p2wpkh), Err(InputSignatureError::IncorrectSignature));
+        match verify_input_signature(&secp, &tx, 2, &p2wpkh) {
+            Err(InputSignatureError::SigHash(_)) => {},
+            other => panic!("expected out of bounds error, got {:?}", other),
+        }
+
+        let tx: Transaction = deserialize(&Vec::from_hex(P2SH_P2WPKH_TX).unwrap()).unwrap();
+        let p2sh = prevout(1_000_000_000, "a9144733f37cf4db86fbc2efed2500b4f4e49f31202387");
+        assert_eq!(verify_input_signature(&secp, &tx, 0, &p2sh), Ok(()));
+        assert_eq!(
+            verify_input_signature(&secp, &tx, 0, &prevout(1_000_000_000, "a914000000000000000000000000000000000000000087")),
+            Err(InputSignatureError::ScriptMismatch)
+        );
+        // BIP143 doesn't sign the scriptSig, but consensus rejects a witness
+        // program pushed any other way than with the minimal push
+        let mut pushdata1 = tx.clone();
+        let mut script_sig = vec![0x4c];
+        script_sig.extend_from_slice(tx.input[0].script_sig.as_bytes());
+        pushdata1.input[0].script_sig = Script::from(script_sig);
+        assert_eq!(verify_input_signature(&secp, &pushdata1, 0, &p2sh), Err(InputSignatureError::UnexpectedStack));
+
+        // and the P2PKH spend from the chain
+        let tx: Transaction = deserialize(&Vec::from_hex(TX).unwrap()).unwrap();
+        let pk = PublicKey::from_slice(&Vec::from_hex(PUBKEY).unwrap()).unwrap();
+        let p2pkh = TxOut { value: 0, script_pubkey: Address::p2pkh(&pk, Network::Monacoin).script_pubkey() };
+        assert_eq!(verify_input_signature(&secp, &tx, 0, &p2pkh), Ok(()));
+    }
+
+    #[test]
+    fn strict_and_lax_der() {
+        // r padded with a superfluous zero byte: valid BER, invalid DER
+        let lax = Vec::from_hex("304702220000f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c01").unwrap();
+        assert!(!is_valid_der_encoding(&lax));
+        assert_eq!(EcdsaSig::from_slice(&lax), Err(EcdsaSigError::NonStrictDer));
+        let ecdsa_sig = EcdsaSig::from_slice_lax(&lax).unwrap();
+        assert_eq!(EcdsaSig::from_der_lax(&lax), Ok(ecdsa_sig));
+        assert_eq!(ecdsa_sig, EcdsaSig::from_str(SIG).unwrap());
+        // re-encoding makes it strict
+        assert_eq!(ecdsa_sig.to_vec(), Vec::from_hex(SIG).unwrap());
+
+        assert!(EcdsaSig::from_str("zz").is_err());
+    }
+
+    #[test]
+    fn bip66_encoding() {
+        let valid = |hex: &str| is_valid_der_encoding(&Vec::from_hex(hex).unwrap());
+
+        // the pushed signatures of the "is incorrectly encoded for DERSIG" cases of Core's
+        // script_tests.json, whose last byte is the sighash byte
+        let script_tests = [
+            ("00000000000000000000000000000000000000000000000000000000000000000000000000000000\
+              00000000000000000000000000000000000000000000000000000000000000000000", "overly long"),
+            ("30220220000000000000000000000000000000000000000000000000000000000000000000", "missing S"),
+            ("3024021077777777777777777777777777777777020a7777777777777777777777777777777777", "invalid S length"),
+            ("302403107777777777777777777777777777777702107777777777777777777777777777777777", "non-integer R"),
+            ("302402107777777777777777777777777777777703107777777777777777777777777777777777", "non-integer S"),
+            ("3014020002107777777777777777777777777777777777", "zero-length R"),
+            ("3014021077777777777777777777777777777777020077", "zero-length S"),
+            ("302402107777777777777777777777777777777702108777777777777777777777777777777777", "negative S"),
+        ];
+        for &(hex, case) in script_tests.iter() {
+            assert!(!valid(hex), "{}", case);
+        }
+
+        // further cases around the same integers, with a SIGHASH_ALL byte
+        let cases = [
+            ("302402107777777777777777777777777777777702107777777777777777777777777777777701", true, "well formed"),
+            ("302402108777777777777777777777777777777702107777777777777777777777777777777701", false, "negative R"),
+            ("30250211007777777777777777777777777777777702107777777777777777777777777777777701", false, "padded R"),
+            ("30250210777777777777777777777777777777770211007777777777777777777777777777777701", false, "padded S"),
+            ("30250211008777777777777777777777777777777702107777777777777777777777777777777701", true, "R needing its zero byte"),
+            ("302502107777777777777777777777777777777702107777777777777777777777777777777701", false, "wrong total length"),
+            ("30240210777777777777777777777777777777770210777777777777777777777777777777770001", false, "trailing garbage"),
+            ("300602010102010101", true, "shortest"),
+            ("3005020101020101", false, "too short"),
+            (SIG, true, "longest"),
+            ("", false, "empty"),
+        ];
+        for &(hex, expected, case) in cases.iter() {
+            assert_eq!(valid(hex), expected, "{}", case);
+            // which from_slice checks after the sighash byte
+            if !expected && !hex.is_empty() {
+                assert_eq!(EcdsaSig::from_slice(&Vec::from_hex(hex).unwrap()), Err(EcdsaSigError::NonStrictDer), "{}", case);
+            }
+        }
+        // the sighash byte is left to the caller
+        let mut sig = Vec::from_hex(SIG).unwrap();
+        *sig.last_mut().unwrap() = 0x00;
+        assert!(is_valid_der_encoding(&sig));
+    }
+
+    #[test]
+    fn normalize_s() {
+        // the signature from the chain has a high S
+        let original = EcdsaSig::from_str(SIG).unwrap();
+        let mut ecdsa_sig = original;
+        ecdsa_sig.normalize_s();
+        assert_ne!(ecdsa_sig, original);
+        assert_eq!(ecdsa_sig.hash_ty, original.hash_ty);
+        let mut low_s = original.sig;
+        low_s.normalize_s();
+        assert_eq!(ecdsa_sig.sig, low_s);
+        assert!(is_valid_der_encoding(&ecdsa_sig.to_vec()));
+        assert_eq!(EcdsaSig::from_slice(&ecdsa_sig.to_vec()), Ok(ecdsa_sig));
+
+        // and normalizing is idempotent
+        let low = ecdsa_sig;
+        ecdsa_sig.normalize_s();
+        assert_eq!(ecdsa_sig, low);
+    }
+}
@@ -0,0 +1,604 @@
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Fee estimation from observed blocks
+//!
+//! A simple fee estimator for clients which can not ask a full node for
+//! `estimatesmartfee`, such as SPV wallets. It records weight-weighted
+//! fee-rate percentiles of recently connected blocks and estimates a fee rate
+//! for a confirmation target by taking the median, over a recent window, of
+//! the percentile which matches how quickly the caller wants to confirm.
+//!
+//! This is explicitly *not* a port of Bitcoin Core's estimator, which tracks
+//! how long mempool transactions take to confirm. It only looks at what got
+//! mined, so it can not tell a quiet network from miners ignoring fees.
+//!
+
+use std::collections::{HashMap, VecDeque};
+use std::{cmp, error, fmt, io};
+
+use hash_types::{BlockHash, Txid};
+use blockdata::block::Block;
+use blockdata::transaction::{OutPoint, Transaction, TxOut};
+use consensus::encode::{self, Decodable, Encodable, VarInt};
+use consensus::params::Params;
+use util::fee_rate::FeeRate;
+use util::persist::Persistable;
+
+/// The percentiles, by weight, recorded for every block
+pub const PERCENTILES: [u8; 5] = [10, 25, 50, 75, 90];
+
+/// How much history is kept by default, in seconds: one day of blocks
+const DEFAULT_HISTORY_SECS: u64 = 24 * 60 * 60;
+
+/// Estimates never look at fewer than this many seconds of blocks, so that at
+/// Monacoin's 90 second spacing a single odd block can not swing the result
+const MIN_LOOKBACK_SECS: u64 = 30 * 60;
+
+/// An error while computing the fees of a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The output spent by a transaction input could not be found
+    MissingPrevout(OutPoint),
+    /// A transaction spends less than it creates, or its values overflow
+    InvalidFee(Txid),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::MissingPrevout(ref outpoint) => write!(f, "missing spent output {}", outpoint),
+            Error::InvalidFee(ref txid) => write!(f, "invalid fee for transaction {}", txid),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::MissingPrevout(..) => "missing spent output",
+            Error::InvalidFee(..) => "invalid transaction fee",
+        }
+    }
+}
+
+/// Fee statistics of a single block
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockFeeStats {
+    /// Hash of the block
+    pub block_hash: BlockHash,
+    /// Number of transactions in the block, not counting the coinbase
+    pub tx_count: u64,
+    /// Sum of all fees paid in the block
+    pub total_fee: u64,
+    /// Fee rates at each of [PERCENTILES], by weight. Empty if the block only
+    /// contains its coinbase.
+    pub fee_rates: Vec<FeeRate>,
+}
+
+impl BlockFeeStats {
+    /// Compute the fee statistics of a block. `spent` is called for every
+    /// input which does not spend an output created earlier in the same block.
+    pub fn from_block<S>(block: &Block, spent: S) -> Result<BlockFeeStats, Error>
+        where S: FnMut(&OutPoint) -> Option<TxOut>
+    {
+        BlockFeeStats::from_transactions(block.block_hash(), &block.txdata, spent)
+    }
+
+    /// Compute the fee statistics of the block with the given hash and
+    /// transactions, e.g. as returned by [Block::into_shared].
+    pub fn from_transactions<T, S>(block_hash: BlockHash, txdata: &[T], mut spent: S) -> Result<BlockFeeStats, Error>
+        where T: AsRef<Transaction>, S: FnMut(&OutPoint) -> Option<TxOut>
+    {
+        let mut in_block: HashMap<Txid, &Transaction> = HashMap::new();
+        let mut samples = Vec::with_capacity(txdata.len());
+        let mut total_fee = 0u64;
+
+        for tx in txdata.iter().map(AsRef::as_ref) {
+            let txid = tx.txid();
+            if !tx.is_coin_base() {
+                let mut input_value = 0u64;
+                for input in &tx.input {
+                    let prevout = &input.previous_output;
+                    let value = match in_block.get(&prevout.txid) {
+                        Some(prev_tx) => prev_tx.output.get(prevout.vout as usize).map(|out| out.value),
+                        None => spent(prevout).map(|out| out.value),
+                    };
+                    let value = value.ok_or(Error::MissingPrevout(*prevout))?;
+                    input_value = input_value.checked_add(value).ok_or(Error::InvalidFee(txid))?;
+                }
+                let mut output_value = 0u64;
+                for output in &tx.output {
+                    output_value = output_value.checked_add(output.value).ok_or(Error::InvalidFee(txid))?;
+                }
+                let fee = input_value.checked_sub(output_value).ok_or(Error::InvalidFee(txid))?;
+                let weight = tx.weight().to_wu();
+                let rate = fee.checked_mul(1000).ok_or(Error::InvalidFee(txid))? / weight;
+
+                total_fee = total_fee.checked_add(fee).ok_or(Error::InvalidFee(txid))?;
+                samples.push((FeeRate::from_sat_per_kwu(rate), weight));
+            }
+            in_block.insert(txid, tx);
+        }
+
+        Ok(BlockFeeStats {
+            block_hash: block_hash,
+            tx_count: samples.len() as u64,
+            total_fee: total_fee,
+            fee_rates: weighted_percentiles(samples),
+        })
+    }
+
+    /// Get the fee rate at the given percentile, if it is one of [PERCENTILES]
+    /// and the block has any non-coinbase transactions.
+    pub fn percentile(&self, percentile: u8) -> Option<FeeRate> {
+        PERCENTILES.iter()
+            .position(|&p| p == percentile)
+            .and_then(|idx| self.fee_rates.get(idx).cloned())
+    }
+}
+
+/// Compute the fee rates at [PERCENTILES] of the given (rate, weight) pairs,
+/// where each transaction counts in proportion to its weight.
+fn weighted_percentiles(mut samples: Vec<(FeeRate, u64)>) -> Vec<FeeRate> {
+    if samples.is_empty() {
+        return vec![];
+    }
+    samples.sort();
+    let total_weight: u64 = samples.iter().map(|&(_, weight)| weight).sum();
+
+    let mut ret = Vec::with_capacity(PERCENTILES.len());
+    let mut samples_iter = samples.iter();
+    let mut cumulative = 0u64;
+    let mut current = FeeRate::ZERO;
+    for &percentile in PERCENTILES.iter() {
+        while cumulative * 100 < total_weight * percentile as u64 {
+            match samples_iter.next() {
+                Some(&(rate, weight)) => {
+                    cumulative += weight;
+                    current = rate;
+                }
+                None => break,
+            }
+        }
+        ret.push(current);
+    }
+    ret
+}
+
+/// Per-block fee-rate percentiles of the most recent blocks, with a rolling
+/// fee rate estimator on top.
+///
+/// Blocks must be added in chain order; on a reorg, disconnect the stale
+/// blocks with [BlockFeeHistogram::disconnect_tip] before adding the new ones.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockFeeHistogram {
+    /// Target block spacing of the chain, in seconds
+    target_spacing: u64,
+    /// Maximum number of blocks kept
+    capacity: u64,
+    /// Statistics of the most recent blocks, oldest first
+    blocks: VecDeque<BlockFeeStats>,
+}
+
+impl BlockFeeHistogram {
+    /// Create an empty histogram keeping one day of blocks of the given chain.
+    pub fn new(params: &Params) -> BlockFeeHistogram {
+        BlockFeeHistogram::with_capacity(params, (DEFAULT_HISTORY_SECS / params.pow_target_spacing) as usize)
+    }
+
+    /// Create an empty histogram keeping at most `capacity` blocks.
+    pub fn with_capacity(params: &Params, capacity: usize) -> BlockFeeHistogram {
+        BlockFeeHistogram {
+            target_spacing: params.pow_target_spacing,
+            capacity: cmp::max(capacity, 1) as u64,
+            blocks: VecDeque::new(),
+        }
+    }
+
+    /// Compute the statistics of the next block in the chain and add them,
+    /// dropping the oldest block if the histogram is full.
+    pub fn add_block<S>(&mut self, block: &Block, spent: S) -> Result<&BlockFeeStats, Error>
+        where S: FnMut(&OutPoint) -> Option<TxOut>
+    {
+        let stats = BlockFeeStats::from_block(block, spent)?;
+        Ok(self.add_stats(stats))
+    }
+
+    /// Like [BlockFeeHistogram::add_block], for a block whose transactions
+    /// are held separately from its header.
+    pub fn add_transactions<T, S>(&mut self, block_hash: BlockHash, txdata: &[T], spent: S) -> Result<&BlockFeeStats, Error>
+        where T: AsRef<Transaction>, S: FnMut(&OutPoint) -> Option<TxOut>
+    {
+        let stats = BlockFeeStats::from_transactions(block_hash, txdata, spent)?;
+        Ok(self.add_stats(stats))
+    }
+
+    /// Add precomputed statistics of the next block in the chain.
+    pub fn add_stats(&mut self, stats: BlockFeeStats) -> &BlockFeeStats {
+        if self.blocks.len() as u64 >= self.capacity {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(stats);
+        self.blocks.back().unwrap()
+    }
+
+    /// Remove and return the statistics of the most recently added block.
+    pub fn disconnect_tip(&mut self) -> Option<BlockFeeStats> {
+        self.blocks.pop_back()
+    }
+
+    /// Iterate over the recorded blocks, oldest first
+    pub fn blocks(&self) -> ::std::collections::vec_deque::Iter<BlockFeeStats> {
+        self.blocks.iter()
+    }
+
+    /// Estimate the fee rate needed to confirm within `target_blocks` blocks.
+    ///
+    /// The shorter the target in wall clock time, the higher the percentile
+    /// used: 90th for up to 3 minutes, 75th for up to 15 minutes, the median
+    /// for up to an hour, 25th up to 6 hours and 10th beyond. The estimate is
+    /// the median of that percentile over the most recent blocks, looking back
+    /// at least 30 minutes or twice the target, whichever is longer.
+    ///
+    /// Blocks containing only a coinbase are ignored, since empty blocks are
+    /// as often a mining artifact as a sign of an empty mempool. Returns
+    /// [None] if no block in the lookback window contains transactions.
+    pub fn estimate_feerate(&self, target_blocks: u32) -> Option<FeeRate> {
+        let target_blocks = cmp::max(target_blocks, 1) as u64;
+        let target_secs = target_blocks * self.target_spacing;
+        let percentile = if target_secs <= 3 * 60 {
+            90
+        } else if target_secs <= 15 * 60 {
+            75
+        } else if target_secs <= 60 * 60 {
+            50
+        } else if target_secs <= 6 * 60 * 60 {
+            25
+        } else {
+            10
+        };
+        let min_lookback = (MIN_LOOKBACK_SECS + self.target_spacing - 1) / self.target_spacing;
+        let lookback = cmp::max(target_blocks * 2, min_lookback) as usize;
+
+        let mut rates: Vec<FeeRate> = self.blocks.iter()
+            .rev()
+            .take(lookback)
+            .filter_map(|stats| stats.percentile(percentile))
+            .collect();
+        if rates.is_empty() {
+            return None;
+        }
+        rates.sort();
+        Some(rates[rates.len() / 2])
+    }
+}
+
+serde_struct_impl!(BlockFeeStats, block_hash, tx_count, total_fee, fee_rates);
+serde_struct_impl!(BlockFeeHistogram, target_spacing, capacity, blocks);
+
+impl Encodable for BlockFeeStats {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.block_hash.consensus_encode(&mut s)?;
+        len += self.tx_count.consensus_encode(&mut s)?;
+        len += self.total_fee.consensus_encode(&mut s)?;
+        let fee_rates: Vec<u64> = self.fee_rates.iter().map(|r| r.as_sat_per_kwu()).collect();
+        len += fee_rates.consensus_encode(&mut s)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for BlockFeeStats {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let block_hash = Decodable::consensus_decode(&mut d)?;
+        let tx_count = Decodable::consensus_decode(&mut d)?;
+        let total_fee = Decodable::consensus_decode(&mut d)?;
+        let fee_rates: Vec<u64> = Decodable::consensus_decode(&mut d)?;
+        if !fee_rates.is_empty() && fee_rates.len() != PERCENTILES.len() {
+            return Err(encode::Error::ParseFailed("wrong number of fee rate percentiles"));
+        }
+        Ok(BlockFeeStats {
+            block_hash: block_hash,
+            tx_count: tx_count,
+            total_fee: total_fee,
+            fee_rates: fee_rates.into_iter().map(FeeRate::from_sat_per_kwu).collect(),
+        })
+    }
+}
+
+impl Encodable for BlockFeeHistogram {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.target_spacing.consensus_encode(&mut s)?;
+        len += self.capacity.consensus_encode(&mut s)?;
+        len += VarInt(self.blocks.len() as u64).consensus_encode(&mut s)?;
+        for stats in &self.blocks {
+            len += stats.consensus_encode(&mut s)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for BlockFeeHistogram {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let target_spacing: u64 = Decodable::consensus_decode(&mut d)?;
+        let capacity: u64 = Decodable::consensus_decode(&mut d)?;
+        let count = VarInt::consensus_decode(&mut d)?.0;
+        if target_spacing == 0 || capacity == 0 || count > capacity {
+            return Err(encode::Error::ParseFailed("invalid fee histogram parameters"));
+        }
+        // the capacity is untrusted, so grow as the blocks are actually read
+        let mut blocks = VecDeque::new();
+        for _ in 0..count {
+            blocks.push_back(Decodable::consensus_decode(&mut d)?);
+        }
+        Ok(BlockFeeHistogram {
+            target_spacing: target_spacing,
+            capacity: capacity,
+            blocks: blocks,
+        })
+    }
+}
+
+/// Fee histograms can be saved with [::util::persist::save] so that estimates
+/// are available right after a restart.
+impl Persistable for BlockFeeHistogram {
+    const MAGIC: [u8; 4] = *b"fees";
+    const CURRENT_VERSION: u32 = 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use hashes::Hash;
+    use hash_types::{Txid, TxMerkleNode};
+    use blockdata::block::{Block, BlockHeader};
+    use blockdata::script::Script;
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut};
+    use consensus::params::Params;
+    use network::constants::Network;
+    use util::fee_rate::FeeRate;
+    use util::persist;
+
+    use super::*;
+
+    fn coinbase() -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::from(vec![0x51, 0x51]),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value: 50_0000_0000, script_pubkey: Script::new() }],
+        }
+    }
+
+    /// Builds a block whose transactions weigh exactly 1000 wu each and pay
+    /// the given fee rates, registering the outputs they spend in `prevouts`.
+    fn block_with_rates(rates: &[u64], prevouts: &mut HashMap<OutPoint, TxOut>) -> Block {
+        let mut txdata = vec![coinbase()];
+        for &rate in rates {
+            let outpoint = OutPoint { txid: Txid::hash(&[prevouts.len() as u8, (prevouts.len() >> 8) as u8]), vout: 0 };
+            prevouts.insert(outpoint, TxOut { value: 100_000 + rate, script_pubkey: Script::new() });
+            let tx = Transaction {
+                version: 1,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn { previous_output: outpoint, script_sig: Script::new(), sequence: Sequence::MAX, witness: vec![] }],
+                output: vec![TxOut { value: 100_000, script_pubkey: Script::from(vec![0x6a; 190]) }],
+            };
+            assert_eq!(tx.weight().to_wu(), 1000);
+            txdata.push(tx);
+        }
+        Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root: TxMerkleNode::default(),
+                time: prevouts.len() as u32,
+                bits: 0,
+                nonce: 0,
+            },
+            txdata: txdata,
+        }
+    }
+
+    fn sat_kwu(rates: &[u64]) -> Vec<FeeRate> {
+        rates.iter().map(|&r| FeeRate::from_sat_per_kwu(r)).collect()
+    }
+
+    #[test]
+    fn block_percentiles() {
+        let mut prevouts = HashMap::new();
+        let block = block_with_rates(&[10, 1, 9, 2, 8, 3, 7, 4, 6, 5], &mut prevouts);
+        let stats = BlockFeeStats::from_block(&block, |o| prevouts.get(o).cloned()).unwrap();
+        assert_eq!(stats.tx_count, 10);
+        assert_eq!(stats.total_fee, 55);
+        assert_eq!(stats.fee_rates, sat_kwu(&[1, 3, 5, 8, 9]));
+        assert_eq!(stats.percentile(50), Some(FeeRate::from_sat_per_kwu(5)));
+        assert_eq!(stats.percentile(42), None);
+
+        // a single transaction is every percentile
+        let block = block_with_rates(&[7], &mut prevouts);
+        let stats = BlockFeeStats::from_block(&block, |o| prevouts.get(o).cloned()).unwrap();
+        assert_eq!(stats.fee_rates, sat_kwu(&[7, 7, 7, 7, 7]));
+    }
+
+    #[test]
+    fn shared_transactions() {
+        let mut prevouts = HashMap::new();
+        let block = block_with_rates(&[10, 1, 9, 2, 8, 3, 7, 4, 6, 5], &mut prevouts);
+        let expected = BlockFeeStats::from_block(&block, |o| prevouts.get(o).cloned()).unwrap();
+
+        let block_hash = block.block_hash();
+        let txdata = block.into_shared();
+        let mempool: Vec<Arc<Transaction>> = txdata.iter().skip(1).cloned().collect();
+        let stats = BlockFeeStats::from_transactions(block_hash, &txdata, |o| prevouts.get(o).cloned()).unwrap();
+        assert_eq!(stats, expected);
+
+        let params = Params::new(Network::Monacoin);
+        let mut hist = BlockFeeHistogram::new(&params);
+        hist.add_transactions(block_hash, &txdata, |o| prevouts.get(o).cloned()).unwrap();
+        assert_eq!(hist.blocks().next(), Some(&expected));
+        // the transactions were never copied
+        assert!(txdata.iter().skip(1).all(|tx| Arc::strong_count(tx) == 2));
+        assert!(mempool.iter().zip(&txdata[1..]).all(|(a, b)| Arc::ptr_eq(a, b)));
+    }
+
+    #[test]
+    fn block_percentiles_weighted() {
+        // one heavy transaction dominates the low percentiles
+        let samples = vec![
+            (FeeRate::from_sat_per_kwu(100), 1000),
+            (FeeRate::from_sat_per_kwu(1), 8000),
+            (FeeRate::from_sat_per_kwu(50), 1000),
+        ];
+        assert_eq!(weighted_percentiles(samples), sat_kwu(&[1, 1, 1, 1, 50]));
+    }
+
+    #[test]
+    fn block_errors() {
+        let mut prevouts = HashMap::new();
+        let block = block_with_rates(&[1, 2], &mut prevouts);
+        let missing = block.txdata[2].input[0].previous_output;
+        assert_eq!(
+            BlockFeeStats::from_block(&block, |o| if *o == missing { None } else { prevouts.get(o).cloned() }),
+            Err(Error::MissingPrevout(missing))
+        );
+        assert_eq!(
+            BlockFeeStats::from_block(&block, |_| Some(TxOut { value: 0, script_pubkey: Script::new() })),
+            Err(Error::InvalidFee(block.txdata[1].txid()))
+        );
+    }
+
+    #[test]
+    fn in_block_spend() {
+        let mut prevouts = HashMap::new();
+        let mut block = block_with_rates(&[4], &mut prevouts);
+        let child = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint { txid: block.txdata[1].txid(), vout: 0 },
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value: 99_990, script_pubkey: Script::from(vec![0x6a; 190]) }],
+        };
+        block.txdata.push(child);
+        let stats = BlockFeeStats::from_block(&block, |o| prevouts.get(o).cloned()).unwrap();
+        assert_eq!(stats.total_fee, 14);
+        assert_eq!(stats.fee_rates, sat_kwu(&[4, 4, 4, 10, 10]));
+    }
+
+    #[test]
+    fn estimates() {
+        let params = Params::new(Network::Monacoin);
+        let mut hist = BlockFeeHistogram::new(&params);
+        assert_eq!(hist.estimate_feerate(1), None);
+
+        let mut prevouts = HashMap::new();
+        for _ in 0..30 {
+            let block = block_with_rates(&[10, 1, 9, 2, 8, 3, 7, 4, 6, 5], &mut prevouts);
+            hist.add_block(&block, |o| prevouts.get(o).cloned()).unwrap();
+        }
+        // 90 second blocks: 2 blocks is 3 minutes, 10 blocks 15 minutes, 40 blocks an hour
+        assert_eq!(hist.estimate_feerate(0), Some(FeeRate::from_sat_per_kwu(9)));
+        assert_eq!(hist.estimate_feerate(2), Some(FeeRate::from_sat_per_kwu(9)));
+        assert_eq!(hist.estimate_feerate(3), Some(FeeRate::from_sat_per_kwu(8)));
+        assert_eq!(hist.estimate_feerate(10), Some(FeeRate::from_sat_per_kwu(8)));
+        assert_eq!(hist.estimate_feerate(40), Some(FeeRate::from_sat_per_kwu(5)));
+        assert_eq!(hist.estimate_feerate(240), Some(FeeRate::from_sat_per_kwu(3)));
+        assert_eq!(hist.estimate_feerate(1000), Some(FeeRate::from_sat_per_kwu(1)));
+
+        // the most recent blocks, at least 20 of them, decide the estimate
+        for _ in 0..19 {
+            let block = block_with_rates(&[100], &mut prevouts);
+            hist.add_block(&block, |o| prevouts.get(o).cloned()).unwrap();
+        }
+        assert_eq!(hist.estimate_feerate(1), Some(FeeRate::from_sat_per_kwu(100)));
+        for _ in 0..10 {
+            hist.disconnect_tip();
+        }
+        assert_eq!(hist.estimate_feerate(1), Some(FeeRate::from_sat_per_kwu(9)));
+    }
+
+    #[test]
+    fn estimates_empty_blocks() {
+        let params = Params::new(Network::Monacoin);
+        let mut hist = BlockFeeHistogram::with_capacity(&params, 25);
+        let mut prevouts = HashMap::new();
+        let block = block_with_rates(&[3], &mut prevouts);
+        hist.add_block(&block, |o| prevouts.get(o).cloned()).unwrap();
+
+        // empty blocks are skipped rather than counted as zero fee
+        for _ in 0..10 {
+            let block = block_with_rates(&[], &mut prevouts);
+            let stats = hist.add_block(&block, |_| None).unwrap();
+            assert_eq!(stats.tx_count, 0);
+            assert!(stats.fee_rates.is_empty());
+        }
+        assert_eq!(hist.estimate_feerate(1), Some(FeeRate::from_sat_per_kwu(3)));
+
+        // once the window only has empty blocks there is nothing to go on
+        for _ in 0..20 {
+            let block = block_with_rates(&[], &mut prevouts);
+            hist.add_block(&block, |_| None).unwrap();
+        }
+        assert_eq!(hist.blocks().count(), 25);
+        assert_eq!(hist.estimate_feerate(1), None);
+        assert_eq!(hist.estimate_feerate(1000), None);
+    }
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let params = Params::new(Network::Monacoin);
+        let mut hist = BlockFeeHistogram::with_capacity(&params, 3);
+        let mut prevouts = HashMap::new();
+        for rates in &[&[1, 2, 3][..], &[], &[4], &[5, 6]] {
+            let block = block_with_rates(rates, &mut prevouts);
+            hist.add_block(&block, |o| prevouts.get(o).cloned()).unwrap();
+        }
+
+        let mut snapshot = vec![];
+        persist::save(&hist, &mut snapshot).unwrap();
+        let loaded: BlockFeeHistogram = persist::load(&snapshot[..]).unwrap();
+        assert_eq!(loaded, hist);
+        assert_eq!(loaded.estimate_feerate(1), hist.estimate_feerate(1));
+
+        // more blocks than the capacity
+        let mut bad = hist.clone();
+        bad.capacity = 2;
+        assert!(encode::deserialize::<BlockFeeHistogram>(&encode::serialize(&bad)).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_roundtrip() {
+        let params = Params::new(Network::Monacoin);
+        let mut hist = BlockFeeHistogram::new(&params);
+        let mut prevouts = HashMap::new();
+        let block = block_with_rates(&[1, 2, 3], &mut prevouts);
+        hist.add_block(&block, |o| prevouts.get(o).cloned()).unwrap();
+        serde_round_trip!(hist);
+    }
+}
@@ -0,0 +1,269 @@
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Fee rates
+//!
+//! This module introduces the [Weight] and [FeeRate] types, which wrap the
+//! bare integers usually used for fee calculations so that weight units,
+//! virtual bytes and satoshis can not be mixed up by accident.
+//!
+
+use std::fmt;
+use std::ops;
+
+use blockdata::constants::WITNESS_SCALE_FACTOR;
+
+/// The weight of a transaction or block, in weight units as defined by BIP141.
+///
+/// Warning!
+///
+/// The operations from [std::ops] that [Weight] implements will panic when
+/// overflow or underflow occurs. Use the `checked_` methods to avoid this.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Weight(u64);
+
+impl Weight {
+    /// Zero weight units.
+    pub const ZERO: Weight = Weight(0);
+
+    /// Create a [Weight] from a number of weight units.
+    pub fn from_wu(wu: u64) -> Weight {
+        Weight(wu)
+    }
+
+    /// Create a [Weight] from a number of virtual bytes, returning [None] on overflow.
+    pub fn from_vb(vb: u64) -> Option<Weight> {
+        vb.checked_mul(WITNESS_SCALE_FACTOR as u64).map(Weight)
+    }
+
+    /// Create a [Weight] from the size of data which is not witness-discounted.
+    pub fn from_non_witness_data_size(size: u64) -> Option<Weight> {
+        Weight::from_vb(size)
+    }
+
+    /// Get the number of weight units.
+    pub fn to_wu(self) -> u64 {
+        self.0
+    }
+
+    /// Get the number of virtual bytes, rounding up.
+    pub fn to_vbytes_ceil(self) -> u64 {
+        let scale = WITNESS_SCALE_FACTOR as u64;
+        self.0 / scale + if self.0 % scale == 0 { 0 } else { 1 }
+    }
+
+    /// Get the number of virtual bytes, rounding down.
+    pub fn to_vbytes_floor(self) -> u64 {
+        self.0 / WITNESS_SCALE_FACTOR as u64
+    }
+
+    /// Checked addition. Returns [None] if overflow occurred.
+    pub fn checked_add(self, rhs: Weight) -> Option<Weight> {
+        self.0.checked_add(rhs.0).map(Weight)
+    }
+
+    /// Checked subtraction. Returns [None] if overflow occurred.
+    pub fn checked_sub(self, rhs: Weight) -> Option<Weight> {
+        self.0.checked_sub(rhs.0).map(Weight)
+    }
+
+    /// Checked multiplication. Returns [None] if overflow occurred.
+    pub fn checked_mul(self, rhs: u64) -> Option<Weight> {
+        self.0.checked_mul(rhs).map(Weight)
+    }
+}
+
+impl fmt::Display for Weight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} wu", self.0)
+    }
+}
+
+impl ops::Add for Weight {
+    type Output = Weight;
+
+    fn add(self, rhs: Weight) -> Self::Output {
+        self.checked_add(rhs).expect("Weight addition error")
+    }
+}
+
+impl ops::AddAssign for Weight {
+    fn add_assign(&mut self, other: Weight) {
+        *self = *self + other
+    }
+}
+
+impl ops::Sub for Weight {
+    type Output = Weight;
+
+    fn sub(self, rhs: Weight) -> Self::Output {
+        self.checked_sub(rhs).expect("Weight subtraction error")
+    }
+}
+
+impl ops::SubAssign for Weight {
+    fn sub_assign(&mut self, other: Weight) {
+        *self = *self - other
+    }
+}
+
+/// A fee rate, stored internally as satoshis per 1000 weight units (sat/kWU).
+///
+/// One sat/vB is exactly 250 sat/kWU, so every sat/vB rate can be represented
+/// without loss of precision.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    /// A fee rate of zero.
+    pub const ZERO: FeeRate = FeeRate(0);
+
+    /// Create a [FeeRate] from satoshis per 1000 weight units.
+    pub const fn from_sat_per_kwu(sat_kwu: u64) -> FeeRate {
+        FeeRate(sat_kwu)
+    }
+
+    /// Create a [FeeRate] from satoshis per virtual byte, returning [None] on overflow.
+    pub fn from_sat_per_vb(sat_vb: u64) -> Option<FeeRate> {
+        // 1 vb == 4 wu, so 1 sat/vb == 1000 / 4 sat/kwu
+        sat_vb.checked_mul(1000 / WITNESS_SCALE_FACTOR as u64).map(FeeRate)
+    }
+
+    /// Get the fee rate in satoshis per 1000 weight units.
+    pub fn as_sat_per_kwu(self) -> u64 {
+        self.0
+    }
+
+    /// Get the fee rate in satoshis per virtual byte, rounding down.
+    pub fn as_sat_per_vb_floor(self) -> u64 {
+        self.0 / (1000 / WITNESS_SCALE_FACTOR as u64)
+    }
+
+    /// Get the fee rate in satoshis per virtual byte, rounding up.
+    pub fn as_sat_per_vb_ceil(self) -> u64 {
+        let scale = 1000 / WITNESS_SCALE_FACTOR as u64;
+        self.0 / scale + if self.0 % scale == 0 { 0 } else { 1 }
+    }
+
+    /// Calculate the fee in satoshis for the given [Weight], rounding up so
+    /// that the resulting fee never falls below the rate.
+    ///
+    /// Returns [None] if the computation overflows.
+    pub fn fee_wu(self, weight: Weight) -> Option<u64> {
+        let fee = self.0.checked_mul(weight.to_wu())?;
+        Some(fee / 1000 + if fee % 1000 == 0 { 0 } else { 1 })
+    }
+
+    /// Calculate the fee in satoshis for the given number of virtual bytes.
+    ///
+    /// Returns [None] if the computation overflows.
+    pub fn fee_vb(self, vb: u64) -> Option<u64> {
+        self.fee_wu(Weight::from_vb(vb)?)
+    }
+
+    /// Checked addition. Returns [None] if overflow occurred.
+    pub fn checked_add(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_add(rhs.0).map(FeeRate)
+    }
+
+    /// Checked subtraction. Returns [None] if overflow occurred.
+    pub fn checked_sub(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_sub(rhs.0).map(FeeRate)
+    }
+
+    /// Checked multiplication. Returns [None] if overflow occurred.
+    pub fn checked_mul(self, rhs: u64) -> Option<FeeRate> {
+        self.0.checked_mul(rhs).map(FeeRate)
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} sat/kwu", self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Weight {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Weight {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<Weight, D::Error> {
+        Ok(Weight(::serde::Deserialize::deserialize(d)?))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for FeeRate {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for FeeRate {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<FeeRate, D::Error> {
+        Ok(FeeRate(::serde::Deserialize::deserialize(d)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weight_conversions() {
+        assert_eq!(Weight::from_vb(10), Some(Weight::from_wu(40)));
+        assert_eq!(Weight::from_vb(u64::max_value()), None);
+        assert_eq!(Weight::from_wu(41).to_vbytes_ceil(), 11);
+        assert_eq!(Weight::from_wu(41).to_vbytes_floor(), 10);
+        assert_eq!(Weight::from_wu(40).to_vbytes_ceil(), 10);
+        assert_eq!(Weight::from_wu(3) + Weight::from_wu(4), Weight::from_wu(7));
+        assert_eq!(Weight::from_wu(u64::max_value()).checked_add(Weight::from_wu(1)), None);
+        assert_eq!(Weight::ZERO.checked_sub(Weight::from_wu(1)), None);
+        assert_eq!(Weight::from_wu(400).to_string(), "400 wu");
+    }
+
+    #[test]
+    fn fee_rate_conversions() {
+        let rate = FeeRate::from_sat_per_vb(1).unwrap();
+        assert_eq!(rate, FeeRate::from_sat_per_kwu(250));
+        assert_eq!(rate.as_sat_per_vb_floor(), 1);
+        assert_eq!(FeeRate::from_sat_per_kwu(251).as_sat_per_vb_floor(), 1);
+        assert_eq!(FeeRate::from_sat_per_kwu(251).as_sat_per_vb_ceil(), 2);
+        assert_eq!(FeeRate::from_sat_per_vb(u64::max_value()), None);
+        assert!(FeeRate::from_sat_per_kwu(1) < FeeRate::from_sat_per_kwu(2));
+        assert_eq!(rate.to_string(), "250 sat/kwu");
+    }
+
+    #[test]
+    fn fee_calculation() {
+        let rate = FeeRate::from_sat_per_vb(10).unwrap();
+        assert_eq!(rate.fee_vb(100), Some(1000));
+        assert_eq!(rate.fee_wu(Weight::from_wu(400)), Some(1000));
+        // rounds up to never underpay
+        assert_eq!(FeeRate::from_sat_per_kwu(1).fee_wu(Weight::from_wu(1)), Some(1));
+        assert_eq!(FeeRate::from_sat_per_kwu(1000).fee_wu(Weight::from_wu(3)), Some(3));
+        assert_eq!(FeeRate::ZERO.fee_wu(Weight::from_wu(u64::max_value())), Some(0));
+    }
+
+    #[test]
+    fn fee_calculation_overflow() {
+        let max_weight = Weight::from_wu(u64::max_value());
+        assert_eq!(FeeRate::from_sat_per_kwu(2).fee_wu(max_weight), None);
+        assert_eq!(FeeRate::from_sat_per_kwu(u64::max_value()).fee_wu(Weight::from_wu(2)), None);
+        assert_eq!(FeeRate::from_sat_per_vb(1).unwrap().fee_vb(u64::max_value()), None);
+        assert_eq!(FeeRate::from_sat_per_kwu(u64::max_value()).checked_mul(2), None);
+    }
+}
@@ -14,64 +14,384 @@
 //! Hash functions
 //!
 //! Utility functions related to hashing data, including merkleization
+//! and BIP340-style tagged hashes
+
 
 use std::cmp::min;
 use std::io;
 
-use hashes::Hash;
+use hashes::{Hash, HashEngine, sha256};
 use consensus::encode::Encodable;
 
 /// Calculates the merkle root of a list of hashes inline
 /// into the allocated slice.
 ///
+/// Returns `None` for an empty slice and the only hash of a single element
+/// slice unchanged. As in Bitcoin Core, the last hash of each level with an
+/// odd number of hashes is paired with itself, so a list whose last hashes
+/// are duplicated has the same root as the list without them
+/// (CVE-2012-2459). Callers which need to detect this can use
+/// [merkle_tree_is_mutated].
+///
 /// In most cases, you'll want to use [bitcoin_merkle_root] instead.
-pub fn bitcoin_merkle_root_inline<T>(data: &mut [T]) -> T
+pub fn bitcoin_merkle_root_inline<T>(data: &mut [T]) -> Option<T>
     where T: Hash + Encodable,
           <T as Hash>::Engine: io::Write,
 {
-    // Base case
     if data.is_empty() {
-        return Default::default();
-    }
-    if data.len() < 2 {
-        return T::from_inner(data[0].into_inner());
-    }
-    // Recursion
-    for idx in 0..((data.len() + 1) / 2) {
-        let idx1 = 2 * idx;
-        let idx2 = min(idx1 + 1, data.len() - 1);
-        let mut encoder = T::engine();
-        data[idx1].consensus_encode(&mut encoder).unwrap();
-        data[idx2].consensus_encode(&mut encoder).unwrap();
-        data[idx] = T::from_engine(encoder);
+        None
+    } else {
+        Some(merkle_root_r(data))
     }
-    let half_len = data.len() / 2 + data.len() % 2;
-    bitcoin_merkle_root_inline(&mut data[0..half_len])
 }
 
-/// Calculates the merkle root of an iterator of hashes.
-pub fn bitcoin_merkle_root<T, I>(mut iter: I) -> T
+/// Calculates the merkle root of an iterator of hashes, see
+/// [bitcoin_merkle_root_inline] for the treatment of empty lists and odd
+/// levels.
+///
+/// The hashes are combined pairwise while they are read, so only half of
+/// them are ever held in memory.
+pub fn bitcoin_merkle_root<T, I>(mut iter: I) -> Option<T>
     where T: Hash + Encodable,
           <T as Hash>::Engine: io::Write,
-          I: ExactSizeIterator<Item = T>,
+          I: Iterator<Item = T>,
 {
     // Base case
-    if iter.len() == 0 {
-        return Default::default();
-    }
-    if iter.len() == 1 {
-        return T::from_inner(iter.nth(0).unwrap().into_inner());
-    }
+    let first = iter.next()?;
+    let second = match iter.next() {
+        Some(second) => second,
+        None => return Some(first),
+    };
     // Recursion
-    let half_len = iter.len() / 2 + iter.len() % 2;
-    let mut alloc = Vec::with_capacity(half_len);
+    let (min_len, max_len) = iter.size_hint();
+    let mut alloc = Vec::with_capacity(max_len.unwrap_or(min_len) / 2 + 1);
+    alloc.push(merkle_node(&first, &second));
     while let Some(hash1) = iter.next() {
         // If the size is odd, use the last element twice.
         let hash2 = iter.next().unwrap_or(hash1);
-        let mut encoder = T::engine();
-        hash1.consensus_encode(&mut encoder).unwrap();
-        hash2.consensus_encode(&mut encoder).unwrap();
-        alloc.push(T::from_engine(encoder));
+        alloc.push(merkle_node(&hash1, &hash2));
+    }
+    Some(merkle_root_r(&mut alloc))
+}
+
+/// Whether any level of the merkle tree of `hashes` has a pair of identical
+/// siblings, as in a list with duplicated last hashes that has the same root
+/// as the list without them (CVE-2012-2459).
+pub fn merkle_tree_is_mutated<T>(mut hashes: Vec<T>) -> bool
+    where T: Hash + Encodable,
+          <T as Hash>::Engine: io::Write,
+{
+    while hashes.len() > 1 {
+        if hashes.chunks(2).any(|pair| pair.len() == 2 && pair[0] == pair[1]) {
+            return true;
+        }
+        hashes = hashes.chunks(2).map(|pair| merkle_node(&pair[0], pair.last().unwrap())).collect();
+    }
+    false
+}
+
+/// Calculates the merkle root of a slice of leaf hashes, see
+/// [bitcoin_merkle_root_inline] for the treatment of empty lists and odd
+/// levels.
+///
+/// Unlike [bitcoin_merkle_root_inline] the leaves are left untouched, so
+/// callers holding them in a slice don't need to copy them first.
+pub fn bitcoin_merkle_root_from_slice<T>(leaves: &[T]) -> Option<T>
+    where T: Hash + Encodable,
+          <T as Hash>::Engine: io::Write,
+{
+    bitcoin_merkle_root(leaves.iter().cloned())
+}
+
+/// Hashes two merkle tree nodes into their parent.
+fn merkle_node<T>(left: &T, right: &T) -> T
+    where T: Hash + Encodable,
+          <T as Hash>::Engine: io::Write,
+{
+    let mut encoder = T::engine();
+    left.consensus_encode(&mut encoder).unwrap();
+    right.consensus_encode(&mut encoder).unwrap();
+    T::from_engine(encoder)
+}
+
+/// Recursively reduces a non-empty level of the merkle tree in place.
+fn merkle_root_r<T>(data: &mut [T]) -> T
+    where T: Hash + Encodable,
+          <T as Hash>::Engine: io::Write,
+{
+    if data.len() == 1 {
+        return data[0];
+    }
+    for idx in 0..((data.len() + 1) / 2) {
+        let idx1 = 2 * idx;
+        let idx2 = min(idx1 + 1, data.len() - 1);
+        data[idx] = merkle_node(&data[idx1], &data[idx2]);
+    }
+    let half_len = data.len() / 2 + data.len() % 2;
+    merkle_root_r(&mut data[0..half_len])
+}
+
+/// Computes the BIP340-style tagged hash `sha256(sha256(tag) || sha256(tag) || msg)`.
+///
+/// To hash many messages with the same tag use a [TaggedHashEngine], and for
+/// the tags listed in [HashTag] prefer [HashTag::hash], which starts from a
+/// precomputed midstate instead of hashing the tag every time.
+pub fn tagged_hash(tag: &str, msg: &[u8]) -> sha256::Hash {
+    TaggedHashEngine::new(tag).hash(msg)
+}
+
+/// Creates tagged hash engines for one tag, committing to the tag only once.
+///
+/// The tag is hashed when the `TaggedHashEngine` is created and every engine
+/// it hands out starts from the resulting midstate.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct TaggedHashEngine {
+    midstate: sha256::Midstate,
+}
+
+impl TaggedHashEngine {
+    /// Commits to `tag`.
+    pub fn new(tag: &str) -> TaggedHashEngine {
+        let tag_hash = sha256::Hash::hash(tag.as_bytes());
+        let mut engine = sha256::Hash::engine();
+        engine.input(&tag_hash[..]);
+        engine.input(&tag_hash[..]);
+        TaggedHashEngine {
+            midstate: engine.midstate(),
+        }
+    }
+
+    /// Returns the SHA256 midstate after committing to the tag.
+    pub fn midstate(&self) -> sha256::Midstate {
+        self.midstate
+    }
+
+    /// Creates a SHA256 engine which has already committed to the tag.
+    pub fn engine(&self) -> sha256::HashEngine {
+        // the two tag hashes fill exactly one 64-byte block
+        sha256::HashEngine::from_midstate(self.midstate, 64)
+    }
+
+    /// Computes the tagged hash of `msg`.
+    pub fn hash(&self, msg: &[u8]) -> sha256::Hash {
+        let mut engine = self.engine();
+        engine.input(msg);
+        sha256::Hash::from_engine(engine)
+    }
+}
+
+impl From<HashTag> for TaggedHashEngine {
+    fn from(tag: HashTag) -> TaggedHashEngine {
+        TaggedHashEngine {
+            midstate: tag.midstate(),
+        }
+    }
+}
+
+/// SHA256 midstate of the `TapLeaf` tag, see [TaggedHashEngine].
+pub const MIDSTATE_TAPLEAF: [u8; 32] = [
+    156, 224, 228, 230, 124, 17, 108, 57, 56, 179, 202, 242, 195, 15, 80, 137,
+    211, 243, 147, 108, 71, 99, 110, 96, 125, 179, 62, 234, 221, 198, 240, 201,
+];
+
+/// SHA256 midstate of the `TapBranch` tag, see [TaggedHashEngine].
+pub const MIDSTATE_TAPBRANCH: [u8; 32] = [
+    35, 168, 101, 169, 184, 164, 13, 167, 151, 124, 30, 4, 196, 158, 36, 111,
+    181, 190, 19, 118, 157, 36, 201, 183, 181, 131, 181, 212, 168, 210, 38, 210,
+];
+
+/// SHA256 midstate of the `TapTweak` tag, see [TaggedHashEngine].
+pub const MIDSTATE_TAPTWEAK: [u8; 32] = [
+    209, 41, 162, 243, 112, 28, 101, 93, 101, 131, 182, 195, 185, 65, 151, 39,
+    149, 244, 226, 50, 148, 253, 84, 244, 162, 174, 141, 133, 71, 202, 89, 11,
+];
+
+/// SHA256 midstate of the `TapSighash` tag, see [TaggedHashEngine].
+pub const MIDSTATE_TAPSIGHASH: [u8; 32] = [
+    245, 4, 164, 37, 215, 248, 120, 59, 19, 99, 134, 138, 227, 229, 86, 88,
+    110, 238, 148, 93, 188, 120, 136, 221, 2, 166, 226, 195, 24, 115, 254, 159,
+];
+
+/// SHA256 midstate of the `BIP0322-signed-message` tag, see [TaggedHashEngine].
+pub const MIDSTATE_BIP322_SIGNED_MESSAGE: [u8; 32] = [
+    137, 110, 101, 166, 158, 24, 33, 51, 154, 160, 217, 89, 167, 185, 222, 252,
+    115, 60, 186, 140, 151, 47, 2, 20, 94, 72, 184, 111, 248, 59, 249, 156,
+];
+
+/// Well-known hash tags whose SHA256 midstates are precomputed.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum HashTag {
+    /// `TapLeaf`, used for taproot script leaves (BIP341)
+    TapLeaf,
+    /// `TapBranch`, used for taproot merkle branches (BIP341)
+    TapBranch,
+    /// `TapTweak`, used for tweaking taproot internal keys (BIP341)
+    TapTweak,
+    /// `TapSighash`, used for taproot signature hashes (BIP341)
+    TapSighash,
+    /// `BIP0322-signed-message`, used for generic signed messages (BIP322)
+    Bip322SignedMessage,
+}
+
+impl HashTag {
+    /// Returns the tag string which is hashed into the midstate.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashTag::TapLeaf => "TapLeaf",
+            HashTag::TapBranch => "TapBranch",
+            HashTag::TapTweak => "TapTweak",
+            HashTag::TapSighash => "TapSighash",
+            HashTag::Bip322SignedMessage => "BIP0322-signed-message",
+        }
+    }
+
+    /// Returns the precomputed SHA256 midstate after committing to the tag.
+    pub fn midstate(self) -> sha256::Midstate {
+        sha256::Midstate::from_inner(match self {
+            HashTag::TapLeaf => MIDSTATE_TAPLEAF,
+            HashTag::TapBranch => MIDSTATE_TAPBRANCH,
+            HashTag::TapTweak => MIDSTATE_TAPTWEAK,
+            HashTag::TapSighash => MIDSTATE_TAPSIGHASH,
+            HashTag::Bip322SignedMessage => MIDSTATE_BIP322_SIGNED_MESSAGE,
+        })
+    }
+
+    /// Creates a SHA256 engine which has already committed to the tag.
+    /// Equivalent to, but cheaper than, `TaggedHashEngine::new(self.as_str()).engine()`.
+    pub fn engine(self) -> sha256::HashEngine {
+        TaggedHashEngine::from(self).engine()
+    }
+
+    /// Computes the tagged hash of `data`.
+    pub fn hash(self, data: &[u8]) -> sha256::Hash {
+        TaggedHashEngine::from(self).hash(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::{Hash, HashEngine, sha256, sha256d};
+    use hashes::hex::ToHex;
+
+    use super::*;
+
+    const ALL_TAGS: [HashTag; 5] = [
+        HashTag::TapLeaf,
+        HashTag::TapBranch,
+        HashTag::TapTweak,
+        HashTag::TapSighash,
+        HashTag::Bip322SignedMessage,
+    ];
+
+    #[test]
+    fn merkle_root_edge_cases() {
+        let hashes: Vec<sha256d::Hash> = (0..9u8).map(|i| sha256d::Hash::hash(&[i])).collect();
+
+        assert_eq!(bitcoin_merkle_root(Vec::<sha256d::Hash>::new().into_iter()), None);
+        assert_eq!(bitcoin_merkle_root_inline::<sha256d::Hash>(&mut []), None);
+        assert_eq!(bitcoin_merkle_root_from_slice::<sha256d::Hash>(&[]), None);
+        assert_eq!(bitcoin_merkle_root(hashes[..1].iter().cloned()), Some(hashes[0]));
+        assert_eq!(bitcoin_merkle_root_inline(&mut hashes[..1].to_vec()), Some(hashes[0]));
+
+        let pair = {
+            let mut engine = sha256d::Hash::engine();
+            engine.input(&hashes[0][..]);
+            engine.input(&hashes[1][..]);
+            sha256d::Hash::from_engine(engine)
+        };
+        assert_eq!(bitcoin_merkle_root(hashes[..2].iter().cloned()), Some(pair));
+
+        for len in 1..hashes.len() + 1 {
+            assert_eq!(bitcoin_merkle_root_from_slice(&hashes[..len]), bitcoin_merkle_root_inline(&mut hashes[..len].to_vec()));
+            // an iterator without a useful size hint
+            let filtered = hashes[..len].iter().cloned().filter(|_| true);
+            assert_eq!(bitcoin_merkle_root(filtered), bitcoin_merkle_root_inline(&mut hashes[..len].to_vec()));
+        }
+    }
+
+    #[test]
+    fn merkle_root_duplicate_mutation() {
+        // CVE-2012-2459: repeating the last hashes of an odd level gives the same root
+        let hashes: Vec<sha256d::Hash> = (0..6u8).map(|i| sha256d::Hash::hash(&[i])).collect();
+        let mut mutated = hashes.clone();
+        mutated.push(hashes[4]);
+        mutated.push(hashes[5]);
+        assert_eq!(bitcoin_merkle_root(hashes.iter().cloned()), bitcoin_merkle_root(mutated.into_iter()));
+
+        let mut mutated = hashes[..5].to_vec();
+        mutated.push(hashes[4]);
+        assert_eq!(bitcoin_merkle_root(hashes[..5].iter().cloned()), bitcoin_merkle_root(mutated.into_iter()));
+    }
+
+    #[test]
+    fn tagged_hash_matches_definition() {
+        let tag_hash = sha256::Hash::hash(b"BIP0340/challenge");
+        let mut expected = sha256::Hash::engine();
+        expected.input(&tag_hash[..]);
+        expected.input(&tag_hash[..]);
+        expected.input(b"abc");
+        let expected = sha256::Hash::from_engine(expected);
+
+        assert_eq!(tagged_hash("BIP0340/challenge", b"abc"), expected);
+        let mut engine = TaggedHashEngine::new("BIP0340/challenge").engine();
+        engine.input(b"abc");
+        assert_eq!(sha256::Hash::from_engine(engine), expected);
+    }
+
+    #[test]
+    fn midstate_reuse() {
+        let tagger = TaggedHashEngine::new("BIP0340/nonce");
+        for len in 0..200 {
+            let msg: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            assert_eq!(tagger.hash(&msg), tagged_hash("BIP0340/nonce", &msg), "length {}", len);
+        }
+        // an engine which was already used doesn't change the tagger's midstate
+        let mut used = tagger.engine();
+        used.input(&[0xab; 100]);
+        assert_eq!(tagger.engine().midstate(), tagger.midstate());
+    }
+
+    #[test]
+    fn precomputed_midstates() {
+        for tag in ALL_TAGS.iter() {
+            let tagger = TaggedHashEngine::new(tag.as_str());
+            assert_eq!(tagger.midstate(), tag.midstate(), "{:?}", tag);
+            assert_eq!(TaggedHashEngine::from(*tag), tagger, "{:?}", tag);
+            assert_eq!(tag.hash(b"some message"), tagged_hash(tag.as_str(), b"some message"), "{:?}", tag);
+        }
+    }
+
+    #[test]
+    fn tagged_hash_vectors() {
+        // BIP322 reference message hashes
+        assert_eq!(
+            HashTag::Bip322SignedMessage.hash(b"").to_hex(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            HashTag::Bip322SignedMessage.hash(b"Hello World").to_hex(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+        assert_eq!(
+            HashTag::TapLeaf.hash(b"abc").to_hex(),
+            "83a56308a9c56f467e8df293da5ae5fdbc85b871952a83c4bf0575ee948ec230"
+        );
+        assert_eq!(
+            HashTag::TapTweak.hash(b"").to_hex(),
+            "8aa4229474ab0100b2d6f0687f031d1fc9d8eef92a042ad97d279bff456b15e4"
+        );
+        // BIP340 tags
+        assert_eq!(
+            tagged_hash("BIP0340/challenge", b"").to_hex(),
+            "c216d352f5818b7b4beacd4ae0a26fe888080823d2a598856661bcd54f1b3713"
+        );
+        assert_eq!(
+            tagged_hash("BIP0340/aux", &[0; 32]).to_hex(),
+            "54f169cfc9e2e5727480441f90ba25c488f461c70b5ea5dcaaf7af69270aa514"
+        );
+        assert_eq!(
+            tagged_hash("BIP0340/nonce", b"abc").to_hex(),
+            "000beb2c0c2b6cebe3a7e17e283816c2cd4a02e148ea65e9f218d8007d35de4b"
+        );
     }
-    bitcoin_merkle_root_inline(&mut alloc)
 }
@@ -16,21 +16,34 @@
 //! Keys used in Monacoin that can be roundtrip (de)serialized.
 //!
 
+
 use std::fmt::{self, Write};
-use std::{io, ops, error};
+use std::{cmp, ops};
+use std::collections::{btree_map, BTreeMap};
+use std::io;
+use std::error;
 use std::str::FromStr;
 
 use secp256k1::{self, Secp256k1};
 use network::constants::Network;
+use hashes::{Hash, hash160, hex, sha256, sha256d};
+use hashes::hex::FromHex;
+use hash_types::{PubkeyHash, WPubkeyHash};
+use util::address::AddressType;
 use util::base58;
 
 /// A key-related error.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     /// Base58 encoding error
     Base58(base58::Error),
-    /// secp256k1-related error
+    /// secp256k1-related error, such as a public key which is not a valid
+    /// curve point
     Secp256k1(secp256k1::Error),
+    /// A serialized public key was neither 33 nor 65 bytes long
+    InvalidPublicKeyLength(usize),
+    /// Hex decoding error
+    Hex(hex::Error),
 }
 
 
@@ -39,15 +52,19 @@ impl fmt::Display for Error {
         match *self {
             Error::Base58(ref e) => write!(f, "base58 error: {}", e),
             Error::Secp256k1(ref e) => write!(f, "secp256k1 error: {}", e),
+            Error::InvalidPublicKeyLength(len) => write!(f, "invalid public key length {}", len),
+            Error::Hex(ref e) => write!(f, "hex error: {}", e),
         }
     }
 }
 
 impl error::Error for Error {
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
         match *self {
             Error::Base58(ref e) => Some(e),
             Error::Secp256k1(ref e) => Some(e),
+            Error::InvalidPublicKeyLength(_) => None,
+            Error::Hex(ref e) => Some(e),
         }
     }
 
@@ -70,8 +87,18 @@ impl From<secp256k1::Error> for Error {
     }
 }
 
+#[doc(hidden)]
+impl From<hex::Error> for Error {
+    fn from(e: hex::Error) -> Error {
+        Error::Hex(e)
+    }
+}
+
 /// A Monacoin ECDSA public key
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+///
+/// Keys are ordered by their serialized form, which is the order BIP67
+/// requires when sorting the keys of a multisig script.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct PublicKey {
     /// Whether this public key should be serialized as compressed
     pub compressed: bool,
@@ -90,6 +117,15 @@ impl PublicKey {
         debug_assert!(write_res.is_ok());
     }
 
+    /// Calls `f` with the serialized public key, without allocating
+    fn with_serialized<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> R {
+        if self.compressed {
+            f(&self.key.serialize())
+        } else {
+            f(&self.key.serialize_uncompressed())
+        }
+    }
+
     /// Serialize the public key to bytes
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut buf = Vec::new();
@@ -97,12 +133,30 @@ impl PublicKey {
         buf
     }
 
-    /// Deserialize a public key from a slice
+    /// Returns the hash of the public key, as used in P2PKH outputs
+    pub fn pubkey_hash(&self) -> PubkeyHash {
+        let mut engine = PubkeyHash::engine();
+        self.write_into(&mut engine);
+        PubkeyHash::from_engine(engine)
+    }
+
+    /// Returns the hash of the public key as used in P2WPKH outputs, or
+    /// [None] if the key is uncompressed, since segwit requires compressed keys
+    pub fn wpubkey_hash(&self) -> Option<WPubkeyHash> {
+        if self.compressed {
+            Some(WPubkeyHash::from_inner(hash160::Hash::hash(&self.key.serialize()).into_inner()))
+        } else {
+            None
+        }
+    }
+
+    /// Deserialize a public key from a slice, which must hold either a
+    /// 33-byte compressed or a 65-byte uncompressed key
     pub fn from_slice(data: &[u8]) -> Result<PublicKey, Error> {
         let compressed: bool = match data.len() {
             33 => true,
             65 => false,
-            len =>  { return Err(base58::Error::InvalidLength(len).into()); },
+            len =>  { return Err(Error::InvalidPublicKeyLength(len)); },
         };
 
         Ok(PublicKey {
@@ -117,29 +171,33 @@ impl PublicKey {
     }
 }
 
+impl PartialOrd for PublicKey {
+    fn partial_cmp(&self, other: &PublicKey) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PublicKey {
+    fn cmp(&self, other: &PublicKey) -> cmp::Ordering {
+        self.with_serialized(|a| other.with_serialized(|b| a.cmp(b)))
+    }
+}
+
 impl fmt::Display for PublicKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.compressed {
-            for ch in &self.key.serialize()[..] {
-                write!(f, "{:02x}", ch)?;
-            }
-        } else {
-            for ch in &self.key.serialize_uncompressed()[..] {
+        self.with_serialized(|data| {
+            for ch in data {
                 write!(f, "{:02x}", ch)?;
             }
-        }
-        Ok(())
+            Ok(())
+        })
     }
 }
 
 impl FromStr for PublicKey {
     type Err = Error;
     fn from_str(s: &str) -> Result<PublicKey, Error> {
-        let key = secp256k1::PublicKey::from_str(s)?;
-        Ok(PublicKey {
-            key: key,
-            compressed: s.len() == 66
-        })
+        PublicKey::from_slice(&Vec::<u8>::from_hex(s)?)
     }
 }
 
@@ -243,6 +301,135 @@ impl ops::Index<ops::RangeFull> for PrivateKey {
     }
 }
 
+/// A problem found by [audit_keys] with the key at `index`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyFinding {
+    /// The key is the same secret as an earlier one
+    Duplicate {
+        /// Position of the key
+        index: usize,
+        /// Position of its first occurrence
+        first: usize,
+    },
+    /// The secret is trivially guessable: a small integer, the negation of
+    /// one, or a hash of the empty string
+    Weak {
+        /// Position of the key
+        index: usize,
+    },
+    /// The key is not a valid secp256k1 secret, only reported by [audit_wif_keys]
+    OutOfRange {
+        /// Position of the key
+        index: usize,
+    },
+    /// The WIF string could not be parsed, only reported by [audit_wif_keys]
+    Invalid {
+        /// Position of the key
+        index: usize,
+        /// Why parsing failed
+        error: Error,
+    },
+    /// The key is uncompressed but meant for segwit outputs, which can't be spent with it
+    UncompressedSegwit {
+        /// Position of the key
+        index: usize,
+    },
+}
+
+impl fmt::Display for KeyFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyFinding::Duplicate { index, first } => write!(f, "key {} duplicates key {}", index, first),
+            KeyFinding::Weak { index } => write!(f, "key {} is trivially guessable", index),
+            KeyFinding::OutOfRange { index } => write!(f, "key {} is not a valid secret key", index),
+            KeyFinding::Invalid { index, ref error } => write!(f, "key {} is invalid: {}", index, error),
+            KeyFinding::UncompressedSegwit { index } => {
+                write!(f, "key {} is uncompressed and can't be used for segwit", index)
+            }
+        }
+    }
+}
+
+/// Whether a 32-byte secret is one an attacker would try first
+fn is_weak_secret(secret: &[u8]) -> bool {
+    // the curve order, whose leading 24 bytes are shared by every secret within 2^64 below it
+    const ORDER_HIGH: [u8; 24] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+        0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b,
+    ];
+    if secret[..24].iter().all(|&b| b == 0) || secret[..24] == ORDER_HIGH[..] {
+        return true;
+    }
+    secret == &sha256::Hash::hash(&[])[..] || secret == &sha256d::Hash::hash(&[])[..]
+}
+
+/// Screen private keys, e.g. from an imported backup, for mistakes which would
+/// make them unsafe or unusable: the same secret occurring more than once,
+/// trivially guessable secrets, and uncompressed keys when `intended` is a
+/// segwit address type.
+///
+/// Findings identify keys by their position only, so they can be logged
+/// without leaking key material. Extended keys can be screened through their
+/// `private_key`.
+pub fn audit_keys<I>(keys: I, intended: Option<AddressType>) -> Vec<KeyFinding>
+    where I: IntoIterator<Item = PrivateKey>
+{
+    let mut findings = vec![];
+    let mut seen = BTreeMap::new();
+    for (index, key) in keys.into_iter().enumerate() {
+        audit_key(index, &key, intended, &mut seen, &mut findings);
+    }
+    findings
+}
+
+/// Like [audit_keys], but for WIF strings. Strings which are not valid keys
+/// are reported rather than stopping the audit.
+pub fn audit_wif_keys<'a, I>(wifs: I, intended: Option<AddressType>) -> Vec<KeyFinding>
+    where I: IntoIterator<Item = &'a str>
+{
+    let mut findings = vec![];
+    let mut seen = BTreeMap::new();
+    for (index, wif) in wifs.into_iter().enumerate() {
+        match PrivateKey::from_wif(wif) {
+            Ok(key) => audit_key(index, &key, intended, &mut seen, &mut findings),
+            Err(Error::Secp256k1(secp256k1::Error::InvalidSecretKey)) => {
+                findings.push(KeyFinding::OutOfRange { index: index });
+            }
+            Err(e) => findings.push(KeyFinding::Invalid { index: index, error: e }),
+        }
+    }
+    findings
+}
+
+fn audit_key(
+    index: usize,
+    key: &PrivateKey,
+    intended: Option<AddressType>,
+    seen: &mut BTreeMap<[u8; 32], usize>,
+    findings: &mut Vec<KeyFinding>,
+) {
+    let mut secret = [0; 32];
+    secret.copy_from_slice(&key.key[..]);
+    match seen.entry(secret) {
+        btree_map::Entry::Occupied(first) => {
+            findings.push(KeyFinding::Duplicate { index: index, first: *first.get() });
+        }
+        btree_map::Entry::Vacant(entry) => {
+            entry.insert(index);
+        }
+    }
+    if is_weak_secret(&secret) {
+        findings.push(KeyFinding::Weak { index: index });
+    }
+    match intended {
+        Some(AddressType::P2wpkh) | Some(AddressType::P2wsh) if !key.compressed => {
+            findings.push(KeyFinding::UncompressedSegwit { index: index });
+        }
+        _ => {}
+    }
+}
+
 #[cfg(feature = "serde")]
 impl ::serde::Serialize for PrivateKey {
     fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
@@ -400,6 +587,146 @@ mod tests {
         assert_eq!(pk, PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap());
     }
 
+    #[test]
+    fn test_pubkey_hash() {
+        use blockdata::script::Script;
+
+        let mut pk = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+        assert_eq!(Script::new_p2pkh(&pk.pubkey_hash()), Address::p2pkh(&pk, Monacoin).script_pubkey());
+        assert_eq!(Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap()), Address::p2wpkh(&pk, Monacoin).unwrap().script_pubkey());
+        assert_eq!(&pk.pubkey_hash()[..], &pk.wpubkey_hash().unwrap()[..]);
+
+        pk.compressed = false;
+        assert_eq!(Script::new_p2pkh(&pk.pubkey_hash()), Address::p2pkh(&pk, Monacoin).script_pubkey());
+        assert_eq!(pk.wpubkey_hash(), None);
+    }
+
+    #[test]
+    fn test_pubkey_parse_errors() {
+        use super::Error;
+        use hashes::hex;
+
+        assert_eq!(PublicKey::from_slice(&[2; 32]), Err(Error::InvalidPublicKeyLength(32)));
+        assert_eq!(PublicKey::from_slice(&[4; 64]), Err(Error::InvalidPublicKeyLength(64)));
+        assert_eq!(PublicKey::from_slice(&[5; 33]), Err(Error::Secp256k1(secp256k1::Error::InvalidPublicKey)));
+        assert_eq!(
+            PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1"),
+            Err(Error::InvalidPublicKeyLength(32))
+        );
+        assert_eq!(
+            PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1a"),
+            Err(Error::Hex(hex::Error::OddLengthString(65)))
+        );
+    }
+
+    #[test]
+    fn test_pubkey_ord() {
+        // BIP67 test vector 1
+        let mut keys = vec![
+            PublicKey::from_str("02ff12471208c14bd580709cb2358d98975247d8765f92bc25eab3b2763ed605f8").unwrap(),
+            PublicKey::from_str("02fe6f0a5a297eb38c391581c4413e084773ea23954d93f7753db7dc0adc188b2f").unwrap(),
+        ];
+        keys.sort();
+        assert_eq!(keys[0].to_string(), "02fe6f0a5a297eb38c391581c4413e084773ea23954d93f7753db7dc0adc188b2f");
+        assert_eq!(keys[1].to_string(), "02ff12471208c14bd580709cb2358d98975247d8765f92bc25eab3b2763ed605f8");
+
+        // BIP67 test vector 3
+        let mut keys = vec![
+            PublicKey::from_str("030000000000000000000000000000000000004141414141414141414141414141").unwrap(),
+            PublicKey::from_str("020000000000000000000000000000000000004141414141414141414141414141").unwrap(),
+            PublicKey::from_str("020000000000000000000000000000000000004141414141414141414141414140").unwrap(),
+            PublicKey::from_str("030000000000000000000000000000000000004141414141414141414141414140").unwrap(),
+        ];
+        keys.sort();
+        let sorted: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+        assert_eq!(sorted, vec![
+            "020000000000000000000000000000000000004141414141414141414141414140",
+            "020000000000000000000000000000000000004141414141414141414141414141",
+            "030000000000000000000000000000000000004141414141414141414141414140",
+            "030000000000000000000000000000000000004141414141414141414141414141",
+        ]);
+
+        // uncompressed keys sort by their 65-byte form
+        let compressed = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+        let uncompressed = PublicKey { compressed: false, ..compressed };
+        assert!(compressed < uncompressed);
+    }
+
+    #[test]
+    fn test_audit_keys() {
+        use hashes::{sha256, Hash};
+        use hashes::hex::FromHex;
+        use secp256k1::SecretKey;
+        use util::address::AddressType;
+        use super::{audit_keys, KeyFinding};
+
+        let key = |secret: &[u8], compressed: bool| PrivateKey {
+            compressed: compressed,
+            network: MonacoinTestnet,
+            key: SecretKey::from_slice(secret).unwrap(),
+        };
+        let mut one = [0; 32];
+        one[31] = 1;
+        let order_minus_one = Vec::<u8>::from_hex("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364140").unwrap();
+        let keys = vec![
+            key(&[0x55; 32], true),
+            key(&one, true),
+            key(&[0x55; 32], false),
+            key(&[0x66; 32], false),
+            key(&sha256::Hash::hash(&[])[..], true),
+            key(&order_minus_one, true),
+        ];
+
+        let weak_and_duplicate = vec![
+            KeyFinding::Weak { index: 1 },
+            KeyFinding::Duplicate { index: 2, first: 0 },
+            KeyFinding::Weak { index: 4 },
+            KeyFinding::Weak { index: 5 },
+        ];
+        assert_eq!(audit_keys(keys.clone(), None), weak_and_duplicate);
+        assert_eq!(audit_keys(keys.clone(), Some(AddressType::P2pkh)), weak_and_duplicate);
+        assert_eq!(audit_keys(keys.clone(), Some(AddressType::P2wpkh)), vec![
+            KeyFinding::Weak { index: 1 },
+            KeyFinding::Duplicate { index: 2, first: 0 },
+            KeyFinding::UncompressedSegwit { index: 2 },
+            KeyFinding::UncompressedSegwit { index: 3 },
+            KeyFinding::Weak { index: 4 },
+            KeyFinding::Weak { index: 5 },
+        ]);
+        assert_eq!(audit_keys(vec![key(&[0x55; 32], true)], None), vec![]);
+    }
+
+    #[test]
+    fn test_audit_wif_keys() {
+        use hashes::hex::FromHex;
+        use util::base58;
+        use super::{audit_wif_keys, Error, KeyFinding};
+
+        let wif = |secret: &[u8]| {
+            let mut data = vec![239];
+            data.extend_from_slice(secret);
+            data.push(1);
+            base58::check_encode_slice(&data)
+        };
+        let order = Vec::<u8>::from_hex("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141").unwrap();
+        let wifs = vec![
+            "cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy".to_owned(),
+            wif(&[0; 32]),
+            wif(&order),
+            "cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpz".to_owned(),
+            "cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy".to_owned(),
+        ];
+        let findings = audit_wif_keys(wifs.iter().map(|s| &s[..]), None);
+        assert_eq!(findings.len(), 4);
+        assert_eq!(findings[0], KeyFinding::OutOfRange { index: 1 });
+        assert_eq!(findings[1], KeyFinding::OutOfRange { index: 2 });
+        match findings[2] {
+            KeyFinding::Invalid { index: 3, error: Error::Base58(_) } => {}
+            ref f => panic!("unexpected finding {:?}", f),
+        }
+        assert_eq!(findings[3], KeyFinding::Duplicate { index: 4, first: 0 });
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn test_key_serde() {
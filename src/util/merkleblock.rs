@@ -53,7 +53,7 @@
 //! ```
 
 use std::collections::HashSet;
-use std::io;
+use std::{error, fmt, io};
 
 use hashes::Hash;
 use hash_types::{Txid, TxMerkleNode};
@@ -77,6 +77,24 @@ pub enum MerkleBlockError {
     BadFormat(String),
 }
 
+impl fmt::Display for MerkleBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MerkleRootMismatch => f.write_str("merkle header root doesn't match to the root calculated from the partial merkle tree"),
+            NoTransactions => f.write_str("partial merkle tree contains no transactions"),
+            TooManyTransactions => f.write_str("too many transactions"),
+            BadFormat(ref s) => write!(f, "general format error: {}", s),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for MerkleBlockError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
 /// Data structure that represents a partial merkle tree.
 ///
 /// It represents a subset of the txid's of a known block, in a way that
@@ -431,8 +449,16 @@ impl MerkleBlock {
     /// assert_eq!(txid, matches[0]);
     /// ```
     pub fn from_block(block: &Block, match_txids: &HashSet<Txid>) -> Self {
+        Self::from_block_with_predicate(block, |txid| match_txids.contains(txid))
+    }
+
+    /// Create a MerkleBlock from a block, that should contain proofs for the
+    /// transactions whose txid satisfies `match_txids`.
+    pub fn from_block_with_predicate<F>(block: &Block, match_txids: F) -> Self
+        where F: Fn(&Txid) -> bool
+    {
         let block_txids: Vec<_> = block.txdata.iter().map(Transaction::txid).collect();
-        Self::from_header_txids(&block.header, &block_txids, match_txids)
+        Self::from_header_txids_with_predicate(&block.header, &block_txids, match_txids)
     }
 
     /// Create a MerkleBlock from the block's header and txids, that should contain proofs for match_txids.
@@ -444,9 +470,21 @@ impl MerkleBlock {
         block_txids: &[Txid],
         match_txids: &HashSet<Txid>,
     ) -> Self {
+        Self::from_header_txids_with_predicate(header, block_txids, |txid| match_txids.contains(txid))
+    }
+
+    /// Create a MerkleBlock from the block's header and txids, that should
+    /// contain proofs for the txids which satisfy `match_txids`.
+    pub fn from_header_txids_with_predicate<F>(
+        header: &BlockHeader,
+        block_txids: &[Txid],
+        match_txids: F,
+    ) -> Self
+        where F: Fn(&Txid) -> bool
+    {
         let matches: Vec<bool> = block_txids
-            .into_iter()
-            .map(|txid| match_txids.contains(txid))
+            .iter()
+            .map(match_txids)
             .collect();
 
         let pmt = PartialMerkleTree::from_txids(&block_txids, &matches);
@@ -505,8 +543,8 @@ mod tests {
 
     use consensus::encode::{deserialize, serialize};
     use util::hash::bitcoin_merkle_root;
-    use util::merkleblock::{MerkleBlock, PartialMerkleTree};
-    use Block;
+    use util::merkleblock::{MerkleBlock, MerkleBlockError, PartialMerkleTree};
+    use {Block, Transaction};
 
     #[test]
     fn pmt_tests() {
@@ -521,7 +559,7 @@ mod tests {
 
             // Calculate the merkle root and height
             let hashes = txids.iter().map(|t| t.as_hash());
-            let merkle_root_1: TxMerkleNode = bitcoin_merkle_root(hashes).into();
+            let merkle_root_1: TxMerkleNode = bitcoin_merkle_root(hashes).unwrap().into();
             let mut height = 1;
             let mut ntx = num_tx;
             while ntx > 1 {
@@ -669,6 +707,70 @@ mod tests {
         assert_eq!(index[1], 8);
     }
 
+    #[test]
+    fn merkleblock_construct_with_predicate() {
+        let block = get_block_13b8a();
+        let txids: Vec<Txid> = block.txdata.iter().map(Transaction::txid).collect();
+
+        // match every other transaction
+        let merkle_block = MerkleBlock::from_block_with_predicate(&block, |txid| {
+            txids.iter().position(|t| t == txid).unwrap() % 2 == 1
+        });
+        let mut matches: Vec<Txid> = vec![];
+        let mut index: Vec<u32> = vec![];
+        assert_eq!(merkle_block.extract_matches(&mut matches, &mut index), Ok(()));
+        assert_eq!(index, vec![1, 3, 5, 7]);
+        assert_eq!(matches, vec![txids[1], txids[3], txids[5], txids[7]]);
+
+        let with_set = MerkleBlock::from_block(&block, &matches.iter().cloned().collect());
+        assert_eq!(with_set, merkle_block);
+
+        // the proof must be for this header
+        let mut other_header = merkle_block.clone();
+        other_header.header.merkle_root = TxMerkleNode::default();
+        assert_eq!(other_header.extract_matches(&mut matches, &mut index), Err(MerkleBlockError::MerkleRootMismatch));
+    }
+
+    #[test]
+    fn pmt_malformed() {
+        let txids: Vec<Txid> = (1..8)
+            .map(|i| Txid::from_hex(&format!("{:064x}", i)).unwrap())
+            .collect();
+        let matches = vec![false, false, true, false, false, false, false];
+        let tree = PartialMerkleTree::from_txids(&txids, &matches);
+        assert!(tree.extract_matches(&mut vec![], &mut vec![]).is_ok());
+
+        // trailing bits beyond the padding of the last byte
+        let mut extra_bits = tree.clone();
+        extra_bits.bits.extend(vec![false; 8]);
+        assert_eq!(
+            extra_bits.extract_matches(&mut vec![], &mut vec![]),
+            Err(MerkleBlockError::BadFormat("Not all bit were consumed".to_owned()))
+        );
+
+        // hashes which are never used
+        let mut extra_hashes = tree.clone();
+        extra_hashes.hashes.push(TxMerkleNode::default());
+        extra_hashes.bits.push(false);
+        assert_eq!(
+            extra_hashes.extract_matches(&mut vec![], &mut vec![]),
+            Err(MerkleBlockError::BadFormat("Not all hashes were consumed".to_owned()))
+        );
+
+        // more hashes than transactions
+        let mut too_many = tree.clone();
+        too_many.hashes = vec![TxMerkleNode::default(); 8];
+        too_many.bits = vec![false; 8];
+        assert_eq!(
+            too_many.extract_matches(&mut vec![], &mut vec![]),
+            Err(MerkleBlockError::BadFormat("Proof contains more hashes than transactions".to_owned()))
+        );
+
+        let mut empty = tree.clone();
+        empty.num_transactions = 0;
+        assert_eq!(empty.extract_matches(&mut vec![], &mut vec![]), Err(MerkleBlockError::NoTransactions));
+    }
+
     /// Create a CMerkleBlock using a list of txids which will not be found in the given block
     #[test]
     fn merkleblock_construct_from_txids_not_found() {
@@ -16,6 +16,7 @@
 //!
 //! Various utility functions
 
+
 use hashes::{sha256d, Hash, HashEngine};
 use blockdata::opcodes;
 use consensus::{encode, Encodable};
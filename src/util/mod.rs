@@ -18,21 +18,39 @@
 
 pub mod key;
 pub mod address;
+#[cfg(feature = "test-utils")]
+pub mod arbitrary;
 pub mod amount;
 pub mod base58;
+pub mod base64;
 pub mod bip32;
+pub mod bip69;
 pub mod bip143;
+pub mod bip152;
+pub mod bloom;
 pub mod contracthash;
+pub mod difficulty;
+pub mod ecdsa;
+pub mod fee_estimator;
+pub mod fee_rate;
 pub mod hash;
 pub mod merkleblock;
 pub mod misc;
+pub mod pay_to_contract;
+pub mod persist;
 pub mod psbt;
+pub mod risk;
+pub mod sighash;
+pub mod taproot;
 pub mod uint;
+pub mod uri;
+pub mod weight_prediction;
 pub mod bip158;
 
 pub(crate) mod endian;
 
-use std::{error, fmt};
+use std::fmt;
+use std::error;
 
 use network;
 use consensus::encode;
@@ -85,7 +103,7 @@ impl fmt::Display for Error {
 
 #[allow(deprecated)]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
         match *self {
             Error::Encode(ref e) => Some(e),
             Error::Network(ref e) => Some(e),
@@ -0,0 +1,416 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Pay-to-contract
+//!
+//! Commits to arbitrary contract data by tweaking public keys, so that the
+//! resulting address can only be derived, and the coins only be spent, by
+//! someone who knows both the base keys and the contract.
+//!
+//! Each key is tweaked by adding `HMAC-SHA256(key, contract)` times the
+//! generator, keyed by the serialization of the base public key; see
+//! Appendix A of the Blockstream sidechains whitepaper. This replaces the
+//! deprecated `util::contracthash` module and also supports segwit outputs.
+
+
+use std::fmt;
+use std::error;
+
+use secp256k1::{self, Secp256k1};
+use hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+
+use blockdata::{opcodes, script};
+use network::constants::Network;
+use util::address::{self, Address};
+use util::key::{PrivateKey, PublicKey};
+
+/// Encoding of "pubkey here" in a script template
+const PUBKEY: u8 = 0xFE;
+
+/// A pay-to-contract error
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Error {
+    /// The tweak was out of range or produced an invalid key
+    Secp(secp256k1::Error),
+    /// An uncompressed key was used for a segwit key hash output
+    UncompressedKey,
+    /// Did not have enough keys to instantiate a template
+    TooFewKeys(usize),
+    /// Had too many keys; template does not match key list
+    TooManyKeys(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Secp(ref e) => fmt::Display::fmt(e, f),
+            Error::UncompressedKey => f.write_str("segwit key hash outputs require compressed keys"),
+            Error::TooFewKeys(n) => write!(f, "got {} keys, which was not enough", n),
+            Error::TooManyKeys(n) => write!(f, "got {} keys, which was too many", n),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            Error::Secp(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+#[doc(hidden)]
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Error {
+        Error::Secp(e)
+    }
+}
+
+/// An element of a script template
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum TemplateElement {
+    Op(opcodes::All),
+    Key,
+}
+
+/// A script with placeholders for keys.
+///
+/// It is parsed from script bytes where each `0xFE` byte marks a key and
+/// every other byte is an opcode; pushes of data are not supported.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScriptTemplate(Vec<TemplateElement>);
+
+impl ScriptTemplate {
+    /// Instantiate the template with `keys`, which must be exactly as many as
+    /// the template requires.
+    pub fn to_script(&self, keys: &[PublicKey]) -> Result<script::Script, Error> {
+        let required = self.required_keys();
+        if keys.len() < required {
+            return Err(Error::TooFewKeys(keys.len()));
+        }
+        if keys.len() > required {
+            return Err(Error::TooManyKeys(keys.len()));
+        }
+
+        let mut keys = keys.iter();
+        let mut ret = script::Builder::new();
+        for elem in &self.0 {
+            ret = match *elem {
+                TemplateElement::Op(opcode) => ret.push_opcode(opcode),
+                TemplateElement::Key => ret.push_key(keys.next().expect("counted above")),
+            };
+        }
+        Ok(ret.into_script())
+    }
+
+    /// Returns the number of keys this template requires to instantiate
+    pub fn required_keys(&self) -> usize {
+        self.0.iter().filter(|e| **e == TemplateElement::Key).count()
+    }
+}
+
+impl<'a> From<&'a [u8]> for ScriptTemplate {
+    fn from(slice: &'a [u8]) -> ScriptTemplate {
+        ScriptTemplate(slice.iter().map(|&byte| {
+            if byte == PUBKEY {
+                TemplateElement::Key
+            } else {
+                TemplateElement::Op(opcodes::All::from(byte))
+            }
+        }).collect())
+    }
+}
+
+/// The kind of output a pay-to-contract address is created for
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Template {
+    /// Pay to the hash of a single key
+    P2pkh,
+    /// Pay to the witness hash of a single compressed key
+    P2wpkh,
+    /// [Template::P2wpkh] nested in P2SH
+    P2shP2wpkh,
+    /// Pay to the hash of an instantiated script
+    P2sh(ScriptTemplate),
+    /// Pay to the witness hash of an instantiated script
+    P2wsh(ScriptTemplate),
+    /// [Template::P2wsh] nested in P2SH
+    P2shP2wsh(ScriptTemplate),
+}
+
+impl Template {
+    /// Returns the number of keys this template requires
+    pub fn required_keys(&self) -> usize {
+        match *self {
+            Template::P2pkh | Template::P2wpkh | Template::P2shP2wpkh => 1,
+            Template::P2sh(ref t) | Template::P2wsh(ref t) | Template::P2shP2wsh(ref t) => t.required_keys(),
+        }
+    }
+
+    /// Create the address for `keys` on `network`. The keys are used as
+    /// given, so they must already be tweaked.
+    pub fn to_address(&self, keys: &[PublicKey], network: Network) -> Result<Address, Error> {
+        let single_key = || match keys.len() {
+            0 => Err(Error::TooFewKeys(0)),
+            1 => Ok(&keys[0]),
+            n => Err(Error::TooManyKeys(n)),
+        };
+        let segwit_key = |res: Result<Address, address::Error>| res.map_err(|_| Error::UncompressedKey);
+
+        match *self {
+            Template::P2pkh => Ok(Address::p2pkh(single_key()?, network)),
+            Template::P2wpkh => segwit_key(Address::p2wpkh(single_key()?, network)),
+            Template::P2shP2wpkh => segwit_key(Address::p2shwpkh(single_key()?, network)),
+            Template::P2sh(ref t) => Ok(Address::p2sh(&t.to_script(keys)?, network)),
+            Template::P2wsh(ref t) => Ok(Address::p2wsh(&t.to_script(keys)?, network)),
+            Template::P2shP2wsh(ref t) => Ok(Address::p2shwsh(&t.to_script(keys)?, network)),
+        }
+    }
+}
+
+/// Compute the tweak committing `base` to `contract`.
+pub fn compute_tweak(base: &PublicKey, contract: &[u8]) -> Hmac<sha256::Hash> {
+    let mut hmac_engine: HmacEngine<sha256::Hash> = if base.compressed {
+        HmacEngine::new(&base.key.serialize())
+    } else {
+        HmacEngine::new(&base.key.serialize_uncompressed())
+    };
+    hmac_engine.input(contract);
+    Hmac::from_engine(hmac_engine)
+}
+
+/// Tweak a public key to commit to `contract`.
+///
+/// Fails, with negligible probability, if the tweak is not a valid secret
+/// key or the tweaked key is the point at infinity.
+pub fn tweak_public_key<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    base: &PublicKey,
+    contract: &[u8],
+) -> Result<PublicKey, Error> {
+    add_public_tweak(secp, base, &compute_tweak(base, contract)[..])
+}
+
+fn add_public_tweak<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    base: &PublicKey,
+    tweak: &[u8],
+) -> Result<PublicKey, Error> {
+    let mut key = *base;
+    key.key.add_exp_assign(secp, tweak)?;
+    Ok(key)
+}
+
+/// Tweak a private key to commit to `contract`, such that its public key is
+/// the [tweak_public_key] of the base private key's public key.
+pub fn tweak_private_key<C: secp256k1::Signing>(
+    secp: &Secp256k1<C>,
+    base: &PrivateKey,
+    contract: &[u8],
+) -> Result<PrivateKey, Error> {
+    add_private_tweak(base, &compute_tweak(&base.public_key(secp), contract)[..])
+}
+
+fn add_private_tweak(base: &PrivateKey, tweak: &[u8]) -> Result<PrivateKey, Error> {
+    let mut key = *base;
+    key.key.add_assign(tweak)?;
+    Ok(key)
+}
+
+/// Tweak each of `base_keys` to commit to `contract` and create the address
+/// of `template` for them.
+pub fn create_address<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    network: Network,
+    base_keys: &[PublicKey],
+    contract: &[u8],
+    template: &Template,
+) -> Result<Address, Error> {
+    let keys = base_keys.iter()
+        .map(|key| tweak_public_key(secp, key, contract))
+        .collect::<Result<Vec<_>, _>>()?;
+    template.to_address(&keys, network)
+}
+
+/// Check whether `address` is the address of `template` for `base_keys`
+/// tweaked to commit to `contract`.
+pub fn verify_contract<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    address: &Address,
+    base_keys: &[PublicKey],
+    contract: &[u8],
+    template: &Template,
+) -> Result<bool, Error> {
+    let expected = create_address(secp, address.network, base_keys, contract, template)?;
+    Ok(expected.payload == address.payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use secp256k1::{Message, Secp256k1};
+    use secp256k1::rand::thread_rng;
+    use hashes::hex::FromHex;
+
+    use network::constants::Network;
+    use util::address::Address;
+    use util::key::{PrivateKey, PublicKey};
+    use super::*;
+
+    macro_rules! hex (($hex:expr) => (Vec::from_hex($hex).unwrap()));
+    macro_rules! hex_key (($hex:expr) => (PublicKey::from_slice(&hex!($hex)).unwrap()));
+
+    const CONTRACT: &'static [u8] = b"if bottle mt dont remembr drink wont pay";
+
+    fn alpha_keys() -> Vec<PublicKey> {
+        vec![
+            hex_key!("0269992fb441ae56968e5b77d46a3e53b69f136444ae65a94041fc937bdb28d933"),
+            hex_key!("021df31471281d4478df85bfce08a10aab82601dca949a79950f8ddf7002bd915a"),
+            hex_key!("02174c82021492c2c6dfcbfa4187d10d38bed06afb7fdcd72c880179fddd641ea1"),
+            hex_key!("033f96e43d72c33327b6a4631ccaa6ea07f0b106c88b9dc71c9000bb6044d5e88a"),
+            hex_key!("0313d8748790f2a86fb524579b46ce3c68fedd58d2a738716249a9f7d5458a15c2"),
+            hex_key!("030b632eeb079eb83648886122a04c7bf6d98ab5dfb94cf353ee3e9382a4c2fab0"),
+            hex_key!("02fb54a7fcaa73c307cfd70f3fa66a2e4247a71858ca731396343ad30c7c4009ce"),
+        ]
+    }
+
+    fn alpha_template() -> ScriptTemplate {
+        ScriptTemplate::from(&hex!("55fefefefefefefe57ae")[..])
+    }
+
+    #[test]
+    fn fixed_vector() {
+        let secp = Secp256k1::verification_only();
+        let pks = [
+            ("02ba604e6ad9d3864eda8dc41c62668514ef7d5417d3b6db46e45cc4533bff001c",
+             "03b3597221b5982a3f1a77aed50f0015d1b6edfc69023ef7f25cfac0e8af1b2041"),
+            ("0365c0755ea55ce85d8a1900c68a524dbfd1c0db45ac3b3840dbb10071fe55e7a8",
+             "0296ece1fd954f7ae94f8d6bad19fd6d583f5b36335cf13135a3053a22f3c1fb05"),
+            ("0202313ca315889b2e69c94cf86901119321c7288139ba53ac022b7af3dc250054",
+             "0230bb1ca5dbc7fcf49294c2c3e582e5582eabf7c87e885735dc774da45d610e51"),
+        ];
+        for &(base, tweaked) in pks.iter() {
+            let base = PublicKey::from_str(base).unwrap();
+            let tweaked = PublicKey::from_str(tweaked).unwrap();
+            assert_eq!(tweak_public_key(&secp, &base, CONTRACT).unwrap(), tweaked);
+        }
+    }
+
+    #[test]
+    fn tweak_untweak_and_sign() {
+        let secp = Secp256k1::new();
+        for &compressed in &[true, false] {
+            let (sk, _) = secp.generate_keypair(&mut thread_rng());
+            let base = PrivateKey { key: sk, compressed: compressed, network: Network::Monacoin };
+            let base_pk = base.public_key(&secp);
+
+            let tweaked = tweak_private_key(&secp, &base, CONTRACT).unwrap();
+            let tweaked_pk = tweak_public_key(&secp, &base_pk, CONTRACT).unwrap();
+            assert_eq!(tweaked.public_key(&secp), tweaked_pk);
+            assert_ne!(tweaked_pk, base_pk);
+
+            // Subtracting the tweak again gives back the base keys.
+            let mut minus_tweak = secp256k1::SecretKey::from_slice(&compute_tweak(&base_pk, CONTRACT)[..]).unwrap();
+            minus_tweak.negate_assign();
+            let mut untweaked = tweaked;
+            untweaked.key.add_assign(&minus_tweak[..]).unwrap();
+            assert_eq!(untweaked.key, base.key);
+            let mut untweaked_pk = tweaked_pk;
+            untweaked_pk.key.add_exp_assign(&secp, &minus_tweak[..]).unwrap();
+            assert_eq!(untweaked_pk, base_pk);
+
+            let msg = Message::from_slice(&[0x42; 32]).unwrap();
+            let sig = secp.sign(&msg, &tweaked.key);
+            assert!(secp.verify(&msg, &sig, &tweaked_pk.key).is_ok());
+            assert!(secp.verify(&msg, &sig, &base_pk.key).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_tweak() {
+        let secp = Secp256k1::new();
+        let (sk, pk) = secp.generate_keypair(&mut thread_rng());
+        let base = PrivateKey { key: sk, compressed: true, network: Network::Monacoin };
+        let base_pk = PublicKey { key: pk, compressed: true };
+
+        // Adding the negated secret key gives zero and the point at infinity.
+        let mut minus_sk = sk;
+        minus_sk.negate_assign();
+        assert!(match add_private_tweak(&base, &minus_sk[..]) { Err(Error::Secp(_)) => true, _ => false });
+        assert!(match add_public_tweak(&secp, &base_pk, &minus_sk[..]) { Err(Error::Secp(_)) => true, _ => false });
+
+        // A tweak of at least the curve order is not a valid scalar.
+        let overflow = [0xff; 32];
+        assert!(match add_private_tweak(&base, &overflow) { Err(Error::Secp(_)) => true, _ => false });
+        assert!(match add_public_tweak(&secp, &base_pk, &overflow) { Err(Error::Secp(_)) => true, _ => false });
+    }
+
+    #[test]
+    fn addresses() {
+        let secp = Secp256k1::verification_only();
+        let keys = alpha_keys();
+        let tweaked = keys.iter().map(|k| tweak_public_key(&secp, k, CONTRACT).unwrap()).collect::<Vec<_>>();
+        let redeem = alpha_template().to_script(&tweaked).unwrap();
+
+        for &network in &[Network::Monacoin, Network::MonacoinTestnet] {
+            let cases = [
+                (Template::P2sh(alpha_template()), Address::p2sh(&redeem, network)),
+                (Template::P2wsh(alpha_template()), Address::p2wsh(&redeem, network)),
+                (Template::P2shP2wsh(alpha_template()), Address::p2shwsh(&redeem, network)),
+            ];
+            for &(ref template, ref expected) in cases.iter() {
+                let addr = create_address(&secp, network, &keys, CONTRACT, template).unwrap();
+                assert_eq!(&addr, expected);
+                assert!(verify_contract(&secp, &addr, &keys, CONTRACT, template).unwrap());
+                assert!(!verify_contract(&secp, &addr, &keys, b"another contract", template).unwrap());
+                assert_eq!(verify_contract(&secp, &addr, &keys, CONTRACT, &Template::P2wpkh), Err(Error::TooManyKeys(7)));
+            }
+
+            let single = [keys[0]];
+            let addr = create_address(&secp, network, &single, CONTRACT, &Template::P2wpkh).unwrap();
+            assert_eq!(addr, Address::p2wpkh(&tweaked[0], network).unwrap());
+            assert!(verify_contract(&secp, &addr, &single, CONTRACT, &Template::P2wpkh).unwrap());
+            assert!(!verify_contract(&secp, &addr, &single, CONTRACT, &Template::P2shP2wpkh).unwrap());
+        }
+
+        let mut uncompressed = keys[0];
+        uncompressed.compressed = false;
+        assert_eq!(
+            create_address(&secp, Network::Monacoin, &[uncompressed], CONTRACT, &Template::P2wpkh),
+            Err(Error::UncompressedKey)
+        );
+    }
+
+    #[test]
+    fn bad_key_number() {
+        let keys = alpha_keys();
+        let template_short = ScriptTemplate::from(&hex!("55fefefefefefe57ae")[..]);
+        let template_long = ScriptTemplate::from(&hex!("55fefefefefefefefe57ae")[..]);
+
+        assert_eq!(template_short.required_keys(), 6);
+        assert_eq!(template_long.required_keys(), 8);
+        assert_eq!(alpha_template().required_keys(), 7);
+        assert_eq!(template_short.to_script(&keys), Err(Error::TooManyKeys(7)));
+        assert_eq!(template_long.to_script(&keys), Err(Error::TooFewKeys(7)));
+        assert_eq!(Template::P2pkh.to_address(&[], Network::Monacoin), Err(Error::TooFewKeys(0)));
+        assert_eq!(Template::P2pkh.to_address(&keys, Network::Monacoin), Err(Error::TooManyKeys(7)));
+    }
+}
@@ -0,0 +1,274 @@
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Versioned snapshots
+//!
+//! A common format for state which clients persist across restarts, such as
+//! fee statistics, so that every such type gets the same versioning and
+//! upgrade story. A snapshot is
+//!
+//! ```text
+//! magic (4 bytes) | version (u32) | payload length (VarInt) | payload | checksum (4 bytes)
+//! ```
+//!
+//! where the payload is the consensus encoding of the object and the checksum
+//! is the first four bytes of the double SHA256 of everything before it.
+//!
+//! Snapshots written by an older version of a type are upgraded on load by
+//! [Persistable::migrate], one version at a time. Snapshots from a newer,
+//! unknown version are rejected.
+//!
+
+
+use std::fmt;
+use std::io;
+use std::error;
+
+use hashes::{sha256d, Hash};
+
+use consensus::encode::{self, Decodable, Encodable, VarInt};
+
+/// Snapshots larger than this are rejected before their payload is read
+pub const MAX_SNAPSHOT_SIZE: u64 = 32 * 1024 * 1024;
+
+/// A type which can be saved to and loaded from a versioned snapshot.
+pub trait Persistable: Encodable + Decodable {
+    /// Identifies the type of a snapshot
+    const MAGIC: [u8; 4];
+    /// The version the consensus encoding of this type is written with.
+    /// Starts at 1 and must be bumped whenever the encoding changes.
+    const CURRENT_VERSION: u32;
+
+    /// Convert the payload of a snapshot written with `old_version` into the
+    /// format of `old_version + 1`.
+    ///
+    /// This is called repeatedly until the payload is at
+    /// [Persistable::CURRENT_VERSION]. The default implementation supports no
+    /// migrations.
+    fn migrate(old_version: u32, payload: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let _ = payload;
+        Err(Error::UnsupportedVersion(old_version))
+    }
+}
+
+/// An error while saving or loading a snapshot
+#[derive(Debug)]
+pub enum Error {
+    /// The snapshot is of another type
+    BadMagic([u8; 4]),
+    /// The snapshot was written by a newer version of the type
+    UnknownVersion(u32),
+    /// The snapshot was written by an older version which can't be upgraded
+    UnsupportedVersion(u32),
+    /// The payload is larger than [MAX_SNAPSHOT_SIZE]
+    TooLarge(u64),
+    /// The checksum does not match the data (expected, actual)
+    BadChecksum([u8; 4], [u8; 4]),
+    /// Error encoding or decoding the snapshot, including I/O errors
+    Encode(encode::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BadMagic(ref m) => write!(f, "unexpected snapshot magic {:?}", m),
+            Error::UnknownVersion(v) => write!(f, "snapshot version {} is newer than supported", v),
+            Error::UnsupportedVersion(v) => write!(f, "snapshot version {} can not be upgraded", v),
+            Error::TooLarge(len) => write!(f, "snapshot payload of {} bytes is too large", len),
+            Error::BadChecksum(ref e, ref a) => write!(f, "snapshot checksum {:?} does not match expected {:?}", a, e),
+            Error::Encode(ref e) => write!(f, "snapshot encoding error: {}", e),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            Error::Encode(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for Error {
+    fn from(e: encode::Error) -> Error {
+        Error::Encode(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Encode(encode::Error::Io(e))
+    }
+}
+
+fn checksum(data: &[u8]) -> [u8; 4] {
+    let hash = sha256d::Hash::hash(data);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Write a snapshot of `obj` at its current version. Returns the number of bytes written.
+pub fn save<T: Persistable, W: io::Write>(obj: &T, mut w: W) -> Result<usize, Error> {
+    let payload = encode::serialize(obj);
+    let mut data = Vec::with_capacity(payload.len() + 20);
+    T::MAGIC.consensus_encode(&mut data)?;
+    T::CURRENT_VERSION.consensus_encode(&mut data)?;
+    payload.consensus_encode(&mut data)?;
+    let checksum = checksum(&data);
+    data.extend_from_slice(&checksum);
+    w.write_all(&data)?;
+    Ok(data.len())
+}
+
+/// Read a snapshot, upgrading it to the current version if it is an older one.
+pub fn load<T: Persistable, R: io::Read>(mut r: R) -> Result<T, Error> {
+    let magic: [u8; 4] = Decodable::consensus_decode(&mut r)?;
+    if magic != T::MAGIC {
+        return Err(Error::BadMagic(magic));
+    }
+    let version = u32::consensus_decode(&mut r)?;
+    if version > T::CURRENT_VERSION {
+        return Err(Error::UnknownVersion(version));
+    }
+    if version == 0 {
+        return Err(Error::UnsupportedVersion(version));
+    }
+
+    let len = VarInt::consensus_decode(&mut r)?.0;
+    if len > MAX_SNAPSHOT_SIZE {
+        return Err(Error::TooLarge(len));
+    }
+    let mut payload = vec![0; len as usize];
+    r.read_exact(&mut payload)?;
+    let expected: [u8; 4] = Decodable::consensus_decode(&mut r)?;
+
+    let mut data = Vec::with_capacity(payload.len() + 16);
+    magic.consensus_encode(&mut data)?;
+    version.consensus_encode(&mut data)?;
+    VarInt(len).consensus_encode(&mut data)?;
+    data.extend_from_slice(&payload);
+    let actual = checksum(&data);
+    if actual != expected {
+        return Err(Error::BadChecksum(expected, actual));
+    }
+
+    for old_version in version..T::CURRENT_VERSION {
+        payload = T::migrate(old_version, payload)?;
+    }
+    Ok(encode::deserialize(&payload)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use consensus::encode::{self, Encodable};
+
+    use super::*;
+
+    /// A record whose version 1 only had a name; version 2 added a height
+    #[derive(Debug, PartialEq, Eq)]
+    struct Record {
+        name: String,
+        height: u32,
+    }
+    impl_consensus_encoding!(Record, name, height);
+
+    impl Persistable for Record {
+        const MAGIC: [u8; 4] = *b"test";
+        const CURRENT_VERSION: u32 = 2;
+
+        fn migrate(old_version: u32, payload: Vec<u8>) -> Result<Vec<u8>, Error> {
+            match old_version {
+                1 => {
+                    let name: String = encode::deserialize(&payload)?;
+                    Ok(encode::serialize(&Record { name: name, height: 0 }))
+                }
+                _ => Err(Error::UnsupportedVersion(old_version)),
+            }
+        }
+    }
+
+    /// A snapshot as an older or newer version of [Record] would have written it
+    fn snapshot<T: Encodable>(version: u32, obj: &T) -> Vec<u8> {
+        let mut data = vec![];
+        Record::MAGIC.consensus_encode(&mut data).unwrap();
+        version.consensus_encode(&mut data).unwrap();
+        encode::serialize(obj).consensus_encode(&mut data).unwrap();
+        let checksum = checksum(&data);
+        data.extend_from_slice(&checksum);
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let record = Record { name: "tip".to_owned(), height: 1_234_567 };
+        let mut data = vec![];
+        let len = save(&record, &mut data).unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(data, snapshot(2, &record));
+        assert_eq!(load::<Record, _>(io::Cursor::new(&data)).unwrap(), record);
+    }
+
+    #[test]
+    fn migration() {
+        let data = snapshot(1, &"tip".to_owned());
+        let record: Record = load(io::Cursor::new(&data)).unwrap();
+        assert_eq!(record, Record { name: "tip".to_owned(), height: 0 });
+    }
+
+    #[test]
+    fn rejects_bad_snapshots() {
+        let record = Record { name: "tip".to_owned(), height: 1 };
+        let data = snapshot(2, &record);
+
+        match load::<Record, _>(io::Cursor::new(&snapshot(3, &record))) {
+            Err(Error::UnknownVersion(3)) => {},
+            other => panic!("expected unknown version, got {:?}", other),
+        }
+        match load::<Record, _>(io::Cursor::new(&snapshot(0, &record))) {
+            Err(Error::UnsupportedVersion(0)) => {},
+            other => panic!("expected unsupported version, got {:?}", other),
+        }
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'T';
+        match load::<Record, _>(io::Cursor::new(&bad_magic)) {
+            Err(Error::BadMagic(m)) => assert_eq!(&m, b"Test"),
+            other => panic!("expected bad magic, got {:?}", other),
+        }
+
+        let mut corrupt = data.clone();
+        corrupt[10] ^= 1;
+        match load::<Record, _>(io::Cursor::new(&corrupt)) {
+            Err(Error::BadChecksum(..)) => {},
+            other => panic!("expected bad checksum, got {:?}", other),
+        }
+
+        match load::<Record, _>(io::Cursor::new(&data[..data.len() - 1])) {
+            Err(Error::Encode(encode::Error::Io(_))) => {},
+            other => panic!("expected truncation error, got {:?}", other),
+        }
+
+        let mut huge = data[..8].to_vec();
+        encode::VarInt(MAX_SNAPSHOT_SIZE + 1).consensus_encode(&mut huge).unwrap();
+        match load::<Record, _>(io::Cursor::new(&huge)) {
+            Err(Error::TooLarge(_)) => {},
+            other => panic!("expected size error, got {:?}", other),
+        }
+    }
+}
@@ -15,7 +15,13 @@
 use std::error;
 use std::fmt;
 
+use hashes::hex;
+
 use blockdata::transaction::Transaction;
+use consensus::encode;
+use util::ecdsa::EcdsaSigError;
+use util::fee_rate::FeeRate;
+use util::{base64, key};
 use util::psbt::raw;
 
 /// Ways that a Partially Signed Transaction might fail.
@@ -28,6 +34,9 @@ pub enum Error {
     InvalidSeparator,
     /// Known keys must be according to spec.
     InvalidKey(raw::Key),
+    /// A key of the proprietary type which doesn't start with a
+    /// compact-size prefixed identifier and a subtype.
+    InvalidProprietaryKey(raw::Key),
     /// Keys within key-value map should never be duplicated.
     DuplicateKey(raw::Key),
     /// The scriptSigs for the unsigned transaction must be empty.
@@ -48,15 +57,45 @@ pub enum Error {
     },
     /// Unable to parse as a standard SigHash type.
     NonStandardSigHashType(u32),
+    /// A public key in a key or value could not be parsed.
+    InvalidPublicKey(key::Error),
+    /// A partial signature could not be parsed.
+    InvalidSignature(EcdsaSigError),
+    /// The input at this index has neither a witness nor a non-witness UTXO
+    /// providing the value of the output it spends.
+    MissingUtxo {
+        /// Index of the input
+        index: usize,
+    },
+    /// The sum of the input or output values overflowed.
+    FeeOverflow,
+    /// The outputs spend more than the inputs provide.
+    NegativeFee,
+    /// The input at this index has neither a final scriptSig nor a final
+    /// scriptWitness.
+    InputNotFinalized {
+        /// Index of the input
+        index: usize,
+    },
+    /// The fee rate of the extracted transaction is above the limit.
+    AbsurdFeeRate {
+        /// Fee rate of the transaction
+        fee_rate: FeeRate,
+        /// Highest fee rate allowed
+        max_fee_rate: FeeRate,
+    },
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::InvalidKey(ref rkey) => write!(f, "invalid key: {}", rkey),
+            Error::InvalidProprietaryKey(ref rkey) => write!(f, "invalid proprietary key: {}", rkey),
             Error::DuplicateKey(ref rkey) => write!(f, "duplicate key: {}", rkey),
             Error::UnexpectedUnsignedTx { expected: ref e, actual: ref a } => write!(f, "different unsigned transaction: expected {}, actual {}", e.txid(), a.txid()),
             Error::NonStandardSigHashType(ref sht) => write!(f, "non-standard sighash type: {}", sht),
+            Error::InvalidPublicKey(ref e) => write!(f, "invalid public key: {}", e),
+            Error::InvalidSignature(ref e) => write!(f, "invalid partial signature: {}", e),
             Error::InvalidMagic => f.write_str("invalid magic"),
             Error::InvalidSeparator => f.write_str("invalid separator"),
             Error::UnsignedTxHasScriptSigs => f.write_str("the unsigned transaction has script sigs"),
@@ -65,13 +104,100 @@ impl fmt::Display for Error {
                 f.write_str("partially signed transactions must have an unsigned transaction")
             }
             Error::NoMorePairs => f.write_str("no more key-value pairs for this psbt map"),
+            Error::MissingUtxo { index } => write!(f, "input {} has no UTXO information", index),
+            Error::FeeOverflow => f.write_str("input or output values overflow"),
+            Error::NegativeFee => f.write_str("output values exceed input values"),
+            Error::InputNotFinalized { index } => write!(f, "input {} is not finalized", index),
+            Error::AbsurdFeeRate { fee_rate, max_fee_rate } => {
+                write!(f, "absurd fee rate {}, the limit is {}", fee_rate, max_fee_rate)
+            }
         }
     }
 }
 
 #[allow(deprecated)]
 impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            Error::InvalidPublicKey(ref e) => Some(e),
+            Error::InvalidSignature(ref e) => Some(e),
+            Error::InvalidMagic
+            | Error::InvalidSeparator
+            | Error::InvalidKey(..)
+            | Error::InvalidProprietaryKey(..)
+            | Error::DuplicateKey(..)
+            | Error::UnsignedTxHasScriptSigs
+            | Error::UnsignedTxHasScriptWitnesses
+            | Error::MustHaveUnsignedTx
+            | Error::NoMorePairs
+            | Error::UnexpectedUnsignedTx { .. }
+            | Error::NonStandardSigHashType(..)
+            | Error::MissingUtxo { .. }
+            | Error::FeeOverflow
+            | Error::NegativeFee
+            | Error::InputNotFinalized { .. }
+            | Error::AbsurdFeeRate { .. } => None,
+        }
+    }
+
     fn description(&self) -> &str {
         "description() is deprecated; use Display"
     }
 }
+
+/// An error parsing a PSBT from its base64 or hex string encoding.
+#[derive(Debug)]
+pub enum PsbtParseError {
+    /// The string is not valid canonical base64
+    Base64(base64::Error),
+    /// The string is not valid hex
+    Hex(hex::Error),
+    /// The decoded bytes are not a valid PSBT
+    PsbtEncoding(encode::Error),
+}
+
+impl fmt::Display for PsbtParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PsbtParseError::Base64(ref e) => write!(f, "invalid base64 encoding: {}", e),
+            PsbtParseError::Hex(ref e) => write!(f, "invalid hex encoding: {}", e),
+            PsbtParseError::PsbtEncoding(ref e) => write!(f, "invalid PSBT: {}", e),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for PsbtParseError {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            PsbtParseError::Base64(ref e) => Some(e),
+            PsbtParseError::Hex(ref e) => Some(e),
+            PsbtParseError::PsbtEncoding(ref e) => Some(e),
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+#[doc(hidden)]
+impl From<base64::Error> for PsbtParseError {
+    fn from(e: base64::Error) -> PsbtParseError {
+        PsbtParseError::Base64(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<hex::Error> for PsbtParseError {
+    fn from(e: hex::Error) -> PsbtParseError {
+        PsbtParseError::Hex(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for PsbtParseError {
+    fn from(e: encode::Error) -> PsbtParseError {
+        PsbtParseError::PsbtEncoding(e)
+    }
+}
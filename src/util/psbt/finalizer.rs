@@ -0,0 +1,684 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Finalizer
+//!
+//! Implementation of the BIP174 Input Finalizer role for the common single
+//! key and multisig script templates.
+
+
+use std::fmt;
+use std::collections::BTreeMap;
+use std::error;
+
+use secp256k1::{Message, Secp256k1, Verification};
+
+use blockdata::opcodes;
+use blockdata::script::{self, Builder, Instruction, Script};
+use blockdata::transaction::{SigHashType, Transaction, TxOut};
+use hash_types::SigHash;
+use util::ecdsa::EcdsaSig;
+use util::key::PublicKey;
+use util::psbt::{Input, InputError, PartiallySignedTransaction};
+use util::sighash::{self, SighashCache};
+
+/// Ways that finalizing a PSBT input might fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinalizeError {
+    /// There is no input with this index.
+    InputIndexOutOfBounds {
+        /// Index of the input
+        index: usize,
+        /// Number of inputs
+        inputs: usize,
+    },
+    /// The input's UTXO information or scripts are inconsistent, see
+    /// [PartiallySignedTransaction::validate_input].
+    InvalidInput(InputError),
+    /// The input has neither a witness nor a non-witness UTXO.
+    MissingUtxo,
+    /// The spent output is P2SH but the input has no redeem script.
+    MissingRedeemScript,
+    /// The spent output is P2WSH but the input has no witness script.
+    MissingWitnessScript,
+    /// The spent script, redeem script or witness script is not one of the
+    /// templates the finalizer supports.
+    UnsupportedScript,
+    /// There are not enough partial signatures for the script.
+    MissingSignatures {
+        /// Number of signatures the script requires
+        required: usize,
+        /// Number of usable signatures present
+        available: usize,
+    },
+    /// A signature does not use the sighash type required by the input.
+    SigHashTypeMismatch {
+        /// Key of the signature
+        pubkey: PublicKey,
+        /// The input's sighash type
+        required: SigHashType,
+        /// The signature's sighash type
+        actual: SigHashType,
+    },
+    /// A signature does not verify against the transaction.
+    InvalidSignature(PublicKey),
+    /// The sighash could not be computed.
+    SigHash(sighash::Error),
+}
+
+impl fmt::Display for FinalizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FinalizeError::InputIndexOutOfBounds { index, inputs } => {
+                write!(f, "input index {} out of bounds (number of inputs: {})", index, inputs)
+            }
+            FinalizeError::InvalidInput(ref e) => write!(f, "invalid input: {}", e),
+            FinalizeError::MissingUtxo => f.write_str("the input has no UTXO information"),
+            FinalizeError::MissingRedeemScript => f.write_str("missing redeem script"),
+            FinalizeError::MissingWitnessScript => f.write_str("missing witness script"),
+            FinalizeError::UnsupportedScript => f.write_str("unsupported script template"),
+            FinalizeError::MissingSignatures { required, available } => {
+                write!(f, "{} signatures required, {} available", required, available)
+            }
+            FinalizeError::SigHashTypeMismatch { ref pubkey, required, actual } => {
+                write!(f, "signature of {} uses sighash type {}, the input requires {}", pubkey, actual, required)
+            }
+            FinalizeError::InvalidSignature(ref pubkey) => write!(f, "invalid signature of {}", pubkey),
+            FinalizeError::SigHash(ref e) => write!(f, "sighash error: {}", e),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for FinalizeError {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            FinalizeError::InvalidInput(ref e) => Some(e),
+            FinalizeError::SigHash(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+#[doc(hidden)]
+impl From<InputError> for FinalizeError {
+    fn from(e: InputError) -> FinalizeError {
+        FinalizeError::InvalidInput(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<sighash::Error> for FinalizeError {
+    fn from(e: sighash::Error) -> FinalizeError {
+        FinalizeError::SigHash(e)
+    }
+}
+
+/// How the signatures of an input are checked
+pub(super) enum SigVersion<'a> {
+    /// Legacy signature hash, committing to the script pubkey
+    Legacy(&'a Script),
+    /// BIP143 signature hash, committing to the script code and value
+    WitnessV0(Script, u64),
+}
+
+impl<'a> SigVersion<'a> {
+    /// The hash signed by a signature of type `hash_ty` of the input at `index`
+    pub(super) fn sighash(
+        &self,
+        cache: &mut SighashCache<&Transaction>,
+        index: usize,
+        hash_ty: SigHashType,
+    ) -> Result<SigHash, sighash::Error> {
+        match *self {
+            SigVersion::Legacy(script_code) => cache.legacy_signature_hash(index, script_code, hash_ty.to_u32()),
+            SigVersion::WitnessV0(ref script_code, value) => {
+                cache.segwit_signature_hash(index, script_code, value, hash_ty)
+            }
+        }
+    }
+}
+
+impl PartiallySignedTransaction {
+    /// Finalize every input which isn't finalized yet, as the BIP174 Input
+    /// Finalizer role does.
+    ///
+    /// Every input is attempted; if some can't be finalized, the errors are
+    /// returned by input index and the other inputs are still finalized.
+    pub fn finalize<C: Verification>(&mut self, secp: &Secp256k1<C>) -> Result<(), BTreeMap<usize, FinalizeError>> {
+        let mut errors = BTreeMap::new();
+        for index in 0..self.inputs.len() {
+            if let Err(e) = self.finalize_input(secp, index) {
+                errors.insert(index, e);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Finalize the input at `index` from its partial signatures and scripts.
+    ///
+    /// Supported are P2PKH, P2WPKH and P2SH-P2WPKH inputs, and P2WSH or
+    /// P2SH-P2WSH inputs whose witness script is `<key> OP_CHECKSIG` or an
+    /// `OP_CHECKMULTISIG` script. Each signature used must verify against the
+    /// transaction and, if the input specifies a sighash type, use it.
+    ///
+    /// The input is checked with [PartiallySignedTransaction::validate_input]
+    /// first. On success the final scriptSig and scriptWitness are set and all other
+    /// fields except the UTXOs and unknown pairs are cleared. Inputs which
+    /// are already finalized are left as they are.
+    pub fn finalize_input<C: Verification>(&mut self, secp: &Secp256k1<C>, index: usize) -> Result<(), FinalizeError> {
+        let (script_sig, witness) = {
+            let input = self.inputs.get(index).ok_or(FinalizeError::InputIndexOutOfBounds {
+                index: index,
+                inputs: self.inputs.len(),
+            })?;
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                return Ok(());
+            }
+            self.validate_input(index)?;
+
+            let spent = spent_output(self, index).ok_or(FinalizeError::MissingUtxo)?;
+            let checker = Checker {
+                secp: secp,
+                cache: SighashCache::new(&self.global.unsigned_tx),
+                index: index,
+                input: input,
+            };
+            checker.finalize(spent)?
+        };
+
+        let input = &mut self.inputs[index];
+        input.final_script_sig = if script_sig.is_empty() { None } else { Some(script_sig) };
+        input.final_script_witness = if witness.is_empty() { None } else { Some(witness) };
+        input.partial_sigs.clear();
+        input.sighash_type = None;
+        input.redeem_script = None;
+        input.witness_script = None;
+        input.hd_keypaths.clear();
+        Ok(())
+    }
+}
+
+/// The output spent by the input at `index`, if it has UTXO information and
+/// the input and spent output exist
+pub(super) fn spent_output(psbt: &PartiallySignedTransaction, index: usize) -> Option<&TxOut> {
+    let input = psbt.inputs.get(index)?;
+    if let Some(ref utxo) = input.witness_utxo {
+        return Some(utxo);
+    }
+    if let Some(ref tx) = input.non_witness_utxo {
+        let vout = psbt.global.unsigned_tx.input.get(index)?.previous_output.vout;
+        return tx.output.get(vout as usize);
+    }
+    None
+}
+
+struct Checker<'a, C: Verification + 'a> {
+    secp: &'a Secp256k1<C>,
+    cache: SighashCache<&'a Transaction>,
+    index: usize,
+    input: &'a Input,
+}
+
+impl<'a, C: Verification> Checker<'a, C> {
+    /// Build the final scriptSig and witness for the input spending `spent`
+    fn finalize(mut self, spent: &TxOut) -> Result<(Script, Vec<Vec<u8>>), FinalizeError> {
+        let spk = &spent.script_pubkey;
+        let input = self.input;
+        if spk.is_p2pkh() {
+            let (pk, sig) = self.single_sig(|pk| Script::new_p2pkh(&pk.pubkey_hash()) == *spk, &SigVersion::Legacy(spk))?;
+            let script_sig = sig.serialize_into_builder(Builder::new()).push_key(&pk).into_script();
+            Ok((script_sig, vec![]))
+        } else if spk.is_p2sh() {
+            let redeem_script = input.redeem_script.as_ref().ok_or(FinalizeError::MissingRedeemScript)?;
+            if redeem_script.to_p2sh() != *spk {
+                return Err(InputError::RedeemScriptMismatch.into());
+            }
+            if !redeem_script.is_v0_p2wpkh() && !redeem_script.is_v0_p2wsh() {
+                return Err(FinalizeError::UnsupportedScript);
+            }
+            let witness = self.finalize_witness_v0(redeem_script, spent.value)?;
+            let script_sig = Builder::new().push_slice(redeem_script.as_bytes()).into_script();
+            Ok((script_sig, witness))
+        } else {
+            let witness = self.finalize_witness_v0(spk, spent.value)?;
+            Ok((Script::new(), witness))
+        }
+    }
+
+    /// Build the witness for the v0 witness program `program`
+    fn finalize_witness_v0(&mut self, program: &Script, value: u64) -> Result<Vec<Vec<u8>>, FinalizeError> {
+        if program.is_v0_p2wpkh() {
+            let matches = |pk: &PublicKey| {
+                pk.wpubkey_hash().map(|hash| Script::new_v0_p2wpkh(&hash)).as_ref() == Some(program)
+            };
+            let script_code = program.p2wpkh_script_code().expect("checked to be P2WPKH");
+            let (pk, sig) = self.single_sig(matches, &SigVersion::WitnessV0(script_code, value))?;
+            let mut witness = vec![];
+            sig.serialize_into_witness(&mut witness);
+            witness.push(pk.to_bytes());
+            Ok(witness)
+        } else if program.is_v0_p2wsh() {
+            let input = self.input;
+            let witness_script = input.witness_script.as_ref().ok_or(FinalizeError::MissingWitnessScript)?;
+            if witness_script.to_v0_p2wsh() != *program {
+                return Err(InputError::WitnessScriptMismatch.into());
+            }
+            let sig_version = SigVersion::WitnessV0(witness_script.clone(), value);
+
+            let mut witness = if let Some(pk) = parse_single_key(witness_script) {
+                let (_, sig) = self.single_sig(|key| *key == pk, &sig_version)?;
+                vec![sig.to_vec()]
+            } else if let Some((required, keys)) = parse_multisig(witness_script) {
+                // CHECKMULTISIG pops one element too many
+                let mut witness = vec![vec![]];
+                let mut available = 0;
+                for pk in keys {
+                    if available == required {
+                        break;
+                    }
+                    if let Some(sig) = input.partial_sigs.get(&pk) {
+                        self.check_sig(&pk, sig, &sig_version)?;
+                        witness.push(sig.to_vec());
+                        available += 1;
+                    }
+                }
+                if available < required {
+                    return Err(FinalizeError::MissingSignatures { required: required, available: available });
+                }
+                witness
+            } else {
+                return Err(FinalizeError::UnsupportedScript);
+            };
+            witness.push(witness_script.to_bytes());
+            Ok(witness)
+        } else {
+            Err(FinalizeError::UnsupportedScript)
+        }
+    }
+
+    /// Find and check the signature of the only key for which `matches` is true
+    fn single_sig<F>(&mut self, matches: F, sig_version: &SigVersion) -> Result<(PublicKey, EcdsaSig), FinalizeError>
+        where F: Fn(&PublicKey) -> bool
+    {
+        let (pk, sig) = match self.input.partial_sigs.iter().find(|&(pk, _)| matches(pk)) {
+            Some((pk, sig)) => (*pk, *sig),
+            None => return Err(FinalizeError::MissingSignatures { required: 1, available: 0 }),
+        };
+        self.check_sig(&pk, &sig, sig_version)?;
+        Ok((pk, sig))
+    }
+
+    /// Check the sighash type of `sig` and verify it against the transaction
+    fn check_sig(&mut self, pk: &PublicKey, sig: &EcdsaSig, sig_version: &SigVersion) -> Result<(), FinalizeError> {
+        if let Some(required) = self.input.sighash_type {
+            if sig.hash_ty != required {
+                return Err(FinalizeError::SigHashTypeMismatch {
+                    pubkey: *pk,
+                    required: required,
+                    actual: sig.hash_ty,
+                });
+            }
+        }
+
+        let sighash = sig_version.sighash(&mut self.cache, self.index, sig.hash_ty)?;
+        let msg = Message::from_slice(&sighash[..]).expect("32 bytes");
+        self.secp.verify(&msg, &sig.sig, &pk.key).map_err(|_| FinalizeError::InvalidSignature(*pk))
+    }
+}
+
+/// Parse `<key> OP_CHECKSIG`
+fn parse_single_key(script: &Script) -> Option<PublicKey> {
+    let mut instructions = script.instructions_minimal();
+    let pk = match instructions.next() {
+        Some(Ok(Instruction::PushBytes(data))) => PublicKey::from_slice(data).ok()?,
+        _ => return None,
+    };
+    match (instructions.next(), instructions.next()) {
+        (Some(Ok(Instruction::Op(opcodes::all::OP_CHECKSIG))), None) => Some(pk),
+        _ => None,
+    }
+}
+
+/// Parse `<m> <key>... <n> OP_CHECKMULTISIG`, returning m and the keys
+fn parse_multisig(script: &Script) -> Option<(usize, Vec<PublicKey>)> {
+    let pushnum = |ins: Option<Result<Instruction, script::Error>>| match ins {
+        Some(Ok(Instruction::Op(op))) => {
+            let n = op.into_u8();
+            let first = opcodes::all::OP_PUSHNUM_1.into_u8();
+            let last = opcodes::all::OP_PUSHNUM_16.into_u8();
+            if n >= first && n <= last { Some((n - first + 1) as usize) } else { None }
+        }
+        _ => None,
+    };
+
+    let mut instructions = script.instructions_minimal().peekable();
+    let required = pushnum(instructions.next())?;
+    let mut keys = vec![];
+    loop {
+        let data = match instructions.peek() {
+            Some(&Ok(Instruction::PushBytes(data))) => data,
+            _ => break,
+        };
+        keys.push(PublicKey::from_slice(data).ok()?);
+        instructions.next();
+    }
+    if pushnum(instructions.next())? != keys.len() || required > keys.len() {
+        return None;
+    }
+    match (instructions.next(), instructions.next()) {
+        (Some(Ok(Instruction::Op(opcodes::all::OP_CHECKMULTISIG))), None) => Some((required, keys)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{Message, Secp256k1, SecretKey, Signing};
+
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{OutPoint, SigHashType, Sequence, Transaction, TxIn, TxOut};
+    use util::ecdsa::EcdsaSig;
+    use util::key::PublicKey;
+    use util::psbt::PartiallySignedTransaction;
+    use util::sighash::SighashCache;
+
+    use util::psbt::InputError;
+
+    use super::{parse_multisig, parse_single_key, FinalizeError};
+
+    const VALUE: u64 = 1_000_000;
+
+    fn keys<C: Signing>(secp: &Secp256k1<C>, n: u8) -> Vec<(SecretKey, PublicKey)> {
+        (1..n + 1).map(|i| {
+            let sk = SecretKey::from_slice(&[i; 32]).unwrap();
+            let pk = PublicKey { compressed: true, key: ::secp256k1::PublicKey::from_secret_key(secp, &sk) };
+            (sk, pk)
+        }).collect()
+    }
+
+    fn multisig(required: i64, keys: &[PublicKey]) -> Script {
+        let mut keys = keys.to_vec();
+        keys.sort_by_key(|pk| pk.to_bytes());
+        let builder = keys.iter().fold(Builder::new().push_int(required), |b, pk| b.push_key(pk));
+        builder.push_int(keys.len() as i64).push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script()
+    }
+
+    /// Creator and updater: a PSBT spending one `VALUE` output paying to each of
+    /// `script_pubkeys`, with the given redeem and witness scripts
+    fn create(script_pubkeys: &[(Script, Option<Script>, Option<Script>)]) -> (PartiallySignedTransaction, Transaction) {
+        let prev_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(1).push_int(1).into_script(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }],
+            output: script_pubkeys.iter().map(|&(ref spk, _, _)| TxOut {
+                value: VALUE,
+                script_pubkey: spk.clone(),
+            }).collect(),
+        };
+        let unsigned_tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: (0..script_pubkeys.len()).map(|vout| TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), vout as u32),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }).collect(),
+            output: vec![TxOut {
+                value: VALUE * script_pubkeys.len() as u64 - 10_000,
+                script_pubkey: hex_script!("0014aef3890b85e63ceeb2f6d59522aa73e358b3fc3c"),
+            }],
+        };
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+        for (input, &(ref spk, ref redeem_script, ref witness_script)) in psbt.inputs.iter_mut().zip(script_pubkeys) {
+            if spk.is_p2pkh() {
+                input.non_witness_utxo = Some(prev_tx.clone());
+            } else {
+                input.witness_utxo = Some(TxOut { value: VALUE, script_pubkey: spk.clone() });
+            }
+            input.redeem_script = redeem_script.clone();
+            input.witness_script = witness_script.clone();
+        }
+        (psbt, prev_tx)
+    }
+
+    /// Signer: add a signature of `sk` to the input at `index`
+    fn sign<C: Signing>(
+        secp: &Secp256k1<C>,
+        psbt: &mut PartiallySignedTransaction,
+        index: usize,
+        key: &(SecretKey, PublicKey),
+        hash_ty: SigHashType,
+    ) {
+        let sighash = {
+            let input = &psbt.inputs[index];
+            let cache = SighashCache::new(&psbt.global.unsigned_tx);
+            if let Some(ref tx) = input.non_witness_utxo {
+                let spk = &tx.output[psbt.global.unsigned_tx.input[index].previous_output.vout as usize].script_pubkey;
+                cache.legacy_signature_hash(index, spk, hash_ty.to_u32()).unwrap()
+            } else {
+                let script_code = match input.witness_script {
+                    Some(ref script) => script.clone(),
+                    None => Script::new_p2pkh(&key.1.pubkey_hash()),
+                };
+                let mut cache = cache;
+                cache.segwit_signature_hash(index, &script_code, VALUE, hash_ty).unwrap()
+            }
+        };
+        let sig = secp.sign(&Message::from_slice(&sighash[..]).unwrap(), &key.0);
+        psbt.inputs[index].partial_sigs.insert(key.1, EcdsaSig { sig: sig, hash_ty: hash_ty });
+    }
+
+    #[test]
+    fn finalize_templates() {
+        let secp = Secp256k1::new();
+        let keys = keys(&secp, 6);
+        let pks: Vec<PublicKey> = keys.iter().map(|k| k.1).collect();
+
+        let wpkh = Script::new_v0_p2wpkh(&pks[2].wpubkey_hash().unwrap());
+        let single = Builder::new().push_key(&pks[3]).push_opcode(opcodes::all::OP_CHECKSIG).into_script();
+        let multi = multisig(2, &pks[3..6]);
+        let (mut psbt, prev_tx) = create(&[
+            (Script::new_p2pkh(&pks[0].pubkey_hash()), None, None),
+            (Script::new_v0_p2wpkh(&pks[1].wpubkey_hash().unwrap()), None, None),
+            (wpkh.to_p2sh(), Some(wpkh.clone()), None),
+            (single.to_v0_p2wsh(), None, Some(single.clone())),
+            (multi.to_v0_p2wsh(), None, Some(multi.clone())),
+            (multi.to_v0_p2wsh().to_p2sh(), Some(multi.to_v0_p2wsh()), Some(multi.clone())),
+        ]);
+        psbt.inputs[1].sighash_type = Some(SigHashType::AllPlusAnyoneCanPay);
+
+        sign(&secp, &mut psbt, 0, &keys[0], SigHashType::All);
+        sign(&secp, &mut psbt, 1, &keys[1], SigHashType::AllPlusAnyoneCanPay);
+        sign(&secp, &mut psbt, 2, &keys[2], SigHashType::All);
+        sign(&secp, &mut psbt, 3, &keys[3], SigHashType::All);
+        // every key signs, only the first two in key order are used
+        for key in &keys[3..6] {
+            sign(&secp, &mut psbt, 4, key, SigHashType::All);
+        }
+        sign(&secp, &mut psbt, 5, &keys[5], SigHashType::Single);
+        sign(&secp, &mut psbt, 5, &keys[3], SigHashType::All);
+
+        psbt.finalize(&secp).unwrap();
+
+        let sig = |index: usize, key: usize| psbt.inputs[index].partial_sigs.get(&pks[key]).map(EcdsaSig::to_vec);
+        assert!(psbt.inputs.iter().all(|input| input.partial_sigs.is_empty()
+            && input.redeem_script.is_none()
+            && input.witness_script.is_none()
+            && input.sighash_type.is_none()
+            && input.hd_keypaths.is_empty()));
+        assert_eq!(sig(0, 0), None);
+
+        let tx = psbt.clone().extract_tx().unwrap();
+        assert_eq!(tx.input[0].witness.len(), 0);
+        assert_eq!(tx.input[0].script_sig.len(), 1 + tx.input[0].script_sig.as_bytes()[0] as usize + 1 + 33);
+        assert!(tx.input[1].script_sig.is_empty());
+        assert_eq!(tx.input[1].witness[1], pks[1].to_bytes());
+        assert_eq!(*tx.input[1].witness[0].last().unwrap(), 0x81);
+        assert_eq!(tx.input[2].script_sig, Builder::new().push_slice(wpkh.as_bytes()).into_script());
+        assert_eq!(tx.input[2].witness[1], pks[2].to_bytes());
+        assert_eq!(tx.input[3].witness.len(), 2);
+        assert_eq!(tx.input[3].witness[1], single.to_bytes());
+        assert_eq!(tx.input[4].witness.len(), 4);
+        assert!(tx.input[4].witness[0].is_empty());
+        assert_eq!(tx.input[4].witness[3], multi.to_bytes());
+        assert_eq!(tx.input[5].witness.len(), 4);
+        assert_eq!(tx.input[5].script_sig, Builder::new().push_slice(multi.to_v0_p2wsh().as_bytes()).into_script());
+
+        // the multisig signatures are in the order of the keys in the script
+        let (_, sorted) = parse_multisig(&multi).unwrap();
+        for (i, input) in tx.input[4..].iter().enumerate() {
+            let signers: Vec<PublicKey> = sorted.iter().filter(|pk| {
+                let sig = EcdsaSig::from_slice(&input.witness[1]).unwrap();
+                let sig2 = EcdsaSig::from_slice(&input.witness[2]).unwrap();
+                [sig, sig2].iter().any(|s| {
+                    let sighash = SighashCache::new(&tx)
+                        .segwit_signature_hash(4 + i, &multi, VALUE, s.hash_ty).unwrap();
+                    secp.verify(&Message::from_slice(&sighash[..]).unwrap(), &s.sig, &pk.key).is_ok()
+                })
+            }).cloned().collect();
+            assert_eq!(signers.len(), 2);
+        }
+
+        #[cfg(feature="bitcoinconsensus")]
+        tx.verify(|outpoint| prev_tx.output.get(outpoint.vout as usize).cloned()).unwrap();
+        let _ = prev_tx;
+
+        // finalizing again changes nothing
+        let finalized = psbt.clone();
+        psbt.finalize(&secp).unwrap();
+        assert_eq!(psbt, finalized);
+    }
+
+    #[test]
+    fn finalize_errors() {
+        let secp = Secp256k1::new();
+        let keys = keys(&secp, 3);
+        let pks: Vec<PublicKey> = keys.iter().map(|k| k.1).collect();
+
+        let wpkh = |i: usize| Script::new_v0_p2wpkh(&pks[i].wpubkey_hash().unwrap());
+        let multi = multisig(2, &pks);
+        let (mut psbt, prev_tx) = create(&[
+            // bare P2PK isn't supported
+            (Script::new_p2pk(&pks[0]), None, None),
+            // only one of the two signatures
+            (multi.to_v0_p2wsh(), None, Some(multi.clone())),
+            // wrong sighash type
+            (wpkh(0), None, None),
+            // signature for another input
+            (wpkh(1), None, None),
+            // missing and mismatched scripts
+            (multi.to_v0_p2wsh(), None, None),
+            (multi.to_v0_p2wsh(), None, Some(wpkh(0))),
+            (wpkh(0).to_p2sh(), None, None),
+            (wpkh(0).to_p2sh(), Some(wpkh(1)), None),
+            // no signature at all
+            (wpkh(2), None, None),
+            // and one input that works
+            (wpkh(2), None, None),
+        ]);
+        psbt.inputs[0].witness_utxo = None;
+        psbt.inputs[0].non_witness_utxo = Some(Transaction {
+            version: 1, lock_time: LockTime::ZERO, input: vec![], output: vec![],
+        });
+
+        sign(&secp, &mut psbt, 1, &keys[0], SigHashType::All);
+        psbt.inputs[2].sighash_type = Some(SigHashType::All);
+        sign(&secp, &mut psbt, 2, &keys[0], SigHashType::None);
+        sign(&secp, &mut psbt, 9, &keys[1], SigHashType::All);
+        let sig = psbt.inputs[9].partial_sigs[&pks[1]];
+        psbt.inputs[3].partial_sigs.insert(pks[1], sig);
+        psbt.inputs[9].partial_sigs.clear();
+        sign(&secp, &mut psbt, 9, &keys[2], SigHashType::All);
+
+        let errors = psbt.finalize(&secp).unwrap_err();
+        match errors[&0] {
+            FinalizeError::InvalidInput(InputError::NonWitnessUtxoMismatch { .. }) => {},
+            ref e => panic!("expected non-witness UTXO mismatch, got {:?}", e),
+        }
+        assert_eq!(errors[&1], FinalizeError::MissingSignatures { required: 2, available: 1 });
+        assert_eq!(errors[&2], FinalizeError::SigHashTypeMismatch {
+            pubkey: pks[0],
+            required: SigHashType::All,
+            actual: SigHashType::None,
+        });
+        assert_eq!(errors[&3], FinalizeError::InvalidSignature(pks[1]));
+        assert_eq!(errors[&4], FinalizeError::MissingWitnessScript);
+        assert_eq!(errors[&5], FinalizeError::InvalidInput(InputError::WitnessScriptMismatch));
+        assert_eq!(errors[&6], FinalizeError::MissingRedeemScript);
+        assert_eq!(errors[&7], FinalizeError::InvalidInput(InputError::RedeemScriptMismatch));
+        assert_eq!(errors[&8], FinalizeError::MissingSignatures { required: 1, available: 0 });
+        assert_eq!(errors.len(), 9);
+        assert!(psbt.inputs[9].final_script_witness.is_some());
+        // the inputs which failed are left untouched
+        assert_eq!(psbt.inputs[1].partial_sigs.len(), 1);
+        assert!(psbt.inputs[1].witness_script.is_some());
+
+        // with the right UTXO, P2PK is recognized as unsupported
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx);
+        assert_eq!(psbt.finalize_input(&secp, 0), Err(FinalizeError::UnsupportedScript));
+        assert_eq!(psbt.finalize_input(&secp, 10), Err(FinalizeError::InputIndexOutOfBounds { index: 10, inputs: 10 }));
+    }
+
+    #[test]
+    fn parse_templates() {
+        let secp = Secp256k1::new();
+        let keys: Vec<PublicKey> = (1..4u8).map(|i| {
+            let sk = ::secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+            PublicKey { compressed: true, key: ::secp256k1::PublicKey::from_secret_key(&secp, &sk) }
+        }).collect();
+        let single = Builder::new().push_key(&keys[0]).push_opcode(opcodes::all::OP_CHECKSIG).into_script();
+        assert_eq!(parse_single_key(&single), Some(keys[0]));
+        assert_eq!(parse_multisig(&single), None);
+
+        let multisig = Builder::new()
+            .push_int(2)
+            .push_key(&keys[0]).push_key(&keys[1]).push_key(&keys[2])
+            .push_int(3)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(parse_multisig(&multisig), Some((2, keys.clone())));
+        assert_eq!(parse_single_key(&multisig), None);
+
+        // m > n
+        let script = Builder::new().push_int(2).push_key(&keys[0]).push_int(1)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script();
+        assert_eq!(parse_multisig(&script), None);
+        // n doesn't match the number of keys
+        let script = Builder::new().push_int(1).push_key(&keys[0]).push_int(2)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script();
+        assert_eq!(parse_multisig(&script), None);
+        // trailing opcodes
+        let script = Builder::new().push_key(&keys[0]).push_opcode(opcodes::all::OP_CHECKSIG)
+            .push_opcode(opcodes::all::OP_DROP).into_script();
+        assert_eq!(parse_single_key(&script), None);
+    }
+}
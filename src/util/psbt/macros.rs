@@ -25,6 +25,16 @@ macro_rules! merge {
     };
 }
 
+/// Merge the key-value map `$thing` of `$other` into `$slf`, keeping the
+/// value already in `$slf` for keys present in both.
+macro_rules! merge_map {
+    ($thing:ident, $slf:ident, $other:ident) => {
+        for (key, value) in $other.$thing {
+            $slf.$thing.entry(key).or_insert(value);
+        }
+    };
+}
+
 macro_rules! impl_psbt_de_serialize {
     ($thing:ty) => {
         impl_psbt_serialize!($thing);
@@ -12,6 +12,7 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
+
 use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
 use std::io::{self, Cursor};
@@ -29,6 +30,8 @@ pub struct Global {
     /// The unsigned transaction, scriptSigs and witnesses for each input must be
     /// empty.
     pub unsigned_tx: Transaction,
+    /// Global proprietary key-value pairs.
+    pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
     /// Unknown global key-value pairs.
     pub unknown: BTreeMap<raw::Key, Vec<u8>>,
 }
@@ -48,6 +51,7 @@ impl Global {
 
         Ok(Global {
             unsigned_tx: tx,
+            proprietary: Default::default(),
             unknown: Default::default(),
         })
     }
@@ -62,6 +66,10 @@ impl Map for Global {
 
         match raw_key.type_value {
             0u8 => return Err(Error::DuplicateKey(raw_key).into()),
+            0xFCu8 => match self.proprietary.entry(raw::ProprietaryKey::from_key(raw_key.clone())?) {
+                Entry::Vacant(empty_key) => {empty_key.insert(raw_value);},
+                Entry::Occupied(_) => return Err(Error::DuplicateKey(raw_key).into()),
+            },
             _ => match self.unknown.entry(raw_key) {
                 Entry::Vacant(empty_key) => {empty_key.insert(raw_value);},
                 Entry::Occupied(k) => return Err(Error::DuplicateKey(k.key().clone()).into()),
@@ -91,6 +99,13 @@ impl Map for Global {
             },
         });
 
+        for (key, value) in self.proprietary.iter() {
+            rv.push(raw::Pair {
+                key: key.to_key(),
+                value: value.clone(),
+            });
+        }
+
         for (key, value) in self.unknown.iter() {
             rv.push(raw::Pair {
                 key: key.clone(),
@@ -109,7 +124,8 @@ impl Map for Global {
             });
         }
 
-        self.unknown.extend(other.unknown);
+        merge_map!(proprietary, self, other);
+        merge_map!(unknown, self, other);
         Ok(())
     }
 }
@@ -120,7 +136,7 @@ impl Decodable for Global {
     fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
 
         let mut tx: Option<Transaction> = None;
-        let mut unknowns: BTreeMap<raw::Key, Vec<u8>> = Default::default();
+        let mut pairs: Vec<raw::Pair> = Default::default();
 
         loop {
             match raw::Pair::consensus_decode(&mut d) {
@@ -154,10 +170,7 @@ impl Decodable for Global {
                                 return Err(Error::InvalidKey(pair.key).into())
                             }
                         }
-                        _ => match unknowns.entry(pair.key) {
-                            Entry::Vacant(empty_key) => {empty_key.insert(pair.value);},
-                            Entry::Occupied(k) => return Err(Error::DuplicateKey(k.key().clone()).into()),
-                        }
+                        _ => pairs.push(pair),
                     }
                 }
                 Err(::consensus::encode::Error::Psbt(::util::psbt::Error::NoMorePairs)) => break,
@@ -167,7 +180,9 @@ impl Decodable for Global {
 
         if let Some(tx) = tx {
             let mut rv: Global = Global::from_unsigned_tx(tx)?;
-            rv.unknown = unknowns;
+            for pair in pairs {
+                rv.insert_pair(pair)?;
+            }
             Ok(rv)
         } else {
             Err(Error::MustHaveUnsignedTx.into())
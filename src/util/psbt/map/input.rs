@@ -12,12 +12,15 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
+
+
 use std::collections::BTreeMap;
 
 use blockdata::script::Script;
 use blockdata::transaction::{SigHashType, Transaction, TxOut};
 use consensus::encode;
-use util::bip32::{DerivationPath, Fingerprint};
+use util::bip32::KeySource;
+use util::ecdsa::EcdsaSig;
 use util::key::PublicKey;
 use util::psbt;
 use util::psbt::map::Map;
@@ -38,7 +41,7 @@ pub struct Input {
     pub witness_utxo: Option<TxOut>,
     /// A map from public keys to their corresponding signature as would be
     /// pushed to the stack from a scriptSig or witness.
-    pub partial_sigs: BTreeMap<PublicKey, Vec<u8>>,
+    pub partial_sigs: BTreeMap<PublicKey, EcdsaSig>,
     /// The sighash type to be used for this input. Signatures for this input
     /// must use the sighash type.
     pub sighash_type: Option<SigHashType>,
@@ -48,13 +51,15 @@ pub struct Input {
     pub witness_script: Option<Script>,
     /// A map from public keys needed to sign this input to their corresponding
     /// master key fingerprints and derivation paths.
-    pub hd_keypaths: BTreeMap<PublicKey, (Fingerprint, DerivationPath)>,
+    pub hd_keypaths: BTreeMap<PublicKey, KeySource>,
     /// The finalized, fully-constructed scriptSig with signatures and any other
     /// scripts necessary for this input to pass validation.
     pub final_script_sig: Option<Script>,
     /// The finalized, fully-constructed scriptWitness with signatures and any
     /// other scripts necessary for this input to pass validation.
     pub final_script_witness: Option<Vec<Vec<u8>>>,
+    /// Proprietary key-value pairs for this input.
+    pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
     /// Unknown key-value pairs for this input.
     pub unknown: BTreeMap<raw::Key, Vec<u8>>,
 }
@@ -104,14 +109,18 @@ impl Map for Input {
             }
             2u8 => {
                 impl_psbt_insert_pair! {
-                    self.partial_sigs <= <raw_key: PublicKey>|<raw_value: Vec<u8>>
+                    self.partial_sigs <= <raw_key: PublicKey>|<raw_value: EcdsaSig>
                 }
             }
             6u8 => {
                 impl_psbt_insert_pair! {
-                    self.hd_keypaths <= <raw_key: PublicKey>|<raw_value: (Fingerprint, DerivationPath)>
+                    self.hd_keypaths <= <raw_key: PublicKey>|<raw_value: KeySource>
                 }
             }
+            0xFCu8 => match self.proprietary.entry(raw::ProprietaryKey::from_key(raw_key.clone())?) {
+                ::std::collections::btree_map::Entry::Vacant(empty_key) => {empty_key.insert(raw_value);},
+                ::std::collections::btree_map::Entry::Occupied(_) => return Err(Error::DuplicateKey(raw_key).into()),
+            },
             _ => match self.unknown.entry(raw_key) {
                 ::std::collections::btree_map::Entry::Vacant(empty_key) => {empty_key.insert(raw_value);},
                 ::std::collections::btree_map::Entry::Occupied(k) => return Err(Error::DuplicateKey(k.key().clone()).into()),
@@ -132,8 +141,18 @@ impl Map for Input {
             rv.push(self.witness_utxo as <1u8, _>|<TxOut>)
         }
 
-        impl_psbt_get_pair! {
-            rv.push(self.partial_sigs as <2u8, PublicKey>|<Vec<u8>>)
+        // Monacoin Core keys partial signatures by key ID, so write them in
+        // the order of the public key hashes rather than of the keys
+        let mut partial_sigs: Vec<(&PublicKey, &EcdsaSig)> = self.partial_sigs.iter().collect();
+        partial_sigs.sort_by_key(|&(key, _)| key.pubkey_hash());
+        for (key, sig) in partial_sigs {
+            rv.push(raw::Pair {
+                key: raw::Key {
+                    type_value: 2u8,
+                    key: psbt::serialize::Serialize::serialize(key),
+                },
+                value: psbt::serialize::Serialize::serialize(sig),
+            });
         }
 
         impl_psbt_get_pair! {
@@ -149,7 +168,7 @@ impl Map for Input {
         }
 
         impl_psbt_get_pair! {
-            rv.push(self.hd_keypaths as <6u8, PublicKey>|<(Fingerprint, DerivationPath)>)
+            rv.push(self.hd_keypaths as <6u8, PublicKey>|<KeySource>)
         }
 
         impl_psbt_get_pair! {
@@ -160,6 +179,13 @@ impl Map for Input {
             rv.push(self.final_script_witness as <8u8, _>|<Script>)
         }
 
+        for (key, value) in self.proprietary.iter() {
+            rv.push(raw::Pair {
+                key: key.to_key(),
+                value: value.clone(),
+            });
+        }
+
         for (key, value) in self.unknown.iter() {
             rv.push(raw::Pair {
                 key: key.clone(),
@@ -171,17 +197,17 @@ impl Map for Input {
     }
 
     fn merge(&mut self, other: Self) -> Result<(), psbt::Error> {
+        // Both UTXO fields may be present, as the non-witness UTXO lets signers
+        // of segwit inputs check the value they are signing for.
         merge!(non_witness_utxo, self, other);
+        merge!(witness_utxo, self, other);
 
-        if let (&None, Some(witness_utxo)) = (&self.witness_utxo, other.witness_utxo) {
-            self.witness_utxo = Some(witness_utxo);
-            self.non_witness_utxo = None; // Clear out any non-witness UTXO when we set a witness one
-        }
-
-        self.partial_sigs.extend(other.partial_sigs);
-        self.hd_keypaths.extend(other.hd_keypaths);
-        self.unknown.extend(other.unknown);
+        merge_map!(partial_sigs, self, other);
+        merge_map!(hd_keypaths, self, other);
+        merge_map!(proprietary, self, other);
+        merge_map!(unknown, self, other);
 
+        merge!(sighash_type, self, other);
         merge!(redeem_script, self, other);
         merge!(witness_script, self, other);
         merge!(final_script_sig, self, other);
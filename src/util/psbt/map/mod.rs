@@ -12,6 +12,7 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
+
 use consensus::encode;
 use util::psbt;
 use util::psbt::raw;
@@ -25,6 +26,9 @@ pub trait Map {
     fn get_pairs(&self) -> Result<Vec<raw::Pair>, encode::Error>;
 
     /// Attempt to merge with another key-value map of the same type.
+    ///
+    /// Where both maps have a value for the same key, the value in `self` is
+    /// kept, which makes merging associative.
     fn merge(&mut self, other: Self) -> Result<(), psbt::Error>;
 }
 
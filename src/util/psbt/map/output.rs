@@ -12,12 +12,13 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
+
 use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
 
 use blockdata::script::Script;
 use consensus::encode;
-use util::bip32::{DerivationPath, Fingerprint};
+use util::bip32::KeySource;
 use util::key::PublicKey;
 use util::psbt;
 use util::psbt::map::Map;
@@ -34,7 +35,9 @@ pub struct Output {
     pub witness_script: Option<Script>,
     /// A map from public keys needed to spend this output to their
     /// corresponding master key fingerprints and derivation paths.
-    pub hd_keypaths: BTreeMap<PublicKey, (Fingerprint, DerivationPath)>,
+    pub hd_keypaths: BTreeMap<PublicKey, KeySource>,
+    /// Proprietary key-value pairs for this output.
+    pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
     /// Unknown key-value pairs for this output.
     pub unknown: BTreeMap<raw::Key, Vec<u8>>,
 }
@@ -59,9 +62,13 @@ impl Map for Output {
             }
             2u8 => {
                 impl_psbt_insert_pair! {
-                    self.hd_keypaths <= <raw_key: PublicKey>|<raw_value: (Fingerprint, DerivationPath)>
+                    self.hd_keypaths <= <raw_key: PublicKey>|<raw_value: KeySource>
                 }
             }
+            0xFCu8 => match self.proprietary.entry(raw::ProprietaryKey::from_key(raw_key.clone())?) {
+                ::std::collections::btree_map::Entry::Vacant(empty_key) => {empty_key.insert(raw_value);},
+                ::std::collections::btree_map::Entry::Occupied(_) => return Err(Error::DuplicateKey(raw_key).into()),
+            },
             _ => match self.unknown.entry(raw_key) {
                     Entry::Vacant(empty_key) => {empty_key.insert(raw_value);},
                     Entry::Occupied(k) => return Err(Error::DuplicateKey(k.key().clone()).into()),
@@ -83,7 +90,14 @@ impl Map for Output {
         }
 
         impl_psbt_get_pair! {
-            rv.push(self.hd_keypaths as <2u8, PublicKey>|<(Fingerprint, DerivationPath)>)
+            rv.push(self.hd_keypaths as <2u8, PublicKey>|<KeySource>)
+        }
+
+        for (key, value) in self.proprietary.iter() {
+            rv.push(raw::Pair {
+                key: key.to_key(),
+                value: value.clone(),
+            });
         }
 
         for (key, value) in self.unknown.iter() {
@@ -97,8 +111,9 @@ impl Map for Output {
     }
 
     fn merge(&mut self, other: Self) -> Result<(), psbt::Error> {
-        self.hd_keypaths.extend(other.hd_keypaths);
-        self.unknown.extend(other.unknown);
+        merge_map!(hd_keypaths, self, other);
+        merge_map!(proprietary, self, other);
+        merge_map!(unknown, self, other);
 
         merge!(redeem_script, self, other);
         merge!(witness_script, self, other);
@@ -18,14 +18,19 @@
 //! defined at https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki
 //! except we define PSBTs containing non-standard SigHash types as invalid.
 
+
 use blockdata::script::Script;
 use blockdata::transaction::Transaction;
 use consensus::{encode, Encodable, Decodable};
+use hashes::hex::FromHex;
+use util::base64;
+use util::fee_rate::{FeeRate, Weight};
 
+use std::{fmt, str};
 use std::io;
 
 mod error;
-pub use self::error::Error;
+pub use self::error::{Error, PsbtParseError};
 
 pub mod raw;
 
@@ -37,6 +42,22 @@ pub mod serialize;
 mod map;
 pub use self::map::{Map, Global, Input, Output};
 
+mod finalizer;
+pub use self::finalizer::FinalizeError;
+
+mod validate;
+pub use self::validate::InputError;
+
+mod sighash;
+pub use self::sighash::{PsbtSighashMsg, SighashError};
+
+mod signer;
+pub use self::signer::{SignError, SigningErrors, SigningKeys};
+
+/// The fee rate above which [PartiallySignedTransaction::extract_tx] refuses to
+/// extract a transaction: 0.1 MONA/kvB, Monacoin Core's default `maxfeerate`.
+pub const DEFAULT_MAX_FEE_RATE: FeeRate = FeeRate::from_sat_per_kwu(2_500_000);
+
 /// A Partially Signed Transaction.
 #[derive(Debug, Clone, PartialEq)]
 pub struct PartiallySignedTransaction {
@@ -50,6 +71,9 @@ pub struct PartiallySignedTransaction {
     pub outputs: Vec<Output>,
 }
 
+/// Shorthand for [PartiallySignedTransaction].
+pub type Psbt = PartiallySignedTransaction;
+
 impl PartiallySignedTransaction {
     /// Create a PartiallySignedTransaction from an unsigned transaction, error
     /// if not unsigned
@@ -61,21 +85,86 @@ impl PartiallySignedTransaction {
         })
     }
 
+    /// Calculate the fee paid by the transaction, which is the sum of the
+    /// values of the outputs spent, taken from the witness or non-witness UTXO
+    /// of each input, minus the sum of the output values.
+    pub fn fee(&self) -> Result<u64, self::Error> {
+        let mut input_value: u64 = 0;
+        for (index, (txin, psbtin)) in self.global.unsigned_tx.input.iter().zip(self.inputs.iter()).enumerate() {
+            let value = if let Some(ref utxo) = psbtin.witness_utxo {
+                utxo.value
+            } else if let Some(ref tx) = psbtin.non_witness_utxo {
+                match tx.output.get(txin.previous_output.vout as usize) {
+                    Some(utxo) => utxo.value,
+                    None => return Err(Error::MissingUtxo { index: index }),
+                }
+            } else {
+                return Err(Error::MissingUtxo { index: index });
+            };
+            input_value = input_value.checked_add(value).ok_or(Error::FeeOverflow)?;
+        }
+
+        let mut output_value: u64 = 0;
+        for txout in &self.global.unsigned_tx.output {
+            output_value = output_value.checked_add(txout.value).ok_or(Error::FeeOverflow)?;
+        }
+
+        input_value.checked_sub(output_value).ok_or(Error::NegativeFee)
+    }
+
     /// Extract the Transaction from a PartiallySignedTransaction by filling in
     /// the available signature information in place.
-    pub fn extract_tx(self) -> Transaction {
+    ///
+    /// Fails if an input is not finalized, or if the fee rate is above
+    /// [DEFAULT_MAX_FEE_RATE] or can not be calculated.
+    pub fn extract_tx(self) -> Result<Transaction, self::Error> {
+        self.extract_tx_with_fee_rate_limit(DEFAULT_MAX_FEE_RATE)
+    }
+
+    /// Extract the Transaction like [PartiallySignedTransaction::extract_tx],
+    /// but with a custom fee rate limit.
+    pub fn extract_tx_with_fee_rate_limit(self, max_fee_rate: FeeRate) -> Result<Transaction, self::Error> {
+        let fee = self.fee()?;
+        let tx = self.extract_tx_unchecked_fee_rate()?;
+
+        let weight = Weight::from_wu(tx.get_weight() as u64);
+        let fee_rate = fee.checked_mul(1000)
+            .map(|fee| FeeRate::from_sat_per_kwu(fee / weight.to_wu()))
+            .ok_or(Error::FeeOverflow)?;
+        if fee_rate > max_fee_rate {
+            return Err(Error::AbsurdFeeRate {
+                fee_rate: fee_rate,
+                max_fee_rate: max_fee_rate,
+            });
+        }
+        Ok(tx)
+    }
+
+    /// Extract the Transaction without checking its fee rate, which allows
+    /// extracting transactions whose inputs lack UTXO information.
+    ///
+    /// Fails if an input is not finalized.
+    pub fn extract_tx_unchecked_fee_rate(self) -> Result<Transaction, self::Error> {
         let mut tx: Transaction = self.global.unsigned_tx;
 
-        for (vin, psbtin) in tx.input.iter_mut().zip(self.inputs.into_iter()) {
+        for (index, (vin, psbtin)) in tx.input.iter_mut().zip(self.inputs.into_iter()).enumerate() {
+            if psbtin.final_script_sig.is_none() && psbtin.final_script_witness.is_none() {
+                return Err(Error::InputNotFinalized { index: index });
+            }
             vin.script_sig = psbtin.final_script_sig.unwrap_or_else(Script::new);
             vin.witness = psbtin.final_script_witness.unwrap_or_else(Vec::new);
         }
 
-        tx
+        Ok(tx)
     }
 
-    /// Attempt to merge with another `PartiallySignedTransaction`.
-    pub fn merge(&mut self, other: Self) -> Result<(), self::Error> {
+    /// Combine with another `PartiallySignedTransaction` for the same unsigned
+    /// transaction, as the BIP174 Combiner role does.
+    ///
+    /// All key-value pairs of `other` are added to `self`. Where both have a
+    /// value for the same key, the value in `self` is kept; BIP174 allows
+    /// either to be chosen, and this choice makes combining associative.
+    pub fn combine(&mut self, other: Self) -> Result<(), self::Error> {
         self.global.merge(other.global)?;
 
         for (self_input, other_input) in self.inputs.iter_mut().zip(other.inputs.into_iter()) {
@@ -88,6 +177,42 @@ impl PartiallySignedTransaction {
 
         Ok(())
     }
+
+    /// Attempt to merge with another `PartiallySignedTransaction`. This is the
+    /// same as [PartiallySignedTransaction::combine].
+    pub fn merge(&mut self, other: Self) -> Result<(), self::Error> {
+        self.combine(other)
+    }
+
+    /// Serialize as a hex string, which is handy for debugging. The base64
+    /// encoding of the [fmt::Display] implementation is the usual exchange format.
+    pub fn to_hex(&self) -> String {
+        encode::serialize_hex(self)
+    }
+
+    /// Deserialize from a hex string, as produced by [PartiallySignedTransaction::to_hex]
+    pub fn from_hex(s: &str) -> Result<Self, PsbtParseError> {
+        let data = Vec::<u8>::from_hex(s)?;
+        Ok(encode::deserialize(&data)?)
+    }
+}
+
+/// Formats the PSBT as base64, as specified by BIP174 and used by the `*psbt` RPCs
+impl fmt::Display for PartiallySignedTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&base64::encode(&encode::serialize(self)))
+    }
+}
+
+/// Parses a base64 PSBT. Only canonically encoded strings are accepted, and
+/// the decoded data must be a single PSBT with nothing following it.
+impl str::FromStr for PartiallySignedTransaction {
+    type Err = PsbtParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = base64::decode(s)?;
+        Ok(encode::deserialize(&data)?)
+    }
 }
 
 impl Encodable for PartiallySignedTransaction {
@@ -166,19 +291,154 @@ mod tests {
     use hash_types::Txid;
 
     use std::collections::BTreeMap;
+    use std::str::FromStr;
 
     use secp256k1::Secp256k1;
 
     use blockdata::script::Script;
-    use blockdata::transaction::{Transaction, TxIn, TxOut, OutPoint};
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{SigHashType, Sequence, Transaction, TxIn, TxOut, OutPoint};
     use network::constants::Network::Monacoin;
     use consensus::encode::{deserialize, serialize, serialize_hex};
-    use util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint};
+    use util::bip32::{ChildNumber, ExtendedPrivKey, ExtendedPubKey, Fingerprint, KeySource};
     use util::key::PublicKey;
-    use util::psbt::map::{Global, Output};
+    use util::fee_rate::FeeRate;
+    use util::psbt::map::{Global, Input, Output};
     use util::psbt::raw;
 
-    use super::PartiallySignedTransaction;
+    use super::{PartiallySignedTransaction, Error, PsbtParseError, DEFAULT_MAX_FEE_RATE};
+
+    /// A 2-of-2 P2WSH input with both signatures
+    const TWO_PARTIAL_SIGS: &'static str = "70736274ff0100890200000001207ae985d787dfe6143d5c58fad79cc7105e0e799fcf033b7f2ba17e62d7b3200000000000ffffffff02563d03000000000022002019899534b9a011043c0dd57c3ff9a381c3522c5f27c6a42319085b56ca543a1d6adc020000000000220020618b47a07ebecca4e156edb1b9ea7c24bdee0139fc049237965ffdaf56d5ee73000000000001012b801a0600000000002200201148e93e9315e37dbed2121be5239257af35adc03ffdfc5d914b083afa44dab82202025fe7371376d53cf8a2783917c28bf30bd690b0a4d4a207690093ca2b920ee076473044022007e06b362e89912abd4661f47945430739b006a85d1b2a16c01dc1a4bd07acab022061576d7aa834988b7ab94ef21d8eebd996ea59ea20529a19b15f0c9cebe3d8ac01220202b3fe93530020a8294f0e527e33fbdff184f047eb6b5a1558a352f62c29972f8a473044022002787f926d6817504431ee281183b8119b6845bfaa6befae45e13b6d430c9d2f02202859f149a6cd26ae2f03a107e7f33c7d91730dade305fe077bae677b5d44952a01010547522102b3fe93530020a8294f0e527e33fbdff184f047eb6b5a1558a352f62c29972f8a21025fe7371376d53cf8a2783917c28bf30bd690b0a4d4a207690093ca2b920ee07652ae0001014752210283ef76537f2d58ae3aa3a4bd8ae41c3f230ccadffb1a0bd3ca504d871cff05e7210353d79cc0cb1396f4ce278d005f16d948e02a6aec9ed1109f13747ecb1507b37b52ae00010147522102b3937241777b6665e0d694e52f9c1b188433641df852da6fc42187b5d8a368a321034cdd474f01cc5aa7ff834ad8bcc882a87e854affc775486bc2a9f62e8f49bd7852ae00";
+
+    #[test]
+    fn base64_and_hex_strings() {
+        // TWO_PARTIAL_SIGS, base64-encoded independently of this crate
+        let b64 = "cHNidP8BAIkCAAAAASB66YXXh9/mFD1cWPrXnMcQXg55n88DO38roX5i17MgAAAAAAD/////AlY9AwAAAAAAIgAgGYmVNLmgEQQ8DdV8P/mjgcNSLF8nxqQjGQhbVspUOh1q3AIAAAAAACIAIGGLR6B+vsyk4VbtsbnqfCS97gE5/ASSN5Zf/a9W1e5zAAAAAAABASuAGgYAAAAAACIAIBFI6T6TFeN9vtISG+UjklevNa3AP/38XZFLCDr6RNq4IgICX+c3E3bVPPiieDkXwovzC9aQsKTUogdpAJPKK5IO4HZHMEQCIAfgazYuiZEqvUZh9HlFQwc5sAaoXRsqFsAdwaS9B6yrAiBhV216qDSYi3q5TvIdjuvZlupZ6iBSmhmxXwyc6+PYrAEiAgKz/pNTACCoKU8OUn4z+9/xhPBH62taFVijUvYsKZcvikcwRAIgAnh/km1oF1BEMe4oEYO4EZtoRb+qa++uReE7bUMMnS8CIChZ8UmmzSauLwOhB+fzPH2Rcw2t4wX+B3uuZ3tdRJUqAQEFR1IhArP+k1MAIKgpTw5SfjP73/GE8Efra1oVWKNS9iwply+KIQJf5zcTdtU8+KJ4ORfCi/ML1pCwpNSiB2kAk8orkg7gdlKuAAEBR1IhAoPvdlN/LViuOqOkvYrkHD8jDMrf+xoL08pQTYcc/wXnIQNT15zAyxOW9M4njQBfFtlI4Cpq7J7REJ8TdH7LFQeze1KuAAEBR1IhArOTckF3e2Zl4NaU5S+cGxiEM2Qd+FLab8Qhh7XYo2ijIQNM3UdPAcxap/+DSti8yIKofoVK/8d1SGvCqfYuj0m9eFKuAA==";
+
+        let psbt: PartiallySignedTransaction = hex_psbt!(TWO_PARTIAL_SIGS).unwrap();
+        assert_eq!(psbt.to_string(), b64);
+        assert_eq!(PartiallySignedTransaction::from_str(b64).unwrap(), psbt);
+        assert_eq!(psbt.to_hex(), TWO_PARTIAL_SIGS);
+        assert_eq!(PartiallySignedTransaction::from_hex(TWO_PARTIAL_SIGS).unwrap(), psbt);
+
+        // an extra zero byte after the PSBT
+        let trailing = format!("{}AAA=", &b64[..b64.len() - 4]);
+        match PartiallySignedTransaction::from_str(&trailing) {
+            Err(PsbtParseError::PsbtEncoding(_)) => {},
+            other => panic!("expected trailing data to be rejected, got {:?}", other),
+        }
+        match PartiallySignedTransaction::from_hex(&format!("{}00", TWO_PARTIAL_SIGS)) {
+            Err(PsbtParseError::PsbtEncoding(_)) => {},
+            other => panic!("expected trailing data to be rejected, got {:?}", other),
+        }
+        // non-canonical or malformed encodings
+        for s in &[
+            format!("{}AB==", &b64[..b64.len() - 4]),
+            format!("{}AA", &b64[..b64.len() - 4]),
+            format!("{}\n", b64),
+            format!(" {}", b64),
+        ] {
+            match PartiallySignedTransaction::from_str(s) {
+                Err(PsbtParseError::Base64(_)) => {},
+                other => panic!("expected base64 error, got {:?}", other),
+            }
+        }
+        match PartiallySignedTransaction::from_hex("70736274ff0") {
+            Err(PsbtParseError::Hex(_)) => {},
+            other => panic!("expected hex error, got {:?}", other),
+        }
+    }
+
+    /// The last error in the `source` chain of `err`
+    fn root_cause<'a>(err: &'a (::std::error::Error + 'static)) -> &'a (::std::error::Error + 'static) {
+        match err.source() {
+            Some(source) => root_cause(source),
+            None => err,
+        }
+    }
+
+    #[test]
+    fn error_sources() {
+        // a plain transaction instead of a PSBT
+        let err = PartiallySignedTransaction::from_hex("0200000000000000000000").unwrap_err();
+        match root_cause(&err).downcast_ref::<Error>() {
+            Some(&Error::InvalidMagic) => {}
+            other => panic!("unexpected root cause {:?} of {}", other, err),
+        }
+
+        // the unsigned transaction twice
+        let unsigned_tx = &TWO_PARTIAL_SIGS[10..10 + 2 * 140];
+        let duplicate = format!("{}{}", &TWO_PARTIAL_SIGS[..10 + 2 * 140], &TWO_PARTIAL_SIGS[10..]);
+        assert_eq!(&duplicate[10 + 2 * 140..10 + 4 * 140], unsigned_tx);
+        let err = PartiallySignedTransaction::from_hex(&duplicate).unwrap_err();
+        match root_cause(&err).downcast_ref::<Error>() {
+            Some(&Error::DuplicateKey(ref key)) => assert_eq!(key.type_value, 0),
+            other => panic!("unexpected root cause {:?} of {}", other, err),
+        }
+
+        // a partial signature of a public key which is not on the curve
+        let bad_key = TWO_PARTIAL_SIGS.replace("2202025fe7", "2202045fe7");
+        assert_ne!(bad_key, TWO_PARTIAL_SIGS);
+        let err = PartiallySignedTransaction::from_hex(&bad_key).unwrap_err();
+        match err {
+            PsbtParseError::PsbtEncoding(::consensus::encode::Error::Psbt(Error::InvalidPublicKey(_))) => {}
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert!(root_cause(&err).downcast_ref::<::secp256k1::Error>().is_some());
+    }
+
+    #[test]
+    fn proprietary_keys() {
+        let vendor = |subtype: u8, key: &[u8]| raw::ProprietaryKey {
+            prefix: b"monawallet".to_vec(),
+            subtype: subtype,
+            key: key.to_vec(),
+        };
+        let mut psbt: PartiallySignedTransaction = hex_psbt!(TWO_PARTIAL_SIGS).unwrap();
+        psbt.global.proprietary.insert(vendor(0, &[]), vec![1]);
+        psbt.inputs[0].proprietary.insert(vendor(1, &[0xaa, 0xbb]), vec![2, 2]);
+        psbt.inputs[0].proprietary.insert(vendor(1, &[0xaa]), vec![]);
+        psbt.outputs[1].proprietary.insert(vendor(0xfc, &[]), vec![3]);
+
+        let ser = serialize(&psbt);
+        // 0xfc, the identifier with its length, the subtype and the key data
+        let input_key = Vec::from_hex("0ffc0a6d6f6e6177616c6c657401aabb").unwrap();
+        assert!(ser.windows(input_key.len()).any(|w| w == &input_key[..]));
+
+        let decoded: PartiallySignedTransaction = deserialize(&ser).unwrap();
+        assert_eq!(decoded, psbt);
+        assert!(decoded.global.unknown.is_empty());
+        assert!(decoded.inputs.iter().all(|input| input.unknown.is_empty()));
+        assert!(decoded.outputs.iter().all(|output| output.unknown.is_empty()));
+
+        // proprietary pairs of either side survive combining
+        let mut other: PartiallySignedTransaction = hex_psbt!(TWO_PARTIAL_SIGS).unwrap();
+        other.inputs[0].proprietary.insert(vendor(1, &[0xaa]), vec![9]);
+        other.inputs[0].proprietary.insert(vendor(2, &[]), vec![4]);
+        other.combine(psbt.clone()).unwrap();
+        assert_eq!(other.global.proprietary, psbt.global.proprietary);
+        assert_eq!(other.outputs[1].proprietary, psbt.outputs[1].proprietary);
+        assert_eq!(other.inputs[0].proprietary.len(), 3);
+        assert_eq!(other.inputs[0].proprietary[&vendor(1, &[0xaa])], vec![9]);
+
+        // a proprietary key must at least have an identifier and a subtype
+        let key = raw::Key { type_value: 0xfc, key: vec![0x02, b'm'] };
+        match raw::ProprietaryKey::from_key(key.clone()) {
+            Err(Error::InvalidProprietaryKey(k)) => assert_eq!(k, key),
+            other => panic!("expected invalid key, got {:?}", other),
+        }
+        let key = raw::Key { type_value: 0xfc, key: vec![0x00, 0x07] };
+        assert_eq!(
+            raw::ProprietaryKey::from_key(key.clone()).unwrap(),
+            raw::ProprietaryKey { prefix: vec![], subtype: 7, key: vec![] }
+        );
+        assert_eq!(raw::ProprietaryKey::from_key(key.clone()).unwrap().to_key(), key);
+        let mut ser = serialize(&psbt);
+        let pos = ser.windows(input_key.len()).position(|w| w == &input_key[..]).unwrap();
+        ser[pos + 2] = 0x0f;
+        assert!(deserialize::<PartiallySignedTransaction>(&ser).is_err());
+    }
 
     #[test]
     fn trivial_psbt() {
@@ -186,10 +446,11 @@ mod tests {
             global: Global {
                 unsigned_tx: Transaction {
                     version: 2,
-                    lock_time: 0,
+                    lock_time: LockTime::ZERO,
                     input: vec![],
                     output: vec![],
                 },
+                proprietary: BTreeMap::new(),
                 unknown: BTreeMap::new(),
             },
             inputs: vec![],
@@ -206,7 +467,7 @@ mod tests {
         let secp = &Secp256k1::new();
         let seed = Vec::from_hex("000102030405060708090a0b0c0d0e0f").unwrap();
 
-        let mut hd_keypaths: BTreeMap<PublicKey, (Fingerprint, DerivationPath)> = Default::default();
+        let mut hd_keypaths: BTreeMap<PublicKey, KeySource> = Default::default();
 
         let mut sk: ExtendedPrivKey = ExtendedPrivKey::new_master(Monacoin, &seed).unwrap();
 
@@ -243,6 +504,13 @@ mod tests {
         let actual: Output = deserialize(&serialize(&expected)).unwrap();
 
         assert_eq!(expected, actual);
+
+        let expected = Input {
+            hd_keypaths: expected.hd_keypaths,
+            ..Default::default()
+        };
+        let actual: Input = deserialize(&serialize(&expected)).unwrap();
+        assert_eq!(expected, actual);
     }
 
     #[test]
@@ -250,7 +518,7 @@ mod tests {
         let expected = Global {
             unsigned_tx: Transaction {
                 version: 2,
-                lock_time: 1257139,
+                lock_time: LockTime::from_consensus(1257139),
                 input: vec![TxIn {
                     previous_output: OutPoint {
                         txid: Txid::from_hex(
@@ -259,7 +527,7 @@ mod tests {
                         vout: 0,
                     },
                     script_sig: Script::new(),
-                    sequence: 4294967294,
+                    sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
                     witness: vec![],
                 }],
                 output: vec![
@@ -277,6 +545,7 @@ mod tests {
                     },
                 ],
             },
+            proprietary: Default::default(),
             unknown: Default::default(),
         };
 
@@ -302,19 +571,248 @@ mod tests {
 
     #[test]
     fn deserialize_and_serialize_psbt_with_two_partial_sigs() {
-        let hex = "70736274ff0100890200000001207ae985d787dfe6143d5c58fad79cc7105e0e799fcf033b7f2ba17e62d7b3200000000000ffffffff02563d03000000000022002019899534b9a011043c0dd57c3ff9a381c3522c5f27c6a42319085b56ca543a1d6adc020000000000220020618b47a07ebecca4e156edb1b9ea7c24bdee0139fc049237965ffdaf56d5ee73000000000001012b801a0600000000002200201148e93e9315e37dbed2121be5239257af35adc03ffdfc5d914b083afa44dab82202025fe7371376d53cf8a2783917c28bf30bd690b0a4d4a207690093ca2b920ee076473044022007e06b362e89912abd4661f47945430739b006a85d1b2a16c01dc1a4bd07acab022061576d7aa834988b7ab94ef21d8eebd996ea59ea20529a19b15f0c9cebe3d8ac01220202b3fe93530020a8294f0e527e33fbdff184f047eb6b5a1558a352f62c29972f8a473044022002787f926d6817504431ee281183b8119b6845bfaa6befae45e13b6d430c9d2f02202859f149a6cd26ae2f03a107e7f33c7d91730dade305fe077bae677b5d44952a01010547522102b3fe93530020a8294f0e527e33fbdff184f047eb6b5a1558a352f62c29972f8a21025fe7371376d53cf8a2783917c28bf30bd690b0a4d4a207690093ca2b920ee07652ae0001014752210283ef76537f2d58ae3aa3a4bd8ae41c3f230ccadffb1a0bd3ca504d871cff05e7210353d79cc0cb1396f4ce278d005f16d948e02a6aec9ed1109f13747ecb1507b37b52ae00010147522102b3937241777b6665e0d694e52f9c1b188433641df852da6fc42187b5d8a368a321034cdd474f01cc5aa7ff834ad8bcc882a87e854affc775486bc2a9f62e8f49bd7852ae00";
+        let hex = TWO_PARTIAL_SIGS;
         let psbt: PartiallySignedTransaction = hex_psbt!(hex).unwrap();
         assert_eq!(hex, serialize_hex(&psbt));
     }
 
+    /// A finalized PSBT spending a P2WPKH output worth `input_value` to a single output
+    fn finalized_psbt(input_value: u64, output_value: u64) -> PartiallySignedTransaction {
+        let unsigned_tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_hex("f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126").unwrap(),
+                    vout: 0,
+                },
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: output_value,
+                script_pubkey: hex_script!("0014aef3890b85e63ceeb2f6d59522aa73e358b3fc3c"),
+            }],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: input_value,
+            script_pubkey: hex_script!("0014f25e193af88c84263dd5e707ba1cb47e91f629a8"),
+        });
+        psbt.inputs[0].final_script_witness = Some(vec![vec![0x30; 72], vec![0x02; 33]]);
+        psbt
+    }
+
+    #[test]
+    fn fee() {
+        let psbt = finalized_psbt(100_000_000, 99_990_000);
+        assert_eq!(psbt.fee().unwrap(), 10_000);
+
+        // non-witness UTXOs are looked up by the spent output index
+        let mut psbt = finalized_psbt(100_000_000, 99_990_000);
+        let mut prev_tx = psbt.global.unsigned_tx.clone();
+        prev_tx.output.push(psbt.inputs[0].witness_utxo.take().unwrap());
+        prev_tx.output[0].value = 1;
+        psbt.global.unsigned_tx.input[0].previous_output.vout = 1;
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx);
+        assert_eq!(psbt.fee().unwrap(), 10_000);
+        psbt.global.unsigned_tx.input[0].previous_output.vout = 2;
+        match psbt.fee() {
+            Err(Error::MissingUtxo { index: 0 }) => {},
+            e => panic!("unexpected result: {:?}", e),
+        }
+
+        let mut psbt = finalized_psbt(100_000_000, 99_990_000);
+        psbt.inputs[0].witness_utxo = None;
+        match psbt.fee() {
+            Err(Error::MissingUtxo { index: 0 }) => {},
+            e => panic!("unexpected result: {:?}", e),
+        }
+
+        match finalized_psbt(100_000_000, 100_000_001).fee() {
+            Err(Error::NegativeFee) => {},
+            e => panic!("unexpected result: {:?}", e),
+        }
+
+        let mut psbt = finalized_psbt(100_000_000, 1);
+        psbt.global.unsigned_tx.output.push(TxOut { value: u64::max_value(), script_pubkey: Script::new() });
+        psbt.outputs.push(Default::default());
+        match psbt.fee() {
+            Err(Error::FeeOverflow) => {},
+            e => panic!("unexpected result: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn extract_tx() {
+        let psbt = finalized_psbt(100_000_000, 99_990_000);
+        let tx = psbt.clone().extract_tx().unwrap();
+        assert_eq!(tx.input[0].witness, psbt.inputs[0].final_script_witness.clone().unwrap());
+        assert_eq!(tx.txid(), psbt.global.unsigned_tx.txid());
+
+        let mut unfinalized = psbt.clone();
+        unfinalized.inputs[0].final_script_witness = None;
+        match unfinalized.clone().extract_tx_unchecked_fee_rate() {
+            Err(Error::InputNotFinalized { index: 0 }) => {},
+            e => panic!("unexpected result: {:?}", e),
+        }
+        // a final scriptSig on its own is enough
+        unfinalized.inputs[0].final_script_sig = Some(hex_script!("00"));
+        assert!(unfinalized.extract_tx().is_ok());
+    }
+
+    #[test]
+    fn extract_tx_absurd_fee() {
+        // a sane fee of ~10 sat/vB...
+        let sane = finalized_psbt(100_000_000, 99_998_900);
+        let tx = sane.clone().extract_tx().unwrap();
+        assert_eq!(tx.get_weight(), 438);
+        // ...and the same transaction paying 1000 times as much
+        let absurd = finalized_psbt(100_000_000, 98_900_000);
+        match absurd.clone().extract_tx() {
+            Err(Error::AbsurdFeeRate { fee_rate, max_fee_rate }) => {
+                assert_eq!(fee_rate, FeeRate::from_sat_per_kwu(2_511_415));
+                assert_eq!(max_fee_rate, DEFAULT_MAX_FEE_RATE);
+            }
+            e => panic!("unexpected result: {:?}", e),
+        }
+
+        assert!(absurd.clone().extract_tx_unchecked_fee_rate().is_ok());
+        assert!(absurd.clone().extract_tx_with_fee_rate_limit(FeeRate::from_sat_per_kwu(2_511_415)).is_ok());
+        match sane.extract_tx_with_fee_rate_limit(FeeRate::from_sat_per_vb(1).unwrap()) {
+            Err(Error::AbsurdFeeRate { .. }) => {},
+            e => panic!("unexpected result: {:?}", e),
+        }
+
+        // the fee rate can't be checked without the UTXOs
+        let mut no_utxo = absurd;
+        no_utxo.inputs[0].witness_utxo = None;
+        assert!(no_utxo.clone().extract_tx().is_err());
+        assert!(no_utxo.extract_tx_unchecked_fee_rate().is_ok());
+    }
+
+    #[test]
+    fn combine_partial_sigs() {
+        let full: PartiallySignedTransaction = hex_psbt!(TWO_PARTIAL_SIGS).unwrap();
+        let keys: Vec<PublicKey> = full.inputs[0].partial_sigs.keys().cloned().collect();
+        assert_eq!(keys.len(), 2);
+
+        // each signer returns the PSBT with only their own signature
+        let mut first = full.clone();
+        first.inputs[0].partial_sigs.remove(&keys[1]);
+        let mut second = full.clone();
+        second.inputs[0].partial_sigs.remove(&keys[0]);
+        second.inputs[0].witness_script = None;
+
+        let mut combined = first.clone();
+        combined.combine(second.clone()).unwrap();
+        assert_eq!(serialize_hex(&combined), TWO_PARTIAL_SIGS);
+
+        let mut combined = second;
+        combined.merge(first).unwrap();
+        assert_eq!(serialize_hex(&combined), TWO_PARTIAL_SIGS);
+    }
+
+    #[test]
+    fn combine_keeps_existing_values() {
+        let key = raw::Key { type_value: 0xf0, key: vec![1] };
+        let mut a: PartiallySignedTransaction = hex_psbt!(TWO_PARTIAL_SIGS).unwrap();
+        let mut b = a.clone();
+        a.inputs[0].unknown.insert(key.clone(), vec![0xaa]);
+        b.inputs[0].unknown.insert(key.clone(), vec![0xbb]);
+        a.outputs[0].witness_script = None;
+        b.outputs[0].witness_script = Some(hex_script!("51"));
+
+        a.combine(b).unwrap();
+        assert_eq!(a.inputs[0].unknown[&key], vec![0xaa]);
+        assert_eq!(a.outputs[0].witness_script, Some(hex_script!("51")));
+    }
+
+    #[test]
+    fn combine_different_unsigned_tx() {
+        let mut a: PartiallySignedTransaction = hex_psbt!(TWO_PARTIAL_SIGS).unwrap();
+        let mut b = a.clone();
+        b.global.unsigned_tx.lock_time = LockTime::from_consensus(1);
+        match a.combine(b) {
+            Err(Error::UnexpectedUnsignedTx { .. }) => {},
+            e => panic!("unexpected result: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn combine_is_associative() {
+        let full: PartiallySignedTransaction = hex_psbt!(TWO_PARTIAL_SIGS).unwrap();
+
+        // xorshift, so the test is deterministic
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut rand = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        // a random subset of the fields of `full`, plus random unknown pairs
+        // drawn from a small key space so that values conflict
+        let mut arbitrary_psbt = move || {
+            let mut psbt = full.clone();
+            let mut bits = rand();
+            let mut coin = || { bits >>= 1; bits & 1 == 1 };
+            {
+                let input = &mut psbt.inputs[0];
+                let keys: Vec<PublicKey> = input.partial_sigs.keys().cloned().collect();
+                for key in keys {
+                    if coin() { input.partial_sigs.remove(&key); }
+                }
+                if coin() { input.witness_utxo = None; }
+                if coin() { input.non_witness_utxo = Some(full.global.unsigned_tx.clone()); }
+                if coin() { input.witness_script = None; }
+                if coin() { input.redeem_script = Some(hex_script!("51")); }
+                if coin() { input.sighash_type = Some(SigHashType::All); }
+                if coin() { input.sighash_type = Some(SigHashType::Single); }
+            }
+            for output in psbt.outputs.iter_mut() {
+                if coin() { output.witness_script = None; }
+            }
+            for _ in 0..3 {
+                let key = raw::Key { type_value: 0xf0, key: vec![(rand() % 4) as u8] };
+                let value = vec![(rand() % 4) as u8];
+                match rand() % 3 {
+                    0 => { psbt.global.unknown.insert(key, value); }
+                    1 => { psbt.inputs[0].unknown.insert(key, value); }
+                    _ => { psbt.outputs[1].unknown.insert(key, value); }
+                }
+            }
+            psbt
+        };
+
+        for _ in 0..200 {
+            let (a, b, c) = (arbitrary_psbt(), arbitrary_psbt(), arbitrary_psbt());
+
+            let mut left = a.clone();
+            left.combine(b.clone()).unwrap();
+            left.combine(c.clone()).unwrap();
+
+            let mut right = b;
+            right.combine(c).unwrap();
+            let mut a = a;
+            a.combine(right).unwrap();
+
+            assert_eq!(left, a);
+        }
+    }
+
     mod bip_vectors {
         use std::collections::BTreeMap;
+        use std::str::FromStr;
 
         use hashes::hex::FromHex;
         use hash_types::Txid;
 
         use blockdata::script::Script;
-        use blockdata::transaction::{SigHashType, Transaction, TxIn, TxOut, OutPoint};
+        use blockdata::locktime::LockTime;
+        use blockdata::transaction::{SigHashType, Sequence, Transaction, TxIn, TxOut, OutPoint};
         use consensus::encode::serialize_hex;
         use util::psbt::map::{Map, Global, Input, Output};
         use util::psbt::raw;
@@ -356,7 +854,7 @@ mod tests {
                 global: Global {
                     unsigned_tx: Transaction {
                         version: 2,
-                        lock_time: 1257139,
+                        lock_time: LockTime::from_consensus(1257139),
                         input: vec![TxIn {
                             previous_output: OutPoint {
                                 txid: Txid::from_hex(
@@ -365,7 +863,7 @@ mod tests {
                                 vout: 0,
                             },
                             script_sig: Script::new(),
-                            sequence: 4294967294,
+                            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
                             witness: vec![],
                         }],
                         output: vec![
@@ -379,12 +877,13 @@ mod tests {
                             },
                         ],
                     },
+                    proprietary: BTreeMap::new(),
                     unknown: BTreeMap::new(),
                 },
                 inputs: vec![Input {
                     non_witness_utxo: Some(Transaction {
                         version: 1,
-                        lock_time: 0,
+                        lock_time: LockTime::ZERO,
                         input: vec![TxIn {
                             previous_output: OutPoint {
                                 txid: Txid::from_hex(
@@ -393,7 +892,7 @@ mod tests {
                                 vout: 1,
                             },
                             script_sig: hex_script!("160014be18d152a9b012039daf3da7de4f53349eecb985"),
-                            sequence: 4294967295,
+                            sequence: Sequence::MAX,
                             witness: vec![
                                 Vec::from_hex("304402202712be22e0270f394f568311dc7ca9a68970b8025fdd3b240229f07f8a5f3a240220018b38d7dcd314e734c9276bd6fb40f673325bc4baa144c800d2f2f02db2765c01").unwrap(),
                                 Vec::from_hex("03d2e15674941bad4a996372cb87e1856d3652606d98562fe39c5e9e7e413f2105").unwrap(),
@@ -407,7 +906,7 @@ mod tests {
                                 vout: 1,
                             },
                             script_sig: hex_script!("160014fe3e9ef1a745e974d902c4355943abcb34bd5353"),
-                            sequence: 4294967295,
+                            sequence: Sequence::MAX,
                             witness: vec![
                                 Vec::from_hex("3045022100d12b852d85dcd961d2f5f4ab660654df6eedcc794c0c33ce5cc309ffb5fce58d022067338a8e0e1725c197fb1a88af59f51e44e4255b20167c8684031c05d1f2592a01").unwrap(),
                                 Vec::from_hex("0223b72beef0965d10be0778efecd61fcac6f79a4ea169393380734464f84f2ab3").unwrap(),
@@ -560,5 +1059,22 @@ mod tests {
 
             assert_eq!(psbt.inputs[0].unknown, unknown)
         }
+
+        #[test]
+        fn combiner_vector() {
+            // the PSBTs of the two Signers, each with the partial signatures
+            // made with its own keys, and the result of the Combiner
+            let signer_1 = PartiallySignedTransaction::from_str("cHNidP8BAJoCAAAAAljoeiG1ba8MI76OcHBFbDNvfLqlyHV5JPVFiHuyq911AAAAAAD/////g40EJ9DsZQpoqka7CwmK6kQiwHGyyng1Kgd5WdB86h0BAAAAAP////8CcKrwCAAAAAAWABTYXCtx0AYLCcmIauuBXlCZHdoSTQDh9QUAAAAAFgAUAK6pouXw+HaliN9VRuh0LR2HAI8AAAAAAAEAuwIAAAABqtc5MQGL0l+ErkALaISL4J23BurCrBgpi6vucatlb4sAAAAASEcwRAIgWPb8fGoz4bMVSNSByCbAFb0wE1qtQs1neQ2rZtKtJDsCIEoc7SYExnNbY5PltBaR3XiwDwxZQvufdRhW+qk4FX26Af7///8CgPD6AgAAAAAXqRQPuUY0IWlrgsgzryQceMF9295JNIfQ8gonAQAAABepFCnKdPigj4GZlCgYXJe12FLkBj9hh2UAAAAiAgKVg785rgpgl0etGZrd1jT6YQhVnWxc05tMIYPxq5bgf0cwRAIgdAGK1BgAl7hzMjwAFXILNoTMgSOJEEjn282bVa1nnJkCIHPTabdA4+tT3O+jOCPIBwUUylWn3ZVE8VfBZ5EyYRGMAQEDBAEAAAABBEdSIQKVg785rgpgl0etGZrd1jT6YQhVnWxc05tMIYPxq5bgfyEC2rYf9JoU22p9ArDNH7t4/EsYMStbTlTa5Nui+/71NtdSriIGApWDvzmuCmCXR60Zmt3WNPphCFWdbFzTm0whg/GrluB/ENkMak8AAACAAAAAgAAAAIAiBgLath/0mhTban0CsM0fu3j8SxgxK1tOVNrk26L7/vU21xDZDGpPAAAAgAAAAIABAACAAAEBIADC6wsAAAAAF6kUt/X69A49QKWkWbHbNTXyty+pIeiHIgIDCJ3BDHrG21T5EymvYXMz2ziM6tDCMfcjN50bmQMLAtxHMEQCIGLrelVhB6fHP0WsSrWh3d9vcHX7EnWWmn84Pv/3hLyyAiAMBdu3Rw2/LwhVfdNWxzJcHtMJE+mWzThAlF2xIijaXwEBAwQBAAAAAQQiACCMI1MXN0O1ld+0oHtyuo5C43l9p06H/n2ddJfjsgKJAwEFR1IhAwidwQx6xttU+RMpr2FzM9s4jOrQwjH3IzedG5kDCwLcIQI63ZBPPW3PWd25BrDe4jUpt/+57VDl6GFRkmhgIh8Oc1KuIgYCOt2QTz1tz1nduQaw3uI1Kbf/ue1Q5ehhUZJoYCIfDnMQ2QxqTwAAAIAAAACAAwAAgCIGAwidwQx6xttU+RMpr2FzM9s4jOrQwjH3IzedG5kDCwLcENkMak8AAACAAAAAgAIAAIAAIgIDqaTDf1mW06ol26xrVwrwZQOUSSlCRgs1R1Ptnuylh3EQ2QxqTwAAAIAAAACABAAAgAAiAgJ/Y5l1fS7/VaE2rQLGhLGDi2VW5fG2s0KCqUtrUAUQlhDZDGpPAAAAgAAAAIAFAACAAA==").unwrap();
+            let signer_2 = PartiallySignedTransaction::from_str("cHNidP8BAJoCAAAAAljoeiG1ba8MI76OcHBFbDNvfLqlyHV5JPVFiHuyq911AAAAAAD/////g40EJ9DsZQpoqka7CwmK6kQiwHGyyng1Kgd5WdB86h0BAAAAAP////8CcKrwCAAAAAAWABTYXCtx0AYLCcmIauuBXlCZHdoSTQDh9QUAAAAAFgAUAK6pouXw+HaliN9VRuh0LR2HAI8AAAAAAAEAuwIAAAABqtc5MQGL0l+ErkALaISL4J23BurCrBgpi6vucatlb4sAAAAASEcwRAIgWPb8fGoz4bMVSNSByCbAFb0wE1qtQs1neQ2rZtKtJDsCIEoc7SYExnNbY5PltBaR3XiwDwxZQvufdRhW+qk4FX26Af7///8CgPD6AgAAAAAXqRQPuUY0IWlrgsgzryQceMF9295JNIfQ8gonAQAAABepFCnKdPigj4GZlCgYXJe12FLkBj9hh2UAAAAiAgLath/0mhTban0CsM0fu3j8SxgxK1tOVNrk26L7/vU210gwRQIhAPYQOLMI3B2oZaNIUnRvAVdyk0IIxtJEVDk82ZvfIhd3AiAFbmdaZ1ptCgK4WxTl4pB02KJam1dgvqKBb2YZEKAG6gEBAwQBAAAAAQRHUiEClYO/Oa4KYJdHrRma3dY0+mEIVZ1sXNObTCGD8auW4H8hAtq2H/SaFNtqfQKwzR+7ePxLGDErW05U2uTbovv+9TbXUq4iBgKVg785rgpgl0etGZrd1jT6YQhVnWxc05tMIYPxq5bgfxDZDGpPAAAAgAAAAIAAAACAIgYC2rYf9JoU22p9ArDNH7t4/EsYMStbTlTa5Nui+/71NtcQ2QxqTwAAAIAAAACAAQAAgAABASAAwusLAAAAABepFLf1+vQOPUClpFmx2zU18rcvqSHohyICAjrdkE89bc9Z3bkGsN7iNSm3/7ntUOXoYVGSaGAiHw5zRzBEAiBl9FulmYtZon/+GnvtAWrx8fkNVLOqj3RQql9WolEDvQIgf3JHA60e25ZoCyhLVtT/y4j3+3Weq74IqjDym4UTg9IBAQMEAQAAAAEEIgAgjCNTFzdDtZXftKB7crqOQuN5fadOh/59nXSX47ICiQMBBUdSIQMIncEMesbbVPkTKa9hczPbOIzq0MIx9yM3nRuZAwsC3CECOt2QTz1tz1nduQaw3uI1Kbf/ue1Q5ehhUZJoYCIfDnNSriIGAjrdkE89bc9Z3bkGsN7iNSm3/7ntUOXoYVGSaGAiHw5zENkMak8AAACAAAAAgAMAAIAiBgMIncEMesbbVPkTKa9hczPbOIzq0MIx9yM3nRuZAwsC3BDZDGpPAAAAgAAAAIACAACAACICA6mkw39ZltOqJdusa1cK8GUDlEkpQkYLNUdT7Z7spYdxENkMak8AAACAAAAAgAQAAIAAIgICf2OZdX0u/1WhNq0CxoSxg4tlVuXxtrNCgqlLa1AFEJYQ2QxqTwAAAIAAAACABQAAgAA=").unwrap();
+            let expected = "cHNidP8BAJoCAAAAAljoeiG1ba8MI76OcHBFbDNvfLqlyHV5JPVFiHuyq911AAAAAAD/////g40EJ9DsZQpoqka7CwmK6kQiwHGyyng1Kgd5WdB86h0BAAAAAP////8CcKrwCAAAAAAWABTYXCtx0AYLCcmIauuBXlCZHdoSTQDh9QUAAAAAFgAUAK6pouXw+HaliN9VRuh0LR2HAI8AAAAAAAEAuwIAAAABqtc5MQGL0l+ErkALaISL4J23BurCrBgpi6vucatlb4sAAAAASEcwRAIgWPb8fGoz4bMVSNSByCbAFb0wE1qtQs1neQ2rZtKtJDsCIEoc7SYExnNbY5PltBaR3XiwDwxZQvufdRhW+qk4FX26Af7///8CgPD6AgAAAAAXqRQPuUY0IWlrgsgzryQceMF9295JNIfQ8gonAQAAABepFCnKdPigj4GZlCgYXJe12FLkBj9hh2UAAAAiAgKVg785rgpgl0etGZrd1jT6YQhVnWxc05tMIYPxq5bgf0cwRAIgdAGK1BgAl7hzMjwAFXILNoTMgSOJEEjn282bVa1nnJkCIHPTabdA4+tT3O+jOCPIBwUUylWn3ZVE8VfBZ5EyYRGMASICAtq2H/SaFNtqfQKwzR+7ePxLGDErW05U2uTbovv+9TbXSDBFAiEA9hA4swjcHahlo0hSdG8BV3KTQgjG0kRUOTzZm98iF3cCIAVuZ1pnWm0KArhbFOXikHTYolqbV2C+ooFvZhkQoAbqAQEDBAEAAAABBEdSIQKVg785rgpgl0etGZrd1jT6YQhVnWxc05tMIYPxq5bgfyEC2rYf9JoU22p9ArDNH7t4/EsYMStbTlTa5Nui+/71NtdSriIGApWDvzmuCmCXR60Zmt3WNPphCFWdbFzTm0whg/GrluB/ENkMak8AAACAAAAAgAAAAIAiBgLath/0mhTban0CsM0fu3j8SxgxK1tOVNrk26L7/vU21xDZDGpPAAAAgAAAAIABAACAAAEBIADC6wsAAAAAF6kUt/X69A49QKWkWbHbNTXyty+pIeiHIgIDCJ3BDHrG21T5EymvYXMz2ziM6tDCMfcjN50bmQMLAtxHMEQCIGLrelVhB6fHP0WsSrWh3d9vcHX7EnWWmn84Pv/3hLyyAiAMBdu3Rw2/LwhVfdNWxzJcHtMJE+mWzThAlF2xIijaXwEiAgI63ZBPPW3PWd25BrDe4jUpt/+57VDl6GFRkmhgIh8Oc0cwRAIgZfRbpZmLWaJ//hp77QFq8fH5DVSzqo90UKpfVqJRA70CIH9yRwOtHtuWaAsoS1bU/8uI9/t1nqu+CKow8puFE4PSAQEDBAEAAAABBCIAIIwjUxc3Q7WV37Sge3K6jkLjeX2nTof+fZ10l+OyAokDAQVHUiEDCJ3BDHrG21T5EymvYXMz2ziM6tDCMfcjN50bmQMLAtwhAjrdkE89bc9Z3bkGsN7iNSm3/7ntUOXoYVGSaGAiHw5zUq4iBgI63ZBPPW3PWd25BrDe4jUpt/+57VDl6GFRkmhgIh8OcxDZDGpPAAAAgAAAAIADAACAIgYDCJ3BDHrG21T5EymvYXMz2ziM6tDCMfcjN50bmQMLAtwQ2QxqTwAAAIAAAACAAgAAgAAiAgOppMN/WZbTqiXbrGtXCvBlA5RJKUJGCzVHU+2e7KWHcRDZDGpPAAAAgAAAAIAEAACAACICAn9jmXV9Lv9VoTatAsaEsYOLZVbl8bazQoKpS2tQBRCWENkMak8AAACAAAAAgAUAAIAA";
+
+            let mut combined = signer_1.clone();
+            combined.combine(signer_2.clone()).unwrap();
+            assert_eq!(combined.to_string(), expected);
+            // in either order
+            let mut combined = signer_2;
+            combined.combine(signer_1).unwrap();
+            assert_eq!(combined.to_string(), expected);
+        }
     }
 }
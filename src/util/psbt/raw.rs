@@ -17,9 +17,11 @@
 //! Raw PSBT key-value pairs as defined at
 //! https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki.
 
-use std::{fmt, io};
 
-use consensus::encode::{self, Decodable, Encodable, VarInt, MAX_VEC_SIZE};
+use std::fmt;
+use std::io;
+
+use consensus::encode::{self, Decodable, Encodable, MAX_VEC_SIZE};
 use hashes::hex::ToHex;
 use util::psbt::Error;
 
@@ -41,6 +43,56 @@ pub struct Pair {
     pub value: Vec<u8>,
 }
 
+/// A BIP174 proprietary key, the key of a pair with type `0xFC`: a
+/// compact-size prefixed identifier, usually naming the vendor, followed by
+/// a vendor defined subtype and key data.
+#[derive(Debug, PartialEq, Hash, Eq, Clone, Ord, PartialOrd)]
+pub struct ProprietaryKey {
+    /// The identifier prefix
+    pub prefix: Vec<u8>,
+    /// The subtype, whose meaning is defined by the owner of the prefix
+    pub subtype: u8,
+    /// The rest of the key data
+    pub key: Vec<u8>,
+}
+
+impl ProprietaryKey {
+    /// The type value of proprietary keys, the same in every PSBT map
+    pub const TYPE_VALUE: u8 = 0xFC;
+
+    /// Parse the proprietary key out of a raw key of type `0xFC`
+    pub fn from_key(key: Key) -> Result<ProprietaryKey, Error> {
+        if key.type_value != ProprietaryKey::TYPE_VALUE {
+            return Err(Error::InvalidProprietaryKey(key));
+        }
+        let parsed = {
+            let mut d = &key.key[..];
+            let prefix: Result<Vec<u8>, encode::Error> = Decodable::consensus_decode(&mut d);
+            let subtype: Result<u8, encode::Error> = Decodable::consensus_decode(&mut d);
+            match (prefix, subtype) {
+                (Ok(prefix), Ok(subtype)) => Some(ProprietaryKey {
+                    prefix: prefix,
+                    subtype: subtype,
+                    key: d.to_vec(),
+                }),
+                _ => None,
+            }
+        };
+        parsed.ok_or(Error::InvalidProprietaryKey(key))
+    }
+
+    /// Serialize into a raw key of type `0xFC`
+    pub fn to_key(&self) -> Key {
+        let mut key = encode::serialize(&self.prefix);
+        key.push(self.subtype);
+        key.extend_from_slice(&self.key);
+        Key {
+            type_value: ProprietaryKey::TYPE_VALUE,
+            key: key,
+        }
+    }
+}
+
 impl fmt::Display for Key {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -54,7 +106,7 @@ impl fmt::Display for Key {
 
 impl Decodable for Key {
     fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
-        let VarInt(byte_size): VarInt = Decodable::consensus_decode(&mut d)?;
+        let byte_size = encode::read_compact_size(&mut d)?;
 
         if byte_size == 0 {
             return Err(Error::NoMorePairs.into());
@@ -71,7 +123,7 @@ impl Decodable for Key {
 
         let type_value: u8 = Decodable::consensus_decode(&mut d)?;
 
-        let mut key = Vec::with_capacity(key_byte_size as usize);
+        let mut key = Vec::with_capacity(encode::prealloc_capacity::<u8>(key_byte_size));
         for _ in 0..key_byte_size {
             key.push(Decodable::consensus_decode(&mut d)?);
         }
@@ -89,7 +141,7 @@ impl Encodable for Key {
         mut s: S,
     ) -> Result<usize, encode::Error> {
         let mut len = 0;
-        len += VarInt((self.key.len() + 1) as u64).consensus_encode(&mut s)?;
+        len += encode::write_compact_size(&mut s, (self.key.len() + 1) as u64)?;
 
         len += self.type_value.consensus_encode(&mut s)?;
 
@@ -17,12 +17,14 @@
 //! Defines traits used for (de)serializing PSBT values into/from raw
 //! bytes in PSBT key-value pairs.
 
+
 use std::io;
 
 use blockdata::script::Script;
 use blockdata::transaction::{SigHashType, Transaction, TxOut};
 use consensus::encode::{self, serialize, Decodable};
-use util::bip32::{ChildNumber, DerivationPath, Fingerprint};
+use util::bip32::{ChildNumber, Fingerprint, KeySource};
+use util::ecdsa::{EcdsaSig, EcdsaSigError};
 use util::key::PublicKey;
 use util::psbt;
 
@@ -66,11 +68,11 @@ impl Serialize for PublicKey {
 impl Deserialize for PublicKey {
     fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
         PublicKey::from_slice(bytes)
-            .map_err(|_| encode::Error::ParseFailed("invalid public key"))
+            .map_err(|e| psbt::Error::InvalidPublicKey(e).into())
     }
 }
 
-impl Serialize for (Fingerprint, DerivationPath) {
+impl Serialize for KeySource {
     fn serialize(&self) -> Vec<u8> {
         let mut rv: Vec<u8> = Vec::with_capacity(4 + 4 * (self.1).as_ref().len());
 
@@ -84,7 +86,7 @@ impl Serialize for (Fingerprint, DerivationPath) {
     }
 }
 
-impl Deserialize for (Fingerprint, DerivationPath) {
+impl Deserialize for KeySource {
     fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
         if bytes.len() < 4 {
             return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
@@ -105,7 +107,6 @@ impl Deserialize for (Fingerprint, DerivationPath) {
     }
 }
 
-// partial sigs
 impl Serialize for Vec<u8> {
     fn serialize(&self) -> Vec<u8> {
         self.clone()
@@ -118,21 +119,38 @@ impl Deserialize for Vec<u8> {
     }
 }
 
+// partial sigs
+impl Serialize for EcdsaSig {
+    fn serialize(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl Deserialize for EcdsaSig {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        // According to BIP 174 the signature must be "as would be pushed to the
+        // stack from a scriptSig or witness", so it is parsed with the same
+        // strict rules Core applies to standard transactions.
+        EcdsaSig::from_slice(bytes).map_err(|e| match e {
+            EcdsaSigError::NonStandardSigHashType(flag) => {
+                psbt::Error::NonStandardSigHashType(flag).into()
+            }
+            e => psbt::Error::InvalidSignature(e).into(),
+        })
+    }
+}
+
 impl Serialize for SigHashType {
     fn serialize(&self) -> Vec<u8> {
-        serialize(&self.as_u32())
+        serialize(&self.to_u32())
     }
 }
 
 impl Deserialize for SigHashType {
     fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
         let raw: u32 = encode::deserialize(bytes)?;
-        let rv: SigHashType = SigHashType::from_u32(raw);
-
-        if rv.as_u32() == raw {
-            Ok(rv)
-        } else {
-            Err(psbt::Error::NonStandardSigHashType(raw).into())
-        }
+        SigHashType::from_u32_standard(raw).map_err(|_| {
+            psbt::Error::NonStandardSigHashType(raw).into()
+        })
     }
 }
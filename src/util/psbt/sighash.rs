@@ -0,0 +1,380 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Signature Hashes
+//!
+//! Computation of the message signed for a PSBT input, with the signature
+//! hash version and script code determined from the input's UTXO
+//! information and scripts.
+
+use std::fmt;
+use std::error;
+
+use secp256k1::Message;
+
+use blockdata::transaction::{SigHashType, Transaction, TxOut};
+use hash_types::SigHash;
+use util::psbt::{Input, InputError, PartiallySignedTransaction};
+use util::psbt::finalizer::{spent_output, SigVersion};
+use util::sighash::{self, SighashCache};
+
+/// Ways that computing the signature hash of a PSBT input might fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SighashError {
+    /// The input's UTXO information or scripts are inconsistent, see
+    /// [PartiallySignedTransaction::validate_input]. This includes redeem
+    /// and witness scripts which conflict with the spent output.
+    InvalidInput(InputError),
+    /// The input has neither a witness nor a non-witness UTXO.
+    MissingUtxo,
+    /// The spent output is P2SH but the input has no redeem script.
+    MissingRedeemScript,
+    /// The spent output is P2WSH but the input has no witness script.
+    MissingWitnessScript,
+    /// The spent output is a witness program of an unsupported version.
+    UnsupportedScript,
+    /// The signature hash could not be computed.
+    SigHash(sighash::Error),
+}
+
+impl fmt::Display for SighashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SighashError::InvalidInput(ref e) => write!(f, "invalid input: {}", e),
+            SighashError::MissingUtxo => f.write_str("the input has no UTXO information"),
+            SighashError::MissingRedeemScript => f.write_str("missing redeem script"),
+            SighashError::MissingWitnessScript => f.write_str("missing witness script"),
+            SighashError::UnsupportedScript => f.write_str("unsupported witness version"),
+            SighashError::SigHash(ref e) => write!(f, "sighash error: {}", e),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for SighashError {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            SighashError::InvalidInput(ref e) => Some(e),
+            SighashError::SigHash(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+#[doc(hidden)]
+impl From<InputError> for SighashError {
+    fn from(e: InputError) -> SighashError {
+        SighashError::InvalidInput(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<sighash::Error> for SighashError {
+    fn from(e: sighash::Error) -> SighashError {
+        SighashError::SigHash(e)
+    }
+}
+
+/// The message signed for a PSBT input, see
+/// [PartiallySignedTransaction::sighash_msg].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PsbtSighashMsg {
+    /// A legacy signature hash
+    Legacy {
+        /// The signature hash
+        sighash: SigHash,
+        /// The sighash type committed to
+        hash_ty: SigHashType,
+    },
+    /// A BIP143 signature hash
+    WitnessV0 {
+        /// The signature hash
+        sighash: SigHash,
+        /// The sighash type committed to
+        hash_ty: SigHashType,
+    },
+}
+
+impl PsbtSighashMsg {
+    /// The signature hash.
+    pub fn sighash(&self) -> SigHash {
+        match *self {
+            PsbtSighashMsg::Legacy { sighash, .. } | PsbtSighashMsg::WitnessV0 { sighash, .. } => sighash,
+        }
+    }
+
+    /// The sighash type committed to, which the signature has to carry.
+    pub fn hash_ty(&self) -> SigHashType {
+        match *self {
+            PsbtSighashMsg::Legacy { hash_ty, .. } | PsbtSighashMsg::WitnessV0 { hash_ty, .. } => hash_ty,
+        }
+    }
+
+    /// The message to sign with ECDSA.
+    pub fn to_secp_msg(&self) -> Message {
+        Message::from_slice(&self.sighash()[..]).expect("32 bytes")
+    }
+}
+
+impl PartiallySignedTransaction {
+    /// The message to sign for the input at `input_index`, committing to the
+    /// input's sighash type, [SigHashType::All] if it has none.
+    ///
+    /// The signature hash version and script code follow from the spent
+    /// output and the input's scripts:
+    ///
+    /// - P2WPKH, native or P2SH-wrapped: BIP143, with the P2PKH script of the
+    ///   key hash as script code,
+    /// - P2WSH, native or P2SH-wrapped: BIP143, with the witness script,
+    /// - other P2SH outputs: legacy, with the redeem script,
+    /// - any other output which isn't a witness program: legacy, with the
+    ///   script pubkey.
+    ///
+    /// Other witness programs, such as taproot outputs, are not supported.
+    /// Neither are legacy inputs with SIGHASH_SINGLE and no output of the
+    /// same index: their signature hash is the constant 1 and must never be
+    /// signed. The input is checked with [PartiallySignedTransaction::validate_input]
+    /// first. `cache` has to be a cache of this PSBT's unsigned transaction;
+    /// using the same one for all inputs saves hashing the parts of the
+    /// transaction which all BIP143 signature hashes commit to.
+    pub fn sighash_msg(
+        &self,
+        input_index: usize,
+        cache: &mut SighashCache<&Transaction>,
+    ) -> Result<PsbtSighashMsg, SighashError> {
+        self.validate_input(input_index)?;
+        self.sighash_msg_unchecked(input_index, cache)
+    }
+
+    /// [PartiallySignedTransaction::sighash_msg] without checking the input
+    /// with [PartiallySignedTransaction::validate_input] first.
+    pub(super) fn sighash_msg_unchecked(
+        &self,
+        input_index: usize,
+        cache: &mut SighashCache<&Transaction>,
+    ) -> Result<PsbtSighashMsg, SighashError> {
+        let input = self.inputs.get(input_index).ok_or(InputError::InputIndexOutOfBounds {
+            index: input_index,
+            inputs: self.inputs.len(),
+        })?;
+        let spent = spent_output(self, input_index).ok_or(SighashError::MissingUtxo)?;
+        let hash_ty = input.sighash_type.unwrap_or(SigHashType::All);
+        let sig_version = sig_version(input, spent)?;
+        if let SigVersion::Legacy(_) = sig_version {
+            // the hash would be the constant 1, and a signature of it spends
+            // any output of the key
+            let outputs = self.global.unsigned_tx.output.len();
+            if hash_ty.split_anyonecanpay_flag().0 == SigHashType::Single && input_index >= outputs {
+                return Err(sighash::Error::SingleWithoutCorrespondingOutput {
+                    index: input_index,
+                    outputs_size: outputs,
+                }.into());
+            }
+        }
+        let sighash = sig_version.sighash(cache, input_index, hash_ty)?;
+        Ok(match sig_version {
+            SigVersion::Legacy(_) => PsbtSighashMsg::Legacy { sighash: sighash, hash_ty: hash_ty },
+            SigVersion::WitnessV0(..) => PsbtSighashMsg::WitnessV0 { sighash: sighash, hash_ty: hash_ty },
+        })
+    }
+}
+
+/// How the signatures of `input`, which spends `spent`, are computed
+fn sig_version<'a>(input: &'a Input, spent: &'a TxOut) -> Result<SigVersion<'a>, SighashError> {
+    let script = if spent.script_pubkey.is_p2sh() {
+        input.redeem_script.as_ref().ok_or(SighashError::MissingRedeemScript)?
+    } else {
+        &spent.script_pubkey
+    };
+
+    if let Some(script_code) = script.p2wpkh_script_code() {
+        Ok(SigVersion::WitnessV0(script_code, spent.value))
+    } else if script.is_v0_p2wsh() {
+        let witness_script = input.witness_script.as_ref().ok_or(SighashError::MissingWitnessScript)?;
+        Ok(SigVersion::WitnessV0(witness_script.clone(), spent.value))
+    } else if script.is_witness_program() {
+        Err(SighashError::UnsupportedScript)
+    } else {
+        Ok(SigVersion::Legacy(script))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+    use hashes::hex::FromHex;
+
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{OutPoint, SigHashType, Sequence, Transaction, TxIn, TxOut};
+    use consensus::encode::deserialize;
+    use hash_types::SigHash;
+    use util::psbt::{InputError, PartiallySignedTransaction};
+    use util::sighash::{self, SighashCache};
+
+    use super::{PsbtSighashMsg, SighashError};
+
+    fn psbt_from_hex(tx: &str) -> PartiallySignedTransaction {
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(tx).unwrap()).unwrap();
+        PartiallySignedTransaction::from_unsigned_tx(tx).unwrap()
+    }
+
+    fn sighash_msg(psbt: &PartiallySignedTransaction, index: usize) -> Result<PsbtSighashMsg, SighashError> {
+        psbt.sighash_msg(index, &mut SighashCache::new(&psbt.global.unsigned_tx))
+    }
+
+    #[test]
+    fn bip143_vectors() {
+        // Native P2WPKH
+        let mut psbt = psbt_from_hex("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000");
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: 600_000_000,
+            script_pubkey: hex_script!("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1"),
+        });
+        assert_eq!(sighash_msg(&psbt, 1), Ok(PsbtSighashMsg::WitnessV0 {
+            sighash: hex_hash!(SigHash, "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"),
+            hash_ty: SigHashType::All,
+        }));
+        assert_eq!(sighash_msg(&psbt, 0), Err(SighashError::MissingUtxo));
+        assert_eq!(
+            sighash_msg(&psbt, 2),
+            Err(SighashError::InvalidInput(InputError::InputIndexOutOfBounds { index: 2, inputs: 2 }))
+        );
+
+        // P2SH-P2WPKH
+        let mut psbt = psbt_from_hex("0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a54770100000000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000");
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 1_000_000_000,
+            script_pubkey: hex_script!("a9144733f37cf4db86fbc2efed2500b4f4e49f31202387"),
+        });
+        assert_eq!(sighash_msg(&psbt, 0), Err(SighashError::MissingRedeemScript));
+        psbt.inputs[0].redeem_script = Some(hex_script!("001479091972186c449eb1ded22b78e40d009bdf0089"));
+        assert_eq!(sighash_msg(&psbt, 0), Ok(PsbtSighashMsg::WitnessV0 {
+            sighash: hex_hash!(SigHash, "64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6"),
+            hash_ty: SigHashType::All,
+        }));
+        // a witness script doesn't belong to a P2WPKH input
+        psbt.inputs[0].witness_script = Some(Script::new());
+        assert_eq!(sighash_msg(&psbt, 0), Err(SighashError::InvalidInput(InputError::WitnessScriptMismatch)));
+    }
+
+    #[test]
+    fn p2sh_p2wsh_multisig() {
+        // 6-of-6 multisig vector of BIP143
+        let mut psbt = psbt_from_hex("010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f05000000001976a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000");
+        let witness_script = hex_script!("56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af9602b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b56ae");
+        let redeem_script = witness_script.to_v0_p2wsh();
+        assert_eq!(redeem_script, hex_script!("0020a16b5755f7f6f96dbd65f5f0d6ab9418b89af4b1f14a1bb8a09062c35f0dcb54"));
+        assert_eq!(redeem_script.to_p2sh(), hex_script!("a9149993a429037b5d912407a71c252019287b8d27a587"));
+        psbt.inputs[0].witness_utxo = Some(TxOut { value: 987654321, script_pubkey: redeem_script.to_p2sh() });
+        psbt.inputs[0].redeem_script = Some(redeem_script.clone());
+
+        assert_eq!(sighash_msg(&psbt, 0), Err(SighashError::MissingWitnessScript));
+        psbt.inputs[0].witness_script = Some(witness_script.clone());
+
+        let vectors = [
+            (SigHashType::All, "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c"),
+            (SigHashType::None, "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36"),
+            (SigHashType::Single, "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea"),
+            (SigHashType::AllPlusAnyoneCanPay, "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e"),
+            (SigHashType::NonePlusAnyoneCanPay, "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a"),
+            (SigHashType::SinglePlusAnyoneCanPay, "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b"),
+        ];
+        for &(hash_ty, expected) in vectors.iter() {
+            psbt.inputs[0].sighash_type = Some(hash_ty);
+            let msg = sighash_msg(&psbt, 0).unwrap();
+            assert_eq!(msg, PsbtSighashMsg::WitnessV0 { sighash: hex_hash!(SigHash, expected), hash_ty: hash_ty });
+            assert_eq!(msg.hash_ty(), hash_ty);
+            assert_eq!(&msg.to_secp_msg()[..], &msg.sighash()[..]);
+        }
+
+        // scripts which conflict with the spent output
+        let mut bad = psbt.clone();
+        bad.inputs[0].witness_script = Some(Script::new());
+        assert_eq!(sighash_msg(&bad, 0), Err(SighashError::InvalidInput(InputError::WitnessScriptMismatch)));
+        let mut bad = psbt.clone();
+        bad.inputs[0].redeem_script = Some(Script::new().to_v0_p2wsh());
+        assert_eq!(sighash_msg(&bad, 0), Err(SighashError::InvalidInput(InputError::RedeemScriptMismatch)));
+        let mut native = psbt.clone();
+        native.inputs[0].witness_utxo = Some(TxOut { value: 987654321, script_pubkey: redeem_script });
+        assert_eq!(sighash_msg(&native, 0), Err(SighashError::InvalidInput(InputError::RedeemScriptMismatch)));
+        // without the redeem script it is a native P2WSH input, committing to the same script code
+        native.inputs[0].redeem_script = None;
+        assert_eq!(sighash_msg(&native, 0), sighash_msg(&psbt, 0));
+    }
+
+    #[test]
+    fn legacy_and_unsupported() {
+        let p2pkh = hex_script!("76a91462744660c6b5133ddeaacbc57d2dc2d7b14d0b0688ac");
+        let redeem_script = Builder::new()
+            .push_int(1)
+            .push_slice(&[2; 33])
+            .push_int(1)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let v1_program = Builder::new().push_int(1).push_slice(&[2; 32]).into_script();
+        let funding_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(101).push_int(0).into_script(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }],
+            output: vec![p2pkh.clone(), redeem_script.to_p2sh(), v1_program.clone()]
+                .into_iter().map(|spk| TxOut { value: 1_000_000, script_pubkey: spk }).collect(),
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: (0..3).map(|vout| TxIn {
+                previous_output: OutPoint::new(funding_tx.txid(), vout),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }).collect(),
+            output: vec![TxOut { value: 2_990_000, script_pubkey: p2pkh.clone() }],
+        }).unwrap();
+        for input in psbt.inputs.iter_mut() {
+            input.non_witness_utxo = Some(funding_tx.clone());
+        }
+        psbt.inputs[0].sighash_type = Some(SigHashType::SinglePlusAnyoneCanPay);
+        psbt.inputs[1].redeem_script = Some(redeem_script.clone());
+
+        let cache = SighashCache::new(&psbt.global.unsigned_tx);
+        assert_eq!(sighash_msg(&psbt, 0), Ok(PsbtSighashMsg::Legacy {
+            sighash: cache.legacy_signature_hash(0, &p2pkh, 0x83).unwrap(),
+            hash_ty: SigHashType::SinglePlusAnyoneCanPay,
+        }));
+        // the redeem script is the script code of P2SH
+        assert_eq!(sighash_msg(&psbt, 1), Ok(PsbtSighashMsg::Legacy {
+            sighash: cache.legacy_signature_hash(1, &redeem_script, 0x01).unwrap(),
+            hash_ty: SigHashType::All,
+        }));
+        assert_eq!(sighash_msg(&psbt, 2), Err(SighashError::UnsupportedScript));
+
+        // SIGHASH_SINGLE without a corresponding output
+        psbt.inputs[1].sighash_type = Some(SigHashType::Single);
+        assert_eq!(
+            sighash_msg(&psbt, 1),
+            Err(SighashError::SigHash(sighash::Error::SingleWithoutCorrespondingOutput { index: 1, outputs_size: 1 }))
+        );
+    }
+}
@@ -0,0 +1,498 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Signer
+//!
+//! Implementation of the BIP174 Signer role for keys derived from a BIP32
+//! extended private key.
+
+
+use std::fmt;
+use std::collections::BTreeMap;
+use std::error;
+
+use secp256k1::{Secp256k1, Signing};
+
+use blockdata::transaction::Transaction;
+use util::bip32::{self, ExtendedPrivKey};
+use util::ecdsa::EcdsaSig;
+use util::key::PublicKey;
+use util::psbt::{InputError, PartiallySignedTransaction, SighashError};
+use util::sighash::{self, SighashCache};
+
+/// Ways that signing a PSBT input might fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignError {
+    /// The input's UTXO information or scripts are inconsistent, see
+    /// [PartiallySignedTransaction::validate_input].
+    InvalidInput(InputError),
+    /// The input has neither a witness nor a non-witness UTXO.
+    MissingUtxo,
+    /// The spent output is P2SH but the input has no redeem script.
+    MissingRedeemScript,
+    /// The spent output is P2WSH but the input has no witness script.
+    MissingWitnessScript,
+    /// The spent output is a witness program of an unknown version.
+    UnsupportedScript,
+    /// The key could not be derived from the extended private key.
+    Derivation(bip32::Error),
+    /// The key derived along the input's derivation path is not this one.
+    KeyMismatch(PublicKey),
+    /// The sighash could not be computed.
+    SigHash(sighash::Error),
+}
+
+impl fmt::Display for SignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SignError::InvalidInput(ref e) => write!(f, "invalid input: {}", e),
+            SignError::MissingUtxo => f.write_str("the input has no UTXO information"),
+            SignError::MissingRedeemScript => f.write_str("missing redeem script"),
+            SignError::MissingWitnessScript => f.write_str("missing witness script"),
+            SignError::UnsupportedScript => f.write_str("unsupported witness version"),
+            SignError::Derivation(ref e) => write!(f, "key derivation error: {}", e),
+            SignError::KeyMismatch(ref pubkey) => write!(f, "derivation path does not lead to {}", pubkey),
+            SignError::SigHash(ref e) => write!(f, "sighash error: {}", e),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for SignError {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            SignError::InvalidInput(ref e) => Some(e),
+            SignError::Derivation(ref e) => Some(e),
+            SignError::SigHash(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+#[doc(hidden)]
+impl From<InputError> for SignError {
+    fn from(e: InputError) -> SignError {
+        SignError::InvalidInput(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<bip32::Error> for SignError {
+    fn from(e: bip32::Error) -> SignError {
+        SignError::Derivation(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<sighash::Error> for SignError {
+    fn from(e: sighash::Error) -> SignError {
+        SignError::SigHash(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<SighashError> for SignError {
+    fn from(e: SighashError) -> SignError {
+        match e {
+            SighashError::InvalidInput(e) => SignError::InvalidInput(e),
+            SighashError::MissingUtxo => SignError::MissingUtxo,
+            SighashError::MissingRedeemScript => SignError::MissingRedeemScript,
+            SighashError::MissingWitnessScript => SignError::MissingWitnessScript,
+            SighashError::UnsupportedScript => SignError::UnsupportedScript,
+            SighashError::SigHash(e) => SignError::SigHash(e),
+        }
+    }
+}
+
+/// The keys that signatures were added for, by input index.
+pub type SigningKeys = BTreeMap<usize, Vec<PublicKey>>;
+
+/// The errors of the inputs which could not be signed, by input index.
+pub type SigningErrors = BTreeMap<usize, SignError>;
+
+impl PartiallySignedTransaction {
+    /// Sign every input with the keys derived from `k`, as the BIP174 Signer
+    /// role does. See [PartiallySignedTransaction::sign_input].
+    ///
+    /// Returns the keys signed with by input index; inputs without a
+    /// signature from `k` are left out. If some inputs can't be signed, their
+    /// errors are returned along with the keys of the inputs which were.
+    /// Inputs failing [PartiallySignedTransaction::validate_input] are not
+    /// signed, see [PartiallySignedTransaction::sign_unchecked].
+    pub fn sign<C: Signing>(
+        &mut self,
+        k: &ExtendedPrivKey,
+        secp: &Secp256k1<C>,
+    ) -> Result<SigningKeys, (SigningKeys, SigningErrors)> {
+        self.sign_inputs(k, secp, true)
+    }
+
+    /// Sign every input like [PartiallySignedTransaction::sign], but without
+    /// checking the inputs with [PartiallySignedTransaction::validate_input].
+    ///
+    /// The signatures commit to whatever amounts and scripts the UTXO fields
+    /// claim, even if they don't belong to the outputs actually spent, so this
+    /// must only be used when the inputs are known to be consistent or the
+    /// caller checks them in some other way. Legacy inputs with
+    /// SIGHASH_SINGLE and no output of the same index are still refused.
+    pub fn sign_unchecked<C: Signing>(
+        &mut self,
+        k: &ExtendedPrivKey,
+        secp: &Secp256k1<C>,
+    ) -> Result<SigningKeys, (SigningKeys, SigningErrors)> {
+        self.sign_inputs(k, secp, false)
+    }
+
+    /// Sign every input, validating them first if `validate` is set
+    fn sign_inputs<C: Signing>(
+        &mut self,
+        k: &ExtendedPrivKey,
+        secp: &Secp256k1<C>,
+        validate: bool,
+    ) -> Result<SigningKeys, (SigningKeys, SigningErrors)> {
+        let mut sigs = BTreeMap::new();
+        let mut errors = BTreeMap::new();
+        {
+            let mut cache = SighashCache::new(&self.global.unsigned_tx);
+            for index in 0..self.inputs.len() {
+                match self.input_signatures(&mut cache, k, secp, index, validate) {
+                    Ok(ref input_sigs) if input_sigs.is_empty() => {}
+                    Ok(input_sigs) => { sigs.insert(index, input_sigs); }
+                    Err(e) => { errors.insert(index, e); }
+                }
+            }
+        }
+
+        let mut signed = BTreeMap::new();
+        for (index, input_sigs) in sigs {
+            signed.insert(index, input_sigs.iter().map(|&(pk, _)| pk).collect());
+            self.inputs[index].partial_sigs.extend(input_sigs);
+        }
+        if errors.is_empty() {
+            Ok(signed)
+        } else {
+            Err((signed, errors))
+        }
+    }
+
+    /// Sign the input at `index` with every key of its BIP32 derivation paths
+    /// which originates from `k`, adding the signatures to its partial
+    /// signatures. Returns the keys signed with.
+    ///
+    /// The message signed is the one of [PartiallySignedTransaction::sighash_msg],
+    /// with the input's sighash type or [SigHashType::All] if it has none.
+    /// Inputs which are already finalized or have no key from `k` are left
+    /// as they are. The input is checked with
+    /// [PartiallySignedTransaction::validate_input] first and not signed if
+    /// it fails, so that a signature never commits to a different amount or
+    /// script than the PSBT shows. Legacy inputs with SIGHASH_SINGLE and no
+    /// output of the same index are refused, since their signature would be
+    /// valid for any transaction spending an output of the key.
+    pub fn sign_input<C: Signing>(
+        &mut self,
+        k: &ExtendedPrivKey,
+        secp: &Secp256k1<C>,
+        index: usize,
+    ) -> Result<Vec<PublicKey>, SignError> {
+        let sigs = {
+            let mut cache = SighashCache::new(&self.global.unsigned_tx);
+            self.input_signatures(&mut cache, k, secp, index, true)?
+        };
+        let keys = sigs.iter().map(|&(pk, _)| pk).collect();
+        self.inputs[index].partial_sigs.extend(sigs);
+        Ok(keys)
+    }
+
+    /// The signatures of the input at `index` by the keys derived from `k`,
+    /// validating the input first if `validate` is set
+    fn input_signatures<C: Signing>(
+        &self,
+        cache: &mut SighashCache<&Transaction>,
+        k: &ExtendedPrivKey,
+        secp: &Secp256k1<C>,
+        index: usize,
+        validate: bool,
+    ) -> Result<Vec<(PublicKey, EcdsaSig)>, SignError> {
+        let input = self.inputs.get(index).ok_or(InputError::InputIndexOutOfBounds {
+            index: index,
+            inputs: self.inputs.len(),
+        })?;
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            return Ok(vec![]);
+        }
+        let fingerprint = k.fingerprint(secp);
+        let keys: Vec<_> = input.hd_keypaths.iter().filter(|&(_, &(fp, _))| fp == fingerprint).collect();
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let sighash_msg = if validate {
+            self.sighash_msg(index, cache)?
+        } else {
+            self.sighash_msg_unchecked(index, cache)?
+        };
+        let msg = sighash_msg.to_secp_msg();
+        let hash_ty = sighash_msg.hash_ty();
+
+        let mut sigs = Vec::with_capacity(keys.len());
+        for (pk, &(_, ref path)) in keys {
+            let sk = k.derive_priv(secp, path)?.private_key;
+            if sk.public_key(secp) != *pk {
+                return Err(SignError::KeyMismatch(*pk));
+            }
+            sigs.push((*pk, EcdsaSig { sig: secp.sign(&msg, &sk.key), hash_ty: hash_ty }));
+        }
+        Ok(sigs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use secp256k1::Secp256k1;
+
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{OutPoint, SigHashType, Sequence, Transaction, TxIn, TxOut};
+    use hashes::hex::FromHex;
+    use network::constants::Network;
+    use util::bip32::{DerivationPath, ExtendedPrivKey};
+    use util::ecdsa::verify_input_signature;
+    use util::key::PublicKey;
+    use util::psbt::{InputError, PartiallySignedTransaction};
+    use util::sighash;
+
+    use super::SignError;
+
+    const VALUE: u64 = 1_000_000;
+
+    #[test]
+    fn sign_finalize_extract() {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(Network::MonacoinRegtest, &Vec::from_hex("000102030405060708090a0b0c0d0e0f").unwrap()).unwrap();
+        let cosigner = ExtendedPrivKey::new_master(Network::MonacoinRegtest, &[7; 32]).unwrap();
+        let derive = |k: &ExtendedPrivKey, path: &str| {
+            let path = DerivationPath::from_str(path).unwrap();
+            let pk = k.derive_priv(&secp, &path).unwrap().private_key.public_key(&secp);
+            (pk, (k.fingerprint(&secp), path))
+        };
+
+        let p2pkh = derive(&master, "m/44'/1'/0'/0/0");
+        let p2wpkh = derive(&master, "m/84'/1'/0'/0/0");
+        let p2sh_p2wpkh = derive(&master, "m/49'/1'/0'/0/0");
+        let ms_ours = derive(&master, "m/48'/1'/0'/2'/0/0");
+        let ms_theirs = derive(&cosigner, "m/48'/1'/0'/2'/0/0");
+        let witness_script = Builder::new()
+            .push_int(2)
+            .push_key(&ms_ours.0)
+            .push_key(&ms_theirs.0)
+            .push_int(2)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let wpkh = |pk: &PublicKey| Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap());
+
+        // funding fixture paying to every template
+        let funding_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(101).push_int(0).into_script(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }],
+            output: vec![
+                Script::new_p2pkh(&p2pkh.0.pubkey_hash()),
+                wpkh(&p2wpkh.0),
+                wpkh(&p2sh_p2wpkh.0).to_p2sh(),
+                witness_script.to_v0_p2wsh(),
+            ].into_iter().map(|spk| TxOut { value: VALUE, script_pubkey: spk }).collect(),
+        };
+
+        let unsigned_tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: (0..4).map(|vout| TxIn {
+                previous_output: OutPoint::new(funding_tx.txid(), vout),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }).collect(),
+            output: vec![TxOut { value: 4 * VALUE - 10_000, script_pubkey: wpkh(&p2wpkh.0) }],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(funding_tx.clone());
+        psbt.inputs[0].hd_keypaths.insert(p2pkh.0, p2pkh.1);
+        psbt.inputs[1].witness_utxo = Some(funding_tx.output[1].clone());
+        psbt.inputs[1].hd_keypaths.insert(p2wpkh.0, p2wpkh.1.clone());
+        psbt.inputs[1].sighash_type = Some(SigHashType::AllPlusAnyoneCanPay);
+        psbt.inputs[2].witness_utxo = Some(funding_tx.output[2].clone());
+        psbt.inputs[2].redeem_script = Some(wpkh(&p2sh_p2wpkh.0));
+        psbt.inputs[2].hd_keypaths.insert(p2sh_p2wpkh.0, p2sh_p2wpkh.1);
+        psbt.inputs[3].witness_utxo = Some(funding_tx.output[3].clone());
+        psbt.inputs[3].witness_script = Some(witness_script.clone());
+        psbt.inputs[3].hd_keypaths.insert(ms_ours.0, ms_ours.1);
+        psbt.inputs[3].hd_keypaths.insert(ms_theirs.0, ms_theirs.1);
+
+        // an inconsistent UTXO is never signed
+        let mut bad = psbt.clone();
+        bad.inputs[2].redeem_script = Some(wpkh(&p2wpkh.0));
+        let (signed, errors) = bad.sign(&master, &secp).unwrap_err();
+        assert_eq!(signed.keys().cloned().collect::<Vec<_>>(), vec![0, 1, 3]);
+        assert_eq!(errors.into_iter().collect::<Vec<_>>(), vec![(2, SignError::InvalidInput(InputError::RedeemScriptMismatch))]);
+        assert!(bad.inputs[2].partial_sigs.is_empty());
+        // unless validation is skipped explicitly
+        let signed = bad.sign_unchecked(&master, &secp).unwrap();
+        assert_eq!(signed.keys().cloned().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert!(bad.inputs[2].partial_sigs.contains_key(&p2sh_p2wpkh.0));
+        // a non-witness UTXO without the spent output is still an error
+        let mut bad = psbt.clone();
+        bad.inputs[0].non_witness_utxo.as_mut().unwrap().output.truncate(0);
+        let (signed, errors) = bad.sign_unchecked(&master, &secp).unwrap_err();
+        assert_eq!(signed.keys().cloned().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(errors.into_iter().collect::<Vec<_>>(), vec![(0, SignError::MissingUtxo)]);
+
+        // a derivation path which doesn't lead to the key
+        let mut bad = psbt.clone();
+        bad.inputs[0].hd_keypaths.insert(p2pkh.0, p2wpkh.1.clone());
+        assert_eq!(bad.sign_input(&master, &secp, 0), Err(SignError::KeyMismatch(p2pkh.0)));
+
+        // each signer only signs for its own keys
+        let signed = psbt.sign(&master, &secp).unwrap();
+        assert_eq!(signed.into_iter().collect::<Vec<_>>(), vec![
+            (0, vec![p2pkh.0]),
+            (1, vec![p2wpkh.0]),
+            (2, vec![p2sh_p2wpkh.0]),
+            (3, vec![ms_ours.0]),
+        ]);
+        assert_eq!(psbt.inputs[0].partial_sigs[&p2pkh.0].hash_ty, SigHashType::All);
+        assert_eq!(psbt.inputs[1].partial_sigs[&p2wpkh.0].hash_ty, SigHashType::AllPlusAnyoneCanPay);
+        assert!(psbt.finalize(&secp).unwrap_err().keys().eq([3].iter()));
+
+        let signed = psbt.sign(&cosigner, &secp).unwrap();
+        assert_eq!(signed.into_iter().collect::<Vec<_>>(), vec![(3, vec![ms_theirs.0])]);
+        psbt.finalize(&secp).unwrap();
+        // finalized inputs are not signed again
+        assert!(psbt.sign(&master, &secp).unwrap().is_empty());
+
+        let tx = psbt.extract_tx().unwrap();
+        for index in 0..3 {
+            verify_input_signature(&secp, &tx, index, &funding_tx.output[index]).unwrap();
+        }
+        #[cfg(feature = "bitcoinconsensus")]
+        tx.verify(|outpoint| funding_tx.output.get(outpoint.vout as usize).cloned()).unwrap();
+    }
+
+    #[test]
+    fn sign_errors() {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(Network::MonacoinRegtest, &[1; 32]).unwrap();
+        let path = DerivationPath::from_str("m/0").unwrap();
+        let pk = master.derive_priv(&secp, &path).unwrap().private_key.public_key(&secp);
+        let key_origin = (master.fingerprint(&secp), path);
+
+        let unsigned_tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+        // nothing to sign with this key
+        assert_eq!(psbt.sign_input(&master, &secp, 0), Ok(vec![]));
+
+        psbt.inputs[0].hd_keypaths.insert(pk, key_origin);
+        assert_eq!(psbt.sign_input(&master, &secp, 0), Err(SignError::MissingUtxo));
+
+        let p2wsh = Script::new().to_v0_p2wsh();
+        psbt.inputs[0].witness_utxo = Some(TxOut { value: VALUE, script_pubkey: p2wsh.to_p2sh() });
+        assert_eq!(psbt.sign_input(&master, &secp, 0), Err(SignError::MissingRedeemScript));
+        psbt.inputs[0].redeem_script = Some(p2wsh);
+        assert_eq!(psbt.sign_input(&master, &secp, 0), Err(SignError::MissingWitnessScript));
+
+        let v1_program = Builder::new().push_int(1).push_slice(&[2; 32]).into_script();
+        psbt.inputs[0].redeem_script = None;
+        psbt.inputs[0].witness_utxo = Some(TxOut { value: VALUE, script_pubkey: v1_program });
+        assert_eq!(psbt.sign_input(&master, &secp, 0), Err(SignError::UnsupportedScript));
+
+        assert_eq!(
+            psbt.sign_input(&master, &secp, 1),
+            Err(SignError::InvalidInput(InputError::InputIndexOutOfBounds { index: 1, inputs: 1 }))
+        );
+    }
+
+    #[test]
+    fn sighash_single_bug() {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(Network::MonacoinRegtest, &[1; 32]).unwrap();
+        let path = DerivationPath::from_str("m/0").unwrap();
+        let pk = master.derive_priv(&secp, &path).unwrap().private_key.public_key(&secp);
+        let key_origin = (master.fingerprint(&secp), path);
+
+        let funding_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![
+                TxOut { value: VALUE, script_pubkey: Script::new_p2pkh(&pk.pubkey_hash()) },
+                TxOut { value: VALUE, script_pubkey: Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap()) },
+            ],
+        };
+        let unsigned_tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: (0..2).map(|vout| TxIn {
+                previous_output: OutPoint::new(funding_tx.txid(), vout),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }).collect(),
+            output: vec![TxOut { value: VALUE, script_pubkey: Script::new() }],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+        for (index, input) in psbt.inputs.iter_mut().enumerate() {
+            input.witness_utxo = Some(funding_tx.output[index].clone());
+            input.hd_keypaths.insert(pk, key_origin.clone());
+            input.sighash_type = Some(SigHashType::SinglePlusAnyoneCanPay);
+        }
+        psbt.inputs[0].witness_utxo = None;
+        psbt.inputs[0].non_witness_utxo = Some(funding_tx.clone());
+
+        // the first input has an output of its own
+        assert_eq!(psbt.sign_input(&master, &secp, 0), Ok(vec![pk]));
+        // the second one doesn't, which only makes a difference for legacy inputs
+        assert_eq!(psbt.sign_input(&master, &secp, 1), Ok(vec![pk]));
+        psbt.inputs.swap(0, 1);
+        psbt.global.unsigned_tx.input.swap(0, 1);
+        for input in psbt.inputs.iter_mut() {
+            input.partial_sigs.clear();
+        }
+        let (signed, errors) = psbt.sign(&master, &secp).unwrap_err();
+        assert!(signed.keys().eq([0].iter()));
+        assert_eq!(errors.into_iter().collect::<Vec<_>>(), vec![
+            (1, SignError::SigHash(sighash::Error::SingleWithoutCorrespondingOutput { index: 1, outputs_size: 1 })),
+        ]);
+        assert!(psbt.inputs[1].partial_sigs.is_empty());
+    }
+}
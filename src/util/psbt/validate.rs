@@ -0,0 +1,288 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Input Validation
+//!
+//! Consistency checks between the UTXO information and scripts of a PSBT
+//! input and the outpoint it spends. A signer which skips them can be
+//! tricked into signing for a different amount or script than it shows.
+
+
+use std::fmt;
+use std::error;
+
+use hash_types::Txid;
+use blockdata::script::Script;
+use blockdata::transaction::TxOut;
+use util::psbt::PartiallySignedTransaction;
+
+/// Ways that a PSBT input can be inconsistent with the transaction or itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    /// There is no PSBT input or no transaction input with this index.
+    InputIndexOutOfBounds {
+        /// Index of the input
+        index: usize,
+        /// Number of PSBT inputs, or of transaction inputs if there are fewer
+        inputs: usize,
+    },
+    /// The non-witness UTXO is not the transaction the input spends from.
+    NonWitnessUtxoMismatch {
+        /// Txid of the spent outpoint
+        expected: Txid,
+        /// Txid of the non-witness UTXO
+        actual: Txid,
+    },
+    /// The non-witness UTXO has no output at the spent outpoint's index.
+    NonWitnessUtxoMissingOutput {
+        /// Index of the spent output
+        vout: u32,
+        /// Number of outputs of the non-witness UTXO
+        outputs: usize,
+    },
+    /// The witness UTXO differs from the spent output of the non-witness UTXO.
+    WitnessUtxoMismatch,
+    /// The witness UTXO is neither a witness program nor P2SH.
+    WitnessUtxoNotSegwit,
+    /// There is a redeem script, but the spent output isn't P2SH or has
+    /// another script hash.
+    RedeemScriptMismatch,
+    /// The witness UTXO is P2SH, but the redeem script is not a witness program.
+    RedeemScriptNotSegwit,
+    /// There is a witness script, but the spent witness program isn't P2WSH
+    /// or has another script hash.
+    WitnessScriptMismatch,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InputError::InputIndexOutOfBounds { index, inputs } => {
+                write!(f, "input index {} out of bounds (number of inputs: {})", index, inputs)
+            }
+            InputError::NonWitnessUtxoMismatch { ref expected, ref actual } => {
+                write!(f, "non-witness UTXO {} is not the spent transaction {}", actual, expected)
+            }
+            InputError::NonWitnessUtxoMissingOutput { vout, outputs } => {
+                write!(f, "non-witness UTXO has {} outputs, output {} is spent", outputs, vout)
+            }
+            InputError::WitnessUtxoMismatch => f.write_str("witness UTXO doesn't match the non-witness UTXO"),
+            InputError::WitnessUtxoNotSegwit => f.write_str("witness UTXO is neither a witness program nor P2SH"),
+            InputError::RedeemScriptMismatch => f.write_str("redeem script does not match the script pubkey"),
+            InputError::RedeemScriptNotSegwit => f.write_str("redeem script of a witness UTXO is not a witness program"),
+            InputError::WitnessScriptMismatch => f.write_str("witness script does not match the witness program"),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for InputError {
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+impl PartiallySignedTransaction {
+    /// Check every input with [PartiallySignedTransaction::validate_input],
+    /// returning the errors of all inconsistent inputs.
+    pub fn validate(&self) -> Result<(), Vec<(usize, InputError)>> {
+        let errors: Vec<(usize, InputError)> = (0..self.inputs.len())
+            .filter_map(|index| self.validate_input(index).err().map(|e| (index, e)))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Check that the UTXO information and scripts of the input at `index`
+    /// are consistent:
+    ///
+    /// - the non-witness UTXO is the transaction the input spends from,
+    /// - the witness UTXO is the spent output of the non-witness UTXO, if both
+    ///   are present, and is a witness program or P2SH-wrapped one,
+    /// - the redeem script hashes to the spent P2SH output, and
+    /// - the witness script hashes to the spent P2WSH program, directly or
+    ///   inside the redeem script.
+    ///
+    /// Missing fields are not an error, since other roles may still add them.
+    /// [PartiallySignedTransaction::finalize_input] runs this check first.
+    pub fn validate_input(&self, index: usize) -> Result<(), InputError> {
+        let input = self.inputs.get(index).ok_or(InputError::InputIndexOutOfBounds {
+            index: index,
+            inputs: self.inputs.len(),
+        })?;
+        let prevout = self.global.unsigned_tx.input.get(index).ok_or(InputError::InputIndexOutOfBounds {
+            index: index,
+            inputs: self.global.unsigned_tx.input.len(),
+        })?.previous_output;
+
+        let mut spent: Option<&TxOut> = None;
+        if let Some(ref tx) = input.non_witness_utxo {
+            let txid = tx.txid();
+            if txid != prevout.txid {
+                return Err(InputError::NonWitnessUtxoMismatch { expected: prevout.txid, actual: txid });
+            }
+            spent = Some(tx.output.get(prevout.vout as usize).ok_or(InputError::NonWitnessUtxoMissingOutput {
+                vout: prevout.vout,
+                outputs: tx.output.len(),
+            })?);
+        }
+        if let Some(ref utxo) = input.witness_utxo {
+            if spent.map_or(false, |spent| spent != utxo) {
+                return Err(InputError::WitnessUtxoMismatch);
+            }
+            if !utxo.script_pubkey.is_witness_program() && !utxo.script_pubkey.is_p2sh() {
+                return Err(InputError::WitnessUtxoNotSegwit);
+            }
+            spent = Some(utxo);
+        }
+        let script_pubkey = match spent {
+            Some(spent) => &spent.script_pubkey,
+            None => return Ok(()),
+        };
+
+        // the script which has to be a witness program for a witness script to be used
+        let mut program: Option<&Script> = Some(script_pubkey);
+        if script_pubkey.is_p2sh() {
+            program = None;
+            if let Some(ref redeem_script) = input.redeem_script {
+                if redeem_script.to_p2sh() != *script_pubkey {
+                    return Err(InputError::RedeemScriptMismatch);
+                }
+                if input.witness_utxo.is_some() && !redeem_script.is_witness_program() {
+                    return Err(InputError::RedeemScriptNotSegwit);
+                }
+                program = Some(redeem_script);
+            }
+        } else if input.redeem_script.is_some() {
+            return Err(InputError::RedeemScriptMismatch);
+        }
+
+        match (&input.witness_script, program) {
+            (&Some(ref witness_script), Some(program)) if witness_script.to_v0_p2wsh() != *program => {
+                Err(InputError::WitnessScriptMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut};
+    use util::psbt::PartiallySignedTransaction;
+
+    use super::InputError;
+
+    fn tx(script_pubkeys: Vec<Script>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(7).into_script(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }],
+            output: script_pubkeys.into_iter().map(|spk| TxOut { value: 50_000, script_pubkey: spk }).collect(),
+        }
+    }
+
+    #[test]
+    fn validate_inputs() {
+        let witness_script = Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_1).into_script();
+        let p2wsh = witness_script.to_v0_p2wsh();
+        let p2sh_p2wsh = p2wsh.to_p2sh();
+        let legacy = Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_2).into_script();
+        let prev_tx = tx(vec![p2wsh.clone(), p2sh_p2wsh.clone(), legacy.to_p2sh()]);
+
+        let mut unsigned_tx = tx(vec![]);
+        unsigned_tx.input = (0..3).map(|vout| TxIn {
+            previous_output: OutPoint::new(prev_tx.txid(), vout),
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: vec![],
+        }).collect();
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+        // nothing to check yet
+        assert_eq!(psbt.validate(), Ok(()));
+
+        for input in psbt.inputs.iter_mut() {
+            input.non_witness_utxo = Some(prev_tx.clone());
+        }
+        psbt.inputs[0].witness_utxo = Some(prev_tx.output[0].clone());
+        psbt.inputs[0].witness_script = Some(witness_script.clone());
+        psbt.inputs[1].witness_utxo = Some(prev_tx.output[1].clone());
+        psbt.inputs[1].redeem_script = Some(p2wsh.clone());
+        psbt.inputs[1].witness_script = Some(witness_script.clone());
+        psbt.inputs[2].redeem_script = Some(legacy.clone());
+        assert_eq!(psbt.validate(), Ok(()));
+
+        // a non-witness UTXO which isn't the spent transaction
+        let mut bad = psbt.clone();
+        let other_tx = tx(vec![p2wsh.clone()]);
+        bad.inputs[0].non_witness_utxo = Some(other_tx.clone());
+        assert_eq!(bad.validate(), Err(vec![(0, InputError::NonWitnessUtxoMismatch {
+            expected: prev_tx.txid(),
+            actual: other_tx.txid(),
+        })]));
+        bad.global.unsigned_tx.input[0].previous_output.vout = 3;
+        bad.inputs[0].non_witness_utxo = Some(prev_tx.clone());
+        assert_eq!(bad.validate_input(0), Err(InputError::NonWitnessUtxoMissingOutput { vout: 3, outputs: 3 }));
+
+        // witness UTXOs disagreeing with the non-witness UTXO or not segwit at all
+        let mut bad = psbt.clone();
+        bad.inputs[0].witness_utxo.as_mut().unwrap().value += 1;
+        bad.inputs[1].non_witness_utxo = None;
+        bad.inputs[1].witness_utxo = Some(TxOut { value: 1, script_pubkey: legacy.clone() });
+        assert_eq!(bad.validate(), Err(vec![
+            (0, InputError::WitnessUtxoMismatch),
+            (1, InputError::WitnessUtxoNotSegwit),
+        ]));
+
+        // scripts which don't hash to what is spent
+        let mut bad = psbt.clone();
+        bad.inputs[0].witness_script = Some(legacy.clone());
+        bad.inputs[1].redeem_script = Some(legacy.to_v0_p2wsh());
+        bad.inputs[2].redeem_script = Some(witness_script.clone());
+        assert_eq!(bad.validate(), Err(vec![
+            (0, InputError::WitnessScriptMismatch),
+            (1, InputError::RedeemScriptMismatch),
+            (2, InputError::RedeemScriptMismatch),
+        ]));
+        let mut bad = psbt.clone();
+        bad.inputs[0].redeem_script = Some(p2wsh.clone());
+        bad.inputs[1].witness_script = Some(legacy.clone());
+        assert_eq!(bad.validate(), Err(vec![
+            (0, InputError::RedeemScriptMismatch),
+            (1, InputError::WitnessScriptMismatch),
+        ]));
+
+        // a P2SH witness UTXO whose redeem script isn't a witness program
+        let mut bad = psbt.clone();
+        bad.inputs[2].witness_utxo = Some(prev_tx.output[2].clone());
+        assert_eq!(bad.validate(), Err(vec![(2, InputError::RedeemScriptNotSegwit)]));
+
+        assert_eq!(psbt.validate_input(3), Err(InputError::InputIndexOutOfBounds { index: 3, inputs: 3 }));
+        // more PSBT inputs than transaction inputs
+        let mut bad = psbt.clone();
+        bad.global.unsigned_tx.input.pop();
+        assert_eq!(bad.validate_input(2), Err(InputError::InputIndexOutOfBounds { index: 2, inputs: 2 }));
+    }
+}
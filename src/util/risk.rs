@@ -0,0 +1,475 @@
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Zero-confirmation risk
+//!
+//! Merchants accepting unconfirmed payments look at the same handful of
+//! signals to decide how likely the payment is to be double-spent. This
+//! module evaluates them in one place: [zero_conf_score] reports every risk
+//! factor it finds as a [Finding], and an overall [RiskLevel] which is that
+//! of the most serious finding.
+//!
+//! None of this makes an unconfirmed payment safe. A low score only means
+//! that none of the easy double-spend avenues are open.
+//!
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use hash_types::Txid;
+use blockdata::script::Script;
+use blockdata::transaction::{OutPoint, Transaction, TxOut};
+use util::fee_rate::FeeRate;
+
+/// Payments seen for less than this many seconds may still have a conflicting
+/// transaction propagating through the network
+pub const MIN_SEEN_SECS: u64 = 10;
+
+/// Unconfirmed ancestries deeper than this are not relayed by default
+pub const MAX_UNCONFIRMED_ANCESTORS: usize = 25;
+
+/// Outputs worth less than this are dust and make a transaction non-standard
+pub const DUST_THRESHOLD: u64 = 546;
+
+/// Transactions heavier than this are non-standard
+const MAX_STANDARD_TX_WEIGHT: u64 = 400_000;
+
+/// Standard scriptSigs are at most this large
+const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+
+/// Standard OP_RETURN outputs are at most this large
+const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// How risky accepting an unconfirmed transaction is
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RiskLevel {
+    /// No known double-spend avenue
+    Low,
+    /// A double-spend needs luck or miner cooperation
+    Medium,
+    /// A double-spend is easy, or the transaction may never confirm
+    High,
+}
+
+/// A single risk factor found in a transaction or its unconfirmed ancestry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Finding {
+    /// The transaction, or the unconfirmed ancestor with this txid, signals
+    /// replaceability as in BIP125
+    SignalsRbf(Txid),
+    /// The transaction spends an output of this unconfirmed transaction
+    UnconfirmedParent(Txid),
+    /// The unconfirmed ancestry is too large to be relayed
+    TooManyAncestors,
+    /// The spent output is neither known confirmed nor in the mempool
+    UnknownPrevout(OutPoint),
+    /// The transaction spends more than its inputs are worth
+    OverspendsInputs,
+    /// The fee rate is below what the fee estimator currently expects
+    LowFeeRate {
+        /// Fee rate of the transaction
+        fee_rate: FeeRate,
+        /// Fee rate currently needed to confirm
+        target: FeeRate,
+    },
+    /// The transaction is not standard and may not be relayed, for the given
+    /// reason
+    NonStandard(&'static str),
+    /// The output with this index pays back to a script the transaction
+    /// spends from, so the payer keeps a convenient way to replace it
+    OutputToSelf(usize),
+    /// The transaction was seen too recently for conflicts to have arrived
+    RecentlySeen {
+        /// Seconds since the transaction was first seen
+        seen_for_secs: u64,
+    },
+}
+
+impl Finding {
+    /// How much this finding alone adds to the risk
+    pub fn risk(&self) -> RiskLevel {
+        match *self {
+            Finding::SignalsRbf(_) |
+            Finding::TooManyAncestors |
+            Finding::UnknownPrevout(_) |
+            Finding::OverspendsInputs |
+            Finding::NonStandard(_) => RiskLevel::High,
+            Finding::UnconfirmedParent(_) |
+            Finding::LowFeeRate { .. } |
+            Finding::RecentlySeen { .. } => RiskLevel::Medium,
+            Finding::OutputToSelf(_) => RiskLevel::Low,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Finding::SignalsRbf(ref txid) => write!(f, "{} signals replaceability", txid),
+            Finding::UnconfirmedParent(ref txid) => write!(f, "spends unconfirmed transaction {}", txid),
+            Finding::TooManyAncestors => f.write_str("too many unconfirmed ancestors"),
+            Finding::UnknownPrevout(ref outpoint) => write!(f, "spends unknown output {}", outpoint),
+            Finding::OverspendsInputs => f.write_str("outputs exceed inputs"),
+            Finding::LowFeeRate { fee_rate, target } => {
+                write!(f, "fee rate {} is below the expected {}", fee_rate, target)
+            }
+            Finding::NonStandard(reason) => write!(f, "non-standard: {}", reason),
+            Finding::OutputToSelf(vout) => write!(f, "output {} pays to a spent script", vout),
+            Finding::RecentlySeen { seen_for_secs } => write!(f, "seen only {} seconds ago", seen_for_secs),
+        }
+    }
+}
+
+/// The result of [zero_conf_score]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RiskReport {
+    /// The highest risk of any finding, [RiskLevel::Low] if there are none
+    pub level: RiskLevel,
+    /// Every risk factor found
+    pub findings: Vec<Finding>,
+}
+
+/// What is known about the network when scoring a transaction
+#[derive(Clone, Debug)]
+pub struct ZeroConfContext<'a> {
+    /// Unconfirmed transactions, such as the mempool, by txid. Spent outputs
+    /// found here are treated as unconfirmed.
+    pub unconfirmed: &'a HashMap<Txid, Transaction>,
+    /// The fee rate currently needed to confirm soon, e.g. from
+    /// [::util::fee_estimator::BlockFeeHistogram::estimate_feerate]
+    pub target_fee_rate: FeeRate,
+    /// Seconds since the transaction was first seen
+    pub seen_for_secs: u64,
+}
+
+/// Assess the risk of accepting `tx` before it confirms.
+///
+/// `confirmed` is called for spent outputs which are not in
+/// [ZeroConfContext::unconfirmed] and should return them if they are
+/// confirmed and unspent.
+pub fn zero_conf_score<S>(tx: &Transaction, mut confirmed: S, context: &ZeroConfContext) -> RiskReport
+    where S: FnMut(&OutPoint) -> Option<TxOut>
+{
+    let mut findings = vec![];
+
+    if tx.is_explicitly_rbf() {
+        findings.push(Finding::SignalsRbf(tx.txid()));
+    }
+    check_ancestry(tx, context.unconfirmed, &mut findings);
+
+    let mut spent_scripts = HashSet::new();
+    let mut input_value = Some(0u64);
+    for input in &tx.input {
+        let prevout = &input.previous_output;
+        let spent = match context.unconfirmed.get(&prevout.txid) {
+            Some(parent) => parent.output.get(prevout.vout as usize).cloned(),
+            None => confirmed(prevout),
+        };
+        match spent {
+            Some(spent) => {
+                input_value = input_value.and_then(|v| v.checked_add(spent.value));
+                spent_scripts.insert(spent.script_pubkey);
+            }
+            None => {
+                findings.push(Finding::UnknownPrevout(*prevout));
+                input_value = None;
+            }
+        }
+    }
+
+    if let Some(input_value) = input_value {
+        let output_value = tx.output.iter().fold(Some(0u64), |sum, out| sum.and_then(|s| s.checked_add(out.value)));
+        match output_value.and_then(|out| input_value.checked_sub(out)) {
+            Some(fee) => {
+                let weight = tx.weight().to_wu();
+                let fee_rate = FeeRate::from_sat_per_kwu(fee.saturating_mul(1000) / weight);
+                if fee_rate < context.target_fee_rate {
+                    findings.push(Finding::LowFeeRate { fee_rate: fee_rate, target: context.target_fee_rate });
+                }
+            }
+            None => findings.push(Finding::OverspendsInputs),
+        }
+    }
+
+    if let Some(reason) = non_standard_reason(tx) {
+        findings.push(Finding::NonStandard(reason));
+    }
+
+    for (vout, output) in tx.output.iter().enumerate() {
+        if spent_scripts.contains(&output.script_pubkey) {
+            findings.push(Finding::OutputToSelf(vout));
+        }
+    }
+
+    if context.seen_for_secs < MIN_SEEN_SECS {
+        findings.push(Finding::RecentlySeen { seen_for_secs: context.seen_for_secs });
+    }
+
+    RiskReport {
+        level: findings.iter().map(Finding::risk).max().unwrap_or(RiskLevel::Low),
+        findings: findings,
+    }
+}
+
+/// Report the unconfirmed parents of `tx`, and any replaceable transaction
+/// among all its unconfirmed ancestors
+fn check_ancestry(tx: &Transaction, unconfirmed: &HashMap<Txid, Transaction>, findings: &mut Vec<Finding>) {
+    let mut parents = vec![];
+    for input in &tx.input {
+        let txid = input.previous_output.txid;
+        if unconfirmed.contains_key(&txid) && !parents.contains(&txid) {
+            parents.push(txid);
+            findings.push(Finding::UnconfirmedParent(txid));
+        }
+    }
+
+    let mut seen: HashSet<Txid> = parents.iter().cloned().collect();
+    let mut queue = parents;
+    while let Some(txid) = queue.pop() {
+        if seen.len() > MAX_UNCONFIRMED_ANCESTORS {
+            findings.push(Finding::TooManyAncestors);
+            return;
+        }
+        let ancestor = &unconfirmed[&txid];
+        if ancestor.is_explicitly_rbf() {
+            findings.push(Finding::SignalsRbf(txid));
+        }
+        for input in &ancestor.input {
+            let txid = input.previous_output.txid;
+            if unconfirmed.contains_key(&txid) && seen.insert(txid) {
+                queue.push(txid);
+            }
+        }
+    }
+}
+
+/// The first reason, if any, why default relay policy would reject `tx`
+fn non_standard_reason(tx: &Transaction) -> Option<&'static str> {
+    if tx.version < 1 || tx.version > 2 {
+        return Some("version");
+    }
+    if tx.weight().to_wu() > MAX_STANDARD_TX_WEIGHT {
+        return Some("tx-size");
+    }
+    for input in &tx.input {
+        if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Some("scriptsig-size");
+        }
+        if !input.script_sig.is_push_only() {
+            return Some("scriptsig-not-pushonly");
+        }
+    }
+
+    let mut op_returns = 0;
+    for output in &tx.output {
+        let spk = &output.script_pubkey;
+        if spk.is_op_return() {
+            if spk.len() > MAX_OP_RETURN_RELAY {
+                return Some("scriptpubkey");
+            }
+            op_returns += 1;
+        } else if !is_standard_script_pubkey(spk) {
+            return Some("scriptpubkey");
+        } else if output.value < DUST_THRESHOLD {
+            return Some("dust");
+        }
+    }
+    if op_returns > 1 {
+        return Some("multi-op-return");
+    }
+    None
+}
+
+/// Whether `spk` is one of the output templates relayed by default, other
+/// than OP_RETURN. Bare multisig is not included.
+fn is_standard_script_pubkey(spk: &Script) -> bool {
+    spk.is_p2pkh() || spk.is_p2sh() || spk.is_p2pk() || spk.is_v0_p2wpkh() || spk.is_v0_p2wsh()
+        || (spk.is_witness_program() && spk.as_bytes()[0] != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hashes::Hash;
+    use hash_types::{PubkeyHash, Txid};
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut};
+    use util::fee_rate::FeeRate;
+
+    use super::*;
+
+    fn p2pkh(i: u8) -> Script {
+        Script::new_p2pkh(&PubkeyHash::hash(&[i]))
+    }
+
+    /// A confirmed output worth 100k for every outpoint with a zero vout
+    fn confirmed(outpoint: &OutPoint) -> Option<TxOut> {
+        if outpoint.vout == 0 {
+            Some(TxOut { value: 100_000, script_pubkey: p2pkh(0) })
+        } else {
+            None
+        }
+    }
+
+    /// A payment of 90k from a confirmed output, weighing 768 wu; 10k fee is 13020 sat/kwu
+    fn payment() -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::hash(&[1]), 0),
+                script_sig: Builder::new().push_slice(&[0; 72]).push_slice(&[2; 33]).into_script(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value: 90_000, script_pubkey: p2pkh(1) }],
+        }
+    }
+
+    fn score(tx: &Transaction, unconfirmed: &HashMap<Txid, Transaction>) -> RiskReport {
+        let context = ZeroConfContext {
+            unconfirmed: unconfirmed,
+            target_fee_rate: FeeRate::from_sat_per_kwu(5_000),
+            seen_for_secs: 60,
+        };
+        zero_conf_score(tx, confirmed, &context)
+    }
+
+    #[test]
+    fn clean_payment() {
+        let tx = payment();
+        assert_eq!(tx.weight().to_wu(), 768);
+        assert_eq!(score(&tx, &HashMap::new()), RiskReport { level: RiskLevel::Low, findings: vec![] });
+    }
+
+    #[test]
+    fn rbf() {
+        let mut tx = payment();
+        tx.input[0].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        assert_eq!(score(&tx, &HashMap::new()).findings, vec![Finding::SignalsRbf(tx.txid())]);
+
+        // locktime-enabling but not replaceable
+        tx.input[0].sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+        assert_eq!(score(&tx, &HashMap::new()).level, RiskLevel::Low);
+    }
+
+    #[test]
+    fn unconfirmed_ancestry() {
+        let mut grandparent = payment();
+        grandparent.input[0].sequence = Sequence::ZERO;
+        let mut parent = payment();
+        parent.input[0].previous_output = OutPoint::new(grandparent.txid(), 0);
+        parent.output[0].value = 95_000;
+        let mut tx = payment();
+        tx.input[0].previous_output = OutPoint::new(parent.txid(), 0);
+        tx.output[0] = TxOut { value: 85_000, script_pubkey: p2pkh(3) };
+
+        let mut unconfirmed = HashMap::new();
+        unconfirmed.insert(parent.txid(), parent.clone());
+        let report = score(&tx, &unconfirmed);
+        assert_eq!(report.findings, vec![Finding::UnconfirmedParent(parent.txid())]);
+        assert_eq!(report.level, RiskLevel::Medium);
+
+        // replaceability anywhere in the ancestry is inherited
+        unconfirmed.insert(grandparent.txid(), grandparent.clone());
+        let report = score(&tx, &unconfirmed);
+        assert_eq!(report.findings, vec![
+            Finding::UnconfirmedParent(parent.txid()),
+            Finding::SignalsRbf(grandparent.txid()),
+        ]);
+        assert_eq!(report.level, RiskLevel::High);
+
+        // a long chain of unconfirmed transactions
+        let mut unconfirmed = HashMap::new();
+        let mut tx = payment();
+        for _ in 0..MAX_UNCONFIRMED_ANCESTORS + 1 {
+            let txid = tx.txid();
+            unconfirmed.insert(txid, tx.clone());
+            tx.input[0].previous_output = OutPoint::new(txid, 0);
+        }
+        assert!(score(&tx, &unconfirmed).findings.contains(&Finding::TooManyAncestors));
+    }
+
+    #[test]
+    fn fees() {
+        let mut tx = payment();
+        tx.output[0].value = 99_000;
+        let report = score(&tx, &HashMap::new());
+        assert_eq!(report.findings, vec![Finding::LowFeeRate {
+            fee_rate: FeeRate::from_sat_per_kwu(1302),
+            target: FeeRate::from_sat_per_kwu(5_000),
+        }]);
+        assert_eq!(report.level, RiskLevel::Medium);
+
+        tx.output[0].value = 100_001;
+        assert_eq!(score(&tx, &HashMap::new()).findings, vec![Finding::OverspendsInputs]);
+
+        // without the value of every input the fee is unknown
+        tx.input[0].previous_output.vout = 1;
+        let report = score(&tx, &HashMap::new());
+        assert_eq!(report.findings, vec![Finding::UnknownPrevout(tx.input[0].previous_output)]);
+        assert_eq!(report.level, RiskLevel::High);
+    }
+
+    #[test]
+    fn non_standard() {
+        let mut tx = payment();
+        tx.version = 3;
+        assert_eq!(score(&tx, &HashMap::new()).findings, vec![Finding::NonStandard("version")]);
+
+        let mut tx = payment();
+        tx.input[0].script_sig = Builder::new().push_opcode(opcodes::all::OP_DUP).into_script();
+        assert_eq!(score(&tx, &HashMap::new()).findings, vec![Finding::NonStandard("scriptsig-not-pushonly")]);
+
+        let mut tx = payment();
+        tx.output.push(TxOut { value: 500, script_pubkey: p2pkh(2) });
+        assert_eq!(score(&tx, &HashMap::new()).findings, vec![Finding::NonStandard("dust")]);
+
+        let mut tx = payment();
+        tx.output[0].script_pubkey = Builder::new().push_opcode(opcodes::all::OP_PUSHNUM_1).into_script();
+        assert_eq!(score(&tx, &HashMap::new()).findings, vec![Finding::NonStandard("scriptpubkey")]);
+
+        let mut tx = payment();
+        tx.output.push(TxOut { value: 0, script_pubkey: Script::new_op_return(&[1; 80]) });
+        assert_eq!(score(&tx, &HashMap::new()).level, RiskLevel::Low);
+        tx.output.push(TxOut { value: 0, script_pubkey: Script::new_op_return(&[2]) });
+        let report = score(&tx, &HashMap::new());
+        assert_eq!(report.findings, vec![Finding::NonStandard("multi-op-return")]);
+        assert_eq!(report.level, RiskLevel::High);
+    }
+
+    #[test]
+    fn output_to_self() {
+        let mut tx = payment();
+        tx.output.push(TxOut { value: 5_000, script_pubkey: p2pkh(0) });
+        tx.output[0].value = 85_000;
+        let report = score(&tx, &HashMap::new());
+        assert_eq!(report.findings, vec![Finding::OutputToSelf(1)]);
+        // on its own not a reason to wait
+        assert_eq!(report.level, RiskLevel::Low);
+    }
+
+    #[test]
+    fn recently_seen() {
+        let tx = payment();
+        let unconfirmed = HashMap::new();
+        let context = ZeroConfContext {
+            unconfirmed: &unconfirmed,
+            target_fee_rate: FeeRate::from_sat_per_kwu(5_000),
+            seen_for_secs: 3,
+        };
+        let report = zero_conf_score(&tx, confirmed, &context);
+        assert_eq!(report.findings, vec![Finding::RecentlySeen { seen_for_secs: 3 }]);
+        assert_eq!(report.level, RiskLevel::Medium);
+    }
+}
@@ -0,0 +1,948 @@
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Signature hash computation
+//!
+//! Implementation of legacy, BIP143 segwit and BIP341 taproot key-path signature
+//! hashes. The [SighashCache] computes the parts of the BIP143 and BIP341 preimages
+//! which are shared between inputs only once, so signing every input of a transaction
+//! takes linear rather than quadratic time.
+//!
+
+
+use std::borrow::{Borrow, BorrowMut};
+use std::fmt;
+use std::io;
+use std::error;
+
+use hashes::{Hash, sha256, sha256d};
+use hash_types::SigHash;
+use blockdata::opcodes;
+use blockdata::script::Script;
+use blockdata::transaction::{Transaction, TxOut, SigHashType};
+use consensus::encode::{self, Encodable};
+use util::endian;
+use util::hash::HashTag;
+use util::taproot::TapSighashHash;
+
+/// Possible errors in computing the signature message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Could happen only by using the `*_encode_signing_data_to` methods with custom writers,
+    /// the hash engines used by the `*_signature_hash` methods never fail
+    Io(io::ErrorKind),
+    /// Requested index is greater than or equal to the number of inputs in the transaction
+    IndexOutOfInputsBounds {
+        /// Requested index
+        index: usize,
+        /// Number of transaction inputs
+        inputs_size: usize,
+    },
+    /// Legacy SIGHASH_SINGLE was requested for an input without a corresponding output. There
+    /// is no preimage to write in that case, the signature hash is the constant `1` instead
+    /// (see [SighashCache::legacy_signature_hash])
+    SingleWithoutCorrespondingOutput {
+        /// Requested index
+        index: usize,
+        /// Number of transaction outputs
+        outputs_size: usize,
+    },
+    /// The number of prevouts given for a taproot signature hash differs from the number of
+    /// transaction inputs
+    PrevoutsSize,
+    /// The single prevout given for a taproot signature hash is not the one of the input
+    /// being signed
+    PrevoutIndex,
+    /// A single prevout was given for a taproot signature hash, but the sighash type commits
+    /// to all of them, which is only the case without `SIGHASH_ANYONECANPAY`
+    PrevoutKind,
+    /// The byte is not a valid taproot sighash type
+    InvalidSigHashType(u8),
+    /// A P2WPKH signature hash was requested for a script which is not a P2WPKH program
+    NotP2wpkhScript,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref kind) => write!(f, "writer errored: {:?}", kind),
+            Error::IndexOutOfInputsBounds { index, inputs_size } =>
+                write!(f, "requested index ({}) is greater or equal than the number of transaction inputs ({})", index, inputs_size),
+            Error::SingleWithoutCorrespondingOutput { index, outputs_size } =>
+                write!(f, "SIGHASH_SINGLE for input ({}) without a corresponding output (number of outputs: {})", index, outputs_size),
+            Error::PrevoutsSize => write!(f, "number of supplied prevouts differs from the number of inputs in transaction"),
+            Error::PrevoutIndex => write!(f, "the single prevout supplied is not the one of the input being signed"),
+            Error::PrevoutKind => write!(f, "a single prevout has been provided but all prevouts are needed without `ANYONECANPAY`"),
+            Error::InvalidSigHashType(hash_type) => write!(f, "invalid taproot sighash type: {:#04x}", hash_type),
+            Error::NotP2wpkhScript => write!(f, "script is not a P2WPKH program"),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "writer errored",
+            Error::IndexOutOfInputsBounds { .. } => "input index out of bounds",
+            Error::SingleWithoutCorrespondingOutput { .. } => "SIGHASH_SINGLE without corresponding output",
+            Error::PrevoutsSize => "wrong number of prevouts",
+            Error::PrevoutIndex => "prevout index mismatch",
+            Error::PrevoutKind => "all prevouts needed",
+            Error::InvalidSigHashType(_) => "invalid taproot sighash type",
+            Error::NotP2wpkhScript => "not a P2WPKH script",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e.kind())
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for Error {
+    fn from(e: encode::Error) -> Error {
+        match e {
+            encode::Error::Io(e) => Error::Io(e.kind()),
+            // Encoding only ever fails because of the underlying writer
+            _ => Error::Io(io::ErrorKind::Other),
+        }
+    }
+}
+
+impl SigHash {
+    /// The signature hash committed to by a legacy `SIGHASH_SINGLE` signature for an input
+    /// without a corresponding output: the number `1` encoded as 32 little-endian bytes.
+    ///
+    /// Such a signature does not commit to any part of the transaction, so it can be replayed
+    /// onto any other transaction spending the same output with the same bug. Callers
+    /// computing legacy signature hashes should compare against this value and decide whether
+    /// they are willing to sign it.
+    pub fn single_bug() -> SigHash {
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        SigHash::from_inner(one)
+    }
+}
+
+/// Encodes a legacy script code the way Bitcoin Core's signature serializer does, with every
+/// OP_CODESEPARATOR removed.
+///
+/// The length prefix is computed before parsing, so for a script ending in a truncated push the
+/// prefix disagrees with the number of bytes written. This matches the reference
+/// implementation and must not be "fixed".
+fn encode_legacy_script_code<W: io::Write>(script: &Script, mut writer: W) -> Result<(), Error> {
+    let bytes = script.as_bytes();
+    let codeseparator = opcodes::all::OP_CODESEPARATOR.into_u8();
+
+    let mut separators = 0;
+    let mut segments = Vec::new();
+    let mut segment_start = 0;
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let opcode = bytes[pos];
+        pos += 1;
+        let size_len = if opcode == opcodes::all::OP_PUSHDATA1.into_u8() {
+            1
+        } else if opcode == opcodes::all::OP_PUSHDATA2.into_u8() {
+            2
+        } else if opcode == opcodes::all::OP_PUSHDATA4.into_u8() {
+            4
+        } else {
+            0
+        };
+        if bytes.len() - pos < size_len {
+            break;
+        }
+        let data_len = match size_len {
+            1 => bytes[pos] as usize,
+            2 => endian::slice_to_u16_le(&bytes[pos..pos + 2]) as usize,
+            4 => endian::slice_to_u32_le(&bytes[pos..pos + 4]) as usize,
+            _ if opcode < opcodes::all::OP_PUSHDATA1.into_u8() => opcode as usize,
+            _ => 0,
+        };
+        pos += size_len;
+        if bytes.len() - pos < data_len {
+            break;
+        }
+        pos += data_len;
+
+        if opcode == codeseparator {
+            separators += 1;
+            segments.push(segment_start..pos - 1);
+            segment_start = pos;
+        }
+    }
+    segments.push(segment_start..pos);
+
+    encode::VarInt((bytes.len() - separators) as u64).consensus_encode(&mut writer)?;
+    for segment in segments {
+        writer.write_all(&bytes[segment])?;
+    }
+    Ok(())
+}
+
+/// Hashtype of a taproot input, see BIP341
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum SchnorrSigHashType {
+    /// 0x0: Used when not explicitly specified, commits to the same data as [SchnorrSigHashType::All]
+    Default = 0x00,
+    /// 0x1: Sign all outputs
+    All = 0x01,
+    /// 0x2: Sign no outputs --- anyone can choose the destination
+    None = 0x02,
+    /// 0x3: Sign the output whose index matches this input's index
+    Single = 0x03,
+    /// 0x81: Sign all outputs but only this input
+    AllPlusAnyoneCanPay = 0x81,
+    /// 0x82: Sign no outputs and only this input
+    NonePlusAnyoneCanPay = 0x82,
+    /// 0x83: Sign one output and only this input
+    SinglePlusAnyoneCanPay = 0x83,
+}
+
+impl SchnorrSigHashType {
+    /// Parses a taproot sighash type. Unlike for legacy and segwit v0 signatures, any other
+    /// value makes the signature invalid.
+    pub fn from_u8(hash_ty: u8) -> Result<SchnorrSigHashType, Error> {
+        match hash_ty {
+            0x00 => Ok(SchnorrSigHashType::Default),
+            0x01 => Ok(SchnorrSigHashType::All),
+            0x02 => Ok(SchnorrSigHashType::None),
+            0x03 => Ok(SchnorrSigHashType::Single),
+            0x81 => Ok(SchnorrSigHashType::AllPlusAnyoneCanPay),
+            0x82 => Ok(SchnorrSigHashType::NonePlusAnyoneCanPay),
+            0x83 => Ok(SchnorrSigHashType::SinglePlusAnyoneCanPay),
+            x => Err(Error::InvalidSigHashType(x)),
+        }
+    }
+
+    /// Returns the sighash type as a byte
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// Splits the sighash flag into the "real" sighash flag and the ANYONECANPAY boolean
+    fn split_anyonecanpay_flag(self) -> (SchnorrSigHashType, bool) {
+        match self {
+            SchnorrSigHashType::Default => (SchnorrSigHashType::Default, false),
+            SchnorrSigHashType::All => (SchnorrSigHashType::All, false),
+            SchnorrSigHashType::None => (SchnorrSigHashType::None, false),
+            SchnorrSigHashType::Single => (SchnorrSigHashType::Single, false),
+            SchnorrSigHashType::AllPlusAnyoneCanPay => (SchnorrSigHashType::All, true),
+            SchnorrSigHashType::NonePlusAnyoneCanPay => (SchnorrSigHashType::None, true),
+            SchnorrSigHashType::SinglePlusAnyoneCanPay => (SchnorrSigHashType::Single, true),
+        }
+    }
+}
+
+/// The outputs spent by a transaction, which BIP341 signature hashes commit to
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Prevouts<'u> {
+    /// Only the output spent by the input being signed, with the index of that input. This is
+    /// enough for `SIGHASH_ANYONECANPAY` signatures only.
+    One(usize, &'u TxOut),
+    /// The outputs spent by every input, in input order
+    All(&'u [TxOut]),
+}
+
+impl<'u> Prevouts<'u> {
+    /// The output spent by the input at `input_index`
+    fn get(&self, input_index: usize) -> Result<&'u TxOut, Error> {
+        match *self {
+            Prevouts::One(index, prevout) if index == input_index => Ok(prevout),
+            Prevouts::One(..) => Err(Error::PrevoutIndex),
+            Prevouts::All(prevouts) => prevouts.get(input_index).ok_or(Error::PrevoutIndex),
+        }
+    }
+}
+
+/// Single SHA256 hashes of the transaction data committed to by BIP341 signature hashes
+#[derive(Debug)]
+struct TaprootCache {
+    prevouts: sha256::Hash,
+    sequences: sha256::Hash,
+    outputs: sha256::Hash,
+}
+
+/// Single SHA256 hashes of the spent outputs committed to by BIP341 signature hashes
+#[derive(Debug)]
+struct TaprootPrevoutsCache {
+    amounts: sha256::Hash,
+    script_pubkeys: sha256::Hash,
+}
+
+/// Efficiently calculates signature hashes for legacy, BIP143 segwit and BIP341 taproot inputs
+///
+/// For the generated sighashes to be valid, no fields in the transaction may change except for
+/// script_sig and witnesses.
+#[derive(Debug)]
+pub struct SighashCache<T: Borrow<Transaction>> {
+    /// Access to transaction required for various introspection
+    tx: T,
+    /// Hash of all the previous outputs, computed as required
+    hash_prevouts: Option<sha256d::Hash>,
+    /// Hash of all the input sequence nos, computed as required
+    hash_sequence: Option<sha256d::Hash>,
+    /// Hash of all the outputs in this transaction, computed as required
+    hash_outputs: Option<sha256d::Hash>,
+    /// Hashes of the transaction data for BIP341, computed as required
+    taproot_cache: Option<TaprootCache>,
+    /// Hashes of the spent outputs for BIP341, computed from the first complete set of
+    /// prevouts given
+    taproot_prevouts_cache: Option<TaprootPrevoutsCache>,
+}
+
+impl<T: Borrow<Transaction>> SighashCache<T> {
+    /// Create a new cache for the given transaction. Nothing is computed until a signature
+    /// hash is requested.
+    pub fn new(tx: T) -> Self {
+        SighashCache {
+            tx: tx,
+            hash_prevouts: None,
+            hash_sequence: None,
+            hash_outputs: None,
+            taproot_cache: None,
+            taproot_prevouts_cache: None,
+        }
+    }
+
+    /// Get a reference to the transaction being signed
+    pub fn transaction(&self) -> &Transaction {
+        self.tx.borrow()
+    }
+
+    /// Destroy the cache and recover the transaction
+    pub fn into_transaction(self) -> T {
+        self.tx
+    }
+
+    /// Calculate hash for prevouts
+    pub fn hash_prevouts(&mut self) -> sha256d::Hash {
+        let tx = self.tx.borrow();
+        *self.hash_prevouts.get_or_insert_with(|| {
+            let mut enc = sha256d::Hash::engine();
+            for txin in &tx.input {
+                txin.previous_output.consensus_encode(&mut enc).unwrap();
+            }
+            sha256d::Hash::from_engine(enc)
+        })
+    }
+
+    /// Calculate hash for input sequence values
+    pub fn hash_sequence(&mut self) -> sha256d::Hash {
+        let tx = self.tx.borrow();
+        *self.hash_sequence.get_or_insert_with(|| {
+            let mut enc = sha256d::Hash::engine();
+            for txin in &tx.input {
+                txin.sequence.consensus_encode(&mut enc).unwrap();
+            }
+            sha256d::Hash::from_engine(enc)
+        })
+    }
+
+    /// Calculate hash for outputs
+    pub fn hash_outputs(&mut self) -> sha256d::Hash {
+        let tx = self.tx.borrow();
+        *self.hash_outputs.get_or_insert_with(|| {
+            let mut enc = sha256d::Hash::engine();
+            for txout in &tx.output {
+                txout.consensus_encode(&mut enc).unwrap();
+            }
+            sha256d::Hash::from_engine(enc)
+        })
+    }
+
+    /// Encode the BIP143 signing data for any flag type into a given object implementing
+    /// [io::Write]. The hash of the written data is the signature hash.
+    ///
+    /// `script_code` is the witness script for P2WSH inputs. For P2WPKH inputs it is not the
+    /// output script but the P2PKH script of the same key hash, see
+    /// [Script::p2wpkh_script_code] and [SighashCache::p2wpkh_signature_hash].
+    pub fn segwit_encode_signing_data_to<W: io::Write>(
+        &mut self,
+        mut writer: W,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sighash_type: SigHashType,
+    ) -> Result<(), Error> {
+        let inputs_size = self.tx.borrow().input.len();
+        if input_index >= inputs_size {
+            return Err(Error::IndexOutOfInputsBounds { index: input_index, inputs_size: inputs_size });
+        }
+
+        let zero_hash = sha256d::Hash::default();
+        let (sighash, anyone_can_pay) = sighash_type.split_anyonecanpay_flag();
+
+        self.tx.borrow().version.consensus_encode(&mut writer)?;
+
+        if !anyone_can_pay {
+            self.hash_prevouts().consensus_encode(&mut writer)?;
+        } else {
+            zero_hash.consensus_encode(&mut writer)?;
+        }
+
+        if !anyone_can_pay && sighash != SigHashType::Single && sighash != SigHashType::None {
+            self.hash_sequence().consensus_encode(&mut writer)?;
+        } else {
+            zero_hash.consensus_encode(&mut writer)?;
+        }
+
+        {
+            let txin = &self.tx.borrow().input[input_index];
+
+            txin.previous_output.consensus_encode(&mut writer)?;
+            script_code.consensus_encode(&mut writer)?;
+            value.consensus_encode(&mut writer)?;
+            txin.sequence.consensus_encode(&mut writer)?;
+        }
+
+        if sighash != SigHashType::Single && sighash != SigHashType::None {
+            self.hash_outputs().consensus_encode(&mut writer)?;
+        } else if sighash == SigHashType::Single && input_index < self.tx.borrow().output.len() {
+            let mut single_enc = SigHash::engine();
+            self.tx.borrow().output[input_index].consensus_encode(&mut single_enc)?;
+            SigHash::from_engine(single_enc).consensus_encode(&mut writer)?;
+        } else {
+            zero_hash.consensus_encode(&mut writer)?;
+        }
+
+        self.tx.borrow().lock_time.consensus_encode(&mut writer)?;
+        sighash_type.to_u32().consensus_encode(&mut writer)?;
+        Ok(())
+    }
+
+    /// Compute the BIP143 sighash for any flag type.
+    ///
+    /// Returns an error rather than panicking if `input_index` is out of bounds. See
+    /// [SighashCache::segwit_encode_signing_data_to] for what `script_code` is.
+    pub fn segwit_signature_hash(
+        &mut self,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sighash_type: SigHashType,
+    ) -> Result<SigHash, Error> {
+        let mut enc = SigHash::engine();
+        self.segwit_encode_signing_data_to(&mut enc, input_index, script_code, value, sighash_type)?;
+        Ok(SigHash::from_engine(enc))
+    }
+
+    /// Compute the BIP143 sighash of a P2WPKH input, deriving the script code from
+    /// `script_pubkey`: the output script for native P2WPKH, the redeem script for
+    /// P2SH-P2WPKH.
+    pub fn p2wpkh_signature_hash(
+        &mut self,
+        input_index: usize,
+        script_pubkey: &Script,
+        value: u64,
+        sighash_type: SigHashType,
+    ) -> Result<SigHash, Error> {
+        let script_code = script_pubkey.p2wpkh_script_code().ok_or(Error::NotP2wpkhScript)?;
+        self.segwit_signature_hash(input_index, &script_code, value, sighash_type)
+    }
+
+    fn taproot_cache(&mut self) -> &TaprootCache {
+        let tx = self.tx.borrow();
+        self.taproot_cache.get_or_insert_with(|| {
+            let mut prevouts = sha256::Hash::engine();
+            let mut sequences = sha256::Hash::engine();
+            for txin in &tx.input {
+                txin.previous_output.consensus_encode(&mut prevouts).unwrap();
+                txin.sequence.consensus_encode(&mut sequences).unwrap();
+            }
+            let mut outputs = sha256::Hash::engine();
+            for txout in &tx.output {
+                txout.consensus_encode(&mut outputs).unwrap();
+            }
+            TaprootCache {
+                prevouts: sha256::Hash::from_engine(prevouts),
+                sequences: sha256::Hash::from_engine(sequences),
+                outputs: sha256::Hash::from_engine(outputs),
+            }
+        })
+    }
+
+    fn taproot_prevouts_cache(&mut self, prevouts: &[TxOut]) -> &TaprootPrevoutsCache {
+        self.taproot_prevouts_cache.get_or_insert_with(|| {
+            let mut amounts = sha256::Hash::engine();
+            let mut script_pubkeys = sha256::Hash::engine();
+            for prevout in prevouts {
+                prevout.value.consensus_encode(&mut amounts).unwrap();
+                prevout.script_pubkey.consensus_encode(&mut script_pubkeys).unwrap();
+            }
+            TaprootPrevoutsCache {
+                amounts: sha256::Hash::from_engine(amounts),
+                script_pubkeys: sha256::Hash::from_engine(script_pubkeys),
+            }
+        })
+    }
+
+    /// Encode the BIP341 signing data of a key-path spend into a given object implementing
+    /// [io::Write]. The `TapSighash` tagged hash of the written data is the signature hash.
+    ///
+    /// Without `SIGHASH_ANYONECANPAY` `prevouts` must be [Prevouts::All], and the hashes of the
+    /// spent outputs are cached, so every call on the same cache must be given the same ones.
+    /// Annexes are not supported yet: the signing data always commits to there being none.
+    pub fn taproot_encode_signing_data_to<W: io::Write>(
+        &mut self,
+        mut writer: W,
+        input_index: usize,
+        prevouts: &Prevouts,
+        sighash_type: SchnorrSigHashType,
+    ) -> Result<(), Error> {
+        let (inputs_size, outputs_size) = {
+            let tx = self.tx.borrow();
+            (tx.input.len(), tx.output.len())
+        };
+        if input_index >= inputs_size {
+            return Err(Error::IndexOutOfInputsBounds { index: input_index, inputs_size: inputs_size });
+        }
+        let (sighash, anyone_can_pay) = sighash_type.split_anyonecanpay_flag();
+        let all_prevouts = match *prevouts {
+            Prevouts::All(all) if all.len() != inputs_size => return Err(Error::PrevoutsSize),
+            Prevouts::All(all) => all,
+            Prevouts::One(..) if !anyone_can_pay => return Err(Error::PrevoutKind),
+            Prevouts::One(..) => &[],
+        };
+        let prevout = prevouts.get(input_index)?;
+        if sighash == SchnorrSigHashType::Single && input_index >= outputs_size {
+            return Err(Error::SingleWithoutCorrespondingOutput { index: input_index, outputs_size: outputs_size });
+        }
+
+        // epoch
+        0u8.consensus_encode(&mut writer)?;
+
+        // hash_type (1).
+        sighash_type.as_u8().consensus_encode(&mut writer)?;
+
+        // nVersion (4): the nVersion of the transaction.
+        // nLockTime (4): the nLockTime of the transaction.
+        self.tx.borrow().version.consensus_encode(&mut writer)?;
+        self.tx.borrow().lock_time.consensus_encode(&mut writer)?;
+
+        // If the hash_type & 0x80 does not equal SIGHASH_ANYONECANPAY:
+        //     sha_prevouts (32): the SHA256 of the serialization of all input outpoints.
+        //     sha_amounts (32): the SHA256 of the serialization of all spent output amounts.
+        //     sha_scriptpubkeys (32): the SHA256 of the serialization of all spent output scriptPubKeys.
+        //     sha_sequences (32): the SHA256 of the serialization of all input nSequence.
+        if !anyone_can_pay {
+            let (prevouts_hash, sequences_hash) = {
+                let cache = self.taproot_cache();
+                (cache.prevouts, cache.sequences)
+            };
+            let cache = self.taproot_prevouts_cache(all_prevouts);
+            writer.write_all(&prevouts_hash[..])?;
+            writer.write_all(&cache.amounts[..])?;
+            writer.write_all(&cache.script_pubkeys[..])?;
+            writer.write_all(&sequences_hash[..])?;
+        }
+
+        // If hash_type & 3 does not equal SIGHASH_NONE or SIGHASH_SINGLE:
+        //     sha_outputs (32): the SHA256 of the serialization of all outputs in CTxOut format.
+        if sighash != SchnorrSigHashType::None && sighash != SchnorrSigHashType::Single {
+            writer.write_all(&self.taproot_cache().outputs[..])?;
+        }
+
+        // spend_type (1): equal to (ext_flag * 2) + annex_present, where annex_present is 0
+        // if no annex is present, or 1 otherwise
+        0u8.consensus_encode(&mut writer)?;
+
+        // If hash_type & 0x80 equals SIGHASH_ANYONECANPAY:
+        //     outpoint (36): the COutPoint of this input (32-byte hash + 4-byte little-endian).
+        //     amount (8): value of the previous output spent by this input.
+        //     scriptPubKey (35): scriptPubKey of the previous output spent by this input, serialized as script inside CTxOut. Its size is always 35 bytes.
+        //     nSequence (4): nSequence of this input.
+        // If hash_type & 0x80 does not equal SIGHASH_ANYONECANPAY:
+        //     input_index (4): index of this input in the transaction input vector. Index of the first input is 0.
+        if anyone_can_pay {
+            let txin = &self.tx.borrow().input[input_index];
+            txin.previous_output.consensus_encode(&mut writer)?;
+            prevout.value.consensus_encode(&mut writer)?;
+            prevout.script_pubkey.consensus_encode(&mut writer)?;
+            txin.sequence.consensus_encode(&mut writer)?;
+        } else {
+            (input_index as u32).consensus_encode(&mut writer)?;
+        }
+
+        // If hash_type & 3 equals SIGHASH_SINGLE:
+        //     sha_single_output (32): the SHA256 of the corresponding output in CTxOut format.
+        if sighash == SchnorrSigHashType::Single {
+            let mut enc = sha256::Hash::engine();
+            self.tx.borrow().output[input_index].consensus_encode(&mut enc)?;
+            writer.write_all(&sha256::Hash::from_engine(enc)[..])?;
+        }
+
+        Ok(())
+    }
+
+    /// Compute the BIP341 signature hash of a key-path spend of the input at `input_index`.
+    ///
+    /// See [SighashCache::taproot_encode_signing_data_to] for the requirements on `prevouts`.
+    pub fn taproot_key_spend_signature_hash(
+        &mut self,
+        input_index: usize,
+        prevouts: &Prevouts,
+        sighash_type: SchnorrSigHashType,
+    ) -> Result<TapSighashHash, Error> {
+        let mut enc = HashTag::TapSighash.engine();
+        self.taproot_encode_signing_data_to(&mut enc, input_index, prevouts, sighash_type)?;
+        Ok(TapSighashHash::from_engine(enc))
+    }
+
+    /// Encode the legacy signing data for any flag type into a given object implementing
+    /// [io::Write]. The hash of the written data is the signature hash.
+    ///
+    /// The `sighash_u32` is taken as a raw integer rather than a [SigHashType] because
+    /// non-standard flag values are committed to as-is.
+    ///
+    /// As in the reference implementation, every OP_CODESEPARATOR is removed from
+    /// `script_pubkey` before it is committed to. Choosing the script code, i.e. cutting
+    /// `script_pubkey` after the last executed OP_CODESEPARATOR, is left to the caller.
+    ///
+    /// Writes nothing and returns [Error::SingleWithoutCorrespondingOutput] if the
+    /// SIGHASH_SINGLE bug applies, since the signature hash then isn't the hash of any data.
+    /// Use [SighashCache::legacy_signature_hash] to have it handled.
+    pub fn legacy_encode_signing_data_to<W: io::Write>(
+        &self,
+        mut writer: W,
+        input_index: usize,
+        script_pubkey: &Script,
+        sighash_u32: u32,
+    ) -> Result<(), Error> {
+        let tx = self.tx.borrow();
+        if input_index >= tx.input.len() {
+            return Err(Error::IndexOutOfInputsBounds { index: input_index, inputs_size: tx.input.len() });
+        }
+
+        let (sighash, anyone_can_pay) = SigHashType::from_u32_consensus(sighash_u32).split_anyonecanpay_flag();
+        if sighash == SigHashType::Single && input_index >= tx.output.len() {
+            return Err(Error::SingleWithoutCorrespondingOutput { index: input_index, outputs_size: tx.output.len() });
+        }
+
+        // The preimage is the serialization of a modified copy of the transaction, which we
+        // write out field by field rather than clone. It never has witnesses, so it always
+        // uses the legacy serialization.
+        tx.version.consensus_encode(&mut writer)?;
+
+        let empty_script = Script::new();
+        let signed_input = |n: usize| -> (&Script, u32) {
+            let input = &tx.input[n];
+            if n == input_index {
+                (script_pubkey, input.sequence.0)
+            } else if sighash == SigHashType::Single || sighash == SigHashType::None {
+                (&empty_script, 0)
+            } else {
+                (&empty_script, input.sequence.0)
+            }
+        };
+        if anyone_can_pay {
+            encode::VarInt(1).consensus_encode(&mut writer)?;
+            tx.input[input_index].previous_output.consensus_encode(&mut writer)?;
+            encode_legacy_script_code(script_pubkey, &mut writer)?;
+            tx.input[input_index].sequence.consensus_encode(&mut writer)?;
+        } else {
+            encode::VarInt(tx.input.len() as u64).consensus_encode(&mut writer)?;
+            for (n, input) in tx.input.iter().enumerate() {
+                let (script_sig, sequence) = signed_input(n);
+                input.previous_output.consensus_encode(&mut writer)?;
+                encode_legacy_script_code(script_sig, &mut writer)?;
+                sequence.consensus_encode(&mut writer)?;
+            }
+        }
+
+        match sighash {
+            SigHashType::All => {
+                tx.output.consensus_encode(&mut writer)?;
+            }
+            SigHashType::Single => {
+                // sign all outputs up to and including this one, but erase
+                // all of them except for this one
+                let blank_output = TxOut::default();
+                encode::VarInt(input_index as u64 + 1).consensus_encode(&mut writer)?;
+                for _ in 0..input_index {
+                    blank_output.consensus_encode(&mut writer)?;
+                }
+                tx.output[input_index].consensus_encode(&mut writer)?;
+            }
+            SigHashType::None => {
+                encode::VarInt(0).consensus_encode(&mut writer)?;
+            }
+            _ => unreachable!()
+        }
+
+        tx.lock_time.consensus_encode(&mut writer)?;
+        sighash_u32.consensus_encode(&mut writer)?;
+        Ok(())
+    }
+
+    /// Compute the legacy sighash for any flag type.
+    ///
+    /// Returns an error rather than panicking if `input_index` is out of bounds. For
+    /// SIGHASH_SINGLE on an input without a corresponding output this returns the constant
+    /// `1` (little-endian) hash which the consensus rules use in that case.
+    pub fn legacy_signature_hash(
+        &self,
+        input_index: usize,
+        script_pubkey: &Script,
+        sighash_u32: u32,
+    ) -> Result<SigHash, Error> {
+        let mut enc = SigHash::engine();
+        match self.legacy_encode_signing_data_to(&mut enc, input_index, script_pubkey, sighash_u32) {
+            Ok(()) => Ok(SigHash::from_engine(enc)),
+            Err(Error::SingleWithoutCorrespondingOutput { .. }) => Ok(SigHash::single_bug()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<T: BorrowMut<Transaction>> SighashCache<T> {
+    /// When the SighashCache is initialized with a mutable reference to a transaction instead of
+    /// a regular reference, this method is available to allow modification to the witnesses.
+    ///
+    /// This allows in-line signing such as
+    /// ```
+    /// use monacoin::blockdata::locktime::LockTime;
+    /// use monacoin::blockdata::transaction::{Transaction, SigHashType};
+    /// use monacoin::util::sighash::SighashCache;
+    /// use monacoin::Script;
+    ///
+    /// let mut tx_to_sign = Transaction { version: 2, lock_time: LockTime::ZERO, input: Vec::new(), output: Vec::new() };
+    /// let input_count = tx_to_sign.input.len();
+    ///
+    /// let mut sig_hasher = SighashCache::new(&mut tx_to_sign);
+    /// for inp in 0..input_count {
+    ///     let prevout_script = Script::new();
+    ///     let _sighash = sig_hasher.segwit_signature_hash(inp, &prevout_script, 42, SigHashType::All).unwrap();
+    ///     // ... sign the sighash
+    ///     sig_hasher.access_witness(inp).push(Vec::new());
+    /// }
+    /// ```
+    pub fn access_witness(&mut self, input_index: usize) -> &mut Vec<Vec<u8>> {
+        &mut self.tx.borrow_mut().input[input_index].witness
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+    use hash_types::SigHash;
+    use blockdata::script::Script;
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{Transaction, SigHashType};
+    use consensus::encode::deserialize;
+    use util::taproot::TapSighashHash;
+
+    use super::*;
+
+    fn run_test_sighash_bip143(tx: &str, script: &str, input_index: usize, value: u64, hash_type: u32, expected_result: &str) {
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(tx).unwrap()[..]).unwrap();
+        let script = Script::from(Vec::<u8>::from_hex(script).unwrap());
+        let expected_result = hex_hash!(SigHash, expected_result);
+        let mut cache = SighashCache::new(&tx);
+        let sighash_type = SigHashType::from_u32_consensus(hash_type);
+        let actual_result = cache.segwit_signature_hash(input_index, &script, value, sighash_type).unwrap();
+        assert_eq!(actual_result, expected_result);
+
+        let mut preimage = Vec::new();
+        cache.segwit_encode_signing_data_to(&mut preimage, input_index, &script, value, sighash_type).unwrap();
+        assert_eq!(SigHash::hash(&preimage), expected_result);
+    }
+
+    #[test]
+    fn bip143_official_vectors() {
+        // Native P2WPKH
+        run_test_sighash_bip143(
+            "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000",
+            "76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac",
+            1, 600_000_000, 0x01,
+            "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670",
+        );
+        // P2SH-P2WPKH
+        run_test_sighash_bip143(
+            "0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a54770100000000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000",
+            "76a91479091972186c449eb1ded22b78e40d009bdf008988ac",
+            0, 1_000_000_000, 0x01,
+            "64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6",
+        );
+        // P2SH-P2WSH 6-of-6 multisig, all six sighash types
+        let tx = "010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f05000000001976a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000";
+        let script = "56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af9602b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b56ae";
+        run_test_sighash_bip143(tx, script, 0, 987654321, 0x01, "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c");
+        run_test_sighash_bip143(tx, script, 0, 987654321, 0x02, "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36");
+        run_test_sighash_bip143(tx, script, 0, 987654321, 0x03, "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea");
+        run_test_sighash_bip143(tx, script, 0, 987654321, 0x81, "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e");
+        run_test_sighash_bip143(tx, script, 0, 987654321, 0x82, "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a");
+        run_test_sighash_bip143(tx, script, 0, 987654321, 0x83, "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b");
+    }
+
+    #[test]
+    fn p2wpkh_script_code() {
+        // Native P2WPKH vector of BIP143
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(
+            "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000"
+        ).unwrap()[..]).unwrap();
+        let spk = Script::from(Vec::<u8>::from_hex("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1").unwrap());
+        let mut cache = SighashCache::new(&tx);
+        assert_eq!(
+            cache.p2wpkh_signature_hash(1, &spk, 600_000_000, SigHashType::All),
+            Ok(hex_hash!(SigHash, "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"))
+        );
+        assert_eq!(cache.p2wpkh_signature_hash(1, &spk.to_p2sh(), 600_000_000, SigHashType::All), Err(Error::NotP2wpkhScript));
+
+        // P2SH-P2WPKH vector of BIP143, from the output script and the redeem script
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(
+            "0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a54770100000000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000"
+        ).unwrap()[..]).unwrap();
+        let redeem_script = Script::from(Vec::<u8>::from_hex("001479091972186c449eb1ded22b78e40d009bdf0089").unwrap());
+        assert_eq!(
+            redeem_script.to_p2sh(),
+            Script::from(Vec::<u8>::from_hex("a9144733f37cf4db86fbc2efed2500b4f4e49f31202387").unwrap())
+        );
+        assert_eq!(
+            redeem_script.p2wpkh_script_code(),
+            Some(Script::from(Vec::<u8>::from_hex("76a91479091972186c449eb1ded22b78e40d009bdf008988ac").unwrap()))
+        );
+        let mut cache = SighashCache::new(&tx);
+        assert_eq!(
+            cache.p2wpkh_signature_hash(0, &redeem_script, 1_000_000_000, SigHashType::All),
+            Ok(hex_hash!(SigHash, "64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6"))
+        );
+    }
+
+    #[test]
+    fn segwit_out_of_bounds() {
+        let tx = Transaction { version: 1, lock_time: LockTime::ZERO, input: vec![], output: vec![] };
+        let mut cache = SighashCache::new(tx);
+        assert_eq!(
+            cache.segwit_signature_hash(0, &Script::new(), 0, SigHashType::All),
+            Err(Error::IndexOutOfInputsBounds { index: 0, inputs_size: 0 })
+        );
+    }
+
+    #[test]
+    fn legacy_matches_transaction_signature_hash() {
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(
+            "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000"
+        ).unwrap()[..]).unwrap();
+        let script = Script::from(Vec::<u8>::from_hex("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap());
+        let cache = SighashCache::new(&tx);
+        for &hash_type in &[0x01, 0x02, 0x03, 0x81, 0x82, 0x83, 0x00, 0xff] {
+            for input_index in 0..tx.input.len() {
+                assert_eq!(
+                    cache.legacy_signature_hash(input_index, &script, hash_type).unwrap(),
+                    tx.signature_hash(input_index, &script, hash_type)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn legacy_sighash_single_bug() {
+        // Two inputs, one output: SIGHASH_SINGLE on the second input signs the constant 1
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(
+            "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff01202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac11000000"
+        ).unwrap()[..]).unwrap();
+        let cache = SighashCache::new(&tx);
+        let one = hex_hash!(SigHash, "0100000000000000000000000000000000000000000000000000000000000000");
+
+        assert_eq!(cache.legacy_signature_hash(1, &Script::new(), 0x03), Ok(one));
+        assert_eq!(cache.legacy_signature_hash(1, &Script::new(), 0x83), Ok(one));
+        assert!(cache.legacy_signature_hash(0, &Script::new(), 0x03).unwrap() != one);
+
+        let mut preimage = Vec::new();
+        assert_eq!(
+            cache.legacy_encode_signing_data_to(&mut preimage, 1, &Script::new(), 0x03),
+            Err(Error::SingleWithoutCorrespondingOutput { index: 1, outputs_size: 1 })
+        );
+        assert!(preimage.is_empty());
+
+        assert_eq!(
+            cache.legacy_signature_hash(2, &Script::new(), 0x03),
+            Err(Error::IndexOutOfInputsBounds { index: 2, inputs_size: 2 })
+        );
+    }
+
+    #[test]
+    fn bip341_key_spending_vectors() {
+        // keyPathSpending vector of the BIP341 wallet test vectors
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(
+            "02000000097de20cbff686da83a54981d2b9bab3586f4ca7e48f57f5b55963115f3b334e9c010000000000000000d7b7cab57b1393ace2d064f4d4a2cb8af6def61273e127517d44759b6dafdd990000000000fffffffff8e1f583384333689228c5d28eac13366be082dc57441760d957275419a418420000000000fffffffff0689180aa63b30cb162a73c6d2a38b7eeda2a83ece74310fda0843ad604853b0100000000feffffffaa5202bdf6d8ccd2ee0f0202afbbb7461d9264a25e5bfd3c5a52ee1239e0ba6c0000000000feffffff956149bdc66faa968eb2be2d2faa29718acbfe3941215893a2a3446d32acd050000000000000000000e664b9773b88c09c32cb70a2a3e4da0ced63b7ba3b22f848531bbb1d5d5f4c94010000000000000000e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7eadfd4eabf0000000000ffffffffa778eb6a263dc090464cd125c466b5a99667720b1c110468831d058aa1b82af10100000000ffffffff0200ca9a3b000000001976a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac807840cb0000000020ac9a87f5594be208f8532db38cff670c450ed2fea8fcdefcc9a663f78bab962b0065cd1d"
+        ).unwrap()[..]).unwrap();
+        let utxos: Vec<TxOut> = [
+            ("512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343", 420000000),
+            ("5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3", 462000000),
+            ("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac", 294000000),
+            ("5120e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e", 504000000),
+            ("512091b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605", 630000000),
+            ("00147dd65592d0ab2fe0d0257d571abf032cd9db93dc", 378000000),
+            ("512075169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831", 672000000),
+            ("5120712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5", 546000000),
+            ("512077e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220", 588000000),
+        ].iter().map(|&(spk, value)| TxOut {
+            value: value,
+            script_pubkey: Script::from(Vec::<u8>::from_hex(spk).unwrap()),
+        }).collect();
+
+        let mut cache = SighashCache::new(&tx);
+        for &(input_index, hash_type, expected) in &[
+            (0, 0x03, "2514a6272f85cfa0f45eb907fcb0d121b808ed37c6ea160a5a9046ed5526d555"),
+            (1, 0x83, "325a644af47e8a5a2591cda0ab0723978537318f10e6a63d4eed783b96a71a4d"),
+            (3, 0x01, "bf013ea93474aa67815b1b6cc441d23b64fa310911d991e713cd34c7f5d46669"),
+            (4, 0x00, "4f900a0bae3f1446fd48490c2958b5a023228f01661cda3496a11da502a7f7ef"),
+            (6, 0x02, "15f25c298eb5cdc7eb1d638dd2d45c97c4c59dcaec6679cfc16ad84f30876b85"),
+            (7, 0x82, "cd292de50313804dabe4685e83f923d2969577191a3e1d2882220dca88cbeb10"),
+            (8, 0x81, "cccb739eca6c13a8a89e6e5cd317ffe55669bbda23f2fd37b0f18755e008edd2"),
+        ] {
+            let sighash_type = SchnorrSigHashType::from_u8(hash_type).unwrap();
+            let expected = TapSighashHash::from_hex(expected).unwrap();
+            assert_eq!(
+                cache.taproot_key_spend_signature_hash(input_index, &Prevouts::All(&utxos), sighash_type),
+                Ok(expected)
+            );
+            if hash_type & 0x80 != 0 {
+                let one = Prevouts::One(input_index, &utxos[input_index]);
+                assert_eq!(cache.taproot_key_spend_signature_hash(input_index, &one, sighash_type), Ok(expected));
+            }
+        }
+
+        let all = SchnorrSigHashType::All;
+        let acp = SchnorrSigHashType::AllPlusAnyoneCanPay;
+        assert_eq!(
+            cache.taproot_key_spend_signature_hash(0, &Prevouts::One(0, &utxos[0]), all),
+            Err(Error::PrevoutKind)
+        );
+        assert_eq!(
+            cache.taproot_key_spend_signature_hash(0, &Prevouts::One(1, &utxos[1]), acp),
+            Err(Error::PrevoutIndex)
+        );
+        assert_eq!(
+            cache.taproot_key_spend_signature_hash(0, &Prevouts::All(&utxos[1..]), all),
+            Err(Error::PrevoutsSize)
+        );
+        assert_eq!(
+            cache.taproot_key_spend_signature_hash(2, &Prevouts::All(&utxos), SchnorrSigHashType::Single),
+            Err(Error::SingleWithoutCorrespondingOutput { index: 2, outputs_size: 2 })
+        );
+        assert_eq!(
+            cache.taproot_key_spend_signature_hash(9, &Prevouts::All(&utxos), all),
+            Err(Error::IndexOutOfInputsBounds { index: 9, inputs_size: 9 })
+        );
+        assert_eq!(SchnorrSigHashType::from_u8(0x04), Err(Error::InvalidSigHashType(0x04)));
+    }
+}
@@ -0,0 +1,620 @@
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Taproot
+//!
+//! Tagged hashes, leaf versions, x-only key tweaking, script trees and control
+//! blocks for taproot outputs (BIP341). Signature hashes of key-path spends are
+//! computed in [util::sighash](::util::sighash).
+//!
+
+
+use std::fmt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error;
+
+use hashes::{Hash, HashEngine, sha256};
+use secp256k1::{self, Secp256k1, Verification};
+use blockdata::script::Script;
+use consensus::encode::Encodable;
+use util::hash::HashTag;
+
+pub use secp256k1::schnorrsig::PublicKey as XOnlyPublicKey;
+
+/// The leaf version of tapscript, the only leaf version with defined semantics
+pub const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+/// The first byte of a taproot annex, and so never a valid leaf version
+pub const TAPROOT_ANNEX_PREFIX: u8 = 0x50;
+/// The bits of the first control block byte which hold the leaf version
+pub const TAPROOT_LEAF_MASK: u8 = 0xfe;
+/// Size of a control block without any merkle branch
+pub const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+/// Size of each merkle branch element of a control block
+pub const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+/// The maximum number of merkle branch elements of a control block, i.e. the
+/// maximum depth of a script tree
+pub const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+/// Size of the largest possible control block
+pub const TAPROOT_CONTROL_MAX_SIZE: usize = TAPROOT_CONTROL_BASE_SIZE + TAPROOT_CONTROL_NODE_SIZE * TAPROOT_CONTROL_MAX_NODE_COUNT;
+
+hash_newtype!(TapLeafHash, sha256::Hash, 32, doc="Taproot-tagged hash of a script leaf");
+hash_newtype!(TapBranchHash, sha256::Hash, 32, doc="Taproot-tagged hash of a node of the script tree");
+hash_newtype!(TapTweakHash, sha256::Hash, 32, doc="Taproot-tagged hash of an internal key and a merkle root, used to tweak the key");
+hash_newtype!(TapSighashHash, sha256::Hash, 32, doc="Taproot-tagged hash of the signature message of a taproot input");
+
+/// Taproot-related errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The leaf version is odd, or collides with the annex prefix
+    InvalidLeafVersion(u8),
+    /// A control block is not 33 bytes plus a multiple of 32 bytes long, or
+    /// longer than [TAPROOT_CONTROL_MAX_SIZE]
+    InvalidControlBlockSize(usize),
+    /// The internal key of a control block is not a valid x-only key
+    InvalidInternalKey(secp256k1::Error),
+    /// A node was added deeper than [TAPROOT_CONTROL_MAX_NODE_COUNT]
+    InvalidMerkleTreeDepth(usize),
+    /// Nodes were not added to a [TaprootBuilder] in depth-first order: the
+    /// node at the given depth still lacks a sibling
+    NodeNotInDfsOrder(usize),
+    /// A node was added to a [TaprootBuilder] whose tree was already complete
+    OverCompleteTree(usize),
+    /// A [TaprootBuilder] was finalized while the node at the given depth
+    /// still lacks a sibling
+    IncompleteTree(usize),
+    /// A [TaprootBuilder] was finalized without any node
+    EmptyTree,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidLeafVersion(v) => write!(f, "invalid taproot leaf version {:#04x}", v),
+            Error::InvalidControlBlockSize(size) => write!(f, "invalid control block size {}", size),
+            Error::InvalidInternalKey(ref e) => write!(f, "invalid internal x-only key: {}", e),
+            Error::InvalidMerkleTreeDepth(depth) => write!(f,
+                "merkle tree depth {} exceeds the maximum of {}", depth, TAPROOT_CONTROL_MAX_NODE_COUNT,
+            ),
+            Error::NodeNotInDfsOrder(depth) => write!(f,
+                "nodes not added in depth-first order, the node at depth {} has no sibling", depth,
+            ),
+            Error::OverCompleteTree(depth) => write!(f, "node at depth {} added to a complete tree", depth),
+            Error::IncompleteTree(depth) => write!(f, "incomplete tree, the node at depth {} has no sibling", depth),
+            Error::EmptyTree => write!(f, "empty tree"),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            Error::InvalidInternalKey(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+/// The version of a script leaf, committed to in its [TapLeafHash]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LeafVersion(u8);
+
+impl LeafVersion {
+    /// Creates a leaf version, which must be even and must not be [TAPROOT_ANNEX_PREFIX]
+    pub fn from_u8(version: u8) -> Result<LeafVersion, Error> {
+        if version & TAPROOT_LEAF_MASK == version && version != TAPROOT_ANNEX_PREFIX {
+            Ok(LeafVersion(version))
+        } else {
+            Err(Error::InvalidLeafVersion(version))
+        }
+    }
+
+    /// Returns the version as a byte
+    pub fn as_u8(self) -> u8 {
+        self.0
+    }
+}
+
+/// Tapscript, [TAPROOT_LEAF_TAPSCRIPT]
+impl Default for LeafVersion {
+    fn default() -> LeafVersion {
+        LeafVersion(TAPROOT_LEAF_TAPSCRIPT)
+    }
+}
+
+impl TapLeafHash {
+    /// Computes the leaf hash of a script with the given leaf version
+    pub fn from_script(script: &Script, version: LeafVersion) -> TapLeafHash {
+        let mut engine = HashTag::TapLeaf.engine();
+        version.as_u8().consensus_encode(&mut engine).expect("engines don't error");
+        script.consensus_encode(&mut engine).expect("engines don't error");
+        TapLeafHash::from_engine(engine)
+    }
+}
+
+impl TapBranchHash {
+    /// Computes the hash of a branch from the hashes of its children, which
+    /// are sorted first so that the order of the children doesn't matter
+    pub fn from_node_hashes(a: sha256::Hash, b: sha256::Hash) -> TapBranchHash {
+        let mut engine = HashTag::TapBranch.engine();
+        if a < b {
+            engine.input(&a[..]);
+            engine.input(&b[..]);
+        } else {
+            engine.input(&b[..]);
+            engine.input(&a[..]);
+        }
+        TapBranchHash::from_engine(engine)
+    }
+}
+
+impl TapTweakHash {
+    /// Computes the tweak for an internal key and the merkle root of its
+    /// script tree; with no script tree the tweak commits to the key alone
+    pub fn from_key_and_tweak(internal_key: XOnlyPublicKey, merkle_root: Option<TapBranchHash>) -> TapTweakHash {
+        let mut engine = HashTag::TapTweak.engine();
+        engine.input(&internal_key.serialize());
+        if let Some(root) = merkle_root {
+            engine.input(&root[..]);
+        }
+        TapTweakHash::from_engine(engine)
+    }
+}
+
+/// Tweaking of x-only public keys with a taproot commitment
+pub trait TapTweak {
+    /// Tweaks an internal key with the merkle root of its script tree, if
+    /// any, returning the output key and whether its y coordinate is odd
+    fn tap_tweak<C: Verification>(self, secp: &Secp256k1<C>, merkle_root: Option<TapBranchHash>) -> (XOnlyPublicKey, bool);
+}
+
+impl TapTweak for XOnlyPublicKey {
+    fn tap_tweak<C: Verification>(self, secp: &Secp256k1<C>, merkle_root: Option<TapBranchHash>) -> (XOnlyPublicKey, bool) {
+        let tweak = TapTweakHash::from_key_and_tweak(self, merkle_root);
+        let mut output_key = self;
+        // Fails with negligible probability, when the tweak is not below the curve order
+        let parity = output_key.tweak_add_assign(secp, &tweak[..]).expect("tap tweak failed");
+        (output_key, parity)
+    }
+}
+
+/// A node of a script tree under construction, with the leaves below it and
+/// their merkle branches up to this node
+#[derive(Debug, Clone)]
+struct NodeInfo {
+    hash: sha256::Hash,
+    leaves: Vec<LeafInfo>,
+}
+
+#[derive(Debug, Clone)]
+struct LeafInfo {
+    script: Script,
+    ver: LeafVersion,
+    merkle_branch: Vec<sha256::Hash>,
+}
+
+impl NodeInfo {
+    fn combine(a: NodeInfo, b: NodeInfo) -> Result<NodeInfo, Error> {
+        let hash = TapBranchHash::from_node_hashes(a.hash, b.hash);
+        let (a_hash, b_hash) = (a.hash, b.hash);
+        let mut leaves = Vec::with_capacity(a.leaves.len() + b.leaves.len());
+        for (mut leaf, sibling) in a.leaves.into_iter().map(|l| (l, b_hash))
+            .chain(b.leaves.into_iter().map(|l| (l, a_hash)))
+        {
+            if leaf.merkle_branch.len() >= TAPROOT_CONTROL_MAX_NODE_COUNT {
+                return Err(Error::InvalidMerkleTreeDepth(leaf.merkle_branch.len() + 1));
+            }
+            leaf.merkle_branch.push(sibling);
+            leaves.push(leaf);
+        }
+        Ok(NodeInfo { hash: sha256::Hash::from_inner(hash.into_inner()), leaves: leaves })
+    }
+}
+
+/// Builds a script tree from its leaves and hidden nodes, given in depth-first
+/// order with their depth, the root being at depth 0
+///
+/// ```
+/// use monacoin::Script;
+/// use monacoin::secp256k1::Secp256k1;
+/// use monacoin::util::taproot::{TaprootBuilder, XOnlyPublicKey};
+/// # use std::str::FromStr;
+///
+/// let secp = Secp256k1::verification_only();
+/// let internal_key = XOnlyPublicKey::from_str("e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6f").unwrap();
+/// //     root
+/// //    /    \
+/// //   a     / \
+/// //        b   c
+/// let info = TaprootBuilder::new()
+///     .add_leaf(1, Script::from(vec![0x51])).unwrap()
+///     .add_leaf(2, Script::from(vec![0x52])).unwrap()
+///     .add_leaf(2, Script::from(vec![0x53])).unwrap()
+///     .finalize(&secp, internal_key).unwrap();
+/// assert!(info.merkle_root().is_some());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TaprootBuilder {
+    /// The nodes which still lack a sibling, indexed by depth
+    branch: Vec<Option<NodeInfo>>,
+}
+
+impl TaprootBuilder {
+    /// Creates an empty builder
+    pub fn new() -> TaprootBuilder {
+        TaprootBuilder { branch: Vec::new() }
+    }
+
+    /// Adds a tapscript leaf at `depth`
+    pub fn add_leaf(self, depth: usize, script: Script) -> Result<TaprootBuilder, Error> {
+        self.add_leaf_with_ver(depth, script, LeafVersion::default())
+    }
+
+    /// Adds a leaf with the given leaf version at `depth`
+    pub fn add_leaf_with_ver(self, depth: usize, script: Script, ver: LeafVersion) -> Result<TaprootBuilder, Error> {
+        let hash = TapLeafHash::from_script(&script, ver);
+        let leaf = LeafInfo { script: script, ver: ver, merkle_branch: Vec::new() };
+        self.insert(NodeInfo { hash: sha256::Hash::from_inner(hash.into_inner()), leaves: vec![leaf] }, depth)
+    }
+
+    /// Adds a node whose contents are not known, only its hash, at `depth`.
+    /// None of the leaves below it can be spent with the resulting tree.
+    pub fn add_hidden_node(self, depth: usize, hash: sha256::Hash) -> Result<TaprootBuilder, Error> {
+        self.insert(NodeInfo { hash: hash, leaves: Vec::new() }, depth)
+    }
+
+    /// Whether the nodes added so far form a complete tree
+    pub fn is_complete(&self) -> bool {
+        self.branch.len() == 1 && self.branch[0].is_some()
+    }
+
+    /// Tweaks `internal_key` with the root of the complete tree
+    pub fn finalize<C: Verification>(mut self, secp: &Secp256k1<C>, internal_key: XOnlyPublicKey) -> Result<TaprootSpendInfo, Error> {
+        if self.branch.len() > 1 {
+            return Err(Error::IncompleteTree(self.branch.len() - 1));
+        }
+        let node = self.branch.pop().ok_or(Error::EmptyTree)?.expect("the last node is always set");
+        Ok(TaprootSpendInfo::from_node_info(secp, internal_key, node))
+    }
+
+    fn insert(mut self, mut node: NodeInfo, mut depth: usize) -> Result<TaprootBuilder, Error> {
+        if depth > TAPROOT_CONTROL_MAX_NODE_COUNT {
+            return Err(Error::InvalidMerkleTreeDepth(depth));
+        }
+        // A shallower node can't be added while a deeper one lacks its sibling
+        if depth + 1 < self.branch.len() {
+            return Err(Error::NodeNotInDfsOrder(self.branch.len() - 1));
+        }
+
+        // Combine the node with its sibling, if any, and propagate upwards
+        while self.branch.len() == depth + 1 {
+            let sibling = match self.branch.pop() {
+                Some(Some(sibling)) => sibling,
+                _ => {
+                    self.branch.push(None);
+                    break;
+                }
+            };
+            if depth == 0 {
+                return Err(Error::OverCompleteTree(depth));
+            }
+            node = NodeInfo::combine(sibling, node)?;
+            depth -= 1;
+        }
+
+        while self.branch.len() < depth + 1 {
+            self.branch.push(None);
+        }
+        self.branch[depth] = Some(node);
+        Ok(self)
+    }
+}
+
+/// Everything needed to spend a taproot output, by the key path or any of
+/// the known script leaves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaprootSpendInfo {
+    internal_key: XOnlyPublicKey,
+    merkle_root: Option<TapBranchHash>,
+    output_key: XOnlyPublicKey,
+    output_key_parity: bool,
+    /// The merkle branches of every known leaf; the same script may appear
+    /// at several places in the tree
+    script_map: BTreeMap<(Script, LeafVersion), BTreeSet<Vec<sha256::Hash>>>,
+}
+
+impl TaprootSpendInfo {
+    /// Creates the spend info of an output without script tree, which can
+    /// only be spent by the key path
+    pub fn new_key_spend<C: Verification>(
+        secp: &Secp256k1<C>,
+        internal_key: XOnlyPublicKey,
+        merkle_root: Option<TapBranchHash>,
+    ) -> TaprootSpendInfo {
+        let (output_key, parity) = internal_key.tap_tweak(secp, merkle_root);
+        TaprootSpendInfo {
+            internal_key: internal_key,
+            merkle_root: merkle_root,
+            output_key: output_key,
+            output_key_parity: parity,
+            script_map: BTreeMap::new(),
+        }
+    }
+
+    fn from_node_info<C: Verification>(secp: &Secp256k1<C>, internal_key: XOnlyPublicKey, node: NodeInfo) -> TaprootSpendInfo {
+        let root = TapBranchHash::from_inner(node.hash.into_inner());
+        let mut info = TaprootSpendInfo::new_key_spend(secp, internal_key, Some(root));
+        for leaf in node.leaves {
+            info.script_map.entry((leaf.script, leaf.ver)).or_insert_with(BTreeSet::new).insert(leaf.merkle_branch);
+        }
+        info
+    }
+
+    /// The untweaked internal key
+    pub fn internal_key(&self) -> XOnlyPublicKey {
+        self.internal_key
+    }
+
+    /// The merkle root of the script tree, if any
+    pub fn merkle_root(&self) -> Option<TapBranchHash> {
+        self.merkle_root
+    }
+
+    /// The tweaked output key, which goes into the output script
+    pub fn output_key(&self) -> XOnlyPublicKey {
+        self.output_key
+    }
+
+    /// Whether the y coordinate of the output key is odd
+    pub fn output_key_parity(&self) -> bool {
+        self.output_key_parity
+    }
+
+    /// The control block to spend the given leaf by the script path, or
+    /// [None] if the leaf is not part of the tree. When a leaf appears at
+    /// several places, the shortest merkle branch is used.
+    pub fn control_block(&self, script_ver: &(Script, LeafVersion)) -> Option<ControlBlock> {
+        let branches = self.script_map.get(script_ver)?;
+        let smallest = branches.iter().min_by_key(|branch| branch.len())?;
+        Some(ControlBlock {
+            leaf_version: script_ver.1,
+            output_key_parity: self.output_key_parity,
+            internal_key: self.internal_key,
+            merkle_branch: smallest.clone(),
+        })
+    }
+}
+
+/// The control block of a script-path spend, the last witness element
+/// before the annex
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlBlock {
+    /// The leaf version of the script being spent
+    pub leaf_version: LeafVersion,
+    /// Whether the y coordinate of the output key is odd
+    pub output_key_parity: bool,
+    /// The untweaked internal key
+    pub internal_key: XOnlyPublicKey,
+    /// The hashes of the siblings of the nodes on the path from the leaf to
+    /// the root, starting with the sibling of the leaf
+    pub merkle_branch: Vec<sha256::Hash>,
+}
+
+impl ControlBlock {
+    /// Parses a control block from the witness
+    pub fn from_slice(sl: &[u8]) -> Result<ControlBlock, Error> {
+        if sl.len() < TAPROOT_CONTROL_BASE_SIZE
+            || sl.len() > TAPROOT_CONTROL_MAX_SIZE
+            || (sl.len() - TAPROOT_CONTROL_BASE_SIZE) % TAPROOT_CONTROL_NODE_SIZE != 0
+        {
+            return Err(Error::InvalidControlBlockSize(sl.len()));
+        }
+        let leaf_version = LeafVersion::from_u8(sl[0] & TAPROOT_LEAF_MASK)?;
+        let internal_key = XOnlyPublicKey::from_slice(&sl[1..TAPROOT_CONTROL_BASE_SIZE])
+            .map_err(Error::InvalidInternalKey)?;
+        let merkle_branch = sl[TAPROOT_CONTROL_BASE_SIZE..]
+            .chunks(TAPROOT_CONTROL_NODE_SIZE)
+            .map(|node| sha256::Hash::from_slice(node).expect("chunks of 32 bytes"))
+            .collect();
+        Ok(ControlBlock {
+            leaf_version: leaf_version,
+            output_key_parity: sl[0] & 1 == 1,
+            internal_key: internal_key,
+            merkle_branch: merkle_branch,
+        })
+    }
+
+    /// The size of the serialized control block
+    pub fn size(&self) -> usize {
+        TAPROOT_CONTROL_BASE_SIZE + TAPROOT_CONTROL_NODE_SIZE * self.merkle_branch.len()
+    }
+
+    /// Serializes the control block for the witness
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.size());
+        buf.push(self.leaf_version.as_u8() | self.output_key_parity as u8);
+        buf.extend_from_slice(&self.internal_key.serialize());
+        for node in &self.merkle_branch {
+            buf.extend_from_slice(&node[..]);
+        }
+        buf
+    }
+
+    /// Checks that `output_key` commits to `script` through this control
+    /// block, which is how script-path spends are validated
+    pub fn verify_taproot_commitment<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        output_key: &XOnlyPublicKey,
+        script: &Script,
+    ) -> bool {
+        let leaf = TapLeafHash::from_script(script, self.leaf_version);
+        let mut node = sha256::Hash::from_inner(leaf.into_inner());
+        for sibling in &self.merkle_branch {
+            node = sha256::Hash::from_inner(TapBranchHash::from_node_hashes(node, *sibling).into_inner());
+        }
+        let tweak = TapTweakHash::from_key_and_tweak(self.internal_key, Some(TapBranchHash::from_inner(node.into_inner())));
+        self.internal_key.tweak_add_check(secp, output_key, self.output_key_parity, tweak.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use hashes::hex::FromHex;
+    use secp256k1::Secp256k1;
+    use blockdata::script::Script;
+
+    use super::*;
+
+    #[test]
+    fn leaf_version() {
+        assert_eq!(LeafVersion::default().as_u8(), 0xc0);
+        assert_eq!(LeafVersion::from_u8(0xc2).map(LeafVersion::as_u8), Ok(0xc2));
+        assert_eq!(LeafVersion::from_u8(0xc1), Err(Error::InvalidLeafVersion(0xc1)));
+        assert_eq!(LeafVersion::from_u8(0x50), Err(Error::InvalidLeafVersion(0x50)));
+    }
+
+    #[test]
+    fn branch_hash_is_sorted() {
+        let a = sha256::Hash::hash(&[1]);
+        let b = sha256::Hash::hash(&[2]);
+        assert_eq!(TapBranchHash::from_node_hashes(a, b), TapBranchHash::from_node_hashes(b, a));
+    }
+
+    #[test]
+    fn bip341_tweak_vectors() {
+        let secp = Secp256k1::verification_only();
+
+        // scriptPubKey vector 0: no script tree
+        let internal_key = XOnlyPublicKey::from_str("d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d").unwrap();
+        assert_eq!(
+            TapTweakHash::from_key_and_tweak(internal_key, None),
+            TapTweakHash::from_hex("b86e7be8f39bab32a6f2c0443abbc210f0edac0e2c53d501b36b64437d9c6c70").unwrap()
+        );
+        let (output_key, _) = internal_key.tap_tweak(&secp, None);
+        assert_eq!(output_key.to_string(), "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343");
+
+        // scriptPubKey vector 1: a single leaf
+        let internal_key = XOnlyPublicKey::from_str("187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27").unwrap();
+        let script = Script::from(Vec::<u8>::from_hex("20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac").unwrap());
+        let leaf = TapLeafHash::from_script(&script, LeafVersion::default());
+        assert_eq!(leaf, TapLeafHash::from_hex("5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21").unwrap());
+        let merkle_root = TapBranchHash::from_inner(leaf.into_inner());
+        assert_eq!(
+            TapTweakHash::from_key_and_tweak(internal_key, Some(merkle_root)),
+            TapTweakHash::from_hex("cbd8679ba636c1110ea247542cfbd964131a6be84f873f7f3b62a777528ed001").unwrap()
+        );
+        let (output_key, parity) = internal_key.tap_tweak(&secp, Some(merkle_root));
+        assert_eq!(output_key.to_string(), "147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3");
+        let tweak = TapTweakHash::from_key_and_tweak(internal_key, Some(merkle_root));
+        assert!(internal_key.tweak_add_check(&secp, &output_key, parity, tweak.into_inner()));
+    }
+
+    #[test]
+    fn bip341_control_blocks() {
+        let secp = Secp256k1::verification_only();
+        let script = |hex: &str| Script::from(Vec::<u8>::from_hex(hex).unwrap());
+
+        // scriptPubKey vector 5: an unbalanced tree of three leaves
+        let internal_key = XOnlyPublicKey::from_str("e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6f").unwrap();
+        let leaves = [
+            (1, script("2072ea6adcf1d371dea8fba1035a09f3d24ed5a059799bae114084130ee5898e69ac"),
+             "c0e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6fffe578e9ea769027e4f5a3de40732f75a88a6353a09d767ddeb66accef85e553"),
+            (2, script("202352d137f2f3ab38d1eaa976758873377fa5ebb817372c71e2c542313d4abda8ac"),
+             "c0e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6f9e31407bffa15fefbf5090b149d53959ecdf3f62b1246780238c24501d5ceaf62645a02e0aac1fe69d69755733a9b7621b694bb5b5cde2bbfc94066ed62b9817"),
+            (2, script("207337c0dd4253cb86f2c43a2351aadd82cccb12a172cd120452b9bb8324f2186aac"),
+             "c0e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6fba982a91d4fc552163cb1c0da03676102d5b7a014304c01f0c77b2b8e888de1c2645a02e0aac1fe69d69755733a9b7621b694bb5b5cde2bbfc94066ed62b9817"),
+        ];
+        let mut builder = TaprootBuilder::new();
+        for &(depth, ref script, _) in &leaves {
+            assert!(!builder.is_complete());
+            builder = builder.add_leaf(depth, script.clone()).unwrap();
+        }
+        assert!(builder.is_complete());
+        let info = builder.finalize(&secp, internal_key).unwrap();
+        assert_eq!(info.output_key().to_string(), "91b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605");
+        assert_eq!(info.output_key_parity(), false);
+        assert_eq!(info.internal_key(), internal_key);
+
+        for &(_, ref script, expected) in &leaves {
+            let cb = info.control_block(&(script.clone(), LeafVersion::default())).unwrap();
+            assert_eq!(cb.serialize(), Vec::<u8>::from_hex(expected).unwrap());
+            assert_eq!(ControlBlock::from_slice(&cb.serialize()), Ok(cb.clone()));
+            assert!(cb.verify_taproot_commitment(&secp, &info.output_key(), script));
+            assert!(!cb.verify_taproot_commitment(&secp, &info.output_key(), &Script::new()));
+        }
+        assert_eq!(info.control_block(&(Script::new(), LeafVersion::default())), None);
+
+        // scriptPubKey vector 3: a leaf with a future leaf version
+        let internal_key = XOnlyPublicKey::from_str("ee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf3786592").unwrap();
+        let output_key = XOnlyPublicKey::from_str("712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5").unwrap();
+        let future = LeafVersion::from_u8(0xfa).unwrap();
+        let info = TaprootBuilder::new()
+            .add_leaf(1, script("20387671353e273264c495656e27e39ba899ea8fee3bb69fb2a680e22093447d48ac")).unwrap()
+            .add_leaf_with_ver(1, script("06424950333431"), future).unwrap()
+            .finalize(&secp, internal_key).unwrap();
+        assert_eq!(info.output_key(), output_key);
+        let cb = info.control_block(&(script("06424950333431"), future)).unwrap();
+        assert_eq!(
+            cb.serialize(),
+            Vec::<u8>::from_hex("faee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf37865928ad69ec7cf41c2a4001fd1f738bf1e505ce2277acdcaa63fe4765192497f47a7").unwrap()
+        );
+        assert!(cb.verify_taproot_commitment(&secp, &output_key, &script("06424950333431")));
+    }
+
+    #[test]
+    fn control_block_errors() {
+        let cb = Vec::<u8>::from_hex("c093478e9488f956df2396be2ce6c5cced75f900dfa18e7dabd2428aae78451820").unwrap();
+        assert!(ControlBlock::from_slice(&cb).is_ok());
+        assert_eq!(ControlBlock::from_slice(&cb[..32]), Err(Error::InvalidControlBlockSize(32)));
+        let mut long = cb.clone();
+        long.push(0);
+        assert_eq!(ControlBlock::from_slice(&long), Err(Error::InvalidControlBlockSize(34)));
+        long.resize(TAPROOT_CONTROL_MAX_SIZE + 32, 0);
+        assert_eq!(ControlBlock::from_slice(&long), Err(Error::InvalidControlBlockSize(TAPROOT_CONTROL_MAX_SIZE + 32)));
+        let mut bad_version = cb.clone();
+        bad_version[0] = 0x50;
+        assert_eq!(ControlBlock::from_slice(&bad_version), Err(Error::InvalidLeafVersion(0x50)));
+    }
+
+    #[test]
+    fn builder_errors() {
+        let secp = Secp256k1::verification_only();
+        let internal_key = XOnlyPublicKey::from_str("e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6f").unwrap();
+        let leaf = || Script::from(vec![0x51]);
+
+        assert_eq!(TaprootBuilder::new().finalize(&secp, internal_key).unwrap_err(), Error::EmptyTree);
+        let incomplete = TaprootBuilder::new().add_leaf(1, leaf()).unwrap().add_leaf(2, leaf()).unwrap();
+        assert_eq!(incomplete.clone().finalize(&secp, internal_key).unwrap_err(), Error::IncompleteTree(2));
+        assert_eq!(incomplete.add_leaf(1, leaf()).unwrap_err(), Error::NodeNotInDfsOrder(2));
+        let complete = TaprootBuilder::new().add_leaf(0, leaf()).unwrap();
+        assert_eq!(complete.add_leaf(0, leaf()).unwrap_err(), Error::OverCompleteTree(0));
+        assert_eq!(
+            TaprootBuilder::new().add_leaf(TAPROOT_CONTROL_MAX_NODE_COUNT + 1, leaf()).unwrap_err(),
+            Error::InvalidMerkleTreeDepth(TAPROOT_CONTROL_MAX_NODE_COUNT + 1)
+        );
+
+        // a hidden node leaves only the other leaf spendable
+        let hidden = sha256::Hash::hash(&[0]);
+        let info = TaprootBuilder::new()
+            .add_hidden_node(1, hidden).unwrap()
+            .add_leaf(1, leaf()).unwrap()
+            .finalize(&secp, internal_key).unwrap();
+        let cb = info.control_block(&(leaf(), LeafVersion::default())).unwrap();
+        assert_eq!(cb.merkle_branch, vec![hidden]);
+        assert!(cb.verify_taproot_commitment(&secp, &info.output_key(), &leaf()));
+    }
+}
@@ -40,6 +40,12 @@ macro_rules! construct_uint {
                 arr[0] as u64
             }
 
+            /// Conversion to u128
+            #[inline]
+            pub fn low_u128(&self) -> u128 {
+                let &$name(ref arr) = self;
+                arr[0] as u128 | (arr[1] as u128) << 64
+            }
 
             /// Return the least number of bits needed to represent the number
             #[inline]
@@ -80,6 +86,15 @@ macro_rules! construct_uint {
                 Some($name(ret))
             }
 
+            /// Create an object from a given unsigned 128-bit integer
+            #[inline]
+            pub fn from_u128(init: u128) -> $name {
+                let mut ret = [0; $n_words];
+                ret[0] = init as u64;
+                ret[1] = (init >> 64) as u64;
+                $name(ret)
+            }
+
             /// Create an object from a given signed 64-bit integer
             #[inline]
             pub fn from_i64(init: i64) -> Option<$name> {
@@ -99,6 +114,156 @@ macro_rules! construct_uint {
                 $name(slice)
             }
 
+            /// Creates big integer value from a byte array using
+            /// little-endian encoding
+            pub fn from_le_bytes(bytes: [u8; $n_words * 8]) -> $name {
+                use super::endian::slice_to_u64_le;
+                let mut ret = [0u64; $n_words];
+                for (word, bytes) in ret.iter_mut().zip(bytes.chunks(8)) {
+                    *word = slice_to_u64_le(bytes);
+                }
+                $name(ret)
+            }
+
+            /// Convert the big integer to a byte array using big-endian encoding
+            pub fn to_be_bytes(&self) -> [u8; $n_words * 8] {
+                let mut ret = self.to_le_bytes();
+                ret.reverse();
+                ret
+            }
+
+            /// Convert the big integer to a byte array using little-endian encoding
+            pub fn to_le_bytes(&self) -> [u8; $n_words * 8] {
+                let &$name(ref arr) = self;
+                let mut ret = [0u8; $n_words * 8];
+                for (bytes, word) in ret.chunks_mut(8).zip(arr.iter()) {
+                    bytes.copy_from_slice(&super::endian::u64_to_array_le(*word));
+                }
+                ret
+            }
+
+            /// The number of leading zero bits
+            #[inline]
+            pub fn leading_zeros(&self) -> usize {
+                0x40 * $n_words - self.bits()
+            }
+
+            /// Addition, returning the wrapped sum and whether it overflowed
+            pub fn overflowing_add(self, other: $name) -> ($name, bool) {
+                let $name(ref me) = self;
+                let $name(ref you) = other;
+                let mut ret = [0u64; $n_words];
+                let mut carry = false;
+                for i in 0..$n_words {
+                    let (sum, overflow1) = me[i].overflowing_add(you[i]);
+                    let (sum, overflow2) = sum.overflowing_add(carry as u64);
+                    ret[i] = sum;
+                    carry = overflow1 || overflow2;
+                }
+                ($name(ret), carry)
+            }
+
+            /// Subtraction, returning the wrapped difference and whether it
+            /// underflowed
+            pub fn overflowing_sub(self, other: $name) -> ($name, bool) {
+                let $name(ref me) = self;
+                let $name(ref you) = other;
+                let mut ret = [0u64; $n_words];
+                let mut borrow = false;
+                for i in 0..$n_words {
+                    let (diff, underflow1) = me[i].overflowing_sub(you[i]);
+                    let (diff, underflow2) = diff.overflowing_sub(borrow as u64);
+                    ret[i] = diff;
+                    borrow = underflow1 || underflow2;
+                }
+                ($name(ret), borrow)
+            }
+
+            /// Multiplication, returning the wrapped product and whether it
+            /// overflowed
+            pub fn overflowing_mul(self, other: $name) -> ($name, bool) {
+                let $name(ref me) = self;
+                let $name(ref you) = other;
+                let mut ret = [0u64; $n_words];
+                let mut overflow = false;
+                for i in 0..$n_words {
+                    let mut carry = 0u64;
+                    for j in 0..$n_words {
+                        let prod = me[i] as u128 * you[j] as u128 + carry as u128;
+                        if i + j < $n_words {
+                            let sum = ret[i + j] as u128 + prod;
+                            ret[i + j] = sum as u64;
+                            carry = (sum >> 64) as u64;
+                        } else {
+                            overflow |= prod != 0;
+                            carry = (prod >> 64) as u64;
+                        }
+                    }
+                    overflow |= carry != 0;
+                }
+                ($name(ret), overflow)
+            }
+
+            /// Checked addition, `None` if the sum overflows
+            pub fn checked_add(self, other: $name) -> Option<$name> {
+                match self.overflowing_add(other) {
+                    (sum, false) => Some(sum),
+                    (_, true) => None,
+                }
+            }
+
+            /// Checked subtraction, `None` if `other` is larger than `self`
+            pub fn checked_sub(self, other: $name) -> Option<$name> {
+                match self.overflowing_sub(other) {
+                    (diff, false) => Some(diff),
+                    (_, true) => None,
+                }
+            }
+
+            /// Checked multiplication, `None` if the product overflows
+            pub fn checked_mul(self, other: $name) -> Option<$name> {
+                match self.overflowing_mul(other) {
+                    (prod, false) => Some(prod),
+                    (_, true) => None,
+                }
+            }
+
+            /// Checked division, `None` if `other` is zero
+            pub fn checked_div(self, other: $name) -> Option<$name> {
+                if other.bits() == 0 { None } else { Some(self.div_rem(other).0) }
+            }
+
+            /// Checked remainder, `None` if `other` is zero
+            pub fn checked_rem(self, other: $name) -> Option<$name> {
+                if other.bits() == 0 { None } else { Some(self.div_rem(other).1) }
+            }
+
+            /// Checked left shift, `None` if `shift` is not smaller than the
+            /// number of bits of the type. Bits shifted out are lost as
+            /// with `<<`.
+            pub fn checked_shl(self, shift: usize) -> Option<$name> {
+                if shift < 0x40 * $n_words { Some(self << shift) } else { None }
+            }
+
+            /// Checked right shift, `None` if `shift` is not smaller than the
+            /// number of bits of the type
+            pub fn checked_shr(self, shift: usize) -> Option<$name> {
+                if shift < 0x40 * $n_words { Some(self >> shift) } else { None }
+            }
+
+            /// Division by a u64, returning the quotient and the remainder
+            fn div_rem_u64(self, other: u64) -> ($name, u64) {
+                let $name(ref arr) = self;
+                let mut ret = [0u64; $n_words];
+                let mut rem = 0u128;
+                for i in (0..$n_words).rev() {
+                    let cur = rem << 64 | arr[i] as u128;
+                    ret[i] = (cur / other as u128) as u64;
+                    rem = cur % other as u128;
+                }
+                ($name(ret), rem as u64)
+            }
+
             // divmod like operation, returns (quotient, remainder)
             #[inline]
             fn div_rem(self, other: Self) -> (Self, Self) {
@@ -139,20 +304,9 @@ macro_rules! construct_uint {
         impl ::std::ops::Add<$name> for $name {
             type Output = $name;
 
+            #[inline]
             fn add(self, other: $name) -> $name {
-                let $name(ref me) = self;
-                let $name(ref you) = other;
-                let mut ret = [0u64; $n_words];
-                let mut carry = [0u64; $n_words];
-                let mut b_carry = false;
-                for i in 0..$n_words {
-                    ret[i] = me[i].wrapping_add(you[i]);
-                    if i < $n_words - 1 && ret[i] < me[i] {
-                        carry[i + 1] = 1;
-                        b_carry = true;
-                    }
-                }
-                if b_carry { $name(ret) + $name(carry) } else { $name(ret) }
+                self.overflowing_add(other).0
             }
         }
 
@@ -161,22 +315,16 @@ macro_rules! construct_uint {
 
             #[inline]
             fn sub(self, other: $name) -> $name {
-                self + !other + $crate::util::BitArray::one()
+                self.overflowing_sub(other).0
             }
         }
 
         impl ::std::ops::Mul<$name> for $name {
             type Output = $name;
 
+            #[inline]
             fn mul(self, other: $name) -> $name {
-                use $crate::util::BitArray;
-                let mut me = $name::zero();
-                // TODO: be more efficient about this
-                for i in 0..(2 * $n_words) {
-                    let to_mul = (other >> (32 * i)).low_u32();
-                    me = me + (self.mul_u32(to_mul) << (32 * i));
-                }
-                me
+                self.overflowing_mul(other).0
             }
         }
 
@@ -358,7 +506,43 @@ macro_rules! construct_uint {
 
         impl ::std::fmt::Display for $name {
             fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-                <::std::fmt::Debug>::fmt(self, f)
+                // print in chunks of 19 digits, the most which fit in a u64
+                const CHUNK: u64 = 10_000_000_000_000_000_000;
+                let mut chunks = vec![];
+                let mut rest = *self;
+                loop {
+                    let (quot, rem) = rest.div_rem_u64(CHUNK);
+                    chunks.push(rem);
+                    if quot.bits() == 0 {
+                        break;
+                    }
+                    rest = quot;
+                }
+                let mut s = chunks.pop().unwrap().to_string();
+                for chunk in chunks.iter().rev() {
+                    s.push_str(&format!("{:019}", chunk));
+                }
+                f.pad_integral(true, "", &s)
+            }
+        }
+
+        impl ::std::fmt::LowerHex for $name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                let &$name(ref data) = self;
+                let mut s = String::new();
+                for word in data.iter().rev() {
+                    if s.is_empty() {
+                        if *word != 0 {
+                            s = format!("{:x}", word);
+                        }
+                    } else {
+                        s.push_str(&format!("{:016x}", word));
+                    }
+                }
+                if s.is_empty() {
+                    s.push('0');
+                }
+                f.pad_integral(true, "0x", &s)
             }
         }
 
@@ -396,20 +580,38 @@ construct_uint!(Uint256, 4);
 construct_uint!(Uint128, 2);
 
 impl Uint256 {
-    /// Increment by 1
+    /// Decode the "compact" encoding of block targets, a floating-point
+    /// format with a one-byte base-256 exponent and a signed three-byte
+    /// mantissa. `None` if the value is negative or doesn't fit in 256 bits,
+    /// the cases in which Bitcoin Core's `SetCompact` sets its negative or
+    /// overflow flags.
+    pub fn from_compact(bits: u32) -> Option<Uint256> {
+        let size = bits >> 24;
+        let mut word = bits & 0x007fffff;
+        if size <= 3 {
+            word >>= 8 * (3 - size);
+        }
+        if word == 0 {
+            return Some(Default::default());
+        }
+        if bits & 0x00800000 != 0 {
+            return None;
+        }
+        if size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32) {
+            return None;
+        }
+        let ret = Uint256::from_u64(word as u64).unwrap();
+        if size <= 3 {
+            Some(ret)
+        } else {
+            Some(ret << (8 * (size as usize - 3)))
+        }
+    }
+
+    /// Increment by 1, wrapping around at the largest value
     #[inline]
     pub fn increment(&mut self) {
-        let &mut Uint256(ref mut arr) = self;
-        arr[0] += 1;
-        if arr[0] == 0 {
-            arr[1] += 1;
-            if arr[1] == 0 {
-                arr[2] += 1;
-                if arr[2] == 0 {
-                    arr[3] += 1;
-                }
-            }
-        }
+        *self = *self + <Uint256 as ::util::BitArray>::one();
     }
 
     /// Decay to a uint128
@@ -422,6 +624,8 @@ impl Uint256 {
 
 #[cfg(test)]
 mod tests {
+    use secp256k1::rand::{thread_rng, Rng};
+
     use consensus::{deserialize, serialize};
     use util::uint::{Uint256, Uint128};
     use util::BitArray;
@@ -453,15 +657,340 @@ mod tests {
 
     #[test]
     pub fn uint256_display_test() {
-        assert_eq!(format!("{}", Uint256::from_u64(0xDEADBEEF).unwrap()),
+        assert_eq!(format!("{:?}", Uint256::from_u64(0xDEADBEEF).unwrap()),
                    "0x00000000000000000000000000000000000000000000000000000000deadbeef");
-        assert_eq!(format!("{}", Uint256::from_u64(u64::max_value()).unwrap()),
+        assert_eq!(format!("{:?}", Uint256::from_u64(u64::max_value()).unwrap()),
                    "0x000000000000000000000000000000000000000000000000ffffffffffffffff");
 
         let max_val = Uint256([0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF,
                                0xFFFFFFFFFFFFFFFF]);
-        assert_eq!(format!("{}", max_val),
+        assert_eq!(format!("{:?}", max_val),
                    "0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff");
+
+        assert_eq!(format!("{}", Uint256::from_u64(0xDEADBEEF).unwrap()), "3735928559");
+        assert_eq!(format!("{}", Uint256::zero()), "0");
+        assert_eq!(format!("{}", Uint256::from_u128(10_000_000_000_000_000_000)), "10000000000000000000");
+        assert_eq!(format!("{}", max_val),
+                   "115792089237316195423570985008687907853269984665640564039457584007913129639935");
+        assert_eq!(format!("{:>5}", Uint256::one()), "    1");
+
+        assert_eq!(format!("{:x}", Uint256::zero()), "0");
+        assert_eq!(format!("{:x}", Uint256([0, 0xDEADBEEF, 0, 0])), "deadbeef0000000000000000");
+        assert_eq!(format!("{:#x}", max_val), format!("0x{}", "f".repeat(64)));
+    }
+
+    #[test]
+    pub fn uint256_bytes_test() {
+        let val = Uint256([0x11fed2bad1c0ffe0, 0xbaadf00ddefaceda, 0xdeafbabe2bedfeed, 0x1badcafedeadbeef]);
+        let be = val.to_be_bytes();
+        assert_eq!(&be[..4], &[0x1b, 0xad, 0xca, 0xfe]);
+        assert_eq!(&be[28..], &[0xd1, 0xc0, 0xff, 0xe0]);
+        assert_eq!(Uint256::from_be_bytes(be), val);
+
+        let le = val.to_le_bytes();
+        assert_eq!(&le[..], &serialize(&val)[..]);
+        assert_eq!(Uint256::from_le_bytes(le), val);
+        let mut rev = le;
+        rev.reverse();
+        assert_eq!(rev, be);
+
+        assert_eq!(Uint256::from_u128(u128::max_value()), Uint256([!0, !0, 0, 0]));
+        assert_eq!(val.low_u128(), 0xbaadf00ddefaceda11fed2bad1c0ffe0);
+        assert_eq!(val.leading_zeros(), 3);
+        assert_eq!(Uint256::zero().leading_zeros(), 256);
+        assert_eq!(Uint256::one().leading_zeros(), 255);
+    }
+
+    #[test]
+    pub fn uint256_checked_test() {
+        let max = !Uint256::zero();
+        let one = Uint256::one();
+        let zero = Uint256::zero();
+
+        assert_eq!(max.overflowing_add(one), (zero, true));
+        assert_eq!(max.checked_add(one), None);
+        assert_eq!(max.checked_add(zero), Some(max));
+        assert_eq!(max + one, zero);
+        assert_eq!(zero.overflowing_sub(one), (max, true));
+        assert_eq!(zero.checked_sub(one), None);
+        assert_eq!(one.checked_sub(one), Some(zero));
+        assert_eq!(zero - one, max);
+        assert_eq!(max.overflowing_mul(max), (one, true));
+        assert_eq!(max.checked_mul(one), Some(max));
+        assert_eq!(max.checked_mul(zero), Some(zero));
+        assert_eq!((one << 128).checked_mul(one << 127), Some(one << 255));
+        assert_eq!((one << 128).checked_mul(one << 128), None);
+        assert_eq!(max.checked_div(zero), None);
+        assert_eq!(max.checked_rem(zero), None);
+        assert_eq!(max.checked_div(max), Some(one));
+        assert_eq!(max.checked_rem(one), Some(zero));
+        assert_eq!(one.checked_shl(255), Some(one << 255));
+        assert_eq!(one.checked_shl(256), None);
+        assert_eq!(max.checked_shr(255), Some(one));
+        assert_eq!(max.checked_shr(256), None);
+    }
+
+    #[test]
+    pub fn uint128_reference_test() {
+        // Uint128 is built by the same macro as Uint256, so compare it to u128
+        let mut rng = thread_rng();
+        let mut operands = vec![0, 1, u64::max_value() as u128, u128::max_value()];
+        for _ in 0..200 {
+            let bits = rng.gen_range(0, 129);
+            let random = (rng.gen::<u64>() as u128) << 64 | rng.gen::<u64>() as u128;
+            operands.push(if bits == 0 { 0 } else { random >> (128 - bits) });
+        }
+        for &a in &operands {
+            let ua = Uint128::from_u128(a);
+            assert_eq!(ua.low_u128(), a);
+            assert_eq!(format!("{}", ua), format!("{}", a));
+            assert_eq!(format!("{:x}", ua), format!("{:x}", a));
+            assert_eq!(ua.leading_zeros(), a.leading_zeros() as usize);
+            assert_eq!(ua.to_be_bytes(), a.to_be_bytes());
+            assert_eq!(Uint128::from_le_bytes(a.to_le_bytes()), ua);
+            for shift in 0..130 {
+                assert_eq!(ua.checked_shl(shift).map(|x| x.low_u128()), a.checked_shl(shift as u32));
+                assert_eq!(ua.checked_shr(shift).map(|x| x.low_u128()), a.checked_shr(shift as u32));
+            }
+            for &b in &operands {
+                let ub = Uint128::from_u128(b);
+                let (sum, overflow) = ua.overflowing_add(ub);
+                assert_eq!((sum.low_u128(), overflow), a.overflowing_add(b));
+                let (diff, overflow) = ua.overflowing_sub(ub);
+                assert_eq!((diff.low_u128(), overflow), a.overflowing_sub(b));
+                let (prod, overflow) = ua.overflowing_mul(ub);
+                assert_eq!((prod.low_u128(), overflow), a.overflowing_mul(b));
+                assert_eq!(ua.checked_div(ub).map(|x| x.low_u128()), a.checked_div(b));
+                assert_eq!(ua.checked_rem(ub).map(|x| x.low_u128()), a.checked_rem(b));
+                assert_eq!(ua.cmp(&ub), a.cmp(&b));
+            }
+        }
+    }
+
+    /// Reference 256-bit arithmetic on little-endian 32-bit limbs, sharing no
+    /// code with the macro
+    mod reference {
+        use std::cmp::Ordering;
+
+        use util::uint::Uint256;
+
+        pub type Limbs = [u32; 8];
+
+        pub fn from_uint(x: Uint256) -> Limbs {
+            let mut ret = [0; 8];
+            for i in 0..4 {
+                ret[2 * i] = x.0[i] as u32;
+                ret[2 * i + 1] = (x.0[i] >> 32) as u32;
+            }
+            ret
+        }
+
+        pub fn to_uint(x: &Limbs) -> Uint256 {
+            let mut ret = [0u64; 4];
+            for i in 0..4 {
+                ret[i] = x[2 * i] as u64 | (x[2 * i + 1] as u64) << 32;
+            }
+            Uint256(ret)
+        }
+
+        pub fn cmp(a: &[u32], b: &[u32]) -> Ordering {
+            for i in (0..a.len()).rev() {
+                match a[i].cmp(&b[i]) {
+                    Ordering::Equal => {}
+                    ord => return ord,
+                }
+            }
+            Ordering::Equal
+        }
+
+        pub fn add(a: &Limbs, b: &Limbs) -> (Limbs, bool) {
+            let mut ret = [0; 8];
+            let mut carry = 0u64;
+            for i in 0..8 {
+                let sum = a[i] as u64 + b[i] as u64 + carry;
+                ret[i] = sum as u32;
+                carry = sum >> 32;
+            }
+            (ret, carry != 0)
+        }
+
+        /// Subtract `b` from `a` in place, returning whether it borrowed
+        fn sub_assign(a: &mut [u32], b: &[u32]) -> bool {
+            let mut borrow = 0i64;
+            for i in 0..a.len() {
+                let diff = a[i] as i64 - b[i] as i64 - borrow;
+                a[i] = diff as u32;
+                borrow = if diff < 0 { 1 } else { 0 };
+            }
+            borrow != 0
+        }
+
+        pub fn sub(a: &Limbs, b: &Limbs) -> (Limbs, bool) {
+            let mut ret = *a;
+            let borrow = sub_assign(&mut ret, b);
+            (ret, borrow)
+        }
+
+        pub fn mul(a: &Limbs, b: &Limbs) -> (Limbs, bool) {
+            let mut wide = [0u32; 16];
+            for i in 0..8 {
+                let mut carry = 0u64;
+                for j in 0..8 {
+                    let cur = wide[i + j] as u64 + a[i] as u64 * b[j] as u64 + carry;
+                    wide[i + j] = cur as u32;
+                    carry = cur >> 32;
+                }
+                wide[i + 8] = carry as u32;
+            }
+            let mut ret = [0; 8];
+            ret.copy_from_slice(&wide[..8]);
+            (ret, wide[8..].iter().any(|&w| w != 0))
+        }
+
+        /// Quotient and remainder by bitwise long division, `b` must not be zero
+        pub fn div_rem(a: &Limbs, b: &Limbs) -> (Limbs, Limbs) {
+            let mut quot = [0; 8];
+            // one more limb, as the remainder is shifted before being reduced
+            let mut rem = [0u32; 9];
+            let mut divisor = [0u32; 9];
+            divisor[..8].copy_from_slice(b);
+            for bit in (0..256).rev() {
+                for i in (1..9).rev() {
+                    rem[i] = rem[i] << 1 | rem[i - 1] >> 31;
+                }
+                rem[0] = rem[0] << 1 | (a[bit / 32] >> (bit % 32)) & 1;
+                if cmp(&rem, &divisor) != Ordering::Less {
+                    sub_assign(&mut rem, &divisor);
+                    quot[bit / 32] |= 1 << (bit % 32);
+                }
+            }
+            let mut ret = [0; 8];
+            ret.copy_from_slice(&rem[..8]);
+            (quot, ret)
+        }
+
+        pub fn shl(a: &Limbs, shift: usize) -> Limbs {
+            let mut ret = [0; 8];
+            for bit in shift..256 {
+                let from = bit - shift;
+                ret[bit / 32] |= ((a[from / 32] >> (from % 32)) & 1) << (bit % 32);
+            }
+            ret
+        }
+
+        pub fn shr(a: &Limbs, shift: usize) -> Limbs {
+            let mut ret = [0; 8];
+            for bit in shift..256 {
+                let to = bit - shift;
+                ret[to / 32] |= ((a[bit / 32] >> (bit % 32)) & 1) << (to % 32);
+            }
+            ret
+        }
+
+        pub fn to_decimal(a: &Limbs) -> String {
+            let mut digits = vec![];
+            let mut cur = *a;
+            loop {
+                let mut rem = 0u64;
+                for i in (0..8).rev() {
+                    let acc = rem << 32 | cur[i] as u64;
+                    cur[i] = (acc / 10) as u32;
+                    rem = acc % 10;
+                }
+                digits.push(b'0' + rem as u8);
+                if cur.iter().all(|&w| w == 0) {
+                    break;
+                }
+            }
+            digits.reverse();
+            String::from_utf8(digits).unwrap()
+        }
+    }
+
+    #[test]
+    pub fn uint256_reference_test() {
+        use self::reference as r;
+
+        let mut rng = thread_rng();
+        let max = !Uint256::zero();
+        // values whose words are all zero or all ones make carries and
+        // borrows run across the upper words
+        let mut operands = vec![];
+        for pattern in 0..16u64 {
+            let words = [
+                if pattern & 1 != 0 { !0 } else { 0 },
+                if pattern & 2 != 0 { !0 } else { 0 },
+                if pattern & 4 != 0 { !0 } else { 0 },
+                if pattern & 8 != 0 { !0 } else { 0 },
+            ];
+            operands.push(Uint256(words));
+            operands.push(Uint256(words) + Uint256::one());
+        }
+        operands.push(Uint256::one() << 255);
+        operands.push(Uint256::one() << 192);
+        operands.push(Uint256::one() << 128);
+        operands.push(max >> 1);
+        for _ in 0..60 {
+            let bits = rng.gen_range(1, 257);
+            let mut words: [u64; 4] = [rng.gen(), rng.gen(), rng.gen(), rng.gen()];
+            for word in words.iter_mut() {
+                match rng.gen_range(0, 4) {
+                    0 => *word = 0,
+                    1 => *word = !0,
+                    _ => {}
+                }
+            }
+            operands.push(Uint256(words) >> (256 - bits));
+        }
+
+        for &a in &operands {
+            let ra = r::from_uint(a);
+            assert_eq!(r::to_uint(&ra), a);
+            assert_eq!(format!("{}", a), r::to_decimal(&ra));
+            for shift in 0..257 {
+                let expected = if shift < 256 { Some(r::to_uint(&r::shl(&ra, shift))) } else { None };
+                assert_eq!(a.checked_shl(shift), expected);
+                let expected = if shift < 256 { Some(r::to_uint(&r::shr(&ra, shift))) } else { None };
+                assert_eq!(a.checked_shr(shift), expected);
+            }
+            for &b in &operands {
+                let rb = r::from_uint(b);
+                let (sum, overflow) = r::add(&ra, &rb);
+                assert_eq!(a.overflowing_add(b), (r::to_uint(&sum), overflow));
+                let (diff, overflow) = r::sub(&ra, &rb);
+                assert_eq!(a.overflowing_sub(b), (r::to_uint(&diff), overflow));
+                let (prod, overflow) = r::mul(&ra, &rb);
+                assert_eq!(a.overflowing_mul(b), (r::to_uint(&prod), overflow));
+                assert_eq!(a * b, r::to_uint(&prod));
+                if b == Uint256::zero() {
+                    assert_eq!(a.checked_div(b), None);
+                    assert_eq!(a.checked_rem(b), None);
+                } else {
+                    let (quot, rem) = r::div_rem(&ra, &rb);
+                    assert_eq!(a / b, r::to_uint(&quot));
+                    assert_eq!(a % b, r::to_uint(&rem));
+                }
+                assert_eq!(a.cmp(&b), r::cmp(&ra, &rb));
+            }
+        }
+    }
+
+    #[test]
+    pub fn uint256_division_test() {
+        let mut rng = thread_rng();
+        let max = !Uint256::zero();
+        let mut operands = vec![Uint256::zero(), Uint256::one(), max];
+        for _ in 0..100 {
+            let bits = rng.gen_range(1, 257);
+            operands.push(Uint256([rng.gen(), rng.gen(), rng.gen(), rng.gen()]) >> (256 - bits));
+        }
+        for &a in &operands {
+            for &b in operands.iter().filter(|b| b.bits() != 0) {
+                let (quot, rem) = (a / b, a % b);
+                assert!(rem < b);
+                assert_eq!(quot.checked_mul(b).and_then(|x| x.checked_add(rem)), Some(a));
+            }
+        }
     }
 
     #[test]
@@ -586,6 +1115,24 @@ mod tests {
         assert_eq!(add << 64, Uint256([0, 0xDEADBEEFDEADBEEF, 0xDEADBEEFDEADBEEF, 0]));
     }
 
+    #[test]
+    pub fn uint256_from_compact_test() {
+        assert_eq!(Uint256::from_compact(0x1d00ffff), Some(Uint256::from_u64(0xffff).unwrap() << 208));
+        assert_eq!(Uint256::from_compact(0x1e0ffff0), Some(Uint256::from_u64(0x0ffff0).unwrap() << 216));
+        assert_eq!(Uint256::from_compact(0x01003456), Some(Uint256::zero()));
+        assert_eq!(Uint256::from_compact(0x01123456), Some(Uint256::from_u64(0x12).unwrap()));
+        assert_eq!(Uint256::from_compact(0x02008000), Some(Uint256::from_u64(0x80).unwrap()));
+        assert_eq!(Uint256::from_compact(0x05009234), Some(Uint256::from_u64(0x92340000).unwrap()));
+        assert_eq!(Uint256::from_compact(0x20123456), Some(Uint256::from_u64(0x123456).unwrap() << 232));
+        assert_eq!(Uint256::from_compact(0x04923456), None);
+        assert_eq!(Uint256::from_compact(0x01fedcba), None);
+        assert_eq!(Uint256::from_compact(0x04800000), Some(Uint256::zero()));
+        assert_eq!(Uint256::from_compact(0x22000001), Some(Uint256::one() << 248));
+        assert_eq!(Uint256::from_compact(0x22000100), None);
+        assert_eq!(Uint256::from_compact(0x21010000), None);
+        assert_eq!(Uint256::from_compact(0xff123456), None);
+    }
+
     #[test]
     pub fn uint256_serialize_test() {
         let start1 = Uint256([0x8C8C3EE70C644118u64, 0x0209E7378231E632, 0, 0]);
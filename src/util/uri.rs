@@ -0,0 +1,328 @@
+// Rust Monacoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Monacoin URIs
+//!
+//! Parsing and generation of `monacoin:` payment URIs as described in BIP21.
+//!
+//! The scheme is matched case-insensitively, parameter values are
+//! percent-decoded, and the `amount` parameter is a decimal number of MONA.
+//! Unknown parameters are kept, except those starting with `req-`, which a
+//! client is required to understand and which therefore make the URI invalid.
+
+
+use std::fmt;
+use std::str::FromStr;
+use std::error;
+
+use util::address::{self, Address};
+use util::amount::{Amount, Denomination, ParseAmountError};
+
+/// The URI scheme, without the colon.
+pub const SCHEME: &'static str = "monacoin";
+
+/// An error parsing a [MonacoinUri].
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The URI does not start with `monacoin:`
+    InvalidScheme,
+    /// The address could not be parsed
+    Address(address::Error),
+    /// The amount could not be parsed
+    Amount(ParseAmountError),
+    /// A `%` was not followed by two hex digits, or the decoded bytes were
+    /// not UTF-8
+    InvalidPercentEncoding,
+    /// A parameter with a fixed meaning appeared more than once
+    DuplicateParameter(String),
+    /// A `req-` parameter this library does not understand
+    UnknownRequiredParameter(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidScheme => write!(f, "URI does not start with {}:", SCHEME),
+            Error::Address(ref e) => write!(f, "invalid address: {}", e),
+            Error::Amount(ref e) => write!(f, "invalid amount: {}", e),
+            Error::InvalidPercentEncoding => f.write_str("invalid percent-encoding"),
+            Error::DuplicateParameter(ref p) => write!(f, "duplicate parameter: {}", p),
+            Error::UnknownRequiredParameter(ref p) => write!(f, "unknown required parameter: {}", p),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            Error::Address(ref e) => Some(e),
+            Error::Amount(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "description() is deprecated; use Display"
+    }
+}
+
+#[doc(hidden)]
+impl From<address::Error> for Error {
+    fn from(e: address::Error) -> Error {
+        Error::Address(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<ParseAmountError> for Error {
+    fn from(e: ParseAmountError) -> Error {
+        Error::Amount(e)
+    }
+}
+
+/// A `monacoin:` payment URI
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonacoinUri {
+    /// The address to pay to
+    pub address: Address,
+    /// The requested amount
+    pub amount: Option<Amount>,
+    /// A label for the address, e.g. the name of the receiver
+    pub label: Option<String>,
+    /// A message describing the payment
+    pub message: Option<String>,
+    /// Any other parameters, in order, with keys and values percent-decoded
+    pub extra: Vec<(String, String)>,
+}
+
+impl MonacoinUri {
+    /// A URI which only names an address.
+    pub fn new(address: Address) -> MonacoinUri {
+        MonacoinUri {
+            address: address,
+            amount: None,
+            label: None,
+            message: None,
+            extra: Vec::new(),
+        }
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+fn percent_decode(s: &str) -> Result<String, Error> {
+    let bytes = s.as_bytes();
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if i + 2 >= bytes.len() {
+                return Err(Error::InvalidPercentEncoding);
+            }
+            match (hex_digit(bytes[i + 1]), hex_digit(bytes[i + 2])) {
+                (Some(hi), Some(lo)) => ret.push(hi << 4 | lo),
+                _ => return Err(Error::InvalidPercentEncoding),
+            }
+            i += 3;
+        } else {
+            ret.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(ret).map_err(|_| Error::InvalidPercentEncoding)
+}
+
+/// Writes `s` with everything but RFC3986 unreserved characters escaped.
+fn percent_encode(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    for &b in s.as_bytes() {
+        match b {
+            b'-' | b'.' | b'_' | b'~' => write!(f, "{}", b as char)?,
+            _ if b.is_ascii_alphanumeric() => write!(f, "{}", b as char)?,
+            _ => write!(f, "%{:02X}", b)?,
+        }
+    }
+    Ok(())
+}
+
+fn set_once(slot: &mut Option<String>, key: &str, value: String) -> Result<(), Error> {
+    if slot.is_some() {
+        return Err(Error::DuplicateParameter(key.to_owned()));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+impl FromStr for MonacoinUri {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<MonacoinUri, Error> {
+        let colon = s.find(':').ok_or(Error::InvalidScheme)?;
+        if !s[..colon].eq_ignore_ascii_case(SCHEME) {
+            return Err(Error::InvalidScheme);
+        }
+        let rest = &s[colon + 1..];
+        let (address, query) = match rest.find('?') {
+            Some(pos) => (&rest[..pos], Some(&rest[pos + 1..])),
+            None => (rest, None),
+        };
+
+        let mut uri = MonacoinUri::new(Address::from_str(address)?);
+        let mut amount = None;
+        for param in query.into_iter().flat_map(|q| q.split('&')).filter(|p| !p.is_empty()) {
+            let (key, value) = match param.find('=') {
+                Some(pos) => (&param[..pos], &param[pos + 1..]),
+                None => (param, ""),
+            };
+            let key = percent_decode(key)?;
+            let value = percent_decode(value)?;
+            match key.as_str() {
+                "amount" => set_once(&mut amount, &key, value)?,
+                "label" => set_once(&mut uri.label, &key, value)?,
+                "message" => set_once(&mut uri.message, &key, value)?,
+                _ if key.starts_with("req-") => return Err(Error::UnknownRequiredParameter(key)),
+                _ => uri.extra.push((key, value)),
+            }
+        }
+        if let Some(amount) = amount {
+            uri.amount = Some(Amount::from_str_in(&amount, Denomination::Monacoin)?);
+        }
+        Ok(uri)
+    }
+}
+
+/// Formats the URI. The alternate format `{:#}` upper-cases bech32
+/// addresses, as for [Address::to_qr_uri].
+impl fmt::Display for MonacoinUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{}:{:#}", SCHEME, self.address)?;
+        } else {
+            write!(f, "{}:{}", SCHEME, self.address)?;
+        }
+
+        let mut sep = '?';
+        if let Some(amount) = self.amount {
+            let value = amount.to_string_in(Denomination::Monacoin);
+            let value = value.trim_end_matches('0').trim_end_matches('.');
+            write!(f, "{}amount={}", sep, value)?;
+            sep = '&';
+        }
+        let named = [("label", &self.label), ("message", &self.message)];
+        for &(key, value) in named.iter() {
+            if let Some(ref value) = *value {
+                write!(f, "{}{}=", sep, key)?;
+                percent_encode(f, value)?;
+                sep = '&';
+            }
+        }
+        for &(ref key, ref value) in &self.extra {
+            write!(f, "{}", sep)?;
+            percent_encode(f, key)?;
+            f.write_str("=")?;
+            percent_encode(f, value)?;
+            sep = '&';
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use util::address::Address;
+    use util::amount::Amount;
+    use super::*;
+
+    fn roundtrips(uri: &MonacoinUri) {
+        assert_eq!(&MonacoinUri::from_str(&uri.to_string()).unwrap(), uri);
+        assert_eq!(&MonacoinUri::from_str(&format!("{:#}", uri)).unwrap(), uri);
+    }
+
+    #[test]
+    fn address_only() {
+        let uri = MonacoinUri::from_str("monacoin:MXCeTRYF62fdUtHT8CJujoaVEduGf5hQP6").unwrap();
+        assert_eq!(uri, MonacoinUri::new(Address::from_str("MXCeTRYF62fdUtHT8CJujoaVEduGf5hQP6").unwrap()));
+        assert_eq!(uri.to_string(), "monacoin:MXCeTRYF62fdUtHT8CJujoaVEduGf5hQP6");
+        roundtrips(&uri);
+    }
+
+    #[test]
+    fn upper_case_bech32() {
+        let uri = MonacoinUri::from_str("MONACOIN:MONA1QVZVKJN4Q3NSZQXRV3NRAGA2R822XJTY3Q96530?amount=1.50000000").unwrap();
+        assert_eq!(uri.address, Address::from_str("mona1qvzvkjn4q3nszqxrv3nraga2r822xjty3q96530").unwrap());
+        assert_eq!(uri.amount, Some(Amount::from_sat(150_000_000)));
+        assert_eq!(uri.to_string(), "monacoin:mona1qvzvkjn4q3nszqxrv3nraga2r822xjty3q96530?amount=1.5");
+        assert_eq!(format!("{:#}", uri), "monacoin:MONA1QVZVKJN4Q3NSZQXRV3NRAGA2R822XJTY3Q96530?amount=1.5");
+        roundtrips(&uri);
+    }
+
+    #[test]
+    fn parameters() {
+        let uri = MonacoinUri::from_str(
+            "monacoin:MXCeTRYF62fdUtHT8CJujoaVEduGf5hQP6?amount=20.3&label=Luke-Jr&message=Donation%20for%20project%20xyz&somethingyoudontunderstand=50&somethingelseyoudontget=999"
+        ).unwrap();
+        assert_eq!(uri.amount, Some(Amount::from_sat(2_030_000_000)));
+        assert_eq!(uri.label.as_ref().map(|s| s.as_str()), Some("Luke-Jr"));
+        assert_eq!(uri.message.as_ref().map(|s| s.as_str()), Some("Donation for project xyz"));
+        assert_eq!(uri.extra, vec![
+            ("somethingyoudontunderstand".to_owned(), "50".to_owned()),
+            ("somethingelseyoudontget".to_owned(), "999".to_owned()),
+        ]);
+        roundtrips(&uri);
+
+        let mut uri = MonacoinUri::new(Address::from_str("MXCeTRYF62fdUtHT8CJujoaVEduGf5hQP6").unwrap());
+        uri.amount = Some(Amount::from_sat(100_000_000));
+        uri.label = Some("モナー & co".to_owned());
+        uri.message = Some("100%".to_owned());
+        assert_eq!(
+            uri.to_string(),
+            "monacoin:MXCeTRYF62fdUtHT8CJujoaVEduGf5hQP6?amount=1&label=%E3%83%A2%E3%83%8A%E3%83%BC%20%26%20co&message=100%25"
+        );
+        roundtrips(&uri);
+
+        uri.amount = Some(Amount::from_sat(1));
+        assert!(uri.to_string().contains("amount=0.00000001&"));
+        roundtrips(&uri);
+    }
+
+    #[test]
+    fn invalid() {
+        let addr = "MXCeTRYF62fdUtHT8CJujoaVEduGf5hQP6";
+        assert_eq!(MonacoinUri::from_str(addr), Err(Error::InvalidScheme));
+        assert_eq!(MonacoinUri::from_str(&format!("bitcoin:{}", addr)), Err(Error::InvalidScheme));
+        assert!(match MonacoinUri::from_str("monacoin:notanaddress") {
+            Err(Error::Address(_)) => true,
+            _ => false,
+        });
+        assert!(match MonacoinUri::from_str(&format!("monacoin:{}?amount=1.000000001", addr)) {
+            Err(Error::Amount(_)) => true,
+            _ => false,
+        });
+        assert_eq!(
+            MonacoinUri::from_str(&format!("monacoin:{}?req-somethingyoudontunderstand=50", addr)),
+            Err(Error::UnknownRequiredParameter("req-somethingyoudontunderstand".to_owned()))
+        );
+        assert_eq!(
+            MonacoinUri::from_str(&format!("monacoin:{}?amount=1&amount=2", addr)),
+            Err(Error::DuplicateParameter("amount".to_owned()))
+        );
+        assert_eq!(MonacoinUri::from_str(&format!("monacoin:{}?label=%4", addr)), Err(Error::InvalidPercentEncoding));
+        assert_eq!(MonacoinUri::from_str(&format!("monacoin:{}?label=%zz", addr)), Err(Error::InvalidPercentEncoding));
+        assert_eq!(MonacoinUri::from_str(&format!("monacoin:{}?label=%ff", addr)), Err(Error::InvalidPercentEncoding));
+    }
+}
@@ -0,0 +1,320 @@
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Weight prediction
+//!
+//! Predicts the weight of a transaction before its inputs are signed, as
+//! needed for coin selection and fee estimation.
+//!
+//! ECDSA signatures are assumed to have their maximum length of 72 bytes,
+//! including the sighash flag. About half of all signatures are one byte
+//! shorter, so a prediction may exceed the weight of the signed transaction by
+//! 1 WU for each ECDSA signature in a witness and by 4 WU for each ECDSA
+//! signature in a scriptSig. Schnorr signatures have a fixed length, so taproot
+//! key spends are predicted exactly.
+//!
+
+use std::iter::IntoIterator;
+
+use blockdata::constants::WITNESS_SCALE_FACTOR;
+use consensus::encode::VarInt;
+use util::fee_rate::Weight;
+
+/// The size of the outpoint, the sequence number and the value of an input
+/// or output which do not depend on the scripts
+const OUTPOINT_SEQUENCE_SIZE: usize = 32 + 4 + 4;
+const VALUE_SIZE: usize = 8;
+
+/// The predicted contribution of a not yet signed input to the weight of a
+/// transaction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InputWeightPrediction {
+    /// Size of the scriptSig including its length prefix
+    script_size: usize,
+    /// Size of the witness including its element count, or zero if it is empty
+    witness_size: usize,
+}
+
+impl InputWeightPrediction {
+    /// Predicts an input whose scriptSig is `script_sig_len` bytes long and
+    /// whose witness consists of elements of the given lengths.
+    pub fn from_slices(script_sig_len: usize, witness_element_lens: &[usize]) -> InputWeightPrediction {
+        let witness_size = if witness_element_lens.is_empty() {
+            0
+        } else {
+            witness_element_lens.iter().fold(VarInt(witness_element_lens.len() as u64).len(), |size, &len| {
+                size + VarInt(len as u64).len() + len
+            })
+        };
+        InputWeightPrediction {
+            script_size: VarInt(script_sig_len as u64).len() + script_sig_len,
+            witness_size: witness_size,
+        }
+    }
+
+    /// A P2PKH input spent with a compressed public key.
+    pub fn p2pkh_compressed() -> InputWeightPrediction {
+        // <sig> <pubkey>
+        InputWeightPrediction::from_slices(1 + 72 + 1 + 33, &[])
+    }
+
+    /// A P2PKH input spent with an uncompressed public key.
+    pub fn p2pkh_uncompressed() -> InputWeightPrediction {
+        // <sig> <pubkey>
+        InputWeightPrediction::from_slices(1 + 72 + 1 + 65, &[])
+    }
+
+    /// A P2WPKH input.
+    pub fn p2wpkh() -> InputWeightPrediction {
+        InputWeightPrediction::from_slices(0, &[72, 33])
+    }
+
+    /// A P2SH-P2WPKH input.
+    pub fn p2sh_p2wpkh() -> InputWeightPrediction {
+        // The scriptSig pushes the 22 byte redeem script
+        InputWeightPrediction::from_slices(1 + 22, &[72, 33])
+    }
+
+    /// A P2TR key-path spend signed with [SchnorrSigHashType::Default](::util::sighash::SchnorrSigHashType::Default).
+    pub fn p2tr_key_default_sighash() -> InputWeightPrediction {
+        InputWeightPrediction::from_slices(0, &[64])
+    }
+
+    /// A P2TR key-path spend signed with any other sighash type, which is
+    /// appended to the signature.
+    pub fn p2tr_key_non_default_sighash() -> InputWeightPrediction {
+        InputWeightPrediction::from_slices(0, &[65])
+    }
+
+    /// The weight this input adds to a transaction, excluding its outpoint and
+    /// sequence number and the segwit marker and flag.
+    pub fn weight(&self) -> Weight {
+        Weight::from_wu((self.script_size * WITNESS_SCALE_FACTOR + self.witness_size) as u64)
+    }
+}
+
+/// Predicts the weight of a transaction with the given inputs and outputs
+/// whose scriptPubKeys have the given lengths.
+///
+/// The segwit marker and flag are only counted if at least one input has a
+/// witness, in which case every input without one adds an empty witness.
+pub fn predict_weight<I, O>(inputs: I, output_script_lens: O) -> Weight
+where
+    I: IntoIterator<Item = InputWeightPrediction>,
+    O: IntoIterator<Item = usize>,
+{
+    let mut input_count = 0;
+    let mut inputs_with_witness = 0;
+    let mut input_weight = 0;
+    for input in inputs {
+        input_count += 1;
+        if input.witness_size > 0 {
+            inputs_with_witness += 1;
+        }
+        input_weight += OUTPOINT_SEQUENCE_SIZE * WITNESS_SCALE_FACTOR + input.weight().to_wu() as usize;
+    }
+
+    let mut output_count = 0;
+    let mut output_size = 0;
+    for len in output_script_lens {
+        output_count += 1;
+        output_size += VALUE_SIZE + VarInt(len as u64).len() + len;
+    }
+
+    // Version, input and output counts, outputs and lock time
+    let base_size = 4 + VarInt(input_count as u64).len() + VarInt(output_count as u64).len() + output_size + 4;
+    let mut weight = base_size * WITNESS_SCALE_FACTOR + input_weight;
+    if inputs_with_witness > 0 {
+        // Marker, flag and an empty witness for each input without one
+        weight += 2 + input_count - inputs_with_witness;
+    }
+    Weight::from_wu(weight as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use secp256k1::{Message, Secp256k1, SecretKey};
+    use secp256k1::schnorrsig::KeyPair;
+
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{OutPoint, Sequence, SigHashType, Transaction, TxIn, TxOut};
+    use network::constants::Network;
+    use util::ecdsa::EcdsaSig;
+    use util::key::PrivateKey;
+    use util::sighash::{Prevouts, SchnorrSigHashType, SighashCache};
+    use util::taproot::XOnlyPublicKey;
+
+    #[derive(Copy, Clone, Debug)]
+    enum Template {
+        P2pkh { compressed: bool },
+        P2wpkh,
+        P2shP2wpkh,
+        P2tr { default_sighash: bool },
+    }
+
+    impl Template {
+        fn prediction(self) -> InputWeightPrediction {
+            match self {
+                Template::P2pkh { compressed: true } => InputWeightPrediction::p2pkh_compressed(),
+                Template::P2pkh { compressed: false } => InputWeightPrediction::p2pkh_uncompressed(),
+                Template::P2wpkh => InputWeightPrediction::p2wpkh(),
+                Template::P2shP2wpkh => InputWeightPrediction::p2sh_p2wpkh(),
+                Template::P2tr { default_sighash: true } => InputWeightPrediction::p2tr_key_default_sighash(),
+                Template::P2tr { default_sighash: false } => InputWeightPrediction::p2tr_key_non_default_sighash(),
+            }
+        }
+    }
+
+    /// Builds and signs a transaction spending one input of each template,
+    /// returning it with the maximum amount the prediction may be too high
+    fn sign(templates: &[Template], output_scripts: &[Script]) -> (Transaction, u64) {
+        let secp = Secp256k1::new();
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: (0..templates.len()).map(|i| TxIn {
+                previous_output: OutPoint::new(Default::default(), i as u32),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: vec![],
+            }).collect(),
+            output: output_scripts.iter().map(|s| TxOut { value: 1000, script_pubkey: s.clone() }).collect(),
+        };
+
+        let secret = |i: usize| [i as u8 + 1; 32];
+        let keys: Vec<PrivateKey> = templates.iter().enumerate().map(|(i, t)| PrivateKey {
+            compressed: match *t { Template::P2pkh { compressed } => compressed, _ => true },
+            network: Network::Monacoin,
+            key: SecretKey::from_slice(&secret(i)).unwrap(),
+        }).collect();
+        // Only used by the P2WPKH templates, whose keys are compressed
+        let redeem_scripts: Vec<Script> = keys.iter().map(|k| {
+            k.public_key(&secp).wpubkey_hash().map(|h| Script::new_v0_p2wpkh(&h)).unwrap_or_default()
+        }).collect();
+        let prevouts: Vec<TxOut> = templates.iter().enumerate().map(|(i, t)| {
+            let script_pubkey = match *t {
+                Template::P2pkh { .. } => Script::new_p2pkh(&keys[i].public_key(&secp).pubkey_hash()),
+                Template::P2wpkh => redeem_scripts[i].clone(),
+                Template::P2shP2wpkh => redeem_scripts[i].to_p2sh(),
+                Template::P2tr { .. } => {
+                    let keypair = KeyPair::from_seckey_slice(&secp, &secret(i)).unwrap();
+                    Builder::new()
+                        .push_opcode(opcodes::all::OP_PUSHNUM_1)
+                        .push_slice(&XOnlyPublicKey::from_keypair(&secp, &keypair).serialize())
+                        .into_script()
+                }
+            };
+            TxOut { value: 100_000, script_pubkey: script_pubkey }
+        }).collect();
+
+        let mut max_error = 0;
+        let mut signed = Vec::with_capacity(templates.len());
+        {
+            let mut cache = SighashCache::new(&tx);
+            for (i, t) in templates.iter().enumerate() {
+                let ecdsa_sig = |hash: &[u8]| EcdsaSig {
+                    sig: secp.sign(&Message::from_slice(hash).unwrap(), &keys[i].key),
+                    hash_ty: SigHashType::All,
+                }.to_vec();
+                let pk = keys[i].public_key(&secp).to_bytes();
+                let (script_sig, witness) = match *t {
+                    Template::P2pkh { .. } => {
+                        let hash = cache.legacy_signature_hash(i, &prevouts[i].script_pubkey, SigHashType::All.to_u32()).unwrap();
+                        let sig = ecdsa_sig(&hash[..]);
+                        max_error += 4 * (72 - sig.len() as u64);
+                        (Builder::new().push_slice(&sig).push_slice(&pk).into_script(), vec![])
+                    }
+                    Template::P2wpkh | Template::P2shP2wpkh => {
+                        let hash = cache.p2wpkh_signature_hash(i, &redeem_scripts[i], 100_000, SigHashType::All).unwrap();
+                        let sig = ecdsa_sig(&hash[..]);
+                        max_error += 72 - sig.len() as u64;
+                        let script_sig = match *t {
+                            Template::P2shP2wpkh => Builder::new().push_slice(redeem_scripts[i].as_bytes()).into_script(),
+                            _ => Script::new(),
+                        };
+                        (script_sig, vec![sig, pk])
+                    }
+                    Template::P2tr { default_sighash } => {
+                        let hash_ty = if default_sighash { SchnorrSigHashType::Default } else { SchnorrSigHashType::All };
+                        let hash = cache.taproot_key_spend_signature_hash(i, &Prevouts::All(&prevouts), hash_ty).unwrap();
+                        let keypair = KeyPair::from_seckey_slice(&secp, &secret(i)).unwrap();
+                        let mut sig = secp.schnorrsig_sign_no_aux_rand(&Message::from_slice(&hash[..]).unwrap(), &keypair)[..].to_vec();
+                        if !default_sighash {
+                            sig.push(hash_ty.as_u8());
+                        }
+                        (Script::new(), vec![sig])
+                    }
+                };
+                signed.push((script_sig, witness));
+            }
+        }
+        for (input, (script_sig, witness)) in tx.input.iter_mut().zip(signed) {
+            input.script_sig = script_sig;
+            input.witness = witness;
+        }
+        (tx, max_error)
+    }
+
+    fn check(templates: &[Template], output_scripts: &[Script]) {
+        let (tx, max_error) = sign(templates, output_scripts);
+        let output_lens = output_scripts.iter().map(|s| s.len());
+
+        let predicted = predict_weight(templates.iter().map(|t| t.prediction()), output_lens.clone());
+        assert!(predicted >= tx.weight(), "{:?}: predicted {} for {}", templates, predicted, tx.weight());
+        assert!(predicted.to_wu() - tx.weight().to_wu() <= max_error, "{:?}: predicted {} for {}", templates, predicted, tx.weight());
+
+        // With the actual lengths the prediction is exact
+        let exact = tx.input.iter().map(|input| {
+            let witness_lens: Vec<usize> = input.witness.iter().map(|elem| elem.len()).collect();
+            InputWeightPrediction::from_slices(input.script_sig.len(), &witness_lens)
+        });
+        assert_eq!(predict_weight(exact, output_lens), tx.weight(), "{:?}", templates);
+    }
+
+    #[test]
+    fn predict_signed_weight() {
+        let outputs = vec![
+            Script::new_p2pkh(&Default::default()),
+            Script::new_v0_p2wpkh(&Default::default()),
+            Script::new_v0_p2wsh(&Default::default()),
+        ];
+        let templates = [
+            Template::P2pkh { compressed: true },
+            Template::P2pkh { compressed: false },
+            Template::P2wpkh,
+            Template::P2shP2wpkh,
+            Template::P2tr { default_sighash: true },
+            Template::P2tr { default_sighash: false },
+        ];
+
+        for t in templates.iter() {
+            check(&[*t], &outputs);
+            check(&[*t, *t, *t], &outputs[..1]);
+        }
+        // Legacy inputs get an empty witness when mixed with segwit ones
+        check(&templates, &outputs);
+        check(&[Template::P2pkh { compressed: true }, Template::P2tr { default_sighash: true }], &outputs[1..]);
+
+        // Varint lengths of large scripts and many outputs
+        let large = Builder::new().push_opcode(opcodes::all::OP_RETURN).push_slice(&[0; 300]).into_script();
+        check(&[Template::P2wpkh], &[large]);
+        check(&[Template::P2pkh { compressed: true }], &vec![outputs[0].clone(); 253]);
+    }
+
+    #[test]
+    fn empty_transaction() {
+        // Version, two empty counts and lock time
+        assert_eq!(predict_weight(vec![], vec![]), Weight::from_wu(40));
+    }
+}
@@ -0,0 +1,97 @@
+//! Decoding data with forged length prefixes must not allocate the claimed
+//! lengths up front. This is a separate test crate because counting the
+//! allocations needs a global allocator, and the library forbids unsafe code.
+
+extern crate monacoin;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fmt;
+use std::mem;
+
+use monacoin::blockdata::block::Block;
+use monacoin::blockdata::constants::{genesis_block, MAX_BLOCK_WEIGHT, MIN_TRANSACTION_WEIGHT};
+use monacoin::blockdata::transaction::{Transaction, TxIn};
+use monacoin::consensus::encode::{deserialize, serialize, Decodable, VarInt, MAX_VEC_PREALLOC, MAX_VEC_SIZE};
+use monacoin::network::constants::Network;
+use monacoin::network::message_blockdata::Inventory;
+
+/// Tracks the memory allocated by each thread, so that parallel tests don't
+/// disturb each other's counts
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = Cell::new(0);
+    static PEAK: Cell<usize> = Cell::new(0);
+}
+
+fn record_alloc(size: usize) {
+    let _ = ALLOCATED.try_with(|allocated| {
+        allocated.set(allocated.get() + size);
+        let _ = PEAK.try_with(|peak| if allocated.get() > peak.get() { peak.set(allocated.get()) });
+    });
+}
+
+fn record_dealloc(size: usize) {
+    let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get().saturating_sub(size)));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_alloc(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        record_dealloc(layout.size());
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record_alloc(new_size);
+        record_dealloc(layout.size());
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The most memory allocated at once while decoding `data`, which must fail
+fn peak_decode_allocation<T: Decodable + fmt::Debug>(data: &[u8]) -> usize {
+    let start = ALLOCATED.with(|allocated| allocated.get());
+    PEAK.with(|peak| peak.set(start));
+    assert!(deserialize::<T>(data).is_err());
+    PEAK.with(|peak| peak.get()) - start
+}
+
+#[test]
+fn transaction_inputs() {
+    let mut data = vec![1, 0, 0, 0];
+    data.extend(serialize(&VarInt((MAX_VEC_SIZE / mem::size_of::<TxIn>()) as u64)));
+    assert!(peak_decode_allocation::<Transaction>(&data) < 2 * MAX_VEC_PREALLOC);
+}
+
+#[test]
+fn transaction_witness() {
+    // a segwit transaction with one input, whose witness element claims the largest allowed size
+    let mut data = vec![2, 0, 0, 0, 0, 1, 1];
+    data.extend(&[0u8; 36][..]);
+    data.extend(&[0, 0, 0, 0, 0, 0, 1]);
+    data.extend(serialize(&VarInt(MAX_VEC_SIZE as u64)));
+    data.extend(&[0u8; 100][..]);
+    assert!(peak_decode_allocation::<Transaction>(&data) < 2 * MAX_VEC_PREALLOC);
+}
+
+#[test]
+fn block_transactions() {
+    let mut data = serialize(&genesis_block(Network::Monacoin).header);
+    data.extend(serialize(&VarInt((MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT) as u64)));
+    assert!(peak_decode_allocation::<Block>(&data) < 2 * MAX_VEC_PREALLOC);
+}
+
+#[test]
+fn inventory() {
+    let data = serialize(&VarInt((MAX_VEC_SIZE / mem::size_of::<Inventory>()) as u64));
+    assert!(peak_decode_allocation::<Vec<Inventory>>(&data) < 2 * MAX_VEC_PREALLOC);
+}